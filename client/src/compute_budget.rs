@@ -0,0 +1,209 @@
+//! Compute budgets for transactions built from this crate's instructions.
+//! Every heavy instruction has its own unit limit, so those paths don't run
+//! into the 200k default split across instructions, or past it. The limits
+//! are estimates with headroom, not measurements; tests/benchmarks.json
+//! holds the measured baselines to check them against.
+//!
+//! Budgets are per transaction, not per instruction: build the instructions,
+//! then put `with_compute_budget` around all of them.
+
+use anchor_lang::Discriminator;
+use solana_sdk::{compute_budget::ComputeBudgetInstruction, instruction::Instruction};
+use staking_pool::instruction;
+
+use crate::PROGRAM_ID;
+
+/// Estimated units per instruction.
+pub mod units {
+    pub const INIT_USER_STATE_FOR: u32 = 30_000;
+    pub const ENTER_STAKING: u32 = 60_000;
+    pub const LEAVE_STAKING: u32 = 80_000;
+    pub const CLAIM_REWARDS: u32 = 60_000;
+
+    /// Settles both the token emissions and the SOL campaign.
+    pub const CLAIM_SOL_REWARDS: u32 = 70_000;
+
+    /// The swap adapter's CPI comes on top of the stake, unstake or claim.
+    pub const ZAP_STAKE: u32 = 250_000;
+    pub const LEAVE_STAKING_AND_SWAP: u32 = 270_000;
+    pub const CLAIM_REWARDS_AND_SWAP: u32 = 250_000;
+
+    /// Anything else: memos, nonce advances and program instructions
+    /// without a builder here.
+    pub const OTHER: u32 = 5_000;
+}
+
+/// Overrides of the default limits.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ComputeBudget {
+    /// Unit limit for the whole transaction instead of the summed defaults.
+    pub unit_limit: Option<u32>,
+
+    /// Priority fee in micro-lamports per unit, none by default.
+    pub unit_price: Option<u64>,
+}
+
+/// Default units of one instruction, recognized by its discriminator.
+pub fn default_units(ix: &Instruction) -> u32 {
+    if ix.program_id != PROGRAM_ID || ix.data.len() < 8 {
+        return units::OTHER;
+    }
+
+    match &ix.data[..8] {
+        data if data == instruction::InitUserStateFor::DISCRIMINATOR => units::INIT_USER_STATE_FOR,
+        data if data == instruction::Stake::DISCRIMINATOR
+            || data == instruction::EnterStaking::DISCRIMINATOR =>
+        {
+            units::ENTER_STAKING
+        }
+        data if data == instruction::Unstake::DISCRIMINATOR
+            || data == instruction::LeaveStaking::DISCRIMINATOR =>
+        {
+            units::LEAVE_STAKING
+        }
+        data if data == instruction::Claim::DISCRIMINATOR
+            || data == instruction::ClaimRewards::DISCRIMINATOR =>
+        {
+            units::CLAIM_REWARDS
+        }
+        data if data == instruction::ClaimSolRewards::DISCRIMINATOR => units::CLAIM_SOL_REWARDS,
+        data if data == instruction::ZapStake::DISCRIMINATOR => units::ZAP_STAKE,
+        data if data == instruction::LeaveStakingAndSwap::DISCRIMINATOR => {
+            units::LEAVE_STAKING_AND_SWAP
+        }
+        data if data == instruction::ClaimRewardsAndSwap::DISCRIMINATOR => {
+            units::CLAIM_REWARDS_AND_SWAP
+        }
+        _ => units::OTHER,
+    }
+}
+
+impl ComputeBudget {
+    /// Compute budget instructions for a transaction running
+    /// `instructions`.
+    pub fn instructions(&self, instructions: &[Instruction]) -> Vec<Instruction> {
+        let unit_limit = self.unit_limit.unwrap_or_else(|| {
+            instructions
+                .iter()
+                .map(default_units)
+                .fold(0u32, u32::saturating_add)
+        });
+
+        let mut budget = vec![ComputeBudgetInstruction::set_compute_unit_limit(unit_limit)];
+        budget.extend(
+            self.unit_price
+                .map(ComputeBudgetInstruction::set_compute_unit_price),
+        );
+
+        budget
+    }
+}
+
+/// `instructions` preceded by their compute budget.
+pub fn with_compute_budget(
+    instructions: &[Instruction],
+    budget: &ComputeBudget,
+) -> Vec<Instruction> {
+    let mut all = budget.instructions(instructions);
+    all.extend_from_slice(instructions);
+
+    all
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_sdk::pubkey::Pubkey;
+
+    fn ix(program_id: Pubkey, discriminator: &[u8]) -> Instruction {
+        Instruction {
+            program_id,
+            accounts: vec![],
+            data: discriminator.to_vec(),
+        }
+    }
+
+    #[test]
+    fn recognizes_instructions_by_discriminator() {
+        let cases = [
+            (instruction::Stake::DISCRIMINATOR, units::ENTER_STAKING),
+            (
+                instruction::EnterStaking::DISCRIMINATOR,
+                units::ENTER_STAKING,
+            ),
+            (instruction::Claim::DISCRIMINATOR, units::CLAIM_REWARDS),
+            (
+                instruction::ClaimSolRewards::DISCRIMINATOR,
+                units::CLAIM_SOL_REWARDS,
+            ),
+            (instruction::ZapStake::DISCRIMINATOR, units::ZAP_STAKE),
+            (
+                instruction::LeaveStakingAndSwap::DISCRIMINATOR,
+                units::LEAVE_STAKING_AND_SWAP,
+            ),
+            (instruction::Initialize::DISCRIMINATOR, units::OTHER),
+        ];
+
+        for (discriminator, expected) in cases {
+            assert_eq!(default_units(&ix(PROGRAM_ID, &discriminator)), expected);
+        }
+    }
+
+    #[test]
+    fn other_programs_and_short_data_get_the_fallback() {
+        let stake = instruction::Stake::DISCRIMINATOR;
+
+        assert_eq!(
+            default_units(&ix(Pubkey::new_unique(), &stake)),
+            units::OTHER
+        );
+        assert_eq!(default_units(&ix(PROGRAM_ID, &stake[..4])), units::OTHER);
+    }
+
+    #[test]
+    fn sums_the_defaults_without_a_price() {
+        let instructions = [
+            ix(PROGRAM_ID, &instruction::Stake::DISCRIMINATOR),
+            ix(PROGRAM_ID, &instruction::Claim::DISCRIMINATOR),
+        ];
+
+        assert_eq!(
+            ComputeBudget::default().instructions(&instructions),
+            vec![ComputeBudgetInstruction::set_compute_unit_limit(
+                units::ENTER_STAKING + units::CLAIM_REWARDS
+            )]
+        );
+    }
+
+    #[test]
+    fn overrides_replace_the_defaults() {
+        let instructions = [ix(PROGRAM_ID, &instruction::ZapStake::DISCRIMINATOR)];
+        let budget = ComputeBudget {
+            unit_limit: Some(400_000),
+            unit_price: Some(7),
+        };
+
+        assert_eq!(
+            budget.instructions(&instructions),
+            vec![
+                ComputeBudgetInstruction::set_compute_unit_limit(400_000),
+                ComputeBudgetInstruction::set_compute_unit_price(7),
+            ]
+        );
+    }
+
+    #[test]
+    fn budget_goes_before_the_instructions() {
+        let instructions = [ix(PROGRAM_ID, &instruction::Unstake::DISCRIMINATOR)];
+
+        let all = with_compute_budget(&instructions, &ComputeBudget::default());
+
+        assert_eq!(
+            all,
+            vec![
+                ComputeBudgetInstruction::set_compute_unit_limit(units::LEAVE_STAKING),
+                instructions[0].clone(),
+            ]
+        );
+    }
+}
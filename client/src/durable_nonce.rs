@@ -0,0 +1,104 @@
+//! Stake and unstake transactions on a durable nonce, for cold wallets that
+//! sign offline and submit later. Nothing in the program depends on when a
+//! transaction was signed: slots are read from the clock at execution, and
+//! the memo check looks at the instruction right before the stake, so the
+//! leading `advance_nonce_account` doesn't get in the way. That includes
+//! `request_withdrawal`, which takes its place in the queue when it lands.
+
+use solana_sdk::{
+    hash::Hash, instruction::Instruction, message::Message, pubkey::Pubkey, system_instruction,
+    transaction::Transaction,
+};
+use staking_pool::MEMO_PROGRAM_ID;
+
+use crate::{
+    compute_budget::{self, ComputeBudget},
+    instructions, PoolAccounts,
+};
+
+/// Where the nonce lives and who may advance it.
+#[derive(Clone, Copy, Debug)]
+pub struct DurableNonce {
+    pub account: Pubkey,
+    pub authority: Pubkey,
+
+    /// The value stored in the nonce account, used as the blockhash.
+    pub nonce: Hash,
+}
+
+/// An unsigned transaction running `instructions` on `nonce` within
+/// `budget`. Sign it with `Transaction::try_partial_sign` wherever each key
+/// is kept; it stays valid until the nonce is advanced.
+pub fn durable_nonce_transaction(
+    payer: &Pubkey,
+    nonce: &DurableNonce,
+    instructions: &[Instruction],
+    budget: &ComputeBudget,
+) -> Transaction {
+    // The nonce advance has to come first.
+    let mut all = vec![system_instruction::advance_nonce_account(
+        &nonce.account,
+        &nonce.authority,
+    )];
+    all.extend(compute_budget::with_compute_budget(instructions, budget));
+
+    let message = Message::new_with_blockhash(&all, Some(payer), &nonce.nonce);
+    Transaction::new_unsigned(message)
+}
+
+/// SPL Memo instruction, which must come right before stake changes in
+/// pools requiring memos.
+pub fn memo(memo: &[u8]) -> Instruction {
+    Instruction {
+        program_id: MEMO_PROGRAM_ID,
+        accounts: vec![],
+        data: memo.to_vec(),
+    }
+}
+
+/// `enter_staking` of `amount` on `nonce`, after `memo` if given.
+#[allow(clippy::too_many_arguments)]
+pub fn enter_staking_transaction(
+    pool: &PoolAccounts,
+    user: &Pubkey,
+    user_mint_acc: &Pubkey,
+    amount: u64,
+    payer: &Pubkey,
+    nonce: &DurableNonce,
+    memo_data: Option<&[u8]>,
+    budget: &ComputeBudget,
+) -> Transaction {
+    let mut instructions: Vec<_> = memo_data.map(memo).into_iter().collect();
+    instructions.push(instructions::enter_staking(
+        pool,
+        user,
+        user_mint_acc,
+        amount,
+        None,
+    ));
+
+    durable_nonce_transaction(payer, nonce, &instructions, budget)
+}
+
+/// `leave_staking` of `amount` on `nonce`, after `memo` if given.
+#[allow(clippy::too_many_arguments)]
+pub fn leave_staking_transaction(
+    pool: &PoolAccounts,
+    user: &Pubkey,
+    user_mint_acc: &Pubkey,
+    amount: u64,
+    payer: &Pubkey,
+    nonce: &DurableNonce,
+    memo_data: Option<&[u8]>,
+    budget: &ComputeBudget,
+) -> Transaction {
+    let mut instructions: Vec<_> = memo_data.map(memo).into_iter().collect();
+    instructions.push(instructions::leave_staking(
+        pool,
+        user,
+        user_mint_acc,
+        amount,
+    ));
+
+    durable_nonce_transaction(payer, nonce, &instructions, budget)
+}
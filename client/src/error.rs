@@ -0,0 +1,17 @@
+use solana_sdk::{message::CompileError, pubkey::Pubkey, signer::SignerError};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ClientError {
+    #[error("failed to compile message: {0}")]
+    Compile(#[from] CompileError),
+
+    #[error("failed to sign transaction: {0}")]
+    Signer(#[from] SignerError),
+
+    #[error("failed to decode the accounts of pool {0}")]
+    Decode(Pubkey),
+
+    #[error("step {0} doesn't fit in one transaction")]
+    StepTooLarge(usize),
+}
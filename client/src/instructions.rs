@@ -0,0 +1,160 @@
+//! Builders for the instructions users send most, through their versioned
+//! entry points. Optional accounts the program accepts (leaderboard,
+//! checkpoints, profile, rate publisher) are left out.
+
+use anchor_lang::{InstructionData, ToAccountMetas};
+use solana_sdk::{instruction::Instruction, pubkey::Pubkey, sysvar};
+use staking_pool::{accounts, instruction, ClaimArgs, StakeArgs, UnstakeArgs, MEMO_PROGRAM_ID};
+
+use crate::{user_state_address, PoolAccounts, PROGRAM_ID};
+
+/// Most `init_user_state_for` instructions that fit the default compute
+/// budget of one transaction.
+pub const USER_STATES_PER_TRANSACTION: usize = 8;
+
+pub fn init_user_state_for(pool: &Pubkey, payer: &Pubkey, authority: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: PROGRAM_ID,
+        accounts: accounts::InitUserStateFor {
+            user_state: user_state_address(pool, authority),
+            pool: *pool,
+            payer: *payer,
+            system_program: solana_sdk::system_program::ID,
+        }
+        .to_account_metas(None),
+        data: instruction::InitUserStateFor {
+            authority: *authority,
+        }
+        .data(),
+    }
+}
+
+/// User state initialization for many wallets, grouped into one batch of
+/// instructions per transaction. Rent comes from `payer`.
+pub fn init_user_states_for(
+    pool: &Pubkey,
+    payer: &Pubkey,
+    authorities: &[Pubkey],
+) -> Vec<Vec<Instruction>> {
+    authorities
+        .chunks(USER_STATES_PER_TRANSACTION)
+        .map(|chunk| {
+            chunk
+                .iter()
+                .map(|authority| init_user_state_for(pool, payer, authority))
+                .collect()
+        })
+        .collect()
+}
+
+/// `tag` is echoed in the program's `Staked` event, for attributing the
+/// deposit off-chain.
+///
+/// In pools requiring memos, an SPL Memo instruction must come right before
+/// this one and `leave_staking`.
+pub fn enter_staking(
+    pool: &PoolAccounts,
+    user: &Pubkey,
+    user_mint_acc: &Pubkey,
+    amount: u64,
+    tag: Option<[u8; 32]>,
+) -> Instruction {
+    Instruction {
+        program_id: PROGRAM_ID,
+        accounts: accounts::EnterStaking {
+            pool: pool.pool,
+            mint: pool.mint,
+            vault: pool.vault,
+            user_mint_acc: *user_mint_acc,
+            user_state: user_state_address(&pool.pool, user),
+            wallet_state: None,
+            authority: *user,
+            token_program: pool.token_program,
+            leaderboard: None,
+            user_checkpoints: None,
+            pool_checkpoints: None,
+            config: pool.config,
+            attestation: None,
+            fee_exemption: None,
+            audit_log: pool.audit_log,
+            user_profile: None,
+            rate_publisher: None,
+            instructions: Some(sysvar::instructions::ID),
+        }
+        .to_account_metas(None),
+        data: instruction::Stake {
+            args: StakeArgs::V1 { amount, tag },
+        }
+        .data(),
+    }
+}
+
+pub fn leave_staking(
+    pool: &PoolAccounts,
+    user: &Pubkey,
+    user_mint_acc: &Pubkey,
+    amount: u64,
+) -> Instruction {
+    Instruction {
+        program_id: PROGRAM_ID,
+        accounts: accounts::LeaveStaking {
+            pool: pool.pool,
+            program_signer: pool.program_signer,
+            mint: pool.mint,
+            vault: pool.vault,
+            user_mint_acc: *user_mint_acc,
+            user_state: user_state_address(&pool.pool, user),
+            wallet_state: None,
+            link_boosted: None,
+            authority: *user,
+            token_program: pool.token_program,
+            leaderboard: None,
+            user_checkpoints: None,
+            pool_checkpoints: None,
+            config: pool.config,
+            guardian: None,
+            fee_exemption: None,
+            audit_log: pool.audit_log,
+            user_profile: None,
+            rate_publisher: None,
+            instructions: Some(sysvar::instructions::ID),
+            memo_program: Some(MEMO_PROGRAM_ID),
+        }
+        .to_account_metas(None),
+        data: instruction::Unstake {
+            args: UnstakeArgs::V1 { amount },
+        }
+        .data(),
+    }
+}
+
+/// Claims `amount` of the pending rewards, or all of them for `None`.
+pub fn claim_rewards(
+    pool: &PoolAccounts,
+    user: &Pubkey,
+    user_mint_acc: &Pubkey,
+    amount: Option<u64>,
+) -> Instruction {
+    Instruction {
+        program_id: PROGRAM_ID,
+        accounts: accounts::ClaimRewards {
+            pool: pool.pool,
+            program_signer: pool.program_signer,
+            mint: pool.mint,
+            vault: pool.vault,
+            user_mint_acc: *user_mint_acc,
+            user: *user,
+            user_state: user_state_address(&pool.pool, user),
+            authority: *user,
+            token_program: pool.token_program,
+            leaderboard: None,
+            wallet_state: None,
+            audit_log: pool.audit_log,
+        }
+        .to_account_metas(None),
+        data: instruction::Claim {
+            args: ClaimArgs::V1 { amount },
+        }
+        .data(),
+    }
+}
@@ -0,0 +1,84 @@
+//! Off-chain helpers for building staking-pool transactions.
+
+pub mod compute_budget;
+pub mod durable_nonce;
+pub mod error;
+pub mod instructions;
+pub mod lookup_table;
+pub mod merkle;
+pub mod planner;
+pub mod portfolio;
+pub mod program_error;
+
+use solana_sdk::pubkey::Pubkey;
+use staking_pool::{position_seed, AUDIT_LOG_SEED, CONFIG_SEED, INTEGRATOR_SEED};
+
+pub use error::ClientError;
+pub use program_error::ProgramFailure;
+pub use staking_pool::ID as PROGRAM_ID;
+
+/// Accounts that are the same for every user of a pool.
+#[derive(Clone, Copy, Debug)]
+pub struct PoolAccounts {
+    pub pool: Pubkey,
+    pub mint: Pubkey,
+    pub vault: Pubkey,
+    pub program_signer: Pubkey,
+    pub token_program: Pubkey,
+
+    /// The pool's `PoolConfig`, once it has one.
+    pub config: Option<Pubkey>,
+
+    /// The pool's `AuditLog`, once it keeps one.
+    pub audit_log: Option<Pubkey>,
+}
+
+impl PoolAccounts {
+    /// Everything worth putting in the pool's address lookup table.
+    pub fn static_addresses(&self) -> Vec<Pubkey> {
+        let mut addresses = vec![
+            self.pool,
+            self.mint,
+            self.vault,
+            self.program_signer,
+            self.token_program,
+            PROGRAM_ID,
+            solana_sdk::system_program::ID,
+        ];
+        addresses.extend(self.config);
+        addresses.extend(self.audit_log);
+
+        addresses
+    }
+}
+
+pub fn program_signer_address(pool: &Pubkey, mint: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[mint.as_ref(), pool.as_ref()], &PROGRAM_ID)
+}
+
+pub fn user_state_address(pool: &Pubkey, user: &Pubkey) -> Pubkey {
+    user_position_address(pool, user, 0)
+}
+
+/// The wallet's position `index` in the pool; 0 is its default user state.
+pub fn user_position_address(pool: &Pubkey, user: &Pubkey, index: u16) -> Pubkey {
+    Pubkey::find_program_address(
+        &[pool.as_ref(), user.as_ref(), &position_seed(index)],
+        &PROGRAM_ID,
+    )
+    .0
+}
+
+/// The PDA the program `integrator` signs `integrator_stake` and
+/// `integrator_unstake` with.
+pub fn integrator_authority_address(integrator: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[INTEGRATOR_SEED], integrator).0
+}
+
+pub fn config_address(pool: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[CONFIG_SEED, pool.as_ref()], &PROGRAM_ID).0
+}
+
+pub fn audit_log_address(pool: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[AUDIT_LOG_SEED, pool.as_ref()], &PROGRAM_ID).0
+}
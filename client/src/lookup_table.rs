@@ -0,0 +1,79 @@
+//! Address lookup tables for a pool's static accounts, and v0 transactions
+//! that use them.
+
+use solana_sdk::{
+    address_lookup_table::{self, AddressLookupTableAccount},
+    hash::Hash,
+    instruction::Instruction,
+    message::{v0, VersionedMessage},
+    pubkey::Pubkey,
+    signers::Signers,
+    transaction::VersionedTransaction,
+};
+
+use crate::{ClientError, PoolAccounts};
+
+/// Addresses added per extend instruction, small enough for one legacy
+/// transaction.
+pub const MAX_ADDRESSES_PER_EXTEND: usize = 20;
+
+/// Instructions creating a lookup table holding the pool's static accounts.
+/// `recent_slot` must be a recent finalized slot; the table address depends
+/// on it.
+pub fn create_pool_lookup_table(
+    pool: &PoolAccounts,
+    authority: &Pubkey,
+    payer: &Pubkey,
+    recent_slot: u64,
+) -> (Pubkey, Vec<Instruction>) {
+    let (create, lookup_table) =
+        address_lookup_table::instruction::create_lookup_table(*authority, *payer, recent_slot);
+
+    let mut instructions = vec![create];
+    instructions.extend(extend_lookup_table(
+        &lookup_table,
+        authority,
+        payer,
+        &pool.static_addresses(),
+    ));
+
+    (lookup_table, instructions)
+}
+
+/// Instructions appending `addresses` to an existing table, split so each
+/// fits in its own transaction.
+pub fn extend_lookup_table(
+    lookup_table: &Pubkey,
+    authority: &Pubkey,
+    payer: &Pubkey,
+    addresses: &[Pubkey],
+) -> Vec<Instruction> {
+    addresses
+        .chunks(MAX_ADDRESSES_PER_EXTEND)
+        .map(|chunk| {
+            address_lookup_table::instruction::extend_lookup_table(
+                *lookup_table,
+                *authority,
+                Some(*payer),
+                chunk.to_vec(),
+            )
+        })
+        .collect()
+}
+
+/// Compiles `instructions` into a signed v0 transaction, resolving accounts
+/// through `lookup_tables` where possible.
+pub fn v0_transaction<T: Signers + ?Sized>(
+    payer: &Pubkey,
+    instructions: &[Instruction],
+    lookup_tables: &[AddressLookupTableAccount],
+    recent_blockhash: Hash,
+    signers: &T,
+) -> Result<VersionedTransaction, ClientError> {
+    let message = v0::Message::try_compile(payer, instructions, lookup_tables, recent_blockhash)?;
+
+    Ok(VersionedTransaction::try_new(
+        VersionedMessage::V0(message),
+        signers,
+    )?)
+}
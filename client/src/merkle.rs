@@ -0,0 +1,71 @@
+//! Merkle trees over `(user, amount)` pairs, as checked by `import_stake`
+//! and `verify_snapshot_proof`.
+
+use solana_sdk::{hash::hashv, pubkey::Pubkey};
+
+pub fn leaf(user: &Pubkey, amount: u64) -> [u8; 32] {
+    hashv(&[user.as_ref(), &amount.to_le_bytes()]).to_bytes()
+}
+
+fn hash_pair(a: &[u8; 32], b: &[u8; 32]) -> [u8; 32] {
+    if a <= b {
+        hashv(&[a, b]).to_bytes()
+    } else {
+        hashv(&[b, a]).to_bytes()
+    }
+}
+
+/// Every level of the tree, leaves first. An odd node out is carried up to
+/// the next level unchanged.
+pub struct MerkleTree {
+    levels: Vec<Vec<[u8; 32]>>,
+}
+
+impl MerkleTree {
+    pub fn new(entries: &[(Pubkey, u64)]) -> Self {
+        let mut levels = vec![entries
+            .iter()
+            .map(|(user, amount)| leaf(user, *amount))
+            .collect::<Vec<_>>()];
+
+        while levels[levels.len() - 1].len() > 1 {
+            let next = levels[levels.len() - 1]
+                .chunks(2)
+                .map(|pair| match pair {
+                    [a, b] => hash_pair(a, b),
+                    [a] => *a,
+                    _ => unreachable!(),
+                })
+                .collect();
+            levels.push(next);
+        }
+
+        Self { levels }
+    }
+
+    /// Root of the tree, zeroes when it has no leaves.
+    pub fn root(&self) -> [u8; 32] {
+        self.levels
+            .last()
+            .and_then(|level| level.first())
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Proof for the entry at `index` in the list the tree was built from.
+    pub fn proof(&self, mut index: usize) -> Option<Vec<[u8; 32]>> {
+        if index >= self.levels[0].len() {
+            return None;
+        }
+
+        let mut proof = Vec::new();
+        for level in &self.levels[..self.levels.len() - 1] {
+            if let Some(sibling) = level.get(index ^ 1) {
+                proof.push(*sibling);
+            }
+            index /= 2;
+        }
+
+        Some(proof)
+    }
+}
@@ -0,0 +1,250 @@
+//! Splitting a flow too big for one transaction into as few as possible.
+//! A flow is a list of steps in the order they have to land, each a group
+//! of instructions that must share a transaction (a memo and the stake it
+//! annotates, say). Steps are packed greedily, in order, until the next one
+//! would break the packet size, the compute limit or the account lock limit.
+//!
+//! Each planned transaction starts with its compute budget and is sized as
+//! a v0 message over `lookup_tables`, so compile it with
+//! `lookup_table::v0_transaction` and the same tables. Send them one after
+//! another; a later transaction may rely on an earlier one having landed.
+
+use solana_sdk::{
+    address_lookup_table::AddressLookupTableAccount,
+    hash::Hash,
+    instruction::Instruction,
+    message::{v0, VersionedMessage},
+    packet::PACKET_DATA_SIZE,
+    pubkey::Pubkey,
+    signature::SIGNATURE_BYTES,
+};
+
+use crate::{
+    compute_budget::{self, ComputeBudget},
+    instructions, ClientError, PoolAccounts,
+};
+
+/// Most compute units one transaction may request.
+pub const MAX_TRANSACTION_UNITS: u32 = 1_400_000;
+
+/// Most accounts one transaction may lock, lookup table entries included.
+pub const MAX_ACCOUNT_LOCKS: usize = 64;
+
+/// Claims `amount` of the pending rewards and stakes them again. Both halves
+/// share a step, so nothing claimed is left unstaked if the plan stops
+/// halfway. In pools requiring memos, put a memo before the stake.
+pub fn compound(
+    pool: &PoolAccounts,
+    user: &Pubkey,
+    user_mint_acc: &Pubkey,
+    amount: u64,
+) -> Vec<Instruction> {
+    vec![
+        instructions::claim_rewards(pool, user, user_mint_acc, Some(amount)),
+        instructions::enter_staking(pool, user, user_mint_acc, amount, None),
+    ]
+}
+
+/// `steps` packed into transactions, in order. Fails with the index of the
+/// first step that doesn't fit in a transaction of its own.
+pub fn plan(
+    payer: &Pubkey,
+    steps: &[Vec<Instruction>],
+    lookup_tables: &[AddressLookupTableAccount],
+    budget: &ComputeBudget,
+) -> Result<Vec<Vec<Instruction>>, ClientError> {
+    let mut transactions = vec![];
+    let mut current: Vec<Instruction> = vec![];
+
+    for (index, step) in steps.iter().enumerate() {
+        let mut candidate = current.clone();
+        candidate.extend_from_slice(step);
+        if fits(payer, &candidate, lookup_tables, budget) {
+            current = candidate;
+            continue;
+        }
+
+        if current.is_empty() || !fits(payer, step, lookup_tables, budget) {
+            return Err(ClientError::StepTooLarge(index));
+        }
+        transactions.push(compute_budget::with_compute_budget(&current, budget));
+        current = step.clone();
+    }
+
+    if !current.is_empty() {
+        transactions.push(compute_budget::with_compute_budget(&current, budget));
+    }
+
+    Ok(transactions)
+}
+
+/// Whether `instructions` and their compute budget make a valid transaction.
+fn fits(
+    payer: &Pubkey,
+    instructions: &[Instruction],
+    lookup_tables: &[AddressLookupTableAccount],
+    budget: &ComputeBudget,
+) -> bool {
+    let units = instructions
+        .iter()
+        .map(compute_budget::default_units)
+        .fold(0u32, u32::saturating_add);
+    if units > MAX_TRANSACTION_UNITS {
+        return false;
+    }
+
+    let all = compute_budget::with_compute_budget(instructions, budget);
+    // The blockhash doesn't change the size.
+    let Ok(message) = v0::Message::try_compile(payer, &all, lookup_tables, Hash::default()) else {
+        return false;
+    };
+
+    let locks = message.account_keys.len()
+        + message
+            .address_table_lookups
+            .iter()
+            .map(|lookup| lookup.writable_indexes.len() + lookup.readonly_indexes.len())
+            .sum::<usize>();
+    if locks > MAX_ACCOUNT_LOCKS {
+        return false;
+    }
+
+    let signatures = usize::from(message.header.num_required_signatures);
+    let size = short_vec_len(signatures)
+        + signatures * SIGNATURE_BYTES
+        + VersionedMessage::V0(message).serialize().len();
+
+    size <= PACKET_DATA_SIZE
+}
+
+/// Bytes of the compact length prefix in front of a serialized array.
+fn short_vec_len(len: usize) -> usize {
+    match len {
+        0..=0x7f => 1,
+        0x80..=0x3fff => 2,
+        _ => 3,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anchor_lang::Discriminator;
+    use solana_sdk::instruction::AccountMeta;
+    use staking_pool::instruction;
+
+    use super::*;
+    use crate::PROGRAM_ID;
+
+    fn ix(program_id: Pubkey, accounts: &[Pubkey], data: Vec<u8>) -> Instruction {
+        Instruction {
+            program_id,
+            accounts: accounts
+                .iter()
+                .map(|key| AccountMeta::new_readonly(*key, false))
+                .collect(),
+            data,
+        }
+    }
+
+    /// A memo-sized instruction of another program carrying `len` bytes.
+    fn data(len: usize) -> Instruction {
+        ix(Pubkey::new_unique(), &[], vec![0; len])
+    }
+
+    fn plan_default(
+        steps: &[Vec<Instruction>],
+        lookup_tables: &[AddressLookupTableAccount],
+    ) -> Result<Vec<Vec<Instruction>>, ClientError> {
+        plan(
+            &Pubkey::new_unique(),
+            steps,
+            lookup_tables,
+            &ComputeBudget::default(),
+        )
+    }
+
+    #[test]
+    fn packs_steps_until_the_packet_is_full() {
+        let steps: Vec<_> = (0..4).map(|_| vec![data(450)]).collect();
+
+        let transactions = plan_default(&steps, &[]).unwrap();
+
+        // Two steps of 450 bytes fit a packet, a third doesn't.
+        assert_eq!(transactions.len(), 2);
+        for (transaction, steps) in transactions.iter().zip(steps.chunks(2)) {
+            // Led by the unit limit.
+            assert_eq!(&transaction[1..], &steps.concat()[..]);
+        }
+    }
+
+    #[test]
+    fn keeps_a_step_in_one_transaction() {
+        let steps = vec![vec![data(400)], vec![data(400), data(400)], vec![data(10)]];
+
+        let transactions = plan_default(&steps, &[]).unwrap();
+
+        assert_eq!(transactions.len(), 2);
+        assert_eq!(&transactions[0][1..], &steps[0][..]);
+        assert_eq!(
+            &transactions[1][1..],
+            &[&steps[1][..], &steps[2][..]].concat()[..]
+        );
+    }
+
+    #[test]
+    fn splits_at_the_account_lock_limit() {
+        let program_id = Pubkey::new_unique();
+        let addresses: Vec<_> = (0..90).map(|_| Pubkey::new_unique()).collect();
+        let lookup_tables = [AddressLookupTableAccount {
+            key: Pubkey::new_unique(),
+            addresses: addresses.clone(),
+        }];
+        let steps: Vec<_> = addresses
+            .chunks(30)
+            .map(|accounts| vec![ix(program_id, accounts, vec![])])
+            .collect();
+
+        let transactions = plan_default(&steps, &lookup_tables).unwrap();
+
+        // The payer and both programs, and 60 looked-up accounts make 63
+        // locks; 90 would be over the limit even though the bytes fit.
+        assert_eq!(transactions.len(), 2);
+        assert_eq!(&transactions[0][1..], &steps[..2].concat()[..]);
+        assert_eq!(&transactions[1][1..], &steps[2][..]);
+    }
+
+    #[test]
+    fn splits_at_the_compute_limit() {
+        let zap = || {
+            ix(
+                PROGRAM_ID,
+                &[],
+                instruction::ZapStake::DISCRIMINATOR.to_vec(),
+            )
+        };
+        let steps: Vec<_> = (0..6).map(|_| vec![zap()]).collect();
+
+        let transactions = plan_default(&steps, &[]).unwrap();
+
+        // Five zaps are 1.25M units, six would be more than 1.4M.
+        assert_eq!(
+            transactions.iter().map(Vec::len).collect::<Vec<_>>(),
+            vec![1 + 5, 1 + 1]
+        );
+    }
+
+    #[test]
+    fn reports_the_step_that_fits_nowhere() {
+        let steps = vec![vec![data(10)], vec![data(PACKET_DATA_SIZE)]];
+
+        assert!(matches!(
+            plan_default(&steps, &[]),
+            Err(ClientError::StepTooLarge(1))
+        ));
+    }
+
+    #[test]
+    fn plans_nothing_for_no_steps() {
+        assert!(plan_default(&[], &[]).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,220 @@
+//! A wallet's positions across pools in one `getMultipleAccounts` call, for
+//! dashboards. Fetch `portfolio_addresses`, then pass the accounts in the same
+//! order to `portfolio`. A wallet's indexed positions in a pool are summed.
+
+use anchor_lang::{AccountDeserialize, Discriminator};
+use solana_sdk::{account::Account, pubkey::Pubkey};
+use staking_pool::{pending_rewards, Pool, UserState};
+
+use crate::{user_position_address, ClientError};
+
+/// Most accounts one `getMultipleAccounts` call returns, so at most
+/// `MAX_MULTIPLE_ACCOUNTS / (1 + positions)` pools per call.
+pub const MAX_MULTIPLE_ACCOUNTS: usize = 100;
+
+/// A wallet's stake in one pool, summed over its positions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Position {
+    pub pool: Pubkey,
+    pub mint: Pubkey,
+    pub staked_amount: u64,
+
+    /// Claimable at the time passed to `portfolio`, including rewards accrued
+    /// since the user state was last written.
+    pub pending_rewards: u64,
+
+    /// Rewards a claim couldn't mint, paid with the next one.
+    pub reward_iou: u64,
+}
+
+/// Each pool followed by the wallet's first `positions` user states in it,
+/// the default one included.
+pub fn portfolio_addresses(wallet: &Pubkey, pools: &[Pubkey], positions: u16) -> Vec<Pubkey> {
+    pools
+        .iter()
+        .flat_map(|pool| {
+            std::iter::once(*pool)
+                .chain((0..positions).map(|index| user_position_address(pool, wallet, index)))
+        })
+        .collect()
+}
+
+/// Decodes the accounts fetched for `portfolio_addresses(wallet, pools,
+/// positions)`, with pending rewards as of `now`. Pools the wallet never
+/// joined are left out.
+pub fn portfolio(
+    pools: &[Pubkey],
+    positions: u16,
+    accounts: &[Option<Account>],
+    now: i64,
+) -> Result<Vec<Position>, ClientError> {
+    let mut portfolio = Vec::new();
+
+    for (pool_key, chunk) in pools.iter().zip(accounts.chunks(1 + positions as usize)) {
+        let Some(pool_account) = &chunk[0] else {
+            continue;
+        };
+        let pool = decode_pool(pool_key, &pool_account.data)?;
+        let mut position = Position {
+            pool: *pool_key,
+            mint: pool.mint,
+            staked_amount: 0,
+            pending_rewards: 0,
+            reward_iou: 0,
+        };
+
+        let mut joined = false;
+        for user_account in chunk[1..].iter().flatten() {
+            let user_state = UserState::try_deserialize(&mut &user_account.data[..])
+                .map_err(|_| ClientError::Decode(*pool_key))?;
+            let pending = pending_rewards(&pool, &user_state, now)
+                .map_err(|_| ClientError::Decode(*pool_key))?;
+
+            position.staked_amount = position
+                .staked_amount
+                .saturating_add(user_state.staked_amount);
+            position.pending_rewards = position.pending_rewards.saturating_add(pending);
+            position.reward_iou = position.reward_iou.saturating_add(user_state.reward_iou);
+            joined = true;
+        }
+
+        if joined {
+            portfolio.push(position);
+        }
+    }
+
+    Ok(portfolio)
+}
+
+fn decode_pool(key: &Pubkey, data: &[u8]) -> Result<Pool, ClientError> {
+    let size = 8 + std::mem::size_of::<Pool>();
+    if data.len() < size || data[..8] != Pool::DISCRIMINATOR {
+        return Err(ClientError::Decode(*key));
+    }
+
+    Ok(bytemuck::pod_read_unaligned(&data[8..size]))
+}
+
+#[cfg(test)]
+mod tests {
+    use anchor_lang::{AccountSerialize, AnchorDeserialize};
+
+    use super::*;
+    use crate::PROGRAM_ID;
+
+    fn account(data: Vec<u8>) -> Option<Account> {
+        Some(Account {
+            lamports: 1,
+            data,
+            owner: PROGRAM_ID,
+            executable: false,
+            rent_epoch: 0,
+        })
+    }
+
+    /// A pool emitting 100 tokens a second on `staked_total`.
+    fn pool(mint: Pubkey, staked_total: u64) -> Option<Account> {
+        let mut pool: Pool = bytemuck::Zeroable::zeroed();
+        pool.mint = mint;
+        pool.emission_rate = 100;
+        pool.staked_total = staked_total;
+
+        account([&Pool::DISCRIMINATOR[..], bytemuck::bytes_of(&pool)].concat())
+    }
+
+    fn user(staked_amount: u64, reward_iou: u64) -> Option<Account> {
+        let mut user = UserState::deserialize(&mut &vec![0; UserState::size()][..]).unwrap();
+        user.initialized = true;
+        user.staked_amount = staked_amount;
+        user.reward_iou = reward_iou;
+
+        let mut data = vec![];
+        user.try_serialize(&mut data).unwrap();
+        account(data)
+    }
+
+    #[test]
+    fn sums_the_positions_in_each_pool() {
+        let pools = [Pubkey::new_unique(), Pubkey::new_unique()];
+        let mints = [Pubkey::new_unique(), Pubkey::new_unique()];
+        let accounts = [
+            pool(mints[0], 1_000),
+            user(400, 5),
+            user(600, 7),
+            pool(mints[1], 2_000),
+            None,
+            user(2_000, 0),
+        ];
+
+        // 10 seconds of emissions, shared by stake.
+        assert_eq!(
+            portfolio(&pools, 2, &accounts, 10).unwrap(),
+            vec![
+                Position {
+                    pool: pools[0],
+                    mint: mints[0],
+                    staked_amount: 1_000,
+                    pending_rewards: 1_000,
+                    reward_iou: 12,
+                },
+                Position {
+                    pool: pools[1],
+                    mint: mints[1],
+                    staked_amount: 2_000,
+                    pending_rewards: 1_000,
+                    reward_iou: 0,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn leaves_out_pools_without_positions() {
+        let pools = [
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        ];
+        let accounts = [
+            // Not found, e.g. closed.
+            None,
+            user(1_000, 0),
+            // Never joined.
+            pool(Pubkey::new_unique(), 1_000),
+            None,
+            pool(Pubkey::new_unique(), 1_000),
+            user(1_000, 0),
+        ];
+
+        let portfolio = portfolio(&pools, 1, &accounts, 0).unwrap();
+
+        assert_eq!(portfolio.len(), 1);
+        assert_eq!(portfolio[0].pool, pools[2]);
+    }
+
+    #[test]
+    fn rejects_accounts_that_are_no_pool() {
+        let pools = [Pubkey::new_unique()];
+        let accounts = [account(vec![0; 8 + std::mem::size_of::<Pool>()])];
+
+        assert!(matches!(
+            portfolio(&pools, 0, &accounts, 0),
+            Err(ClientError::Decode(pool)) if pool == pools[0]
+        ));
+    }
+
+    #[test]
+    fn lists_each_pool_before_its_positions() {
+        let wallet = Pubkey::new_unique();
+        let pool = Pubkey::new_unique();
+
+        assert_eq!(
+            portfolio_addresses(&wallet, &[pool], 2),
+            vec![
+                pool,
+                user_position_address(&pool, &wallet, 0),
+                user_position_address(&pool, &wallet, 1),
+            ]
+        );
+    }
+}
@@ -0,0 +1,470 @@
+//! What a failed transaction's custom error code means. The pool's own
+//! errors, Anchor's account checks and the SPL Token errors of its CPIs all
+//! surface as `InstructionError::Custom`; `ProgramFailure` tells them apart
+//! and says what to change before sending again.
+
+use anchor_lang::error::{ErrorCode, ERROR_CODE_OFFSET};
+use num_traits::FromPrimitive;
+use solana_sdk::{instruction::InstructionError, transaction::TransactionError};
+use spl_token::error::TokenError;
+use staking_pool::PoolError;
+use thiserror::Error;
+
+/// A custom error code, decoded.
+#[derive(Clone, Debug, Error)]
+pub enum ProgramFailure {
+    #[error("{0}")]
+    Pool(PoolError),
+
+    #[error("{0}")]
+    Anchor(ErrorCode),
+
+    #[error("token program: {0}")]
+    Token(TokenError),
+
+    #[error("unknown custom error {0}")]
+    Unknown(u32),
+}
+
+/// `PoolError` in declaration order, so that code 6000 + i is entry i.
+const POOL_ERRORS: &[PoolError] = &[
+    PoolError::InvalidMint,
+    PoolError::InvalidVault,
+    PoolError::InvalidProgramSigner,
+    PoolError::InvalidUserMintAccount,
+    PoolError::UserNotInitialized,
+    PoolError::ZeroAmount,
+    PoolError::PoolInvariantViolated,
+    PoolError::Unauthorized,
+    PoolError::InvalidEmissionSchedule,
+    PoolError::InvalidAprParameters,
+    PoolError::InvalidStreakBoost,
+    PoolError::InvalidLeaderboard,
+    PoolError::InvalidCheckpoints,
+    PoolError::CheckpointPageFull,
+    PoolError::InvalidAdapter,
+    PoolError::InsufficientBuffer,
+    PoolError::InvalidWithdrawalRequest,
+    PoolError::InvalidPoolConfig,
+    PoolError::StakeCapExceeded,
+    PoolError::InvalidMerkleProof,
+    PoolError::InsufficientEscrow,
+    PoolError::InvalidSnapshot,
+    PoolError::InvalidGuardian,
+    PoolError::GuardianApprovalRequired,
+    PoolError::InvalidSessionKey,
+    PoolError::InvalidVipBadge,
+    PoolError::ClaimCooldown,
+    PoolError::InvalidDepositSchedule,
+    PoolError::DepositNotDue,
+    PoolError::InsufficientDelegation,
+    PoolError::PoolPaused,
+    PoolError::InstructionDisabled,
+    PoolError::InvalidAuditLog,
+    PoolError::IncidentActive,
+    PoolError::NoIncident,
+    PoolError::InvalidIncident,
+    PoolError::ShortfallSettled,
+    PoolError::InvalidConfigChange,
+    PoolError::InvalidPosition,
+    PoolError::InvalidColdStorage,
+    PoolError::InvalidIntegrator,
+    PoolError::AllowanceExceeded,
+    PoolError::AchievementUnavailable,
+    PoolError::ExtensionTooSmall,
+    PoolError::WithdrawalBatchUnavailable,
+    PoolError::QueueNotEmpty,
+    PoolError::CampaignNotOwned,
+    PoolError::TermsNotAccepted,
+    PoolError::InvalidTerms,
+    PoolError::InvalidDistribution,
+    PoolError::DistributionCapExceeded,
+    PoolError::ColdApprovalRequired,
+    PoolError::UserStateActive,
+    PoolError::InvalidClaimAdapter,
+    PoolError::InvalidSolRewards,
+    PoolError::SolRewardsUnfunded,
+    PoolError::ClaimExceedsRewards,
+    PoolError::AttestationNotRevoked,
+    PoolError::UserUnwound,
+    PoolError::MemoRequired,
+    PoolError::RateChangeExceeded,
+    PoolError::InvalidRateGuard,
+    PoolError::AttestationsDisabled,
+    PoolError::CompressionDisabled,
+    PoolError::CompressionEnabled,
+    PoolError::ProfileFull,
+    PoolError::InvalidBoostLink,
+    PoolError::InvalidRewardStream,
+    PoolError::InvalidSwapAdapter,
+    PoolError::SlippageExceeded,
+    PoolError::InvalidRedirect,
+    PoolError::MintBlocked,
+    PoolError::MintNotAllowed,
+    PoolError::RewardsUnbacked,
+    PoolError::PoolAlreadyInitialized,
+    PoolError::NoPendingRotation,
+    PoolError::RotationTimelocked,
+    PoolError::NoPendingAdapterChange,
+    PoolError::AdapterChangeTimelocked,
+    PoolError::InvalidWalletState,
+    PoolError::RedirectPending,
+    PoolError::ReserveAlarm,
+    PoolError::LayoutUpToDate,
+    PoolError::EmissionsUnfunded,
+    PoolError::InvalidLottery,
+    PoolError::LotteryNotDrawn,
+    PoolError::InvalidRandomness,
+    PoolError::PrizeClaimed,
+    PoolError::NotAWinner,
+    PoolError::InvalidShard,
+    PoolError::ShardDepositPending,
+];
+
+// New variants shift the codes of everything after them; this fails to
+// compile until the table above follows.
+const _: () = {
+    let mut i = 0;
+    while i < POOL_ERRORS.len() {
+        assert!(POOL_ERRORS[i] as usize == i);
+        i += 1;
+    }
+    assert!(POOL_ERRORS.len() == PoolError::ShardDepositPending as usize + 1);
+};
+
+/// The Anchor errors a client can cause by passing the wrong accounts or
+/// arguments; the rest mean a broken program and decode as `Unknown`.
+const ANCHOR_ERRORS: &[ErrorCode] = &[
+    ErrorCode::InstructionFallbackNotFound,
+    ErrorCode::InstructionDidNotDeserialize,
+    ErrorCode::ConstraintMut,
+    ErrorCode::ConstraintHasOne,
+    ErrorCode::ConstraintSigner,
+    ErrorCode::ConstraintRaw,
+    ErrorCode::ConstraintOwner,
+    ErrorCode::ConstraintSeeds,
+    ErrorCode::ConstraintAddress,
+    ErrorCode::ConstraintTokenMint,
+    ErrorCode::ConstraintTokenOwner,
+    ErrorCode::ConstraintTokenTokenProgram,
+    ErrorCode::ConstraintMintTokenProgram,
+    ErrorCode::ConstraintAssociatedTokenTokenProgram,
+    ErrorCode::AccountDiscriminatorMismatch,
+    ErrorCode::AccountDidNotDeserialize,
+    ErrorCode::AccountNotEnoughKeys,
+    ErrorCode::AccountNotMutable,
+    ErrorCode::AccountOwnedByWrongProgram,
+    ErrorCode::InvalidProgramId,
+    ErrorCode::AccountNotSigner,
+    ErrorCode::AccountNotInitialized,
+];
+
+impl ProgramFailure {
+    /// Decodes the code of an `InstructionError::Custom`. Codes below
+    /// Anchor's range can only come from the token program, since the pool
+    /// itself never returns them.
+    pub fn from_code(code: u32) -> Self {
+        if let Some(error) = code
+            .checked_sub(ERROR_CODE_OFFSET)
+            .and_then(|index| POOL_ERRORS.get(index as usize))
+        {
+            return Self::Pool(*error);
+        }
+        if let Some(error) = ANCHOR_ERRORS
+            .iter()
+            .find(|error| u32::from(**error) == code)
+        {
+            return Self::Anchor(*error);
+        }
+        if code < u32::from(ErrorCode::InstructionMissing) {
+            if let Some(error) = TokenError::from_u32(code) {
+                return Self::Token(error);
+            }
+        }
+
+        Self::Unknown(code)
+    }
+
+    /// The failure behind an instruction error, if it's a custom one.
+    pub fn from_instruction_error(error: &InstructionError) -> Option<Self> {
+        match error {
+            InstructionError::Custom(code) => Some(Self::from_code(*code)),
+            _ => None,
+        }
+    }
+
+    /// The index of the failed instruction and its failure, if the
+    /// transaction failed with a custom error.
+    pub fn from_transaction_error(error: &TransactionError) -> Option<(u8, Self)> {
+        match error {
+            TransactionError::InstructionError(index, error) => {
+                Self::from_instruction_error(error).map(|failure| (*index, failure))
+            }
+            _ => None,
+        }
+    }
+
+    /// The custom error code this was decoded from.
+    pub fn code(&self) -> u32 {
+        match self {
+            Self::Pool(error) => u32::from(*error),
+            Self::Anchor(error) => u32::from(*error),
+            Self::Token(error) => error.clone() as u32,
+            Self::Unknown(code) => *code,
+        }
+    }
+
+    /// What to change before sending again, if anything will help.
+    pub fn suggestion(&self) -> Option<&'static str> {
+        match self {
+            Self::Pool(error) => pool_suggestion(*error),
+            Self::Anchor(error) => anchor_suggestion(*error),
+            Self::Token(error) => token_suggestion(error),
+            Self::Unknown(_) => None,
+        }
+    }
+}
+
+fn pool_suggestion(error: PoolError) -> Option<&'static str> {
+    let suggestion = match error {
+        PoolError::InvalidMint => "Pass the mint the pool was initialized with.",
+        PoolError::InvalidVault => "Pass the pool's current vault; it may have been rotated.",
+        PoolError::InvalidProgramSigner => {
+            "Derive the program signer with `program_signer_address`."
+        }
+        PoolError::InvalidUserMintAccount => {
+            "Pass a token account of the pool's mint owned by the user."
+        }
+        PoolError::UserNotInitialized => "Create the user state first with `init_user_state_for`.",
+        PoolError::ZeroAmount => "Use an amount above zero.",
+        PoolError::PoolInvariantViolated => {
+            "Nothing to fix client-side; report it to the pool authority."
+        }
+        PoolError::Unauthorized => "Sign with the key this instruction is restricted to.",
+        PoolError::InvalidEmissionSchedule => "Give halving and step schedules a nonzero period.",
+        PoolError::InvalidAprParameters => {
+            "Keep min <= max <= MAX_APR_BPS and set a utilization target when enabled."
+        }
+        PoolError::InvalidStreakBoost => {
+            "Use a nonzero epoch and a boost of at most MAX_STREAK_BOOST_BPS."
+        }
+        PoolError::InvalidLeaderboard => "Pass the pool's leaderboard for the current season.",
+        PoolError::InvalidCheckpoints => "Enable checkpoints and pass the user's current page.",
+        PoolError::CheckpointPageFull => "Open the user's next checkpoint page.",
+        PoolError::InvalidAdapter => "Pass the restaking adapter set on the pool.",
+        PoolError::InsufficientBuffer => {
+            "Withdraw less, or queue a withdrawal until funds are recalled."
+        }
+        PoolError::InvalidWithdrawalRequest => {
+            "Pass the request at the head of the queue, once it is fundable."
+        }
+        PoolError::InvalidPoolConfig => "Keep fees and bounds within their documented limits.",
+        PoolError::StakeCapExceeded => "Stake less; the pool or wallet cap is reached.",
+        PoolError::InvalidMerkleProof => "Rebuild the proof against the published root.",
+        PoolError::InsufficientEscrow => "Fund the migration escrow before importing.",
+        PoolError::InvalidSnapshot => "Pass the snapshot for the committed root and slot.",
+        PoolError::InvalidGuardian => "Use a nonzero announcement delay.",
+        PoolError::GuardianApprovalRequired => {
+            "Have the guardian co-sign, or announce the withdrawal and wait out the delay."
+        }
+        PoolError::InvalidSessionKey => {
+            "Register an unexpired session key, or sign with the owner."
+        }
+        PoolError::InvalidVipBadge => "Pass a badge token account of the VIP mint holding one.",
+        PoolError::ClaimCooldown => "Wait for the claim cooldown to elapse.",
+        PoolError::InvalidDepositSchedule => "Use a nonzero amount and interval.",
+        PoolError::DepositNotDue => "Wait until the schedule's next deposit time.",
+        PoolError::InsufficientDelegation => {
+            "Approve the program signer for at least the scheduled amount."
+        }
+        PoolError::PoolPaused => "Wait for the authority to unpause the pool.",
+        PoolError::InstructionDisabled => "Use another instruction; this one is turned off.",
+        PoolError::InvalidAuditLog => "Pass the pool's audit log.",
+        PoolError::IncidentActive => "Only emergency withdrawals work until the incident ends.",
+        PoolError::NoIncident => "Set an incident first.",
+        PoolError::InvalidIncident => "Use a nonzero incident reason.",
+        PoolError::ShortfallSettled => {
+            "Only emergency withdrawals are open after a shortfall settlement."
+        }
+        PoolError::InvalidConfigChange => {
+            "Pass the next config change PDA while the pool keeps a config history."
+        }
+        PoolError::InvalidPosition => "Pass each of the wallet's own positions once.",
+        PoolError::InvalidColdStorage => "Set a cold key, a destination and a nonzero window.",
+        PoolError::InvalidIntegrator => "Call through the integrator program set for the pool.",
+        PoolError::AllowanceExceeded => "Ask the authority to raise the integrator allowance.",
+        PoolError::AchievementUnavailable => {
+            "Reach the milestone first; each achievement mints once per wallet."
+        }
+        PoolError::ExtensionTooSmall => "Top up enough for at least a day at the current rate.",
+        PoolError::WithdrawalBatchUnavailable => {
+            "Turn pro-rata withdrawals on, or fill the current batch first."
+        }
+        PoolError::QueueNotEmpty => "Fulfil or cancel the queued withdrawals first.",
+        PoolError::CampaignNotOwned => "Wait for the running SOL campaign to end.",
+        PoolError::TermsNotAccepted => {
+            "Call `accept_terms` with the pool's current terms hash first."
+        }
+        PoolError::InvalidTerms => "Pass the pool's current `terms_hash`.",
+        PoolError::InvalidDistribution => "List one token account per amount, in order.",
+        PoolError::DistributionCapExceeded => {
+            "Raise the cap with set_distribution_cap or drop some amounts."
+        }
+        PoolError::ColdApprovalRequired => {
+            "Have the cold key approve the request before it expires."
+        }
+        PoolError::UserStateActive => "Unstake and claim everything before closing the state.",
+        PoolError::InvalidClaimAdapter => "Pass the claim adapter set on the pool.",
+        PoolError::InvalidSolRewards => {
+            "End the campaign in the future; extensions need one running."
+        }
+        PoolError::SolRewardsUnfunded => "Fund the SOL reward vault.",
+        PoolError::ClaimExceedsRewards => "Claim at most the pending rewards.",
+        PoolError::AttestationNotRevoked => "Wait for the attestation to expire or be revoked.",
+        PoolError::UserUnwound => "The wallet was force-unwound and can't stake again.",
+        PoolError::MemoRequired => "Put a memo instruction right before this one.",
+        PoolError::RateChangeExceeded => "Retry in a later slot.",
+        PoolError::InvalidRateGuard => "Use a guard of at most 10000 basis points.",
+        PoolError::AttestationsDisabled => "Use a build with the wormhole feature.",
+        PoolError::CompressionDisabled => {
+            "Enable compressed mode first; pools with a config or points mode can't."
+        }
+        PoolError::CompressionEnabled => {
+            "Compressed mode is already on, and rules out points mode."
+        }
+        PoolError::ProfileFull => "Leave one of the profile's pools before staking in another.",
+        PoolError::InvalidBoostLink => "Pass the boost link and source pool it was made for.",
+        PoolError::InvalidRewardStream => "Set a stream account along with the stream program.",
+        PoolError::InvalidSwapAdapter => "Pass the swap adapter set on the pool.",
+        PoolError::SlippageExceeded => "Lower the minimum output or retry later.",
+        PoolError::InvalidRedirect => {
+            "Redirect at most MAX_REDIRECT_BPS to the destination the pool records."
+        }
+        PoolError::MintBlocked => "Use another mint; this one is on the blocklist.",
+        PoolError::MintNotAllowed => "Ask the global authority to list the mint.",
+        PoolError::RewardsUnbacked => {
+            "Ask the authority to hand the mint authority back to the program signer."
+        }
+        PoolError::PoolAlreadyInitialized => "Use a fresh pool account.",
+        PoolError::NoPendingRotation => "Propose a rotation first.",
+        PoolError::RotationTimelocked => "Wait for the rotation timelock to elapse.",
+        PoolError::NoPendingAdapterChange => "Propose the adapter first.",
+        PoolError::AdapterChangeTimelocked => "Wait for the adapter timelock to elapse.",
+        PoolError::LayoutUpToDate => "Nothing to migrate; migrate the pool before its users.",
+        PoolError::EmissionsUnfunded => "Fund the reward reserve with fund_emissions first.",
+        PoolError::InvalidLottery => "Use 1 to 8 winners, a nonzero weight and a drawn winner.",
+        PoolError::LotteryNotDrawn => "Crank draw_lottery once the randomness is revealed.",
+        PoolError::InvalidRandomness => {
+            "Reveal the randomness account committed by the lottery's request."
+        }
+        PoolError::PrizeClaimed => "This winner was already credited.",
+        PoolError::NotAWinner => "Check the winning ticket against your stake range.",
+        PoolError::InvalidShard => "Use a shard index below 16; merged deposits are credited.",
+        PoolError::ShardDepositPending => {
+            "Crank credit_shard_deposit, or deposit through another shard."
+        }
+        PoolError::ReserveAlarm => {
+            "Withdraw through emergency_withdraw until the alarm is cleared."
+        }
+        PoolError::RedirectPending => {
+            "Crank distribute_redirected_rewards in the same transaction first."
+        }
+        PoolError::InvalidWalletState => {
+            "Pass the wallet state for wallets holding several positions, and only then."
+        }
+    };
+
+    Some(suggestion)
+}
+
+fn anchor_suggestion(error: ErrorCode) -> Option<&'static str> {
+    let suggestion = match error {
+        ErrorCode::InstructionFallbackNotFound | ErrorCode::InstructionDidNotDeserialize => {
+            "Rebuild the instruction with this crate's version of the program."
+        }
+        ErrorCode::ConstraintMut | ErrorCode::AccountNotMutable => "Mark the account writable.",
+        ErrorCode::ConstraintSigner | ErrorCode::AccountNotSigner => "Sign with the account's key.",
+        ErrorCode::ConstraintHasOne | ErrorCode::ConstraintAddress => {
+            "Pass the account the pool records for this slot."
+        }
+        ErrorCode::ConstraintSeeds => "Derive the PDA with this crate's address helpers.",
+        ErrorCode::ConstraintRaw => "Check the accounts against the instruction's docs.",
+        ErrorCode::ConstraintOwner | ErrorCode::AccountOwnedByWrongProgram => {
+            "Pass an account owned by the expected program."
+        }
+        ErrorCode::ConstraintTokenMint => "Pass a token account of the expected mint.",
+        ErrorCode::ConstraintTokenOwner => "Pass a token account owned by the signer.",
+        ErrorCode::ConstraintTokenTokenProgram
+        | ErrorCode::ConstraintMintTokenProgram
+        | ErrorCode::ConstraintAssociatedTokenTokenProgram
+        | ErrorCode::InvalidProgramId => "Pass the token program that owns the mint.",
+        ErrorCode::AccountDiscriminatorMismatch | ErrorCode::AccountDidNotDeserialize => {
+            "Pass an account of the expected type."
+        }
+        ErrorCode::AccountNotEnoughKeys => "Pass every account the instruction lists.",
+        ErrorCode::AccountNotInitialized => "Create the account first.",
+        _ => return None,
+    };
+
+    Some(suggestion)
+}
+
+fn token_suggestion(error: &TokenError) -> Option<&'static str> {
+    let suggestion = match error {
+        TokenError::InsufficientFunds => "Lower the amount to the token account's balance.",
+        TokenError::MintMismatch => "Pass token accounts of the pool's mint.",
+        TokenError::OwnerMismatch => "Sign with the token account's owner or delegate.",
+        TokenError::AccountFrozen => "Ask the freeze authority to thaw the account.",
+        TokenError::Overflow => "Lower the amount.",
+        _ => return None,
+    };
+
+    Some(suggestion)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `PoolError`'s variants as declared in the program, in order.
+    fn declared_pool_errors() -> Vec<&'static str> {
+        let source = include_str!("../../programs/staking-pool/src/lib.rs");
+        let start = source.find("pub enum PoolError {").unwrap();
+        let end = start + source[start..].find("\n}\n").unwrap();
+
+        source[start..end]
+            .lines()
+            .skip(1)
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with("#[") && !line.starts_with("//"))
+            .map(|line| line.trim_end_matches(','))
+            .collect()
+    }
+
+    #[test]
+    fn decodes_every_pool_error() {
+        let declared = declared_pool_errors();
+        assert_eq!(POOL_ERRORS.len(), declared.len());
+
+        for (index, name) in declared.into_iter().enumerate() {
+            let code = ERROR_CODE_OFFSET + index as u32;
+            match ProgramFailure::from_code(code) {
+                ProgramFailure::Pool(error) => {
+                    assert_eq!(error.name(), name);
+                    assert_eq!(u32::from(error), code);
+                }
+                failure => panic!("{code} decoded as {failure:?}, not {name}"),
+            }
+        }
+    }
+
+    #[test]
+    fn codes_past_the_pool_errors_are_unknown() {
+        let code = ERROR_CODE_OFFSET + POOL_ERRORS.len() as u32;
+
+        assert!(matches!(
+            ProgramFailure::from_code(code),
+            ProgramFailure::Unknown(unknown) if unknown == code
+        ));
+    }
+}
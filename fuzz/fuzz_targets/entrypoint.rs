@@ -0,0 +1,20 @@
+//! Fuzzes the program entrypoint with `FuzzCase`s, borsh-encoded.
+//!
+//! ```sh
+//! cargo install honggfuzz
+//! cd fuzz && HFUZZ_INPUT=corpus/entrypoint cargo hfuzz run entrypoint
+//! ```
+
+use anchor_lang::AnchorDeserialize;
+use honggfuzz::fuzz;
+use staking_pool_fuzz::{run, FuzzCase};
+
+fn main() {
+    loop {
+        fuzz!(|data: &[u8]| {
+            if let Ok(case) = FuzzCase::deserialize(&mut &data[..]) {
+                run(&case);
+            }
+        });
+    }
+}
@@ -0,0 +1,14 @@
+//! Writes the seed corpus to `corpus/entrypoint`.
+
+use std::{fs, path::Path};
+
+use staking_pool_fuzz::seeds::seeds;
+
+fn main() {
+    let corpus = Path::new(env!("CARGO_MANIFEST_DIR")).join("corpus/entrypoint");
+    fs::create_dir_all(&corpus).unwrap();
+
+    for seed in seeds() {
+        fs::write(corpus.join(seed.name), seed.input()).unwrap();
+    }
+}
@@ -0,0 +1,403 @@
+//! Fuzzing harness for the program entrypoint. A `FuzzCase` is a sequence of
+//! instructions from a user and an attacker against one pool, each with its
+//! accounts optionally swapped for others the harness knows about, run
+//! in-process through `staking_pool::entry`. After every instruction that
+//! succeeds the vault must still cover the pool's liabilities, and the
+//! attacker, who never stakes, must not have gained a token.
+
+pub mod seeds;
+mod stubs;
+
+use anchor_lang::{
+    prelude::{borsh, AccountInfo, AccountMeta, ProgramError, Pubkey},
+    solana_program::{entrypoint::ProgramResult, program_option::COption, program_pack::Pack},
+    AccountSerialize, AnchorDeserialize, AnchorSerialize, Discriminator, InstructionData,
+    ToAccountMetas,
+};
+use staking_pool::{accounts, instruction, Pool, UserState, INIT_MAGIC_NUMBER};
+
+/// Instructions past this many are ignored, to keep runs short.
+pub const MAX_INSTRUCTIONS: usize = 16;
+
+pub const USER_BALANCE: u64 = 1_000_000_000_000;
+pub const ATTACKER_BALANCE: u64 = 1_000_000_000;
+pub const EMISSION_RATE: u64 = 1_000;
+
+/// Indices of the accounts the harness knows about, which substitutions
+/// pick from.
+pub const POOL: u8 = 0;
+pub const MINT: u8 = 1;
+pub const VAULT: u8 = 2;
+pub const PROGRAM_SIGNER: u8 = 3;
+pub const USER: u8 = 4;
+pub const USER_TOKENS: u8 = 5;
+pub const USER_STATE: u8 = 6;
+pub const ATTACKER: u8 = 7;
+pub const ATTACKER_TOKENS: u8 = 8;
+
+/// Token account of the pool's mint owned by the program signer, but not
+/// the pool's vault.
+pub const FAKE_VAULT: u8 = 9;
+
+/// PDA of the program signer's seeds with a non-canonical bump.
+pub const FAKE_PROGRAM_SIGNER: u8 = 10;
+pub const TOKEN_PROGRAM: u8 = 11;
+
+/// Stands in for optional accounts left out.
+pub const PROGRAM: u8 = 12;
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct FuzzCase {
+    /// Added to the program signer's bump to get the nonce stored in the
+    /// pool, so anything but zero makes a pool that can't sign.
+    pub nonce_offset: u8,
+    pub instructions: Vec<FuzzInstruction>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct FuzzInstruction {
+    pub wallet: Wallet,
+    pub action: Action,
+    pub substitutions: Vec<Substitution>,
+}
+
+/// Whose wallet signs the instruction. Only it can sign, whatever accounts
+/// are substituted.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug)]
+pub enum Wallet {
+    User,
+    Attacker,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug)]
+pub enum Action {
+    EnterStaking { amount: u64 },
+    LeaveStaking { amount: u64 },
+    ClaimRewards { amount: Option<u64> },
+    Warp { seconds: u32 },
+}
+
+/// Replaces the account at `position` of the instruction with the known
+/// account `account`, both taken modulo the number available.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug)]
+pub struct Substitution {
+    pub position: u8,
+    pub account: u8,
+}
+
+/// Runs `case` against a fresh pool, returning the result of each
+/// instruction. Panics when an invariant breaks.
+pub fn run(case: &FuzzCase) -> Vec<ProgramResult> {
+    let mut harness = Harness::new(case.nonce_offset);
+
+    case.instructions
+        .iter()
+        .take(MAX_INSTRUCTIONS)
+        .map(|ix| {
+            let result = harness.execute(ix);
+            if result.is_ok() {
+                harness.check();
+            }
+            result
+        })
+        .collect()
+}
+
+struct Account {
+    key: Pubkey,
+    owner: Pubkey,
+    lamports: u64,
+    executable: bool,
+
+    /// Backing store, offset by 8 bytes so that data after the
+    /// discriminator is aligned like on chain.
+    words: Vec<u128>,
+    len: usize,
+}
+
+impl Account {
+    fn new(key: Pubkey, owner: Pubkey, data: &[u8]) -> Self {
+        let mut account = Self {
+            key,
+            owner,
+            lamports: 1_000_000_000,
+            executable: false,
+            words: vec![0; (data.len() + 8).div_ceil(16)],
+            len: data.len(),
+        };
+        account.data_mut().copy_from_slice(data);
+
+        account
+    }
+
+    fn program(key: Pubkey) -> Self {
+        Self {
+            executable: true,
+            ..Self::new(key, Pubkey::default(), &[])
+        }
+    }
+
+    fn data(&self) -> &[u8] {
+        &bytemuck::cast_slice(&self.words)[8..8 + self.len]
+    }
+
+    fn data_mut(&mut self) -> &mut [u8] {
+        &mut bytemuck::cast_slice_mut(&mut self.words)[8..8 + self.len]
+    }
+
+    fn info(&mut self) -> AccountInfo<'_> {
+        let Self {
+            key,
+            owner,
+            lamports,
+            executable,
+            words,
+            len,
+        } = self;
+
+        AccountInfo::new(
+            key,
+            false,
+            false,
+            lamports,
+            &mut bytemuck::cast_slice_mut(words)[8..8 + *len],
+            owner,
+            *executable,
+            0,
+        )
+    }
+}
+
+struct Harness {
+    accounts: Vec<Account>,
+}
+
+impl Harness {
+    fn new(nonce_offset: u8) -> Self {
+        stubs::install();
+        stubs::set_now(0);
+
+        let pool = Pubkey::new_from_array([1; 32]);
+        let mint = Pubkey::new_from_array([2; 32]);
+        let vault = Pubkey::new_from_array([3; 32]);
+        let user = Pubkey::new_from_array([4; 32]);
+        let user_tokens = Pubkey::new_from_array([5; 32]);
+        let attacker = Pubkey::new_from_array([7; 32]);
+        let attacker_tokens = Pubkey::new_from_array([8; 32]);
+        let fake_vault = Pubkey::new_from_array([9; 32]);
+
+        let (program_signer, bump) =
+            Pubkey::find_program_address(&[mint.as_ref(), pool.as_ref()], &staking_pool::ID);
+        let fake_program_signer = (0..bump)
+            .rev()
+            .find_map(|bump| {
+                Pubkey::create_program_address(
+                    &[mint.as_ref(), pool.as_ref(), &[bump]],
+                    &staking_pool::ID,
+                )
+                .ok()
+            })
+            .unwrap();
+        let user_state = user_state_address(&pool, &user);
+
+        let mut pool_state: Pool = bytemuck::Zeroable::zeroed();
+        pool_state.magic = INIT_MAGIC_NUMBER;
+        pool_state.program_signer = program_signer;
+        pool_state.mint = mint;
+        pool_state.vault = vault;
+        pool_state.nonce = bump.wrapping_add(nonce_offset);
+        pool_state.authority = Pubkey::new_from_array([13; 32]);
+        pool_state.emission_rate = EMISSION_RATE;
+        let pool_data = [&Pool::DISCRIMINATOR[..], bytemuck::bytes_of(&pool_state)].concat();
+
+        let mut user_state_data = vec![0; 8 + UserState::size()];
+        let mut user_state_account = UserState::deserialize(&mut &user_state_data[8..]).unwrap();
+        user_state_account.initialized = true;
+        user_state_account
+            .try_serialize(&mut &mut user_state_data[..])
+            .unwrap();
+
+        let mint_data = pack(spl_token::state::Mint {
+            mint_authority: COption::Some(program_signer),
+            supply: USER_BALANCE + ATTACKER_BALANCE,
+            decimals: 9,
+            is_initialized: true,
+            freeze_authority: COption::None,
+        });
+        let tokens = |owner, amount| {
+            pack(spl_token::state::Account {
+                mint,
+                owner,
+                amount,
+                state: spl_token::state::AccountState::Initialized,
+                ..Default::default()
+            })
+        };
+
+        // In the order of the indices above.
+        let accounts = vec![
+            Account::new(pool, staking_pool::ID, &pool_data),
+            Account::new(mint, spl_token::ID, &mint_data),
+            Account::new(vault, spl_token::ID, &tokens(program_signer, 0)),
+            Account::new(program_signer, Pubkey::default(), &[]),
+            Account::new(user, Pubkey::default(), &[]),
+            Account::new(user_tokens, spl_token::ID, &tokens(user, USER_BALANCE)),
+            Account::new(user_state, staking_pool::ID, &user_state_data),
+            Account::new(attacker, Pubkey::default(), &[]),
+            Account::new(
+                attacker_tokens,
+                spl_token::ID,
+                &tokens(attacker, ATTACKER_BALANCE),
+            ),
+            Account::new(fake_vault, spl_token::ID, &tokens(program_signer, 0)),
+            Account::new(fake_program_signer, Pubkey::default(), &[]),
+            Account::program(spl_token::ID),
+            Account::program(staking_pool::ID),
+        ];
+
+        Self { accounts }
+    }
+
+    fn key(&self, index: u8) -> Pubkey {
+        self.accounts[index as usize].key
+    }
+
+    fn execute(&mut self, ix: &FuzzInstruction) -> ProgramResult {
+        let (wallet, tokens) = match ix.wallet {
+            Wallet::User => (self.key(USER), self.key(USER_TOKENS)),
+            Wallet::Attacker => (self.key(ATTACKER), self.key(ATTACKER_TOKENS)),
+        };
+        let pool = self.key(POOL);
+        let user_state = user_state_address(&pool, &wallet);
+
+        let (mut metas, data): (Vec<AccountMeta>, Vec<u8>) = match ix.action {
+            Action::EnterStaking { amount } => (
+                accounts::EnterStaking {
+                    pool,
+                    mint: self.key(MINT),
+                    vault: self.key(VAULT),
+                    user_mint_acc: tokens,
+                    user_state,
+                    wallet_state: None,
+                    authority: wallet,
+                    token_program: self.key(TOKEN_PROGRAM),
+                    leaderboard: None,
+                    user_checkpoints: None,
+                    pool_checkpoints: None,
+                    config: None,
+                    attestation: None,
+                    fee_exemption: None,
+                    audit_log: None,
+                    user_profile: None,
+                    rate_publisher: None,
+                    instructions: None,
+                }
+                .to_account_metas(None),
+                instruction::EnterStaking { amount, tag: None }.data(),
+            ),
+            Action::LeaveStaking { amount } => (
+                accounts::LeaveStaking {
+                    pool,
+                    program_signer: self.key(PROGRAM_SIGNER),
+                    mint: self.key(MINT),
+                    vault: self.key(VAULT),
+                    user_mint_acc: tokens,
+                    user_state,
+                    wallet_state: None,
+                    link_boosted: None,
+                    authority: wallet,
+                    token_program: self.key(TOKEN_PROGRAM),
+                    leaderboard: None,
+                    user_checkpoints: None,
+                    pool_checkpoints: None,
+                    config: None,
+                    guardian: None,
+                    fee_exemption: None,
+                    audit_log: None,
+                    user_profile: None,
+                    rate_publisher: None,
+                    instructions: None,
+                    memo_program: None,
+                }
+                .to_account_metas(None),
+                instruction::LeaveStaking { amount }.data(),
+            ),
+            Action::ClaimRewards { amount } => (
+                accounts::ClaimRewards {
+                    pool,
+                    program_signer: self.key(PROGRAM_SIGNER),
+                    mint: self.key(MINT),
+                    vault: self.key(VAULT),
+                    user_mint_acc: tokens,
+                    user: wallet,
+                    user_state,
+                    authority: wallet,
+                    token_program: self.key(TOKEN_PROGRAM),
+                    leaderboard: None,
+                    wallet_state: None,
+                    audit_log: None,
+                }
+                .to_account_metas(None),
+                instruction::ClaimRewards { amount }.data(),
+            ),
+            Action::Warp { seconds } => {
+                stubs::set_now(stubs::now() + seconds as i64);
+                return Ok(());
+            }
+        };
+
+        for substitution in &ix.substitutions {
+            let position = substitution.position as usize % metas.len();
+            let account = substitution.account as usize % self.accounts.len();
+            metas[position].pubkey = self.accounts[account].key;
+        }
+
+        let infos: Vec<AccountInfo> = self
+            .accounts
+            .iter_mut()
+            .map(|account| account.info())
+            .collect();
+        let mut ix_infos = Vec::with_capacity(metas.len());
+        for meta in &metas {
+            let mut info = infos
+                .iter()
+                .find(|info| *info.key == meta.pubkey)
+                .ok_or(ProgramError::NotEnoughAccountKeys)?
+                .clone();
+            info.is_signer = meta.is_signer && meta.pubkey == wallet;
+            info.is_writable = meta.is_writable;
+            ix_infos.push(info);
+        }
+
+        staking_pool::entry(&staking_pool::ID, &ix_infos, &data)
+    }
+
+    fn check(&self) {
+        let account = |index: u8| &self.accounts[index as usize];
+        let tokens = |index: u8| spl_token::state::Account::unpack(account(index).data()).unwrap();
+        let pool: Pool =
+            bytemuck::pod_read_unaligned(&account(POOL).data()[8..8 + std::mem::size_of::<Pool>()]);
+
+        let liabilities = pool
+            .written_down_total(pool.liabilities().unwrap())
+            .unwrap();
+        let reserves = tokens(VAULT).amount as u128 + pool.deployed_amount as u128;
+        assert!(reserves >= liabilities, "vault covers liabilities");
+
+        assert!(
+            tokens(ATTACKER_TOKENS).amount <= ATTACKER_BALANCE,
+            "attacker gained nothing"
+        );
+    }
+}
+
+fn user_state_address(pool: &Pubkey, user: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[pool.as_ref(), user.as_ref()], &staking_pool::ID).0
+}
+
+fn pack<T: Pack>(state: T) -> Vec<u8> {
+    let mut data = vec![0; T::LEN];
+    state.pack_into_slice(&mut data);
+
+    data
+}
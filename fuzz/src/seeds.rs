@@ -0,0 +1,204 @@
+//! Seed corpus for the paths known to be tricky, checked in under
+//! `corpus/entrypoint`. Run the `seed-corpus` binary to write it out again
+//! after changing these.
+
+use anchor_lang::{
+    error::{Error, ErrorCode},
+    prelude::ProgramError,
+    solana_program::entrypoint::ProgramResult,
+    AnchorSerialize,
+};
+use staking_pool::PoolError;
+
+use crate::*;
+
+/// Where substituted accounts go in `enter_staking` and `leave_staking`.
+const ENTER_VAULT: u8 = 2;
+const LEAVE_PROGRAM_SIGNER: u8 = 1;
+const LEAVE_VAULT: u8 = 3;
+const LEAVE_USER_STATE: u8 = 5;
+
+const STAKE: u64 = 1_000_000;
+
+pub struct Seed {
+    pub name: &'static str,
+    pub case: FuzzCase,
+
+    /// Result of the last instruction.
+    pub expected: ProgramResult,
+}
+
+impl Seed {
+    pub fn input(&self) -> Vec<u8> {
+        self.case.try_to_vec().unwrap()
+    }
+}
+
+pub fn seeds() -> Vec<Seed> {
+    let stake = ix(Wallet::User, Action::EnterStaking { amount: STAKE }, &[]);
+
+    vec![
+        Seed {
+            name: "zero_stake",
+            case: case(
+                0,
+                vec![ix(Wallet::User, Action::EnterStaking { amount: 0 }, &[])],
+            ),
+            expected: Err(pool_error(PoolError::ZeroAmount)),
+        },
+        Seed {
+            name: "zero_unstake",
+            case: case(
+                0,
+                vec![
+                    stake.clone(),
+                    ix(Wallet::User, Action::LeaveStaking { amount: 0 }, &[]),
+                ],
+            ),
+            expected: Err(pool_error(PoolError::ZeroAmount)),
+        },
+        Seed {
+            name: "zero_claim",
+            case: case(
+                0,
+                vec![ix(
+                    Wallet::User,
+                    Action::ClaimRewards { amount: Some(0) },
+                    &[],
+                )],
+            ),
+            expected: Err(pool_error(PoolError::ZeroAmount)),
+        },
+        Seed {
+            name: "fake_vault_stake",
+            case: case(
+                0,
+                vec![ix(
+                    Wallet::User,
+                    Action::EnterStaking { amount: STAKE },
+                    &[(ENTER_VAULT, FAKE_VAULT)],
+                )],
+            ),
+            expected: Err(pool_error(PoolError::InvalidVault)),
+        },
+        Seed {
+            name: "fake_vault_unstake",
+            case: case(
+                0,
+                vec![
+                    stake.clone(),
+                    ix(
+                        Wallet::User,
+                        Action::LeaveStaking { amount: STAKE },
+                        &[(LEAVE_VAULT, FAKE_VAULT)],
+                    ),
+                ],
+            ),
+            expected: Err(pool_error(PoolError::InvalidVault)),
+        },
+        Seed {
+            name: "fake_program_signer",
+            case: case(
+                0,
+                vec![
+                    stake.clone(),
+                    ix(
+                        Wallet::User,
+                        Action::LeaveStaking { amount: STAKE },
+                        &[(LEAVE_PROGRAM_SIGNER, FAKE_PROGRAM_SIGNER)],
+                    ),
+                ],
+            ),
+            expected: Err(pool_error(PoolError::InvalidProgramSigner)),
+        },
+        Seed {
+            name: "nonce_mismatch",
+            case: case(
+                1,
+                vec![
+                    stake.clone(),
+                    ix(Wallet::User, Action::LeaveStaking { amount: STAKE }, &[]),
+                ],
+            ),
+            expected: Err(ProgramError::InvalidSeeds),
+        },
+        Seed {
+            name: "stolen_user_state",
+            case: case(
+                0,
+                vec![
+                    stake.clone(),
+                    ix(
+                        Wallet::Attacker,
+                        Action::LeaveStaking { amount: STAKE },
+                        &[(LEAVE_USER_STATE, USER_STATE)],
+                    ),
+                ],
+            ),
+            expected: Err(Error::from(ErrorCode::ConstraintSeeds).into()),
+        },
+        Seed {
+            name: "full_cycle",
+            case: case(
+                0,
+                vec![
+                    stake,
+                    ix(Wallet::User, Action::Warp { seconds: 3_600 }, &[]),
+                    ix(Wallet::User, Action::ClaimRewards { amount: None }, &[]),
+                    ix(Wallet::User, Action::LeaveStaking { amount: STAKE }, &[]),
+                ],
+            ),
+            expected: Ok(()),
+        },
+    ]
+}
+
+fn case(nonce_offset: u8, instructions: Vec<FuzzInstruction>) -> FuzzCase {
+    FuzzCase {
+        nonce_offset,
+        instructions,
+    }
+}
+
+fn ix(wallet: Wallet, action: Action, substitutions: &[(u8, u8)]) -> FuzzInstruction {
+    FuzzInstruction {
+        wallet,
+        action,
+        substitutions: substitutions
+            .iter()
+            .map(|&(position, account)| Substitution { position, account })
+            .collect(),
+    }
+}
+
+fn pool_error(error: PoolError) -> ProgramError {
+    Error::from(error).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use super::*;
+
+    #[test]
+    fn seeds_reach_their_paths() {
+        for seed in seeds() {
+            let results = run(&seed.case);
+            assert_eq!(results.last(), Some(&seed.expected), "{}", seed.name);
+        }
+    }
+
+    #[test]
+    fn corpus_is_up_to_date() {
+        let corpus = Path::new(env!("CARGO_MANIFEST_DIR")).join("corpus/entrypoint");
+        for seed in seeds() {
+            let input = fs::read(corpus.join(seed.name)).unwrap_or_default();
+            assert!(
+                input == seed.input(),
+                "corpus/entrypoint/{} is stale, run `cargo run --bin seed-corpus`",
+                seed.name
+            );
+        }
+    }
+}
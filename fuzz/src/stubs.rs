@@ -0,0 +1,105 @@
+//! Syscalls for running the program outside the runtime. Token CPIs go
+//! through the real SPL Token processor with the runtime's signer checks,
+//! the clock is whatever the harness warped it to, and logs are dropped.
+
+use std::cell::Cell;
+use std::sync::Once;
+
+use anchor_lang::solana_program::{
+    account_info::AccountInfo,
+    clock::Clock,
+    entrypoint::{ProgramResult, SUCCESS},
+    instruction::Instruction,
+    program_error::ProgramError,
+    program_stubs::{set_syscall_stubs, SyscallStubs},
+    pubkey::Pubkey,
+    rent::Rent,
+};
+
+thread_local! {
+    static UNIX_TIMESTAMP: Cell<i64> = const { Cell::new(0) };
+}
+
+pub fn now() -> i64 {
+    UNIX_TIMESTAMP.with(Cell::get)
+}
+
+pub fn set_now(unix_timestamp: i64) {
+    UNIX_TIMESTAMP.with(|now| now.set(unix_timestamp));
+}
+
+pub fn install() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        set_syscall_stubs(Box::new(Stubs));
+    });
+}
+
+struct Stubs;
+
+impl SyscallStubs for Stubs {
+    fn sol_log(&self, _message: &str) {}
+
+    fn sol_log_data(&self, _fields: &[&[u8]]) {}
+
+    fn sol_invoke_signed(
+        &self,
+        instruction: &Instruction,
+        account_infos: &[AccountInfo],
+        signers_seeds: &[&[&[u8]]],
+    ) -> ProgramResult {
+        // Like the runtime, only PDAs of the calling program can be signed
+        // for, so a pool with the wrong nonce can't move its vault.
+        let signers = signers_seeds
+            .iter()
+            .map(|seeds| Pubkey::create_program_address(seeds, &staking_pool::ID))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| ProgramError::InvalidSeeds)?;
+
+        let mut accounts = Vec::with_capacity(instruction.accounts.len());
+        for meta in &instruction.accounts {
+            let mut account = account_infos
+                .iter()
+                .find(|account| *account.key == meta.pubkey)
+                .ok_or(ProgramError::NotEnoughAccountKeys)?
+                .clone();
+
+            if meta.is_signer && !account.is_signer {
+                if !signers.contains(&meta.pubkey) {
+                    return Err(ProgramError::MissingRequiredSignature);
+                }
+                account.is_signer = true;
+            }
+            if meta.is_writable && !account.is_writable {
+                return Err(ProgramError::InvalidArgument);
+            }
+
+            accounts.push(account);
+        }
+
+        if instruction.program_id != spl_token::ID {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        spl_token::processor::Processor::process(
+            &instruction.program_id,
+            &accounts,
+            &instruction.data,
+        )
+    }
+
+    fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+        let clock = Clock {
+            unix_timestamp: now(),
+            ..Clock::default()
+        };
+        unsafe { *(var_addr as *mut Clock) = clock };
+
+        SUCCESS
+    }
+
+    fn sol_get_rent_sysvar(&self, var_addr: *mut u8) -> u64 {
+        unsafe { *(var_addr as *mut Rent) = Rent::default() };
+
+        SUCCESS
+    }
+}
@@ -0,0 +1,379 @@
+//! State-machine model of the pool for property tests. Random sequences of
+//! `Op`s run against the same pool and user-state code the instructions use,
+//! with the conservation invariants checked after every step. A feature that
+//! moves tokens or rewards adds its `Op` here, so the match in `Model::apply`
+//! stops compiling until it is modeled.
+
+use anchor_lang::{require, AnchorDeserialize};
+use anchor_safe_math::SafeMath;
+use proptest::prelude::*;
+
+use crate::{update_rewards, Lottery, Pool, PoolResult, ShardBatch, UserState};
+
+/// Users the model spreads operations over.
+const USERS: usize = 3;
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Op {
+    Stake {
+        user: usize,
+        amount: u64,
+    },
+    Unstake {
+        user: usize,
+        amount: u64,
+    },
+    Claim {
+        user: usize,
+        amount: u64,
+    },
+
+    /// Yield arriving in the vault, e.g. from a buyback.
+    Fund {
+        amount: u64,
+    },
+
+    /// Tokens deposited into the reserve backing scheduled emissions.
+    FundEmissions {
+        amount: u64,
+    },
+    /// Moves the end of scheduled emissions `seconds` past now.
+    ExtendEmissions {
+        seconds: i64,
+    },
+
+    /// Tokens minted to a recipient out of the distribution cap.
+    Distribute {
+        amount: u64,
+    },
+
+    /// A drawn lottery splitting `amount` among `winners`, replacing the
+    /// last one, and a winner's prize credited to `user`.
+    Lottery {
+        amount: u64,
+        winners: u8,
+    },
+    ClaimPrize {
+        user: usize,
+        winner: u8,
+    },
+
+    /// Stake deposited into a shard, the crank merging the shard, and a
+    /// user's oldest merged deposit credited to them.
+    ShardDeposit {
+        user: usize,
+        amount: u64,
+    },
+    MergeShard,
+    CreditShard {
+        user: usize,
+    },
+    Advance {
+        seconds: i64,
+    },
+}
+
+pub(crate) fn op() -> impl Strategy<Value = Op> {
+    let user = 0..USERS;
+    let amount = 0..1_000_000_000u64;
+
+    prop_oneof![
+        (user.clone(), amount.clone()).prop_map(|(user, amount)| Op::Stake { user, amount }),
+        (user.clone(), amount.clone()).prop_map(|(user, amount)| Op::Unstake { user, amount }),
+        (user.clone(), amount.clone()).prop_map(|(user, amount)| Op::Claim { user, amount }),
+        amount.clone().prop_map(|amount| Op::Fund { amount }),
+        amount
+            .clone()
+            .prop_map(|amount| Op::FundEmissions { amount }),
+        (0..864_000i64).prop_map(|seconds| Op::ExtendEmissions { seconds }),
+        amount.clone().prop_map(|amount| Op::Distribute { amount }),
+        (amount.clone(), 0..10u8).prop_map(|(amount, winners)| Op::Lottery { amount, winners }),
+        (user.clone(), 0..10u8).prop_map(|(user, winner)| Op::ClaimPrize { user, winner }),
+        (user.clone(), amount.clone()).prop_map(|(user, amount)| Op::ShardDeposit { user, amount }),
+        Just(Op::MergeShard),
+        user.prop_map(|user| Op::CreditShard { user }),
+        (0..86_400i64).prop_map(|seconds| Op::Advance { seconds }),
+    ]
+}
+
+pub(crate) struct Model {
+    pub pool: Pool,
+    pub users: Vec<UserState>,
+    pub now: i64,
+
+    /// Token balance of the staking vault.
+    pub vault: u64,
+
+    /// Rewards paid out, and everything ever made available for rewards.
+    pub claimed: u64,
+    pub funded: u128,
+
+    /// Tokens ever deposited to back rewards: yield and the emission reserve.
+    pub deposited: u128,
+
+    /// Tokens minted by distributions, and the cap they started from.
+    pub distributed: u64,
+    pub distribution_cap: u64,
+
+    pub lottery: Lottery,
+
+    /// Each user's deposit in the shard, and the merged batches with the
+    /// deposits still to credit from them.
+    pub shard: Vec<u64>,
+    pub batches: Vec<(ShardBatch, Vec<u64>)>,
+}
+
+impl Model {
+    /// `emission_stop_ts` 0 models a legacy pool minting emissions without
+    /// an end.
+    pub fn new(emission_rate: u64, emission_stop_ts: i64, distribution_cap: u64) -> Self {
+        let mut pool: Pool = bytemuck::Zeroable::zeroed();
+        pool.emission_rate = emission_rate;
+        pool.emission_stop_ts = emission_stop_ts;
+        pool.distribution_cap = distribution_cap;
+
+        let user = || {
+            let mut user = UserState::deserialize(&mut &vec![0; UserState::size()][..]).unwrap();
+            user.initialized = true;
+            user
+        };
+
+        Self {
+            pool,
+            users: (0..USERS).map(|_| user()).collect(),
+            now: 0,
+            vault: 0,
+            claimed: 0,
+            funded: 0,
+            deposited: 0,
+            distributed: 0,
+            distribution_cap,
+            lottery: Lottery::deserialize(&mut &vec![0; Lottery::size()][..]).unwrap(),
+            shard: vec![0; USERS],
+            batches: Vec::new(),
+        }
+    }
+
+    /// Runs `op` the way its instruction would. Ops the program would reject
+    /// return an error, at most after settling rewards, which changes nothing
+    /// the invariants see.
+    pub fn apply(&mut self, op: Op) -> PoolResult {
+        let (pool, now) = (&mut self.pool, self.now);
+
+        match op {
+            Op::Stake { user, amount } => {
+                require!(amount > 0, crate::PoolError::ZeroAmount);
+                let user = &mut self.users[user];
+                update_rewards(pool, user, now)?;
+                pool.staked_total = pool.staked_total.safe_add(amount)?;
+                user.staked_amount = user.staked_amount.safe_add(amount)?;
+                self.vault = self.vault.safe_add(amount)?;
+            }
+            Op::Unstake { user, amount } => {
+                let user = &mut self.users[user];
+                require!(
+                    amount > 0 && amount <= user.staked_amount,
+                    crate::PoolError::ZeroAmount
+                );
+                update_rewards(pool, user, now)?;
+                pool.staked_total = pool.staked_total.safe_sub(amount)?;
+                user.staked_amount = user.staked_amount.safe_sub(amount)?;
+                self.vault = self.vault.safe_sub(pool.written_down(amount)?)?;
+            }
+            Op::Claim { user, amount } => {
+                let user = &mut self.users[user];
+                update_rewards(pool, user, now)?;
+                require!(
+                    amount <= user.pending_rewards,
+                    crate::PoolError::ClaimExceedsRewards
+                );
+                let rewards = user.take_rewards(pool, now, Some(amount))?;
+                let (from_vault, _) = pool.split_rewards(rewards)?;
+                self.vault = self.vault.safe_sub(from_vault)?;
+                self.claimed = self.claimed.safe_add(rewards)?;
+            }
+            Op::Fund { amount } => {
+                // Staked tokens aren't yield: with nothing staked, funds stay
+                // in the vault as surplus.
+                if pool.staked_total > 0 {
+                    self.funded = self.funded.safe_add(amount as u128)?;
+                    self.deposited = self.deposited.safe_add(amount as u128)?;
+                }
+                pool.distribute_yield(amount, now)?;
+                self.vault = self.vault.safe_add(amount)?;
+            }
+            Op::FundEmissions { amount } => {
+                // Accrual draws it into `funded_rewards` as emissions come due.
+                require!(amount > 0, crate::PoolError::ZeroAmount);
+                pool.reward_reserve = pool.reward_reserve.safe_add(amount)?;
+                self.vault = self.vault.safe_add(amount)?;
+                self.deposited = self.deposited.safe_add(amount as u128)?;
+            }
+            Op::ExtendEmissions { seconds } => {
+                let end_ts = now + seconds;
+                require!(
+                    pool.emission_stop_ts != 0 && end_ts > pool.emission_stop_ts.max(now),
+                    crate::PoolError::InvalidEmissionSchedule
+                );
+                pool.update_reward_index(now)?;
+
+                let old = pool.emission_stop_ts;
+                pool.emission_stop_ts = end_ts;
+                if let Err(error) = pool.require_emissions_funded() {
+                    pool.emission_stop_ts = old;
+                    return Err(error);
+                }
+            }
+            Op::Distribute { amount } => {
+                // Minted straight to the recipient, outside the pool's books.
+                require!(pool.paused == 0, crate::PoolError::PoolPaused);
+                require!(
+                    amount <= pool.distribution_cap,
+                    crate::PoolError::DistributionCapExceeded
+                );
+                pool.distribution_cap -= amount;
+                self.distributed = self.distributed.safe_add(amount)?;
+            }
+            Op::Lottery { amount, winners } => {
+                // The draw itself moves nothing, so the model starts drawn.
+                require!(amount > 0, crate::PoolError::ZeroAmount);
+                require!(
+                    (1..=crate::MAX_LOTTERY_WINNERS).contains(&winners),
+                    crate::PoolError::InvalidLottery
+                );
+                pool.funded_rewards = pool.funded_rewards.safe_add(amount)?;
+                self.vault = self.vault.safe_add(amount)?;
+                self.funded = self.funded.safe_add(amount as u128)?;
+                self.deposited = self.deposited.safe_add(amount as u128)?;
+
+                self.lottery.prize = amount / winners as u64;
+                self.lottery.winners = winners;
+                self.lottery.claimed = 0;
+                self.lottery.drawn = true;
+            }
+            Op::ClaimPrize { user, winner } => {
+                let prize = self.lottery.take_prize(winner)?;
+                let user = &mut self.users[user];
+                update_rewards(pool, user, now)?;
+                user.pending_rewards = user.pending_rewards.safe_add(prize)?;
+            }
+            Op::ShardDeposit { user, amount } => {
+                // Waits in the shard's own account, outside the pool.
+                require!(amount > 0, crate::PoolError::ZeroAmount);
+                self.shard[user] = self.shard[user].safe_add(amount)?;
+            }
+            Op::MergeShard => {
+                let amount: u64 = self.shard.iter().sum();
+                require!(amount > 0, crate::PoolError::ZeroAmount);
+                pool.update_reward_index(now)?;
+                pool.staked_total = pool.staked_total.safe_add(amount)?;
+                self.vault = self.vault.safe_add(amount)?;
+
+                let mut batch =
+                    ShardBatch::deserialize(&mut &vec![0; ShardBatch::size()][..]).unwrap();
+                batch.reward_per_token = pool.reward_per_token;
+                batch.sol_reward_per_token = pool.sol_reward_per_token;
+                batch.deposited = amount;
+                batch.received = amount;
+                self.batches
+                    .push((batch, std::mem::replace(&mut self.shard, vec![0; USERS])));
+            }
+            Op::CreditShard { user } => {
+                let Some((batch, deposits)) = self
+                    .batches
+                    .iter_mut()
+                    .find(|(_, deposits)| deposits[user] > 0)
+                else {
+                    return Err(crate::PoolError::InvalidShard.into());
+                };
+                let state = &mut self.users[user];
+                update_rewards(pool, state, now)?;
+                let (share, rewards, _) = batch.take(deposits[user], pool)?;
+                deposits[user] = 0;
+                state.staked_amount = state.staked_amount.safe_add(share)?;
+                state.pending_rewards = state.pending_rewards.safe_add(rewards)?;
+            }
+            Op::Advance { seconds } => {
+                // Emission only counts while someone is there to earn it.
+                if pool.staked_total > 0 {
+                    self.funded = self
+                        .funded
+                        .safe_add((pool.emission_rate as u128).safe_mul(seconds as u128)?)?;
+                }
+                self.now = now + seconds;
+                pool.update_reward_index(self.now)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Conservation invariants that hold after every op.
+    pub fn check(&self) {
+        // Merged shard deposits are stake before they are credited.
+        let staked: u64 = self
+            .users
+            .iter()
+            .map(|user| user.staked_amount)
+            .sum::<u64>()
+            + self
+                .batches
+                .iter()
+                .map(|(batch, _)| batch.received)
+                .sum::<u64>();
+        assert_eq!(staked, self.pool.staked_total, "stake is conserved");
+
+        // Emissions are minted; yield stays in the vault until claimed.
+        assert!(
+            self.vault as u128
+                >= self
+                    .pool
+                    .written_down_total(self.pool.liabilities().unwrap())
+                    .unwrap(),
+            "vault covers liabilities"
+        );
+
+        let pending: u128 = self
+            .users
+            .iter()
+            .map(|user| user.pending_rewards as u128)
+            .sum();
+        assert!(
+            self.claimed as u128 + pending <= self.funded,
+            "rewards never exceed what was funded"
+        );
+        assert_eq!(
+            self.claimed, self.pool.total_rewards_distributed,
+            "claims are counted"
+        );
+
+        assert!(
+            self.pool.reward_reserve as u128 + self.pool.funded_rewards as u128 <= self.deposited,
+            "the reserve and funded rewards were deposited"
+        );
+
+        assert_eq!(
+            self.distributed as u128 + self.pool.distribution_cap as u128,
+            self.distribution_cap as u128,
+            "distributions stay within the cap"
+        );
+    }
+}
+
+proptest! {
+    #[test]
+    fn invariants_hold(
+        emission_rate in 0..1_000_000u64,
+        emission_stop_ts in 0..864_000i64,
+        distribution_cap in 0..10_000_000_000u64,
+        ops in prop::collection::vec(op(), 1..64),
+    ) {
+        let mut model = Model::new(emission_rate, emission_stop_ts, distribution_cap);
+
+        for op in ops {
+            // Rejected ops are fine, they just mustn't break anything.
+            let _ = model.apply(op);
+            model.check();
+        }
+    }
+}
@@ -0,0 +1,220 @@
+//! Discriminators and byte offsets of the program's accounts, for memcmp
+//! filters and Geyser plugins that read accounts without Anchor. Offsets
+//! count from the start of the account data, discriminator included, and
+//! integers are little-endian.
+//!
+//! Zero-copy offsets are taken from the structs, so they follow any change.
+//! Borsh accounts have no padding, their offsets add up the fields before
+//! and the tests below hold them to the serialized data.
+
+use std::mem::size_of;
+
+use anchor_lang::{prelude::Pubkey, Discriminator};
+
+use crate::{
+    AuditLog, BoostLink, Checkpoints, ConfigChange, DepositSchedule, FeeExemption, GlobalConfig,
+    GrantPosition, ImportReceipt, Leaderboard, Migration, MintListing, Pool, PoolConfig,
+    PoolMarker, RatePublisher, Snapshot, UserProfile, UserState, WithdrawalRequest,
+};
+
+pub const DISCRIMINATOR_LEN: usize = 8;
+
+/// Offset of a field of a zero-copy account.
+macro_rules! field_offset {
+    ($account:ty, $field:ident) => {
+        DISCRIMINATOR_LEN + {
+            let account = std::mem::MaybeUninit::<$account>::uninit();
+            let base = account.as_ptr();
+            // SAFETY: only the field's address is taken, nothing is read.
+            unsafe {
+                (std::ptr::addr_of!((*base).$field) as *const u8).offset_from(base as *const u8)
+                    as usize
+            }
+        }
+    };
+}
+
+pub const POOL_DISCRIMINATOR: [u8; 8] = Pool::DISCRIMINATOR;
+pub const USER_STATE_DISCRIMINATOR: [u8; 8] = UserState::DISCRIMINATOR;
+pub const POOL_CONFIG_DISCRIMINATOR: [u8; 8] = PoolConfig::DISCRIMINATOR;
+pub const WITHDRAWAL_REQUEST_DISCRIMINATOR: [u8; 8] = WithdrawalRequest::DISCRIMINATOR;
+pub const GRANT_POSITION_DISCRIMINATOR: [u8; 8] = GrantPosition::DISCRIMINATOR;
+pub const DEPOSIT_SCHEDULE_DISCRIMINATOR: [u8; 8] = DepositSchedule::DISCRIMINATOR;
+pub const FEE_EXEMPTION_DISCRIMINATOR: [u8; 8] = FeeExemption::DISCRIMINATOR;
+pub const USER_PROFILE_DISCRIMINATOR: [u8; 8] = UserProfile::DISCRIMINATOR;
+pub const BOOST_LINK_DISCRIMINATOR: [u8; 8] = BoostLink::DISCRIMINATOR;
+pub const RATE_PUBLISHER_DISCRIMINATOR: [u8; 8] = RatePublisher::DISCRIMINATOR;
+pub const CHECKPOINTS_DISCRIMINATOR: [u8; 8] = Checkpoints::DISCRIMINATOR;
+pub const AUDIT_LOG_DISCRIMINATOR: [u8; 8] = AuditLog::DISCRIMINATOR;
+pub const CONFIG_CHANGE_DISCRIMINATOR: [u8; 8] = ConfigChange::DISCRIMINATOR;
+pub const LEADERBOARD_DISCRIMINATOR: [u8; 8] = Leaderboard::DISCRIMINATOR;
+pub const MIGRATION_DISCRIMINATOR: [u8; 8] = Migration::DISCRIMINATOR;
+pub const IMPORT_RECEIPT_DISCRIMINATOR: [u8; 8] = ImportReceipt::DISCRIMINATOR;
+pub const SNAPSHOT_DISCRIMINATOR: [u8; 8] = Snapshot::DISCRIMINATOR;
+pub const POOL_MARKER_DISCRIMINATOR: [u8; 8] = PoolMarker::DISCRIMINATOR;
+pub const GLOBAL_CONFIG_DISCRIMINATOR: [u8; 8] = GlobalConfig::DISCRIMINATOR;
+pub const MINT_LISTING_DISCRIMINATOR: [u8; 8] = MintListing::DISCRIMINATOR;
+
+/// Size of a pool account. The other accounts' sizes depend on the
+/// target's alignment, so filter those by discriminator.
+pub const POOL_LEN: usize = DISCRIMINATOR_LEN + size_of::<Pool>();
+
+pub const POOL_PROGRAM_SIGNER_OFFSET: usize = field_offset!(Pool, program_signer);
+pub const POOL_MINT_OFFSET: usize = field_offset!(Pool, mint);
+pub const POOL_VAULT_OFFSET: usize = field_offset!(Pool, vault);
+pub const POOL_STAKED_TOTAL_OFFSET: usize = field_offset!(Pool, staked_total);
+pub const POOL_AUTHORITY_OFFSET: usize = field_offset!(Pool, authority);
+pub const POOL_REWARD_PER_TOKEN_OFFSET: usize = field_offset!(Pool, reward_per_token);
+
+/// Offset of `Pool::light_client_state`. `Pool` only grows at the end, so
+/// it never moves.
+pub const LIGHT_CLIENT_STATE_OFFSET: usize = field_offset!(Pool, light_client_state);
+
+/// Offsets of the `LightClientState` fields in the pool account.
+pub const LIGHT_CLIENT_STAKED_TOTAL_OFFSET: usize = LIGHT_CLIENT_STATE_OFFSET;
+pub const LIGHT_CLIENT_SLOT_OFFSET: usize = LIGHT_CLIENT_STATE_OFFSET + 8;
+pub const LIGHT_CLIENT_REWARD_PER_TOKEN_OFFSET: usize = LIGHT_CLIENT_STATE_OFFSET + 16;
+pub const LIGHT_CLIENT_EXCHANGE_RATE_OFFSET: usize = LIGHT_CLIENT_STATE_OFFSET + 32;
+
+/// Where accounts scoped to one pool keep its address: every account but
+/// `Pool`, `UserState`, `UserProfile`, `ImportReceipt`, `GlobalConfig` and
+/// `MintListing`.
+pub const POOL_KEY_OFFSET: usize = DISCRIMINATOR_LEN;
+
+/// The wallet an account is for, right after the pool in
+/// `WithdrawalRequest`, `DepositSchedule`, `GrantPosition` (the
+/// beneficiary), `FeeExemption` and `Checkpoints` (the holder,
+/// `Pubkey::default()` for the pool total).
+pub const WALLET_KEY_OFFSET: usize = POOL_KEY_OFFSET + size_of::<Pubkey>();
+
+pub const USER_PROFILE_OWNER_OFFSET: usize = DISCRIMINATOR_LEN;
+pub const IMPORT_RECEIPT_USER_OFFSET: usize = DISCRIMINATOR_LEN;
+
+pub const USER_STATE_INITIALIZED_OFFSET: usize = DISCRIMINATOR_LEN;
+pub const USER_STATE_STAKED_AMOUNT_OFFSET: usize = USER_STATE_INITIALIZED_OFFSET + 1;
+pub const USER_STATE_REWARD_PER_TOKEN_PAID_OFFSET: usize = USER_STATE_STAKED_AMOUNT_OFFSET + 8;
+pub const USER_STATE_PENDING_REWARDS_OFFSET: usize = USER_STATE_REWARD_PER_TOKEN_PAID_OFFSET + 16;
+
+const _: () = assert!(field_offset!(Checkpoints, pool) == POOL_KEY_OFFSET);
+const _: () = assert!(field_offset!(Checkpoints, holder) == WALLET_KEY_OFFSET);
+const _: () = assert!(field_offset!(AuditLog, pool) == POOL_KEY_OFFSET);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anchor_lang::{AccountDeserialize, AccountSerialize, AnchorDeserialize};
+
+    /// `account` zeroed, changed by `set`, then serialized.
+    fn serialized<T: AnchorDeserialize + AccountSerialize>(set: impl FnOnce(&mut T)) -> Vec<u8> {
+        let mut account = T::deserialize(&mut &[0; 4096][..]).unwrap();
+        set(&mut account);
+
+        let mut data = Vec::new();
+        account.try_serialize(&mut data).unwrap();
+        data
+    }
+
+    fn read<const N: usize>(data: &[u8], offset: usize) -> [u8; N] {
+        data[offset..offset + N].try_into().unwrap()
+    }
+
+    #[test]
+    fn user_state_offsets() {
+        let data = serialized::<UserState>(|user| {
+            user.initialized = true;
+            user.staked_amount = 1;
+            user.reward_per_token_paid = 2;
+            user.pending_rewards = 3;
+        });
+
+        assert_eq!(read(&data, 0), USER_STATE_DISCRIMINATOR);
+        assert_eq!(data[USER_STATE_INITIALIZED_OFFSET], 1);
+        assert_eq!(
+            read(&data, USER_STATE_STAKED_AMOUNT_OFFSET),
+            1u64.to_le_bytes()
+        );
+        assert_eq!(
+            read(&data, USER_STATE_REWARD_PER_TOKEN_PAID_OFFSET),
+            2u128.to_le_bytes()
+        );
+        assert_eq!(
+            read(&data, USER_STATE_PENDING_REWARDS_OFFSET),
+            3u64.to_le_bytes()
+        );
+
+        let user = UserState::try_deserialize(&mut &data[..]).unwrap();
+        assert_eq!(user.pending_rewards, 3);
+    }
+
+    #[test]
+    fn pool_and_wallet_keys() {
+        let pool = Pubkey::new_unique();
+        let wallet = Pubkey::new_unique();
+        let keys = |data: Vec<u8>| {
+            (
+                Pubkey::new_from_array(read(&data, POOL_KEY_OFFSET)),
+                Pubkey::new_from_array(read(&data, WALLET_KEY_OFFSET)),
+            )
+        };
+
+        let accounts = [
+            serialized::<WithdrawalRequest>(|request| {
+                (request.pool, request.user) = (pool, wallet)
+            }),
+            serialized::<DepositSchedule>(|schedule| {
+                (schedule.pool, schedule.user) = (pool, wallet)
+            }),
+            serialized::<GrantPosition>(|grant| (grant.pool, grant.beneficiary) = (pool, wallet)),
+            serialized::<FeeExemption>(|exemption| {
+                (exemption.pool, exemption.wallet) = (pool, wallet)
+            }),
+        ];
+        for data in accounts {
+            assert_eq!(keys(data), (pool, wallet));
+        }
+
+        let profile = serialized::<UserProfile>(|profile| profile.owner = wallet);
+        assert_eq!(read(&profile, USER_PROFILE_OWNER_OFFSET), wallet.to_bytes());
+        let receipt = serialized::<ImportReceipt>(|receipt| receipt.user = wallet);
+        assert_eq!(
+            read(&receipt, IMPORT_RECEIPT_USER_OFFSET),
+            wallet.to_bytes()
+        );
+    }
+
+    /// The sizes `tests/benchmarks.json` holds the TypeScript suite to,
+    /// checked here too so a growing account fails without a validator.
+    #[test]
+    fn account_sizes_match_benchmarks() {
+        let benchmarks: serde_json::Value =
+            serde_json::from_str(include_str!("../../../tests/benchmarks.json")).unwrap();
+        let sizes = &benchmarks["accountSizes"];
+
+        assert_eq!(sizes["pool"], POOL_LEN);
+        assert_eq!(sizes["userState"], DISCRIMINATOR_LEN + UserState::size());
+    }
+
+    #[test]
+    fn pool_offsets() {
+        let mut pool: Pool = bytemuck::Zeroable::zeroed();
+        pool.mint = Pubkey::new_unique();
+        pool.vault = Pubkey::new_unique();
+        pool.authority = Pubkey::new_unique();
+        pool.staked_total = 7;
+        pool.light_client_state.exchange_rate = 9;
+        let data = [&POOL_DISCRIMINATOR[..], bytemuck::bytes_of(&pool)].concat();
+
+        assert_eq!(data.len(), POOL_LEN);
+        assert_eq!(read(&data, POOL_MINT_OFFSET), pool.mint.to_bytes());
+        assert_eq!(read(&data, POOL_VAULT_OFFSET), pool.vault.to_bytes());
+        assert_eq!(
+            read(&data, POOL_AUTHORITY_OFFSET),
+            pool.authority.to_bytes()
+        );
+        assert_eq!(read(&data, POOL_STAKED_TOTAL_OFFSET), 7u64.to_le_bytes());
+        assert_eq!(
+            read(&data, LIGHT_CLIENT_EXCHANGE_RATE_OFFSET),
+            9u128.to_le_bytes()
+        );
+    }
+}
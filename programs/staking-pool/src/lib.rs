@@ -1,15 +1,258 @@
 use anchor_lang::error_code;
 use anchor_lang::prelude::*;
 use anchor_lang::solana_program::program_option::COption::Some as CSome;
+use anchor_lang::solana_program::{
+    hash::{hash, hashv},
+    instruction::{AccountMeta, Instruction},
+    program::{get_return_data, invoke, invoke_signed},
+    sysvar,
+};
+use anchor_lang::system_program;
+use anchor_lang::Discriminator;
 use anchor_safe_math::SafeMath;
-use anchor_spl::token::{self, Mint, MintTo, Token, TokenAccount, Transfer};
+use anchor_spl::associated_token::AssociatedToken;
+use anchor_spl::token_2022::spl_token_2022::{
+    self,
+    extension::{transfer_fee::TransferFeeConfig, BaseStateWithExtensions, StateWithExtensions},
+};
+use anchor_spl::token_interface::{self, Burn, Mint, MintTo, TokenAccount, TokenInterface};
+
+#[cfg(test)]
+mod invariants;
+pub mod layout;
+mod math;
+mod rewards;
+
+pub use layout::{
+    LIGHT_CLIENT_EXCHANGE_RATE_OFFSET, LIGHT_CLIENT_REWARD_PER_TOKEN_OFFSET,
+    LIGHT_CLIENT_SLOT_OFFSET, LIGHT_CLIENT_STAKED_TOTAL_OFFSET, LIGHT_CLIENT_STATE_OFFSET,
+};
+use math::{bps_of, mul_div, mul_div_amount, mul_div_rem, Rounding};
+pub use rewards::pending_rewards;
+use rewards::update_rewards;
 
 declare_id!("7VWcjkxeQNAnb9PDTkXYxP3oSFxXEiYUMBStn39rr4PX");
 
-const INIT_MAGIC_NUMBER: u64 = 0x6666;
+pub const INIT_MAGIC_NUMBER: u64 = 0x6666;
+
+/// `Pool::layout_version` of pools written by this program. Pools from
+/// before the field existed read 0 and are brought up by `migrate_pool`.
+pub const POOL_LAYOUT_VERSION: u8 = 3;
+
+/// `UserState::layout_version` of user states written by this program,
+/// brought up the same way by `migrate_user_state`.
+pub const USER_STATE_LAYOUT_VERSION: u8 = 1;
+
+/// Fixed-point scale of `Pool::reward_per_token`.
+const REWARD_PRECISION: u128 = 1_000_000_000_000;
+
+const BPS_DENOMINATOR: u128 = 10_000;
+
+const SECONDS_PER_YEAR: u128 = 365 * 24 * 60 * 60;
+
+/// Upper bound for the dynamic APR parameters (1000%).
+const MAX_APR_BPS: u32 = 100_000;
+
+/// Upper bound for the streak boost (+100%).
+const MAX_STREAK_BOOST_BPS: u32 = 10_000;
+
+/// Upper bound for the boost from a linked pool (+150%).
+const MAX_LINK_BOOST_BPS: u32 = 15_000;
+
+pub const BOOST_LINK_SEED: &[u8] = b"boost_link";
+
+pub const LEADERBOARD_SEED: &[u8] = b"leaderboard";
+
+/// Number of ranked stakers kept per season.
+const LEADERBOARD_SIZE: usize = 10;
+
+pub const CHECKPOINTS_SEED: &[u8] = b"checkpoints";
+
+/// Number of stake checkpoints held by one `Checkpoints` page.
+const CHECKPOINTS_PER_PAGE: usize = 64;
+
+pub const WITHDRAWAL_SEED: &[u8] = b"withdrawal";
+
+pub const PRIORITY_WITHDRAWAL_SEED: &[u8] = b"priority_withdrawal";
+
+pub const CONFIG_SEED: &[u8] = b"config";
+
+pub const MIGRATION_SEED: &[u8] = b"migration";
+
+pub const IMPORT_RECEIPT_SEED: &[u8] = b"imported";
+
+pub const SNAPSHOT_SEED: &[u8] = b"snapshot";
+
+pub const DEPOSIT_SCHEDULE_SEED: &[u8] = b"deposit_schedule";
+
+pub const FEE_EXEMPTION_SEED: &[u8] = b"fee_exemption";
+
+pub const POOL_MARKER_SEED: &[u8] = b"pool_marker";
+
+pub const GLOBAL_CONFIG_SEED: &[u8] = b"global_config";
+
+pub const CONFIG_CHANGE_SEED: &[u8] = b"config_change";
+
+/// Room for a parameter's Borsh encoding in a `ConfigChange`.
+const CONFIG_VALUE_LEN: usize = 128;
+
+pub const MINT_LISTING_SEED: &[u8] = b"mint_listing";
+
+pub const AUDIT_LOG_SEED: &[u8] = b"audit_log";
+
+pub const USER_PROFILE_SEED: &[u8] = b"user_profile";
+
+pub const RATE_PUBLISHER_SEED: &[u8] = b"rate_publisher";
+
+/// Time constant of `RatePublisher::twap`, in seconds.
+const RATE_TWAP_WINDOW: i64 = 60 * 60;
+
+/// Seed of the program's Wormhole emitter.
+pub const EMITTER_SEED: &[u8] = b"emitter";
+
+/// Wormhole core bridge.
+pub const WORMHOLE_PROGRAM_ID: Pubkey = pubkey!("worm2ZoG2kUd4vFXhvjh93UUH596ayRfgQ2MgjNMTth");
+
+pub const GRANT_SEED: &[u8] = b"grant";
+
+pub const ACHIEVEMENT_SEED: &[u8] = b"achievement";
+
+/// Metaplex Token Metadata.
+pub const TOKEN_METADATA_PROGRAM_ID: Pubkey =
+    pubkey!("metaqbxxUerdq28cj1RbAWkYQm3ybzjb6a8bt518x1s");
+
+/// Longest `AchievementConfig::base_uri`, short enough for the config
+/// history.
+const MAX_ACHIEVEMENT_URI_LEN: usize = 100;
+
+const ACHIEVEMENT_SYMBOL: &str = "STAKE";
+
+/// Seed of the PDA an integrator program signs its CPIs with, derived from
+/// the integrator's own program ID.
+pub const INTEGRATOR_SEED: &[u8] = b"staking_pool_integrator";
+
+/// Last seed of a user's position `index`. Empty for the default position, so
+/// its address is the one `initialize_user_state` creates.
+pub fn position_seed(index: u16) -> Vec<u8> {
+    match index {
+        0 => Vec::new(),
+        index => index.to_le_bytes().to_vec(),
+    }
+}
+
+/// Seed of a wallet's `WalletState`, after the pool and the wallet.
+pub const WALLET_SEED: &[u8] = b"wallet";
+
+/// Seed of the system account holding a pool's SOL rewards.
+pub const SOL_REWARD_VAULT_SEED: &[u8] = b"sol_rewards";
+
+/// Shortest extension `extend_sol_rewards` accepts (one day at the current
+/// rate), so dust top-ups can't be used to touch a campaign.
+const MIN_SOL_REWARD_EXTENSION: i64 = 24 * 60 * 60;
+
+pub const CAMPAIGN_MANAGER_SEED: &[u8] = b"campaign_manager";
+
+/// Seed of the PDA that signs for swap adapters. It only ever owns the
+/// scratch accounts a swap's input is moved into first, never the vault or
+/// the mint.
+pub const SWAP_AUTHORITY_SEED: &[u8] = b"swap";
+
+/// Seed of the PDA that signs for the restaking adapter. Like the swap
+/// authority it only owns a scratch account and the adapter's positions.
+pub const RESTAKING_AUTHORITY_SEED: &[u8] = b"restaking";
+
+/// Seed of the PDA a reward stream pays. It owns the account stream
+/// withdrawals land in before they move on to the vault.
+pub const STREAM_RECIPIENT_SEED: &[u8] = b"stream";
+
+pub const LOTTERY_SEED: &[u8] = b"lottery";
+
+/// Most winners one lottery draws, one bit each of `Lottery::claimed`.
+const MAX_LOTTERY_WINNERS: u8 = 8;
+
+pub const SHARD_SEED: &[u8] = b"shard";
+
+/// Seed of a shard's token account, after the shard.
+pub const SHARD_VAULT_SEED: &[u8] = b"shard_vault";
+
+pub const SHARD_BATCH_SEED: &[u8] = b"shard_batch";
+
+/// Deposit shards a pool can open.
+const MAX_STAKE_SHARDS: u8 = 16;
+
+/// SPL account-compression, which owns the trees of compressed positions.
+pub const COMPRESSION_PROGRAM_ID: Pubkey = pubkey!("cmtDvXumGCrqC1Age74AVPhSRVXJMd8PJS91L8KbNCK");
+
+/// SPL Memo, whose instruction must precede transfers in memo-required pools.
+pub const MEMO_PROGRAM_ID: Pubkey = pubkey!("MemoSq4gqABAXKb96qnH8TysNcWxMyWCqXgDLGmfcHr");
+
+/// SPL noop, through which the compression program logs tree changes.
+pub const NOOP_PROGRAM_ID: Pubkey = pubkey!("noopb9bkMVfRPU8AsbpTUg8AQkHtKwMYZiFUjNRtMmV");
+
+/// Most pools a `UserProfile` tracks positions in at once.
+const PROFILE_POOLS: usize = 16;
+
+/// Number of recent operations kept by the audit log.
+const AUDIT_LOG_SIZE: usize = 64;
+
+/// Bits of `Pool::disabled_instructions`.
+pub mod instruction_flags {
+    pub const DISTRIBUTE: u64 = 1 << 0;
+    pub const ENTER_STAKING: u64 = 1 << 1;
+    pub const LEAVE_STAKING: u64 = 1 << 2;
+    pub const CLAIM_REWARDS: u64 = 1 << 3;
+    pub const REQUEST_WITHDRAWAL: u64 = 1 << 4;
+    pub const SCHEDULED_DEPOSIT: u64 = 1 << 5;
+    pub const PULL_STAKE: u64 = 1 << 6;
+    pub const IMPORT_STAKE: u64 = 1 << 7;
+    pub const DEPLOY_FUNDS: u64 = 1 << 8;
+    pub const ZAP_STAKE: u64 = 1 << 9;
+}
+
+/// Codes for `Pool::incident_reason`. Other nonzero codes are free for the
+/// admin and watcher to use.
+pub mod incident_reasons {
+    /// `check_reserves` found liabilities above reserves.
+    pub const RESERVE_SHORTFALL: u16 = 1;
+
+    /// `reconcile` found the counters out of line with each other or the
+    /// vault.
+    pub const COUNTER_MISMATCH: u16 = 2;
+}
+
+/// Upper bound for the share of emissions diverted from stakers (50%).
+const MAX_REDIRECT_BPS: u16 = 5_000;
+
+/// Upper bound for deposit and withdrawal fees (10%).
+const MAX_FEE_BPS: u16 = 1_000;
+
+/// Upper bound for the early-withdrawal penalty (25%).
+const MAX_PENALTY_BPS: u16 = 2_500;
+
+/// How long a proposed mint authority change waits before it can be executed
+/// (two days).
+const AUTHORITY_ROTATION_DELAY: i64 = 2 * 24 * 60 * 60;
+
+/// How long a proposed vault rotation waits before it can be executed (two
+/// days).
+const VAULT_ROTATION_DELAY: i64 = 2 * 24 * 60 * 60;
+
+/// How long a newly proposed swap or claim adapter waits before it can be
+/// applied (two days).
+const ADAPTER_CHANGE_DELAY: i64 = 2 * 24 * 60 * 60;
+
+/// Upper bound for the lock duration (one year).
+const MAX_LOCK_DURATION: i64 = 365 * 24 * 60 * 60;
+
+/// Window over which withdrawals under the guardian threshold add up
+/// (one day).
+const GUARDIAN_WINDOW: i64 = 24 * 60 * 60;
+
+// A withdrawal can never be charged its whole amount.
+const _: () = assert!((MAX_FEE_BPS as u128) + (MAX_PENALTY_BPS as u128) < BPS_DENOMINATOR);
 
 #[error_code]
-enum PoolError {
+pub enum PoolError {
     #[msg("Invalid mint")]
     InvalidMint,
 
@@ -27,335 +270,15119 @@ enum PoolError {
 
     #[msg("Zero amount")]
     ZeroAmount,
-}
 
-type PoolResult<T = ()> = Result<T>;
+    #[msg("Pool invariant violated")]
+    PoolInvariantViolated,
 
-#[account(zero_copy)]
-pub struct Pool {
-    pub magic: u64,
+    #[msg("Unauthorized")]
+    Unauthorized,
 
-    /// Program signer
-    pub program_signer: Pubkey,
+    #[msg("Invalid emission schedule")]
+    InvalidEmissionSchedule,
 
-    /// The mint of the SPL token staked in.
-    pub mint: Pubkey,
+    #[msg("Invalid APR parameters")]
+    InvalidAprParameters,
 
-    /// Address of the account's token vault.
-    pub vault: Pubkey,
+    #[msg("Invalid streak boost parameters")]
+    InvalidStreakBoost,
 
-    /// Staked total
-    pub staked_total: u64,
+    #[msg("Invalid leaderboard")]
+    InvalidLeaderboard,
 
-    /// Program singer nonce.
-    pub nonce: u8,
+    #[msg("Invalid checkpoints")]
+    InvalidCheckpoints,
 
-    pub padding: [u8; 7],
-}
+    #[msg("Checkpoint page full")]
+    CheckpointPageFull,
 
-#[account]
-pub struct UserState {
-    pub initialized: bool,
-    pub staked_amount: u64,
-}
+    #[msg("Invalid restaking adapter")]
+    InvalidAdapter,
 
-impl UserState {
-    pub fn size() -> usize {
-        std::mem::size_of::<UserState>()
-    }
-}
+    #[msg("Liquidity buffer too low")]
+    InsufficientBuffer,
 
-#[derive(Accounts)]
-pub struct Initialize<'info> {
-    #[account(zero)]
-    pub pool: AccountLoader<'info, Pool>,
+    #[msg("Invalid withdrawal request")]
+    InvalidWithdrawalRequest,
 
-    /// CHECK:
-    #[account(constraint= mint.mint_authority == CSome(program_signer.key()) && mint.freeze_authority == CSome(authority.key()))]
-    mint: Box<Account<'info, Mint>>,
+    #[msg("Invalid pool config")]
+    InvalidPoolConfig,
 
-    /// CHECK:
-    pub program_signer: AccountInfo<'info>,
+    #[msg("Stake cap exceeded")]
+    StakeCapExceeded,
 
-    /// CHECK: staking vault
-    #[account(constraint = vault.mint == mint.key() && vault.owner == program_signer.key()  @PoolError::InvalidVault)]
-    vault: Box<Account<'info, TokenAccount>>,
+    #[msg("Invalid Merkle proof")]
+    InvalidMerkleProof,
 
-    #[account(mut)]
-    pub authority: Signer<'info>,
-}
+    #[msg("Migration escrow too low")]
+    InsufficientEscrow,
 
-#[derive(Accounts)]
-pub struct AirDrop<'info> {
-    #[account(owner = *__program_id )]
-    pub pool: AccountLoader<'info, Pool>,
+    #[msg("Invalid snapshot")]
+    InvalidSnapshot,
 
-    /// CHECK
-    pub program_signer: AccountInfo<'info>,
+    #[msg("Invalid guardian settings")]
+    InvalidGuardian,
 
-    /// CHECK:
-    #[account(mut)]
-    mint: Box<Account<'info, Mint>>,
+    #[msg("Withdrawal needs guardian approval or an elapsed announcement")]
+    GuardianApprovalRequired,
 
-    #[account(
-        mut,
-        constraint = (user_mint_acc.owner == *authority.key)
-    )]
-    pub user_mint_acc: Box<Account<'info, TokenAccount>>,
+    #[msg("Invalid session key")]
+    InvalidSessionKey,
 
-    /// CHECK
-    pub authority: Signer<'info>,
+    #[msg("Invalid VIP badge")]
+    InvalidVipBadge,
 
-    /// CHECK
-    #[account(executable, constraint = (token_program.key == &token::ID))]
-    pub token_program: AccountInfo<'info>,
-}
+    #[msg("Claim cooldown has not elapsed")]
+    ClaimCooldown,
 
-#[derive(Accounts)]
-pub struct InitializeUserState<'info> {
-    /// CHECK
-    #[account(
-        init,
-        seeds = [pool.key().as_ref(), authority.key.as_ref()],
-        bump,
-        payer = authority,
-        space = 8 + UserState::size()
-    )]
-    pub user_state: Account<'info, UserState>,
-    // pub user_state: UncheckedAccount<'info>,
-    /// CHECK
-    pub pool: UncheckedAccount<'info>,
+    #[msg("Invalid deposit schedule")]
+    InvalidDepositSchedule,
 
-    #[account(mut)]
-    pub authority: Signer<'info>,
+    #[msg("Scheduled deposit is not due yet")]
+    DepositNotDue,
 
-    pub system_program: Program<'info, System>,
-}
+    #[msg("Program signer is not approved for this amount")]
+    InsufficientDelegation,
 
-#[derive(Accounts)]
-pub struct EnterStaking<'info> {
-    #[account(mut, owner = *__program_id )]
-    pub pool: AccountLoader<'info, Pool>,
+    #[msg("Pool is paused")]
+    PoolPaused,
 
-    /// CHECK
-    mint: AccountInfo<'info>,
+    #[msg("Instruction is disabled for this pool")]
+    InstructionDisabled,
 
-    /// CHECK
-    #[account(mut)]
-    vault: AccountInfo<'info>,
+    #[msg("Invalid audit log")]
+    InvalidAuditLog,
 
-    #[account(
-         mut,
-         constraint = (user_mint_acc.owner == *authority.key && user_mint_acc.mint == *mint.key)
-     )]
-    user_mint_acc: Box<Account<'info, TokenAccount>>,
+    #[msg("Pool is in incident mode")]
+    IncidentActive,
 
-    /// CHECK
-    #[account(mut, seeds = [pool.key().as_ref(), authority.key().as_ref()], bump, owner = *__program_id)]
-    pub user_state: Account<'info, UserState>,
+    #[msg("Pool is not in incident mode")]
+    NoIncident,
 
-    /// CHECK
-    pub authority: Signer<'info>,
+    #[msg("Incident reason must be nonzero")]
+    InvalidIncident,
 
-    /// CHECK
-    #[account(executable, constraint = (token_program.key == &token::ID))]
-    pub token_program: AccountInfo<'info>,
-}
+    #[msg("Pool is settling a shortfall")]
+    ShortfallSettled,
 
-#[derive(Accounts)]
-pub struct LeaveStaking<'info> {
-    #[account(mut, owner = *__program_id )]
-    pub pool: AccountLoader<'info, Pool>,
+    #[msg("Invalid config change")]
+    InvalidConfigChange,
 
-    /// CHECK
-    pub program_signer: AccountInfo<'info>,
+    #[msg("Position is not the wallet's or is listed twice")]
+    InvalidPosition,
 
-    /// CHECK
-    mint: AccountInfo<'info>,
+    #[msg("Invalid cold storage settings")]
+    InvalidColdStorage,
 
-    /// CHECK
-    #[account(mut)]
-    vault: AccountInfo<'info>,
+    #[msg("Signer is not the approved integrator program")]
+    InvalidIntegrator,
 
-    #[account(
-         mut,
-         constraint = (user_mint_acc.owner == *authority.key && user_mint_acc.mint == *mint.key)
-     )]
-    user_mint_acc: Box<Account<'info, TokenAccount>>,
+    #[msg("Integrator allowance exceeded")]
+    AllowanceExceeded,
 
-    /// CHECK
-    #[account(mut, seeds = [pool.key().as_ref(), authority.key().as_ref()], bump, owner = *__program_id)]
-    pub user_state: Account<'info, UserState>,
+    #[msg("Milestone not reached or its achievement already minted")]
+    AchievementUnavailable,
 
-    /// CHECK
-    pub authority: Signer<'info>,
+    #[msg("Top-up too small to extend the campaign")]
+    ExtensionTooSmall,
 
-    pub token_program: Program<'info, Token>,
-}
+    #[msg("Pro-rata withdrawals are off, or the current batch isn't filled yet")]
+    WithdrawalBatchUnavailable,
 
-fn handle_initialize(ctx: Context<Initialize>, nonce: u8) -> PoolResult {
-    let (program_signer, nonce_found) = Pubkey::find_program_address(
-        &[
-            &ctx.accounts.mint.key().to_bytes(),
-            &ctx.accounts.pool.to_account_info().key.to_bytes(),
-        ],
-        ctx.program_id,
-    );
+    #[msg("Withdrawal queue must be empty")]
+    QueueNotEmpty,
 
-    require!(
-        nonce == nonce_found && ctx.accounts.program_signer.key() == program_signer,
-        PoolError::InvalidProgramSigner
-    );
+    #[msg("SOL campaign is run by another manager")]
+    CampaignNotOwned,
 
-    let pool = &mut ctx.accounts.pool.load_init()?;
+    #[msg("The pool's current terms must be accepted first")]
+    TermsNotAccepted,
 
-    pool.magic = INIT_MAGIC_NUMBER;
-    pool.mint = ctx.accounts.mint.key();
-    pool.vault = ctx.accounts.vault.key();
-    pool.program_signer = program_signer;
-    pool.nonce = nonce;
+    #[msg("Terms hash doesn't match the pool's")]
+    InvalidTerms,
 
-    Ok(())
-}
+    #[msg("Need one amount per listed token account")]
+    InvalidDistribution,
 
-fn handle_airdrop(ctx: Context<AirDrop>, amount: u64) -> PoolResult {
-    let pool = &ctx.accounts.pool.load()?;
+    #[msg("Distribution exceeds its cap")]
+    DistributionCapExceeded,
 
-    require!(pool.mint == ctx.accounts.mint.key(), PoolError::InvalidMint);
+    #[msg("Withdrawal needs an unexpired cold key approval")]
+    ColdApprovalRequired,
 
-    require!(
-        pool.program_signer == ctx.accounts.program_signer.key(),
-        PoolError::InvalidProgramSigner
-    );
+    #[msg("User state is still in use")]
+    UserStateActive,
 
-    require!(
-        pool.mint == ctx.accounts.user_mint_acc.mint,
-        PoolError::InvalidUserMintAccount
-    );
+    #[msg("Invalid claim adapter")]
+    InvalidClaimAdapter,
 
-    let seeds = &[
-        pool.mint.as_ref(),
-        ctx.accounts.pool.to_account_info().key.as_ref(),
-        &[pool.nonce],
-    ];
+    #[msg("Invalid SOL reward campaign")]
+    InvalidSolRewards,
 
-    let signer = &[&seeds[..]];
+    #[msg("SOL reward vault can't cover the claim")]
+    SolRewardsUnfunded,
 
-    let cpi_accounts = MintTo {
-        mint: ctx.accounts.mint.to_account_info(),
-        to: ctx.accounts.user_mint_acc.to_account_info(),
-        authority: ctx.accounts.program_signer.to_account_info(),
-    };
-    let cpi_ctx =
-        CpiContext::new_with_signer(ctx.accounts.token_program.clone(), cpi_accounts, signer);
+    #[msg("Claim exceeds pending rewards")]
+    ClaimExceedsRewards,
 
-    token::mint_to(cpi_ctx, amount)?;
+    #[msg("Attestation is still valid")]
+    AttestationNotRevoked,
 
-    Ok(())
-}
+    #[msg("Wallet was unwound and can't stake again")]
+    UserUnwound,
 
-fn handle_initialize_user_state(ctx: Context<InitializeUserState>) -> PoolResult {
-    ctx.accounts.user_state.initialized = true;
-    ctx.accounts.user_state.staked_amount = 0u64;
+    #[msg("A memo must precede this instruction")]
+    MemoRequired,
 
-    Ok(())
-}
+    #[msg("Exchange rate moved too far within a slot")]
+    RateChangeExceeded,
 
-fn handle_enter_staking(ctx: Context<EnterStaking>, amount: u64) -> PoolResult {
-    let pool = &mut ctx.accounts.pool.load_mut()?;
+    #[msg("Invalid rate guard")]
+    InvalidRateGuard,
 
-    require!(amount > 0, PoolError::ZeroAmount);
-    require_eq!(pool.mint, ctx.accounts.mint.key(), PoolError::InvalidMint);
-    require_eq!(
-        pool.vault,
-        ctx.accounts.vault.key(),
-        PoolError::InvalidVault
-    );
+    // Unused. `attest_stake` only exists in builds with the `wormhole`
+    // feature.
+    #[msg("Cross-chain attestations are not built in")]
+    AttestationsDisabled,
 
-    require!(
-        ctx.accounts.user_state.initialized,
-        PoolError::UserNotInitialized
-    );
+    #[msg("Compressed mode is off")]
+    CompressionDisabled,
 
-    let cpi_accounts = Transfer {
-        from: ctx.accounts.user_mint_acc.to_account_info(),
-        to: ctx.accounts.vault.to_account_info(),
-        authority: ctx.accounts.authority.to_account_info(),
-    };
-    let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts);
-    token::transfer(cpi_ctx, amount)?;
+    #[msg("Compressed mode is already on")]
+    CompressionEnabled,
 
-    pool.staked_total = pool.staked_total.safe_add(amount)?;
-    ctx.accounts.user_state.staked_amount =
-        ctx.accounts.user_state.staked_amount.safe_add(amount)?;
+    #[msg("User profile tracks too many pools")]
+    ProfileFull,
 
-    Ok(())
-}
+    #[msg("Invalid boost link")]
+    InvalidBoostLink,
 
-fn handle_leave_staking(ctx: Context<LeaveStaking>, amount: u64) -> PoolResult {
-    let pool = &mut ctx.accounts.pool.load_mut()?;
+    #[msg("Invalid reward stream")]
+    InvalidRewardStream,
 
-    require!(amount > 0, PoolError::ZeroAmount);
-    require_eq!(pool.mint, ctx.accounts.mint.key(), PoolError::InvalidMint);
-    require_eq!(
-        pool.vault,
-        ctx.accounts.vault.key(),
-        PoolError::InvalidVault
-    );
+    #[msg("Invalid swap adapter")]
+    InvalidSwapAdapter,
 
-    require!(
-        ctx.accounts.user_state.initialized,
-        PoolError::UserNotInitialized
-    );
+    #[msg("Swap returned less than the minimum output")]
+    SlippageExceeded,
 
-    let seeds = &[
-        ctx.accounts.mint.key.as_ref(),
-        ctx.accounts.pool.to_account_info().key.as_ref(),
-        &[pool.nonce],
-    ];
-    let signer = &[&seeds[..]];
+    #[msg("Invalid reward redirect")]
+    InvalidRedirect,
 
-    let cpi_accounts = Transfer {
-        from: ctx.accounts.vault.to_account_info(),
-        to: ctx.accounts.user_mint_acc.to_account_info(),
-        authority: ctx.accounts.program_signer.to_account_info(),
-    };
+    #[msg("Mint is blocked for new pools")]
+    MintBlocked,
 
-    let cpi_ctx = CpiContext::new_with_signer(
-        ctx.accounts.token_program.to_account_info(),
-        cpi_accounts,
-        signer,
-    );
-    token::transfer(cpi_ctx, amount)?;
+    #[msg("Mint is not on the allowlist")]
+    MintNotAllowed,
 
-    pool.staked_total = pool.staked_total.safe_sub(amount)?;
-    ctx.accounts.user_state.staked_amount =
-        ctx.accounts.user_state.staked_amount.safe_sub(amount)?;
+    #[msg("Program signer can no longer mint rewards")]
+    RewardsUnbacked,
 
-    Ok(())
-}
+    #[msg("Pool is already initialized")]
+    PoolAlreadyInitialized,
 
-#[program]
-pub mod staking_pool {
-    use super::*;
+    #[msg("No authority rotation is pending")]
+    NoPendingRotation,
 
-    pub fn initialize(ctx: Context<Initialize>, nonce: u8) -> PoolResult {
-        handle_initialize(ctx, nonce)
-    }
+    #[msg("Authority rotation is still timelocked")]
+    RotationTimelocked,
 
-    pub fn airdrop(ctx: Context<AirDrop>, amount: u64) -> PoolResult {
-        handle_airdrop(ctx, amount)
-    }
+    #[msg("No adapter change is pending")]
+    NoPendingAdapterChange,
 
-    pub fn initialize_user_state(ctx: Context<InitializeUserState>) -> PoolResult {
-        handle_initialize_user_state(ctx)
-    }
+    #[msg("Adapter change is still timelocked")]
+    AdapterChangeTimelocked,
 
-    pub fn enter_staking(ctx: Context<EnterStaking>, amount: u64) -> PoolResult {
-        handle_enter_staking(ctx, amount)
-    }
+    #[msg("Wallet state missing or not the position's")]
+    InvalidWalletState,
 
-    pub fn leave_staking(ctx: Context<LeaveStaking>, amount: u64) -> PoolResult {
-        handle_leave_staking(ctx, amount)
+    #[msg("Redirected rewards must be distributed first")]
+    RedirectPending,
+
+    #[msg("Reserves fell short of what the pool owes")]
+    ReserveAlarm,
+
+    #[msg("Account is already on the current layout")]
+    LayoutUpToDate,
+
+    #[msg("Reward reserve doesn't cover the emissions")]
+    EmissionsUnfunded,
+
+    #[msg("Invalid lottery or winner")]
+    InvalidLottery,
+
+    #[msg("Lottery hasn't been drawn")]
+    LotteryNotDrawn,
+
+    #[msg("Randomness already requested, or not revealed for the request")]
+    InvalidRandomness,
+
+    #[msg("Prize already claimed")]
+    PrizeClaimed,
+
+    #[msg("Ticket isn't the winner's")]
+    NotAWinner,
+
+    #[msg("Invalid stake shard, or the deposit was already merged")]
+    InvalidShard,
+
+    #[msg("Shard deposit must be credited first")]
+    ShardDepositPending,
+}
+
+type PoolResult<T = ()> = Result<T>;
+
+#[event]
+pub struct PointsSettled {
+    pub pool: Pubkey,
+    pub user: Pubkey,
+    pub points: u64,
+    pub settled_points: u64,
+    pub label: Option<[u8; 16]>,
+}
+
+#[event]
+pub struct FeeCharged {
+    pub pool: Pubkey,
+    pub user: Pubkey,
+    pub fee: u64,
+    pub exempt: bool,
+    pub label: Option<[u8; 16]>,
+}
+
+#[event]
+pub struct ReserveCheck {
+    pub pool: Pubkey,
+    pub reserves: u128,
+    pub liabilities: u128,
+    pub healthy: bool,
+}
+
+#[event]
+pub struct Reconciled {
+    pub pool: Pubkey,
+    pub reserves: u128,
+    pub liabilities: u128,
+    pub expected_protocol_fees: u64,
+    pub protocol_fees: u64,
+    pub queue_consistent: bool,
+    pub balanced: bool,
+}
+
+#[event]
+pub struct AuthorityRotationProposed {
+    pub pool: Pubkey,
+    pub kind: MintAuthority,
+    pub new_authority: Option<Pubkey>,
+    pub eta: i64,
+}
+
+#[event]
+pub struct MintAuthorityChanged {
+    pub pool: Pubkey,
+    pub kind: MintAuthority,
+    pub new_authority: Option<Pubkey>,
+}
+
+#[event]
+pub struct VaultRotationProposed {
+    pub pool: Pubkey,
+    pub new_vault: Pubkey,
+    pub eta: i64,
+}
+
+#[event]
+pub struct SwapAdapterProposed {
+    pub pool: Pubkey,
+    pub swap_adapter: Pubkey,
+    pub eta: i64,
+}
+
+#[event]
+pub struct ClaimAdapterProposed {
+    pub pool: Pubkey,
+    pub claim_adapter: Pubkey,
+    pub output_mint: Pubkey,
+    pub eta: i64,
+}
+
+#[event]
+pub struct VaultRotated {
+    pub pool: Pubkey,
+    pub old_vault: Pubkey,
+    pub new_vault: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+pub struct RewardsClaimedAndSwapped {
+    pub pool: Pubkey,
+    pub user: Pubkey,
+    pub amount: u64,
+    pub output_mint: Pubkey,
+    pub amount_out: u64,
+    pub label: Option<[u8; 16]>,
+}
+
+#[event]
+pub struct UserStateCollected {
+    pub pool: Pubkey,
+    pub user: Pubkey,
+    pub payer_lamports: u64,
+    pub cranker: Pubkey,
+    pub cranker_lamports: u64,
+    pub label: Option<[u8; 16]>,
+}
+
+#[event]
+pub struct EmissionsFunded {
+    pub pool: Pubkey,
+    pub funder: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+pub struct SolRewardsFunded {
+    pub pool: Pubkey,
+    pub funder: Pubkey,
+    pub lamports: u64,
+}
+
+#[event]
+pub struct SolRewardsExtended {
+    pub pool: Pubkey,
+    pub funder: Pubkey,
+    pub lamports: u64,
+    pub end_ts: i64,
+}
+
+#[event]
+pub struct SolRewardsClaimed {
+    pub pool: Pubkey,
+    pub user: Pubkey,
+    pub lamports: u64,
+    pub label: Option<[u8; 16]>,
+}
+
+#[event]
+pub struct Distributed {
+    pub pool: Pubkey,
+    pub recipients: u32,
+    pub total: u64,
+}
+
+#[event]
+pub struct TermsAccepted {
+    pub pool: Pubkey,
+    pub user: Pubkey,
+    pub terms_hash: [u8; 32],
+    pub label: Option<[u8; 16]>,
+}
+
+#[event]
+pub struct ShortfallSettled {
+    pub pool: Pubkey,
+    pub reserves: u128,
+    pub liabilities: u128,
+    pub haircut: u128,
+}
+
+#[event]
+pub struct RewardsRedirected {
+    pub pool: Pubkey,
+
+    /// `None` when the share was burned.
+    pub destination: Option<Pubkey>,
+    pub amount: u64,
+}
+
+#[event]
+pub struct RewardsClaimed {
+    pub pool: Pubkey,
+    pub user: Pubkey,
+    pub amount: u64,
+    pub label: Option<[u8; 16]>,
+}
+
+/// A claim that couldn't be paid in full; `owed` is left as an IOU.
+#[event]
+pub struct RewardsClaimedPartially {
+    pub pool: Pubkey,
+    pub user: Pubkey,
+    pub amount: u64,
+    pub owed: u64,
+    pub label: Option<[u8; 16]>,
+}
+
+#[event]
+pub struct RewardStreamClaimed {
+    pub pool: Pubkey,
+    pub stream: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+pub struct GrantStaked {
+    pub pool: Pubkey,
+    pub beneficiary: Pubkey,
+    pub treasury: Pubkey,
+    pub amount: u64,
+}
+
+/// A grant handed over to its beneficiary as a regular stake.
+#[event]
+pub struct GrantReleased {
+    pub pool: Pubkey,
+    pub beneficiary: Pubkey,
+    pub amount: u64,
+    pub rewards: u64,
+}
+
+#[event]
+pub struct GrantClawedBack {
+    pub pool: Pubkey,
+    pub beneficiary: Pubkey,
+    pub treasury: Pubkey,
+    pub amount: u64,
+    pub rewards: u64,
+}
+
+#[event]
+pub struct CompressedPositionOpened {
+    pub pool: Pubkey,
+    pub owner: Pubkey,
+    pub index: u32,
+}
+
+/// Emitted on every compressed stake change, so indexers can rebuild the
+/// leaf needed for the next one.
+#[event]
+pub struct CompressedPositionChanged {
+    pub pool: Pubkey,
+    pub owner: Pubkey,
+    pub index: u32,
+    pub position: CompressedPosition,
+}
+
+/// Payload of the Wormhole message published by `attest_stake`,
+/// Borsh-encoded.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StakeAttestation {
+    pub pool: Pubkey,
+    pub user: Pubkey,
+    pub staked_amount: u64,
+
+    /// Settled rewards not yet claimed.
+    pub pending_rewards: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct DustSwept {
+    pub pool: Pubkey,
+    pub destination: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+pub struct PoolInitialized {
+    pub pool: Pubkey,
+    pub authority: Pubkey,
+    pub mint: Pubkey,
+    pub vault: Pubkey,
+    pub program_signer: Pubkey,
+    pub nonce: u8,
+    pub token_program: Pubkey,
+
+    /// Economic parameters, copied from the template by `clone_pool`.
+    pub emission_rate: u64,
+    pub emission_schedule: EmissionSchedule,
+    pub dynamic_apr: bool,
+    pub utilization_target: u64,
+    pub min_apr_bps: u32,
+    pub max_apr_bps: u32,
+    pub streak_epoch_duration: i64,
+    pub streak_boost_bps: u32,
+    pub max_streak_boost_bps: u32,
+    pub points_mode: bool,
+    pub claim_cooldown: u32,
+    pub dust_policy: DustPolicy,
+}
+
+#[event]
+pub struct Staked {
+    pub pool: Pubkey,
+    pub user: Pubkey,
+    pub amount: u64,
+
+    /// Free-form reference from the depositor, e.g. a custodian's sub-account.
+    pub tag: Option<[u8; 32]>,
+
+    /// Label of the position staked into, if it has one.
+    pub label: Option<[u8; 16]>,
+}
+
+#[event]
+pub struct ColdWithdrawalRequested {
+    pub pool: Pubkey,
+    pub user: Pubkey,
+    pub amount: u64,
+    pub expires_ts: i64,
+    pub label: Option<[u8; 16]>,
+}
+
+#[event]
+pub struct ColdWithdrawalApproved {
+    pub pool: Pubkey,
+    pub user: Pubkey,
+    pub amount: u64,
+    pub label: Option<[u8; 16]>,
+}
+
+#[event]
+pub struct ProtocolFeesCollected {
+    pub pool: Pubkey,
+    pub amount: u64,
+    pub total_rewards_distributed: u64,
+    pub total_fees_collected: u64,
+    pub total_penalties: u64,
+}
+
+#[event]
+pub struct AchievementMinted {
+    pub pool: Pubkey,
+    pub user: Pubkey,
+    pub achievement: Achievement,
+    pub mint: Pubkey,
+    pub label: Option<[u8; 16]>,
+}
+
+#[event]
+pub struct Unstaked {
+    pub pool: Pubkey,
+    pub user: Pubkey,
+    pub amount: u64,
+    pub label: Option<[u8; 16]>,
+}
+
+#[event]
+pub struct WithdrawalQueued {
+    pub pool: Pubkey,
+    pub user: Pubkey,
+    pub id: u64,
+    pub amount: u64,
+    pub label: Option<[u8; 16]>,
+}
+
+#[event]
+pub struct StakeUnwound {
+    pub pool: Pubkey,
+    pub user: Pubkey,
+    pub compliance: Pubkey,
+    pub id: u64,
+    pub amount: u64,
+    pub label: Option<[u8; 16]>,
+}
+
+#[event]
+pub struct WithdrawalFulfilled {
+    pub pool: Pubkey,
+    pub user: Pubkey,
+    pub id: u64,
+    pub amount: u64,
+}
+
+#[event]
+pub struct WithdrawalPartiallyFilled {
+    pub pool: Pubkey,
+    pub user: Pubkey,
+    pub id: u64,
+    pub amount: u64,
+    pub remaining: u64,
+}
+
+#[event]
+pub struct WithdrawalBatchOpened {
+    pub pool: Pubkey,
+    pub batch: u64,
+    pub fill_rate: u128,
+    pub queued_amount: u64,
+}
+
+#[event]
+pub struct LotteryStarted {
+    pub pool: Pubkey,
+    pub epoch: u64,
+    pub winners: u8,
+    pub prize: u64,
+}
+
+#[event]
+pub struct LotteryDrawn {
+    pub pool: Pubkey,
+    pub epoch: u64,
+    pub randomness: [u8; 32],
+}
+
+#[event]
+pub struct LotteryPrizeCredited {
+    pub pool: Pubkey,
+    pub epoch: u64,
+    pub winner: u8,
+    pub user: Pubkey,
+    pub prize: u64,
+}
+
+#[event]
+pub struct ShardDeposited {
+    pub pool: Pubkey,
+    pub shard: u8,
+    pub user: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+pub struct ShardMerged {
+    pub pool: Pubkey,
+    pub shard: u8,
+    pub generation: u64,
+    pub amount: u64,
+}
+
+#[event]
+pub struct ShardDepositCredited {
+    pub pool: Pubkey,
+    pub user: Pubkey,
+    pub amount: u64,
+    pub rewards: u64,
+}
+
+#[account(zero_copy)]
+pub struct Pool {
+    pub magic: u64,
+
+    /// Program signer
+    pub program_signer: Pubkey,
+
+    /// The mint of the SPL token staked in.
+    pub mint: Pubkey,
+
+    /// Address of the account's token vault.
+    pub vault: Pubkey,
+
+    /// Staked total
+    pub staked_total: u64,
+
+    /// Program singer nonce.
+    pub nonce: u8,
+
+    pub padding: [u8; 7],
+
+    /// Pool admin, allowed to change reward parameters.
+    pub authority: Pubkey,
+
+    /// Last time the reward index was brought up to date.
+    pub last_update_ts: i64,
+
+    /// Rewards accrued per staked token, scaled by `REWARD_PRECISION`.
+    pub reward_per_token: u128,
+
+    /// Start of the current emission schedule.
+    pub emission_start_ts: i64,
+
+    /// Emission rate at `emission_start_ts`, in reward tokens per second.
+    pub emission_rate: u64,
+
+    /// Decay duration or halving interval, depending on the schedule.
+    pub emission_period: i64,
+
+    /// `EmissionSchedule` discriminant.
+    pub emission_schedule: u8,
+
+    pub reward_padding: [u8; 7],
+
+    /// Stake at which the dynamic APR bottoms out at `min_apr_bps`.
+    pub utilization_target: u64,
+
+    /// APR paid once `staked_total` reaches `utilization_target`.
+    pub min_apr_bps: u32,
+
+    /// APR paid to an empty pool.
+    pub max_apr_bps: u32,
+
+    /// When set, rewards follow the utilization curve instead of the
+    /// emission schedule.
+    pub dynamic_apr: u8,
+
+    pub apr_padding: [u8; 15],
+
+    /// Length of a streak epoch in seconds.
+    pub streak_epoch_duration: i64,
+
+    /// Reward boost earned per completed streak epoch.
+    pub streak_boost_bps: u32,
+
+    /// Cap on the total streak boost.
+    pub max_streak_boost_bps: u32,
+
+    /// When set, claims credit `UserState::points` instead of minting.
+    pub points_mode: u8,
+
+    pub points_padding: [u8; 15],
+
+    /// Start of the current leaderboard season.
+    pub season_start_ts: i64,
+
+    /// Current leaderboard season, 0 before the first one starts.
+    pub season: u32,
+
+    pub season_padding: [u8; 4],
+
+    /// Pages opened for the pool total's checkpoint history.
+    pub checkpoint_pages: u32,
+
+    /// When set, every stake change appends to the checkpoint histories.
+    pub checkpoints_enabled: u8,
+
+    pub checkpoint_padding: [u8; 11],
+
+    /// Whitelisted program vault funds may be deployed to,
+    /// `Pubkey::default()` when restaking is off.
+    pub adapter_program: Pubkey,
+
+    /// Principal currently held by the adapter.
+    pub deployed_amount: u64,
+
+    /// Share of `staked_total` that must stay in the vault for instant
+    /// withdrawals.
+    pub buffer_bps: u32,
+
+    pub adapter_padding: [u8; 4],
+
+    /// Id of the oldest unfulfilled withdrawal request.
+    pub queue_head: u64,
+
+    /// Id the next withdrawal request will get.
+    pub queue_tail: u64,
+
+    /// Unstaked amount still owed to queued requests.
+    pub queued_amount: u64,
+
+    pub queue_padding: [u8; 8],
+
+    /// Fees and penalties collected, still held in the vault.
+    pub protocol_fees: u64,
+
+    /// Set once the pool's `PoolConfig` exists; it is then required by every
+    /// stake change.
+    pub config_enabled: u8,
+
+    pub config_padding: [u8; 7],
+
+    /// Tokens deposited for legacy stake that hasn't been imported yet.
+    pub migration_escrow: u64,
+
+    pub migration_padding: [u8; 8],
+
+    /// Number of snapshots committed; also the id of the next one.
+    pub snapshot_count: u64,
+
+    pub snapshot_padding: [u8; 8],
+
+    /// Holders of this token may queue withdrawals in the priority lane,
+    /// `Pubkey::default()` when there is none.
+    pub vip_mint: Pubkey,
+
+    /// Head and tail ids of the priority lane, which is paid out before
+    /// the regular queue.
+    pub priority_head: u64,
+    pub priority_tail: u64,
+
+    /// Minimum number of seconds between two claims by the same user.
+    pub claim_cooldown: u32,
+
+    pub claim_padding: [u8; 12],
+
+    /// Rewards lost to rounding, in `reward_per_token` and in each user's
+    /// settlement, scaled by `REWARD_PRECISION`.
+    pub reward_dust: u128,
+
+    /// `DustPolicy` discriminant.
+    pub dust_policy: u8,
+
+    pub dust_padding: [u8; 15],
+
+    /// Set while the admin has paused deposits and claims.
+    pub paused: u8,
+
+    /// Whether rewards stop accruing during the current pause.
+    pub pause_freezes_rewards: u8,
+
+    pub pause_padding: [u8; 6],
+
+    pub paused_at: i64,
+
+    /// Seconds spent paused over the pool's lifetime.
+    pub total_paused_duration: i64,
+
+    pub pause_padding2: [u8; 8],
+
+    /// `instruction_flags` the admin has switched off.
+    pub disabled_instructions: u64,
+
+    pub flags_padding: [u8; 8],
+
+    /// Set once the pool's `AuditLog` exists; it is then required by every
+    /// instruction that moves stake or rewards.
+    pub audit_log_enabled: u8,
+
+    pub audit_padding: [u8; 15],
+
+    /// Raised by `check_reserves` when liabilities exceeded reserves; only
+    /// the admin clears it. Blocks withdrawals but emergency ones, fee
+    /// collection and deployments meanwhile.
+    pub reserve_alarm: u8,
+
+    pub alarm_padding: [u8; 15],
+
+    /// May declare incidents alongside the admin, `Pubkey::default()` when
+    /// unset.
+    pub watcher: Pubkey,
+
+    /// Why the pool is in incident mode, 0 when it isn't. See
+    /// `incident_reasons`.
+    pub incident_reason: u16,
+
+    pub incident_padding: [u8; 14],
+
+    /// Share of every payout written off after `settle_shortfall`, scaled by
+    /// `REWARD_PRECISION`. 0 while the pool is whole.
+    pub haircut: u128,
+
+    /// Pending change of a mint authority, see `propose_authority_rotation`.
+    /// `Pubkey::default()` as the target revokes the authority.
+    pub rotation_target: Pubkey,
+    pub rotation_eta: i64,
+
+    /// `MintAuthority` being rotated plus one, 0 when nothing is pending.
+    pub rotation_kind: u8,
+
+    pub rotation_padding: [u8; 7],
+
+    /// Share of emissions diverted from stakers, in bps.
+    pub redirect_bps: u16,
+
+    pub redirect_padding: [u8; 14],
+
+    /// Token account receiving the diverted share, `Pubkey::default()` to
+    /// burn it out of `funded_rewards`.
+    pub redirect_destination: Pubkey,
+
+    /// Diverted rewards not yet paid out, scaled by `REWARD_PRECISION`.
+    pub redirected_rewards: u128,
+
+    /// Whitelisted program swaps are routed through, `Pubkey::default()`
+    /// when swapping is off.
+    pub swap_adapter: Pubkey,
+
+    /// When set, every parameter change must record a `ConfigChange`.
+    pub config_history_enabled: u8,
+
+    pub history_padding: [u8; 7],
+
+    /// `ConfigChange` entries recorded so far; also the id of the next one.
+    pub config_change_count: u64,
+
+    /// Program of the stream funding rewards, `Pubkey::default()` when the
+    /// pool has none.
+    pub reward_stream_program: Pubkey,
+
+    /// Stream whose recipient is the program signer.
+    pub reward_stream: Pubkey,
+
+    /// Concurrent Merkle tree holding compressed positions,
+    /// `Pubkey::default()` when compressed mode is off.
+    pub compressed_tree: Pubkey,
+
+    /// Leaves appended to `compressed_tree`; also the index of the next one.
+    pub compressed_leaves: u64,
+
+    pub compression_padding: [u8; 8],
+
+    /// Copy of the critical state for light clients, see
+    /// `LIGHT_CLIENT_STATE_OFFSET`.
+    pub light_client_state: LightClientState,
+
+    /// Largest move of the exchange rate allowed within a slot, 0 when
+    /// unguarded.
+    pub max_rate_change_bps: u16,
+
+    pub rate_guard_padding: [u8; 6],
+
+    /// Slot `rate_guard_baseline` was taken in.
+    pub rate_guard_slot: u64,
+
+    /// Exchange rate at the first reward index update of `rate_guard_slot`.
+    pub rate_guard_baseline: u128,
+
+    /// When set, deposits and withdrawals must follow an SPL Memo instruction
+    /// in their transaction.
+    pub memo_required: u8,
+
+    pub memo_padding: [u8; 15],
+
+    /// May unwind users whose attestation was revoked, `Pubkey::default()`
+    /// when unset.
+    pub compliance: Pubkey,
+
+    /// Pending move of the vault, see `propose_vault_rotation`.
+    /// `Pubkey::default()` when nothing is pending.
+    pub vault_rotation_target: Pubkey,
+    pub vault_rotation_eta: i64,
+
+    pub vault_rotation_padding: [u8; 8],
+
+    /// Lamports accrued per staked token by the SOL campaign, scaled by
+    /// `REWARD_PRECISION`.
+    pub sol_reward_per_token: u128,
+
+    /// SOL campaign emission in lamports per second, paid from the
+    /// `SOL_REWARD_VAULT_SEED` account until `sol_emission_end_ts`.
+    pub sol_emission_rate: u64,
+    pub sol_emission_end_ts: i64,
+
+    /// Last time `sol_reward_per_token` was brought up to date.
+    pub sol_last_update_ts: i64,
+
+    pub sol_reward_padding: [u8; 8],
+
+    /// Whitelisted program claims may be swapped through,
+    /// `Pubkey::default()` when converting rewards is off.
+    pub claim_adapter: Pubkey,
+
+    /// The only token rewards may be converted into, e.g. USDC.
+    pub claim_output_mint: Pubkey,
+
+    /// Lifetime totals for reporting: rewards paid out, and the withdrawal
+    /// fees and penalties charged, collected or not.
+    pub total_rewards_distributed: u64,
+    pub total_fees_collected: u64,
+    pub total_penalties: u64,
+
+    /// Fees and penalties sent out by `collect_protocol_fees`.
+    pub total_fees_paid_out: u64,
+
+    /// When set, the withdrawal queue is paid out pro rata in batches
+    /// instead of in FIFO order, see `open_withdrawal_batch`.
+    pub pro_rata_withdrawals: u8,
+
+    pub batch_padding: [u8; 7],
+
+    /// Current batch, 0 before the first one opens.
+    pub batch_id: u64,
+
+    /// Share of its remaining amount every request in the batch is paid,
+    /// scaled by `REWARD_PRECISION`.
+    pub batch_fill_rate: u128,
+
+    /// Requests in either lane with lower ids belong to the batch.
+    pub batch_queue_tail: u64,
+    pub batch_priority_tail: u64,
+
+    /// Queued amount of the batch's requests that haven't been filled yet;
+    /// the next batch opens once it is 0.
+    pub batch_remaining: u64,
+
+    pub batch_padding2: [u8; 8],
+
+    /// Keeper key that may run the operational instructions, like
+    /// starting seasons, committing snapshots and distributing revenue,
+    /// but no parameter changes. `Pubkey::default()` when unset.
+    pub operator: Pubkey,
+
+    /// Who set the current SOL campaign, the authority or one of the pool's
+    /// campaign managers.
+    pub sol_campaign_owner: Pubkey,
+
+    /// Hash of the terms users must accept before staking, all zeros when
+    /// none are required.
+    pub terms_hash: [u8; 32],
+
+    /// Swap adapter proposed by `set_swap_adapter` and when it can be
+    /// applied, 0 when none is pending.
+    pub swap_adapter_target: Pubkey,
+    pub swap_adapter_eta: i64,
+
+    /// Claim adapter and output mint proposed by `set_claim_adapter` and
+    /// when they can be applied, 0 when none are pending.
+    pub claim_adapter_target: Pubkey,
+    pub claim_output_mint_target: Pubkey,
+    pub claim_adapter_eta: i64,
+
+    /// Yield shared into the reward index that is still in the vault.
+    /// Claims are paid from it first, so only emissions are minted.
+    pub funded_rewards: u64,
+
+    /// `POOL_LAYOUT_VERSION` the account was last written or migrated with.
+    pub layout_version: u8,
+
+    pub layout_padding: [u8; 7],
+
+    /// Tokens in the vault backing emissions that haven't accrued yet.
+    /// Accruing moves them to `funded_rewards`.
+    pub reward_reserve: u64,
+
+    /// Emissions stop at this time. 0 for never, which only pools from
+    /// before the reserve have.
+    pub emission_stop_ts: i64,
+
+    /// Tokens `distribute` may still mint. Set by the authority, drawn down
+    /// by every distribution.
+    pub distribution_cap: u64,
+
+    /// Lamports `attest_stake` charges on top of the bridge fee, paid to the
+    /// authority.
+    pub attestation_fee: u64,
+}
+
+/// The pool state bridges and light clients verify, at a fixed offset in
+/// the pool account so it can be read from an account proof without Anchor.
+/// Refreshed whenever staked tokens move and by `publish_state`.
+#[zero_copy]
+pub struct LightClientState {
+    pub staked_total: u64,
+
+    /// Slot the state was published at.
+    pub slot: u64,
+
+    /// `Pool::reward_per_token`.
+    pub reward_per_token: u128,
+
+    /// What one token staked since the pool started is worth, see
+    /// `Pool::exchange_rate`.
+    pub exchange_rate: u128,
+}
+
+/// Where rounding dust from reward distribution ends up.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DustPolicy {
+    /// Carried into the next index update.
+    NextDistribution,
+
+    /// Kept for the admin to sweep to the treasury.
+    Treasury,
+
+    /// Paid to whoever claims next, in whole tokens.
+    LastClaimant,
+}
+
+/// The stake mint's authorities, which the program signer holds once the
+/// pool is set up.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MintAuthority {
+    /// Mints distributions and rewards.
+    MintTokens,
+
+    FreezeAccount,
+}
+
+impl From<MintAuthority> for spl_token_2022::instruction::AuthorityType {
+    fn from(kind: MintAuthority) -> Self {
+        match kind {
+            MintAuthority::MintTokens => Self::MintTokens,
+            MintAuthority::FreezeAccount => Self::FreezeAccount,
+        }
+    }
+}
+
+/// How the emission rate evolves after `Pool::emission_start_ts`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EmissionSchedule {
+    /// The rate never changes.
+    Constant,
+
+    /// The rate falls linearly to zero over `duration` seconds.
+    LinearDecay { duration: i64 },
+
+    /// The rate halves every `interval` seconds.
+    Halving { interval: i64 },
+}
+
+impl Pool {
+    pub fn dust_policy(&self) -> DustPolicy {
+        match self.dust_policy {
+            1 => DustPolicy::Treasury,
+            2 => DustPolicy::LastClaimant,
+            _ => DustPolicy::NextDistribution,
+        }
+    }
+
+    /// Tokens the pool owes: stake, queued withdrawals, uncollected fees and
+    /// penalties, escrow for legacy stake, and rewards funded or reserved.
+    pub fn liabilities(&self) -> PoolResult<u128> {
+        // Each balance fits a `u64`, their sum may not.
+        Ok((self.staked_total as u128)
+            .safe_add(self.queued_amount as u128)?
+            .safe_add(self.protocol_fees as u128)?
+            .safe_add(self.migration_escrow as u128)?
+            .safe_add(self.funded_rewards as u128)?
+            .safe_add(self.reward_reserve as u128)?)
+    }
+
+    /// `amount` after the pool's haircut, rounded in the pool's favour.
+    pub fn written_down(&self, amount: u64) -> PoolResult<u64> {
+        u64::try_from(self.written_down_total(amount as u128)?)
+            .map_err(|_| ProgramError::ArithmeticOverflow.into())
+    }
+
+    /// `written_down` of a sum of balances.
+    pub fn written_down_total(&self, amount: u128) -> PoolResult<u128> {
+        if self.haircut == 0 {
+            return Ok(amount);
+        }
+
+        let cut = mul_div(amount, self.haircut, REWARD_PRECISION, Rounding::Liability)?;
+
+        Ok(amount.safe_sub(cut)?)
+    }
+
+    /// Shares `amount` that arrived in the vault among current stakers, kept
+    /// there as `funded_rewards` to pay their claims. With nothing staked it
+    /// stays in the vault as surplus.
+    fn distribute_yield(&mut self, amount: u64, now: i64) -> PoolResult {
+        if amount == 0 || self.staked_total == 0 {
+            return Ok(());
+        }
+
+        self.update_reward_index(now)?;
+        let (increment, dust) =
+            mul_div_rem(amount as u128, REWARD_PRECISION, self.staked_total as u128)?;
+        self.reward_per_token = self.reward_per_token.safe_add(increment)?;
+        self.reward_dust = self.reward_dust.safe_add(dust)?;
+        self.funded_rewards = self.funded_rewards.safe_add(amount)?;
+
+        Ok(())
+    }
+
+    /// Splits `rewards` into what `funded_rewards` covers, paid from the
+    /// vault and taken off the balance, and what is left to mint.
+    fn split_rewards(&mut self, rewards: u64) -> PoolResult<(u64, u64)> {
+        let funded = rewards.min(self.funded_rewards);
+        self.funded_rewards = self.funded_rewards.safe_sub(funded)?;
+
+        Ok((funded, rewards.safe_sub(funded)?))
+    }
+
+    /// What one token staked since the pool started is worth now, its
+    /// rewards included and after any haircut, scaled by `REWARD_PRECISION`.
+    /// It grows as rewards accrue, so it only moves with the reward index.
+    pub fn exchange_rate(&self) -> PoolResult<u128> {
+        mul_div(
+            REWARD_PRECISION.safe_add(self.reward_per_token)?,
+            REWARD_PRECISION.safe_sub(self.haircut)?,
+            REWARD_PRECISION,
+            Rounding::Payout,
+        )
+    }
+
+    /// Makes the current rate the guard's baseline at the first reward index
+    /// update of a slot. Scheduled emissions accrue before it is taken, so
+    /// only what moves the rate within a slot counts: yield shared out in
+    /// one go, or a donation.
+    fn take_rate_baseline(&mut self) -> PoolResult {
+        if self.max_rate_change_bps == 0 {
+            return Ok(());
+        }
+
+        let slot = Clock::get()?.slot;
+        if slot != self.rate_guard_slot {
+            self.rate_guard_slot = slot;
+            self.rate_guard_baseline = self.exchange_rate()?;
+        }
+
+        Ok(())
+    }
+
+    /// Publishes the light-client state, failing if the exchange rate moved
+    /// more than `max_rate_change_bps` within the slot.
+    fn publish_state(&mut self) -> PoolResult {
+        let slot = Clock::get()?.slot;
+
+        if self.max_rate_change_bps > 0 {
+            self.take_rate_baseline()?;
+
+            // A fully written down pool has no rate to measure against.
+            let baseline = self.rate_guard_baseline;
+            if baseline > 0 {
+                let change = self.exchange_rate()?.abs_diff(baseline);
+                require_gte!(
+                    baseline.safe_mul(self.max_rate_change_bps as u128)?,
+                    change.safe_mul(BPS_DENOMINATOR)?,
+                    PoolError::RateChangeExceeded
+                );
+            }
+        }
+
+        self.write_light_client_state(slot)
+    }
+
+    /// Publishes the light-client state unchecked and makes the current rate
+    /// the guard's baseline, for rate changes the admin settled on purpose.
+    fn rebase_rate_guard(&mut self) -> PoolResult {
+        let slot = Clock::get()?.slot;
+        self.write_light_client_state(slot)?;
+
+        self.rate_guard_slot = slot;
+        self.rate_guard_baseline = self.light_client_state.exchange_rate;
+
+        Ok(())
+    }
+
+    fn write_light_client_state(&mut self, slot: u64) -> PoolResult {
+        self.light_client_state = LightClientState {
+            staked_total: self.staked_total,
+            slot,
+            reward_per_token: self.reward_per_token,
+            exchange_rate: self.exchange_rate()?,
+        };
+
+        Ok(())
+    }
+
+    /// Tokens the pool holds, in the vault or deployed to the adapter.
+    pub fn reserves(&self, vault: &TokenAccount) -> PoolResult<u128> {
+        Ok((vault.amount as u128).safe_add(self.deployed_amount as u128)?)
+    }
+
+    /// What an emergency exit of `amount` pays: all of it while the pool is
+    /// covered, otherwise the same share of what is left as everyone else
+    /// leaving now, so the ratio for those who stay doesn't get worse.
+    fn emergency_payout(&self, vault: &TokenAccount, amount: u64) -> PoolResult<u64> {
+        let reserves = self.reserves(vault)?;
+        let liabilities = self.liabilities()?;
+        let payout = if reserves >= liabilities {
+            amount
+        } else {
+            mul_div_amount(amount, reserves, liabilities, Rounding::Payout)?
+        };
+        require_gte!(vault.amount, payout, PoolError::InsufficientBuffer);
+
+        Ok(payout)
+    }
+
+    fn require_enabled(&self, flag: u64) -> PoolResult {
+        require!(
+            self.disabled_instructions & flag == 0,
+            PoolError::InstructionDisabled
+        );
+
+        Ok(())
+    }
+
+    /// Copies the emission, APR, streak and reward-handling settings of
+    /// `template`. Balances, roles, adapters and anything tied to the
+    /// template's mint stay as they are. The emissions stop right away,
+    /// until the clone's own reserve is funded and they are extended.
+    fn copy_economics(&mut self, template: &Pool) {
+        self.emission_rate = template.emission_rate;
+        self.emission_period = template.emission_period;
+        self.emission_schedule = template.emission_schedule;
+        self.emission_stop_ts = self.emission_start_ts;
+
+        self.dynamic_apr = template.dynamic_apr;
+        self.utilization_target = template.utilization_target;
+        self.min_apr_bps = template.min_apr_bps;
+        self.max_apr_bps = template.max_apr_bps;
+
+        self.streak_epoch_duration = template.streak_epoch_duration;
+        self.streak_boost_bps = template.streak_boost_bps;
+        self.max_streak_boost_bps = template.max_streak_boost_bps;
+
+        self.points_mode = template.points_mode;
+        self.claim_cooldown = template.claim_cooldown;
+        self.dust_policy = template.dust_policy;
+    }
+
+    fn pending_rotation(&self) -> Option<MintAuthority> {
+        match self.rotation_kind {
+            1 => Some(MintAuthority::MintTokens),
+            2 => Some(MintAuthority::FreezeAccount),
+            _ => None,
+        }
+    }
+
+    fn rewards_frozen(&self) -> bool {
+        self.paused != 0 && self.pause_freezes_rewards != 0
+    }
+
+    /// Books fees and penalties charged, held in the vault until collected.
+    fn book_charges(&mut self, fee: u64, penalty: u64) -> PoolResult {
+        self.protocol_fees = self.protocol_fees.safe_add(fee)?.safe_add(penalty)?;
+        self.total_fees_collected = self.total_fees_collected.safe_add(fee)?;
+        self.total_penalties = self.total_penalties.safe_add(penalty)?;
+
+        Ok(())
+    }
+
+    fn record_distribution(&mut self, rewards: u64) -> PoolResult {
+        self.total_rewards_distributed = self.total_rewards_distributed.safe_add(rewards)?;
+
+        Ok(())
+    }
+
+    /// Whether `key` may run operational instructions: the admin, or the
+    /// operator if one is set.
+    fn can_operate(&self, key: Pubkey) -> bool {
+        key == self.authority || (self.operator != Pubkey::default() && key == self.operator)
+    }
+
+    /// Marks `request` as filled in the open batch and returns the amount
+    /// it is paid.
+    fn fill_from_batch(&mut self, request: &mut WithdrawalRequest) -> PoolResult<u64> {
+        let tail = match request.priority {
+            true => self.batch_priority_tail,
+            false => self.batch_queue_tail,
+        };
+        require!(
+            request.id < tail && request.batch < self.batch_id,
+            PoolError::InvalidWithdrawalRequest
+        );
+        request.batch = self.batch_id;
+        self.batch_remaining = self.batch_remaining.safe_sub(request.amount)?;
+
+        // The rate never exceeds one, so neither does the fill.
+        Ok(mul_div(
+            request.amount as u128,
+            self.batch_fill_rate,
+            REWARD_PRECISION,
+            Rounding::Payout,
+        )? as u64)
+    }
+
+    /// Takes the whole tokens out of `reward_dust`, leaving the fraction.
+    fn take_dust(&mut self) -> PoolResult<u64> {
+        take_whole_tokens(&mut self.reward_dust)
+    }
+
+    /// Takes the whole tokens out of `redirected_rewards`.
+    fn take_redirected(&mut self) -> PoolResult<u64> {
+        take_whole_tokens(&mut self.redirected_rewards)
+    }
+
+    pub fn emission_schedule(&self) -> EmissionSchedule {
+        match self.emission_schedule {
+            1 => EmissionSchedule::LinearDecay {
+                duration: self.emission_period,
+            },
+            2 => EmissionSchedule::Halving {
+                interval: self.emission_period,
+            },
+            _ => EmissionSchedule::Constant,
+        }
+    }
+
+    fn set_emission_schedule(&mut self, schedule: EmissionSchedule) -> PoolResult {
+        let (kind, period) = match schedule {
+            EmissionSchedule::Constant => (0, 0),
+            EmissionSchedule::LinearDecay { duration } => (1, duration),
+            EmissionSchedule::Halving { interval } => (2, interval),
+        };
+
+        require!(kind == 0 || period > 0, PoolError::InvalidEmissionSchedule);
+
+        self.emission_schedule = kind;
+        self.emission_period = period;
+
+        Ok(())
+    }
+
+    /// Total rewards emitted in the first `elapsed` seconds of the schedule.
+    fn cumulative_emission(&self, elapsed: i64) -> PoolResult<u128> {
+        let elapsed = elapsed.max(0) as u128;
+        let rate = self.emission_rate as u128;
+
+        match self.emission_schedule() {
+            EmissionSchedule::Constant => Ok(rate.safe_mul(elapsed)?),
+            EmissionSchedule::LinearDecay { duration } => {
+                let duration = duration as u128;
+                let elapsed = elapsed.min(duration);
+                let decayed = rate
+                    .safe_mul(elapsed)?
+                    .safe_mul(elapsed)?
+                    .safe_div(duration.safe_mul(2)?)?;
+
+                Ok(rate.safe_mul(elapsed)?.safe_sub(decayed)?)
+            }
+            EmissionSchedule::Halving { interval } => {
+                let interval = interval as u128;
+                let halvings = elapsed / interval;
+
+                let mut emitted = 0u128;
+                for period in 0..halvings.min(u64::BITS as u128) {
+                    emitted = emitted.safe_add((rate >> period).safe_mul(interval)?)?;
+                }
+                if halvings < u64::BITS as u128 {
+                    let remainder = elapsed.safe_sub(halvings.safe_mul(interval)?)?;
+                    emitted = emitted.safe_add((rate >> halvings).safe_mul(remainder)?)?;
+                }
+
+                Ok(emitted)
+            }
+        }
+    }
+
+    /// Per-second emission `elapsed` seconds into the schedule.
+    fn emission_rate_at(&self, elapsed: i64) -> PoolResult<u128> {
+        let elapsed = elapsed.max(0) as u128;
+        let rate = self.emission_rate as u128;
+
+        match self.emission_schedule() {
+            EmissionSchedule::Constant => Ok(rate),
+            EmissionSchedule::LinearDecay { duration } => {
+                let duration = duration as u128;
+                let remaining = duration.saturating_sub(elapsed);
+
+                Ok(rate.safe_mul(remaining)?.safe_div(duration)?)
+            }
+            EmissionSchedule::Halving { interval } => {
+                let halvings = elapsed / interval as u128;
+
+                Ok(rate
+                    .checked_shr(halvings.min(u32::MAX as u128) as u32)
+                    .unwrap_or(0))
+            }
+        }
+    }
+
+    /// Time the schedule stops emitting, `now` if it emits nothing and
+    /// `None` if it never stops.
+    pub fn emission_end_ts(&self, now: i64) -> PoolResult<Option<i64>> {
+        if self.emission_rate == 0 {
+            return Ok(Some(now));
+        }
+
+        let stop = Some(self.emission_stop_ts).filter(|stop| *stop != 0);
+        let duration = match self.emission_schedule() {
+            EmissionSchedule::Constant => return Ok(stop),
+            EmissionSchedule::LinearDecay { duration } => Some(duration),
+            // The rate rounds down to zero after this many halvings.
+            EmissionSchedule::Halving { .. } => self
+                .emission_period
+                .checked_mul((u64::BITS - self.emission_rate.leading_zeros()) as i64),
+        };
+
+        let end = duration
+            .and_then(|duration| self.emission_start_ts.checked_add(duration))
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+
+        Ok(Some(stop.map_or(end, |stop| stop.min(end))))
+    }
+
+    /// Rewards the schedule emits from `last_update_ts` until it stops,
+    /// `None` if it never does. Under dynamic APR, the most it can emit:
+    /// `max_apr_bps` on the full utilization target.
+    fn committed_emissions(&self) -> PoolResult<Option<u64>> {
+        if self.dynamic_apr != 0 {
+            if self.emission_stop_ts == 0 {
+                return Ok(None);
+            }
+            let seconds = (self.emission_stop_ts - self.last_update_ts).max(0) as u128;
+            let committed = mul_div(
+                (self.max_apr_bps as u128)
+                    .safe_mul(self.utilization_target as u128)?
+                    .safe_mul(seconds)?,
+                1,
+                BPS_DENOMINATOR.safe_mul(SECONDS_PER_YEAR)?,
+                Rounding::Liability,
+            )?;
+
+            return Ok(Some(
+                u64::try_from(committed).map_err(|_| ProgramError::ArithmeticOverflow)?,
+            ));
+        }
+        if self.emission_rate == 0 {
+            return Ok(Some(0));
+        }
+        let Some(end) = self.emission_end_ts(self.last_update_ts)? else {
+            return Ok(None);
+        };
+
+        let committed = self
+            .cumulative_emission(end - self.emission_start_ts)?
+            .saturating_sub(
+                self.cumulative_emission(self.last_update_ts - self.emission_start_ts)?,
+            );
+
+        Ok(Some(
+            u64::try_from(committed).map_err(|_| ProgramError::ArithmeticOverflow)?,
+        ))
+    }
+
+    /// Checks that `reward_reserve` covers everything the schedule still
+    /// emits. The index must already be up to date.
+    fn require_emissions_funded(&self) -> PoolResult {
+        match self.committed_emissions()? {
+            Some(committed) if committed <= self.reward_reserve => Ok(()),
+            _ => err!(PoolError::EmissionsUnfunded),
+        }
+    }
+
+    /// APR on the utilization curve: `max_apr_bps` for an empty pool, falling
+    /// linearly to `min_apr_bps` at `utilization_target` and flat beyond it.
+    pub fn current_apr_bps(&self) -> u128 {
+        let (min_apr, max_apr) = (self.min_apr_bps as u128, self.max_apr_bps as u128);
+
+        if self.staked_total >= self.utilization_target {
+            return min_apr;
+        }
+
+        max_apr - (max_apr - min_apr) * self.staked_total as u128 / self.utilization_target as u128
+    }
+
+    /// Accrues everything emitted since `last_update_ts` into
+    /// `reward_per_token`, and the SOL campaign into `sol_reward_per_token`.
+    /// Emissions while nothing is staked are dropped.
+    fn update_reward_index(&mut self, now: i64) -> PoolResult {
+        self.accrue_emissions(now)?;
+        self.take_rate_baseline()
+    }
+
+    fn accrue_emissions(&mut self, now: i64) -> PoolResult {
+        self.update_sol_reward_index(now)?;
+
+        if now <= self.last_update_ts {
+            return Ok(());
+        }
+
+        if self.rewards_frozen() {
+            self.last_update_ts = now;
+            return Ok(());
+        }
+
+        if self.staked_total > 0 {
+            let end = match self.emission_stop_ts {
+                0 => now,
+                stop => now.min(stop),
+            };
+            let increment = if self.dynamic_apr != 0 {
+                // Every staked token earns the same APR, so the index moves
+                // independently of how much is staked.
+                let increment = mul_div(
+                    self.current_apr_bps()
+                        .safe_mul((end - self.last_update_ts).max(0) as u128)?,
+                    REWARD_PRECISION,
+                    BPS_DENOMINATOR.safe_mul(SECONDS_PER_YEAR)?,
+                    Rounding::Payout,
+                )?;
+
+                if self.emission_stop_ts == 0 {
+                    increment
+                } else {
+                    // A funded curve pays out of the reserve and stops
+                    // when it runs dry, whatever the utilization.
+                    let owed = mul_div(
+                        increment,
+                        self.staked_total as u128,
+                        REWARD_PRECISION,
+                        Rounding::Payout,
+                    )?;
+                    let drawn = (self.reward_reserve as u128).min(owed) as u64;
+                    self.reward_reserve = self.reward_reserve.safe_sub(drawn)?;
+                    self.funded_rewards = self.funded_rewards.safe_add(drawn)?;
+
+                    match (drawn as u128) < owed {
+                        true => mul_div(
+                            drawn as u128,
+                            REWARD_PRECISION,
+                            self.staked_total as u128,
+                            Rounding::Payout,
+                        )?,
+                        false => increment,
+                    }
+                }
+            } else {
+                let carried = match self.dust_policy() {
+                    DustPolicy::NextDistribution => std::mem::take(&mut self.reward_dust),
+                    _ => 0,
+                };
+                let tokens = self
+                    .cumulative_emission(end - self.emission_start_ts)?
+                    .saturating_sub(
+                        self.cumulative_emission(self.last_update_ts - self.emission_start_ts)?,
+                    );
+
+                // What the reserve backs is paid from the vault from now on.
+                let drawn = (self.reward_reserve as u128).min(tokens) as u64;
+                self.reward_reserve = self.reward_reserve.safe_sub(drawn)?;
+                self.funded_rewards = self.funded_rewards.safe_add(drawn)?;
+
+                let emitted = tokens.safe_mul(REWARD_PRECISION)?.safe_add(carried)?;
+                let increment = emitted.safe_div(self.staked_total as u128)?;
+
+                self.reward_dust = self
+                    .reward_dust
+                    .safe_add(emitted.safe_sub(increment.safe_mul(self.staked_total as u128)?)?)?;
+
+                increment
+            };
+
+            let redirected = mul_div(
+                increment,
+                self.redirect_bps as u128,
+                BPS_DENOMINATOR,
+                Rounding::Payout,
+            )?;
+            self.redirected_rewards = self
+                .redirected_rewards
+                .safe_add(redirected.safe_mul(self.staked_total as u128)?)?;
+
+            self.reward_per_token = self
+                .reward_per_token
+                .safe_add(increment.safe_sub(redirected)?)?;
+        }
+        self.last_update_ts = now;
+
+        Ok(())
+    }
+
+    /// Accrues the SOL campaign up to `now`, or its end, like a constant
+    /// emission schedule. Rounding dust stays in the vault.
+    fn update_sol_reward_index(&mut self, now: i64) -> PoolResult {
+        let end = now.min(self.sol_emission_end_ts);
+
+        if end > self.sol_last_update_ts && self.staked_total > 0 && !self.rewards_frozen() {
+            let emitted = (self.sol_emission_rate as u128)
+                .safe_mul((end - self.sol_last_update_ts) as u128)?
+                .safe_mul(REWARD_PRECISION)?;
+
+            self.sol_reward_per_token = self
+                .sol_reward_per_token
+                .safe_add(emitted.safe_div(self.staked_total as u128)?)?;
+        }
+        self.sol_last_update_ts = self.sol_last_update_ts.max(now);
+
+        Ok(())
+    }
+}
+
+/// Takes the whole tokens out of an amount scaled by `REWARD_PRECISION`,
+/// leaving the fraction.
+fn take_whole_tokens(scaled: &mut u128) -> PoolResult<u64> {
+    let amount = scaled.safe_div(REWARD_PRECISION)?;
+    *scaled = scaled.safe_sub(amount.safe_mul(REWARD_PRECISION)?)?;
+
+    u64::try_from(amount).map_err(|_| ProgramError::ArithmeticOverflow.into())
+}
+
+#[account]
+pub struct UserState {
+    pub initialized: bool,
+    pub staked_amount: u64,
+
+    /// `Pool::reward_per_token` at the last settlement.
+    pub reward_per_token_paid: u128,
+
+    /// Settled rewards not yet claimed.
+    pub pending_rewards: u64,
+
+    /// Start of the current run of continuous nonzero stake.
+    pub streak_start_ts: i64,
+
+    /// Completed streak epochs as of the last settlement.
+    pub streak_epochs: u64,
+
+    /// Claimed rewards held as points, awaiting settlement by the authority.
+    pub points: u64,
+
+    /// Points settled so far.
+    pub settled_points: u64,
+
+    /// Time stake-seconds were last accrued.
+    pub last_settle_ts: i64,
+
+    /// Season `season_stake_seconds` belongs to.
+    pub season: u32,
+
+    /// Stake integrated over time during `season`.
+    pub season_stake_seconds: u128,
+
+    /// Wallet voting with this stake, `Pubkey::default()` for the owner.
+    pub vote_delegate: Pubkey,
+
+    /// Pages opened for this user's checkpoint history.
+    pub checkpoint_pages: u32,
+
+    /// Withdrawals before this time pay the early-withdrawal penalty.
+    pub locked_until: i64,
+
+    /// Co-signer for withdrawals above `guardian_threshold`,
+    /// `Pubkey::default()` when unset.
+    pub guardian: Pubkey,
+    pub guardian_threshold: u64,
+
+    /// How long an announced withdrawal waits before it can skip the
+    /// guardian.
+    pub guardian_delay: i64,
+
+    pub announced_amount: u64,
+    pub announced_ts: i64,
+
+    /// Key allowed to claim on the user's behalf until
+    /// `session_expiry_slot`, `Pubkey::default()` when unset.
+    pub session_key: Pubkey,
+    pub session_expiry_slot: u64,
+
+    pub last_claim_ts: i64,
+
+    /// Boost from stake in the pool's `BoostLink`, as of the last sync.
+    pub link_boost_bps: u32,
+
+    /// Queued for exit by `force_unwind`; the user can't stake again.
+    pub unwound: bool,
+
+    /// Rewards claimed while the program signer couldn't mint them, paid
+    /// out by the next claim that can.
+    pub reward_iou: u64,
+
+    /// `Pool::sol_reward_per_token` at the last settlement.
+    pub sol_reward_per_token_paid: u128,
+
+    /// Settled SOL rewards not yet claimed, in lamports.
+    pub sol_pending_rewards: u64,
+
+    /// Paid the account's rent, which goes back to it when the account is
+    /// garbage collected.
+    pub payer: Pubkey,
+
+    /// Which of the wallet's positions in the pool this is, 0 for the
+    /// default one.
+    pub index: u16,
+
+    /// A custodian's name for an indexed position, carried in its events so
+    /// sub-account flows reconcile from the logs.
+    pub label: Option<[u8; 16]>,
+
+    /// While set, stake only leaves to the token account `cold_destination`,
+    /// in a withdrawal the user requested and this key approved within
+    /// `cold_window` seconds. `Pubkey::default()` when unset.
+    pub cold_key: Pubkey,
+    pub cold_destination: Pubkey,
+    pub cold_window: i64,
+
+    pub cold_requested_amount: u64,
+    pub cold_requested_ts: i64,
+    pub cold_approved: bool,
+
+    /// Program allowed to stake and unstake for the user through CPI, up to
+    /// `integrator_allowance` tokens in total. `Pubkey::default()` when unset.
+    pub integrator: Pubkey,
+    pub integrator_allowance: u64,
+
+    /// Unused. Achievements are tracked per wallet in `WalletAchievements`.
+    pub legacy_achievements: u8,
+
+    /// `Pool::terms_hash` the user last accepted.
+    pub terms_hash: [u8; 32],
+
+    /// Withdrawn without the guardian since `guardian_window_start`, held
+    /// to `guardian_threshold` for `GUARDIAN_WINDOW`.
+    pub guardian_window_start: i64,
+    pub guardian_window_amount: u64,
+
+    /// Key allowed to `pull_stake` for the user besides the user themself.
+    /// `Pubkey::default()` when unset.
+    pub puller: Pubkey,
+
+    /// Position in another pool this stake boosts through its `BoostLink`.
+    /// Its boost is dropped when this stake shrinks. `Pubkey::default()`
+    /// when unset.
+    pub link_boosted: Pubkey,
+
+    /// The wallet holds several positions, whose stake and unwinding its
+    /// `WalletState` tracks; every stake change then has to pass it.
+    pub wallet_tracked: bool,
+
+    /// `USER_STATE_LAYOUT_VERSION` the account was last written or migrated
+    /// with.
+    pub layout_version: u8,
+}
+
+/// Stake of a wallet holding several positions in a pool, which the
+/// per-user cap applies to, and whether any of them was force-unwound.
+/// Leaderboard scores and voting history follow this total.
+#[account]
+pub struct WalletState {
+    pub pool: Pubkey,
+    pub wallet: Pubkey,
+    pub staked_amount: u64,
+    pub unwound: bool,
+
+    /// Pages opened for the wallet's checkpoint history.
+    pub checkpoint_pages: u32,
+
+    /// Season `season_stake_seconds` belongs to.
+    pub season: u32,
+
+    /// `staked_amount` integrated over time during `season`.
+    pub season_stake_seconds: u128,
+
+    /// Time stake-seconds were last accrued.
+    pub last_settle_ts: i64,
+}
+
+impl WalletState {
+    pub fn size() -> usize {
+        std::mem::size_of::<WalletState>()
+    }
+
+    /// Accrues the wallet's season stake-seconds up to `now`, like
+    /// `UserState::update_rewards` does for a single position. Must run
+    /// before `staked_amount` changes.
+    pub fn settle_season(&mut self, pool: &Pool, now: i64) -> PoolResult<u128> {
+        if self.season != pool.season {
+            // Time before the season started does not count towards it.
+            self.season = pool.season;
+            self.season_stake_seconds = 0;
+            self.last_settle_ts = self.last_settle_ts.max(pool.season_start_ts);
+        }
+        self.season_stake_seconds = self.season_stake_seconds.safe_add(
+            (self.staked_amount as u128).safe_mul((now - self.last_settle_ts).max(0) as u128)?,
+        )?;
+        self.last_settle_ts = now;
+
+        Ok(self.season_stake_seconds)
+    }
+}
+
+/// Achievement NFTs a wallet minted in a pool. Never closed, so reopening or
+/// adding positions doesn't earn them again.
+#[account]
+pub struct WalletAchievements {
+    pub pool: Pubkey,
+    pub wallet: Pubkey,
+
+    /// `Achievement::bit`s of the NFTs already minted.
+    pub achievements: u8,
+}
+
+impl WalletAchievements {
+    pub fn size() -> usize {
+        std::mem::size_of::<WalletAchievements>()
+    }
+}
+
+impl UserState {
+    /// Checks that `signer` is the user or their unexpired session key.
+    fn authorize_session(&self, user: Pubkey, signer: Pubkey) -> PoolResult {
+        if signer == user {
+            return Ok(());
+        }
+
+        require!(
+            self.session_key != Pubkey::default()
+                && signer == self.session_key
+                && Clock::get()?.slot < self.session_expiry_slot,
+            PoolError::InvalidSessionKey
+        );
+
+        Ok(())
+    }
+
+    /// Under two-key withdrawals, lets the requested and approved exit to
+    /// `destination` through until the window closes. The approval is used
+    /// up.
+    fn authorize_cold_withdrawal(
+        &mut self,
+        destination: Pubkey,
+        amount: u64,
+        now: i64,
+    ) -> PoolResult {
+        if self.cold_key == Pubkey::default() {
+            return Ok(());
+        }
+
+        require!(
+            self.cold_approved
+                && destination == self.cold_destination
+                && amount == self.cold_requested_amount
+                && now <= self.cold_requested_ts.saturating_add(self.cold_window),
+            PoolError::ColdApprovalRequired
+        );
+        self.cold_approved = false;
+        self.cold_requested_amount = 0;
+
+        Ok(())
+    }
+
+    /// Spends `amount` of the allowance if `authority` is the approved
+    /// integrator's `INTEGRATOR_SEED` PDA, which only that program can sign
+    /// for.
+    fn spend_allowance(&mut self, authority: Pubkey, amount: u64) -> PoolResult {
+        require!(
+            self.integrator != Pubkey::default()
+                && authority
+                    == Pubkey::find_program_address(&[INTEGRATOR_SEED], &self.integrator).0,
+            PoolError::InvalidIntegrator
+        );
+        self.integrator_allowance = self
+            .integrator_allowance
+            .checked_sub(amount)
+            .ok_or(PoolError::AllowanceExceeded)?;
+
+        Ok(())
+    }
+
+    /// Lets a withdrawal of `amount` through if it keeps the withdrawals of
+    /// the last `GUARDIAN_WINDOW` under the threshold, is co-signed by the
+    /// guardian, or is covered by an announcement whose delay has passed.
+    /// The announcement is used up.
+    fn authorize_withdrawal(
+        &mut self,
+        guardian: Option<&Signer>,
+        amount: u64,
+        now: i64,
+    ) -> PoolResult {
+        if self.guardian == Pubkey::default() {
+            return Ok(());
+        }
+
+        if now >= self.guardian_window_start.saturating_add(GUARDIAN_WINDOW) {
+            self.guardian_window_start = now;
+            self.guardian_window_amount = 0;
+        }
+        let windowed = self.guardian_window_amount.safe_add(amount)?;
+        if windowed <= self.guardian_threshold {
+            self.guardian_window_amount = windowed;
+            return Ok(());
+        }
+
+        if guardian.is_some_and(|guardian| guardian.key() == self.guardian) {
+            return Ok(());
+        }
+
+        require!(
+            amount <= self.announced_amount
+                && now >= self.announced_ts.saturating_add(self.guardian_delay),
+            PoolError::GuardianApprovalRequired
+        );
+        self.announced_amount -= amount;
+
+        Ok(())
+    }
+}
+
+/// Arguments of `stake`. Parameters are added as new versions, so clients
+/// keep working with the versions they know.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StakeArgs {
+    V1 { amount: u64, tag: Option<[u8; 32]> },
+}
+
+/// Arguments of `unstake`, versioned like `StakeArgs`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnstakeArgs {
+    V1 { amount: u64 },
+}
+
+/// Arguments of `claim`, versioned like `StakeArgs`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClaimArgs {
+    V1 { amount: Option<u64> },
+}
+
+/// Economic parameters of a pool. Zero disables a fee, penalty or cap.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct PoolConfigParams {
+    pub deposit_fee_bps: u16,
+    pub withdrawal_fee_bps: u16,
+    pub early_withdrawal_penalty_bps: u16,
+    pub lock_duration: i64,
+    pub max_stake_per_user: u64,
+    pub max_staked_total: u64,
+
+    /// Per-user cap for wallets with an attestation, replacing
+    /// `max_stake_per_user`. Zero leaves attested wallets at the default
+    /// cap; otherwise it can only raise a set default.
+    pub verified_max_stake_per_user: u64,
+
+    /// Program issuing attestations, `Pubkey::default()` when unused. An
+    /// attestation is the account it owns at the PDA
+    /// `[attestation_schema, user]`, laid out as `AttestationData`.
+    pub attestation_program: Pubkey,
+    pub attestation_schema: Pubkey,
+
+    /// Seconds without activity after which an empty user state can be
+    /// closed by anyone with `gc_user_state`.
+    pub gc_inactivity: i64,
+
+    /// Share of the reclaimed rent paid to whoever closes it, the rest goes
+    /// back to the payer.
+    pub gc_cranker_bps: u16,
+}
+
+impl PoolConfigParams {
+    fn validate(&self) -> PoolResult {
+        require!(
+            self.deposit_fee_bps <= MAX_FEE_BPS
+                && self.withdrawal_fee_bps <= MAX_FEE_BPS
+                && self.early_withdrawal_penalty_bps <= MAX_PENALTY_BPS
+                && (0..=MAX_LOCK_DURATION).contains(&self.lock_duration)
+                && self.gc_inactivity >= 0
+                && self.gc_cranker_bps as u128 <= BPS_DENOMINATOR,
+            PoolError::InvalidPoolConfig
+        );
+        require!(
+            self.verified_max_stake_per_user == 0
+                || (self.max_stake_per_user != 0
+                    && self.verified_max_stake_per_user >= self.max_stake_per_user),
+            PoolError::InvalidPoolConfig
+        );
+
+        Ok(())
+    }
+}
+
+/// Boosts rewards by the user's stake in another pool, e.g. governance
+/// stakers farming LP tokens. The boost grows linearly up to
+/// `max_boost_bps` at `full_boost_stake` linked tokens.
+#[account]
+pub struct BoostLink {
+    pub pool: Pubkey,
+    pub linked_pool: Pubkey,
+    pub max_boost_bps: u32,
+    pub full_boost_stake: u64,
+}
+
+impl BoostLink {
+    pub fn size() -> usize {
+        std::mem::size_of::<BoostLink>()
+    }
+
+    fn validate(&self) -> PoolResult {
+        require!(
+            self.linked_pool != self.pool
+                && self.max_boost_bps <= MAX_LINK_BOOST_BPS
+                && self.full_boost_stake > 0,
+            PoolError::InvalidBoostLink
+        );
+
+        Ok(())
+    }
+
+    /// Boost earned by `linked_stake` tokens staked in the linked pool.
+    fn boost_bps(&self, linked_stake: u64) -> u32 {
+        let stake = linked_stake.min(self.full_boost_stake) as u128;
+
+        (self.max_boost_bps as u128 * stake / self.full_boost_stake as u128) as u32
+    }
+}
+
+/// A stake kept as a leaf of the pool's compressed tree instead of a
+/// `UserState`. Rewards are paid out on every change, so no pending balance,
+/// streak or points are kept.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CompressedPosition {
+    pub staked_amount: u64,
+    pub reward_per_token_paid: u128,
+}
+
+impl CompressedPosition {
+    fn leaf(&self, pool: &Pubkey, owner: &Pubkey) -> [u8; 32] {
+        hashv(&[
+            pool.as_ref(),
+            owner.as_ref(),
+            &self.staked_amount.to_le_bytes(),
+            &self.reward_per_token_paid.to_le_bytes(),
+        ])
+        .to_bytes()
+    }
+
+    /// Rewards earned since the position last changed.
+    fn earned(&self, pool: &Pool) -> PoolResult<u64> {
+        mul_div_amount(
+            self.staked_amount,
+            pool.reward_per_token.safe_sub(self.reward_per_token_paid)?,
+            REWARD_PRECISION,
+            Rounding::Payout,
+        )
+    }
+}
+
+/// Stake granted by the pool authority, e.g. employee tokens, held apart
+/// from the beneficiary's `UserState` until released. Until then the
+/// authority can claw the principal and its rewards back to `treasury`.
+#[account]
+pub struct GrantPosition {
+    pub pool: Pubkey,
+    pub beneficiary: Pubkey,
+
+    /// Token account receiving clawed-back tokens.
+    pub treasury: Pubkey,
+    pub staked_amount: u64,
+    pub reward_per_token_paid: u128,
+}
+
+impl GrantPosition {
+    pub fn size() -> usize {
+        std::mem::size_of::<GrantPosition>()
+    }
+
+    /// Rewards earned since the grant was staked.
+    fn earned(&self, pool: &Pool) -> PoolResult<u64> {
+        mul_div_amount(
+            self.staked_amount,
+            pool.reward_per_token.safe_sub(self.reward_per_token_paid)?,
+            REWARD_PRECISION,
+            Rounding::Payout,
+        )
+    }
+}
+
+/// The pool's exchange rate for lending markets pricing staked positions,
+/// refreshed by the instructions that pass it.
+#[account]
+pub struct RatePublisher {
+    pub pool: Pubkey,
+
+    /// `Pool::exchange_rate` at the last update.
+    pub rate: u128,
+    pub slot: u64,
+    pub updated_ts: i64,
+
+    /// Rate averaged with an exponential weight over `RATE_TWAP_WINDOW`.
+    /// Only the time a rate was held counts, so a rate set and undone within
+    /// one slot never moves it.
+    pub twap: u128,
+
+    /// Rate integrated over time, for integrators averaging over their own
+    /// window.
+    pub cumulative_rate: u128,
+}
+
+impl RatePublisher {
+    pub fn size() -> usize {
+        std::mem::size_of::<RatePublisher>()
+    }
+
+    fn update(&mut self, pool: &Pool) -> PoolResult {
+        let clock = Clock::get()?;
+        let elapsed = (clock.unix_timestamp - self.updated_ts).max(0);
+
+        // The previous rate held for the whole of `elapsed`.
+        self.cumulative_rate = self
+            .cumulative_rate
+            .safe_add(self.rate.safe_mul(elapsed as u128)?)?;
+
+        let weight = elapsed.min(RATE_TWAP_WINDOW) as u128;
+        let window = RATE_TWAP_WINDOW as u128;
+        self.twap = if self.rate >= self.twap {
+            self.twap
+                .safe_add((self.rate - self.twap).safe_mul(weight)?.safe_div(window)?)?
+        } else {
+            self.twap
+                .safe_sub((self.twap - self.rate).safe_mul(weight)?.safe_div(window)?)?
+        };
+
+        self.rate = pool.exchange_rate()?;
+        self.slot = clock.slot;
+        self.updated_ts = clock.unix_timestamp;
+
+        Ok(())
+    }
+}
+
+/// A wallet's positions across pools, so UIs read one account instead of
+/// scanning every pool. Only kept up to date by stake changes that pass it.
+#[account]
+pub struct UserProfile {
+    pub owner: Pubkey,
+
+    /// Pools with nonzero stake, the first `pool_count` entries.
+    pub pools: [Pubkey; PROFILE_POOLS],
+    pub pool_count: u32,
+
+    pub lifetime_staked: u64,
+    pub lifetime_unstaked: u64,
+}
+
+impl UserProfile {
+    pub fn size() -> usize {
+        std::mem::size_of::<UserProfile>()
+    }
+
+    /// Records a stake change in `pool` from `before` to `after`.
+    fn record(&mut self, pool: Pubkey, before: u64, after: u64) -> PoolResult {
+        let count = self.pool_count as usize;
+
+        if after > before {
+            self.lifetime_staked = self.lifetime_staked.safe_add(after - before)?;
+        } else {
+            self.lifetime_unstaked = self.lifetime_unstaked.safe_add(before - after)?;
+        }
+
+        if before == 0 && after > 0 {
+            require_gt!(PROFILE_POOLS, count, PoolError::ProfileFull);
+            self.pools[count] = pool;
+            self.pool_count += 1;
+        } else if before > 0 && after == 0 {
+            if let Some(i) = self.pools[..count].iter().position(|p| *p == pool) {
+                self.pools[i] = self.pools[count - 1];
+                self.pools[count - 1] = Pubkey::default();
+                self.pool_count -= 1;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Leading fields of an attestation account, in the layout of the Solana
+/// Attestation Service. Revoking an attestation closes its account.
+#[derive(AnchorDeserialize)]
+struct AttestationData {
+    _discriminator: u8,
+
+    /// The attested wallet.
+    nonce: Pubkey,
+    _credential: Pubkey,
+    schema: Pubkey,
+    _data: Vec<u8>,
+    _signer: Pubkey,
+
+    /// Zero for attestations that don't expire.
+    expiry: i64,
+}
+
+/// Rarely-changing economic parameters, kept apart from the hot `Pool` so
+/// stake changes only read them.
+#[account]
+pub struct PoolConfig {
+    pub pool: Pubkey,
+    pub params: PoolConfigParams,
+}
+
+impl PoolConfig {
+    pub fn size() -> usize {
+        std::mem::size_of::<PoolConfig>()
+    }
+
+    fn attestation_address(&self, user: Pubkey) -> Pubkey {
+        Pubkey::find_program_address(
+            &[self.params.attestation_schema.as_ref(), user.as_ref()],
+            &self.params.attestation_program,
+        )
+        .0
+    }
+
+    /// Whether `attestation` is a live attestation of `user` under the
+    /// configured program and schema at `now`.
+    fn is_attested(&self, user: Pubkey, attestation: Option<&UncheckedAccount>, now: i64) -> bool {
+        let program = self.params.attestation_program;
+
+        match attestation {
+            Some(attestation) if program != Pubkey::default() => {
+                if attestation.key() != self.attestation_address(user)
+                    || *attestation.owner != program
+                {
+                    return false;
+                }
+
+                let Ok(data) = attestation.try_borrow_data() else {
+                    return false;
+                };
+                match AttestationData::deserialize(&mut &data[..]) {
+                    Ok(data) => {
+                        data.nonce == user
+                            && data.schema == self.params.attestation_schema
+                            && (data.expiry == 0 || data.expiry > now)
+                    }
+                    Err(_) => false,
+                }
+            }
+            _ => false,
+        }
+    }
+
+    /// Deposit fee on `amount`, without checking any cap.
+    fn fee_on_deposit(&self, amount: u64, exempt: bool) -> PoolResult<u64> {
+        match exempt {
+            true => Ok(0),
+            false => bps_of(
+                amount,
+                self.params.deposit_fee_bps as u128,
+                Rounding::Liability,
+            ),
+        }
+    }
+
+    /// Most a wallet may stake, 0 for no cap.
+    fn user_cap(&self, attested: bool) -> u64 {
+        match self.params.verified_max_stake_per_user {
+            verified if attested && verified != 0 => verified,
+            _ => self.params.max_stake_per_user,
+        }
+    }
+
+    /// Deposit fee on `amount` after checking the stake caps against the
+    /// credited remainder. `user_staked` is the wallet's stake across its
+    /// positions.
+    fn deposit_fee(
+        &self,
+        amount: u64,
+        pool: &Pool,
+        user_staked: u64,
+        attested: bool,
+        exempt: bool,
+    ) -> PoolResult<u64> {
+        let fee = self.fee_on_deposit(amount, exempt)?;
+        let credited = amount.safe_sub(fee)?;
+
+        let caps = [
+            (user_staked, self.user_cap(attested)),
+            (pool.staked_total, self.params.max_staked_total),
+        ];
+        for (staked, cap) in caps {
+            require!(
+                cap == 0 || staked.safe_add(credited)? <= cap,
+                PoolError::StakeCapExceeded
+            );
+        }
+
+        Ok(fee)
+    }
+
+    /// Withdrawal fee, unless exempt, plus, while the stake is locked, the
+    /// early-withdrawal penalty.
+    fn withdrawal_charge(
+        &self,
+        amount: u64,
+        user_state: &UserState,
+        now: i64,
+        exempt: bool,
+    ) -> PoolResult<u64> {
+        let (fee, penalty) = self.withdrawal_charges(amount, user_state, now, exempt)?;
+
+        Ok(fee.safe_add(penalty)?)
+    }
+
+    /// `withdrawal_charge` split into the fee and the penalty.
+    fn withdrawal_charges(
+        &self,
+        amount: u64,
+        user_state: &UserState,
+        now: i64,
+        exempt: bool,
+    ) -> PoolResult<(u64, u64)> {
+        let fee = match exempt {
+            true => 0,
+            false => bps_of(
+                amount,
+                self.params.withdrawal_fee_bps as u128,
+                Rounding::Liability,
+            )?,
+        };
+        let penalty = match now < user_state.locked_until {
+            true => bps_of(
+                amount,
+                self.params.early_withdrawal_penalty_bps as u128,
+                Rounding::Liability,
+            )?,
+            false => 0,
+        };
+
+        Ok((fee, penalty))
+    }
+
+    /// Smallest amount to unstake so that `net` is left after
+    /// `withdrawal_charge`.
+    fn gross_for_net(
+        &self,
+        net: u64,
+        user_state: &UserState,
+        now: i64,
+        exempt: bool,
+    ) -> PoolResult<u64> {
+        let mut bps = match exempt {
+            true => 0,
+            false => self.params.withdrawal_fee_bps as u128,
+        };
+        if now < user_state.locked_until {
+            bps = bps.safe_add(self.params.early_withdrawal_penalty_bps as u128)?;
+        }
+
+        let kept = BPS_DENOMINATOR.safe_sub(bps)?;
+        let mut gross = u64::try_from((net as u128).safe_mul(BPS_DENOMINATOR)?.div_ceil(kept))
+            .map_err(|_| ProgramError::ArithmeticOverflow)?;
+
+        // Each part of the charge rounds up on its own, so the estimate can
+        // be a token or two low, never high.
+        while gross.safe_sub(self.withdrawal_charge(gross, user_state, now, exempt)?)? < net {
+            gross = gross.safe_add(1)?;
+        }
+
+        Ok(gross)
+    }
+}
+
+/// Snapshot of the legacy staking program, published once by the admin.
+/// Leaves are `hashv([user, amount_le])`.
+#[account]
+pub struct Migration {
+    pub pool: Pubkey,
+    pub merkle_root: [u8; 32],
+    pub imported_total: u64,
+}
+
+impl Migration {
+    pub fn size() -> usize {
+        std::mem::size_of::<Migration>()
+    }
+}
+
+/// Merkle root of every `(user, staked_amount)` pair at `slot`, committed
+/// so third parties can check eligibility proofs against on-chain data.
+/// Leaves are built like `Migration` leaves.
+#[account]
+pub struct Snapshot {
+    pub pool: Pubkey,
+    pub id: u64,
+    pub slot: u64,
+    pub merkle_root: [u8; 32],
+    pub staked_total: u64,
+}
+
+impl Snapshot {
+    pub fn size() -> usize {
+        std::mem::size_of::<Snapshot>()
+    }
+}
+
+/// Stake-weighted prize draw of `epoch`. Each `(user, start, amount)` leaf
+/// of `merkle_root` holds the tickets `start..start + amount` out of
+/// `total_weight`, and winner `i` is whoever holds `winning_ticket(i)`.
+/// Winners are drawn with replacement, so a large staker can win twice.
+#[account]
+pub struct Lottery {
+    pub pool: Pubkey,
+    pub epoch: u64,
+    pub merkle_root: [u8; 32],
+    pub total_weight: u64,
+
+    /// Prize credited to each winner.
+    pub prize: u64,
+    pub winners: u8,
+
+    /// Bit `i` is set once winner `i` has been credited.
+    pub claimed: u8,
+
+    /// Adapter that requests and reveals randomness, e.g. for Switchboard
+    /// randomness accounts. `reveal` returns the seed slot and the value.
+    pub randomness_program: Pubkey,
+
+    /// Randomness account and slot of the request, default until requested.
+    /// The value must be seeded after the request, so nobody knew it then.
+    pub randomness_account: Pubkey,
+    pub request_slot: u64,
+
+    pub randomness: [u8; 32],
+    pub drawn: bool,
+}
+
+impl Lottery {
+    pub fn size() -> usize {
+        std::mem::size_of::<Lottery>()
+    }
+
+    /// Ticket of winner `winner`. Reducing 128 bits keeps the modulo bias
+    /// negligible for any `u64` weight.
+    pub fn winning_ticket(&self, winner: u8) -> u64 {
+        let digest = hashv(&[&self.randomness, &[winner]]).to_bytes();
+        let value = u128::from_le_bytes(digest[..16].try_into().unwrap());
+
+        (value % self.total_weight as u128) as u64
+    }
+
+    /// Marks winner `winner` credited and returns the prize.
+    fn take_prize(&mut self, winner: u8) -> PoolResult<u64> {
+        require!(self.drawn, PoolError::LotteryNotDrawn);
+        require!(winner < self.winners, PoolError::InvalidLottery);
+        require!(self.claimed & (1 << winner) == 0, PoolError::PrizeClaimed);
+        self.claimed |= 1 << winner;
+
+        Ok(self.prize)
+    }
+}
+
+/// Deposit bucket with its own token account. Deposits only write their
+/// shard, so users spread over shards don't contend on the pool or the
+/// vault at peak times; a crank merges each shard into the pool in one go.
+#[account]
+pub struct StakeShard {
+    pub pool: Pubkey,
+    pub index: u8,
+    pub vault: Pubkey,
+
+    /// Deposits taken since the last merge, which will be batch
+    /// `generation`.
+    pub generation: u64,
+    pub pending_amount: u64,
+}
+
+impl StakeShard {
+    pub fn size() -> usize {
+        std::mem::size_of::<StakeShard>()
+    }
+}
+
+/// A wallet's deposit into a shard during `generation`, credited to its
+/// default position once that generation is merged.
+#[account]
+pub struct ShardDeposit {
+    pub shard: Pubkey,
+    pub user: Pubkey,
+    pub generation: u64,
+    pub amount: u64,
+}
+
+impl ShardDeposit {
+    pub fn size() -> usize {
+        std::mem::size_of::<ShardDeposit>()
+    }
+}
+
+/// A shard's deposits of one generation after the merge. They are stake
+/// from the merge on, earning from the reward indices taken then until
+/// they are credited to their positions.
+#[account]
+pub struct ShardBatch {
+    pub shard: Pubkey,
+    pub generation: u64,
+    pub reward_per_token: u128,
+    pub sol_reward_per_token: u128,
+
+    /// Deposits not credited yet, and what is left of the tokens that
+    /// reached the vault for them after transfer fees.
+    pub deposited: u64,
+    pub received: u64,
+
+    /// Paid the rent, refunded once every deposit is credited.
+    pub payer: Pubkey,
+}
+
+impl ShardBatch {
+    pub fn size() -> usize {
+        std::mem::size_of::<ShardBatch>()
+    }
+
+    /// Takes a deposit of `amount` out of the batch: its share of the
+    /// tokens received, and the token and SOL rewards the share earned
+    /// since the merge. The last deposit gets what rounding left.
+    fn take(&mut self, amount: u64, pool: &Pool) -> PoolResult<(u64, u64, u64)> {
+        let share = if amount == self.deposited {
+            self.received
+        } else {
+            mul_div_amount(
+                amount,
+                self.received as u128,
+                self.deposited as u128,
+                Rounding::Payout,
+            )?
+        };
+        self.deposited = self.deposited.safe_sub(amount)?;
+        self.received = self.received.safe_sub(share)?;
+
+        let rewards = mul_div_amount(
+            share,
+            pool.reward_per_token.safe_sub(self.reward_per_token)?,
+            REWARD_PRECISION,
+            Rounding::Payout,
+        )?;
+        let sol_rewards = mul_div_amount(
+            share,
+            pool.sol_reward_per_token
+                .safe_sub(self.sol_reward_per_token)?,
+            REWARD_PRECISION,
+            Rounding::Payout,
+        )?;
+
+        Ok((share, rewards, sol_rewards))
+    }
+}
+
+/// Recurring deposit of `amount` every `interval` seconds, pulled from
+/// `funding` through the program signer's delegate approval.
+#[account]
+pub struct DepositSchedule {
+    pub pool: Pubkey,
+    pub user: Pubkey,
+    pub funding: Pubkey,
+    pub amount: u64,
+    pub interval: i64,
+    pub next_execution_ts: i64,
+}
+
+impl DepositSchedule {
+    pub fn size() -> usize {
+        std::mem::size_of::<DepositSchedule>()
+    }
+}
+
+/// Protocol-wide settings, one per deployment. Until it exists pool creation
+/// is open to any mint.
+#[account]
+pub struct GlobalConfig {
+    pub admin: Pubkey,
+
+    /// Only allowlisted mints can back new pools. Otherwise every mint that
+    /// isn't blocked can.
+    pub allowlist_only: bool,
+
+    /// The program's upgrade authority as of the last sync, see
+    /// `sync_upgrade_authority`.
+    pub upgrade_authority: Pubkey,
+
+    /// Only the upgrade authority can create pools, so that every pool of
+    /// the deployment is an official one.
+    pub permissioned: bool,
+
+    /// Entries in the protocol's config history, which is kept like a
+    /// pool's but always on.
+    pub config_change_count: u64,
+}
+
+impl GlobalConfig {
+    pub fn size() -> usize {
+        std::mem::size_of::<GlobalConfig>()
+    }
+}
+
+/// The protocol's verdict on one mint for new pools. Existing pools are not
+/// affected.
+#[account]
+pub struct MintListing {
+    pub mint: Pubkey,
+    pub allowed: bool,
+}
+
+impl MintListing {
+    pub fn size() -> usize {
+        std::mem::size_of::<MintListing>()
+    }
+}
+
+/// Created once per pool address by `initialize`. It outlives the pool
+/// account, so an address that was closed and recreated can't be initialized
+/// a second time.
+#[account]
+pub struct PoolMarker {
+    pub pool: Pubkey,
+}
+
+impl PoolMarker {
+    pub fn size() -> usize {
+        std::mem::size_of::<PoolMarker>()
+    }
+}
+
+/// Marks `wallet` as exempt from the pool's deposit and withdrawal fees.
+/// Early-withdrawal penalties still apply.
+#[account]
+pub struct FeeExemption {
+    pub pool: Pubkey,
+    pub wallet: Pubkey,
+}
+
+impl FeeExemption {
+    pub fn size() -> usize {
+        std::mem::size_of::<FeeExemption>()
+    }
+}
+
+/// Lets `manager`, a co-incentive partner, run the pool's SOL campaign
+/// while no one else's is running. Pool-wide settings stay with the
+/// authority.
+#[account]
+pub struct CampaignManager {
+    pub pool: Pubkey,
+    pub manager: Pubkey,
+}
+
+impl CampaignManager {
+    pub fn size() -> usize {
+        std::mem::size_of::<CampaignManager>()
+    }
+}
+
+/// Marks a user's legacy stake as imported.
+#[account]
+pub struct ImportReceipt {
+    pub user: Pubkey,
+    pub amount: u64,
+}
+
+impl ImportReceipt {
+    pub fn size() -> usize {
+        std::mem::size_of::<ImportReceipt>()
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum WithdrawalStatus {
+    Pending,
+    Fulfilled,
+}
+
+/// A queued withdrawal, fulfilled in FIFO order once the vault holds enough
+/// liquidity, or in part with every batch in pro-rata mode.
+#[account]
+pub struct WithdrawalRequest {
+    pub pool: Pubkey,
+    pub user: Pubkey,
+    pub id: u64,
+    pub amount: u64,
+    pub requested_ts: i64,
+    pub status: WithdrawalStatus,
+
+    /// Queued in the priority lane.
+    pub priority: bool,
+
+    /// Last pro-rata batch the request was filled in.
+    pub batch: u64,
+
+    /// Picked by the user to key the request account; zero for requests
+    /// queued by `force_unwind`.
+    pub nonce: u64,
+}
+
+impl WithdrawalRequest {
+    pub fn size() -> usize {
+        std::mem::size_of::<WithdrawalRequest>()
+    }
+
+    pub fn seed(priority: bool) -> &'static [u8] {
+        if priority {
+            PRIORITY_WITHDRAWAL_SEED
+        } else {
+            WITHDRAWAL_SEED
+        }
+    }
+}
+
+#[zero_copy]
+pub struct Checkpoint {
+    pub slot: u64,
+    pub amount: u64,
+}
+
+/// One page of an append-only stake history. `holder` is the user, or the
+/// pool itself for the pool total; pages are numbered from 0 and a new one
+/// is only opened once the previous is full.
+#[account(zero_copy)]
+pub struct Checkpoints {
+    pub pool: Pubkey,
+    pub holder: Pubkey,
+    pub page: u32,
+    pub len: u32,
+    pub entries: [Checkpoint; CHECKPOINTS_PER_PAGE],
+}
+
+impl Checkpoints {
+    pub fn size() -> usize {
+        std::mem::size_of::<Checkpoints>()
+    }
+
+    /// Records `amount` as the stake from `slot` on. Several changes within a
+    /// slot collapse into one checkpoint.
+    ///
+    /// A full page only refuses stake going up. A drop is folded into the
+    /// last checkpoint instead, which understates the stake since then but
+    /// never overstates it, so exits don't wait for someone to pay for the
+    /// next page.
+    fn push(&mut self, slot: u64, amount: u64) -> PoolResult {
+        let len = self.len as usize;
+
+        if len > 0 && self.entries[len - 1].slot == slot {
+            self.entries[len - 1].amount = amount;
+            return Ok(());
+        }
+
+        if len == CHECKPOINTS_PER_PAGE && amount <= self.entries[len - 1].amount {
+            self.entries[len - 1].amount = amount;
+            return Ok(());
+        }
+
+        require!(len < CHECKPOINTS_PER_PAGE, PoolError::CheckpointPageFull);
+        self.entries[len] = Checkpoint { slot, amount };
+        self.len += 1;
+
+        Ok(())
+    }
+
+    /// Stake held at `slot` according to this page, `None` if the page
+    /// starts after it.
+    pub fn amount_at(&self, slot: u64) -> Option<u64> {
+        let entries = &self.entries[..self.len as usize];
+        let idx = entries.partition_point(|c| c.slot <= slot);
+
+        idx.checked_sub(1).map(|i| entries[i].amount)
+    }
+}
+
+/// Kind of operation in an `AuditEntry`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AuditOp {
+    EnterStaking,
+    LeaveStaking,
+    ClaimRewards,
+    RequestWithdrawal,
+    FulfillWithdrawal,
+    ScheduledDeposit,
+    PullStake,
+    ImportStake,
+    EmergencyWithdraw,
+    ZapStake,
+    ClaimSolRewards,
+    IntegratorStake,
+    IntegratorUnstake,
+    Distribute,
+    ShardDeposit,
+}
+
+/// Admin settings tracked by the config history.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigParam {
+    EmissionSchedule,
+    DynamicApr,
+    StreakBoost,
+    PointsMode,
+    CheckpointsEnabled,
+    SwapAdapter,
+    RestakingAdapter,
+    VipMint,
+    PoolConfig,
+    Watcher,
+    ClaimCooldown,
+    DisabledInstructions,
+    RewardRedirect,
+    DustPolicy,
+    ConfigHistory,
+    RewardStream,
+    BoostLink,
+    RateGuard,
+    MemoRequired,
+    Compliance,
+    SolRewards,
+    ClaimAdapter,
+    AchievementUri,
+    ProRataWithdrawals,
+    Operator,
+    Terms,
+    Paused,
+    Incident,
+    MintAuthorityRotation,
+    MintAuthority,
+    VaultRotation,
+    Vault,
+    CampaignManager,
+    FeeExemption,
+    GlobalConfig,
+    MintListing,
+    UpgradeAuthority,
+    EmissionEnd,
+    DistributionCap,
+    AttestationFee,
+}
+
+/// One admin parameter change. Values are the Borsh encoding of the
+/// setter's arguments, zero-padded.
+#[account]
+pub struct ConfigChange {
+    /// The pool, or the `GlobalConfig` for protocol-wide changes.
+    pub pool: Pubkey,
+    pub id: u64,
+    pub param: ConfigParam,
+    pub old_value: [u8; CONFIG_VALUE_LEN],
+    pub new_value: [u8; CONFIG_VALUE_LEN],
+    pub slot: u64,
+    pub authority: Pubkey,
+}
+
+impl ConfigChange {
+    pub fn size() -> usize {
+        std::mem::size_of::<ConfigChange>()
+    }
+}
+
+#[zero_copy]
+pub struct AuditEntry {
+    pub user: Pubkey,
+    pub amount: u64,
+    pub slot: u64,
+
+    /// `AuditOp` discriminant.
+    pub op: u8,
+
+    pub padding: [u8; 7],
+}
+
+/// Ring buffer of the pool's last `AUDIT_LOG_SIZE` operations, oldest
+/// overwritten first.
+#[account(zero_copy)]
+pub struct AuditLog {
+    pub pool: Pubkey,
+
+    /// Operations recorded so far; the next goes to `head % AUDIT_LOG_SIZE`.
+    pub head: u64,
+
+    pub entries: [AuditEntry; AUDIT_LOG_SIZE],
+}
+
+impl AuditLog {
+    pub fn size() -> usize {
+        std::mem::size_of::<AuditLog>()
+    }
+
+    fn push(&mut self, op: AuditOp, user: Pubkey, amount: u64, slot: u64) -> PoolResult {
+        self.entries[(self.head % AUDIT_LOG_SIZE as u64) as usize] = AuditEntry {
+            user,
+            amount,
+            slot,
+            op: op as u8,
+            padding: [0; 7],
+        };
+        self.head = self.head.safe_add(1)?;
+
+        Ok(())
+    }
+}
+
+/// Reward outlook of a pool, as returned by `get_pool_stats`. Rewards are
+/// paid in the staked token, so rates need no decimal conversion.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PoolStats {
+    pub staked_total: u64,
+
+    /// Rewards emitted per second right now.
+    pub emission_rate: u64,
+
+    /// Annual rate at the current emission, zero while nothing is staked.
+    pub apr_bps: u64,
+
+    /// `apr_bps` compounded daily.
+    pub apy_bps: u64,
+
+    pub emission_end_ts: Option<i64>,
+    pub seconds_to_emission_end: Option<i64>,
+
+    /// `Pool` lifetime totals.
+    pub total_rewards_distributed: u64,
+    pub total_fees_collected: u64,
+    pub total_penalties: u64,
+}
+
+/// Pool books recomputed by `reconcile`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Reconciliation {
+    /// Vault plus deployed funds, and what the counters say the pool owes.
+    pub reserves: u128,
+    pub liabilities: u128,
+
+    /// Fees and penalties charged minus those paid out, which
+    /// `protocol_fees` must equal.
+    pub expected_protocol_fees: u64,
+    pub protocol_fees: u64,
+
+    /// Withdrawal queue ids in order, and nothing owed once both lanes
+    /// are empty.
+    pub queue_consistent: bool,
+
+    /// All of the above hold.
+    pub balanced: bool,
+}
+
+/// Action previewed by `simulate_action`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SimulatedAction {
+    Stake,
+    Unstake,
+
+    /// `amount` as passed to `claim_rewards`, 0 for everything.
+    Claim,
+}
+
+/// Outcome of an action as computed by `simulate_action`, with the same
+/// math and rounding the action itself uses.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ActionPreview {
+    /// Deposit or withdrawal fee.
+    pub fee: u64,
+
+    /// Early-withdrawal penalty.
+    pub penalty: u64,
+
+    /// Stake credited for a deposit, tokens reaching the wallet for a
+    /// withdrawal.
+    pub received: u64,
+
+    /// Rewards minted to the wallet.
+    pub rewards: u64,
+
+    /// Balances after the action.
+    pub staked_amount: u64,
+    pub staked_total: u64,
+    pub pending_rewards: u64,
+}
+
+/// Compounds `apr_bps` daily over a year, saturating on overflow.
+fn apy_bps(apr_bps: u128) -> u64 {
+    const DAYS_PER_YEAR: u32 = 365;
+
+    let mul = |a: u128, b: u128| a.checked_mul(b).map(|product| product / REWARD_PRECISION);
+
+    let growth = (|| {
+        let mut daily = REWARD_PRECISION.checked_add(
+            apr_bps.checked_mul(REWARD_PRECISION)? / (BPS_DENOMINATOR * DAYS_PER_YEAR as u128),
+        )?;
+
+        // Square-and-multiply keeps this to a handful of u128 divisions.
+        let mut growth = REWARD_PRECISION;
+        let mut days = DAYS_PER_YEAR;
+        while days > 0 {
+            if days & 1 == 1 {
+                growth = mul(growth, daily)?;
+            }
+            days >>= 1;
+            if days > 0 {
+                daily = mul(daily, daily)?;
+            }
+        }
+
+        Some(growth)
+    })();
+
+    growth
+        .and_then(|growth| {
+            ((growth - REWARD_PRECISION) * BPS_DENOMINATOR / REWARD_PRECISION)
+                .try_into()
+                .ok()
+        })
+        .unwrap_or(u64::MAX)
+}
+
+/// Voting power of one user state, as returned by `get_voter_weight`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VoterWeight {
+    pub voter: Pubkey,
+    pub weight: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct LeaderboardEntry {
+    pub user: Pubkey,
+    pub stake_seconds: u128,
+}
+
+/// Milestones a wallet can mint an NFT for, once each per pool.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Achievement {
+    FirstStake,
+
+    /// A year of continuous stake.
+    OneYear,
+
+    /// Ranked on the current season's leaderboard.
+    Leaderboard,
+}
+
+impl Achievement {
+    pub fn bit(self) -> u8 {
+        1 << self as u8
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Achievement::FirstStake => "First Stake",
+            Achievement::OneYear => "One Year Staked",
+            Achievement::Leaderboard => "Leaderboard",
+        }
+    }
+
+    fn slug(self) -> &'static str {
+        match self {
+            Achievement::FirstStake => "first_stake",
+            Achievement::OneYear => "one_year",
+            Achievement::Leaderboard => "leaderboard",
+        }
+    }
+}
+
+/// Where a pool's achievement NFTs point their metadata: `base_uri`
+/// followed by the achievement's slug and `.json`.
+#[account]
+pub struct AchievementConfig {
+    pub pool: Pubkey,
+    pub base_uri: String,
+}
+
+impl AchievementConfig {
+    pub fn size() -> usize {
+        32 + 4 + MAX_ACHIEVEMENT_URI_LEN
+    }
+}
+
+/// Top stakers of one pool season, ranked by stake-seconds.
+#[account]
+pub struct Leaderboard {
+    pub pool: Pubkey,
+    pub season: u32,
+    pub entries: [LeaderboardEntry; LEADERBOARD_SIZE],
+}
+
+impl Leaderboard {
+    pub fn size() -> usize {
+        std::mem::size_of::<Leaderboard>()
+    }
+
+    /// Updates `user`'s score, taking a slot if it beats the lowest ranked
+    /// entry, and keeps the entries sorted best first.
+    fn record(&mut self, user: Pubkey, stake_seconds: u128) {
+        if let Some(entry) = self.entries.iter_mut().find(|e| e.user == user) {
+            entry.stake_seconds = stake_seconds;
+        } else {
+            let last = &mut self.entries[LEADERBOARD_SIZE - 1];
+            if last.user != Pubkey::default() && last.stake_seconds >= stake_seconds {
+                return;
+            }
+            *last = LeaderboardEntry {
+                user,
+                stake_seconds,
+            };
+        }
+
+        self.entries
+            .sort_by_key(|e| std::cmp::Reverse(e.stake_seconds));
+    }
+}
+
+impl UserState {
+    pub fn size() -> usize {
+        std::mem::size_of::<UserState>()
+    }
+
+    /// Streak boost in basis points for the epochs checkpointed so far.
+    pub fn streak_boost_bps(&self, pool: &Pool) -> u128 {
+        (self.streak_epochs as u128 * pool.streak_boost_bps as u128)
+            .min(pool.max_streak_boost_bps as u128)
+    }
+
+    /// Streak and linked-pool boosts combined.
+    pub fn boost_bps(&self, pool: &Pool) -> u128 {
+        self.streak_boost_bps(pool) + self.link_boost_bps as u128
+    }
+
+    /// Moves rewards earned since the last settlement into `pending_rewards`
+    /// and checkpoints the staking streak. The pool's index must already be
+    /// up to date. What rounding the user's share down drops goes to the
+    /// pool's `reward_dust`.
+    fn settle_rewards(&mut self, pool: &mut Pool) -> PoolResult {
+        let now = pool.last_update_ts;
+
+        // Rewards since the last checkpoint are boosted by the streak reached
+        // at that checkpoint, never by one the user has not held yet.
+        let (earned, dust) = mul_div_rem(
+            self.staked_amount as u128,
+            pool.reward_per_token.safe_sub(self.reward_per_token_paid)?,
+            REWARD_PRECISION,
+        )?;
+        pool.reward_dust = pool.reward_dust.safe_add(dust)?;
+        let earned = mul_div_amount(
+            u64::try_from(earned).map_err(|_| ProgramError::ArithmeticOverflow)?,
+            BPS_DENOMINATOR.safe_add(self.boost_bps(pool))?,
+            BPS_DENOMINATOR,
+            Rounding::Payout,
+        )?;
+
+        self.pending_rewards = self.pending_rewards.safe_add(earned)?;
+        self.reward_per_token_paid = pool.reward_per_token;
+
+        // Boosts are minted, the SOL vault only pays out what was funded.
+        let earned_sol = mul_div_amount(
+            self.staked_amount,
+            pool.sol_reward_per_token
+                .safe_sub(self.sol_reward_per_token_paid)?,
+            REWARD_PRECISION,
+            Rounding::Payout,
+        )?;
+        self.sol_pending_rewards = self.sol_pending_rewards.safe_add(earned_sol)?;
+        self.sol_reward_per_token_paid = pool.sol_reward_per_token;
+
+        if self.season != pool.season {
+            // Time before the season started does not count towards it.
+            self.season = pool.season;
+            self.season_stake_seconds = 0;
+            self.last_settle_ts = self.last_settle_ts.max(pool.season_start_ts);
+        }
+        self.season_stake_seconds = self.season_stake_seconds.safe_add(
+            (self.staked_amount as u128).safe_mul((now - self.last_settle_ts).max(0) as u128)?,
+        )?;
+        self.last_settle_ts = now;
+
+        if self.staked_amount == 0 {
+            // No stake, no streak: it restarts with the next deposit.
+            self.streak_start_ts = now;
+            self.streak_epochs = 0;
+        } else if pool.streak_epoch_duration > 0 {
+            self.streak_epochs = ((now - self.streak_start_ts) / pool.streak_epoch_duration) as u64;
+        }
+
+        Ok(())
+    }
+
+    /// Takes `amount` of `pending_rewards`, or all of them for `None` or 0,
+    /// and returns the amount to mint; in points mode they are credited as
+    /// points instead and nothing is minted.
+    fn take_rewards(&mut self, pool: &mut Pool, now: i64, amount: Option<u64>) -> PoolResult<u64> {
+        let full = self.pending_rewards;
+        let mut amount = match amount {
+            Some(amount) if amount > 0 => amount,
+            _ => full,
+        };
+        require_gte!(full, amount, PoolError::ClaimExceedsRewards);
+        self.pending_rewards = full.safe_sub(amount)?;
+        self.last_claim_ts = now;
+
+        // Dust only goes along with a full claim.
+        if amount == full && pool.dust_policy() == DustPolicy::LastClaimant {
+            amount = amount.safe_add(pool.take_dust()?)?;
+        }
+
+        if pool.points_mode != 0 {
+            self.points = self.points.safe_add(amount)?;
+            return Ok(0);
+        }
+        pool.record_distribution(amount)?;
+
+        Ok(amount)
+    }
+}
+
+#[derive(Accounts)]
+pub struct ClonePool<'info> {
+    pub init: Initialize<'info>,
+
+    #[account(owner = *__program_id)]
+    pub template_pool: AccountLoader<'info, Pool>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeGlobalConfig<'info> {
+    #[account(
+        init,
+        seeds = [GLOBAL_CONFIG_SEED],
+        bump,
+        payer = authority,
+        space = 8 + GlobalConfig::size()
+    )]
+    pub global_config: Account<'info, GlobalConfig>,
+
+    #[account(constraint = program.programdata_address()? == Some(program_data.key()) @PoolError::Unauthorized)]
+    pub program: Program<'info, crate::program::StakingPool>,
+
+    #[account(constraint = program_data.upgrade_authority_address == Some(authority.key()) @PoolError::Unauthorized)]
+    pub program_data: Account<'info, ProgramData>,
+
+    /// The program's upgrade authority.
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SyncUpgradeAuthority<'info> {
+    #[account(mut, seeds = [GLOBAL_CONFIG_SEED], bump)]
+    pub global_config: Account<'info, GlobalConfig>,
+
+    #[account(constraint = program.programdata_address()? == Some(program_data.key()) @PoolError::Unauthorized)]
+    pub program: Program<'info, crate::program::StakingPool>,
+
+    pub program_data: Account<'info, ProgramData>,
+
+    /// Whoever cranks the sync, paying for the history entry.
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    /// Next entry of the protocol's config history.
+    #[account(
+        init,
+        seeds = [CONFIG_CHANGE_SEED, global_config.key().as_ref(), &global_config.config_change_count.to_le_bytes()],
+        bump,
+        payer = payer,
+        space = 8 + ConfigChange::size()
+    )]
+    pub config_change: Account<'info, ConfigChange>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetGlobalConfig<'info> {
+    #[account(mut, seeds = [GLOBAL_CONFIG_SEED], bump, has_one = admin @PoolError::Unauthorized)]
+    pub global_config: Account<'info, GlobalConfig>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    /// Next entry of the protocol's config history.
+    #[account(
+        init,
+        seeds = [CONFIG_CHANGE_SEED, global_config.key().as_ref(), &global_config.config_change_count.to_le_bytes()],
+        bump,
+        payer = admin,
+        space = 8 + ConfigChange::size()
+    )]
+    pub config_change: Account<'info, ConfigChange>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(mint: Pubkey)]
+pub struct ListMint<'info> {
+    #[account(mut, seeds = [GLOBAL_CONFIG_SEED], bump, has_one = admin @PoolError::Unauthorized)]
+    pub global_config: Account<'info, GlobalConfig>,
+
+    #[account(
+        init,
+        seeds = [MINT_LISTING_SEED, mint.as_ref()],
+        bump,
+        payer = admin,
+        space = 8 + MintListing::size()
+    )]
+    pub mint_listing: Account<'info, MintListing>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    /// Next entry of the protocol's config history.
+    #[account(
+        init,
+        seeds = [CONFIG_CHANGE_SEED, global_config.key().as_ref(), &global_config.config_change_count.to_le_bytes()],
+        bump,
+        payer = admin,
+        space = 8 + ConfigChange::size()
+    )]
+    pub config_change: Account<'info, ConfigChange>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct DelistMint<'info> {
+    #[account(mut, seeds = [GLOBAL_CONFIG_SEED], bump, has_one = admin @PoolError::Unauthorized)]
+    pub global_config: Account<'info, GlobalConfig>,
+
+    #[account(mut, close = admin)]
+    pub mint_listing: Account<'info, MintListing>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    /// Next entry of the protocol's config history.
+    #[account(
+        init,
+        seeds = [CONFIG_CHANGE_SEED, global_config.key().as_ref(), &global_config.config_change_count.to_le_bytes()],
+        bump,
+        payer = admin,
+        space = 8 + ConfigChange::size()
+    )]
+    pub config_change: Account<'info, ConfigChange>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct Initialize<'info> {
+    #[account(zero)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    /// CHECK: the freeze authority may still be the initializer until
+    /// `take_freeze_authority` hands it to the program signer.
+    #[account(constraint= mint.mint_authority == CSome(program_signer.key())
+        && (mint.freeze_authority == CSome(authority.key()) || mint.freeze_authority == CSome(program_signer.key())))]
+    mint: Box<InterfaceAccount<'info, Mint>>,
+
+    /// CHECK: signs for the vault and the mint, derived from the pool
+    #[account(seeds = [mint.key().as_ref(), pool.key().as_ref()], bump)]
+    pub program_signer: AccountInfo<'info>,
+
+    /// Staking vault, the program signer's associated token account. Created
+    /// here unless someone already did.
+    #[account(
+        init_if_needed,
+        payer = authority,
+        associated_token::mint = mint,
+        associated_token::authority = program_signer,
+        associated_token::token_program = token_program
+    )]
+    vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        init,
+        seeds = [POOL_MARKER_SEED, pool.key().as_ref()],
+        bump,
+        payer = authority,
+        space = 8 + PoolMarker::size()
+    )]
+    pub pool_marker: Account<'info, PoolMarker>,
+
+    /// CHECK: only read if it has been created, see `load_global_config`
+    #[account(seeds = [GLOBAL_CONFIG_SEED], bump)]
+    pub global_config: UncheckedAccount<'info>,
+
+    /// CHECK: only read if it has been created, see `check_mint_listing`
+    #[account(seeds = [MINT_LISTING_SEED, mint.key().as_ref()], bump)]
+    pub mint_listing: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+pub struct Distribute<'info> {
+    #[account(mut, owner = *__program_id, has_one = authority @PoolError::Unauthorized)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    /// CHECK: signs the mints
+    #[account(constraint = program_signer.key() == pool.load()?.program_signer @PoolError::InvalidProgramSigner)]
+    pub program_signer: AccountInfo<'info>,
+
+    #[account(mut, constraint = mint.key() == pool.load()?.mint @PoolError::InvalidMint, mint::token_program = token_program)]
+    mint: Box<InterfaceAccount<'info, Mint>>,
+
+    pub authority: Signer<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+
+    /// Required once the pool keeps an audit log.
+    #[account(mut, seeds = [AUDIT_LOG_SEED, pool.key().as_ref()], bump)]
+    pub audit_log: Option<AccountLoader<'info, AuditLog>>,
+}
+
+#[cfg(feature = "wormhole")]
+#[derive(Accounts)]
+pub struct SetAttestationFee<'info> {
+    #[account(mut, owner = *__program_id, has_one = authority @PoolError::Unauthorized)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// Next entry of the config history, required while the pool keeps one.
+    #[account(
+        init,
+        seeds = [CONFIG_CHANGE_SEED, pool.key().as_ref(), &pool.load()?.config_change_count.to_le_bytes()],
+        bump,
+        payer = authority,
+        space = 8 + ConfigChange::size()
+    )]
+    pub config_change: Option<Account<'info, ConfigChange>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetDistributionCap<'info> {
+    #[account(mut, owner = *__program_id, has_one = authority @PoolError::Unauthorized)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// Next entry of the config history, required while the pool keeps one.
+    #[account(
+        init,
+        seeds = [CONFIG_CHANGE_SEED, pool.key().as_ref(), &pool.load()?.config_change_count.to_le_bytes()],
+        bump,
+        payer = authority,
+        space = 8 + ConfigChange::size()
+    )]
+    pub config_change: Option<Account<'info, ConfigChange>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeUserState<'info> {
+    /// CHECK
+    #[account(
+        init,
+        seeds = [pool.key().as_ref(), authority.key.as_ref()],
+        bump,
+        payer = payer,
+        space = 8 + UserState::size()
+    )]
+    pub user_state: Account<'info, UserState>,
+    // pub user_state: UncheckedAccount<'info>,
+    /// CHECK
+    pub pool: UncheckedAccount<'info>,
+
+    pub authority: Signer<'info>,
+
+    /// Pays the rent; the user or a dApp sponsoring them.
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(index: u16)]
+pub struct InitializeUserPosition<'info> {
+    #[account(
+        init,
+        seeds = [pool.key().as_ref(), authority.key().as_ref(), &position_seed(index)],
+        bump,
+        payer = payer,
+        space = 8 + UserState::size()
+    )]
+    pub user_state: Account<'info, UserState>,
+
+    /// The wallet's default position, which has to exist first.
+    #[account(mut, seeds = [pool.key().as_ref(), authority.key().as_ref()], bump, owner = *__program_id)]
+    pub default_user_state: Account<'info, UserState>,
+
+    /// Created with the wallet's first indexed position.
+    #[account(
+        init_if_needed,
+        seeds = [WALLET_SEED, pool.key().as_ref(), authority.key().as_ref()],
+        bump,
+        payer = payer,
+        space = 8 + WalletState::size()
+    )]
+    pub wallet_state: Account<'info, WalletState>,
+
+    /// CHECK
+    #[account(owner = *__program_id)]
+    pub pool: UncheckedAccount<'info>,
+
+    pub authority: Signer<'info>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetPositionLabel<'info> {
+    /// CHECK
+    pub pool: UncheckedAccount<'info>,
+
+    #[account(mut, seeds = [pool.key().as_ref(), authority.key().as_ref(), &position_seed(user_state.index)], bump, owner = *__program_id)]
+    pub user_state: Account<'info, UserState>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeUserProfile<'info> {
+    #[account(
+        init,
+        seeds = [USER_PROFILE_SEED, authority.key().as_ref()],
+        bump,
+        payer = payer,
+        space = 8 + UserProfile::size()
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+
+    pub authority: Signer<'info>,
+
+    /// Pays the rent; the user or a relayer acting for them.
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(authority: Pubkey)]
+pub struct InitUserStateFor<'info> {
+    #[account(
+        init,
+        seeds = [pool.key().as_ref(), authority.as_ref()],
+        bump,
+        payer = payer,
+        space = 8 + UserState::size()
+    )]
+    pub user_state: Account<'info, UserState>,
+
+    /// CHECK
+    #[account(owner = *__program_id)]
+    pub pool: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct GcUserState<'info> {
+    #[account(owner = *__program_id)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    #[account(seeds = [CONFIG_SEED, pool.key().as_ref()], bump)]
+    pub config: Account<'info, PoolConfig>,
+
+    /// CHECK: only used to derive the user state
+    pub user: UncheckedAccount<'info>,
+
+    #[account(mut, seeds = [pool.key().as_ref(), user.key().as_ref(), &position_seed(user_state.index)], bump, close = payer)]
+    pub user_state: Account<'info, UserState>,
+
+    /// CHECK: receives the rest of the rent
+    #[account(mut, address = user_state.payer @PoolError::Unauthorized)]
+    pub payer: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub cranker: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct MigratePool<'info> {
+    /// CHECK: a pool in any layout, checked and grown by the handler
+    #[account(mut, owner = *__program_id)]
+    pub pool: UncheckedAccount<'info>,
+
+    /// The pool's mint, whose freeze authority created a version 0 pool and
+    /// takes it over as the pool authority.
+    pub mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        init_if_needed,
+        seeds = [POOL_MARKER_SEED, pool.key().as_ref()],
+        bump,
+        payer = authority,
+        space = 8 + PoolMarker::size()
+    )]
+    pub pool_marker: Account<'info, PoolMarker>,
+
+    /// Pays for the larger account. The mint's freeze authority for version
+    /// 0 pools, the pool authority after that.
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateUserState<'info> {
+    #[account(owner = *__program_id)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    /// CHECK: the user's default position in any layout, checked and grown
+    /// by the handler
+    #[account(mut, seeds = [pool.key().as_ref(), user.key().as_ref()], bump, owner = *__program_id)]
+    pub user_state: UncheckedAccount<'info>,
+
+    /// Pays for the larger account, and gets the rent back on `gc_user_state`.
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct EnterStaking<'info> {
+    #[account(mut, owner = *__program_id )]
+    pub pool: AccountLoader<'info, Pool>,
+
+    /// CHECK
+    #[account(mint::token_program = token_program)]
+    mint: Box<InterfaceAccount<'info, Mint>>,
+
+    /// CHECK: staking vault, must be owned by the pool's program signer
+    #[account(mut, constraint = vault.owner == pool.load()?.program_signer @PoolError::InvalidVault)]
+    vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+         mut,
+         constraint = (user_mint_acc.owner == *authority.key && user_mint_acc.mint == mint.key())
+     )]
+    user_mint_acc: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// CHECK
+    #[account(mut, seeds = [pool.key().as_ref(), authority.key().as_ref(), &position_seed(user_state.index)], bump, owner = *__program_id)]
+    pub user_state: Account<'info, UserState>,
+
+    /// The wallet's stake across its positions, required once it holds
+    /// several.
+    #[account(mut, seeds = [WALLET_SEED, pool.key().as_ref(), authority.key().as_ref()], bump)]
+    pub wallet_state: Option<Account<'info, WalletState>>,
+
+    /// CHECK
+    pub authority: Signer<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+
+    /// Current season leaderboard, updated if passed.
+    #[account(mut)]
+    pub leaderboard: Option<Account<'info, Leaderboard>>,
+
+    /// Current checkpoint pages of the user and of the pool total, required
+    /// while checkpoints are enabled.
+    #[account(mut)]
+    pub user_checkpoints: Option<AccountLoader<'info, Checkpoints>>,
+
+    #[account(mut)]
+    pub pool_checkpoints: Option<AccountLoader<'info, Checkpoints>>,
+
+    /// Required once the pool has a config.
+    #[account(seeds = [CONFIG_SEED, pool.key().as_ref()], bump)]
+    pub config: Option<Account<'info, PoolConfig>>,
+
+    /// CHECK: checked against the config's attestation program and schema;
+    /// raises the per-user cap when valid
+    pub attestation: Option<UncheckedAccount<'info>>,
+
+    /// Waives deposit and withdrawal fees when passed.
+    #[account(seeds = [FEE_EXEMPTION_SEED, pool.key().as_ref(), authority.key().as_ref()], bump)]
+    pub fee_exemption: Option<Account<'info, FeeExemption>>,
+
+    /// Required once the pool keeps an audit log.
+    #[account(mut, seeds = [AUDIT_LOG_SEED, pool.key().as_ref()], bump)]
+    pub audit_log: Option<AccountLoader<'info, AuditLog>>,
+
+    /// The wallet's profile, updated if passed.
+    #[account(mut, seeds = [USER_PROFILE_SEED, authority.key().as_ref()], bump)]
+    pub user_profile: Option<Account<'info, UserProfile>>,
+
+    /// The pool's rate publisher, refreshed if passed.
+    #[account(mut, seeds = [RATE_PUBLISHER_SEED, pool.key().as_ref()], bump)]
+    pub rate_publisher: Option<Account<'info, RatePublisher>>,
+
+    /// CHECK: instructions sysvar, required while the pool requires memos
+    #[account(address = sysvar::instructions::ID)]
+    pub instructions: Option<UncheckedAccount<'info>>,
+}
+
+/// Accounts the swap adapter needs beyond these are passed as remaining
+/// accounts.
+#[derive(Accounts)]
+pub struct ZapStake<'info> {
+    #[account(mut, owner = *__program_id )]
+    pub pool: AccountLoader<'info, Pool>,
+
+    /// CHECK
+    #[account(constraint = mint.key() == pool.load()?.mint @PoolError::InvalidMint, mint::token_program = token_program)]
+    mint: Box<InterfaceAccount<'info, Mint>>,
+
+    /// CHECK: staking vault
+    #[account(mut, constraint = vault.key() == pool.load()?.vault @PoolError::InvalidVault)]
+    vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mint::token_program = input_token_program)]
+    input_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        token::mint = input_mint,
+        token::authority = authority,
+        constraint = user_input_acc.mint != mint.key() @PoolError::InvalidMint
+    )]
+    user_input_acc: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// CHECK
+    #[account(mut, seeds = [pool.key().as_ref(), authority.key().as_ref(), &position_seed(user_state.index)], bump, owner = *__program_id)]
+    pub user_state: Account<'info, UserState>,
+
+    /// The wallet's stake across its positions, required once it holds
+    /// several.
+    #[account(mut, seeds = [WALLET_SEED, pool.key().as_ref(), authority.key().as_ref()], bump)]
+    pub wallet_state: Option<Account<'info, WalletState>>,
+
+    /// CHECK: must be the adapter whitelisted in the pool
+    #[account(executable, constraint = swap_adapter.key() == pool.load()?.swap_adapter @PoolError::InvalidSwapAdapter)]
+    pub swap_adapter: UncheckedAccount<'info>,
+
+    pub authority: Signer<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+
+    pub input_token_program: Interface<'info, TokenInterface>,
+
+    /// Current season leaderboard, updated if passed.
+    #[account(mut)]
+    pub leaderboard: Option<Account<'info, Leaderboard>>,
+
+    #[account(mut)]
+    pub user_checkpoints: Option<AccountLoader<'info, Checkpoints>>,
+
+    #[account(mut)]
+    pub pool_checkpoints: Option<AccountLoader<'info, Checkpoints>>,
+
+    /// Required once the pool has a config.
+    #[account(seeds = [CONFIG_SEED, pool.key().as_ref()], bump)]
+    pub config: Option<Account<'info, PoolConfig>>,
+
+    /// CHECK: see `EnterStaking::attestation`
+    pub attestation: Option<UncheckedAccount<'info>>,
+
+    #[account(seeds = [FEE_EXEMPTION_SEED, pool.key().as_ref(), authority.key().as_ref()], bump)]
+    pub fee_exemption: Option<Account<'info, FeeExemption>>,
+
+    /// Required once the pool keeps an audit log.
+    #[account(mut, seeds = [AUDIT_LOG_SEED, pool.key().as_ref()], bump)]
+    pub audit_log: Option<AccountLoader<'info, AuditLog>>,
+
+    /// The wallet's profile, updated if passed.
+    #[account(mut, seeds = [USER_PROFILE_SEED, authority.key().as_ref()], bump)]
+    pub user_profile: Option<Account<'info, UserProfile>>,
+
+    /// The pool's rate publisher, refreshed if passed.
+    #[account(mut, seeds = [RATE_PUBLISHER_SEED, pool.key().as_ref()], bump)]
+    pub rate_publisher: Option<Account<'info, RatePublisher>>,
+
+    /// CHECK: instructions sysvar, required while the pool requires memos
+    #[account(address = sysvar::instructions::ID)]
+    pub instructions: Option<UncheckedAccount<'info>>,
+}
+
+/// Accounts the swap adapter needs beyond these are passed as remaining
+/// accounts. `leave.user_mint_acc` still receives rewards minted on a full
+/// exit.
+#[derive(Accounts)]
+pub struct LeaveStakingAndSwap<'info> {
+    pub leave: LeaveStaking<'info>,
+
+    /// CHECK: signs the swap, see `SWAP_AUTHORITY_SEED`
+    #[account(seeds = [SWAP_AUTHORITY_SEED, leave.pool.key().as_ref()], bump)]
+    pub swap_authority: UncheckedAccount<'info>,
+
+    /// Scratch account the payout is swapped out of.
+    #[account(
+        mut,
+        token::mint = leave.mint,
+        token::authority = swap_authority,
+        token::token_program = leave.token_program
+    )]
+    swap_source: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mint::token_program = output_token_program)]
+    output_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        token::mint = output_mint,
+        token::authority = leave.authority,
+        constraint = user_output_acc.mint != leave.mint.key() @PoolError::InvalidMint
+    )]
+    user_output_acc: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// CHECK: must be the adapter whitelisted in the pool
+    #[account(executable, constraint = swap_adapter.key() == leave.pool.load()?.swap_adapter @PoolError::InvalidSwapAdapter)]
+    pub swap_adapter: UncheckedAccount<'info>,
+
+    pub output_token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct LeaveStaking<'info> {
+    #[account(mut, owner = *__program_id )]
+    pub pool: AccountLoader<'info, Pool>,
+
+    /// CHECK: must be the signer stored in the pool
+    #[account(constraint = program_signer.key() == pool.load()?.program_signer @PoolError::InvalidProgramSigner)]
+    pub program_signer: AccountInfo<'info>,
+
+    /// CHECK: mutable so rewards can be minted on a full exit
+    #[account(mut, mint::token_program = token_program)]
+    mint: Box<InterfaceAccount<'info, Mint>>,
+
+    /// CHECK: staking vault, must be owned by the pool's program signer
+    #[account(mut, constraint = vault.owner == pool.load()?.program_signer @PoolError::InvalidVault)]
+    vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// The user's token account, or the registered cold storage destination.
+    #[account(
+         mut,
+         constraint = ((user_mint_acc.owner == *authority.key || user_mint_acc.key() == user_state.cold_destination) && user_mint_acc.mint == mint.key())
+     )]
+    user_mint_acc: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// CHECK
+    #[account(mut, seeds = [pool.key().as_ref(), authority.key().as_ref(), &position_seed(user_state.index)], bump, owner = *__program_id)]
+    pub user_state: Account<'info, UserState>,
+
+    /// The wallet's stake across its positions, required once it holds
+    /// several.
+    #[account(mut, seeds = [WALLET_SEED, pool.key().as_ref(), authority.key().as_ref()], bump)]
+    pub wallet_state: Option<Account<'info, WalletState>>,
+
+    /// CHECK: the position this stake boosts through a `BoostLink`, required
+    /// while there is one
+    #[account(mut, address = user_state.link_boosted @PoolError::InvalidBoostLink)]
+    pub link_boosted: Option<UncheckedAccount<'info>>,
+
+    /// CHECK
+    pub authority: Signer<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+
+    /// Current season leaderboard, updated if passed.
+    #[account(mut)]
+    pub leaderboard: Option<Account<'info, Leaderboard>>,
+
+    /// Current checkpoint pages of the user and of the pool total, required
+    /// while checkpoints are enabled.
+    #[account(mut)]
+    pub user_checkpoints: Option<AccountLoader<'info, Checkpoints>>,
+
+    #[account(mut)]
+    pub pool_checkpoints: Option<AccountLoader<'info, Checkpoints>>,
+
+    /// Required once the pool has a config.
+    #[account(seeds = [CONFIG_SEED, pool.key().as_ref()], bump)]
+    pub config: Option<Account<'info, PoolConfig>>,
+
+    /// Co-signer required for withdrawals above the user's guardian
+    /// threshold, unless announced in advance.
+    pub guardian: Option<Signer<'info>>,
+
+    /// Waives deposit and withdrawal fees when passed.
+    #[account(seeds = [FEE_EXEMPTION_SEED, pool.key().as_ref(), authority.key().as_ref()], bump)]
+    pub fee_exemption: Option<Account<'info, FeeExemption>>,
+
+    /// Required once the pool keeps an audit log.
+    #[account(mut, seeds = [AUDIT_LOG_SEED, pool.key().as_ref()], bump)]
+    pub audit_log: Option<AccountLoader<'info, AuditLog>>,
+
+    /// The wallet's profile, updated if passed.
+    #[account(mut, seeds = [USER_PROFILE_SEED, authority.key().as_ref()], bump)]
+    pub user_profile: Option<Account<'info, UserProfile>>,
+
+    /// The pool's rate publisher, refreshed if passed.
+    #[account(mut, seeds = [RATE_PUBLISHER_SEED, pool.key().as_ref()], bump)]
+    pub rate_publisher: Option<Account<'info, RatePublisher>>,
+
+    /// CHECK: instructions sysvar, required while the pool requires memos
+    #[account(address = sysvar::instructions::ID)]
+    pub instructions: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: checked by address; repeats the memo ahead of the payout
+    #[account(address = MEMO_PROGRAM_ID)]
+    pub memo_program: Option<UncheckedAccount<'info>>,
+}
+
+/// Checks that the pool's books are covered by its vault. Run at the end of
+/// every instruction that moves staked tokens, after any CPI has returned,
+/// and follow it with `Pool::publish_state`.
+fn assert_pool_invariants(pool: &Pool, vault: &TokenAccount) -> PoolResult {
+    require_eq!(pool.mint, vault.mint, PoolError::InvalidVault);
+    require_eq!(
+        pool.program_signer,
+        vault.owner,
+        PoolError::InvalidProgramSigner
+    );
+    require_gte!(
+        pool.reserves(vault)?,
+        pool.written_down_total(pool.liabilities()?)?,
+        PoolError::PoolInvariantViolated
+    );
+
+    Ok(())
+}
+
+/// `assert_pool_invariants` after an emergency exit, given the reserves and
+/// liabilities before it. An exit can't undo a shortfall, so a pool that
+/// was short only has to cover what is left at no worse a ratio.
+fn assert_exit_invariants(pool: &Pool, vault: &TokenAccount, before: (u128, u128)) -> PoolResult {
+    let (reserves, liabilities) = before;
+    if reserves >= liabilities {
+        return assert_pool_invariants(pool, vault);
+    }
+
+    require_eq!(pool.mint, vault.mint, PoolError::InvalidVault);
+    require_eq!(
+        pool.program_signer,
+        vault.owner,
+        PoolError::InvalidProgramSigner
+    );
+    require_gte!(
+        pool.reserves(vault)?.safe_mul(liabilities)?,
+        pool.liabilities()?.safe_mul(reserves)?,
+        PoolError::PoolInvariantViolated
+    );
+
+    Ok(())
+}
+
+/// The memo of the transaction's previous instruction, which must be an
+/// SPL Memo while the pool requires memos; `None` otherwise.
+fn require_memo(
+    pool: &Pool,
+    instructions: Option<&UncheckedAccount>,
+) -> PoolResult<Option<Vec<u8>>> {
+    if pool.memo_required == 0 {
+        return Ok(None);
+    }
+
+    let instructions = instructions.ok_or(PoolError::MemoRequired)?;
+    let current = sysvar::instructions::load_current_index_checked(instructions)?;
+    require!(current > 0, PoolError::MemoRequired);
+
+    let previous =
+        sysvar::instructions::load_instruction_at_checked(current as usize - 1, instructions)?;
+    require!(
+        previous.program_id == MEMO_PROGRAM_ID && !previous.data.is_empty(),
+        PoolError::MemoRequired
+    );
+
+    Ok(Some(previous.data))
+}
+
+/// Logs `memo` through the memo program right before a transfer out, as
+/// Token-2022 accounts with the required-memo extension expect.
+fn forward_memo(memo_program: Option<&UncheckedAccount>, memo: &[u8]) -> PoolResult {
+    let memo_program = memo_program.ok_or(PoolError::MemoRequired)?;
+
+    invoke(
+        &Instruction {
+            program_id: memo_program.key(),
+            accounts: vec![],
+            data: memo.to_vec(),
+        },
+        &[],
+    )?;
+
+    Ok(())
+}
+
+fn can_mint_rewards(pool: &Pool, mint: &InterfaceAccount<Mint>) -> bool {
+    mint.mint_authority == CSome(pool.program_signer)
+}
+
+/// Returns the transfer fee the mint will withhold when moving `amount`, or
+/// zero for mints without the transfer-fee extension.
+fn transfer_fee(mint: &InterfaceAccount<Mint>, amount: u64) -> PoolResult<u64> {
+    let mint_info = mint.to_account_info();
+    let mint_data = mint_info.try_borrow_data()?;
+    let mint_state = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&mint_data)?;
+
+    match mint_state.get_extension::<TransferFeeConfig>() {
+        Ok(fee_config) => Ok(fee_config
+            .calculate_epoch_fee(Clock::get()?.epoch, amount)
+            .ok_or(ProgramError::ArithmeticOverflow)?),
+        Err(_) => Ok(0),
+    }
+}
+
+/// Returns the transfer fee to add to `amount` so that `amount` arrives after
+/// the mint withholds its fee, or zero for mints without the extension.
+fn inverse_transfer_fee(mint: &InterfaceAccount<Mint>, amount: u64) -> PoolResult<u64> {
+    let mint_info = mint.to_account_info();
+    let mint_data = mint_info.try_borrow_data()?;
+    let mint_state = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&mint_data)?;
+
+    match mint_state.get_extension::<TransferFeeConfig>() {
+        Ok(fee_config) => Ok(fee_config
+            .calculate_inverse_epoch_fee(Clock::get()?.epoch, amount)
+            .ok_or(ProgramError::ArithmeticOverflow)?),
+        Err(_) => Ok(0),
+    }
+}
+
+/// `transfer_checked` that also works for transfer-hook mints: the extra
+/// accounts the hook needs are looked up in `remaining_accounts`.
+#[allow(clippy::too_many_arguments)]
+fn transfer_checked_with_hook<'info>(
+    token_program: &Interface<'info, TokenInterface>,
+    from: AccountInfo<'info>,
+    mint: &InterfaceAccount<'info, Mint>,
+    to: AccountInfo<'info>,
+    authority: AccountInfo<'info>,
+    remaining_accounts: &[AccountInfo<'info>],
+    amount: u64,
+    signer_seeds: &[&[&[u8]]],
+) -> PoolResult {
+    spl_token_2022::onchain::invoke_transfer_checked(
+        token_program.key,
+        from,
+        mint.to_account_info(),
+        to,
+        authority,
+        remaining_accounts,
+        amount,
+        mint.decimals,
+        signer_seeds,
+    )?;
+
+    Ok(())
+}
+
+/// Pays rewards split by `Pool::split_rewards` to `to`: the funded part
+/// out of the vault, the rest minted.
+#[allow(clippy::too_many_arguments)]
+fn pay_rewards<'info>(
+    token_program: &Interface<'info, TokenInterface>,
+    mint: &InterfaceAccount<'info, Mint>,
+    vault: AccountInfo<'info>,
+    to: AccountInfo<'info>,
+    authority: AccountInfo<'info>,
+    remaining_accounts: &[AccountInfo<'info>],
+    (funded, minted): (u64, u64),
+    signer_seeds: &[&[&[u8]]],
+) -> PoolResult {
+    if funded > 0 {
+        transfer_checked_with_hook(
+            token_program,
+            vault,
+            mint,
+            to.clone(),
+            authority.clone(),
+            remaining_accounts,
+            funded,
+            signer_seeds,
+        )?;
+    }
+
+    mint_rewards(token_program, mint, to, authority, minted, signer_seeds)
+}
+
+/// Mints `amount` of reward tokens to `to`. Does nothing for zero.
+fn mint_rewards<'info>(
+    token_program: &Interface<'info, TokenInterface>,
+    mint: &InterfaceAccount<'info, Mint>,
+    to: AccountInfo<'info>,
+    authority: AccountInfo<'info>,
+    amount: u64,
+    signer_seeds: &[&[&[u8]]],
+) -> PoolResult {
+    if amount == 0 {
+        return Ok(());
+    }
+
+    let cpi_accounts = MintTo {
+        mint: mint.to_account_info(),
+        to,
+        authority,
+    };
+    let cpi_ctx =
+        CpiContext::new_with_signer(token_program.to_account_info(), cpi_accounts, signer_seeds);
+
+    token_interface::mint_to(cpi_ctx, amount)
+}
+
+#[derive(Accounts)]
+pub struct SetEmissionSchedule<'info> {
+    #[account(mut, owner = *__program_id, has_one = authority @PoolError::Unauthorized)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// Next entry of the config history, required while the pool keeps one.
+    #[account(
+        init,
+        seeds = [CONFIG_CHANGE_SEED, pool.key().as_ref(), &pool.load()?.config_change_count.to_le_bytes()],
+        bump,
+        payer = authority,
+        space = 8 + ConfigChange::size()
+    )]
+    pub config_change: Option<Account<'info, ConfigChange>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetDynamicApr<'info> {
+    #[account(mut, owner = *__program_id, has_one = authority @PoolError::Unauthorized)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// Next entry of the config history, required while the pool keeps one.
+    #[account(
+        init,
+        seeds = [CONFIG_CHANGE_SEED, pool.key().as_ref(), &pool.load()?.config_change_count.to_le_bytes()],
+        bump,
+        payer = authority,
+        space = 8 + ConfigChange::size()
+    )]
+    pub config_change: Option<Account<'info, ConfigChange>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetStreakBoost<'info> {
+    #[account(mut, owner = *__program_id, has_one = authority @PoolError::Unauthorized)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// Next entry of the config history, required while the pool keeps one.
+    #[account(
+        init,
+        seeds = [CONFIG_CHANGE_SEED, pool.key().as_ref(), &pool.load()?.config_change_count.to_le_bytes()],
+        bump,
+        payer = authority,
+        space = 8 + ConfigChange::size()
+    )]
+    pub config_change: Option<Account<'info, ConfigChange>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetPointsMode<'info> {
+    #[account(mut, owner = *__program_id, has_one = authority @PoolError::Unauthorized)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// Next entry of the config history, required while the pool keeps one.
+    #[account(
+        init,
+        seeds = [CONFIG_CHANGE_SEED, pool.key().as_ref(), &pool.load()?.config_change_count.to_le_bytes()],
+        bump,
+        payer = authority,
+        space = 8 + ConfigChange::size()
+    )]
+    pub config_change: Option<Account<'info, ConfigChange>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SettlePoints<'info> {
+    #[account(owner = *__program_id, has_one = authority @PoolError::Unauthorized)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    /// CHECK: only used to derive the user state address
+    pub user: UncheckedAccount<'info>,
+
+    #[account(mut, seeds = [pool.key().as_ref(), user.key().as_ref(), &position_seed(user_state.index)], bump, owner = *__program_id)]
+    pub user_state: Account<'info, UserState>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(season: u32)]
+pub struct StartSeason<'info> {
+    #[account(mut, owner = *__program_id)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    #[account(
+        init,
+        seeds = [LEADERBOARD_SEED, pool.key().as_ref(), &season.to_le_bytes()],
+        bump,
+        payer = authority,
+        space = 8 + Leaderboard::size()
+    )]
+    pub leaderboard: Account<'info, Leaderboard>,
+
+    /// The admin or the operator.
+    #[account(mut, constraint = pool.load()?.can_operate(authority.key()) @PoolError::Unauthorized)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateLeaderboard<'info> {
+    #[account(mut, owner = *__program_id )]
+    pub pool: AccountLoader<'info, Pool>,
+
+    /// CHECK: only used to derive the user state address
+    pub user: UncheckedAccount<'info>,
+
+    #[account(mut, seeds = [pool.key().as_ref(), user.key().as_ref()], bump, owner = *__program_id)]
+    pub user_state: Account<'info, UserState>,
+
+    #[account(mut)]
+    pub leaderboard: Account<'info, Leaderboard>,
+
+    /// The wallet's stake across its positions, required once it holds
+    /// several.
+    #[account(mut, seeds = [WALLET_SEED, pool.key().as_ref(), user.key().as_ref()], bump)]
+    pub wallet_state: Option<Account<'info, WalletState>>,
+}
+
+#[derive(Accounts)]
+pub struct SetVoteDelegate<'info> {
+    /// CHECK
+    pub pool: UncheckedAccount<'info>,
+
+    #[account(mut, seeds = [pool.key().as_ref(), authority.key().as_ref(), &position_seed(user_state.index)], bump, owner = *__program_id)]
+    pub user_state: Account<'info, UserState>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetGuardian<'info> {
+    /// CHECK
+    pub pool: UncheckedAccount<'info>,
+
+    #[account(mut, seeds = [pool.key().as_ref(), authority.key().as_ref(), &position_seed(user_state.index)], bump, owner = *__program_id)]
+    pub user_state: Account<'info, UserState>,
+
+    pub authority: Signer<'info>,
+
+    /// The current guardian, required to change settings once one is set.
+    pub guardian: Option<Signer<'info>>,
+}
+
+#[derive(Accounts)]
+pub struct AnnounceWithdrawal<'info> {
+    /// CHECK
+    pub pool: UncheckedAccount<'info>,
+
+    #[account(mut, seeds = [pool.key().as_ref(), authority.key().as_ref(), &position_seed(user_state.index)], bump, owner = *__program_id)]
+    pub user_state: Account<'info, UserState>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetColdStorage<'info> {
+    /// CHECK
+    pub pool: UncheckedAccount<'info>,
+
+    #[account(mut, seeds = [pool.key().as_ref(), authority.key().as_ref(), &position_seed(user_state.index)], bump, owner = *__program_id)]
+    pub user_state: Account<'info, UserState>,
+
+    pub authority: Signer<'info>,
+
+    /// The current cold key, required to change settings once one is set.
+    pub cold_key: Option<Signer<'info>>,
+}
+
+#[derive(Accounts)]
+pub struct RequestColdWithdrawal<'info> {
+    /// CHECK
+    pub pool: UncheckedAccount<'info>,
+
+    #[account(mut, seeds = [pool.key().as_ref(), authority.key().as_ref(), &position_seed(user_state.index)], bump, owner = *__program_id)]
+    pub user_state: Account<'info, UserState>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ApproveColdWithdrawal<'info> {
+    /// CHECK
+    pub pool: UncheckedAccount<'info>,
+
+    /// CHECK: only used to derive the user state
+    pub user: UncheckedAccount<'info>,
+
+    #[account(mut, seeds = [pool.key().as_ref(), user.key().as_ref(), &position_seed(user_state.index)], bump, owner = *__program_id)]
+    pub user_state: Account<'info, UserState>,
+
+    #[account(address = user_state.cold_key @PoolError::ColdApprovalRequired)]
+    pub cold_key: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetSessionKey<'info> {
+    /// CHECK
+    pub pool: UncheckedAccount<'info>,
+
+    #[account(mut, seeds = [pool.key().as_ref(), authority.key().as_ref(), &position_seed(user_state.index)], bump, owner = *__program_id)]
+    pub user_state: Account<'info, UserState>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct GetVoterWeight<'info> {
+    /// CHECK
+    pub pool: UncheckedAccount<'info>,
+
+    /// CHECK: only used to derive the user state address
+    pub user: UncheckedAccount<'info>,
+
+    #[account(seeds = [pool.key().as_ref(), user.key().as_ref()], bump, owner = *__program_id)]
+    pub user_state: Account<'info, UserState>,
+}
+
+#[derive(Accounts)]
+pub struct CheckReserves<'info> {
+    #[account(mut, owner = *__program_id )]
+    pub pool: AccountLoader<'info, Pool>,
+
+    #[account(constraint = vault.key() == pool.load()?.vault @PoolError::InvalidVault)]
+    vault: Box<InterfaceAccount<'info, TokenAccount>>,
+}
+
+#[derive(Accounts)]
+pub struct TakeFreezeAuthority<'info> {
+    #[account(owner = *__program_id, has_one = authority @PoolError::Unauthorized, has_one = mint @PoolError::InvalidMint)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    /// CHECK: must be the signer stored in the pool
+    #[account(constraint = program_signer.key() == pool.load()?.program_signer @PoolError::InvalidProgramSigner)]
+    pub program_signer: AccountInfo<'info>,
+
+    #[account(mut, mint::token_program = token_program)]
+    mint: Box<InterfaceAccount<'info, Mint>>,
+
+    /// Pool authority and current freeze authority.
+    pub authority: Signer<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct AuthorityRotation<'info> {
+    #[account(mut, owner = *__program_id, has_one = authority @PoolError::Unauthorized)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// Next entry of the config history, required while the pool keeps one.
+    #[account(
+        init,
+        seeds = [CONFIG_CHANGE_SEED, pool.key().as_ref(), &pool.load()?.config_change_count.to_le_bytes()],
+        bump,
+        payer = authority,
+        space = 8 + ConfigChange::size()
+    )]
+    pub config_change: Option<Account<'info, ConfigChange>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ExecuteAuthorityRotation<'info> {
+    #[account(mut, owner = *__program_id, has_one = authority @PoolError::Unauthorized, has_one = mint @PoolError::InvalidMint)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    /// CHECK: must be the signer stored in the pool
+    #[account(constraint = program_signer.key() == pool.load()?.program_signer @PoolError::InvalidProgramSigner)]
+    pub program_signer: AccountInfo<'info>,
+
+    #[account(mut, mint::token_program = token_program)]
+    mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+
+    /// Next entry of the config history, required while the pool keeps one.
+    #[account(
+        init,
+        seeds = [CONFIG_CHANGE_SEED, pool.key().as_ref(), &pool.load()?.config_change_count.to_le_bytes()],
+        bump,
+        payer = authority,
+        space = 8 + ConfigChange::size()
+    )]
+    pub config_change: Option<Account<'info, ConfigChange>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ProposeVaultRotation<'info> {
+    #[account(mut, owner = *__program_id, has_one = authority @PoolError::Unauthorized)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    #[account(
+        constraint = new_vault.mint == pool.load()?.mint
+            && new_vault.owner == pool.load()?.program_signer
+            && new_vault.key() != pool.load()?.vault @PoolError::InvalidVault
+    )]
+    new_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// Next entry of the config history, required while the pool keeps one.
+    #[account(
+        init,
+        seeds = [CONFIG_CHANGE_SEED, pool.key().as_ref(), &pool.load()?.config_change_count.to_le_bytes()],
+        bump,
+        payer = authority,
+        space = 8 + ConfigChange::size()
+    )]
+    pub config_change: Option<Account<'info, ConfigChange>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CancelVaultRotation<'info> {
+    #[account(mut, owner = *__program_id, has_one = authority @PoolError::Unauthorized)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// Next entry of the config history, required while the pool keeps one.
+    #[account(
+        init,
+        seeds = [CONFIG_CHANGE_SEED, pool.key().as_ref(), &pool.load()?.config_change_count.to_le_bytes()],
+        bump,
+        payer = authority,
+        space = 8 + ConfigChange::size()
+    )]
+    pub config_change: Option<Account<'info, ConfigChange>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RotateVault<'info> {
+    #[account(
+        mut,
+        owner = *__program_id,
+        has_one = authority @PoolError::Unauthorized,
+        has_one = mint @PoolError::InvalidMint,
+        has_one = vault @PoolError::InvalidVault
+    )]
+    pub pool: AccountLoader<'info, Pool>,
+
+    /// CHECK: must be the signer stored in the pool
+    #[account(constraint = program_signer.key() == pool.load()?.program_signer @PoolError::InvalidProgramSigner)]
+    pub program_signer: AccountInfo<'info>,
+
+    #[account(mint::token_program = token_program)]
+    mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(mut)]
+    vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut, constraint = new_vault.key() == pool.load()?.vault_rotation_target @PoolError::InvalidVault)]
+    new_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+
+    /// Next entry of the config history, required while the pool keeps one.
+    #[account(
+        init,
+        seeds = [CONFIG_CHANGE_SEED, pool.key().as_ref(), &pool.load()?.config_change_count.to_le_bytes()],
+        bump,
+        payer = authority,
+        space = 8 + ConfigChange::size()
+    )]
+    pub config_change: Option<Account<'info, ConfigChange>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetSolRewards<'info> {
+    #[account(mut, owner = *__program_id, constraint = pool.load()?.authority == authority.key() || campaign_manager.is_some() @PoolError::Unauthorized)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    /// The authority, or a campaign manager of the pool.
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// Required when a campaign manager signs.
+    #[account(seeds = [CAMPAIGN_MANAGER_SEED, pool.key().as_ref(), authority.key().as_ref()], bump)]
+    pub campaign_manager: Option<Account<'info, CampaignManager>>,
+
+    /// Next entry of the config history, required while the pool keeps one.
+    #[account(
+        init,
+        seeds = [CONFIG_CHANGE_SEED, pool.key().as_ref(), &pool.load()?.config_change_count.to_le_bytes()],
+        bump,
+        payer = authority,
+        space = 8 + ConfigChange::size()
+    )]
+    pub config_change: Option<Account<'info, ConfigChange>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(manager: Pubkey)]
+pub struct AddCampaignManager<'info> {
+    #[account(mut, owner = *__program_id, has_one = authority @PoolError::Unauthorized)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    #[account(
+        init,
+        seeds = [CAMPAIGN_MANAGER_SEED, pool.key().as_ref(), manager.as_ref()],
+        bump,
+        payer = authority,
+        space = 8 + CampaignManager::size()
+    )]
+    pub campaign_manager: Account<'info, CampaignManager>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// Next entry of the config history, required while the pool keeps one.
+    #[account(
+        init,
+        seeds = [CONFIG_CHANGE_SEED, pool.key().as_ref(), &pool.load()?.config_change_count.to_le_bytes()],
+        bump,
+        payer = authority,
+        space = 8 + ConfigChange::size()
+    )]
+    pub config_change: Option<Account<'info, ConfigChange>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RemoveCampaignManager<'info> {
+    #[account(mut, owner = *__program_id, has_one = authority @PoolError::Unauthorized)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    #[account(mut, close = authority, has_one = pool)]
+    pub campaign_manager: Account<'info, CampaignManager>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// Next entry of the config history, required while the pool keeps one.
+    #[account(
+        init,
+        seeds = [CONFIG_CHANGE_SEED, pool.key().as_ref(), &pool.load()?.config_change_count.to_le_bytes()],
+        bump,
+        payer = authority,
+        space = 8 + ConfigChange::size()
+    )]
+    pub config_change: Option<Account<'info, ConfigChange>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct FundSolRewards<'info> {
+    #[account(owner = *__program_id)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    #[account(mut, seeds = [SOL_REWARD_VAULT_SEED, pool.key().as_ref()], bump)]
+    pub sol_reward_vault: SystemAccount<'info>,
+
+    #[account(mut)]
+    pub funder: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct FundEmissions<'info> {
+    #[account(mut, owner = *__program_id)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    #[account(constraint = mint.key() == pool.load()?.mint @PoolError::InvalidMint, mint::token_program = token_program)]
+    mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(mut, constraint = vault.key() == pool.load()?.vault @PoolError::InvalidVault)]
+    vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut, token::mint = mint, token::authority = funder)]
+    funder_mint_acc: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    pub funder: Signer<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct ExtendEmissions<'info> {
+    #[account(mut, owner = *__program_id, has_one = authority @PoolError::Unauthorized)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// Next entry of the config history, required while the pool keeps one.
+    #[account(
+        init,
+        seeds = [CONFIG_CHANGE_SEED, pool.key().as_ref(), &pool.load()?.config_change_count.to_le_bytes()],
+        bump,
+        payer = authority,
+        space = 8 + ConfigChange::size()
+    )]
+    pub config_change: Option<Account<'info, ConfigChange>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ExtendSolRewards<'info> {
+    #[account(mut, owner = *__program_id)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    #[account(mut, seeds = [SOL_REWARD_VAULT_SEED, pool.key().as_ref()], bump)]
+    pub sol_reward_vault: SystemAccount<'info>,
+
+    #[account(mut)]
+    pub funder: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimSolRewards<'info> {
+    #[account(mut, owner = *__program_id)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    #[account(mut, seeds = [SOL_REWARD_VAULT_SEED, pool.key().as_ref()], bump)]
+    pub sol_reward_vault: SystemAccount<'info>,
+
+    /// CHECK: the staker; rewards always go to their wallet
+    #[account(mut)]
+    pub user: UncheckedAccount<'info>,
+
+    #[account(mut, seeds = [pool.key().as_ref(), user.key().as_ref(), &position_seed(user_state.index)], bump, owner = *__program_id)]
+    pub user_state: Account<'info, UserState>,
+
+    /// The user, or their session key.
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+
+    /// Required once the pool keeps an audit log.
+    #[account(mut, seeds = [AUDIT_LOG_SEED, pool.key().as_ref()], bump)]
+    pub audit_log: Option<AccountLoader<'info, AuditLog>>,
+}
+
+#[derive(Accounts)]
+pub struct SettleShortfall<'info> {
+    #[account(mut, owner = *__program_id, has_one = authority @PoolError::Unauthorized)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    #[account(constraint = vault.key() == pool.load()?.vault @PoolError::InvalidVault)]
+    vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetConfigHistory<'info> {
+    #[account(mut, owner = *__program_id, has_one = authority @PoolError::Unauthorized)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// Next entry of the config history, required while the pool keeps one.
+    #[account(
+        init,
+        seeds = [CONFIG_CHANGE_SEED, pool.key().as_ref(), &pool.load()?.config_change_count.to_le_bytes()],
+        bump,
+        payer = authority,
+        space = 8 + ConfigChange::size()
+    )]
+    pub config_change: Option<Account<'info, ConfigChange>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetRateGuard<'info> {
+    #[account(mut, owner = *__program_id, has_one = authority @PoolError::Unauthorized)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// Next entry of the config history, required while the pool keeps one.
+    #[account(
+        init,
+        seeds = [CONFIG_CHANGE_SEED, pool.key().as_ref(), &pool.load()?.config_change_count.to_le_bytes()],
+        bump,
+        payer = authority,
+        space = 8 + ConfigChange::size()
+    )]
+    pub config_change: Option<Account<'info, ConfigChange>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetMemoRequired<'info> {
+    #[account(mut, owner = *__program_id, has_one = authority @PoolError::Unauthorized)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// Next entry of the config history, required while the pool keeps one.
+    #[account(
+        init,
+        seeds = [CONFIG_CHANGE_SEED, pool.key().as_ref(), &pool.load()?.config_change_count.to_le_bytes()],
+        bump,
+        payer = authority,
+        space = 8 + ConfigChange::size()
+    )]
+    pub config_change: Option<Account<'info, ConfigChange>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetWatcher<'info> {
+    #[account(mut, owner = *__program_id, has_one = authority @PoolError::Unauthorized)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// Next entry of the config history, required while the pool keeps one.
+    #[account(
+        init,
+        seeds = [CONFIG_CHANGE_SEED, pool.key().as_ref(), &pool.load()?.config_change_count.to_le_bytes()],
+        bump,
+        payer = authority,
+        space = 8 + ConfigChange::size()
+    )]
+    pub config_change: Option<Account<'info, ConfigChange>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetOperator<'info> {
+    #[account(mut, owner = *__program_id, has_one = authority @PoolError::Unauthorized)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// Next entry of the config history, required while the pool keeps one.
+    #[account(
+        init,
+        seeds = [CONFIG_CHANGE_SEED, pool.key().as_ref(), &pool.load()?.config_change_count.to_le_bytes()],
+        bump,
+        payer = authority,
+        space = 8 + ConfigChange::size()
+    )]
+    pub config_change: Option<Account<'info, ConfigChange>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetTerms<'info> {
+    #[account(mut, owner = *__program_id, has_one = authority @PoolError::Unauthorized)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// Next entry of the config history, required while the pool keeps one.
+    #[account(
+        init,
+        seeds = [CONFIG_CHANGE_SEED, pool.key().as_ref(), &pool.load()?.config_change_count.to_le_bytes()],
+        bump,
+        payer = authority,
+        space = 8 + ConfigChange::size()
+    )]
+    pub config_change: Option<Account<'info, ConfigChange>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptTerms<'info> {
+    #[account(owner = *__program_id)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    #[account(mut, seeds = [pool.key().as_ref(), authority.key().as_ref(), &position_seed(user_state.index)], bump, owner = *__program_id)]
+    pub user_state: Account<'info, UserState>,
+
+    /// The user itself; session keys can't accept terms.
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetIncident<'info> {
+    #[account(mut, owner = *__program_id )]
+    pub pool: AccountLoader<'info, Pool>,
+
+    /// The pool authority or its watcher.
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// Next entry of the config history, required while the pool keeps one.
+    #[account(
+        init,
+        seeds = [CONFIG_CHANGE_SEED, pool.key().as_ref(), &pool.load()?.config_change_count.to_le_bytes()],
+        bump,
+        payer = authority,
+        space = 8 + ConfigChange::size()
+    )]
+    pub config_change: Option<Account<'info, ConfigChange>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ResolveIncident<'info> {
+    #[account(mut, owner = *__program_id, has_one = authority @PoolError::Unauthorized)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// Next entry of the config history, required while the pool keeps one.
+    #[account(
+        init,
+        seeds = [CONFIG_CHANGE_SEED, pool.key().as_ref(), &pool.load()?.config_change_count.to_le_bytes()],
+        bump,
+        payer = authority,
+        space = 8 + ConfigChange::size()
+    )]
+    pub config_change: Option<Account<'info, ConfigChange>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct EmergencyWithdraw<'info> {
+    #[account(mut, owner = *__program_id )]
+    pub pool: AccountLoader<'info, Pool>,
+
+    /// CHECK: must be the signer stored in the pool
+    #[account(constraint = program_signer.key() == pool.load()?.program_signer @PoolError::InvalidProgramSigner)]
+    pub program_signer: AccountInfo<'info>,
+
+    /// CHECK
+    #[account(mint::token_program = token_program)]
+    mint: Box<InterfaceAccount<'info, Mint>>,
+
+    /// CHECK: staking vault, must be owned by the pool's program signer
+    #[account(mut, constraint = vault.key() == pool.load()?.vault @PoolError::InvalidVault)]
+    vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+         mut,
+         constraint = (user_mint_acc.owner == *authority.key && user_mint_acc.mint == mint.key())
+     )]
+    user_mint_acc: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// CHECK
+    #[account(mut, seeds = [pool.key().as_ref(), authority.key().as_ref(), &position_seed(user_state.index)], bump, owner = *__program_id)]
+    pub user_state: Account<'info, UserState>,
+
+    /// The wallet's stake across its positions, required once it holds
+    /// several.
+    #[account(mut, seeds = [WALLET_SEED, pool.key().as_ref(), authority.key().as_ref()], bump)]
+    pub wallet_state: Option<Account<'info, WalletState>>,
+
+    /// CHECK: the position this stake boosts through a `BoostLink`, required
+    /// while there is one
+    #[account(mut, address = user_state.link_boosted @PoolError::InvalidBoostLink)]
+    pub link_boosted: Option<UncheckedAccount<'info>>,
+
+    pub authority: Signer<'info>,
+
+    /// Co-signer required for withdrawals above the user's guardian
+    /// threshold.
+    pub guardian: Option<Signer<'info>>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+
+    #[account(mut)]
+    pub user_checkpoints: Option<AccountLoader<'info, Checkpoints>>,
+
+    #[account(mut)]
+    pub pool_checkpoints: Option<AccountLoader<'info, Checkpoints>>,
+
+    /// Required once the pool keeps an audit log.
+    #[account(mut, seeds = [AUDIT_LOG_SEED, pool.key().as_ref()], bump)]
+    pub audit_log: Option<AccountLoader<'info, AuditLog>>,
+}
+
+#[derive(Accounts)]
+pub struct ClearReserveAlarm<'info> {
+    #[account(mut, owner = *__program_id, has_one = authority @PoolError::Unauthorized)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct GetPoolStats<'info> {
+    #[account(owner = *__program_id )]
+    pub pool: AccountLoader<'info, Pool>,
+}
+
+#[derive(Accounts)]
+pub struct SimulateAction<'info> {
+    #[account(owner = *__program_id )]
+    pub pool: AccountLoader<'info, Pool>,
+
+    /// CHECK
+    #[account(constraint = mint.key() == pool.load()?.mint @PoolError::InvalidMint)]
+    mint: Box<InterfaceAccount<'info, Mint>>,
+
+    /// CHECK: the user the action is previewed for
+    pub user: UncheckedAccount<'info>,
+
+    #[account(seeds = [pool.key().as_ref(), user.key().as_ref(), &position_seed(user_state.index)], bump, owner = *__program_id)]
+    pub user_state: Account<'info, UserState>,
+
+    /// The wallet's stake across its positions, required once it holds
+    /// several.
+    #[account(seeds = [WALLET_SEED, pool.key().as_ref(), user.key().as_ref()], bump)]
+    pub wallet_state: Option<Account<'info, WalletState>>,
+
+    /// Required once the pool has a config.
+    #[account(seeds = [CONFIG_SEED, pool.key().as_ref()], bump)]
+    pub config: Option<Account<'info, PoolConfig>>,
+
+    /// CHECK: see `EnterStaking::attestation`
+    pub attestation: Option<UncheckedAccount<'info>>,
+
+    #[account(seeds = [FEE_EXEMPTION_SEED, pool.key().as_ref(), user.key().as_ref()], bump)]
+    pub fee_exemption: Option<Account<'info, FeeExemption>>,
+}
+
+#[derive(Accounts)]
+pub struct SetCheckpointsEnabled<'info> {
+    #[account(mut, owner = *__program_id, has_one = authority @PoolError::Unauthorized)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// Next entry of the config history, required while the pool keeps one.
+    #[account(
+        init,
+        seeds = [CONFIG_CHANGE_SEED, pool.key().as_ref(), &pool.load()?.config_change_count.to_le_bytes()],
+        bump,
+        payer = authority,
+        space = 8 + ConfigChange::size()
+    )]
+    pub config_change: Option<Account<'info, ConfigChange>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(page: u32)]
+pub struct OpenCheckpointPage<'info> {
+    #[account(mut, owner = *__program_id )]
+    pub pool: AccountLoader<'info, Pool>,
+
+    /// CHECK: the user whose history this is, or the pool for the pool total
+    pub holder: UncheckedAccount<'info>,
+
+    /// Required unless `holder` is the pool.
+    #[account(mut, seeds = [pool.key().as_ref(), holder.key().as_ref()], bump, owner = *__program_id)]
+    pub user_state: Option<Account<'info, UserState>>,
+
+    /// The wallet's stake across its positions, whose history this is once
+    /// it holds several.
+    #[account(mut, seeds = [WALLET_SEED, pool.key().as_ref(), holder.key().as_ref()], bump)]
+    pub wallet_state: Option<Account<'info, WalletState>>,
+
+    /// The full page before `page`, required unless opening page 0.
+    pub previous_checkpoints: Option<AccountLoader<'info, Checkpoints>>,
+
+    #[account(
+        init,
+        seeds = [CHECKPOINTS_SEED, pool.key().as_ref(), holder.key().as_ref(), &page.to_le_bytes()],
+        bump,
+        payer = payer,
+        space = 8 + Checkpoints::size()
+    )]
+    pub checkpoints: AccountLoader<'info, Checkpoints>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct GetVotingPowerAt<'info> {
+    #[account(owner = *__program_id )]
+    pub pool: AccountLoader<'info, Pool>,
+
+    /// CHECK: the user whose history is queried, or the pool for the pool total
+    pub holder: UncheckedAccount<'info>,
+
+    /// Required unless `holder` is the pool.
+    #[account(seeds = [pool.key().as_ref(), holder.key().as_ref()], bump, owner = *__program_id)]
+    pub user_state: Option<Account<'info, UserState>>,
+
+    /// The wallet's stake across its positions, whose history this is once
+    /// it holds several.
+    #[account(seeds = [WALLET_SEED, pool.key().as_ref(), holder.key().as_ref()], bump)]
+    pub wallet_state: Option<Account<'info, WalletState>>,
+
+    /// The page covering the queried slot.
+    pub checkpoints: AccountLoader<'info, Checkpoints>,
+
+    /// The page after `checkpoints`, required unless that is the last one.
+    pub next_checkpoints: Option<AccountLoader<'info, Checkpoints>>,
+}
+
+#[derive(Accounts)]
+pub struct SetRestakingAdapter<'info> {
+    #[account(mut, owner = *__program_id, has_one = authority @PoolError::Unauthorized)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// Next entry of the config history, required while the pool keeps one.
+    #[account(
+        init,
+        seeds = [CONFIG_CHANGE_SEED, pool.key().as_ref(), &pool.load()?.config_change_count.to_le_bytes()],
+        bump,
+        payer = authority,
+        space = 8 + ConfigChange::size()
+    )]
+    pub config_change: Option<Account<'info, ConfigChange>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+/// Shared by `deploy_funds` and `recall_funds`. Accounts the adapter needs
+/// beyond these are passed as remaining accounts.
+#[derive(Accounts)]
+pub struct MoveFunds<'info> {
+    #[account(mut, owner = *__program_id, has_one = authority @PoolError::Unauthorized)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    /// CHECK: must be the signer stored in the pool
+    #[account(constraint = program_signer.key() == pool.load()?.program_signer @PoolError::InvalidProgramSigner)]
+    pub program_signer: AccountInfo<'info>,
+
+    /// CHECK
+    #[account(mint::token_program = token_program)]
+    mint: Box<InterfaceAccount<'info, Mint>>,
+
+    /// CHECK: staking vault, must be owned by the pool's program signer
+    #[account(mut, constraint = vault.key() == pool.load()?.vault @PoolError::InvalidVault)]
+    vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// CHECK: must be the adapter whitelisted in the pool
+    #[account(executable, constraint = adapter_program.key() == pool.load()?.adapter_program @PoolError::InvalidAdapter)]
+    pub adapter_program: UncheckedAccount<'info>,
+
+    /// CHECK: signs for the adapter, see `RESTAKING_AUTHORITY_SEED`
+    #[account(seeds = [RESTAKING_AUTHORITY_SEED, pool.key().as_ref()], bump)]
+    pub restaking_authority: UncheckedAccount<'info>,
+
+    /// Scratch account deposits are moved into for the adapter to take.
+    #[account(
+        mut,
+        token::mint = mint,
+        token::authority = restaking_authority,
+        token::token_program = token_program
+    )]
+    restaking_source: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    pub authority: Signer<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct SetSwapAdapter<'info> {
+    #[account(mut, owner = *__program_id, has_one = authority @PoolError::Unauthorized)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// Next entry of the config history, required while the pool keeps one.
+    #[account(
+        init,
+        seeds = [CONFIG_CHANGE_SEED, pool.key().as_ref(), &pool.load()?.config_change_count.to_le_bytes()],
+        bump,
+        payer = authority,
+        space = 8 + ConfigChange::size()
+    )]
+    pub config_change: Option<Account<'info, ConfigChange>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetClaimAdapter<'info> {
+    #[account(mut, owner = *__program_id, has_one = authority @PoolError::Unauthorized)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// Next entry of the config history, required while the pool keeps one.
+    #[account(
+        init,
+        seeds = [CONFIG_CHANGE_SEED, pool.key().as_ref(), &pool.load()?.config_change_count.to_le_bytes()],
+        bump,
+        payer = authority,
+        space = 8 + ConfigChange::size()
+    )]
+    pub config_change: Option<Account<'info, ConfigChange>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetAchievementUri<'info> {
+    #[account(mut, owner = *__program_id, has_one = authority @PoolError::Unauthorized)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    #[account(
+        init_if_needed,
+        seeds = [ACHIEVEMENT_SEED, pool.key().as_ref()],
+        bump,
+        payer = authority,
+        space = 8 + AchievementConfig::size()
+    )]
+    pub achievement_config: Account<'info, AchievementConfig>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// Next entry of the config history, required while the pool keeps one.
+    #[account(
+        init,
+        seeds = [CONFIG_CHANGE_SEED, pool.key().as_ref(), &pool.load()?.config_change_count.to_le_bytes()],
+        bump,
+        payer = authority,
+        space = 8 + ConfigChange::size()
+    )]
+    pub config_change: Option<Account<'info, ConfigChange>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(achievement: Achievement)]
+pub struct MintAchievement<'info> {
+    #[account(owner = *__program_id)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    #[account(seeds = [ACHIEVEMENT_SEED, pool.key().as_ref()], bump)]
+    pub achievement_config: Account<'info, AchievementConfig>,
+
+    /// CHECK: must be the signer stored in the pool; mints the NFT and is its
+    /// verified creator
+    #[account(constraint = program_signer.key() == pool.load()?.program_signer @PoolError::InvalidProgramSigner)]
+    pub program_signer: AccountInfo<'info>,
+
+    /// Pays for the NFT's accounts.
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    /// Any of the wallet's positions that reached the milestone.
+    #[account(seeds = [pool.key().as_ref(), user.key().as_ref(), &position_seed(user_state.index)], bump, owner = *__program_id)]
+    pub user_state: Account<'info, UserState>,
+
+    /// Current season leaderboard, required for `Achievement::Leaderboard`.
+    pub leaderboard: Option<Account<'info, Leaderboard>>,
+
+    #[account(
+        init_if_needed,
+        seeds = [ACHIEVEMENT_SEED, pool.key().as_ref(), user.key().as_ref()],
+        bump,
+        payer = user,
+        space = 8 + WalletAchievements::size()
+    )]
+    pub wallet_achievements: Account<'info, WalletAchievements>,
+
+    /// One per wallet and achievement, so it can't be minted twice even
+    /// past `wallet_achievements`.
+    #[account(
+        init,
+        seeds = [ACHIEVEMENT_SEED, pool.key().as_ref(), user.key().as_ref(), &[achievement as u8]],
+        bump,
+        payer = user,
+        mint::decimals = 0,
+        mint::authority = program_signer,
+        mint::freeze_authority = program_signer,
+        mint::token_program = nft_token_program
+    )]
+    achievement_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        init,
+        payer = user,
+        associated_token::mint = achievement_mint,
+        associated_token::authority = user,
+        associated_token::token_program = nft_token_program
+    )]
+    user_nft_acc: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// CHECK: created by the metadata program
+    #[account(
+        mut,
+        seeds = [b"metadata", TOKEN_METADATA_PROGRAM_ID.as_ref(), achievement_mint.key().as_ref()],
+        seeds::program = TOKEN_METADATA_PROGRAM_ID,
+        bump
+    )]
+    pub metadata: UncheckedAccount<'info>,
+
+    /// CHECK: created by the metadata program
+    #[account(
+        mut,
+        seeds = [b"metadata", TOKEN_METADATA_PROGRAM_ID.as_ref(), achievement_mint.key().as_ref(), b"edition"],
+        seeds::program = TOKEN_METADATA_PROGRAM_ID,
+        bump
+    )]
+    pub master_edition: UncheckedAccount<'info>,
+
+    /// CHECK: the Metaplex metadata program
+    #[account(address = TOKEN_METADATA_PROGRAM_ID)]
+    pub token_metadata_program: UncheckedAccount<'info>,
+
+    /// Metaplex only takes classic SPL Token mints.
+    #[account(address = anchor_spl::token::ID)]
+    pub nft_token_program: Interface<'info, TokenInterface>,
+
+    pub associated_token_program: Program<'info, AssociatedToken>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct PublishState<'info> {
+    #[account(mut, owner = *__program_id)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    /// CHECK: staking vault
+    #[account(constraint = vault.key() == pool.load()?.vault @PoolError::InvalidVault)]
+    vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// The pool's rate publisher, refreshed if passed.
+    #[account(mut, seeds = [RATE_PUBLISHER_SEED, pool.key().as_ref()], bump)]
+    pub rate_publisher: Option<Account<'info, RatePublisher>>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeRatePublisher<'info> {
+    #[account(owner = *__program_id)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    #[account(
+        init,
+        seeds = [RATE_PUBLISHER_SEED, pool.key().as_ref()],
+        bump,
+        payer = payer,
+        space = 8 + RatePublisher::size()
+    )]
+    pub rate_publisher: Account<'info, RatePublisher>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetRewardStream<'info> {
+    #[account(mut, owner = *__program_id, has_one = authority @PoolError::Unauthorized)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// Next entry of the config history, required while the pool keeps one.
+    #[account(
+        init,
+        seeds = [CONFIG_CHANGE_SEED, pool.key().as_ref(), &pool.load()?.config_change_count.to_le_bytes()],
+        bump,
+        payer = authority,
+        space = 8 + ConfigChange::size()
+    )]
+    pub config_change: Option<Account<'info, ConfigChange>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+/// Anyone may crank the stream. Accounts the stream program needs beyond
+/// these are passed as remaining accounts.
+#[derive(Accounts)]
+pub struct ClaimRewardStream<'info> {
+    #[account(mut, owner = *__program_id)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    /// CHECK: the stream's recipient, see `STREAM_RECIPIENT_SEED`
+    #[account(seeds = [STREAM_RECIPIENT_SEED, pool.key().as_ref()], bump)]
+    pub stream_recipient: UncheckedAccount<'info>,
+
+    /// Where the stream pays out.
+    #[account(
+        mut,
+        token::mint = mint,
+        token::authority = stream_recipient,
+        token::token_program = token_program
+    )]
+    stream_tokens: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// CHECK
+    #[account(constraint = mint.key() == pool.load()?.mint @PoolError::InvalidMint, mint::token_program = token_program)]
+    mint: Box<InterfaceAccount<'info, Mint>>,
+
+    /// CHECK: staking vault, must be owned by the pool's program signer
+    #[account(mut, constraint = vault.key() == pool.load()?.vault @PoolError::InvalidVault)]
+    vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// CHECK: owned and validated by the stream program
+    #[account(mut, constraint = stream.key() == pool.load()?.reward_stream @PoolError::InvalidRewardStream)]
+    pub stream: UncheckedAccount<'info>,
+
+    /// CHECK: must be the stream program registered in the pool
+    #[account(executable, constraint = stream_program.key() == pool.load()?.reward_stream_program @PoolError::InvalidRewardStream)]
+    pub stream_program: UncheckedAccount<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+/// The tree must be allocated by the client beforehand, sized for
+/// `max_depth` and `max_buffer_size`.
+#[derive(Accounts)]
+pub struct EnableCompression<'info> {
+    #[account(mut, owner = *__program_id, has_one = authority @PoolError::Unauthorized)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    /// CHECK: must be the signer stored in the pool
+    #[account(constraint = program_signer.key() == pool.load()?.program_signer @PoolError::InvalidProgramSigner)]
+    pub program_signer: AccountInfo<'info>,
+
+    /// CHECK: initialized by the compression program
+    #[account(mut, owner = COMPRESSION_PROGRAM_ID)]
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    /// CHECK: checked by address
+    #[account(address = COMPRESSION_PROGRAM_ID)]
+    pub compression_program: UncheckedAccount<'info>,
+
+    /// CHECK: checked by address
+    #[account(address = NOOP_PROGRAM_ID)]
+    pub noop_program: UncheckedAccount<'info>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct OpenCompressedPosition<'info> {
+    #[account(mut, owner = *__program_id)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    /// CHECK: must be the signer stored in the pool
+    #[account(constraint = program_signer.key() == pool.load()?.program_signer @PoolError::InvalidProgramSigner)]
+    pub program_signer: AccountInfo<'info>,
+
+    /// CHECK: must be the pool's tree
+    #[account(mut, constraint = merkle_tree.key() == pool.load()?.compressed_tree @PoolError::CompressionDisabled)]
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    /// CHECK: checked by address
+    #[account(address = COMPRESSION_PROGRAM_ID)]
+    pub compression_program: UncheckedAccount<'info>,
+
+    /// CHECK: checked by address
+    #[account(address = NOOP_PROGRAM_ID)]
+    pub noop_program: UncheckedAccount<'info>,
+
+    pub authority: Signer<'info>,
+}
+
+/// The proof of the position's leaf is passed as remaining accounts, so
+/// mints with transfer hooks can't be staked in compressed mode.
+#[derive(Accounts)]
+pub struct CompressedStake<'info> {
+    #[account(mut, owner = *__program_id)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    /// CHECK: must be the signer stored in the pool
+    #[account(constraint = program_signer.key() == pool.load()?.program_signer @PoolError::InvalidProgramSigner)]
+    pub program_signer: AccountInfo<'info>,
+
+    /// CHECK
+    #[account(mut, constraint = mint.key() == pool.load()?.mint @PoolError::InvalidMint, mint::token_program = token_program)]
+    mint: Box<InterfaceAccount<'info, Mint>>,
+
+    /// CHECK: staking vault, must be owned by the pool's program signer
+    #[account(mut, constraint = vault.key() == pool.load()?.vault @PoolError::InvalidVault)]
+    vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut, token::mint = mint, token::authority = authority)]
+    user_mint_acc: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// CHECK: must be the pool's tree
+    #[account(mut, constraint = merkle_tree.key() == pool.load()?.compressed_tree @PoolError::CompressionDisabled)]
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    /// CHECK: checked by address
+    #[account(address = COMPRESSION_PROGRAM_ID)]
+    pub compression_program: UncheckedAccount<'info>,
+
+    /// CHECK: checked by address
+    #[account(address = NOOP_PROGRAM_ID)]
+    pub noop_program: UncheckedAccount<'info>,
+
+    pub authority: Signer<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+
+    /// CHECK: instructions sysvar, required while the pool requires memos
+    #[account(address = sysvar::instructions::ID)]
+    pub instructions: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: checked by address; repeats the memo ahead of the payout
+    #[account(address = MEMO_PROGRAM_ID)]
+    pub memo_program: Option<UncheckedAccount<'info>>,
+}
+
+#[derive(Accounts)]
+#[instruction(beneficiary: Pubkey)]
+pub struct StakeGrant<'info> {
+    #[account(mut, owner = *__program_id, has_one = authority @PoolError::Unauthorized)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    /// CHECK
+    #[account(constraint = mint.key() == pool.load()?.mint @PoolError::InvalidMint, mint::token_program = token_program)]
+    mint: Box<InterfaceAccount<'info, Mint>>,
+
+    /// CHECK: staking vault, must be owned by the pool's program signer
+    #[account(mut, constraint = vault.key() == pool.load()?.vault @PoolError::InvalidVault)]
+    vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut, token::mint = mint, token::authority = authority)]
+    authority_mint_acc: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(token::mint = mint)]
+    treasury: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        init,
+        seeds = [GRANT_SEED, pool.key().as_ref(), beneficiary.as_ref()],
+        bump,
+        payer = authority,
+        space = 8 + GrantPosition::size()
+    )]
+    pub grant: Account<'info, GrantPosition>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ReleaseGrant<'info> {
+    #[account(mut, owner = *__program_id, has_one = authority @PoolError::Unauthorized)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    #[account(
+        mut,
+        seeds = [GRANT_SEED, pool.key().as_ref(), grant.beneficiary.as_ref()],
+        bump,
+        close = authority
+    )]
+    pub grant: Account<'info, GrantPosition>,
+
+    #[account(mut, seeds = [pool.key().as_ref(), grant.beneficiary.as_ref()], bump, owner = *__program_id)]
+    pub user_state: Account<'info, UserState>,
+
+    /// The wallet's stake across its positions, required once it holds
+    /// several.
+    #[account(mut, seeds = [WALLET_SEED, pool.key().as_ref(), grant.beneficiary.as_ref()], bump)]
+    pub wallet_state: Option<Account<'info, WalletState>>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// Current checkpoint pages of the beneficiary and of the pool total,
+    /// required while checkpoints are enabled.
+    #[account(mut)]
+    pub user_checkpoints: Option<AccountLoader<'info, Checkpoints>>,
+
+    #[account(mut)]
+    pub pool_checkpoints: Option<AccountLoader<'info, Checkpoints>>,
+}
+
+#[derive(Accounts)]
+pub struct ClawbackGrant<'info> {
+    #[account(mut, owner = *__program_id, has_one = authority @PoolError::Unauthorized)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    /// CHECK: must be the signer stored in the pool
+    #[account(constraint = program_signer.key() == pool.load()?.program_signer @PoolError::InvalidProgramSigner)]
+    pub program_signer: AccountInfo<'info>,
+
+    /// CHECK
+    #[account(mut, constraint = mint.key() == pool.load()?.mint @PoolError::InvalidMint, mint::token_program = token_program)]
+    mint: Box<InterfaceAccount<'info, Mint>>,
+
+    /// CHECK: staking vault, must be owned by the pool's program signer
+    #[account(mut, constraint = vault.key() == pool.load()?.vault @PoolError::InvalidVault)]
+    vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        seeds = [GRANT_SEED, pool.key().as_ref(), grant.beneficiary.as_ref()],
+        bump,
+        close = authority
+    )]
+    pub grant: Account<'info, GrantPosition>,
+
+    #[account(mut, address = grant.treasury)]
+    treasury: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+/// Wormhole's own accounts are checked by the bridge.
+#[cfg(feature = "wormhole")]
+#[derive(Accounts)]
+pub struct AttestStake<'info> {
+    #[account(mut, owner = *__program_id)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    /// CHECK: receives the pool's attestation fee
+    #[account(mut, address = pool.load()?.authority @PoolError::Unauthorized)]
+    pub pool_authority: UncheckedAccount<'info>,
+
+    #[account(mut, seeds = [pool.key().as_ref(), authority.key().as_ref(), &position_seed(user_state.index)], bump)]
+    pub user_state: Account<'info, UserState>,
+
+    pub authority: Signer<'info>,
+
+    /// Pays the attestation fee, the bridge fee and the message's rent.
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    /// CHECK: program emitter, signs the message
+    #[account(seeds = [EMITTER_SEED], bump)]
+    pub emitter: UncheckedAccount<'info>,
+
+    /// CHECK: the bridge's config, holding its fee
+    #[account(mut, owner = WORMHOLE_PROGRAM_ID)]
+    pub wormhole_bridge: UncheckedAccount<'info>,
+
+    /// New message account.
+    #[account(mut)]
+    pub wormhole_message: Signer<'info>,
+
+    /// CHECK: the emitter's sequence
+    #[account(mut)]
+    pub wormhole_sequence: UncheckedAccount<'info>,
+
+    /// CHECK: receives the bridge fee
+    #[account(mut)]
+    pub wormhole_fee_collector: UncheckedAccount<'info>,
+
+    /// CHECK: checked by address
+    #[account(address = WORMHOLE_PROGRAM_ID)]
+    pub wormhole_program: UncheckedAccount<'info>,
+
+    pub clock: Sysvar<'info, Clock>,
+
+    pub rent: Sysvar<'info, Rent>,
+
+    pub system_program: Program<'info, System>,
+}
+
+/// Accounts the swap adapter needs beyond these are passed as remaining
+/// accounts.
+#[derive(Accounts)]
+pub struct BuybackAndDistribute<'info> {
+    #[account(mut, owner = *__program_id)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    /// CHECK: must be the signer stored in the pool
+    #[account(constraint = program_signer.key() == pool.load()?.program_signer @PoolError::InvalidProgramSigner)]
+    pub program_signer: AccountInfo<'info>,
+
+    /// CHECK
+    #[account(constraint = mint.key() == pool.load()?.mint @PoolError::InvalidMint, mint::token_program = token_program)]
+    mint: Box<InterfaceAccount<'info, Mint>>,
+
+    /// CHECK: staking vault, must be owned by the pool's program signer
+    #[account(mut, constraint = vault.key() == pool.load()?.vault @PoolError::InvalidVault)]
+    vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Fee revenue in another token, e.g. USDC, held by the program signer.
+    #[account(
+        mut,
+        token::mint = revenue_mint,
+        token::authority = program_signer,
+        token::token_program = revenue_token_program,
+        constraint = revenue.mint != mint.key() @PoolError::InvalidMint
+    )]
+    revenue: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mint::token_program = revenue_token_program)]
+    revenue_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    /// CHECK: signs the swap, see `SWAP_AUTHORITY_SEED`
+    #[account(seeds = [SWAP_AUTHORITY_SEED, pool.key().as_ref()], bump)]
+    pub swap_authority: UncheckedAccount<'info>,
+
+    /// Scratch account the revenue is swapped out of.
+    #[account(
+        mut,
+        token::mint = revenue_mint,
+        token::authority = swap_authority,
+        token::token_program = revenue_token_program
+    )]
+    swap_source: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// CHECK: must be the adapter whitelisted in the pool
+    #[account(executable, constraint = swap_adapter.key() == pool.load()?.swap_adapter @PoolError::InvalidSwapAdapter)]
+    pub swap_adapter: UncheckedAccount<'info>,
+
+    /// The admin or the operator.
+    #[account(constraint = pool.load()?.can_operate(authority.key()) @PoolError::Unauthorized)]
+    pub authority: Signer<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+
+    pub revenue_token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+#[instruction(amount: u64, nonce: u64, priority: bool)]
+pub struct RequestWithdrawal<'info> {
+    #[account(mut, owner = *__program_id )]
+    pub pool: AccountLoader<'info, Pool>,
+
+    /// CHECK
+    #[account(mut, seeds = [pool.key().as_ref(), authority.key().as_ref(), &position_seed(user_state.index)], bump, owner = *__program_id)]
+    pub user_state: Account<'info, UserState>,
+
+    /// The wallet's stake across its positions, required once it holds
+    /// several.
+    #[account(mut, seeds = [WALLET_SEED, pool.key().as_ref(), authority.key().as_ref()], bump)]
+    pub wallet_state: Option<Account<'info, WalletState>>,
+
+    /// CHECK: the position this stake boosts through a `BoostLink`, required
+    /// while there is one
+    #[account(mut, address = user_state.link_boosted @PoolError::InvalidBoostLink)]
+    pub link_boosted: Option<UncheckedAccount<'info>>,
+
+    #[account(
+        init,
+        seeds = [WithdrawalRequest::seed(priority), pool.key().as_ref(), authority.key().as_ref(), &nonce.to_le_bytes()],
+        bump,
+        payer = payer,
+        space = 8 + WithdrawalRequest::size()
+    )]
+    pub withdrawal_request: Account<'info, WithdrawalRequest>,
+
+    pub authority: Signer<'info>,
+
+    /// Pays the rent; the user or a relayer acting for them.
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+
+    /// Current season leaderboard, updated if passed.
+    #[account(mut)]
+    pub leaderboard: Option<Account<'info, Leaderboard>>,
+
+    /// Current checkpoint pages of the user and of the pool total, required
+    /// while checkpoints are enabled.
+    #[account(mut)]
+    pub user_checkpoints: Option<AccountLoader<'info, Checkpoints>>,
+
+    #[account(mut)]
+    pub pool_checkpoints: Option<AccountLoader<'info, Checkpoints>>,
+
+    /// Required once the pool has a config.
+    #[account(seeds = [CONFIG_SEED, pool.key().as_ref()], bump)]
+    pub config: Option<Account<'info, PoolConfig>>,
+
+    /// Co-signer required for withdrawals above the user's guardian
+    /// threshold, unless announced in advance.
+    pub guardian: Option<Signer<'info>>,
+
+    /// The user's VIP badge token account, required for the priority lane.
+    pub vip_badge: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    /// Waives deposit and withdrawal fees when passed.
+    #[account(seeds = [FEE_EXEMPTION_SEED, pool.key().as_ref(), authority.key().as_ref()], bump)]
+    pub fee_exemption: Option<Account<'info, FeeExemption>>,
+
+    /// Required once the pool keeps an audit log.
+    #[account(mut, seeds = [AUDIT_LOG_SEED, pool.key().as_ref()], bump)]
+    pub audit_log: Option<AccountLoader<'info, AuditLog>>,
+}
+
+#[derive(Accounts)]
+#[instruction(user: Pubkey)]
+pub struct ForceUnwind<'info> {
+    #[account(mut, owner = *__program_id )]
+    pub pool: AccountLoader<'info, Pool>,
+
+    #[account(mut, seeds = [pool.key().as_ref(), user.as_ref(), &position_seed(user_state.index)], bump, owner = *__program_id)]
+    pub user_state: Account<'info, UserState>,
+
+    /// The wallet's stake across its positions, required once it holds
+    /// several.
+    #[account(mut, seeds = [WALLET_SEED, pool.key().as_ref(), user.as_ref()], bump)]
+    pub wallet_state: Option<Account<'info, WalletState>>,
+
+    /// CHECK: the position this stake boosts through a `BoostLink`, required
+    /// while there is one
+    #[account(mut, address = user_state.link_boosted @PoolError::InvalidBoostLink)]
+    pub link_boosted: Option<UncheckedAccount<'info>>,
+
+    #[account(
+        init,
+        seeds = [WithdrawalRequest::seed(false), pool.key().as_ref(), &pool.load()?.queue_tail.to_le_bytes()],
+        bump,
+        payer = compliance,
+        space = 8 + WithdrawalRequest::size()
+    )]
+    pub withdrawal_request: Account<'info, WithdrawalRequest>,
+
+    #[account(seeds = [CONFIG_SEED, pool.key().as_ref()], bump)]
+    pub config: Account<'info, PoolConfig>,
+
+    /// CHECK: the user's attestation address, which must no longer hold a
+    /// valid attestation
+    pub attestation: UncheckedAccount<'info>,
+
+    /// The pool's compliance key.
+    #[account(mut)]
+    pub compliance: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+
+    /// Current checkpoint pages of the user and of the pool total, required
+    /// while checkpoints are enabled.
+    #[account(mut)]
+    pub user_checkpoints: Option<AccountLoader<'info, Checkpoints>>,
+
+    #[account(mut)]
+    pub pool_checkpoints: Option<AccountLoader<'info, Checkpoints>>,
+
+    /// Required once the pool keeps an audit log.
+    #[account(mut, seeds = [AUDIT_LOG_SEED, pool.key().as_ref()], bump)]
+    pub audit_log: Option<AccountLoader<'info, AuditLog>>,
+}
+
+#[derive(Accounts)]
+pub struct SetCompliance<'info> {
+    #[account(mut, owner = *__program_id, has_one = authority @PoolError::Unauthorized)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// Next entry of the config history, required while the pool keeps one.
+    #[account(
+        init,
+        seeds = [CONFIG_CHANGE_SEED, pool.key().as_ref(), &pool.load()?.config_change_count.to_le_bytes()],
+        bump,
+        payer = authority,
+        space = 8 + ConfigChange::size()
+    )]
+    pub config_change: Option<Account<'info, ConfigChange>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetVipMint<'info> {
+    #[account(mut, owner = *__program_id, has_one = authority @PoolError::Unauthorized)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// Next entry of the config history, required while the pool keeps one.
+    #[account(
+        init,
+        seeds = [CONFIG_CHANGE_SEED, pool.key().as_ref(), &pool.load()?.config_change_count.to_le_bytes()],
+        bump,
+        payer = authority,
+        space = 8 + ConfigChange::size()
+    )]
+    pub config_change: Option<Account<'info, ConfigChange>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetClaimCooldown<'info> {
+    #[account(mut, owner = *__program_id, has_one = authority @PoolError::Unauthorized)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// Next entry of the config history, required while the pool keeps one.
+    #[account(
+        init,
+        seeds = [CONFIG_CHANGE_SEED, pool.key().as_ref(), &pool.load()?.config_change_count.to_le_bytes()],
+        bump,
+        payer = authority,
+        space = 8 + ConfigChange::size()
+    )]
+    pub config_change: Option<Account<'info, ConfigChange>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct FulfillWithdrawal<'info> {
+    #[account(mut, owner = *__program_id )]
+    pub pool: AccountLoader<'info, Pool>,
+
+    /// CHECK: must be the signer stored in the pool
+    #[account(constraint = program_signer.key() == pool.load()?.program_signer @PoolError::InvalidProgramSigner)]
+    pub program_signer: AccountInfo<'info>,
+
+    /// CHECK
+    #[account(mint::token_program = token_program)]
+    mint: Box<InterfaceAccount<'info, Mint>>,
+
+    /// CHECK: staking vault, must be owned by the pool's program signer
+    #[account(mut, constraint = vault.key() == pool.load()?.vault @PoolError::InvalidVault)]
+    vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut, has_one = pool @PoolError::InvalidWithdrawalRequest)]
+    pub withdrawal_request: Account<'info, WithdrawalRequest>,
+
+    /// CHECK: the requester, refunded the request's rent once it is
+    /// fulfilled
+    #[account(mut, address = withdrawal_request.user @PoolError::InvalidWithdrawalRequest)]
+    pub requester: UncheckedAccount<'info>,
+
+    #[account(
+         mut,
+         constraint = (user_mint_acc.owner == withdrawal_request.user && user_mint_acc.mint == mint.key())
+     )]
+    user_mint_acc: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+
+    /// Required once the pool keeps an audit log.
+    #[account(mut, seeds = [AUDIT_LOG_SEED, pool.key().as_ref()], bump)]
+    pub audit_log: Option<AccountLoader<'info, AuditLog>>,
+}
+
+#[derive(Accounts)]
+pub struct OpenWithdrawalBatch<'info> {
+    #[account(mut, owner = *__program_id)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    #[account(constraint = vault.key() == pool.load()?.vault @PoolError::InvalidVault)]
+    vault: Box<InterfaceAccount<'info, TokenAccount>>,
+}
+
+#[derive(Accounts)]
+pub struct SetProRataWithdrawals<'info> {
+    #[account(mut, owner = *__program_id, has_one = authority @PoolError::Unauthorized)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// Next entry of the config history, required while the pool keeps one.
+    #[account(
+        init,
+        seeds = [CONFIG_CHANGE_SEED, pool.key().as_ref(), &pool.load()?.config_change_count.to_le_bytes()],
+        bump,
+        payer = authority,
+        space = 8 + ConfigChange::size()
+    )]
+    pub config_change: Option<Account<'info, ConfigChange>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct InitializePoolConfig<'info> {
+    #[account(mut, owner = *__program_id, has_one = authority @PoolError::Unauthorized)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    #[account(
+        init,
+        seeds = [CONFIG_SEED, pool.key().as_ref()],
+        bump,
+        payer = authority,
+        space = 8 + PoolConfig::size()
+    )]
+    pub config: Account<'info, PoolConfig>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeBoostLink<'info> {
+    #[account(owner = *__program_id, has_one = authority @PoolError::Unauthorized)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    #[account(
+        init,
+        seeds = [BOOST_LINK_SEED, pool.key().as_ref()],
+        bump,
+        payer = authority,
+        space = 8 + BoostLink::size()
+    )]
+    pub boost_link: Account<'info, BoostLink>,
+
+    pub linked_pool: AccountLoader<'info, Pool>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetBoostLink<'info> {
+    #[account(mut, owner = *__program_id, has_one = authority @PoolError::Unauthorized)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    #[account(mut, seeds = [BOOST_LINK_SEED, pool.key().as_ref()], bump)]
+    pub boost_link: Account<'info, BoostLink>,
+
+    pub linked_pool: AccountLoader<'info, Pool>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// Next entry of the config history, required while the pool keeps one.
+    #[account(
+        init,
+        seeds = [CONFIG_CHANGE_SEED, pool.key().as_ref(), &pool.load()?.config_change_count.to_le_bytes()],
+        bump,
+        payer = authority,
+        space = 8 + ConfigChange::size()
+    )]
+    pub config_change: Option<Account<'info, ConfigChange>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SyncLinkBoost<'info> {
+    #[account(mut, owner = *__program_id)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    #[account(seeds = [BOOST_LINK_SEED, pool.key().as_ref()], bump)]
+    pub boost_link: Account<'info, BoostLink>,
+
+    pub user: Signer<'info>,
+
+    #[account(mut, seeds = [pool.key().as_ref(), user.key().as_ref(), &position_seed(user_state.index)], bump)]
+    pub user_state: Account<'info, UserState>,
+
+    #[account(address = boost_link.linked_pool @PoolError::InvalidBoostLink)]
+    pub linked_pool: AccountLoader<'info, Pool>,
+
+    /// The user's state in the linked pool; without one the boost is zero.
+    #[account(mut, seeds = [linked_pool.key().as_ref(), user.key().as_ref(), &position_seed(linked_user_state.index)], bump)]
+    pub linked_user_state: Option<Account<'info, UserState>>,
+}
+
+#[derive(Accounts)]
+pub struct SetPoolConfig<'info> {
+    #[account(mut, owner = *__program_id, has_one = authority @PoolError::Unauthorized)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    #[account(mut, seeds = [CONFIG_SEED, pool.key().as_ref()], bump)]
+    pub config: Account<'info, PoolConfig>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// Next entry of the config history, required while the pool keeps one.
+    #[account(
+        init,
+        seeds = [CONFIG_CHANGE_SEED, pool.key().as_ref(), &pool.load()?.config_change_count.to_le_bytes()],
+        bump,
+        payer = authority,
+        space = 8 + ConfigChange::size()
+    )]
+    pub config_change: Option<Account<'info, ConfigChange>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CollectProtocolFees<'info> {
+    #[account(mut, owner = *__program_id, has_one = authority @PoolError::Unauthorized)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    /// CHECK: must be the signer stored in the pool
+    #[account(constraint = program_signer.key() == pool.load()?.program_signer @PoolError::InvalidProgramSigner)]
+    pub program_signer: AccountInfo<'info>,
+
+    /// CHECK
+    #[account(mint::token_program = token_program)]
+    mint: Box<InterfaceAccount<'info, Mint>>,
+
+    /// CHECK: staking vault, must be owned by the pool's program signer
+    #[account(mut, constraint = vault.key() == pool.load()?.vault @PoolError::InvalidVault)]
+    vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut, token::mint = mint)]
+    destination: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    pub authority: Signer<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeAuditLog<'info> {
+    #[account(mut, owner = *__program_id, has_one = authority @PoolError::Unauthorized)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    #[account(
+        init,
+        seeds = [AUDIT_LOG_SEED, pool.key().as_ref()],
+        bump,
+        payer = authority,
+        space = 8 + AuditLog::size()
+    )]
+    pub audit_log: AccountLoader<'info, AuditLog>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetDisabledInstructions<'info> {
+    #[account(mut, owner = *__program_id, has_one = authority @PoolError::Unauthorized)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// Next entry of the config history, required while the pool keeps one.
+    #[account(
+        init,
+        seeds = [CONFIG_CHANGE_SEED, pool.key().as_ref(), &pool.load()?.config_change_count.to_le_bytes()],
+        bump,
+        payer = authority,
+        space = 8 + ConfigChange::size()
+    )]
+    pub config_change: Option<Account<'info, ConfigChange>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetPaused<'info> {
+    #[account(mut, owner = *__program_id, has_one = authority @PoolError::Unauthorized)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// Next entry of the config history, required while the pool keeps one.
+    #[account(
+        init,
+        seeds = [CONFIG_CHANGE_SEED, pool.key().as_ref(), &pool.load()?.config_change_count.to_le_bytes()],
+        bump,
+        payer = authority,
+        space = 8 + ConfigChange::size()
+    )]
+    pub config_change: Option<Account<'info, ConfigChange>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetDustPolicy<'info> {
+    #[account(mut, owner = *__program_id, has_one = authority @PoolError::Unauthorized)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// Next entry of the config history, required while the pool keeps one.
+    #[account(
+        init,
+        seeds = [CONFIG_CHANGE_SEED, pool.key().as_ref(), &pool.load()?.config_change_count.to_le_bytes()],
+        bump,
+        payer = authority,
+        space = 8 + ConfigChange::size()
+    )]
+    pub config_change: Option<Account<'info, ConfigChange>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SweepDust<'info> {
+    #[account(mut, owner = *__program_id, has_one = authority @PoolError::Unauthorized)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    /// CHECK: must be the signer stored in the pool
+    #[account(constraint = program_signer.key() == pool.load()?.program_signer @PoolError::InvalidProgramSigner)]
+    pub program_signer: AccountInfo<'info>,
+
+    /// CHECK: rewards are minted in the staked token
+    #[account(mut, constraint = mint.key() == pool.load()?.mint @PoolError::InvalidMint, mint::token_program = token_program)]
+    mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(mut, token::mint = mint)]
+    destination: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    pub authority: Signer<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct SetRewardRedirect<'info> {
+    #[account(mut, owner = *__program_id, has_one = authority @PoolError::Unauthorized)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// Next entry of the config history, required while the pool keeps one.
+    #[account(
+        init,
+        seeds = [CONFIG_CHANGE_SEED, pool.key().as_ref(), &pool.load()?.config_change_count.to_le_bytes()],
+        bump,
+        payer = authority,
+        space = 8 + ConfigChange::size()
+    )]
+    pub config_change: Option<Account<'info, ConfigChange>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct DistributeRedirectedRewards<'info> {
+    #[account(mut, owner = *__program_id )]
+    pub pool: AccountLoader<'info, Pool>,
+
+    /// CHECK: must be the signer stored in the pool
+    #[account(constraint = program_signer.key() == pool.load()?.program_signer @PoolError::InvalidProgramSigner)]
+    pub program_signer: AccountInfo<'info>,
+
+    /// CHECK: rewards are minted in the staked token
+    #[account(mut, constraint = mint.key() == pool.load()?.mint @PoolError::InvalidMint, mint::token_program = token_program)]
+    mint: Box<InterfaceAccount<'info, Mint>>,
+
+    /// Required unless the share is burned.
+    #[account(mut, token::mint = mint, constraint = destination.key() == pool.load()?.redirect_destination @PoolError::InvalidRedirect)]
+    destination: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    /// CHECK: staking vault, the burned share comes out of its funded rewards
+    #[account(mut, constraint = vault.key() == pool.load()?.vault @PoolError::InvalidVault)]
+    vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct CreateDepositSchedule<'info> {
+    /// CHECK
+    pub pool: UncheckedAccount<'info>,
+
+    #[account(
+        init,
+        seeds = [DEPOSIT_SCHEDULE_SEED, pool.key().as_ref(), authority.key().as_ref()],
+        bump,
+        payer = payer,
+        space = 8 + DepositSchedule::size()
+    )]
+    pub deposit_schedule: Account<'info, DepositSchedule>,
+
+    #[account(constraint = funding.owner == authority.key())]
+    pub funding: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    pub authority: Signer<'info>,
+
+    /// Pays the rent; the user or a relayer acting for them.
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CancelDepositSchedule<'info> {
+    #[account(
+        mut,
+        close = authority,
+        seeds = [DEPOSIT_SCHEDULE_SEED, deposit_schedule.pool.as_ref(), authority.key().as_ref()],
+        bump
+    )]
+    pub deposit_schedule: Account<'info, DepositSchedule>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ExecuteScheduledDeposit<'info> {
+    #[account(mut, owner = *__program_id )]
+    pub pool: AccountLoader<'info, Pool>,
+
+    /// CHECK: must be the signer stored in the pool
+    #[account(constraint = program_signer.key() == pool.load()?.program_signer @PoolError::InvalidProgramSigner)]
+    pub program_signer: AccountInfo<'info>,
+
+    /// CHECK
+    #[account(mint::token_program = token_program)]
+    mint: Box<InterfaceAccount<'info, Mint>>,
+
+    /// CHECK: staking vault, must be owned by the pool's program signer
+    #[account(mut, constraint = vault.owner == pool.load()?.program_signer @PoolError::InvalidVault)]
+    vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut, has_one = pool, has_one = funding)]
+    pub deposit_schedule: Account<'info, DepositSchedule>,
+
+    #[account(mut, token::mint = mint, constraint = funding.owner == deposit_schedule.user)]
+    funding: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// CHECK
+    #[account(mut, seeds = [pool.key().as_ref(), deposit_schedule.user.as_ref()], bump, owner = *__program_id)]
+    pub user_state: Account<'info, UserState>,
+
+    /// The wallet's stake across its positions, required once it holds
+    /// several.
+    #[account(mut, seeds = [WALLET_SEED, pool.key().as_ref(), deposit_schedule.user.as_ref()], bump)]
+    pub wallet_state: Option<Account<'info, WalletState>>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+
+    #[account(mut)]
+    pub leaderboard: Option<Account<'info, Leaderboard>>,
+
+    #[account(mut)]
+    pub user_checkpoints: Option<AccountLoader<'info, Checkpoints>>,
+
+    #[account(mut)]
+    pub pool_checkpoints: Option<AccountLoader<'info, Checkpoints>>,
+
+    #[account(seeds = [CONFIG_SEED, pool.key().as_ref()], bump)]
+    pub config: Option<Account<'info, PoolConfig>>,
+
+    /// CHECK: checked against the config's attestation program and schema
+    pub attestation: Option<UncheckedAccount<'info>>,
+
+    #[account(seeds = [FEE_EXEMPTION_SEED, pool.key().as_ref(), deposit_schedule.user.as_ref()], bump)]
+    pub fee_exemption: Option<Account<'info, FeeExemption>>,
+
+    /// Required once the pool keeps an audit log.
+    #[account(mut, seeds = [AUDIT_LOG_SEED, pool.key().as_ref()], bump)]
+    pub audit_log: Option<AccountLoader<'info, AuditLog>>,
+}
+
+#[derive(Accounts)]
+pub struct SetIntegrator<'info> {
+    /// CHECK
+    pub pool: UncheckedAccount<'info>,
+
+    #[account(mut, seeds = [pool.key().as_ref(), authority.key().as_ref(), &position_seed(user_state.index)], bump, owner = *__program_id)]
+    pub user_state: Account<'info, UserState>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetPuller<'info> {
+    /// CHECK
+    pub pool: UncheckedAccount<'info>,
+
+    #[account(mut, seeds = [pool.key().as_ref(), authority.key().as_ref(), &position_seed(user_state.index)], bump, owner = *__program_id)]
+    pub user_state: Account<'info, UserState>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct IntegratorStake<'info> {
+    #[account(mut, owner = *__program_id )]
+    pub pool: AccountLoader<'info, Pool>,
+
+    /// CHECK
+    #[account(mint::token_program = token_program)]
+    mint: Box<InterfaceAccount<'info, Mint>>,
+
+    /// CHECK: staking vault, must be owned by the pool's program signer
+    #[account(mut, constraint = vault.owner == pool.load()?.program_signer @PoolError::InvalidVault)]
+    vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// The integrator's tokens staked for the user.
+    #[account(mut, token::mint = mint, constraint = funding.owner == integrator_authority.key())]
+    funding: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// CHECK: only used to derive the user state
+    pub user: UncheckedAccount<'info>,
+
+    #[account(mut, seeds = [pool.key().as_ref(), user.key().as_ref(), &position_seed(user_state.index)], bump, owner = *__program_id)]
+    pub user_state: Account<'info, UserState>,
+
+    /// The wallet's stake across its positions, required once it holds
+    /// several.
+    #[account(mut, seeds = [WALLET_SEED, pool.key().as_ref(), user.key().as_ref()], bump)]
+    pub wallet_state: Option<Account<'info, WalletState>>,
+
+    /// The integrator program's `INTEGRATOR_SEED` PDA.
+    pub integrator_authority: Signer<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+
+    #[account(mut)]
+    pub user_checkpoints: Option<AccountLoader<'info, Checkpoints>>,
+
+    #[account(mut)]
+    pub pool_checkpoints: Option<AccountLoader<'info, Checkpoints>>,
+
+    #[account(seeds = [CONFIG_SEED, pool.key().as_ref()], bump)]
+    pub config: Option<Account<'info, PoolConfig>>,
+
+    #[account(seeds = [FEE_EXEMPTION_SEED, pool.key().as_ref(), user.key().as_ref()], bump)]
+    pub fee_exemption: Option<Account<'info, FeeExemption>>,
+
+    /// Required once the pool keeps an audit log.
+    #[account(mut, seeds = [AUDIT_LOG_SEED, pool.key().as_ref()], bump)]
+    pub audit_log: Option<AccountLoader<'info, AuditLog>>,
+}
+
+#[derive(Accounts)]
+pub struct IntegratorUnstake<'info> {
+    #[account(mut, owner = *__program_id )]
+    pub pool: AccountLoader<'info, Pool>,
+
+    /// CHECK: must be the signer stored in the pool
+    #[account(constraint = program_signer.key() == pool.load()?.program_signer @PoolError::InvalidProgramSigner)]
+    pub program_signer: AccountInfo<'info>,
+
+    /// CHECK
+    #[account(mint::token_program = token_program)]
+    mint: Box<InterfaceAccount<'info, Mint>>,
+
+    /// CHECK: staking vault, must be owned by the pool's program signer
+    #[account(mut, constraint = vault.owner == pool.load()?.program_signer @PoolError::InvalidVault)]
+    vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// CHECK: only used to derive the user state
+    pub user: UncheckedAccount<'info>,
+
+    #[account(mut, seeds = [pool.key().as_ref(), user.key().as_ref(), &position_seed(user_state.index)], bump, owner = *__program_id)]
+    pub user_state: Account<'info, UserState>,
+
+    /// The wallet's stake across its positions, required once it holds
+    /// several.
+    #[account(mut, seeds = [WALLET_SEED, pool.key().as_ref(), user.key().as_ref()], bump)]
+    pub wallet_state: Option<Account<'info, WalletState>>,
+
+    /// CHECK: the position this stake boosts through a `BoostLink`, required
+    /// while there is one
+    #[account(mut, address = user_state.link_boosted @PoolError::InvalidBoostLink)]
+    pub link_boosted: Option<UncheckedAccount<'info>>,
+
+    /// The stake always goes back to the user, never to the integrator.
+    #[account(mut, token::mint = mint, constraint = user_mint_acc.owner == user.key())]
+    user_mint_acc: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// The integrator program's `INTEGRATOR_SEED` PDA.
+    pub integrator_authority: Signer<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+
+    #[account(mut)]
+    pub user_checkpoints: Option<AccountLoader<'info, Checkpoints>>,
+
+    #[account(mut)]
+    pub pool_checkpoints: Option<AccountLoader<'info, Checkpoints>>,
+
+    #[account(seeds = [CONFIG_SEED, pool.key().as_ref()], bump)]
+    pub config: Option<Account<'info, PoolConfig>>,
+
+    #[account(seeds = [FEE_EXEMPTION_SEED, pool.key().as_ref(), user.key().as_ref()], bump)]
+    pub fee_exemption: Option<Account<'info, FeeExemption>>,
+
+    /// Required once the pool keeps an audit log.
+    #[account(mut, seeds = [AUDIT_LOG_SEED, pool.key().as_ref()], bump)]
+    pub audit_log: Option<AccountLoader<'info, AuditLog>>,
+}
+
+#[derive(Accounts)]
+pub struct PullStake<'info> {
+    #[account(mut, owner = *__program_id )]
+    pub pool: AccountLoader<'info, Pool>,
+
+    /// CHECK: must be the signer stored in the pool
+    #[account(constraint = program_signer.key() == pool.load()?.program_signer @PoolError::InvalidProgramSigner)]
+    pub program_signer: AccountInfo<'info>,
+
+    /// CHECK
+    #[account(mint::token_program = token_program)]
+    mint: Box<InterfaceAccount<'info, Mint>>,
+
+    /// CHECK: staking vault
+    #[account(mut, constraint = vault.key() == pool.load()?.vault @PoolError::InvalidVault)]
+    vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// The user's token account the program signer is a delegate of.
+    #[account(mut, token::mint = mint, constraint = funding.owner == user.key())]
+    funding: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// CHECK: the staker; only their own approved tokens are pulled
+    pub user: UncheckedAccount<'info>,
+
+    /// CHECK
+    #[account(mut, seeds = [pool.key().as_ref(), user.key().as_ref(), &position_seed(user_state.index)], bump, owner = *__program_id)]
+    pub user_state: Account<'info, UserState>,
+
+    /// The wallet's stake across its positions, required once it holds
+    /// several.
+    #[account(mut, seeds = [WALLET_SEED, pool.key().as_ref(), user.key().as_ref()], bump)]
+    pub wallet_state: Option<Account<'info, WalletState>>,
+
+    /// The user, or the puller they registered with `set_puller`.
+    #[account(constraint = authority.key() == user.key() || authority.key() == user_state.puller @PoolError::Unauthorized)]
+    pub authority: Signer<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+
+    #[account(mut)]
+    pub leaderboard: Option<Account<'info, Leaderboard>>,
+
+    #[account(mut)]
+    pub user_checkpoints: Option<AccountLoader<'info, Checkpoints>>,
+
+    #[account(mut)]
+    pub pool_checkpoints: Option<AccountLoader<'info, Checkpoints>>,
+
+    #[account(seeds = [CONFIG_SEED, pool.key().as_ref()], bump)]
+    pub config: Option<Account<'info, PoolConfig>>,
+
+    /// CHECK: checked against the config's attestation program and schema
+    pub attestation: Option<UncheckedAccount<'info>>,
+
+    /// Waives deposit and withdrawal fees when passed.
+    #[account(seeds = [FEE_EXEMPTION_SEED, pool.key().as_ref(), user.key().as_ref()], bump)]
+    pub fee_exemption: Option<Account<'info, FeeExemption>>,
+
+    /// Required once the pool keeps an audit log.
+    #[account(mut, seeds = [AUDIT_LOG_SEED, pool.key().as_ref()], bump)]
+    pub audit_log: Option<AccountLoader<'info, AuditLog>>,
+}
+
+#[derive(Accounts)]
+#[instruction(wallet: Pubkey)]
+pub struct AddFeeExemption<'info> {
+    #[account(mut, owner = *__program_id, has_one = authority @PoolError::Unauthorized)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    #[account(
+        init,
+        seeds = [FEE_EXEMPTION_SEED, pool.key().as_ref(), wallet.as_ref()],
+        bump,
+        payer = authority,
+        space = 8 + FeeExemption::size()
+    )]
+    pub fee_exemption: Account<'info, FeeExemption>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// Next entry of the config history, required while the pool keeps one.
+    #[account(
+        init,
+        seeds = [CONFIG_CHANGE_SEED, pool.key().as_ref(), &pool.load()?.config_change_count.to_le_bytes()],
+        bump,
+        payer = authority,
+        space = 8 + ConfigChange::size()
+    )]
+    pub config_change: Option<Account<'info, ConfigChange>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RemoveFeeExemption<'info> {
+    #[account(mut, owner = *__program_id, has_one = authority @PoolError::Unauthorized)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    #[account(mut, close = authority, has_one = pool)]
+    pub fee_exemption: Account<'info, FeeExemption>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// Next entry of the config history, required while the pool keeps one.
+    #[account(
+        init,
+        seeds = [CONFIG_CHANGE_SEED, pool.key().as_ref(), &pool.load()?.config_change_count.to_le_bytes()],
+        bump,
+        payer = authority,
+        space = 8 + ConfigChange::size()
+    )]
+    pub config_change: Option<Account<'info, ConfigChange>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct PublishMigrationRoot<'info> {
+    #[account(owner = *__program_id, has_one = authority @PoolError::Unauthorized)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    #[account(
+        init,
+        seeds = [MIGRATION_SEED, pool.key().as_ref()],
+        bump,
+        payer = authority,
+        space = 8 + Migration::size()
+    )]
+    pub migration: Account<'info, Migration>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct FundMigration<'info> {
+    #[account(mut, owner = *__program_id, has_one = authority @PoolError::Unauthorized)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    /// CHECK
+    #[account(mint::token_program = token_program)]
+    mint: Box<InterfaceAccount<'info, Mint>>,
+
+    /// CHECK: staking vault, must be owned by the pool's program signer
+    #[account(mut, constraint = vault.key() == pool.load()?.vault @PoolError::InvalidVault)]
+    vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+         mut,
+         constraint = (source.owner == *authority.key && source.mint == mint.key())
+     )]
+    source: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    pub authority: Signer<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct ImportStake<'info> {
+    #[account(mut, owner = *__program_id )]
+    pub pool: AccountLoader<'info, Pool>,
+
+    #[account(mut, seeds = [MIGRATION_SEED, pool.key().as_ref()], bump)]
+    pub migration: Account<'info, Migration>,
+
+    /// CHECK: the wallet whose legacy stake is imported
+    pub user: UncheckedAccount<'info>,
+
+    /// CHECK
+    #[account(mut, seeds = [pool.key().as_ref(), user.key().as_ref(), &position_seed(user_state.index)], bump, owner = *__program_id)]
+    pub user_state: Account<'info, UserState>,
+
+    /// The wallet's stake across its positions, required once it holds
+    /// several.
+    #[account(mut, seeds = [WALLET_SEED, pool.key().as_ref(), user.key().as_ref()], bump)]
+    pub wallet_state: Option<Account<'info, WalletState>>,
+
+    #[account(
+        init,
+        seeds = [IMPORT_RECEIPT_SEED, pool.key().as_ref(), user.key().as_ref()],
+        bump,
+        payer = payer,
+        space = 8 + ImportReceipt::size()
+    )]
+    pub import_receipt: Account<'info, ImportReceipt>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+
+    /// Current checkpoint pages of the user and of the pool total, required
+    /// while checkpoints are enabled.
+    #[account(mut)]
+    pub user_checkpoints: Option<AccountLoader<'info, Checkpoints>>,
+
+    #[account(mut)]
+    pub pool_checkpoints: Option<AccountLoader<'info, Checkpoints>>,
+
+    /// Required once the pool keeps an audit log.
+    #[account(mut, seeds = [AUDIT_LOG_SEED, pool.key().as_ref()], bump)]
+    pub audit_log: Option<AccountLoader<'info, AuditLog>>,
+}
+
+#[derive(Accounts)]
+#[instruction(id: u64)]
+pub struct CommitSnapshot<'info> {
+    #[account(mut, owner = *__program_id)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    #[account(
+        init,
+        seeds = [SNAPSHOT_SEED, pool.key().as_ref(), &id.to_le_bytes()],
+        bump,
+        payer = authority,
+        space = 8 + Snapshot::size()
+    )]
+    pub snapshot: Account<'info, Snapshot>,
+
+    /// The admin or the operator.
+    #[account(mut, constraint = pool.load()?.can_operate(authority.key()) @PoolError::Unauthorized)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct VerifySnapshotProof<'info> {
+    pub snapshot: Account<'info, Snapshot>,
+}
+
+#[derive(Accounts)]
+#[instruction(epoch: u64)]
+pub struct StartLottery<'info> {
+    #[account(mut, owner = *__program_id, has_one = authority @PoolError::Unauthorized)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    #[account(
+        init,
+        seeds = [LOTTERY_SEED, pool.key().as_ref(), &epoch.to_le_bytes()],
+        bump,
+        payer = authority,
+        space = 8 + Lottery::size()
+    )]
+    pub lottery: Account<'info, Lottery>,
+
+    /// CHECK: only ever invoked, by the draw's cranks
+    #[account(executable)]
+    pub randomness_program: UncheckedAccount<'info>,
+
+    #[account(constraint = mint.key() == pool.load()?.mint @PoolError::InvalidMint, mint::token_program = token_program)]
+    mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(mut, constraint = vault.key() == pool.load()?.vault @PoolError::InvalidVault)]
+    vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut, token::mint = mint, token::authority = authority)]
+    authority_mint_acc: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RequestLotteryDraw<'info> {
+    #[account(
+        mut,
+        seeds = [LOTTERY_SEED, lottery.pool.as_ref(), &lottery.epoch.to_le_bytes()],
+        bump
+    )]
+    pub lottery: Account<'info, Lottery>,
+
+    /// CHECK: the adapter validates it
+    #[account(address = lottery.randomness_program)]
+    pub randomness_program: UncheckedAccount<'info>,
+
+    /// CHECK: the adapter validates it
+    #[account(mut)]
+    pub randomness_account: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct DrawLottery<'info> {
+    #[account(
+        mut,
+        seeds = [LOTTERY_SEED, lottery.pool.as_ref(), &lottery.epoch.to_le_bytes()],
+        bump
+    )]
+    pub lottery: Account<'info, Lottery>,
+
+    /// CHECK: the adapter validates it
+    #[account(address = lottery.randomness_program)]
+    pub randomness_program: UncheckedAccount<'info>,
+
+    /// CHECK: the account committed by the request
+    #[account(mut, address = lottery.randomness_account @PoolError::InvalidRandomness)]
+    pub randomness_account: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimLotteryPrize<'info> {
+    #[account(mut, owner = *__program_id)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    #[account(
+        mut,
+        seeds = [LOTTERY_SEED, pool.key().as_ref(), &lottery.epoch.to_le_bytes()],
+        bump
+    )]
+    pub lottery: Account<'info, Lottery>,
+
+    #[account(mut, seeds = [pool.key().as_ref(), authority.key().as_ref()], bump, owner = *__program_id)]
+    pub user_state: Account<'info, UserState>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(index: u8)]
+pub struct InitializeStakeShard<'info> {
+    #[account(owner = *__program_id, has_one = authority @PoolError::Unauthorized)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    /// CHECK: must be the signer stored in the pool
+    #[account(constraint = program_signer.key() == pool.load()?.program_signer @PoolError::InvalidProgramSigner)]
+    pub program_signer: AccountInfo<'info>,
+
+    #[account(
+        init,
+        seeds = [SHARD_SEED, pool.key().as_ref(), &[index]],
+        bump,
+        payer = authority,
+        space = 8 + StakeShard::size()
+    )]
+    pub shard: Account<'info, StakeShard>,
+
+    #[account(
+        init,
+        seeds = [SHARD_VAULT_SEED, shard.key().as_ref()],
+        bump,
+        payer = authority,
+        token::mint = mint,
+        token::authority = program_signer,
+        token::token_program = token_program
+    )]
+    shard_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(constraint = mint.key() == pool.load()?.mint @PoolError::InvalidMint, mint::token_program = token_program)]
+    mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+/// Only reads the pool, so deposits into different shards run in parallel.
+#[derive(Accounts)]
+pub struct DepositToShard<'info> {
+    #[account(owner = *__program_id)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    #[account(mut, seeds = [SHARD_SEED, pool.key().as_ref(), &[shard.index]], bump)]
+    pub shard: Account<'info, StakeShard>,
+
+    #[account(mut, address = shard.vault)]
+    shard_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(constraint = mint.key() == pool.load()?.mint @PoolError::InvalidMint, mint::token_program = token_program)]
+    mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        init_if_needed,
+        seeds = [SHARD_SEED, shard.key().as_ref(), authority.key().as_ref()],
+        bump,
+        payer = authority,
+        space = 8 + ShardDeposit::size()
+    )]
+    pub shard_deposit: Account<'info, ShardDeposit>,
+
+    #[account(mut, token::mint = mint, token::authority = authority)]
+    user_mint_acc: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// The default position, which the deposit is credited to.
+    #[account(seeds = [pool.key().as_ref(), authority.key().as_ref()], bump, owner = *__program_id)]
+    pub user_state: Account<'info, UserState>,
+
+    /// The wallet's stake across its positions, required once it holds
+    /// several.
+    #[account(seeds = [WALLET_SEED, pool.key().as_ref(), authority.key().as_ref()], bump)]
+    pub wallet_state: Option<Account<'info, WalletState>>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// Required once the pool has a config.
+    #[account(seeds = [CONFIG_SEED, pool.key().as_ref()], bump)]
+    pub config: Option<Account<'info, PoolConfig>>,
+
+    /// CHECK: checked against the config's attestation program and schema;
+    /// raises the per-user cap when valid
+    pub attestation: Option<UncheckedAccount<'info>>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MergeStakeShard<'info> {
+    #[account(mut, owner = *__program_id)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    /// CHECK: must be the signer stored in the pool
+    #[account(constraint = program_signer.key() == pool.load()?.program_signer @PoolError::InvalidProgramSigner)]
+    pub program_signer: AccountInfo<'info>,
+
+    #[account(constraint = mint.key() == pool.load()?.mint @PoolError::InvalidMint, mint::token_program = token_program)]
+    mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(mut, constraint = vault.key() == pool.load()?.vault @PoolError::InvalidVault)]
+    vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut, seeds = [SHARD_SEED, pool.key().as_ref(), &[shard.index]], bump)]
+    pub shard: Account<'info, StakeShard>,
+
+    #[account(mut, address = shard.vault)]
+    shard_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        init,
+        seeds = [SHARD_BATCH_SEED, shard.key().as_ref(), &shard.generation.to_le_bytes()],
+        bump,
+        payer = payer,
+        space = 8 + ShardBatch::size()
+    )]
+    pub shard_batch: Account<'info, ShardBatch>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    /// Required once the pool has a config.
+    #[account(seeds = [CONFIG_SEED, pool.key().as_ref()], bump)]
+    pub config: Option<Account<'info, PoolConfig>>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CreditShardDeposit<'info> {
+    #[account(mut, owner = *__program_id)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    #[account(seeds = [SHARD_SEED, pool.key().as_ref(), &[shard.index]], bump)]
+    pub shard: Account<'info, StakeShard>,
+
+    #[account(
+        mut,
+        seeds = [SHARD_SEED, shard.key().as_ref(), shard_deposit.user.as_ref()],
+        bump,
+        close = user
+    )]
+    pub shard_deposit: Account<'info, ShardDeposit>,
+
+    /// CHECK: receives the deposit's rent
+    #[account(mut, address = shard_deposit.user)]
+    pub user: UncheckedAccount<'info>,
+
+    /// Only exists once the deposit's generation was merged.
+    #[account(
+        mut,
+        seeds = [SHARD_BATCH_SEED, shard.key().as_ref(), &shard_deposit.generation.to_le_bytes()],
+        bump
+    )]
+    pub shard_batch: Account<'info, ShardBatch>,
+
+    /// CHECK: receives the batch's rent with its last deposit
+    #[account(mut, address = shard_batch.payer)]
+    pub batch_payer: UncheckedAccount<'info>,
+
+    #[account(mut, seeds = [pool.key().as_ref(), shard_deposit.user.as_ref()], bump, owner = *__program_id)]
+    pub user_state: Account<'info, UserState>,
+
+    /// The wallet's stake across its positions, required once it holds
+    /// several.
+    #[account(mut, seeds = [WALLET_SEED, pool.key().as_ref(), shard_deposit.user.as_ref()], bump)]
+    pub wallet_state: Option<Account<'info, WalletState>>,
+
+    #[account(mut)]
+    pub leaderboard: Option<Account<'info, Leaderboard>>,
+
+    #[account(mut)]
+    pub user_checkpoints: Option<AccountLoader<'info, Checkpoints>>,
+
+    #[account(mut)]
+    pub pool_checkpoints: Option<AccountLoader<'info, Checkpoints>>,
+
+    #[account(seeds = [CONFIG_SEED, pool.key().as_ref()], bump)]
+    pub config: Option<Account<'info, PoolConfig>>,
+
+    #[account(seeds = [FEE_EXEMPTION_SEED, pool.key().as_ref(), shard_deposit.user.as_ref()], bump)]
+    pub fee_exemption: Option<Account<'info, FeeExemption>>,
+
+    /// Required once the pool keeps an audit log.
+    #[account(mut, seeds = [AUDIT_LOG_SEED, pool.key().as_ref()], bump)]
+    pub audit_log: Option<AccountLoader<'info, AuditLog>>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawShardDeposit<'info> {
+    #[account(owner = *__program_id)]
+    pub pool: AccountLoader<'info, Pool>,
+
+    /// CHECK: must be the signer stored in the pool
+    #[account(constraint = program_signer.key() == pool.load()?.program_signer @PoolError::InvalidProgramSigner)]
+    pub program_signer: AccountInfo<'info>,
+
+    #[account(constraint = mint.key() == pool.load()?.mint @PoolError::InvalidMint, mint::token_program = token_program)]
+    mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(mut, seeds = [SHARD_SEED, pool.key().as_ref(), &[shard.index]], bump)]
+    pub shard: Account<'info, StakeShard>,
+
+    #[account(mut, address = shard.vault)]
+    shard_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        seeds = [SHARD_SEED, shard.key().as_ref(), authority.key().as_ref()],
+        bump,
+        close = authority
+    )]
+    pub shard_deposit: Account<'info, ShardDeposit>,
+
+    #[account(mut, token::mint = mint, token::authority = authority)]
+    user_mint_acc: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimRewards<'info> {
+    #[account(mut, owner = *__program_id )]
+    pub pool: AccountLoader<'info, Pool>,
+
+    /// CHECK: must be the signer stored in the pool
+    #[account(constraint = program_signer.key() == pool.load()?.program_signer @PoolError::InvalidProgramSigner)]
+    pub program_signer: AccountInfo<'info>,
+
+    /// CHECK: rewards are minted in the staked token
+    #[account(mut, constraint = mint.key() == pool.load()?.mint @PoolError::InvalidMint, mint::token_program = token_program)]
+    mint: Box<InterfaceAccount<'info, Mint>>,
+
+    /// CHECK: staking vault, pays the rewards yield funds
+    #[account(mut, constraint = vault.key() == pool.load()?.vault @PoolError::InvalidVault)]
+    vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+         mut,
+         constraint = (user_mint_acc.owner == user.key() && user_mint_acc.mint == mint.key())
+     )]
+    user_mint_acc: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// CHECK: the staker; rewards always go to their token account
+    pub user: UncheckedAccount<'info>,
+
+    /// CHECK
+    #[account(mut, seeds = [pool.key().as_ref(), user.key().as_ref(), &position_seed(user_state.index)], bump, owner = *__program_id)]
+    pub user_state: Account<'info, UserState>,
+
+    /// The user, or their session key.
+    pub authority: Signer<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+
+    /// Current season leaderboard, updated if passed.
+    #[account(mut)]
+    pub leaderboard: Option<Account<'info, Leaderboard>>,
+
+    /// The wallet's stake across its positions, required with the
+    /// leaderboard once it holds several.
+    #[account(mut, seeds = [WALLET_SEED, pool.key().as_ref(), user.key().as_ref()], bump)]
+    pub wallet_state: Option<Account<'info, WalletState>>,
+
+    /// Required once the pool keeps an audit log.
+    #[account(mut, seeds = [AUDIT_LOG_SEED, pool.key().as_ref()], bump)]
+    pub audit_log: Option<AccountLoader<'info, AuditLog>>,
+}
+
+/// Accounts the claim adapter needs beyond these are passed as remaining
+/// accounts. `claim.user_mint_acc` isn't paid.
+#[derive(Accounts)]
+pub struct ClaimRewardsAndSwap<'info> {
+    pub claim: ClaimRewards<'info>,
+
+    /// CHECK: signs the swap, see `SWAP_AUTHORITY_SEED`
+    #[account(seeds = [SWAP_AUTHORITY_SEED, claim.pool.key().as_ref()], bump)]
+    pub swap_authority: UncheckedAccount<'info>,
+
+    /// Scratch account the rewards are minted into and swapped out of.
+    #[account(
+        mut,
+        token::mint = claim.mint,
+        token::authority = swap_authority,
+        token::token_program = claim.token_program
+    )]
+    swap_source: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        constraint = output_mint.key() == claim.pool.load()?.claim_output_mint @PoolError::InvalidMint,
+        mint::token_program = output_token_program
+    )]
+    output_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(mut, token::mint = output_mint, token::authority = claim.user)]
+    user_output_acc: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// CHECK: must be the adapter whitelisted in the pool
+    #[account(executable, constraint = claim_adapter.key() == claim.pool.load()?.claim_adapter @PoolError::InvalidClaimAdapter)]
+    pub claim_adapter: UncheckedAccount<'info>,
+
+    pub output_token_program: Interface<'info, TokenInterface>,
+}
+
+/// Records the wallet's season score if a leaderboard was passed in. The
+/// user state must have just been settled; the wallet's total across its
+/// positions is settled here.
+fn record_on_leaderboard(
+    leaderboard: Option<&mut Account<Leaderboard>>,
+    pool_key: Pubkey,
+    pool: &Pool,
+    user: Pubkey,
+    user_state: &UserState,
+    wallet_state: Option<&mut Account<WalletState>>,
+    now: i64,
+) -> PoolResult {
+    if let Some(leaderboard) = leaderboard {
+        require!(
+            leaderboard.pool == pool_key && leaderboard.season == pool.season,
+            PoolError::InvalidLeaderboard
+        );
+        require!(
+            wallet_state.is_some() == user_state.wallet_tracked,
+            PoolError::InvalidWalletState
+        );
+
+        // Rankings are per wallet, over all of its positions.
+        let stake_seconds = match wallet_state {
+            Some(wallet_state) => wallet_state.settle_season(pool, now)?,
+            None => user_state.season_stake_seconds,
+        };
+        leaderboard.record(user, stake_seconds);
+    }
+
+    Ok(())
+}
+
+/// Appends the user's and the pool's new stake to their checkpoint
+/// histories when the pool tracks them. Stake must already be booked.
+/// Voting history is per wallet, so a tracked wallet's total is recorded.
+#[allow(clippy::too_many_arguments)]
+fn record_checkpoints<'info>(
+    pool: &Pool,
+    pool_key: Pubkey,
+    user_state: &UserState,
+    wallet_state: Option<&Account<WalletState>>,
+    user: Pubkey,
+    user_checkpoints: Option<&AccountLoader<'info, Checkpoints>>,
+    pool_checkpoints: Option<&AccountLoader<'info, Checkpoints>>,
+) -> PoolResult {
+    if pool.checkpoints_enabled == 0 {
+        return Ok(());
+    }
+
+    require!(
+        wallet_state.is_some() == user_state.wallet_tracked,
+        PoolError::InvalidWalletState
+    );
+    let (user_pages, user_amount) = match wallet_state {
+        Some(wallet_state) => (wallet_state.checkpoint_pages, wallet_state.staked_amount),
+        None => (user_state.checkpoint_pages, user_state.staked_amount),
+    };
+
+    let slot = Clock::get()?.slot;
+
+    for (checkpoints, holder, pages, amount) in [
+        (user_checkpoints, user, user_pages, user_amount),
+        (
+            pool_checkpoints,
+            pool_key,
+            pool.checkpoint_pages,
+            pool.staked_total,
+        ),
+    ] {
+        let checkpoints = &mut checkpoints
+            .ok_or(PoolError::InvalidCheckpoints)?
+            .load_mut()?;
+
+        require!(
+            checkpoints.pool == pool_key
+                && checkpoints.holder == holder
+                && checkpoints.page.safe_add(1)? == pages,
+            PoolError::InvalidCheckpoints
+        );
+
+        checkpoints.push(slot, amount)?;
+    }
+
+    Ok(())
+}
+
+/// Calls `deposit` or `withdraw` on the restaking adapter. Adapters expose
+/// both as Anchor-style instructions taking a `u64` amount, with the
+/// restaking authority, its scratch account, the vault, mint and token
+/// program first in their account list. Deposits are taken from the scratch
+/// account and withdrawals paid into the vault; the adapter never gets a
+/// signature over the vault.
+fn invoke_adapter<'info>(
+    accounts: &MoveFunds<'info>,
+    remaining_accounts: &[AccountInfo<'info>],
+    name: &str,
+    amount: u64,
+    signer_seeds: &[&[&[u8]]],
+) -> PoolResult {
+    let mut data = hash(format!("global:{name}").as_bytes()).to_bytes()[..8].to_vec();
+    data.extend_from_slice(&amount.to_le_bytes());
+
+    let mut metas = vec![
+        AccountMeta::new_readonly(accounts.restaking_authority.key(), true),
+        AccountMeta::new(accounts.restaking_source.key(), false),
+        AccountMeta::new(accounts.vault.key(), false),
+        AccountMeta::new_readonly(accounts.mint.key(), false),
+        AccountMeta::new_readonly(accounts.token_program.key(), false),
+    ];
+    metas.extend(remaining_accounts.iter().map(|acc| AccountMeta {
+        pubkey: acc.key(),
+        is_signer: acc.is_signer,
+        is_writable: acc.is_writable,
+    }));
+
+    let mut infos = vec![
+        accounts.restaking_authority.to_account_info(),
+        accounts.restaking_source.to_account_info(),
+        accounts.vault.to_account_info(),
+        accounts.mint.to_account_info(),
+        accounts.token_program.to_account_info(),
+    ];
+    infos.extend_from_slice(remaining_accounts);
+
+    invoke_signed(
+        &Instruction {
+            program_id: accounts.adapter_program.key(),
+            accounts: metas,
+            data,
+        },
+        &infos,
+        signer_seeds,
+    )?;
+
+    Ok(())
+}
+
+/// Calls `swap` on the pool's swap adapter. Adapters expose it as an
+/// Anchor-style instruction taking `amount_in: u64, min_out: u64`, with the
+/// source owner, source, destination, input mint, output mint and the two
+/// mints' token programs first in their account list. The caller checks
+/// what actually arrived.
+#[allow(clippy::too_many_arguments)]
+fn invoke_swap<'info>(
+    swap_adapter: &AccountInfo<'info>,
+    owner: AccountInfo<'info>,
+    source: AccountInfo<'info>,
+    destination: AccountInfo<'info>,
+    mints: [AccountInfo<'info>; 2],
+    token_programs: [AccountInfo<'info>; 2],
+    remaining_accounts: &[AccountInfo<'info>],
+    amount_in: u64,
+    min_out: u64,
+    signer_seeds: &[&[&[u8]]],
+) -> PoolResult {
+    let mut data = hash(b"global:swap").to_bytes()[..8].to_vec();
+    data.extend_from_slice(&amount_in.to_le_bytes());
+    data.extend_from_slice(&min_out.to_le_bytes());
+
+    let [input_mint, output_mint] = mints;
+    let [input_token_program, output_token_program] = token_programs;
+
+    let mut metas = vec![
+        AccountMeta::new_readonly(owner.key(), true),
+        AccountMeta::new(source.key(), false),
+        AccountMeta::new(destination.key(), false),
+        AccountMeta::new_readonly(input_mint.key(), false),
+        AccountMeta::new_readonly(output_mint.key(), false),
+        AccountMeta::new_readonly(input_token_program.key(), false),
+        AccountMeta::new_readonly(output_token_program.key(), false),
+    ];
+    metas.extend(remaining_accounts.iter().map(|acc| AccountMeta {
+        pubkey: acc.key(),
+        is_signer: acc.is_signer,
+        is_writable: acc.is_writable,
+    }));
+
+    let mut infos = vec![
+        owner,
+        source,
+        destination,
+        input_mint,
+        output_mint,
+        input_token_program,
+        output_token_program,
+    ];
+    infos.extend_from_slice(remaining_accounts);
+
+    invoke_signed(
+        &Instruction {
+            program_id: swap_adapter.key(),
+            accounts: metas,
+            data,
+        },
+        &infos,
+        signer_seeds,
+    )?;
+
+    Ok(())
+}
+
+/// Calls `withdraw` on a Streamflow/Zebec-style stream program for
+/// everything vested so far. The recipient authority signs, followed by the
+/// stream, the recipient token account, the mint and its token program; the
+/// program's other accounts come from `remaining_accounts`.
+fn invoke_stream_withdraw<'info>(
+    accounts: &ClaimRewardStream<'info>,
+    remaining_accounts: &[AccountInfo<'info>],
+    signer_seeds: &[&[&[u8]]],
+) -> PoolResult {
+    let recipient = accounts.stream_recipient.to_account_info();
+    let stream = accounts.stream.to_account_info();
+    let destination = accounts.stream_tokens.to_account_info();
+    let mint = accounts.mint.to_account_info();
+    let token_program = accounts.token_program.to_account_info();
+
+    let mut data = hash(b"global:withdraw").to_bytes()[..8].to_vec();
+    data.extend_from_slice(&u64::MAX.to_le_bytes());
+
+    let mut metas = vec![
+        AccountMeta::new_readonly(recipient.key(), true),
+        AccountMeta::new(stream.key(), false),
+        AccountMeta::new(destination.key(), false),
+        AccountMeta::new_readonly(mint.key(), false),
+        AccountMeta::new_readonly(token_program.key(), false),
+    ];
+    metas.extend(remaining_accounts.iter().map(|acc| AccountMeta {
+        pubkey: acc.key(),
+        is_signer: acc.is_signer,
+        is_writable: acc.is_writable,
+    }));
+
+    let mut infos = vec![recipient, stream, destination, mint, token_program];
+    infos.extend_from_slice(remaining_accounts);
+
+    invoke_signed(
+        &Instruction {
+            program_id: accounts.stream_program.key(),
+            accounts: metas,
+            data,
+        },
+        &infos,
+        signer_seeds,
+    )?;
+
+    Ok(())
+}
+
+/// Calls `name` on a lottery's randomness adapter with the randomness
+/// account, then the remaining accounts the adapter needs, e.g. its oracle
+/// queue and a payer.
+fn invoke_randomness<'info>(
+    randomness_program: &AccountInfo<'info>,
+    randomness_account: &AccountInfo<'info>,
+    remaining_accounts: &[AccountInfo<'info>],
+    name: &str,
+) -> PoolResult {
+    let data = hash(format!("global:{name}").as_bytes()).to_bytes()[..8].to_vec();
+
+    let mut metas = vec![AccountMeta::new(randomness_account.key(), false)];
+    metas.extend(remaining_accounts.iter().map(|acc| AccountMeta {
+        pubkey: acc.key(),
+        is_signer: acc.is_signer,
+        is_writable: acc.is_writable,
+    }));
+
+    let mut infos = vec![randomness_account.clone()];
+    infos.extend_from_slice(remaining_accounts);
+
+    invoke(
+        &Instruction {
+            program_id: randomness_program.key(),
+            accounts: metas,
+            data,
+        },
+        &infos,
+    )?;
+
+    Ok(())
+}
+
+/// Calls the account-compression program on `merkle_tree` with the program
+/// signer as tree authority. `proof` is only passed for leaf replacements.
+fn invoke_compression<'info>(
+    accounts: [AccountInfo<'info>; 4],
+    proof: &[AccountInfo<'info>],
+    name: &str,
+    args: &[u8],
+    signer_seeds: &[&[&[u8]]],
+) -> PoolResult {
+    let [compression_program, merkle_tree, authority, noop_program] = accounts;
+
+    let mut data = hash(format!("global:{name}").as_bytes()).to_bytes()[..8].to_vec();
+    data.extend_from_slice(args);
+
+    let mut metas = vec![
+        AccountMeta::new(merkle_tree.key(), false),
+        AccountMeta::new_readonly(authority.key(), true),
+        AccountMeta::new_readonly(noop_program.key(), false),
+    ];
+    metas.extend(
+        proof
+            .iter()
+            .map(|node| AccountMeta::new_readonly(node.key(), false)),
+    );
+
+    let mut infos = vec![merkle_tree, authority, noop_program];
+    infos.extend_from_slice(proof);
+
+    invoke_signed(
+        &Instruction {
+            program_id: compression_program.key(),
+            accounts: metas,
+            data,
+        },
+        &infos,
+        signer_seeds,
+    )?;
+
+    Ok(())
+}
+
+/// The pool's config, which must be passed once the pool has one.
+fn pool_config<'a>(
+    pool: &Pool,
+    config: &'a Option<Account<PoolConfig>>,
+) -> PoolResult<Option<&'a PoolConfig>> {
+    require!(
+        pool.config_enabled == 0 || config.is_some(),
+        PoolError::InvalidPoolConfig
+    );
+
+    Ok(config.as_deref())
+}
+
+/// Appends to the pool's audit log, which must be passed while it is enabled.
+fn record_audit(
+    pool: &Pool,
+    pool_key: Pubkey,
+    audit_log: Option<&AccountLoader<AuditLog>>,
+    op: AuditOp,
+    user: Pubkey,
+    amount: u64,
+) -> PoolResult {
+    if pool.audit_log_enabled == 0 {
+        return Ok(());
+    }
+
+    let audit_log = &mut audit_log.ok_or(PoolError::InvalidAuditLog)?.load_mut()?;
+    require_keys_eq!(audit_log.pool, pool_key, PoolError::InvalidAuditLog);
+
+    audit_log.push(op, user, amount, Clock::get()?.slot)
+}
+
+fn encode_config_value<T: AnchorSerialize>(value: &T) -> PoolResult<[u8; CONFIG_VALUE_LEN]> {
+    let bytes = value.try_to_vec()?;
+    require_gte!(
+        CONFIG_VALUE_LEN,
+        bytes.len(),
+        PoolError::InvalidConfigChange
+    );
+
+    let mut encoded = [0; CONFIG_VALUE_LEN];
+    encoded[..bytes.len()].copy_from_slice(&bytes);
+
+    Ok(encoded)
+}
+
+/// Fills in the next `ConfigChange`, which must be passed while the pool
+/// keeps a config history.
+fn record_config_change<T: AnchorSerialize>(
+    pool: &mut Pool,
+    pool_key: Pubkey,
+    config_change: Option<&mut Account<ConfigChange>>,
+    param: ConfigParam,
+    old: &T,
+    new: &T,
+    authority: Pubkey,
+) -> PoolResult {
+    let Some(change) = config_change else {
+        require!(
+            pool.config_history_enabled == 0,
+            PoolError::InvalidConfigChange
+        );
+        return Ok(());
+    };
+
+    fill_config_change(
+        change,
+        pool_key,
+        pool.config_change_count,
+        param,
+        old,
+        new,
+        authority,
+    )?;
+    pool.config_change_count = pool.config_change_count.safe_add(1)?;
+
+    Ok(())
+}
+
+/// Fills in the next `ConfigChange` of the protocol's history.
+fn record_global_config_change<T: AnchorSerialize>(
+    global_config: &mut Account<GlobalConfig>,
+    config_change: &mut Account<ConfigChange>,
+    param: ConfigParam,
+    old: &T,
+    new: &T,
+    authority: Pubkey,
+) -> PoolResult {
+    fill_config_change(
+        config_change,
+        global_config.key(),
+        global_config.config_change_count,
+        param,
+        old,
+        new,
+        authority,
+    )?;
+    global_config.config_change_count = global_config.config_change_count.safe_add(1)?;
+
+    Ok(())
+}
+
+fn fill_config_change<T: AnchorSerialize>(
+    change: &mut ConfigChange,
+    target: Pubkey,
+    id: u64,
+    param: ConfigParam,
+    old: &T,
+    new: &T,
+    authority: Pubkey,
+) -> PoolResult {
+    change.pool = target;
+    change.id = id;
+    change.param = param;
+    change.old_value = encode_config_value(old)?;
+    change.new_value = encode_config_value(new)?;
+    change.slot = Clock::get()?.slot;
+    change.authority = authority;
+
+    Ok(())
+}
+
+/// Checks `leaf` against `root`, hashing each level as a sorted pair.
+fn verify_merkle_proof(proof: &[[u8; 32]], root: &[u8; 32], leaf: [u8; 32]) -> bool {
+    let computed = proof.iter().fold(leaf, |node, sibling| {
+        if node <= *sibling {
+            hashv(&[&node, sibling]).to_bytes()
+        } else {
+            hashv(&[sibling, &node]).to_bytes()
+        }
+    });
+
+    computed == *root
+}
+
+/// The protocol's `GlobalConfig`, if it has been created. It is a fixed
+/// PDA, so a pool creator can't dodge it by leaving it out.
+fn load_global_config(global_config: &AccountInfo) -> PoolResult<Option<GlobalConfig>> {
+    if global_config.owner != &crate::ID {
+        return Ok(None);
+    }
+
+    Ok(Some(GlobalConfig::try_deserialize(
+        &mut &global_config.try_borrow_data()?[..],
+    )?))
+}
+
+/// Applies the protocol's mint lists to a new pool.
+fn check_mint_listing(config: &GlobalConfig, mint_listing: &AccountInfo) -> PoolResult {
+    let allowed = if mint_listing.owner == &crate::ID {
+        Some(MintListing::try_deserialize(&mut &mint_listing.try_borrow_data()?[..])?.allowed)
+    } else {
+        None
+    };
+
+    match allowed {
+        Some(true) => Ok(()),
+        Some(false) => err!(PoolError::MintBlocked),
+        None => {
+            require!(!config.allowlist_only, PoolError::MintNotAllowed);
+            Ok(())
+        }
+    }
+}
+
+/// Sets up a new pool, with the economic parameters of `template` if given.
+fn init_pool(accounts: &mut Initialize, nonce: u8, template: Option<&Pool>) -> PoolResult {
+    if let Some(config) = load_global_config(&accounts.global_config)? {
+        check_mint_listing(&config, &accounts.mint_listing)?;
+        require!(
+            !config.permissioned || accounts.authority.key() == config.upgrade_authority,
+            PoolError::Unauthorized
+        );
+    }
+
+    let pool = &mut accounts.pool.load_init()?;
+    accounts.pool_marker.pool = accounts.pool.key();
+
+    pool.magic = INIT_MAGIC_NUMBER;
+    pool.mint = accounts.mint.key();
+    pool.vault = accounts.vault.key();
+    pool.program_signer = accounts.program_signer.key();
+    pool.nonce = nonce;
+    pool.authority = accounts.authority.key();
+    pool.layout_version = POOL_LAYOUT_VERSION;
+
+    let now = Clock::get()?.unix_timestamp;
+    pool.last_update_ts = now;
+    pool.emission_start_ts = now;
+
+    if let Some(template) = template {
+        pool.copy_economics(template);
+    }
+
+    emit!(PoolInitialized {
+        pool: accounts.pool.key(),
+        authority: pool.authority,
+        mint: pool.mint,
+        vault: pool.vault,
+        program_signer: pool.program_signer,
+        nonce: pool.nonce,
+        token_program: *accounts.mint.to_account_info().owner,
+        emission_rate: pool.emission_rate,
+        emission_schedule: pool.emission_schedule(),
+        dynamic_apr: pool.dynamic_apr != 0,
+        utilization_target: pool.utilization_target,
+        min_apr_bps: pool.min_apr_bps,
+        max_apr_bps: pool.max_apr_bps,
+        streak_epoch_duration: pool.streak_epoch_duration,
+        streak_boost_bps: pool.streak_boost_bps,
+        max_streak_boost_bps: pool.max_streak_boost_bps,
+        points_mode: pool.points_mode != 0,
+        claim_cooldown: pool.claim_cooldown,
+        dust_policy: pool.dust_policy(),
+    });
+
+    assert_pool_invariants(pool, &accounts.vault)?;
+    pool.publish_state()
+}
+
+fn handle_initialize(ctx: Context<Initialize>) -> PoolResult {
+    init_pool(ctx.accounts, ctx.bumps.program_signer, None)
+}
+
+fn handle_clone_pool(ctx: Context<ClonePool>) -> PoolResult {
+    let template = ctx.accounts.template_pool.load()?;
+    init_pool(
+        &mut ctx.accounts.init,
+        ctx.bumps.init.program_signer,
+        Some(&template),
+    )
+}
+
+fn handle_initialize_global_config(
+    ctx: Context<InitializeGlobalConfig>,
+    allowlist_only: bool,
+    permissioned: bool,
+) -> PoolResult {
+    let global_config = &mut ctx.accounts.global_config;
+    global_config.admin = ctx.accounts.authority.key();
+    global_config.allowlist_only = allowlist_only;
+    global_config.upgrade_authority = ctx.accounts.authority.key();
+    global_config.permissioned = permissioned;
+
+    Ok(())
+}
+
+fn handle_sync_upgrade_authority(ctx: Context<SyncUpgradeAuthority>) -> PoolResult {
+    let global_config = &mut ctx.accounts.global_config;
+    let old = global_config.upgrade_authority;
+    global_config.upgrade_authority = ctx
+        .accounts
+        .program_data
+        .upgrade_authority_address
+        .unwrap_or_default();
+    let new = global_config.upgrade_authority;
+
+    record_global_config_change(
+        global_config,
+        &mut ctx.accounts.config_change,
+        ConfigParam::UpgradeAuthority,
+        &old,
+        &new,
+        ctx.accounts.payer.key(),
+    )
+}
+
+fn handle_set_global_config(
+    ctx: Context<SetGlobalConfig>,
+    admin: Pubkey,
+    allowlist_only: bool,
+    permissioned: bool,
+) -> PoolResult {
+    let global_config = &mut ctx.accounts.global_config;
+    let old = (
+        global_config.admin,
+        global_config.allowlist_only,
+        global_config.permissioned,
+    );
+    global_config.admin = admin;
+    global_config.allowlist_only = allowlist_only;
+    global_config.permissioned = permissioned;
+
+    record_global_config_change(
+        global_config,
+        &mut ctx.accounts.config_change,
+        ConfigParam::GlobalConfig,
+        &old,
+        &(admin, allowlist_only, permissioned),
+        ctx.accounts.admin.key(),
+    )
+}
+
+fn handle_list_mint(ctx: Context<ListMint>, mint: Pubkey, allowed: bool) -> PoolResult {
+    let mint_listing = &mut ctx.accounts.mint_listing;
+    mint_listing.mint = mint;
+    mint_listing.allowed = allowed;
+
+    record_global_config_change(
+        &mut ctx.accounts.global_config,
+        &mut ctx.accounts.config_change,
+        ConfigParam::MintListing,
+        &(mint, None::<bool>),
+        &(mint, Some(allowed)),
+        ctx.accounts.admin.key(),
+    )
+}
+
+fn handle_delist_mint(ctx: Context<DelistMint>) -> PoolResult {
+    let mint_listing = &ctx.accounts.mint_listing;
+
+    record_global_config_change(
+        &mut ctx.accounts.global_config,
+        &mut ctx.accounts.config_change,
+        ConfigParam::MintListing,
+        &(mint_listing.mint, Some(mint_listing.allowed)),
+        &(mint_listing.mint, None::<bool>),
+        ctx.accounts.admin.key(),
+    )
+}
+
+fn handle_distribute<'info>(
+    ctx: Context<'_, '_, '_, 'info, Distribute<'info>>,
+    amounts: Vec<u64>,
+) -> PoolResult {
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+    pool.require_enabled(instruction_flags::DISTRIBUTE)?;
+    require!(pool.paused == 0, PoolError::PoolPaused);
+
+    require_eq!(
+        amounts.len(),
+        ctx.remaining_accounts.len(),
+        PoolError::InvalidDistribution
+    );
+    let total = amounts
+        .iter()
+        .try_fold(0u64, |total, amount| total.safe_add(*amount))?;
+    require_gte!(
+        pool.distribution_cap,
+        total,
+        PoolError::DistributionCapExceeded
+    );
+    pool.distribution_cap -= total;
+
+    let pool_key = ctx.accounts.pool.key();
+    let seeds = &[pool.mint.as_ref(), pool_key.as_ref(), &[pool.nonce]];
+    let signer = &[&seeds[..]];
+
+    for (info, amount) in ctx.remaining_accounts.iter().zip(amounts) {
+        require_keys_eq!(
+            *info.owner,
+            ctx.accounts.token_program.key(),
+            PoolError::InvalidUserMintAccount
+        );
+        let account = TokenAccount::try_deserialize(&mut &info.try_borrow_data()?[..])?;
+        require_keys_eq!(account.mint, pool.mint, PoolError::InvalidUserMintAccount);
+        record_audit(
+            pool,
+            pool_key,
+            ctx.accounts.audit_log.as_ref(),
+            AuditOp::Distribute,
+            account.owner,
+            amount,
+        )?;
+
+        token_interface::mint_to(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                MintTo {
+                    mint: ctx.accounts.mint.to_account_info(),
+                    to: info.clone(),
+                    authority: ctx.accounts.program_signer.to_account_info(),
+                },
+                signer,
+            ),
+            amount,
+        )?;
+    }
+
+    emit!(Distributed {
+        pool: pool_key,
+        recipients: ctx.remaining_accounts.len() as u32,
+        total,
+    });
+
+    Ok(())
+}
+
+fn handle_initialize_user_state(ctx: Context<InitializeUserState>) -> PoolResult {
+    ctx.accounts.user_state.initialized = true;
+    ctx.accounts.user_state.staked_amount = 0u64;
+    ctx.accounts.user_state.last_settle_ts = Clock::get()?.unix_timestamp;
+    ctx.accounts.user_state.payer = ctx.accounts.payer.key();
+    ctx.accounts.user_state.layout_version = USER_STATE_LAYOUT_VERSION;
+
+    Ok(())
+}
+
+fn handle_initialize_user_position(
+    ctx: Context<InitializeUserPosition>,
+    index: u16,
+    label: Option<[u8; 16]>,
+) -> PoolResult {
+    // The default position comes from `initialize_user_state`.
+    require!(index > 0, PoolError::InvalidPosition);
+
+    // From the first indexed position on, the wallet's stake is tracked
+    // across all of them, starting from what the default position holds.
+    let default_user_state = &mut ctx.accounts.default_user_state;
+    let wallet_state = &mut ctx.accounts.wallet_state;
+    if !default_user_state.wallet_tracked {
+        wallet_state.pool = ctx.accounts.pool.key();
+        wallet_state.wallet = ctx.accounts.authority.key();
+        wallet_state.staked_amount = default_user_state.staked_amount;
+        wallet_state.unwound = default_user_state.unwound;
+        // Voting history and the season score carry on from there.
+        wallet_state.checkpoint_pages = default_user_state.checkpoint_pages;
+        wallet_state.season = default_user_state.season;
+        wallet_state.season_stake_seconds = default_user_state.season_stake_seconds;
+        wallet_state.last_settle_ts = default_user_state.last_settle_ts;
+        default_user_state.wallet_tracked = true;
+    }
+    require!(!wallet_state.unwound, PoolError::UserUnwound);
+
+    let user_state = &mut ctx.accounts.user_state;
+    user_state.initialized = true;
+    user_state.last_settle_ts = Clock::get()?.unix_timestamp;
+    user_state.payer = ctx.accounts.payer.key();
+    user_state.index = index;
+    user_state.label = label;
+    user_state.wallet_tracked = true;
+    user_state.layout_version = USER_STATE_LAYOUT_VERSION;
+
+    Ok(())
+}
+
+fn handle_set_position_label(
+    ctx: Context<SetPositionLabel>,
+    label: Option<[u8; 16]>,
+) -> PoolResult {
+    let user_state = &mut ctx.accounts.user_state;
+    require!(user_state.index > 0, PoolError::InvalidPosition);
+
+    user_state.label = label;
+
+    Ok(())
+}
+
+fn handle_initialize_user_profile(ctx: Context<InitializeUserProfile>) -> PoolResult {
+    ctx.accounts.user_profile.owner = ctx.accounts.authority.key();
+
+    Ok(())
+}
+
+/// Mirrors a stake change into the wallet's profile, if passed.
+fn record_on_profile(
+    user_profile: Option<&mut Account<UserProfile>>,
+    pool_key: Pubkey,
+    before: u64,
+    after: u64,
+) -> PoolResult {
+    match user_profile {
+        Some(profile) => profile.record(pool_key, before, after),
+        None => Ok(()),
+    }
+}
+
+fn handle_init_user_state_for(ctx: Context<InitUserStateFor>, _authority: Pubkey) -> PoolResult {
+    ctx.accounts.user_state.initialized = true;
+    ctx.accounts.user_state.last_settle_ts = Clock::get()?.unix_timestamp;
+    ctx.accounts.user_state.payer = ctx.accounts.payer.key();
+    ctx.accounts.user_state.layout_version = USER_STATE_LAYOUT_VERSION;
+
+    Ok(())
+}
+
+fn handle_gc_user_state(ctx: Context<GcUserState>) -> PoolResult {
+    let params = &ctx.accounts.config.params;
+    let user_state = &ctx.accounts.user_state;
+    let now = Clock::get()?.unix_timestamp;
+
+    require!(params.gc_inactivity > 0, PoolError::UserStateActive);
+
+    // Anything the user could still claim keeps the account, and so do
+    // checkpoint pages, which a recreated account would try to open again.
+    require!(
+        user_state.staked_amount == 0
+            && user_state.pending_rewards == 0
+            && user_state.reward_iou == 0
+            && user_state.sol_pending_rewards == 0
+            && user_state.points == 0
+            && user_state.checkpoint_pages == 0,
+        PoolError::UserStateActive
+    );
+    // A recreated default position would lose track of the wallet's other
+    // positions.
+    require!(
+        user_state.index > 0 || !user_state.wallet_tracked,
+        PoolError::UserStateActive
+    );
+    require!(
+        now >= user_state
+            .last_settle_ts
+            .max(user_state.last_claim_ts)
+            .saturating_add(params.gc_inactivity),
+        PoolError::UserStateActive
+    );
+
+    let user_state = ctx.accounts.user_state.to_account_info();
+    let rent = user_state.lamports();
+    let cranker_lamports = bps_of(rent, params.gc_cranker_bps as u128, Rounding::Payout)?;
+    user_state.sub_lamports(cranker_lamports)?;
+    ctx.accounts.cranker.add_lamports(cranker_lamports)?;
+
+    emit!(UserStateCollected {
+        pool: ctx.accounts.pool.key(),
+        user: ctx.accounts.user.key(),
+        payer_lamports: rent.safe_sub(cranker_lamports)?,
+        cranker: ctx.accounts.cranker.key(),
+        cranker_lamports,
+        label: ctx.accounts.user_state.label,
+    });
+
+    // `close` hands the rest to the payer.
+    Ok(())
+}
+
+/// Grows `account`, owned by this program and starting with
+/// `discriminator`, to hold `size` bytes after the discriminator, with
+/// `payer` topping up the rent. The new bytes read as zero.
+fn grow_account<'info>(
+    account: &AccountInfo<'info>,
+    discriminator: &[u8],
+    size: usize,
+    payer: &Signer<'info>,
+    system_program: &Program<'info, System>,
+) -> PoolResult {
+    require!(
+        account.try_borrow_data()?.starts_with(discriminator),
+        ErrorCode::AccountDiscriminatorMismatch
+    );
+
+    let len = 8 + size;
+    if account.data_len() >= len {
+        return Ok(());
+    }
+
+    let top_up = Rent::get()?
+        .minimum_balance(len)
+        .saturating_sub(account.lamports());
+    if top_up > 0 {
+        system_program::transfer(
+            CpiContext::new(
+                system_program.to_account_info(),
+                system_program::Transfer {
+                    from: payer.to_account_info(),
+                    to: account.clone(),
+                },
+            ),
+            top_up,
+        )?;
+    }
+
+    Ok(account.realloc(len, true)?)
+}
+
+/// Brings a pool from an older layout up to `POOL_LAYOUT_VERSION`. Fields
+/// added since then start at zero, except the ones `initialize` sets.
+fn handle_migrate_pool(ctx: Context<MigratePool>) -> PoolResult {
+    let pool_info: &AccountInfo = &ctx.accounts.pool;
+    grow_account(
+        pool_info,
+        &<Pool as Discriminator>::DISCRIMINATOR,
+        std::mem::size_of::<Pool>(),
+        &ctx.accounts.authority,
+        &ctx.accounts.system_program,
+    )?;
+
+    let mut data = pool_info.try_borrow_mut_data()?;
+    let pool: &mut Pool = bytemuck::from_bytes_mut(&mut data[8..8 + std::mem::size_of::<Pool>()]);
+    require!(pool.mint == ctx.accounts.mint.key(), PoolError::InvalidMint);
+    require!(
+        pool.layout_version < POOL_LAYOUT_VERSION,
+        PoolError::LayoutUpToDate
+    );
+
+    let authority = match pool.layout_version {
+        0 => ctx.accounts.mint.freeze_authority.into(),
+        _ => Some(pool.authority),
+    };
+    require!(
+        authority == Some(ctx.accounts.authority.key()),
+        PoolError::Unauthorized
+    );
+
+    // Version 0: the pool only knew its mint, vault, signer and stake.
+    // Rewards start accruing now, under the mint's freeze authority.
+    // Version 1: no reward reserve, so emissions keep running unbounded
+    // with `emission_stop_ts` at 0.
+    // Version 2: no distribution cap, so `distribute` mints nothing until
+    // one is set.
+    if pool.layout_version == 0 {
+        let now = Clock::get()?.unix_timestamp;
+        pool.authority = ctx.accounts.authority.key();
+        pool.last_update_ts = now;
+        pool.emission_start_ts = now;
+    }
+    pool.layout_version = POOL_LAYOUT_VERSION;
+    ctx.accounts.pool_marker.pool = ctx.accounts.pool.key();
+
+    pool.publish_state()
+}
+
+/// Brings a user state from an older layout up to
+/// `USER_STATE_LAYOUT_VERSION`, once its pool is migrated.
+fn handle_migrate_user_state(ctx: Context<MigrateUserState>) -> PoolResult {
+    require!(
+        ctx.accounts.pool.load()?.layout_version >= POOL_LAYOUT_VERSION,
+        PoolError::LayoutUpToDate
+    );
+
+    let user_state_info: &AccountInfo = &ctx.accounts.user_state;
+    grow_account(
+        user_state_info,
+        &<UserState as Discriminator>::DISCRIMINATOR,
+        UserState::size(),
+        &ctx.accounts.user,
+        &ctx.accounts.system_program,
+    )?;
+
+    let mut data = user_state_info.try_borrow_mut_data()?;
+    let mut user_state = UserState::try_deserialize(&mut &data[..])?;
+    require!(
+        user_state.layout_version < USER_STATE_LAYOUT_VERSION,
+        PoolError::LayoutUpToDate
+    );
+
+    // Version 0: only the stake was recorded. It earns from the pool's
+    // migration on, which left the reward index at zero, and its streak
+    // starts now.
+    if user_state.layout_version == 0 {
+        let now = Clock::get()?.unix_timestamp;
+        user_state.last_settle_ts = now;
+        user_state.streak_start_ts = now;
+        user_state.payer = ctx.accounts.user.key();
+    }
+    user_state.layout_version = USER_STATE_LAYOUT_VERSION;
+
+    user_state.try_serialize(&mut &mut data[..])
+}
+
+/// `PoolConfig::withdrawal_charges`, logged as one fee with whether the fee
+/// was waived.
+fn charge_withdrawal(
+    config: &PoolConfig,
+    pool_key: Pubkey,
+    user: Pubkey,
+    user_state: &UserState,
+    amount: u64,
+    now: i64,
+    exempt: bool,
+) -> PoolResult<(u64, u64)> {
+    let (fee, penalty) = config.withdrawal_charges(amount, user_state, now, exempt)?;
+    emit!(FeeCharged {
+        pool: pool_key,
+        user,
+        fee: fee.safe_add(penalty)?,
+        exempt,
+        label: user_state.label,
+    });
+
+    Ok((fee, penalty))
+}
+
+/// Stake of the wallet owning `user_state` across its positions, and
+/// whether it was unwound. `wallet_state` is passed exactly for positions
+/// of wallets holding several.
+fn wallet_stake(
+    user_state: &UserState,
+    wallet_state: Option<&Account<WalletState>>,
+) -> PoolResult<(u64, bool)> {
+    require!(
+        wallet_state.is_some() == user_state.wallet_tracked,
+        PoolError::InvalidWalletState
+    );
+
+    Ok(match wallet_state {
+        Some(wallet_state) => (
+            wallet_state.staked_amount,
+            wallet_state.unwound || user_state.unwound,
+        ),
+        None => (user_state.staked_amount, user_state.unwound),
+    })
+}
+
+/// Books the change of `user_state`'s stake from `before` in the wallet's
+/// total, settling the wallet's season score first.
+fn book_wallet_stake(
+    pool: &Pool,
+    user_state: &UserState,
+    wallet_state: Option<&mut Account<WalletState>>,
+    before: u64,
+) -> PoolResult {
+    require!(
+        wallet_state.is_some() == user_state.wallet_tracked,
+        PoolError::InvalidWalletState
+    );
+
+    if let Some(wallet_state) = wallet_state {
+        wallet_state.settle_season(pool, Clock::get()?.unix_timestamp)?;
+        wallet_state.staked_amount = wallet_state
+            .staked_amount
+            .safe_sub(before)?
+            .safe_add(user_state.staked_amount)?;
+    }
+
+    Ok(())
+}
+
+/// Books `received` vault tokens as `user`'s stake, net of the deposit fee.
+/// Callers transfer the tokens afterwards.
+#[allow(clippy::too_many_arguments)]
+fn credit_stake<'info>(
+    pool: &mut Pool,
+    pool_key: Pubkey,
+    user: Pubkey,
+    user_state: &mut UserState,
+    received: u64,
+    config: &Option<Account<'info, PoolConfig>>,
+    attestation: Option<&UncheckedAccount<'info>>,
+    leaderboard: Option<&mut Account<'info, Leaderboard>>,
+    user_checkpoints: Option<&AccountLoader<'info, Checkpoints>>,
+    pool_checkpoints: Option<&AccountLoader<'info, Checkpoints>>,
+    fee_exemption: Option<&Account<'info, FeeExemption>>,
+    mut wallet_state: Option<&mut Account<'info, WalletState>>,
+    reset_lock: bool,
+) -> PoolResult {
+    require!(user_state.initialized, PoolError::UserNotInitialized);
+    let (wallet_staked, unwound) = wallet_stake(user_state, wallet_state.as_deref())?;
+    require!(!unwound, PoolError::UserUnwound);
+    require!(
+        pool.terms_hash == [0; 32] || user_state.terms_hash == pool.terms_hash,
+        PoolError::TermsNotAccepted
+    );
+    require!(pool.paused == 0, PoolError::PoolPaused);
+    require!(pool.incident_reason == 0, PoolError::IncidentActive);
+    // New stake would take a loss it wasn't around for.
+    require!(pool.haircut == 0, PoolError::ShortfallSettled);
+
+    require!(received > 0, PoolError::ZeroAmount);
+
+    let now = Clock::get()?.unix_timestamp;
+    let config = pool_config(pool, config)?;
+    let fee = match config {
+        Some(config) => {
+            let attested = config.is_attested(user, attestation, now);
+            let exempt = fee_exemption.is_some();
+            let fee = config.deposit_fee(received, pool, wallet_staked, attested, exempt)?;
+            emit!(FeeCharged {
+                pool: pool_key,
+                user,
+                fee,
+                exempt,
+                label: user_state.label,
+            });
+
+            fee
+        }
+        None => 0,
+    };
+    let credited = received.safe_sub(fee)?;
+    require!(credited > 0, PoolError::ZeroAmount);
+
+    update_rewards(pool, user_state, now)?;
+    record_on_leaderboard(
+        leaderboard,
+        pool_key,
+        pool,
+        user,
+        user_state,
+        wallet_state.as_deref_mut(),
+        now,
+    )?;
+
+    // Book the stake before handing control to the token program.
+    pool.staked_total = pool.staked_total.safe_add(credited)?;
+    pool.book_charges(fee, 0)?;
+    let before = user_state.staked_amount;
+    user_state.staked_amount = before.safe_add(credited)?;
+    book_wallet_stake(pool, user_state, wallet_state.as_deref_mut(), before)?;
+    // Deposits the user didn't sign for never extend their lock.
+    if let (Some(config), true) = (config, reset_lock) {
+        user_state.locked_until = now
+            .checked_add(config.params.lock_duration)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+    }
+
+    record_checkpoints(
+        pool,
+        pool_key,
+        user_state,
+        wallet_state.as_deref(),
+        user,
+        user_checkpoints,
+        pool_checkpoints,
+    )
+}
+
+fn handle_enter_staking<'info>(
+    ctx: Context<'_, '_, '_, 'info, EnterStaking<'info>>,
+    amount: u64,
+    tag: Option<[u8; 32]>,
+) -> PoolResult {
+    // Only what actually lands in the vault is credited.
+    let received = amount.safe_sub(transfer_fee(&ctx.accounts.mint, amount)?)?;
+
+    {
+        let pool = &mut ctx.accounts.pool.load_mut()?;
+        pool.require_enabled(instruction_flags::ENTER_STAKING)?;
+
+        require!(amount > 0, PoolError::ZeroAmount);
+        require_eq!(pool.mint, ctx.accounts.mint.key(), PoolError::InvalidMint);
+        require_eq!(
+            pool.vault,
+            ctx.accounts.vault.key(),
+            PoolError::InvalidVault
+        );
+
+        require_memo(pool, ctx.accounts.instructions.as_ref())?;
+
+        let before = ctx.accounts.user_state.staked_amount;
+        credit_stake(
+            pool,
+            ctx.accounts.pool.key(),
+            ctx.accounts.authority.key(),
+            &mut ctx.accounts.user_state,
+            received,
+            &ctx.accounts.config,
+            ctx.accounts.attestation.as_ref(),
+            ctx.accounts.leaderboard.as_mut(),
+            ctx.accounts.user_checkpoints.as_ref(),
+            ctx.accounts.pool_checkpoints.as_ref(),
+            ctx.accounts.fee_exemption.as_ref(),
+            ctx.accounts.wallet_state.as_mut(),
+            true,
+        )?;
+        record_audit(
+            pool,
+            ctx.accounts.pool.key(),
+            ctx.accounts.audit_log.as_ref(),
+            AuditOp::EnterStaking,
+            ctx.accounts.authority.key(),
+            received,
+        )?;
+        record_on_profile(
+            ctx.accounts.user_profile.as_mut(),
+            ctx.accounts.pool.key(),
+            before,
+            ctx.accounts.user_state.staked_amount,
+        )?;
+        record_rate(ctx.accounts.rate_publisher.as_mut(), pool)?;
+    }
+
+    emit!(Staked {
+        pool: ctx.accounts.pool.key(),
+        user: ctx.accounts.authority.key(),
+        amount: received,
+        tag,
+        label: ctx.accounts.user_state.label,
+    });
+
+    transfer_checked_with_hook(
+        &ctx.accounts.token_program,
+        ctx.accounts.user_mint_acc.to_account_info(),
+        &ctx.accounts.mint,
+        ctx.accounts.vault.to_account_info(),
+        ctx.accounts.authority.to_account_info(),
+        ctx.remaining_accounts,
+        amount,
+        &[],
+    )?;
+
+    ctx.accounts.vault.reload()?;
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+    assert_pool_invariants(pool, &ctx.accounts.vault)?;
+    pool.publish_state()
+}
+
+/// Books an exit of `amount` from the caller's stake and returns what the
+/// vault pays out for it and the rewards to mint, if the position emptied.
+/// Returns the payout and the rewards, split by `Pool::split_rewards`.
+fn release_stake(accounts: &mut LeaveStaking, amount: u64) -> PoolResult<(u64, (u64, u64))> {
+    let pool = &mut accounts.pool.load_mut()?;
+    pool.require_enabled(instruction_flags::LEAVE_STAKING)?;
+    if let Some(memo) = require_memo(pool, accounts.instructions.as_ref())? {
+        forward_memo(accounts.memo_program.as_ref(), &memo)?;
+    }
+    // Exits go through the pro-rata emergency path until it is resolved.
+    require!(pool.incident_reason == 0, PoolError::IncidentActive);
+    require!(pool.reserve_alarm == 0, PoolError::ReserveAlarm);
+
+    require!(amount > 0, PoolError::ZeroAmount);
+    require_eq!(pool.mint, accounts.mint.key(), PoolError::InvalidMint);
+    require_eq!(pool.vault, accounts.vault.key(), PoolError::InvalidVault);
+
+    require!(
+        accounts.user_state.initialized,
+        PoolError::UserNotInitialized
+    );
+
+    let now = Clock::get()?.unix_timestamp;
+    let destination = accounts.user_mint_acc.key();
+    accounts
+        .user_state
+        .authorize_cold_withdrawal(destination, amount, now)?;
+    accounts
+        .user_state
+        .authorize_withdrawal(accounts.guardian.as_ref(), amount, now)?;
+    let (fee, penalty) = match pool_config(pool, &accounts.config)? {
+        Some(config) => charge_withdrawal(
+            config,
+            accounts.pool.key(),
+            accounts.authority.key(),
+            &accounts.user_state,
+            amount,
+            now,
+            accounts.fee_exemption.is_some(),
+        )?,
+        None => (0, 0),
+    };
+    let charge = fee.safe_add(penalty)?;
+
+    update_rewards(pool, &mut accounts.user_state, now)?;
+    record_on_leaderboard(
+        accounts.leaderboard.as_mut(),
+        accounts.pool.key(),
+        pool,
+        accounts.authority.key(),
+        &accounts.user_state,
+        accounts.wallet_state.as_mut(),
+        now,
+    )?;
+
+    // Queued withdrawals are paid out before instant ones.
+    require_gte!(
+        accounts.vault.amount,
+        pool.written_down(pool.queued_amount.safe_add(amount)?)?,
+        PoolError::InsufficientBuffer
+    );
+
+    // Release the stake before handing control to the token program.
+    let before = accounts.user_state.staked_amount;
+    pool.staked_total = pool.staked_total.safe_sub(amount)?;
+    pool.book_charges(fee, penalty)?;
+    accounts.user_state.staked_amount = before.safe_sub(amount)?;
+    book_wallet_stake(
+        pool,
+        &accounts.user_state,
+        accounts.wallet_state.as_mut(),
+        before,
+    )?;
+    drop_link_boost(&mut accounts.user_state, accounts.link_boosted.as_ref())?;
+    record_on_profile(
+        accounts.user_profile.as_mut(),
+        accounts.pool.key(),
+        before,
+        accounts.user_state.staked_amount,
+    )?;
+    record_rate(accounts.rate_publisher.as_mut(), pool)?;
+
+    record_checkpoints(
+        pool,
+        accounts.pool.key(),
+        &accounts.user_state,
+        accounts.wallet_state.as_ref(),
+        accounts.authority.key(),
+        accounts.user_checkpoints.as_ref(),
+        accounts.pool_checkpoints.as_ref(),
+    )?;
+    record_audit(
+        pool,
+        accounts.pool.key(),
+        accounts.audit_log.as_ref(),
+        AuditOp::LeaveStaking,
+        accounts.authority.key(),
+        amount,
+    )?;
+    emit!(Unstaked {
+        pool: accounts.pool.key(),
+        user: accounts.authority.key(),
+        amount,
+        label: accounts.user_state.label,
+    });
+
+    // Don't leave rewards stranded on an emptied position.
+    let rewards = if accounts.user_state.staked_amount == 0 {
+        accounts.user_state.take_rewards(pool, now, None)?
+    } else {
+        0
+    };
+
+    Ok((
+        pool.written_down(amount.safe_sub(charge)?)?,
+        pool.split_rewards(rewards)?,
+    ))
+}
+
+fn handle_leave_staking<'info>(
+    ctx: Context<'_, '_, '_, 'info, LeaveStaking<'info>>,
+    amount: u64,
+) -> PoolResult {
+    let (payout, rewards) = release_stake(ctx.accounts, amount)?;
+    let (mint, nonce) = {
+        let pool = ctx.accounts.pool.load()?;
+        (pool.mint, pool.nonce)
+    };
+
+    let seeds = &[
+        mint.as_ref(),
+        ctx.accounts.pool.to_account_info().key.as_ref(),
+        &[nonce],
+    ];
+    let signer = &[&seeds[..]];
+
+    transfer_checked_with_hook(
+        &ctx.accounts.token_program,
+        ctx.accounts.vault.to_account_info(),
+        &ctx.accounts.mint,
+        ctx.accounts.user_mint_acc.to_account_info(),
+        ctx.accounts.program_signer.to_account_info(),
+        ctx.remaining_accounts,
+        payout,
+        signer,
+    )?;
+    pay_rewards(
+        &ctx.accounts.token_program,
+        &ctx.accounts.mint,
+        ctx.accounts.vault.to_account_info(),
+        ctx.accounts.user_mint_acc.to_account_info(),
+        ctx.accounts.program_signer.to_account_info(),
+        ctx.remaining_accounts,
+        rewards,
+        signer,
+    )?;
+
+    ctx.accounts.vault.reload()?;
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+    assert_pool_invariants(pool, &ctx.accounts.vault)?;
+    pool.publish_state()
+}
+
+fn handle_leave_staking_and_swap<'info>(
+    ctx: Context<'_, '_, '_, 'info, LeaveStakingAndSwap<'info>>,
+    amount: u64,
+    min_out: u64,
+) -> PoolResult {
+    // The payout goes to the output account, never the cold destination.
+    require!(
+        ctx.accounts.leave.user_state.cold_key == Pubkey::default(),
+        PoolError::ColdApprovalRequired
+    );
+    let (payout, rewards) = release_stake(&mut ctx.accounts.leave, amount)?;
+    let (mint, nonce) = {
+        let pool = ctx.accounts.leave.pool.load()?;
+        (pool.mint, pool.nonce)
+    };
+
+    let seeds = &[
+        mint.as_ref(),
+        ctx.accounts.leave.pool.to_account_info().key.as_ref(),
+        &[nonce],
+    ];
+    let signer = &[&seeds[..]];
+
+    let pool_key = ctx.accounts.leave.pool.key();
+    let swap_seeds = &[
+        SWAP_AUTHORITY_SEED,
+        pool_key.as_ref(),
+        &[ctx.bumps.swap_authority],
+    ];
+
+    // The adapter only gets a signature over the payout, moved out of the
+    // vault first.
+    let leave = &mut ctx.accounts.leave;
+    let scratch_before = ctx.accounts.swap_source.amount;
+    transfer_checked_with_hook(
+        &leave.token_program,
+        leave.vault.to_account_info(),
+        &leave.mint,
+        ctx.accounts.swap_source.to_account_info(),
+        leave.program_signer.to_account_info(),
+        ctx.remaining_accounts,
+        payout,
+        signer,
+    )?;
+    ctx.accounts.swap_source.reload()?;
+    let amount_in = ctx.accounts.swap_source.amount.safe_sub(scratch_before)?;
+
+    let output_before = ctx.accounts.user_output_acc.amount;
+    invoke_swap(
+        &ctx.accounts.swap_adapter,
+        ctx.accounts.swap_authority.to_account_info(),
+        ctx.accounts.swap_source.to_account_info(),
+        ctx.accounts.user_output_acc.to_account_info(),
+        [
+            leave.mint.to_account_info(),
+            ctx.accounts.output_mint.to_account_info(),
+        ],
+        [
+            leave.token_program.to_account_info(),
+            ctx.accounts.output_token_program.to_account_info(),
+        ],
+        ctx.remaining_accounts,
+        amount_in,
+        min_out,
+        &[&swap_seeds[..]],
+    )?;
+    leave.vault.reload()?;
+    ctx.accounts.swap_source.reload()?;
+    ctx.accounts.user_output_acc.reload()?;
+
+    // Nothing but the payout may leave the scratch account.
+    require_gte!(
+        ctx.accounts.swap_source.amount,
+        scratch_before,
+        PoolError::InvalidSwapAdapter
+    );
+    require_gte!(
+        ctx.accounts
+            .user_output_acc
+            .amount
+            .safe_sub(output_before)?,
+        min_out,
+        PoolError::SlippageExceeded
+    );
+
+    pay_rewards(
+        &leave.token_program,
+        &leave.mint,
+        leave.vault.to_account_info(),
+        leave.user_mint_acc.to_account_info(),
+        leave.program_signer.to_account_info(),
+        ctx.remaining_accounts,
+        rewards,
+        signer,
+    )?;
+    leave.vault.reload()?;
+
+    let pool = &mut leave.pool.load_mut()?;
+    assert_pool_invariants(pool, &leave.vault)?;
+    pool.publish_state()
+}
+
+fn handle_leave_staking_exact_out<'info>(
+    ctx: Context<'_, '_, '_, 'info, LeaveStaking<'info>>,
+    net_amount: u64,
+) -> PoolResult {
+    require!(net_amount > 0, PoolError::ZeroAmount);
+
+    let payout = net_amount.safe_add(inverse_transfer_fee(&ctx.accounts.mint, net_amount)?)?;
+    let amount = {
+        let pool = &ctx.accounts.pool.load()?;
+        // The haircut's rounding makes an exact output unreachable.
+        require!(pool.haircut == 0, PoolError::ShortfallSettled);
+
+        match pool_config(pool, &ctx.accounts.config)? {
+            Some(config) => config.gross_for_net(
+                payout,
+                &ctx.accounts.user_state,
+                Clock::get()?.unix_timestamp,
+                ctx.accounts.fee_exemption.is_some(),
+            )?,
+            None => payout,
+        }
+    };
+
+    handle_leave_staking(ctx, amount)
+}
+
+fn handle_set_emission_schedule(
+    ctx: Context<SetEmissionSchedule>,
+    emission_rate: u64,
+    schedule: EmissionSchedule,
+    end_ts: i64,
+) -> PoolResult {
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+    let now = Clock::get()?.unix_timestamp;
+
+    require!(
+        emission_rate == 0 || end_ts > now,
+        PoolError::InvalidEmissionSchedule
+    );
+
+    // Close out the old schedule before switching to the new one.
+    pool.update_reward_index(now)?;
+
+    let old = (
+        pool.emission_rate,
+        pool.emission_schedule(),
+        pool.emission_stop_ts,
+    );
+    pool.set_emission_schedule(schedule)?;
+    pool.emission_rate = emission_rate;
+    pool.emission_start_ts = now;
+    pool.emission_stop_ts = end_ts.max(now);
+    pool.require_emissions_funded()?;
+    let new = (emission_rate, schedule, pool.emission_stop_ts);
+    record_config_change(
+        pool,
+        ctx.accounts.pool.key(),
+        ctx.accounts.config_change.as_mut(),
+        ConfigParam::EmissionSchedule,
+        &old,
+        &new,
+        ctx.accounts.authority.key(),
+    )?;
+
+    Ok(())
+}
+
+fn handle_fund_emissions<'info>(
+    ctx: Context<'_, '_, '_, 'info, FundEmissions<'info>>,
+    amount: u64,
+) -> PoolResult {
+    require!(amount > 0, PoolError::ZeroAmount);
+
+    let before = ctx.accounts.vault.amount;
+    transfer_checked_with_hook(
+        &ctx.accounts.token_program,
+        ctx.accounts.funder_mint_acc.to_account_info(),
+        &ctx.accounts.mint,
+        ctx.accounts.vault.to_account_info(),
+        ctx.accounts.funder.to_account_info(),
+        ctx.remaining_accounts,
+        amount,
+        &[],
+    )?;
+    ctx.accounts.vault.reload()?;
+
+    // Only what arrived, after any transfer fee, backs emissions.
+    let received = ctx.accounts.vault.amount.safe_sub(before)?;
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+    pool.reward_reserve = pool.reward_reserve.safe_add(received)?;
+
+    emit!(EmissionsFunded {
+        pool: ctx.accounts.pool.key(),
+        funder: ctx.accounts.funder.key(),
+        amount: received,
+    });
+
+    assert_pool_invariants(pool, &ctx.accounts.vault)?;
+    pool.publish_state()
+}
+
+fn handle_extend_emissions(ctx: Context<ExtendEmissions>, end_ts: i64) -> PoolResult {
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+    let now = Clock::get()?.unix_timestamp;
+
+    require!(
+        pool.emission_stop_ts != 0 && end_ts > pool.emission_stop_ts.max(now),
+        PoolError::InvalidEmissionSchedule
+    );
+
+    pool.update_reward_index(now)?;
+
+    let old = pool.emission_stop_ts;
+    pool.emission_stop_ts = end_ts;
+    pool.require_emissions_funded()?;
+    record_config_change(
+        pool,
+        ctx.accounts.pool.key(),
+        ctx.accounts.config_change.as_mut(),
+        ConfigParam::EmissionEnd,
+        &old,
+        &end_ts,
+        ctx.accounts.authority.key(),
+    )?;
+
+    Ok(())
+}
+
+fn handle_set_dynamic_apr(
+    ctx: Context<SetDynamicApr>,
+    enabled: bool,
+    utilization_target: u64,
+    min_apr_bps: u32,
+    max_apr_bps: u32,
+    end_ts: i64,
+) -> PoolResult {
+    let now = Clock::get()?.unix_timestamp;
+    require!(
+        min_apr_bps <= max_apr_bps && max_apr_bps <= MAX_APR_BPS,
+        PoolError::InvalidAprParameters
+    );
+    require!(
+        !enabled || (utilization_target > 0 && end_ts > now),
+        PoolError::InvalidAprParameters
+    );
+
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+
+    // Settle under the old curve before switching.
+    pool.update_reward_index(now)?;
+
+    let old = (
+        pool.dynamic_apr != 0,
+        pool.utilization_target,
+        pool.min_apr_bps,
+        pool.max_apr_bps,
+        pool.emission_stop_ts,
+    );
+    pool.dynamic_apr = enabled as u8;
+    pool.utilization_target = utilization_target;
+    pool.min_apr_bps = min_apr_bps;
+    pool.max_apr_bps = max_apr_bps;
+    if enabled {
+        pool.emission_stop_ts = end_ts;
+    }
+    // Back on a legacy unbounded schedule, nothing changes by disabling.
+    if enabled || pool.emission_stop_ts != 0 {
+        pool.require_emissions_funded()?;
+    }
+    let new = (
+        enabled,
+        utilization_target,
+        min_apr_bps,
+        max_apr_bps,
+        pool.emission_stop_ts,
+    );
+    record_config_change(
+        pool,
+        ctx.accounts.pool.key(),
+        ctx.accounts.config_change.as_mut(),
+        ConfigParam::DynamicApr,
+        &old,
+        &new,
+        ctx.accounts.authority.key(),
+    )?;
+
+    Ok(())
+}
+
+fn handle_set_streak_boost(
+    ctx: Context<SetStreakBoost>,
+    epoch_duration: i64,
+    boost_bps_per_epoch: u32,
+    max_boost_bps: u32,
+) -> PoolResult {
+    require!(
+        epoch_duration > 0 && max_boost_bps <= MAX_STREAK_BOOST_BPS,
+        PoolError::InvalidStreakBoost
+    );
+
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+
+    pool.update_reward_index(Clock::get()?.unix_timestamp)?;
+
+    let old = (
+        pool.streak_epoch_duration,
+        pool.streak_boost_bps,
+        pool.max_streak_boost_bps,
+    );
+    pool.streak_epoch_duration = epoch_duration;
+    pool.streak_boost_bps = boost_bps_per_epoch;
+    pool.max_streak_boost_bps = max_boost_bps;
+    record_config_change(
+        pool,
+        ctx.accounts.pool.key(),
+        ctx.accounts.config_change.as_mut(),
+        ConfigParam::StreakBoost,
+        &old,
+        &(epoch_duration, boost_bps_per_epoch, max_boost_bps),
+        ctx.accounts.authority.key(),
+    )?;
+
+    Ok(())
+}
+
+/// Settles the user's rewards and takes `amount` of them for a claim.
+/// Returns what to pay, split by `Pool::split_rewards`; the part left to mint
+/// is kept as an IOU instead while the program signer can't mint.
+fn settle_claim(accounts: &mut ClaimRewards, amount: Option<u64>) -> PoolResult<(u64, u64)> {
+    let pool = &mut accounts.pool.load_mut()?;
+    pool.require_enabled(instruction_flags::CLAIM_REWARDS)?;
+
+    require!(pool.paused == 0, PoolError::PoolPaused);
+    require!(
+        accounts.user_state.initialized,
+        PoolError::UserNotInitialized
+    );
+    accounts
+        .user_state
+        .authorize_session(accounts.user.key(), accounts.authority.key())?;
+
+    let now = Clock::get()?.unix_timestamp;
+    require!(
+        accounts.user_state.last_claim_ts == 0
+            || now
+                >= accounts
+                    .user_state
+                    .last_claim_ts
+                    .saturating_add(pool.claim_cooldown as i64),
+        PoolError::ClaimCooldown
+    );
+
+    update_rewards(pool, &mut accounts.user_state, now)?;
+    record_on_leaderboard(
+        accounts.leaderboard.as_mut(),
+        accounts.pool.key(),
+        pool,
+        accounts.user.key(),
+        &accounts.user_state,
+        accounts.wallet_state.as_mut(),
+        now,
+    )?;
+
+    let user_state = &mut accounts.user_state;
+    require!(
+        user_state.pending_rewards > 0 || user_state.reward_iou > 0,
+        PoolError::ZeroAmount
+    );
+    let owed = user_state
+        .take_rewards(pool, now, amount)?
+        .safe_add(std::mem::take(&mut user_state.reward_iou))?;
+
+    // The funded balance pays what it covers; without the mint authority
+    // the rest waits as an IOU until it is handed back or yield comes in.
+    let (funded, minted) = pool.split_rewards(owed)?;
+    let minted = match can_mint_rewards(pool, &accounts.mint) {
+        true => minted,
+        false => {
+            user_state.reward_iou = minted;
+            0
+        }
+    };
+    let amount = funded.safe_add(minted)?;
+    match user_state.reward_iou {
+        0 => emit!(RewardsClaimed {
+            pool: accounts.pool.key(),
+            user: accounts.user.key(),
+            amount,
+            label: user_state.label,
+        }),
+        owed => emit!(RewardsClaimedPartially {
+            pool: accounts.pool.key(),
+            user: accounts.user.key(),
+            amount,
+            owed,
+            label: user_state.label,
+        }),
+    }
+    record_audit(
+        pool,
+        accounts.pool.key(),
+        accounts.audit_log.as_ref(),
+        AuditOp::ClaimRewards,
+        accounts.user.key(),
+        amount,
+    )?;
+
+    Ok((funded, minted))
+}
+
+fn handle_claim_rewards<'info>(
+    ctx: Context<'_, '_, '_, 'info, ClaimRewards<'info>>,
+    amount: Option<u64>,
+) -> PoolResult {
+    let rewards = settle_claim(ctx.accounts, amount)?;
+    let (mint, nonce) = {
+        let pool = ctx.accounts.pool.load()?;
+        (pool.mint, pool.nonce)
+    };
+
+    let seeds = &[
+        mint.as_ref(),
+        ctx.accounts.pool.to_account_info().key.as_ref(),
+        &[nonce],
+    ];
+    let signer = &[&seeds[..]];
+
+    pay_rewards(
+        &ctx.accounts.token_program,
+        &ctx.accounts.mint,
+        ctx.accounts.vault.to_account_info(),
+        ctx.accounts.user_mint_acc.to_account_info(),
+        ctx.accounts.program_signer.to_account_info(),
+        ctx.remaining_accounts,
+        rewards,
+        signer,
+    )?;
+
+    ctx.accounts.vault.reload()?;
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+    assert_pool_invariants(pool, &ctx.accounts.vault)?;
+    pool.publish_state()
+}
+
+fn handle_claim_rewards_and_swap<'info>(
+    ctx: Context<'_, '_, '_, 'info, ClaimRewardsAndSwap<'info>>,
+    amount: Option<u64>,
+    min_out: u64,
+) -> PoolResult {
+    let (mint, nonce) = {
+        let pool = ctx.accounts.claim.pool.load()?;
+        require!(pool.points_mode == 0, PoolError::InvalidClaimAdapter);
+        (pool.mint, pool.nonce)
+    };
+
+    // Only a claim paid in full is swapped.
+    let rewards = settle_claim(&mut ctx.accounts.claim, amount)?;
+    let amount = rewards.0.safe_add(rewards.1)?;
+    require!(
+        amount > 0 && ctx.accounts.claim.user_state.reward_iou == 0,
+        PoolError::RewardsUnbacked
+    );
+
+    let seeds = &[
+        mint.as_ref(),
+        ctx.accounts.claim.pool.to_account_info().key.as_ref(),
+        &[nonce],
+    ];
+    let signer = &[&seeds[..]];
+
+    let pool_key = ctx.accounts.claim.pool.key();
+    let swap_seeds = &[
+        SWAP_AUTHORITY_SEED,
+        pool_key.as_ref(),
+        &[ctx.bumps.swap_authority],
+    ];
+
+    // The adapter only gets a signature over the rewards, paid into the
+    // scratch account first.
+    let claim = &ctx.accounts.claim;
+    let scratch_before = ctx.accounts.swap_source.amount;
+    let output_before = ctx.accounts.user_output_acc.amount;
+    pay_rewards(
+        &claim.token_program,
+        &claim.mint,
+        claim.vault.to_account_info(),
+        ctx.accounts.swap_source.to_account_info(),
+        claim.program_signer.to_account_info(),
+        ctx.remaining_accounts,
+        rewards,
+        signer,
+    )?;
+    ctx.accounts.swap_source.reload()?;
+    let amount_in = ctx.accounts.swap_source.amount.safe_sub(scratch_before)?;
+    invoke_swap(
+        &ctx.accounts.claim_adapter,
+        ctx.accounts.swap_authority.to_account_info(),
+        ctx.accounts.swap_source.to_account_info(),
+        ctx.accounts.user_output_acc.to_account_info(),
+        [
+            claim.mint.to_account_info(),
+            ctx.accounts.output_mint.to_account_info(),
+        ],
+        [
+            claim.token_program.to_account_info(),
+            ctx.accounts.output_token_program.to_account_info(),
+        ],
+        ctx.remaining_accounts,
+        amount_in,
+        min_out,
+        &[&swap_seeds[..]],
+    )?;
+    ctx.accounts.swap_source.reload()?;
+    ctx.accounts.user_output_acc.reload()?;
+
+    // Nothing but the rewards may leave the scratch account.
+    require_gte!(
+        ctx.accounts.swap_source.amount,
+        scratch_before,
+        PoolError::InvalidClaimAdapter
+    );
+    let amount_out = ctx
+        .accounts
+        .user_output_acc
+        .amount
+        .safe_sub(output_before)?;
+    require_gte!(amount_out, min_out, PoolError::SlippageExceeded);
+
+    emit!(RewardsClaimedAndSwapped {
+        pool: ctx.accounts.claim.pool.key(),
+        user: ctx.accounts.claim.user.key(),
+        amount,
+        output_mint: ctx.accounts.output_mint.key(),
+        amount_out,
+        label: ctx.accounts.claim.user_state.label,
+    });
+
+    let claim = &mut ctx.accounts.claim;
+    claim.vault.reload()?;
+    let pool = &mut claim.pool.load_mut()?;
+    assert_pool_invariants(pool, &claim.vault)?;
+    pool.publish_state()
+}
+
+fn handle_set_points_mode(ctx: Context<SetPointsMode>, enabled: bool) -> PoolResult {
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+    // Compressed positions are paid their rewards on every change.
+    require!(
+        !enabled || pool.compressed_tree == Pubkey::default(),
+        PoolError::CompressionEnabled
+    );
+
+    let old = pool.points_mode != 0;
+    pool.points_mode = enabled as u8;
+    record_config_change(
+        pool,
+        ctx.accounts.pool.key(),
+        ctx.accounts.config_change.as_mut(),
+        ConfigParam::PointsMode,
+        &old,
+        &enabled,
+        ctx.accounts.authority.key(),
+    )?;
+
+    Ok(())
+}
+
+fn handle_settle_points(ctx: Context<SettlePoints>) -> PoolResult {
+    let user_state = &mut ctx.accounts.user_state;
+
+    let points = user_state.points;
+    require!(points > 0, PoolError::ZeroAmount);
+
+    user_state.points = 0;
+    user_state.settled_points = user_state.settled_points.safe_add(points)?;
+
+    emit!(PointsSettled {
+        pool: ctx.accounts.pool.key(),
+        user: ctx.accounts.user.key(),
+        points,
+        settled_points: user_state.settled_points,
+        label: user_state.label,
+    });
+
+    Ok(())
+}
+
+fn handle_start_season(ctx: Context<StartSeason>, season: u32) -> PoolResult {
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+
+    require_eq!(
+        season,
+        pool.season.safe_add(1)?,
+        PoolError::InvalidLeaderboard
+    );
+
+    let now = Clock::get()?.unix_timestamp;
+    pool.update_reward_index(now)?;
+    pool.season = season;
+    pool.season_start_ts = now;
+
+    let leaderboard = &mut ctx.accounts.leaderboard;
+    leaderboard.pool = ctx.accounts.pool.key();
+    leaderboard.season = season;
+
+    Ok(())
+}
+
+fn handle_update_leaderboard(ctx: Context<UpdateLeaderboard>) -> PoolResult {
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+
+    require!(
+        ctx.accounts.user_state.initialized,
+        PoolError::UserNotInitialized
+    );
+
+    let now = Clock::get()?.unix_timestamp;
+    update_rewards(pool, &mut ctx.accounts.user_state, now)?;
+
+    record_on_leaderboard(
+        Some(&mut ctx.accounts.leaderboard),
+        ctx.accounts.pool.key(),
+        pool,
+        ctx.accounts.user.key(),
+        &ctx.accounts.user_state,
+        ctx.accounts.wallet_state.as_mut(),
+        now,
+    )
+}
+
+fn handle_set_vote_delegate(ctx: Context<SetVoteDelegate>, delegate: Option<Pubkey>) -> PoolResult {
+    ctx.accounts.user_state.vote_delegate = delegate.unwrap_or_default();
+
+    Ok(())
+}
+
+fn handle_set_guardian(
+    ctx: Context<SetGuardian>,
+    guardian: Option<Pubkey>,
+    threshold: u64,
+    delay: i64,
+) -> PoolResult {
+    let user_state = &mut ctx.accounts.user_state;
+
+    // A stolen wallet must not be able to simply drop the guardian.
+    if user_state.guardian != Pubkey::default() {
+        require!(
+            ctx.accounts
+                .guardian
+                .as_ref()
+                .is_some_and(|signer| signer.key() == user_state.guardian),
+            PoolError::GuardianApprovalRequired
+        );
+    }
+
+    match guardian {
+        Some(guardian) => {
+            require!(delay > 0, PoolError::InvalidGuardian);
+
+            user_state.guardian = guardian;
+            user_state.guardian_threshold = threshold;
+            user_state.guardian_delay = delay;
+        }
+        None => {
+            user_state.guardian = Pubkey::default();
+            user_state.guardian_threshold = 0;
+            user_state.guardian_delay = 0;
+        }
+    }
+    user_state.announced_amount = 0;
+
+    Ok(())
+}
+
+fn handle_announce_withdrawal(ctx: Context<AnnounceWithdrawal>, amount: u64) -> PoolResult {
+    let user_state = &mut ctx.accounts.user_state;
+
+    user_state.announced_amount = amount;
+    user_state.announced_ts = Clock::get()?.unix_timestamp;
+
+    Ok(())
+}
+
+fn handle_set_cold_storage(
+    ctx: Context<SetColdStorage>,
+    cold_key: Option<Pubkey>,
+    destination: Pubkey,
+    window: i64,
+) -> PoolResult {
+    let user_state = &mut ctx.accounts.user_state;
+
+    // Otherwise the hot key could just turn the cold key off.
+    if user_state.cold_key != Pubkey::default() {
+        require!(
+            ctx.accounts
+                .cold_key
+                .as_ref()
+                .is_some_and(|signer| signer.key() == user_state.cold_key),
+            PoolError::ColdApprovalRequired
+        );
+    }
+
+    match cold_key {
+        Some(cold_key) => {
+            require!(
+                cold_key != Pubkey::default() && destination != Pubkey::default() && window > 0,
+                PoolError::InvalidColdStorage
+            );
+
+            user_state.cold_key = cold_key;
+            user_state.cold_destination = destination;
+            user_state.cold_window = window;
+        }
+        None => {
+            user_state.cold_key = Pubkey::default();
+            user_state.cold_destination = Pubkey::default();
+            user_state.cold_window = 0;
+        }
+    }
+
+    // A pending request was made under the old settings.
+    user_state.cold_requested_amount = 0;
+    user_state.cold_requested_ts = 0;
+    user_state.cold_approved = false;
+
+    Ok(())
+}
+
+fn handle_request_cold_withdrawal(ctx: Context<RequestColdWithdrawal>, amount: u64) -> PoolResult {
+    let user_state = &mut ctx.accounts.user_state;
+    require!(
+        user_state.cold_key != Pubkey::default(),
+        PoolError::InvalidColdStorage
+    );
+    require!(amount > 0, PoolError::ZeroAmount);
+
+    let now = Clock::get()?.unix_timestamp;
+    user_state.cold_requested_amount = amount;
+    user_state.cold_requested_ts = now;
+    user_state.cold_approved = false;
+
+    emit!(ColdWithdrawalRequested {
+        pool: ctx.accounts.pool.key(),
+        user: ctx.accounts.authority.key(),
+        amount,
+        expires_ts: now.saturating_add(user_state.cold_window),
+        label: user_state.label,
+    });
+
+    Ok(())
+}
+
+fn handle_approve_cold_withdrawal(ctx: Context<ApproveColdWithdrawal>, amount: u64) -> PoolResult {
+    let user_state = &mut ctx.accounts.user_state;
+
+    // The amount is repeated so a request replaced after the cold key looked
+    // at it isn't approved by accident.
+    require!(
+        amount > 0
+            && amount == user_state.cold_requested_amount
+            && Clock::get()?.unix_timestamp
+                <= user_state
+                    .cold_requested_ts
+                    .saturating_add(user_state.cold_window),
+        PoolError::ColdApprovalRequired
+    );
+    user_state.cold_approved = true;
+
+    emit!(ColdWithdrawalApproved {
+        pool: ctx.accounts.pool.key(),
+        user: ctx.accounts.user.key(),
+        amount,
+        label: user_state.label,
+    });
+
+    Ok(())
+}
+
+fn handle_set_session_key(
+    ctx: Context<SetSessionKey>,
+    session_key: Option<Pubkey>,
+    expiry_slot: u64,
+) -> PoolResult {
+    let user_state = &mut ctx.accounts.user_state;
+
+    match session_key {
+        Some(session_key) => {
+            require!(
+                expiry_slot > Clock::get()?.slot,
+                PoolError::InvalidSessionKey
+            );
+
+            user_state.session_key = session_key;
+            user_state.session_expiry_slot = expiry_slot;
+        }
+        None => {
+            user_state.session_key = Pubkey::default();
+            user_state.session_expiry_slot = 0;
+        }
+    }
+
+    Ok(())
+}
+
+fn handle_get_voter_weight(ctx: Context<GetVoterWeight>) -> PoolResult<VoterWeight> {
+    let user_state = &ctx.accounts.user_state;
+    let (pool_key, user_key) = (ctx.accounts.pool.key(), ctx.accounts.user.key());
+
+    // The wallet's other positions, passed in increasing index order, add
+    // their stake.
+    let mut weight = user_state.staked_amount;
+    let mut last_index = user_state.index;
+    for info in ctx.remaining_accounts {
+        require_keys_eq!(*info.owner, crate::ID, PoolError::InvalidPosition);
+        let position = UserState::try_deserialize(&mut &info.try_borrow_data()?[..])?;
+        let (address, _) = Pubkey::find_program_address(
+            &[
+                pool_key.as_ref(),
+                user_key.as_ref(),
+                &position_seed(position.index),
+            ],
+            &crate::ID,
+        );
+        require!(
+            info.key() == address && position.index > last_index,
+            PoolError::InvalidPosition
+        );
+
+        last_index = position.index;
+        weight = weight.safe_add(position.staked_amount)?;
+    }
+
+    let voter = if user_state.vote_delegate == Pubkey::default() {
+        ctx.accounts.user.key()
+    } else {
+        user_state.vote_delegate
+    };
+
+    Ok(VoterWeight { voter, weight })
+}
+
+fn handle_set_checkpoints_enabled(
+    ctx: Context<SetCheckpointsEnabled>,
+    enabled: bool,
+) -> PoolResult {
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+
+    // The pool total's history has to exist before anything can be appended.
+    require!(
+        !enabled || pool.checkpoint_pages > 0,
+        PoolError::InvalidCheckpoints
+    );
+
+    let old = pool.checkpoints_enabled != 0;
+    pool.checkpoints_enabled = enabled as u8;
+    record_config_change(
+        pool,
+        ctx.accounts.pool.key(),
+        ctx.accounts.config_change.as_mut(),
+        ConfigParam::CheckpointsEnabled,
+        &old,
+        &enabled,
+        ctx.accounts.authority.key(),
+    )?;
+
+    Ok(())
+}
+
+/// Checkpoint pages and current stake of a user's history: the wallet's
+/// once it holds several positions, else its default position's.
+fn holder_pages(
+    user_state: Option<&Account<UserState>>,
+    wallet_state: Option<&Account<WalletState>>,
+) -> PoolResult<(u32, u64)> {
+    let user_state = user_state.ok_or(PoolError::InvalidCheckpoints)?;
+    require!(
+        wallet_state.is_some() == user_state.wallet_tracked,
+        PoolError::InvalidWalletState
+    );
+
+    Ok(match wallet_state {
+        Some(wallet_state) => (wallet_state.checkpoint_pages, wallet_state.staked_amount),
+        None => (user_state.checkpoint_pages, user_state.staked_amount),
+    })
+}
+
+fn handle_open_checkpoint_page(ctx: Context<OpenCheckpointPage>, page: u32) -> PoolResult {
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+    let pool_key = ctx.accounts.pool.key();
+    let holder = ctx.accounts.holder.key();
+
+    let (pages, amount) = if holder == pool_key {
+        (pool.checkpoint_pages, pool.staked_total)
+    } else {
+        holder_pages(
+            ctx.accounts.user_state.as_ref(),
+            ctx.accounts.wallet_state.as_ref(),
+        )?
+    };
+
+    require_eq!(page, pages, PoolError::InvalidCheckpoints);
+
+    if page > 0 {
+        let previous = &ctx
+            .accounts
+            .previous_checkpoints
+            .as_ref()
+            .ok_or(PoolError::InvalidCheckpoints)?
+            .load()?;
+
+        require!(
+            previous.pool == pool_key
+                && previous.holder == holder
+                && previous.page + 1 == page
+                && previous.len as usize == CHECKPOINTS_PER_PAGE,
+            PoolError::InvalidCheckpoints
+        );
+    }
+
+    let checkpoints = &mut ctx.accounts.checkpoints.load_init()?;
+    checkpoints.pool = pool_key;
+    checkpoints.holder = holder;
+    checkpoints.page = page;
+
+    if page == 0 {
+        // History starts with whatever is staked when tracking begins.
+        checkpoints.push(Clock::get()?.slot, amount)?;
+    }
+
+    if holder == pool_key {
+        pool.checkpoint_pages += 1;
+    } else if let Some(wallet_state) = ctx.accounts.wallet_state.as_mut() {
+        wallet_state.checkpoint_pages += 1;
+    } else if let Some(user_state) = ctx.accounts.user_state.as_mut() {
+        user_state.checkpoint_pages += 1;
+    }
+
+    Ok(())
+}
+
+fn handle_get_voting_power_at(ctx: Context<GetVotingPowerAt>, slot: u64) -> PoolResult<u64> {
+    let pool = &ctx.accounts.pool.load()?;
+    let pool_key = ctx.accounts.pool.key();
+    let holder = ctx.accounts.holder.key();
+
+    let pages = if holder == pool_key {
+        pool.checkpoint_pages
+    } else {
+        holder_pages(
+            ctx.accounts.user_state.as_ref(),
+            ctx.accounts.wallet_state.as_ref(),
+        )?
+        .0
+    };
+
+    let checkpoints = &ctx.accounts.checkpoints.load()?;
+    require!(
+        checkpoints.pool == pool_key && checkpoints.holder == holder,
+        PoolError::InvalidCheckpoints
+    );
+
+    // Unless this is the last page, the next one must show that nothing
+    // newer applies to `slot`.
+    if checkpoints.page + 1 < pages {
+        let next = &ctx
+            .accounts
+            .next_checkpoints
+            .as_ref()
+            .ok_or(PoolError::InvalidCheckpoints)?
+            .load()?;
+
+        require!(
+            next.pool == pool_key
+                && next.holder == holder
+                && next.page == checkpoints.page + 1
+                && next.amount_at(slot).is_none(),
+            PoolError::InvalidCheckpoints
+        );
+    }
+
+    match checkpoints.amount_at(slot) {
+        Some(amount) => Ok(amount),
+        None => {
+            // Only the first page may predate the queried slot.
+            require_eq!(checkpoints.page, 0, PoolError::InvalidCheckpoints);
+            Ok(0)
+        }
+    }
+}
+
+fn handle_set_swap_adapter(
+    ctx: Context<SetSwapAdapter>,
+    swap_adapter: Option<Pubkey>,
+) -> PoolResult {
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+
+    pool.swap_adapter_target = Pubkey::default();
+    pool.swap_adapter_eta = 0;
+
+    // A new adapter gets the vault's swaps, so it waits out the timelock;
+    // turning swaps off can't hurt anyone and takes effect right away.
+    if let Some(swap_adapter) = swap_adapter {
+        require!(
+            swap_adapter != Pubkey::default(),
+            PoolError::InvalidSwapAdapter
+        );
+        let eta = Clock::get()?
+            .unix_timestamp
+            .checked_add(ADAPTER_CHANGE_DELAY)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        pool.swap_adapter_target = swap_adapter;
+        pool.swap_adapter_eta = eta;
+
+        emit!(SwapAdapterProposed {
+            pool: ctx.accounts.pool.key(),
+            swap_adapter,
+            eta,
+        });
+
+        return Ok(());
+    }
+
+    apply_swap_adapter(
+        pool,
+        ctx.accounts.pool.key(),
+        ctx.accounts.config_change.as_mut(),
+        Pubkey::default(),
+        ctx.accounts.authority.key(),
+    )
+}
+
+fn handle_apply_swap_adapter(ctx: Context<SetSwapAdapter>) -> PoolResult {
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+    require!(
+        pool.swap_adapter_eta != 0,
+        PoolError::NoPendingAdapterChange
+    );
+    require!(
+        Clock::get()?.unix_timestamp >= pool.swap_adapter_eta,
+        PoolError::AdapterChangeTimelocked
+    );
+
+    let new = std::mem::take(&mut pool.swap_adapter_target);
+    pool.swap_adapter_eta = 0;
+    apply_swap_adapter(
+        pool,
+        ctx.accounts.pool.key(),
+        ctx.accounts.config_change.as_mut(),
+        new,
+        ctx.accounts.authority.key(),
+    )
+}
+
+fn apply_swap_adapter(
+    pool: &mut Pool,
+    pool_key: Pubkey,
+    config_change: Option<&mut Account<ConfigChange>>,
+    new: Pubkey,
+    authority: Pubkey,
+) -> PoolResult {
+    let old = pool.swap_adapter;
+    pool.swap_adapter = new;
+    record_config_change(
+        pool,
+        pool_key,
+        config_change,
+        ConfigParam::SwapAdapter,
+        &old,
+        &new,
+        authority,
+    )
+}
+
+fn handle_set_claim_adapter(
+    ctx: Context<SetClaimAdapter>,
+    claim_adapter: Option<Pubkey>,
+    output_mint: Pubkey,
+) -> PoolResult {
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+
+    pool.claim_adapter_target = Pubkey::default();
+    pool.claim_output_mint_target = Pubkey::default();
+    pool.claim_adapter_eta = 0;
+
+    // Same timelock as `set_swap_adapter`.
+    if let Some(claim_adapter) = claim_adapter {
+        require!(
+            claim_adapter != Pubkey::default()
+                && output_mint != Pubkey::default()
+                && output_mint != pool.mint,
+            PoolError::InvalidClaimAdapter
+        );
+        let eta = Clock::get()?
+            .unix_timestamp
+            .checked_add(ADAPTER_CHANGE_DELAY)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        pool.claim_adapter_target = claim_adapter;
+        pool.claim_output_mint_target = output_mint;
+        pool.claim_adapter_eta = eta;
+
+        emit!(ClaimAdapterProposed {
+            pool: ctx.accounts.pool.key(),
+            claim_adapter,
+            output_mint,
+            eta,
+        });
+
+        return Ok(());
+    }
+
+    apply_claim_adapter(
+        pool,
+        ctx.accounts.pool.key(),
+        ctx.accounts.config_change.as_mut(),
+        Default::default(),
+        ctx.accounts.authority.key(),
+    )
+}
+
+fn handle_apply_claim_adapter(ctx: Context<SetClaimAdapter>) -> PoolResult {
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+    require!(
+        pool.claim_adapter_eta != 0,
+        PoolError::NoPendingAdapterChange
+    );
+    require!(
+        Clock::get()?.unix_timestamp >= pool.claim_adapter_eta,
+        PoolError::AdapterChangeTimelocked
+    );
+
+    let new = (
+        std::mem::take(&mut pool.claim_adapter_target),
+        std::mem::take(&mut pool.claim_output_mint_target),
+    );
+    pool.claim_adapter_eta = 0;
+    apply_claim_adapter(
+        pool,
+        ctx.accounts.pool.key(),
+        ctx.accounts.config_change.as_mut(),
+        new,
+        ctx.accounts.authority.key(),
+    )
+}
+
+fn apply_claim_adapter(
+    pool: &mut Pool,
+    pool_key: Pubkey,
+    config_change: Option<&mut Account<ConfigChange>>,
+    new: (Pubkey, Pubkey),
+    authority: Pubkey,
+) -> PoolResult {
+    let old = (pool.claim_adapter, pool.claim_output_mint);
+    (pool.claim_adapter, pool.claim_output_mint) = new;
+    record_config_change(
+        pool,
+        pool_key,
+        config_change,
+        ConfigParam::ClaimAdapter,
+        &old,
+        &new,
+        authority,
+    )
+}
+
+fn handle_set_achievement_uri(ctx: Context<SetAchievementUri>, base_uri: String) -> PoolResult {
+    require!(
+        base_uri.len() <= MAX_ACHIEVEMENT_URI_LEN,
+        PoolError::InvalidPoolConfig
+    );
+
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+    let config = &mut ctx.accounts.achievement_config;
+    config.pool = ctx.accounts.pool.key();
+
+    let old = std::mem::replace(&mut config.base_uri, base_uri);
+    record_config_change(
+        pool,
+        ctx.accounts.pool.key(),
+        ctx.accounts.config_change.as_mut(),
+        ConfigParam::AchievementUri,
+        &old,
+        &config.base_uri,
+        ctx.accounts.authority.key(),
+    )?;
+
+    Ok(())
+}
+
+fn handle_mint_achievement(ctx: Context<MintAchievement>, achievement: Achievement) -> PoolResult {
+    let pool_key = ctx.accounts.pool.key();
+    let user = ctx.accounts.user.key();
+    let (mint, nonce) = {
+        let pool = ctx.accounts.pool.load()?;
+        let user_state = &ctx.accounts.user_state;
+        require!(user_state.initialized, PoolError::UserNotInitialized);
+
+        let now = Clock::get()?.unix_timestamp;
+        let earned = match achievement {
+            Achievement::FirstStake => user_state.staked_amount > 0,
+            Achievement::OneYear => {
+                user_state.staked_amount > 0
+                    && user_state.streak_start_ts > 0
+                    && now.saturating_sub(user_state.streak_start_ts) >= SECONDS_PER_YEAR as i64
+            }
+            Achievement::Leaderboard => ctx.accounts.leaderboard.as_ref().is_some_and(|board| {
+                board.pool == pool_key
+                    && board.season == pool.season
+                    && board.entries.iter().any(|entry| entry.user == user)
+            }),
+        };
+        let minted = &mut ctx.accounts.wallet_achievements;
+        require!(
+            earned && minted.achievements & achievement.bit() == 0,
+            PoolError::AchievementUnavailable
+        );
+        minted.pool = pool_key;
+        minted.wallet = user;
+        minted.achievements |= achievement.bit();
+
+        (pool.mint, pool.nonce)
+    };
+
+    let seeds = &[mint.as_ref(), pool_key.as_ref(), &[nonce]];
+    let signer = &[&seeds[..]];
+
+    let accounts = &ctx.accounts;
+    mint_rewards(
+        &accounts.nft_token_program,
+        &accounts.achievement_mint,
+        accounts.user_nft_acc.to_account_info(),
+        accounts.program_signer.to_account_info(),
+        1,
+        signer,
+    )?;
+
+    // `CreateMetadataAccountV3` is instruction 33: name, symbol, URI, no
+    // royalties, the program signer as sole verified creator, no collection
+    // or uses, immutable.
+    let creators = Some(vec![(accounts.program_signer.key(), true, 100u8)]);
+    let uri = format!(
+        "{}{}.json",
+        accounts.achievement_config.base_uri,
+        achievement.slug()
+    );
+    let mut data = vec![33u8];
+    data.extend_from_slice(
+        &(
+            achievement.name().to_string(),
+            ACHIEVEMENT_SYMBOL.to_string(),
+            uri,
+            0u16,
+            creators,
+            None::<u8>,
+            None::<u8>,
+            false,
+            None::<u8>,
+        )
+            .try_to_vec()?,
+    );
+    invoke_signed(
+        &Instruction {
+            program_id: TOKEN_METADATA_PROGRAM_ID,
+            accounts: vec![
+                AccountMeta::new(accounts.metadata.key(), false),
+                AccountMeta::new_readonly(accounts.achievement_mint.key(), false),
+                AccountMeta::new_readonly(accounts.program_signer.key(), true),
+                AccountMeta::new(accounts.user.key(), true),
+                AccountMeta::new_readonly(accounts.program_signer.key(), true),
+                AccountMeta::new_readonly(accounts.system_program.key(), false),
+            ],
+            data,
+        },
+        &[
+            accounts.metadata.to_account_info(),
+            accounts.achievement_mint.to_account_info(),
+            accounts.program_signer.to_account_info(),
+            accounts.user.to_account_info(),
+            accounts.system_program.to_account_info(),
+        ],
+        signer,
+    )?;
+
+    // `CreateMasterEditionV3` is instruction 17. A max supply of 0 makes
+    // this the only token, and takes over the mint and freeze authority.
+    let mut data = vec![17u8];
+    data.extend_from_slice(&Some(0u64).try_to_vec()?);
+    invoke_signed(
+        &Instruction {
+            program_id: TOKEN_METADATA_PROGRAM_ID,
+            accounts: vec![
+                AccountMeta::new(accounts.master_edition.key(), false),
+                AccountMeta::new(accounts.achievement_mint.key(), false),
+                AccountMeta::new_readonly(accounts.program_signer.key(), true),
+                AccountMeta::new_readonly(accounts.program_signer.key(), true),
+                AccountMeta::new(accounts.user.key(), true),
+                AccountMeta::new(accounts.metadata.key(), false),
+                AccountMeta::new_readonly(accounts.nft_token_program.key(), false),
+                AccountMeta::new_readonly(accounts.system_program.key(), false),
+            ],
+            data,
+        },
+        &[
+            accounts.master_edition.to_account_info(),
+            accounts.achievement_mint.to_account_info(),
+            accounts.program_signer.to_account_info(),
+            accounts.user.to_account_info(),
+            accounts.metadata.to_account_info(),
+            accounts.nft_token_program.to_account_info(),
+            accounts.system_program.to_account_info(),
+        ],
+        signer,
+    )?;
+
+    emit!(AchievementMinted {
+        pool: pool_key,
+        user,
+        achievement,
+        mint: accounts.achievement_mint.key(),
+        label: accounts.user_state.label,
+    });
+
+    Ok(())
+}
+
+fn handle_enable_compression(
+    ctx: Context<EnableCompression>,
+    max_depth: u32,
+    max_buffer_size: u32,
+) -> PoolResult {
+    let (mint, nonce) = {
+        let pool = ctx.accounts.pool.load()?;
+        require!(
+            pool.compressed_tree == Pubkey::default(),
+            PoolError::CompressionEnabled
+        );
+        // Compressed positions skip fees, locks, checkpoints and points.
+        require!(
+            pool.config_enabled == 0 && pool.points_mode == 0,
+            PoolError::CompressionDisabled
+        );
+        (pool.mint, pool.nonce)
+    };
+    let pool_key = ctx.accounts.pool.key();
+    let seeds = &[mint.as_ref(), pool_key.as_ref(), &[nonce]];
+
+    invoke_compression(
+        [
+            ctx.accounts.compression_program.to_account_info(),
+            ctx.accounts.merkle_tree.to_account_info(),
+            ctx.accounts.program_signer.to_account_info(),
+            ctx.accounts.noop_program.to_account_info(),
+        ],
+        &[],
+        "init_empty_merkle_tree",
+        &(max_depth, max_buffer_size).try_to_vec()?,
+        &[&seeds[..]],
+    )?;
+
+    ctx.accounts.pool.load_mut()?.compressed_tree = ctx.accounts.merkle_tree.key();
+
+    Ok(())
+}
+
+fn handle_open_compressed_position(ctx: Context<OpenCompressedPosition>) -> PoolResult {
+    let pool_key = ctx.accounts.pool.key();
+    let owner = ctx.accounts.authority.key();
+
+    let (mint, nonce, index) = {
+        let pool = &mut ctx.accounts.pool.load_mut()?;
+        let index =
+            u32::try_from(pool.compressed_leaves).map_err(|_| ProgramError::ArithmeticOverflow)?;
+        pool.compressed_leaves = pool.compressed_leaves.safe_add(1)?;
+        (pool.mint, pool.nonce, index)
+    };
+    let seeds = &[mint.as_ref(), pool_key.as_ref(), &[nonce]];
+
+    let leaf = CompressedPosition::default().leaf(&pool_key, &owner);
+    invoke_compression(
+        [
+            ctx.accounts.compression_program.to_account_info(),
+            ctx.accounts.merkle_tree.to_account_info(),
+            ctx.accounts.program_signer.to_account_info(),
+            ctx.accounts.noop_program.to_account_info(),
+        ],
+        &[],
+        "append",
+        &leaf,
+        &[&seeds[..]],
+    )?;
+
+    emit!(CompressedPositionOpened {
+        pool: pool_key,
+        owner,
+        index,
+    });
+
+    Ok(())
+}
+
+/// Moves `position` at `index` to `staked_amount`, replacing its leaf under
+/// `root`, and returns the rewards it earned. Unless `settle`, the rewards
+/// are left in the position and none are returned. The pool's index must
+/// already be up to date.
+fn update_compressed_position<'info>(
+    ctx: &Context<'_, '_, '_, 'info, CompressedStake<'info>>,
+    pool: &Pool,
+    root: [u8; 32],
+    index: u32,
+    position: CompressedPosition,
+    staked_amount: u64,
+    settle: bool,
+) -> PoolResult<u64> {
+    let pool_key = ctx.accounts.pool.key();
+    let owner = ctx.accounts.authority.key();
+
+    let (rewards, reward_per_token_paid) = match settle {
+        true => (position.earned(pool)?, pool.reward_per_token),
+        false => (0, position.reward_per_token_paid),
+    };
+    let updated = CompressedPosition {
+        staked_amount,
+        reward_per_token_paid,
+    };
+
+    let seeds = &[pool.mint.as_ref(), pool_key.as_ref(), &[pool.nonce]];
+    let mut args = root.to_vec();
+    args.extend_from_slice(&position.leaf(&pool_key, &owner));
+    args.extend_from_slice(&updated.leaf(&pool_key, &owner));
+    args.extend_from_slice(&index.to_le_bytes());
+
+    // The compression program rejects the call unless the old leaf is
+    // proven at `index`.
+    invoke_compression(
+        [
+            ctx.accounts.compression_program.to_account_info(),
+            ctx.accounts.merkle_tree.to_account_info(),
+            ctx.accounts.program_signer.to_account_info(),
+            ctx.accounts.noop_program.to_account_info(),
+        ],
+        ctx.remaining_accounts,
+        "replace_leaf",
+        &args,
+        &[&seeds[..]],
+    )?;
+
+    emit!(CompressedPositionChanged {
+        pool: pool_key,
+        owner,
+        index,
+        position: updated,
+    });
+
+    Ok(rewards)
+}
+
+fn handle_compressed_stake<'info>(
+    ctx: Context<'_, '_, '_, 'info, CompressedStake<'info>>,
+    amount: u64,
+    root: [u8; 32],
+    index: u32,
+    position: CompressedPosition,
+) -> PoolResult {
+    require!(amount > 0, PoolError::ZeroAmount);
+
+    // Only what actually lands in the vault is credited.
+    let received = amount.safe_sub(transfer_fee(&ctx.accounts.mint, amount)?)?;
+
+    let (rewards, mint, nonce) = {
+        let pool = &mut ctx.accounts.pool.load_mut()?;
+        pool.require_enabled(instruction_flags::ENTER_STAKING)?;
+        require_memo(pool, ctx.accounts.instructions.as_ref())?;
+        require!(pool.paused == 0, PoolError::PoolPaused);
+        require!(pool.incident_reason == 0, PoolError::IncidentActive);
+        require!(pool.haircut == 0, PoolError::ShortfallSettled);
+        // A config added after compressed mode was enabled would go unapplied.
+        require!(pool.config_enabled == 0, PoolError::CompressionDisabled);
+        // Leaves have nowhere to keep points.
+        require!(pool.points_mode == 0, PoolError::CompressionDisabled);
+        // Compressed positions have nowhere to record an acceptance.
+        require!(pool.terms_hash == [0; 32], PoolError::TermsNotAccepted);
+
+        pool.update_reward_index(Clock::get()?.unix_timestamp)?;
+        let rewards = update_compressed_position(
+            &ctx,
+            pool,
+            root,
+            index,
+            position,
+            position.staked_amount.safe_add(received)?,
+            true,
+        )?;
+        pool.staked_total = pool.staked_total.safe_add(received)?;
+        pool.record_distribution(rewards)?;
+
+        (pool.split_rewards(rewards)?, pool.mint, pool.nonce)
+    };
+
+    let pool_key = ctx.accounts.pool.key();
+    let seeds = &[mint.as_ref(), pool_key.as_ref(), &[nonce]];
+    transfer_checked_with_hook(
+        &ctx.accounts.token_program,
+        ctx.accounts.user_mint_acc.to_account_info(),
+        &ctx.accounts.mint,
+        ctx.accounts.vault.to_account_info(),
+        ctx.accounts.authority.to_account_info(),
+        &[],
+        amount,
+        &[],
+    )?;
+    pay_rewards(
+        &ctx.accounts.token_program,
+        &ctx.accounts.mint,
+        ctx.accounts.vault.to_account_info(),
+        ctx.accounts.user_mint_acc.to_account_info(),
+        ctx.accounts.program_signer.to_account_info(),
+        &[],
+        rewards,
+        &[&seeds[..]],
+    )?;
+
+    ctx.accounts.vault.reload()?;
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+    assert_pool_invariants(pool, &ctx.accounts.vault)?;
+    pool.publish_state()
+}
+
+fn handle_compressed_unstake<'info>(
+    ctx: Context<'_, '_, '_, 'info, CompressedStake<'info>>,
+    amount: u64,
+    root: [u8; 32],
+    index: u32,
+    position: CompressedPosition,
+) -> PoolResult {
+    require!(amount > 0, PoolError::ZeroAmount);
+
+    let (payout, rewards, mint, nonce, incident) = {
+        let pool = &mut ctx.accounts.pool.load_mut()?;
+        pool.require_enabled(instruction_flags::LEAVE_STAKING)?;
+        if let Some(memo) = require_memo(pool, ctx.accounts.instructions.as_ref())? {
+            forward_memo(ctx.accounts.memo_program.as_ref(), &memo)?;
+        }
+        require!(pool.points_mode == 0, PoolError::CompressionDisabled);
+
+        // Compressed positions have no user state for `emergency_withdraw`,
+        // so during an incident they take its pro-rata exit here.
+        let incident = match pool.incident_reason {
+            0 => None,
+            _ => Some((pool.reserves(&ctx.accounts.vault)?, pool.liabilities()?)),
+        };
+        let payout = if incident.is_some() {
+            pool.emergency_payout(&ctx.accounts.vault, amount)?
+        } else {
+            require!(pool.reserve_alarm == 0, PoolError::ReserveAlarm);
+            // Queued withdrawals are paid out before instant ones.
+            require_gte!(
+                ctx.accounts.vault.amount,
+                pool.written_down(pool.queued_amount.safe_add(amount)?)?,
+                PoolError::InsufficientBuffer
+            );
+            pool.written_down(amount)?
+        };
+
+        // Like `emergency_withdraw`, an incident pays no rewards. They stay
+        // with what is left in the position, those on the withdrawn stake
+        // are given up with it.
+        pool.update_reward_index(Clock::get()?.unix_timestamp)?;
+        let rewards = update_compressed_position(
+            &ctx,
+            pool,
+            root,
+            index,
+            position,
+            position.staked_amount.safe_sub(amount)?,
+            incident.is_none(),
+        )?;
+        pool.staked_total = pool.staked_total.safe_sub(amount)?;
+        pool.record_distribution(rewards)?;
+
+        (
+            payout,
+            pool.split_rewards(rewards)?,
+            pool.mint,
+            pool.nonce,
+            incident,
+        )
+    };
+
+    let pool_key = ctx.accounts.pool.key();
+    let seeds = &[mint.as_ref(), pool_key.as_ref(), &[nonce]];
+    let signer = &[&seeds[..]];
+    transfer_checked_with_hook(
+        &ctx.accounts.token_program,
+        ctx.accounts.vault.to_account_info(),
+        &ctx.accounts.mint,
+        ctx.accounts.user_mint_acc.to_account_info(),
+        ctx.accounts.program_signer.to_account_info(),
+        &[],
+        payout,
+        signer,
+    )?;
+    pay_rewards(
+        &ctx.accounts.token_program,
+        &ctx.accounts.mint,
+        ctx.accounts.vault.to_account_info(),
+        ctx.accounts.user_mint_acc.to_account_info(),
+        ctx.accounts.program_signer.to_account_info(),
+        &[],
+        rewards,
+        signer,
+    )?;
+
+    ctx.accounts.vault.reload()?;
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+    match incident {
+        Some(before) => assert_exit_invariants(pool, &ctx.accounts.vault, before)?,
+        None => assert_pool_invariants(pool, &ctx.accounts.vault)?,
+    }
+    pool.publish_state()
+}
+
+fn handle_stake_grant(ctx: Context<StakeGrant>, beneficiary: Pubkey, amount: u64) -> PoolResult {
+    require!(amount > 0, PoolError::ZeroAmount);
+
+    // Only what actually lands in the vault is credited.
+    let received = amount.safe_sub(transfer_fee(&ctx.accounts.mint, amount)?)?;
+
+    {
+        let pool = &mut ctx.accounts.pool.load_mut()?;
+        pool.require_enabled(instruction_flags::ENTER_STAKING)?;
+        // Carries no memo.
+        require!(pool.memo_required == 0, PoolError::MemoRequired);
+        require!(pool.paused == 0, PoolError::PoolPaused);
+        require!(pool.incident_reason == 0, PoolError::IncidentActive);
+        require!(pool.haircut == 0, PoolError::ShortfallSettled);
+
+        pool.update_reward_index(Clock::get()?.unix_timestamp)?;
+        pool.staked_total = pool.staked_total.safe_add(received)?;
+
+        let grant = &mut ctx.accounts.grant;
+        grant.pool = ctx.accounts.pool.key();
+        grant.beneficiary = beneficiary;
+        grant.treasury = ctx.accounts.treasury.key();
+        grant.staked_amount = received;
+        grant.reward_per_token_paid = pool.reward_per_token;
+    }
+
+    transfer_checked_with_hook(
+        &ctx.accounts.token_program,
+        ctx.accounts.authority_mint_acc.to_account_info(),
+        &ctx.accounts.mint,
+        ctx.accounts.vault.to_account_info(),
+        ctx.accounts.authority.to_account_info(),
+        &[],
+        amount,
+        &[],
+    )?;
+
+    emit!(GrantStaked {
+        pool: ctx.accounts.pool.key(),
+        beneficiary,
+        treasury: ctx.accounts.treasury.key(),
+        amount: received,
+    });
+
+    ctx.accounts.vault.reload()?;
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+    assert_pool_invariants(pool, &ctx.accounts.vault)?;
+    pool.publish_state()
+}
+
+fn handle_release_grant(ctx: Context<ReleaseGrant>) -> PoolResult {
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+    let grant = &ctx.accounts.grant;
+    let user_state = &mut ctx.accounts.user_state;
+    require!(user_state.initialized, PoolError::UserNotInitialized);
+    let (_, unwound) = wallet_stake(user_state, ctx.accounts.wallet_state.as_ref())?;
+    require!(!unwound, PoolError::UserUnwound);
+
+    update_rewards(pool, user_state, Clock::get()?.unix_timestamp)?;
+
+    // The stake was already counted in the pool total.
+    let rewards = grant.earned(pool)?;
+    let before = user_state.staked_amount;
+    user_state.staked_amount = before.safe_add(grant.staked_amount)?;
+    book_wallet_stake(pool, user_state, ctx.accounts.wallet_state.as_mut(), before)?;
+    user_state.pending_rewards = user_state.pending_rewards.safe_add(rewards)?;
+
+    record_checkpoints(
+        pool,
+        ctx.accounts.pool.key(),
+        user_state,
+        ctx.accounts.wallet_state.as_ref(),
+        grant.beneficiary,
+        ctx.accounts.user_checkpoints.as_ref(),
+        ctx.accounts.pool_checkpoints.as_ref(),
+    )?;
+
+    emit!(GrantReleased {
+        pool: grant.pool,
+        beneficiary: grant.beneficiary,
+        amount: grant.staked_amount,
+        rewards,
+    });
+
+    Ok(())
+}
+
+fn handle_clawback_grant(ctx: Context<ClawbackGrant>) -> PoolResult {
+    let grant = &ctx.accounts.grant;
+
+    let (payout, rewards, mint, nonce) = {
+        let pool = &mut ctx.accounts.pool.load_mut()?;
+        // Carries no memo.
+        require!(pool.memo_required == 0, PoolError::MemoRequired);
+        require!(pool.incident_reason == 0, PoolError::IncidentActive);
+
+        // Queued withdrawals are paid out before instant ones.
+        require_gte!(
+            ctx.accounts.vault.amount,
+            pool.written_down(pool.queued_amount.safe_add(grant.staked_amount)?)?,
+            PoolError::InsufficientBuffer
+        );
+
+        pool.update_reward_index(Clock::get()?.unix_timestamp)?;
+        let rewards = grant.earned(pool)?;
+        pool.staked_total = pool.staked_total.safe_sub(grant.staked_amount)?;
+        pool.record_distribution(rewards)?;
+
+        (
+            pool.written_down(grant.staked_amount)?,
+            pool.split_rewards(rewards)?,
+            pool.mint,
+            pool.nonce,
+        )
+    };
+
+    let pool_key = ctx.accounts.pool.key();
+    let seeds = &[mint.as_ref(), pool_key.as_ref(), &[nonce]];
+    let signer = &[&seeds[..]];
+    transfer_checked_with_hook(
+        &ctx.accounts.token_program,
+        ctx.accounts.vault.to_account_info(),
+        &ctx.accounts.mint,
+        ctx.accounts.treasury.to_account_info(),
+        ctx.accounts.program_signer.to_account_info(),
+        &[],
+        payout,
+        signer,
+    )?;
+    pay_rewards(
+        &ctx.accounts.token_program,
+        &ctx.accounts.mint,
+        ctx.accounts.vault.to_account_info(),
+        ctx.accounts.treasury.to_account_info(),
+        ctx.accounts.program_signer.to_account_info(),
+        &[],
+        rewards,
+        signer,
+    )?;
+
+    emit!(GrantClawedBack {
+        pool: pool_key,
+        beneficiary: grant.beneficiary,
+        treasury: grant.treasury,
+        amount: payout,
+        rewards: rewards.0.safe_add(rewards.1)?,
+    });
+
+    ctx.accounts.vault.reload()?;
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+    assert_pool_invariants(pool, &ctx.accounts.vault)?;
+    pool.publish_state()
+}
+
+fn handle_publish_state(ctx: Context<PublishState>) -> PoolResult {
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+
+    pool.update_reward_index(Clock::get()?.unix_timestamp)?;
+    assert_pool_invariants(pool, &ctx.accounts.vault)?;
+    pool.publish_state()?;
+    record_rate(ctx.accounts.rate_publisher.as_mut(), pool)
+}
+
+fn handle_initialize_rate_publisher(ctx: Context<InitializeRatePublisher>) -> PoolResult {
+    let pool = ctx.accounts.pool.load()?;
+    let clock = Clock::get()?;
+
+    let rate = pool.exchange_rate()?;
+    let publisher = &mut ctx.accounts.rate_publisher;
+    publisher.pool = ctx.accounts.pool.key();
+    publisher.rate = rate;
+    publisher.twap = rate;
+    publisher.slot = clock.slot;
+    publisher.updated_ts = clock.unix_timestamp;
+
+    Ok(())
+}
+
+/// Refreshes the pool's rate publisher, if passed.
+fn record_rate(rate_publisher: Option<&mut Account<RatePublisher>>, pool: &Pool) -> PoolResult {
+    match rate_publisher {
+        Some(publisher) => publisher.update(pool),
+        None => Ok(()),
+    }
+}
+
+#[cfg(feature = "wormhole")]
+fn handle_attest_stake(ctx: Context<AttestStake>, nonce: u32) -> PoolResult {
+    use anchor_lang::system_program::{transfer, Transfer};
+
+    let now = Clock::get()?.unix_timestamp;
+    let (attestation, attestation_fee) = {
+        let pool = &mut ctx.accounts.pool.load_mut()?;
+        let user_state = &mut ctx.accounts.user_state;
+        require!(user_state.initialized, PoolError::UserNotInitialized);
+
+        update_rewards(pool, user_state, now)?;
+
+        let attestation = StakeAttestation {
+            pool: ctx.accounts.pool.key(),
+            user: ctx.accounts.authority.key(),
+            staked_amount: user_state.staked_amount,
+            pending_rewards: user_state.pending_rewards,
+            timestamp: now,
+        };
+
+        (attestation, pool.attestation_fee)
+    };
+
+    if attestation_fee > 0 {
+        transfer(
+            CpiContext::new(
+                ctx.accounts.system_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.payer.to_account_info(),
+                    to: ctx.accounts.pool_authority.to_account_info(),
+                },
+            ),
+            attestation_fee,
+        )?;
+    }
+
+    // The bridge's fee follows a u32 guardian set index, a u64 lamport
+    // balance and a u32 guardian set expiration in its config.
+    let fee = {
+        let data = ctx.accounts.wormhole_bridge.try_borrow_data()?;
+        let bytes = data.get(16..24).ok_or(ProgramError::InvalidAccountData)?;
+        u64::from_le_bytes(bytes.try_into().unwrap())
+    };
+    if fee > 0 {
+        transfer(
+            CpiContext::new(
+                ctx.accounts.system_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.payer.to_account_info(),
+                    to: ctx.accounts.wormhole_fee_collector.to_account_info(),
+                },
+            ),
+            fee,
+        )?;
+    }
+
+    // `PostMessage` is the bridge's instruction 1; 1 asks for finality.
+    let mut data = vec![1u8];
+    data.extend_from_slice(&(nonce, attestation.try_to_vec()?, 1u8).try_to_vec()?);
+
+    let accounts = &ctx.accounts;
+    invoke_signed(
+        &Instruction {
+            program_id: WORMHOLE_PROGRAM_ID,
+            accounts: vec![
+                AccountMeta::new(accounts.wormhole_bridge.key(), false),
+                AccountMeta::new(accounts.wormhole_message.key(), true),
+                AccountMeta::new_readonly(accounts.emitter.key(), true),
+                AccountMeta::new(accounts.wormhole_sequence.key(), false),
+                AccountMeta::new(accounts.payer.key(), true),
+                AccountMeta::new(accounts.wormhole_fee_collector.key(), false),
+                AccountMeta::new_readonly(accounts.clock.key(), false),
+                AccountMeta::new_readonly(accounts.system_program.key(), false),
+                AccountMeta::new_readonly(accounts.rent.key(), false),
+            ],
+            data,
+        },
+        &[
+            accounts.wormhole_bridge.to_account_info(),
+            accounts.wormhole_message.to_account_info(),
+            accounts.emitter.to_account_info(),
+            accounts.wormhole_sequence.to_account_info(),
+            accounts.payer.to_account_info(),
+            accounts.wormhole_fee_collector.to_account_info(),
+            accounts.clock.to_account_info(),
+            accounts.system_program.to_account_info(),
+            accounts.rent.to_account_info(),
+        ],
+        &[&[EMITTER_SEED, &[ctx.bumps.emitter]]],
+    )?;
+
+    Ok(())
+}
+
+fn handle_set_reward_stream(
+    ctx: Context<SetRewardStream>,
+    stream_program: Option<Pubkey>,
+    stream: Pubkey,
+) -> PoolResult {
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+
+    let old = (pool.reward_stream_program, pool.reward_stream);
+    let new = match stream_program {
+        Some(program) => {
+            require!(stream != Pubkey::default(), PoolError::InvalidRewardStream);
+            (program, stream)
+        }
+        None => (Pubkey::default(), Pubkey::default()),
+    };
+    (pool.reward_stream_program, pool.reward_stream) = new;
+    record_config_change(
+        pool,
+        ctx.accounts.pool.key(),
+        ctx.accounts.config_change.as_mut(),
+        ConfigParam::RewardStream,
+        &old,
+        &new,
+        ctx.accounts.authority.key(),
+    )?;
+
+    Ok(())
+}
+
+fn handle_claim_reward_stream<'info>(
+    ctx: Context<'_, '_, '_, 'info, ClaimRewardStream<'info>>,
+) -> PoolResult {
+    require!(
+        ctx.accounts.pool.load()?.reward_stream_program != Pubkey::default(),
+        PoolError::InvalidRewardStream
+    );
+    let pool_key = ctx.accounts.pool.key();
+    let seeds = &[
+        STREAM_RECIPIENT_SEED,
+        pool_key.as_ref(),
+        &[ctx.bumps.stream_recipient],
+    ];
+    let signer = &[&seeds[..]];
+
+    // The stream program is only signed for by the recipient PDA, never by
+    // the vault's owner.
+    let stream_before = ctx.accounts.stream_tokens.amount;
+    invoke_stream_withdraw(ctx.accounts, ctx.remaining_accounts, signer)?;
+    ctx.accounts.stream_tokens.reload()?;
+    let withdrawn = ctx.accounts.stream_tokens.amount.safe_sub(stream_before)?;
+
+    let before = ctx.accounts.vault.amount;
+    transfer_checked_with_hook(
+        &ctx.accounts.token_program,
+        ctx.accounts.stream_tokens.to_account_info(),
+        &ctx.accounts.mint,
+        ctx.accounts.vault.to_account_info(),
+        ctx.accounts.stream_recipient.to_account_info(),
+        ctx.remaining_accounts,
+        withdrawn,
+        signer,
+    )?;
+    ctx.accounts.vault.reload()?;
+
+    let claimed = ctx.accounts.vault.amount.safe_sub(before)?;
+
+    // The stream's vesting paces the grant, so each claim goes straight into
+    // the reward index the way restaking yield does, and claims are paid
+    // from it through `funded_rewards` rather than minted on top.
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+    pool.distribute_yield(claimed, Clock::get()?.unix_timestamp)?;
+
+    emit!(RewardStreamClaimed {
+        pool: pool_key,
+        stream: ctx.accounts.stream.key(),
+        amount: claimed,
+    });
+
+    assert_pool_invariants(pool, &ctx.accounts.vault)?;
+    pool.publish_state()
+}
+
+fn handle_buyback_and_distribute<'info>(
+    ctx: Context<'_, '_, '_, 'info, BuybackAndDistribute<'info>>,
+    amount_in: u64,
+    min_out: u64,
+) -> PoolResult {
+    require!(amount_in > 0, PoolError::ZeroAmount);
+
+    let (mint, nonce) = {
+        let pool = ctx.accounts.pool.load()?;
+        (pool.mint, pool.nonce)
+    };
+    let pool_key = ctx.accounts.pool.key();
+    let seeds = &[mint.as_ref(), pool_key.as_ref(), &[nonce]];
+    let swap_seeds = &[
+        SWAP_AUTHORITY_SEED,
+        pool_key.as_ref(),
+        &[ctx.bumps.swap_authority],
+    ];
+
+    // The adapter only gets a signature over the exact input, moved out of
+    // the program signer's hands first.
+    let scratch_before = ctx.accounts.swap_source.amount;
+    transfer_checked_with_hook(
+        &ctx.accounts.revenue_token_program,
+        ctx.accounts.revenue.to_account_info(),
+        &ctx.accounts.revenue_mint,
+        ctx.accounts.swap_source.to_account_info(),
+        ctx.accounts.program_signer.to_account_info(),
+        ctx.remaining_accounts,
+        amount_in,
+        &[&seeds[..]],
+    )?;
+
+    let before = ctx.accounts.vault.amount;
+    invoke_swap(
+        &ctx.accounts.swap_adapter,
+        ctx.accounts.swap_authority.to_account_info(),
+        ctx.accounts.swap_source.to_account_info(),
+        ctx.accounts.vault.to_account_info(),
+        [
+            ctx.accounts.revenue_mint.to_account_info(),
+            ctx.accounts.mint.to_account_info(),
+        ],
+        [
+            ctx.accounts.revenue_token_program.to_account_info(),
+            ctx.accounts.token_program.to_account_info(),
+        ],
+        ctx.remaining_accounts,
+        amount_in,
+        min_out,
+        &[&swap_seeds[..]],
+    )?;
+    ctx.accounts.vault.reload()?;
+    ctx.accounts.swap_source.reload()?;
+
+    // Nothing but the input may leave the scratch account.
+    require_gte!(
+        ctx.accounts.swap_source.amount,
+        scratch_before,
+        PoolError::InvalidSwapAdapter
+    );
+
+    let bought = ctx.accounts.vault.amount.safe_sub(before)?;
+    require_gte!(bought, min_out, PoolError::SlippageExceeded);
+
+    // Shared among stakers like restaking yield.
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+    pool.distribute_yield(bought, Clock::get()?.unix_timestamp)?;
+
+    assert_pool_invariants(pool, &ctx.accounts.vault)?;
+    pool.publish_state()
+}
+
+fn handle_set_restaking_adapter(
+    ctx: Context<SetRestakingAdapter>,
+    adapter_program: Pubkey,
+    buffer_bps: u32,
+) -> PoolResult {
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+
+    require_gte!(
+        BPS_DENOMINATOR,
+        buffer_bps as u128,
+        PoolError::InvalidAdapter
+    );
+
+    // Funds must be recalled before switching adapters.
+    require!(
+        adapter_program == pool.adapter_program || pool.deployed_amount == 0,
+        PoolError::InvalidAdapter
+    );
+
+    let old = (pool.adapter_program, pool.buffer_bps);
+    pool.adapter_program = adapter_program;
+    pool.buffer_bps = buffer_bps;
+    record_config_change(
+        pool,
+        ctx.accounts.pool.key(),
+        ctx.accounts.config_change.as_mut(),
+        ConfigParam::RestakingAdapter,
+        &old,
+        &(adapter_program, buffer_bps),
+        ctx.accounts.authority.key(),
+    )?;
+
+    Ok(())
+}
+
+fn handle_deploy_funds<'info>(
+    ctx: Context<'_, '_, '_, 'info, MoveFunds<'info>>,
+    amount: u64,
+) -> PoolResult {
+    require!(amount > 0, PoolError::ZeroAmount);
+
+    let (mint, nonce) = {
+        let pool = ctx.accounts.pool.load()?;
+        pool.require_enabled(instruction_flags::DEPLOY_FUNDS)?;
+        // Nothing more leaves the vault while it may already fall short.
+        require!(pool.reserve_alarm == 0, PoolError::ReserveAlarm);
+        (pool.mint, pool.nonce)
+    };
+    let pool_key = ctx.accounts.pool.key();
+    let seeds = &[mint.as_ref(), pool_key.as_ref(), &[nonce]];
+    let restaking_seeds = &[
+        RESTAKING_AUTHORITY_SEED,
+        pool_key.as_ref(),
+        &[ctx.bumps.restaking_authority],
+    ];
+
+    // The adapter only gets a signature over the deposit, moved out of the
+    // vault first.
+    let scratch_before = ctx.accounts.restaking_source.amount;
+    transfer_checked_with_hook(
+        &ctx.accounts.token_program,
+        ctx.accounts.vault.to_account_info(),
+        &ctx.accounts.mint,
+        ctx.accounts.restaking_source.to_account_info(),
+        ctx.accounts.program_signer.to_account_info(),
+        ctx.remaining_accounts,
+        amount,
+        &[&seeds[..]],
+    )?;
+    ctx.accounts.restaking_source.reload()?;
+    let landed = ctx
+        .accounts
+        .restaking_source
+        .amount
+        .safe_sub(scratch_before)?;
+
+    invoke_adapter(
+        ctx.accounts,
+        ctx.remaining_accounts,
+        "deposit",
+        landed,
+        &[&restaking_seeds[..]],
+    )?;
+    ctx.accounts.vault.reload()?;
+    ctx.accounts.restaking_source.reload()?;
+
+    // All of it has to be taken, or it would sit outside the reserves.
+    require_eq!(
+        ctx.accounts.restaking_source.amount,
+        scratch_before,
+        PoolError::InvalidAdapter
+    );
+    let deployed = landed;
+
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+    pool.deployed_amount = pool.deployed_amount.safe_add(deployed)?;
+
+    let buffer = bps_of(
+        pool.staked_total,
+        pool.buffer_bps as u128,
+        Rounding::Liability,
+    )?;
+    require_gte!(
+        ctx.accounts.vault.amount,
+        buffer,
+        PoolError::InsufficientBuffer
+    );
+
+    assert_pool_invariants(pool, &ctx.accounts.vault)?;
+    pool.publish_state()
+}
+
+fn handle_recall_funds<'info>(
+    ctx: Context<'_, '_, '_, 'info, MoveFunds<'info>>,
+    amount: u64,
+) -> PoolResult {
+    require!(amount > 0, PoolError::ZeroAmount);
+
+    let pool_key = ctx.accounts.pool.key();
+    let restaking_seeds = &[
+        RESTAKING_AUTHORITY_SEED,
+        pool_key.as_ref(),
+        &[ctx.bumps.restaking_authority],
+    ];
+
+    let before = ctx.accounts.vault.amount;
+    let scratch_before = ctx.accounts.restaking_source.amount;
+    invoke_adapter(
+        ctx.accounts,
+        ctx.remaining_accounts,
+        "withdraw",
+        amount,
+        &[&restaking_seeds[..]],
+    )?;
+    ctx.accounts.vault.reload()?;
+    ctx.accounts.restaking_source.reload()?;
+    require_gte!(
+        ctx.accounts.restaking_source.amount,
+        scratch_before,
+        PoolError::InvalidAdapter
+    );
+
+    let received = ctx.accounts.vault.amount.safe_sub(before)?;
+
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+    let principal = received.min(pool.deployed_amount);
+    pool.deployed_amount = pool.deployed_amount.safe_sub(principal)?;
+
+    // Anything returned beyond principal is yield, shared among stakers
+    // through the reward index.
+    pool.distribute_yield(received.safe_sub(principal)?, Clock::get()?.unix_timestamp)?;
+
+    assert_pool_invariants(pool, &ctx.accounts.vault)?;
+    pool.publish_state()
+}
+
+fn handle_request_withdrawal(
+    ctx: Context<RequestWithdrawal>,
+    amount: u64,
+    nonce: u64,
+    priority: bool,
+) -> PoolResult {
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+    pool.require_enabled(instruction_flags::REQUEST_WITHDRAWAL)?;
+    // Carries no memo.
+    require!(pool.memo_required == 0, PoolError::MemoRequired);
+    require!(pool.incident_reason == 0, PoolError::IncidentActive);
+    require!(pool.reserve_alarm == 0, PoolError::ReserveAlarm);
+
+    require!(amount > 0, PoolError::ZeroAmount);
+    require!(
+        ctx.accounts.user_state.initialized,
+        PoolError::UserNotInitialized
+    );
+    // Queued withdrawals pay the wallet, not the cold destination.
+    require!(
+        ctx.accounts.user_state.cold_key == Pubkey::default(),
+        PoolError::ColdApprovalRequired
+    );
+
+    if priority {
+        let badge = ctx
+            .accounts
+            .vip_badge
+            .as_ref()
+            .ok_or(PoolError::InvalidVipBadge)?;
+        require!(
+            pool.vip_mint != Pubkey::default()
+                && badge.mint == pool.vip_mint
+                && badge.owner == ctx.accounts.authority.key()
+                && badge.amount > 0,
+            PoolError::InvalidVipBadge
+        );
+    }
+
+    // The request's place in line, taken when it lands rather than when it
+    // was signed.
+    let id = if priority {
+        pool.priority_tail
+    } else {
+        pool.queue_tail
+    };
+    if priority {
+        pool.priority_tail = pool.priority_tail.safe_add(1)?;
+    } else {
+        pool.queue_tail = pool.queue_tail.safe_add(1)?;
+    }
+
+    let now = Clock::get()?.unix_timestamp;
+    ctx.accounts
+        .user_state
+        .authorize_withdrawal(ctx.accounts.guardian.as_ref(), amount, now)?;
+    let (fee, penalty) = match pool_config(pool, &ctx.accounts.config)? {
+        Some(config) => charge_withdrawal(
+            config,
+            ctx.accounts.pool.key(),
+            ctx.accounts.authority.key(),
+            &ctx.accounts.user_state,
+            amount,
+            now,
+            ctx.accounts.fee_exemption.is_some(),
+        )?,
+        None => (0, 0),
+    };
+    let payout = amount.safe_sub(fee.safe_add(penalty)?)?;
+
+    update_rewards(pool, &mut ctx.accounts.user_state, now)?;
+    record_on_leaderboard(
+        ctx.accounts.leaderboard.as_mut(),
+        ctx.accounts.pool.key(),
+        pool,
+        ctx.accounts.authority.key(),
+        &ctx.accounts.user_state,
+        ctx.accounts.wallet_state.as_mut(),
+        now,
+    )?;
+
+    // Queued stake stops earning right away.
+    pool.staked_total = pool.staked_total.safe_sub(amount)?;
+    let before = ctx.accounts.user_state.staked_amount;
+    ctx.accounts.user_state.staked_amount = before.safe_sub(amount)?;
+    book_wallet_stake(
+        pool,
+        &ctx.accounts.user_state,
+        ctx.accounts.wallet_state.as_mut(),
+        before,
+    )?;
+    drop_link_boost(
+        &mut ctx.accounts.user_state,
+        ctx.accounts.link_boosted.as_ref(),
+    )?;
+
+    record_checkpoints(
+        pool,
+        ctx.accounts.pool.key(),
+        &ctx.accounts.user_state,
+        ctx.accounts.wallet_state.as_ref(),
+        ctx.accounts.authority.key(),
+        ctx.accounts.user_checkpoints.as_ref(),
+        ctx.accounts.pool_checkpoints.as_ref(),
+    )?;
+    record_audit(
+        pool,
+        ctx.accounts.pool.key(),
+        ctx.accounts.audit_log.as_ref(),
+        AuditOp::RequestWithdrawal,
+        ctx.accounts.authority.key(),
+        amount,
+    )?;
+
+    pool.queued_amount = pool.queued_amount.safe_add(payout)?;
+    pool.book_charges(fee, penalty)?;
+
+    let request = &mut ctx.accounts.withdrawal_request;
+    request.pool = ctx.accounts.pool.key();
+    request.user = ctx.accounts.authority.key();
+    request.id = id;
+    request.amount = payout;
+    request.requested_ts = now;
+    request.status = WithdrawalStatus::Pending;
+    request.priority = priority;
+    request.nonce = nonce;
+
+    emit!(WithdrawalQueued {
+        pool: request.pool,
+        user: request.user,
+        id,
+        amount: payout,
+        label: ctx.accounts.user_state.label,
+    });
+
+    Ok(())
+}
+
+fn handle_force_unwind(ctx: Context<ForceUnwind>, user: Pubkey) -> PoolResult {
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+    let compliance = ctx.accounts.compliance.key();
+
+    require!(
+        pool.compliance != Pubkey::default() && compliance == pool.compliance,
+        PoolError::Unauthorized
+    );
+    require!(pool.incident_reason == 0, PoolError::IncidentActive);
+
+    let config = &ctx.accounts.config;
+    let attestation = &ctx.accounts.attestation;
+    require!(
+        config.params.attestation_program != Pubkey::default()
+            && attestation.key() == config.attestation_address(user),
+        PoolError::InvalidPoolConfig
+    );
+    require!(
+        !config.is_attested(user, Some(attestation), Clock::get()?.unix_timestamp),
+        PoolError::AttestationNotRevoked
+    );
+
+    let user_state = &mut ctx.accounts.user_state;
+    require!(user_state.initialized, PoolError::UserNotInitialized);
+    require!(!user_state.unwound, PoolError::UserUnwound);
+    let amount = user_state.staked_amount;
+    require!(amount > 0, PoolError::ZeroAmount);
+
+    let id = pool.queue_tail;
+    pool.queue_tail = pool.queue_tail.safe_add(1)?;
+
+    let now = Clock::get()?.unix_timestamp;
+    update_rewards(pool, user_state, now)?;
+
+    // The user didn't choose to leave, so the exit is free of fees, the
+    // early-withdrawal penalty and the guardian.
+    pool.staked_total = pool.staked_total.safe_sub(amount)?;
+    user_state.staked_amount = 0;
+    user_state.unwound = true;
+    // The wallet's other positions can't stake again either.
+    book_wallet_stake(pool, user_state, ctx.accounts.wallet_state.as_mut(), amount)?;
+    if let Some(wallet_state) = ctx.accounts.wallet_state.as_mut() {
+        wallet_state.unwound = true;
+    }
+    drop_link_boost(user_state, ctx.accounts.link_boosted.as_ref())?;
+
+    record_checkpoints(
+        pool,
+        ctx.accounts.pool.key(),
+        user_state,
+        ctx.accounts.wallet_state.as_ref(),
+        user,
+        ctx.accounts.user_checkpoints.as_ref(),
+        ctx.accounts.pool_checkpoints.as_ref(),
+    )?;
+    record_audit(
+        pool,
+        ctx.accounts.pool.key(),
+        ctx.accounts.audit_log.as_ref(),
+        AuditOp::RequestWithdrawal,
+        user,
+        amount,
+    )?;
+
+    pool.queued_amount = pool.queued_amount.safe_add(amount)?;
+
+    let request = &mut ctx.accounts.withdrawal_request;
+    request.pool = ctx.accounts.pool.key();
+    request.user = user;
+    request.id = id;
+    request.amount = amount;
+    request.requested_ts = now;
+    request.status = WithdrawalStatus::Pending;
+    request.priority = false;
+
+    let label = ctx.accounts.user_state.label;
+    emit!(WithdrawalQueued {
+        pool: request.pool,
+        user,
+        id,
+        amount,
+        label,
+    });
+    emit!(StakeUnwound {
+        pool: request.pool,
+        user,
+        compliance,
+        id,
+        amount,
+        label,
+    });
+
+    Ok(())
+}
+
+fn handle_set_vip_mint(ctx: Context<SetVipMint>, vip_mint: Option<Pubkey>) -> PoolResult {
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+
+    let old = pool.vip_mint;
+    let new = vip_mint.unwrap_or_default();
+    pool.vip_mint = new;
+    record_config_change(
+        pool,
+        ctx.accounts.pool.key(),
+        ctx.accounts.config_change.as_mut(),
+        ConfigParam::VipMint,
+        &old,
+        &new,
+        ctx.accounts.authority.key(),
+    )?;
+
+    Ok(())
+}
+
+#[cfg(feature = "wormhole")]
+fn handle_set_attestation_fee(ctx: Context<SetAttestationFee>, fee: u64) -> PoolResult {
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+
+    let old = pool.attestation_fee;
+    pool.attestation_fee = fee;
+    record_config_change(
+        pool,
+        ctx.accounts.pool.key(),
+        ctx.accounts.config_change.as_mut(),
+        ConfigParam::AttestationFee,
+        &old,
+        &fee,
+        ctx.accounts.authority.key(),
+    )?;
+
+    Ok(())
+}
+
+fn handle_set_distribution_cap(ctx: Context<SetDistributionCap>, cap: u64) -> PoolResult {
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+
+    let old = pool.distribution_cap;
+    pool.distribution_cap = cap;
+    record_config_change(
+        pool,
+        ctx.accounts.pool.key(),
+        ctx.accounts.config_change.as_mut(),
+        ConfigParam::DistributionCap,
+        &old,
+        &cap,
+        ctx.accounts.authority.key(),
+    )?;
+
+    Ok(())
+}
+
+fn handle_set_claim_cooldown(ctx: Context<SetClaimCooldown>, cooldown: u32) -> PoolResult {
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+
+    let old = pool.claim_cooldown;
+    pool.claim_cooldown = cooldown;
+    record_config_change(
+        pool,
+        ctx.accounts.pool.key(),
+        ctx.accounts.config_change.as_mut(),
+        ConfigParam::ClaimCooldown,
+        &old,
+        &cooldown,
+        ctx.accounts.authority.key(),
+    )?;
+
+    Ok(())
+}
+
+fn handle_fulfill_withdrawal<'info>(
+    ctx: Context<'_, '_, '_, 'info, FulfillWithdrawal<'info>>,
+) -> PoolResult {
+    let request = &mut ctx.accounts.withdrawal_request;
+
+    let (mint, nonce, fill, payout) = {
+        let pool = &mut ctx.accounts.pool.load_mut()?;
+
+        require!(
+            request.status == WithdrawalStatus::Pending,
+            PoolError::InvalidWithdrawalRequest
+        );
+
+        let fill = if pool.pro_rata_withdrawals != 0 {
+            pool.fill_from_batch(request)?
+        } else {
+            // The priority lane drains before the regular queue moves.
+            if request.priority {
+                require_eq!(
+                    request.id,
+                    pool.priority_head,
+                    PoolError::InvalidWithdrawalRequest
+                );
+                pool.priority_head = pool.priority_head.safe_add(1)?;
+            } else {
+                require!(
+                    request.id == pool.queue_head && pool.priority_head == pool.priority_tail,
+                    PoolError::InvalidWithdrawalRequest
+                );
+                pool.queue_head = pool.queue_head.safe_add(1)?;
+            }
+
+            request.amount
+        };
+
+        let payout = pool.written_down(fill)?;
+        require_gte!(
+            ctx.accounts.vault.amount,
+            payout,
+            PoolError::InsufficientBuffer
+        );
+
+        pool.queued_amount = pool.queued_amount.safe_sub(fill)?;
+        request.amount = request.amount.safe_sub(fill)?;
+        if request.amount == 0 {
+            request.status = WithdrawalStatus::Fulfilled;
+        }
+        // Batches fill out of order; once nothing is owed, every request
+        // before the tails is done.
+        if pool.pro_rata_withdrawals != 0 && pool.queued_amount == 0 {
+            pool.queue_head = pool.queue_tail;
+            pool.priority_head = pool.priority_tail;
+        }
+        record_audit(
+            pool,
+            ctx.accounts.pool.key(),
+            ctx.accounts.audit_log.as_ref(),
+            AuditOp::FulfillWithdrawal,
+            request.user,
+            fill,
+        )?;
+
+        (pool.mint, pool.nonce, fill, payout)
+    };
+
+    let seeds = &[
+        mint.as_ref(),
+        ctx.accounts.pool.to_account_info().key.as_ref(),
+        &[nonce],
+    ];
+    let signer = &[&seeds[..]];
+
+    transfer_checked_with_hook(
+        &ctx.accounts.token_program,
+        ctx.accounts.vault.to_account_info(),
+        &ctx.accounts.mint,
+        ctx.accounts.user_mint_acc.to_account_info(),
+        ctx.accounts.program_signer.to_account_info(),
+        ctx.remaining_accounts,
+        payout,
+        signer,
+    )?;
+
+    if request.status == WithdrawalStatus::Fulfilled {
+        emit!(WithdrawalFulfilled {
+            pool: request.pool,
+            user: request.user,
+            id: request.id,
+            amount: fill,
+        });
+        // Nothing reads a fulfilled request; hand the rent back.
+        request.close(ctx.accounts.requester.to_account_info())?;
+    } else {
+        emit!(WithdrawalPartiallyFilled {
+            pool: request.pool,
+            user: request.user,
+            id: request.id,
+            amount: fill,
+            remaining: request.amount,
+        });
+    }
+
+    ctx.accounts.vault.reload()?;
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+    assert_pool_invariants(pool, &ctx.accounts.vault)?;
+    pool.publish_state()
+}
+
+fn handle_open_withdrawal_batch(ctx: Context<OpenWithdrawalBatch>) -> PoolResult {
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+    require!(
+        pool.pro_rata_withdrawals != 0 && pool.batch_remaining == 0,
+        PoolError::WithdrawalBatchUnavailable
+    );
+    require!(pool.queued_amount > 0, PoolError::ZeroAmount);
+
+    // Whatever the vault holds is shared by everything queued right now.
+    let owed = pool.written_down(pool.queued_amount)?;
+    let available = ctx.accounts.vault.amount;
+    pool.batch_fill_rate = match available >= owed {
+        true => REWARD_PRECISION,
+        false => mul_div(
+            available as u128,
+            REWARD_PRECISION,
+            owed as u128,
+            Rounding::Payout,
+        )?,
+    };
+    pool.batch_id = pool.batch_id.safe_add(1)?;
+    pool.batch_queue_tail = pool.queue_tail;
+    pool.batch_priority_tail = pool.priority_tail;
+    pool.batch_remaining = pool.queued_amount;
+
+    emit!(WithdrawalBatchOpened {
+        pool: ctx.accounts.pool.key(),
+        batch: pool.batch_id,
+        fill_rate: pool.batch_fill_rate,
+        queued_amount: pool.queued_amount,
+    });
+
+    Ok(())
+}
+
+fn handle_set_pro_rata_withdrawals(
+    ctx: Context<SetProRataWithdrawals>,
+    enabled: bool,
+) -> PoolResult {
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+
+    // Neither mode can take over requests ordered by the other.
+    require!(pool.queued_amount == 0, PoolError::QueueNotEmpty);
+
+    let old = pool.pro_rata_withdrawals != 0;
+    pool.pro_rata_withdrawals = enabled as u8;
+    record_config_change(
+        pool,
+        ctx.accounts.pool.key(),
+        ctx.accounts.config_change.as_mut(),
+        ConfigParam::ProRataWithdrawals,
+        &old,
+        &enabled,
+        ctx.accounts.authority.key(),
+    )?;
+
+    Ok(())
+}
+
+fn handle_initialize_pool_config(
+    ctx: Context<InitializePoolConfig>,
+    params: PoolConfigParams,
+) -> PoolResult {
+    params.validate()?;
+
+    let config = &mut ctx.accounts.config;
+    config.pool = ctx.accounts.pool.key();
+    config.params = params;
+
+    ctx.accounts.pool.load_mut()?.config_enabled = 1;
+
+    Ok(())
+}
+
+fn handle_initialize_boost_link(
+    ctx: Context<InitializeBoostLink>,
+    max_boost_bps: u32,
+    full_boost_stake: u64,
+) -> PoolResult {
+    let boost_link = &mut ctx.accounts.boost_link;
+    boost_link.pool = ctx.accounts.pool.key();
+    boost_link.linked_pool = ctx.accounts.linked_pool.key();
+    boost_link.max_boost_bps = max_boost_bps;
+    boost_link.full_boost_stake = full_boost_stake;
+
+    boost_link.validate()
+}
+
+fn handle_set_boost_link(
+    ctx: Context<SetBoostLink>,
+    max_boost_bps: u32,
+    full_boost_stake: u64,
+) -> PoolResult {
+    let boost_link = &mut ctx.accounts.boost_link;
+
+    let old = (
+        boost_link.linked_pool,
+        boost_link.max_boost_bps,
+        boost_link.full_boost_stake,
+    );
+    boost_link.linked_pool = ctx.accounts.linked_pool.key();
+    boost_link.max_boost_bps = max_boost_bps;
+    boost_link.full_boost_stake = full_boost_stake;
+    boost_link.validate()?;
+
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+    record_config_change(
+        pool,
+        ctx.accounts.pool.key(),
+        ctx.accounts.config_change.as_mut(),
+        ConfigParam::BoostLink,
+        &old,
+        &(boost_link.linked_pool, max_boost_bps, full_boost_stake),
+        ctx.accounts.authority.key(),
+    )?;
+
+    Ok(())
+}
+
+fn handle_sync_link_boost(ctx: Context<SyncLinkBoost>) -> PoolResult {
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+    let user_state = &mut ctx.accounts.user_state;
+
+    require!(user_state.initialized, PoolError::UserNotInitialized);
+
+    // Rewards so far are earned at the old boost.
+    update_rewards(pool, user_state, Clock::get()?.unix_timestamp)?;
+
+    // Linked stake boosts one position at a time, which loses the boost as
+    // soon as the stake shrinks.
+    let linked_stake = match ctx.accounts.linked_user_state.as_mut() {
+        Some(linked) => {
+            require!(
+                linked.link_boosted == Pubkey::default() || linked.link_boosted == user_state.key(),
+                PoolError::InvalidBoostLink
+            );
+            if linked.staked_amount > 0 {
+                linked.link_boosted = user_state.key();
+            }
+            linked.staked_amount
+        }
+        None => 0,
+    };
+    user_state.link_boost_bps = ctx.accounts.boost_link.boost_bps(linked_stake);
+
+    Ok(())
+}
+
+/// Drops the boost `user_state`'s stake gives through a `BoostLink` now that
+/// it shrank. `link_boosted` is the boosted position, required while there
+/// is one.
+fn drop_link_boost(
+    user_state: &mut UserState,
+    link_boosted: Option<&UncheckedAccount>,
+) -> PoolResult {
+    if user_state.link_boosted == Pubkey::default() {
+        return Ok(());
+    }
+
+    // A boosted position garbage collected since has no boost left.
+    let boosted = link_boosted.ok_or(PoolError::InvalidBoostLink)?;
+    if *boosted.owner == crate::ID {
+        let mut data = boosted.try_borrow_mut_data()?;
+        let mut state = UserState::try_deserialize(&mut &data[..])?;
+        state.link_boost_bps = 0;
+        state.try_serialize(&mut &mut data[..])?;
+    }
+    user_state.link_boosted = Pubkey::default();
+
+    Ok(())
+}
+
+fn handle_set_pool_config(ctx: Context<SetPoolConfig>, params: PoolConfigParams) -> PoolResult {
+    params.validate()?;
+
+    let old = ctx.accounts.config.params;
+    ctx.accounts.config.params = params;
+
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+    record_config_change(
+        pool,
+        ctx.accounts.pool.key(),
+        ctx.accounts.config_change.as_mut(),
+        ConfigParam::PoolConfig,
+        &old,
+        &params,
+        ctx.accounts.authority.key(),
+    )?;
+
+    Ok(())
+}
+
+fn handle_collect_protocol_fees<'info>(
+    ctx: Context<'_, '_, '_, 'info, CollectProtocolFees<'info>>,
+) -> PoolResult {
+    let (mint, nonce, fees) = {
+        let pool = &mut ctx.accounts.pool.load_mut()?;
+        let fees = pool.protocol_fees;
+        require!(fees > 0, PoolError::ZeroAmount);
+        // Stakers are made whole before fees leave.
+        require!(pool.reserve_alarm == 0, PoolError::ReserveAlarm);
+
+        pool.protocol_fees = 0;
+        pool.total_fees_paid_out = pool.total_fees_paid_out.safe_add(fees)?;
+
+        emit!(ProtocolFeesCollected {
+            pool: ctx.accounts.pool.key(),
+            amount: fees,
+            total_rewards_distributed: pool.total_rewards_distributed,
+            total_fees_collected: pool.total_fees_collected,
+            total_penalties: pool.total_penalties,
+        });
+
+        (pool.mint, pool.nonce, pool.written_down(fees)?)
+    };
+
+    let seeds = &[
+        mint.as_ref(),
+        ctx.accounts.pool.to_account_info().key.as_ref(),
+        &[nonce],
+    ];
+    let signer = &[&seeds[..]];
+
+    transfer_checked_with_hook(
+        &ctx.accounts.token_program,
+        ctx.accounts.vault.to_account_info(),
+        &ctx.accounts.mint,
+        ctx.accounts.destination.to_account_info(),
+        ctx.accounts.program_signer.to_account_info(),
+        ctx.remaining_accounts,
+        fees,
+        signer,
+    )?;
+
+    ctx.accounts.vault.reload()?;
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+    assert_pool_invariants(pool, &ctx.accounts.vault)?;
+    pool.publish_state()
+}
+
+fn handle_reconcile(ctx: Context<CheckReserves>) -> PoolResult<Reconciliation> {
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+
+    let reserves = pool.reserves(&ctx.accounts.vault)?;
+    let liabilities = pool.written_down_total(pool.liabilities()?)?;
+
+    // Paying out more than was ever charged is drift too.
+    let charged = pool.total_fees_collected.safe_add(pool.total_penalties)?;
+    let expected_protocol_fees = charged.saturating_sub(pool.total_fees_paid_out);
+    let fees_consistent =
+        charged >= pool.total_fees_paid_out && expected_protocol_fees == pool.protocol_fees;
+
+    let queue_consistent = pool.queue_head <= pool.queue_tail
+        && pool.priority_head <= pool.priority_tail
+        && pool.batch_remaining <= pool.queued_amount
+        && (pool.queued_amount == 0
+            || pool.queue_head < pool.queue_tail
+            || pool.priority_head < pool.priority_tail);
+
+    let balanced = reserves >= liabilities && fees_consistent && queue_consistent;
+
+    // Record the mismatch instead of failing, which would roll it back.
+    if !balanced && pool.incident_reason == 0 {
+        pool.incident_reason = incident_reasons::COUNTER_MISMATCH;
+    }
+
+    emit!(Reconciled {
+        pool: ctx.accounts.pool.key(),
+        reserves,
+        liabilities,
+        expected_protocol_fees,
+        protocol_fees: pool.protocol_fees,
+        queue_consistent,
+        balanced,
+    });
+
+    Ok(Reconciliation {
+        reserves,
+        liabilities,
+        expected_protocol_fees,
+        protocol_fees: pool.protocol_fees,
+        queue_consistent,
+        balanced,
+    })
+}
+
+fn handle_check_reserves(ctx: Context<CheckReserves>) -> PoolResult<bool> {
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+
+    // Deployed funds are only the adapter's word until they are recalled.
+    let reserves = ctx.accounts.vault.amount as u128;
+    let liabilities = pool.written_down_total(pool.liabilities()?)?;
+    let healthy = reserves >= liabilities;
+
+    // Record the breach instead of failing, which would roll the flag back.
+    if !healthy {
+        pool.reserve_alarm = 1;
+        if pool.incident_reason == 0 {
+            pool.incident_reason = incident_reasons::RESERVE_SHORTFALL;
+        }
+    }
+
+    emit!(ReserveCheck {
+        pool: ctx.accounts.pool.key(),
+        reserves,
+        liabilities,
+        healthy,
+    });
+
+    Ok(healthy)
+}
+
+fn handle_take_freeze_authority(ctx: Context<TakeFreezeAuthority>) -> PoolResult {
+    let program_signer = ctx.accounts.program_signer.key();
+
+    token_interface::set_authority(
+        CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            token_interface::SetAuthority {
+                current_authority: ctx.accounts.authority.to_account_info(),
+                account_or_mint: ctx.accounts.mint.to_account_info(),
+            },
+        ),
+        MintAuthority::FreezeAccount.into(),
+        Some(program_signer),
+    )?;
+
+    emit!(MintAuthorityChanged {
+        pool: ctx.accounts.pool.key(),
+        kind: MintAuthority::FreezeAccount,
+        new_authority: Some(program_signer),
+    });
+
+    Ok(())
+}
+
+fn handle_propose_authority_rotation(
+    ctx: Context<AuthorityRotation>,
+    kind: MintAuthority,
+    new_authority: Option<Pubkey>,
+) -> PoolResult {
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+    let eta = Clock::get()?
+        .unix_timestamp
+        .checked_add(AUTHORITY_ROTATION_DELAY)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+
+    let old = (pool.rotation_kind, pool.rotation_target, pool.rotation_eta);
+    pool.rotation_kind = kind as u8 + 1;
+    pool.rotation_target = new_authority.unwrap_or_default();
+    pool.rotation_eta = eta;
+    let new = (pool.rotation_kind, pool.rotation_target, eta);
+    record_config_change(
+        pool,
+        ctx.accounts.pool.key(),
+        ctx.accounts.config_change.as_mut(),
+        ConfigParam::MintAuthorityRotation,
+        &old,
+        &new,
+        ctx.accounts.authority.key(),
+    )?;
+
+    emit!(AuthorityRotationProposed {
+        pool: ctx.accounts.pool.key(),
+        kind,
+        new_authority,
+        eta,
+    });
+
+    Ok(())
+}
+
+fn handle_cancel_authority_rotation(ctx: Context<AuthorityRotation>) -> PoolResult {
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+    require!(
+        pool.pending_rotation().is_some(),
+        PoolError::NoPendingRotation
+    );
+
+    let old = (pool.rotation_kind, pool.rotation_target, pool.rotation_eta);
+    pool.rotation_kind = 0;
+    pool.rotation_target = Pubkey::default();
+    pool.rotation_eta = 0;
+    record_config_change(
+        pool,
+        ctx.accounts.pool.key(),
+        ctx.accounts.config_change.as_mut(),
+        ConfigParam::MintAuthorityRotation,
+        &old,
+        &(0u8, Pubkey::default(), 0i64),
+        ctx.accounts.authority.key(),
+    )?;
+
+    Ok(())
+}
+
+fn handle_execute_authority_rotation(ctx: Context<ExecuteAuthorityRotation>) -> PoolResult {
+    let (kind, new_authority, mint, nonce) = {
+        let pool = &mut ctx.accounts.pool.load_mut()?;
+        let kind = pool
+            .pending_rotation()
+            .ok_or(PoolError::NoPendingRotation)?;
+        require!(
+            Clock::get()?.unix_timestamp >= pool.rotation_eta,
+            PoolError::RotationTimelocked
+        );
+
+        let new_authority = Some(pool.rotation_target).filter(|key| *key != Pubkey::default());
+        pool.rotation_kind = 0;
+        pool.rotation_target = Pubkey::default();
+        pool.rotation_eta = 0;
+
+        let old_authority = match kind {
+            MintAuthority::MintTokens => ctx.accounts.mint.mint_authority,
+            MintAuthority::FreezeAccount => ctx.accounts.mint.freeze_authority,
+        };
+        record_config_change(
+            pool,
+            ctx.accounts.pool.key(),
+            ctx.accounts.config_change.as_mut(),
+            ConfigParam::MintAuthority,
+            &(kind, Option::<Pubkey>::from(old_authority)),
+            &(kind, new_authority),
+            ctx.accounts.authority.key(),
+        )?;
+
+        (kind, new_authority, pool.mint, pool.nonce)
+    };
+
+    let seeds = &[
+        mint.as_ref(),
+        ctx.accounts.pool.to_account_info().key.as_ref(),
+        &[nonce],
+    ];
+    let signer = &[&seeds[..]];
+
+    token_interface::set_authority(
+        CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            token_interface::SetAuthority {
+                current_authority: ctx.accounts.program_signer.to_account_info(),
+                account_or_mint: ctx.accounts.mint.to_account_info(),
+            },
+            signer,
+        ),
+        kind.into(),
+        new_authority,
+    )?;
+
+    emit!(MintAuthorityChanged {
+        pool: ctx.accounts.pool.key(),
+        kind,
+        new_authority,
+    });
+
+    Ok(())
+}
+
+fn handle_propose_vault_rotation(ctx: Context<ProposeVaultRotation>) -> PoolResult {
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+    let eta = Clock::get()?
+        .unix_timestamp
+        .checked_add(VAULT_ROTATION_DELAY)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+
+    let old = (pool.vault_rotation_target, pool.vault_rotation_eta);
+    pool.vault_rotation_target = ctx.accounts.new_vault.key();
+    pool.vault_rotation_eta = eta;
+    let new = (pool.vault_rotation_target, eta);
+    record_config_change(
+        pool,
+        ctx.accounts.pool.key(),
+        ctx.accounts.config_change.as_mut(),
+        ConfigParam::VaultRotation,
+        &old,
+        &new,
+        ctx.accounts.authority.key(),
+    )?;
+
+    emit!(VaultRotationProposed {
+        pool: ctx.accounts.pool.key(),
+        new_vault: pool.vault_rotation_target,
+        eta,
+    });
+
+    Ok(())
+}
+
+fn handle_cancel_vault_rotation(ctx: Context<CancelVaultRotation>) -> PoolResult {
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+    require!(
+        pool.vault_rotation_target != Pubkey::default(),
+        PoolError::NoPendingRotation
+    );
+
+    let old = (pool.vault_rotation_target, pool.vault_rotation_eta);
+    pool.vault_rotation_target = Pubkey::default();
+    pool.vault_rotation_eta = 0;
+    record_config_change(
+        pool,
+        ctx.accounts.pool.key(),
+        ctx.accounts.config_change.as_mut(),
+        ConfigParam::VaultRotation,
+        &old,
+        &(Pubkey::default(), 0i64),
+        ctx.accounts.authority.key(),
+    )?;
+
+    Ok(())
+}
+
+fn handle_rotate_vault<'info>(ctx: Context<'_, '_, '_, 'info, RotateVault<'info>>) -> PoolResult {
+    let (mint, nonce) = {
+        let pool = &ctx.accounts.pool.load()?;
+        require!(
+            pool.vault_rotation_target != Pubkey::default(),
+            PoolError::NoPendingRotation
+        );
+        require!(
+            Clock::get()?.unix_timestamp >= pool.vault_rotation_eta,
+            PoolError::RotationTimelocked
+        );
+
+        (pool.mint, pool.nonce)
+    };
+
+    let seeds = &[
+        mint.as_ref(),
+        ctx.accounts.pool.to_account_info().key.as_ref(),
+        &[nonce],
+    ];
+    let signer = &[&seeds[..]];
+
+    let amount = ctx.accounts.vault.amount;
+    transfer_checked_with_hook(
+        &ctx.accounts.token_program,
+        ctx.accounts.vault.to_account_info(),
+        &ctx.accounts.mint,
+        ctx.accounts.new_vault.to_account_info(),
+        ctx.accounts.program_signer.to_account_info(),
+        ctx.remaining_accounts,
+        amount,
+        signer,
+    )?;
+    ctx.accounts.new_vault.reload()?;
+
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+    pool.vault = ctx.accounts.new_vault.key();
+    pool.vault_rotation_target = Pubkey::default();
+    pool.vault_rotation_eta = 0;
+    record_config_change(
+        pool,
+        ctx.accounts.pool.key(),
+        ctx.accounts.config_change.as_mut(),
+        ConfigParam::Vault,
+        &ctx.accounts.vault.key(),
+        &ctx.accounts.new_vault.key(),
+        ctx.accounts.authority.key(),
+    )?;
+
+    emit!(VaultRotated {
+        pool: ctx.accounts.pool.key(),
+        old_vault: ctx.accounts.vault.key(),
+        new_vault: pool.vault,
+        amount,
+    });
+
+    // A transfer fee on the move would leave the new vault short.
+    assert_pool_invariants(pool, &ctx.accounts.new_vault)?;
+    pool.publish_state()
+}
+
+fn handle_set_sol_rewards(
+    ctx: Context<SetSolRewards>,
+    emission_rate: u64,
+    end_ts: i64,
+) -> PoolResult {
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+    let now = Clock::get()?.unix_timestamp;
+
+    require!(
+        emission_rate == 0 || end_ts > now,
+        PoolError::InvalidSolRewards
+    );
+
+    // Managers start a campaign once the running one is over and only
+    // change their own; the authority can override any of them.
+    let authority = ctx.accounts.authority.key();
+    if authority != pool.authority {
+        let running = pool.sol_emission_rate > 0 && pool.sol_emission_end_ts > now;
+        require!(
+            !running || pool.sol_campaign_owner == authority,
+            PoolError::CampaignNotOwned
+        );
+    }
+
+    // Close out the running campaign before switching to the new one.
+    pool.update_reward_index(now)?;
+
+    let old = (pool.sol_emission_rate, pool.sol_emission_end_ts);
+    pool.sol_emission_rate = emission_rate;
+    pool.sol_emission_end_ts = end_ts;
+    pool.sol_campaign_owner = authority;
+    record_config_change(
+        pool,
+        ctx.accounts.pool.key(),
+        ctx.accounts.config_change.as_mut(),
+        ConfigParam::SolRewards,
+        &old,
+        &(emission_rate, end_ts),
+        authority,
+    )?;
+
+    Ok(())
+}
+
+fn handle_add_campaign_manager(ctx: Context<AddCampaignManager>, manager: Pubkey) -> PoolResult {
+    let campaign_manager = &mut ctx.accounts.campaign_manager;
+    campaign_manager.pool = ctx.accounts.pool.key();
+    campaign_manager.manager = manager;
+
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+    record_config_change(
+        pool,
+        ctx.accounts.pool.key(),
+        ctx.accounts.config_change.as_mut(),
+        ConfigParam::CampaignManager,
+        &(manager, false),
+        &(manager, true),
+        ctx.accounts.authority.key(),
+    )?;
+
+    Ok(())
+}
+
+fn handle_remove_campaign_manager(ctx: Context<RemoveCampaignManager>) -> PoolResult {
+    let manager = ctx.accounts.campaign_manager.manager;
+
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+    record_config_change(
+        pool,
+        ctx.accounts.pool.key(),
+        ctx.accounts.config_change.as_mut(),
+        ConfigParam::CampaignManager,
+        &(manager, true),
+        &(manager, false),
+        ctx.accounts.authority.key(),
+    )?;
+
+    Ok(())
+}
+
+fn handle_fund_sol_rewards(ctx: Context<FundSolRewards>, lamports: u64) -> PoolResult {
+    require!(lamports > 0, PoolError::ZeroAmount);
+
+    system_program::transfer(
+        CpiContext::new(
+            ctx.accounts.system_program.to_account_info(),
+            system_program::Transfer {
+                from: ctx.accounts.funder.to_account_info(),
+                to: ctx.accounts.sol_reward_vault.to_account_info(),
+            },
+        ),
+        lamports,
+    )?;
+
+    emit!(SolRewardsFunded {
+        pool: ctx.accounts.pool.key(),
+        funder: ctx.accounts.funder.key(),
+        lamports,
+    });
+
+    Ok(())
+}
+
+fn handle_extend_sol_rewards(ctx: Context<ExtendSolRewards>, lamports: u64) -> PoolResult {
+    let end_ts = {
+        let pool = &mut ctx.accounts.pool.load_mut()?;
+        let now = Clock::get()?.unix_timestamp;
+        require!(
+            pool.sol_emission_rate > 0 && pool.sol_emission_end_ts > now,
+            PoolError::InvalidSolRewards
+        );
+
+        // The rate stays as it is and the campaign runs for as long as the
+        // top-up lasts at that rate; a remainder below one second's worth
+        // stays in the vault.
+        let seconds = lamports / pool.sol_emission_rate;
+        require!(
+            seconds >= MIN_SOL_REWARD_EXTENSION as u64,
+            PoolError::ExtensionTooSmall
+        );
+
+        pool.update_reward_index(now)?;
+        pool.sol_emission_end_ts = i64::try_from(seconds)
+            .ok()
+            .and_then(|seconds| pool.sol_emission_end_ts.checked_add(seconds))
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+
+        pool.sol_emission_end_ts
+    };
+
+    system_program::transfer(
+        CpiContext::new(
+            ctx.accounts.system_program.to_account_info(),
+            system_program::Transfer {
+                from: ctx.accounts.funder.to_account_info(),
+                to: ctx.accounts.sol_reward_vault.to_account_info(),
+            },
+        ),
+        lamports,
+    )?;
+
+    emit!(SolRewardsExtended {
+        pool: ctx.accounts.pool.key(),
+        funder: ctx.accounts.funder.key(),
+        lamports,
+        end_ts,
+    });
+
+    Ok(())
+}
+
+fn handle_claim_sol_rewards(ctx: Context<ClaimSolRewards>) -> PoolResult {
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+    pool.require_enabled(instruction_flags::CLAIM_REWARDS)?;
+
+    require!(pool.paused == 0, PoolError::PoolPaused);
+    require!(
+        ctx.accounts.user_state.initialized,
+        PoolError::UserNotInitialized
+    );
+    ctx.accounts
+        .user_state
+        .authorize_session(ctx.accounts.user.key(), ctx.accounts.authority.key())?;
+
+    let now = Clock::get()?.unix_timestamp;
+    update_rewards(pool, &mut ctx.accounts.user_state, now)?;
+
+    let lamports = std::mem::take(&mut ctx.accounts.user_state.sol_pending_rewards);
+    require!(lamports > 0, PoolError::ZeroAmount);
+
+    // The vault is a plain system account and has to stay rent exempt.
+    let available = ctx
+        .accounts
+        .sol_reward_vault
+        .lamports()
+        .saturating_sub(Rent::get()?.minimum_balance(0));
+    require_gte!(available, lamports, PoolError::SolRewardsUnfunded);
+
+    record_audit(
+        pool,
+        ctx.accounts.pool.key(),
+        ctx.accounts.audit_log.as_ref(),
+        AuditOp::ClaimSolRewards,
+        ctx.accounts.user.key(),
+        lamports,
+    )?;
+    emit!(SolRewardsClaimed {
+        pool: ctx.accounts.pool.key(),
+        user: ctx.accounts.user.key(),
+        lamports,
+        label: ctx.accounts.user_state.label,
+    });
+
+    let pool_key = ctx.accounts.pool.key();
+    let seeds = &[
+        SOL_REWARD_VAULT_SEED,
+        pool_key.as_ref(),
+        &[ctx.bumps.sol_reward_vault],
+    ];
+    system_program::transfer(
+        CpiContext::new_with_signer(
+            ctx.accounts.system_program.to_account_info(),
+            system_program::Transfer {
+                from: ctx.accounts.sol_reward_vault.to_account_info(),
+                to: ctx.accounts.user.to_account_info(),
+            },
+            &[&seeds[..]],
+        ),
+        lamports,
+    )?;
+
+    Ok(())
+}
+
+fn handle_settle_shortfall(ctx: Context<SettleShortfall>) -> PoolResult {
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+
+    let reserves = pool.reserves(&ctx.accounts.vault)?;
+    let liabilities = pool.liabilities()?;
+
+    // Rounded up so that paying everyone out never overdraws the vault.
+    pool.haircut = if reserves >= liabilities {
+        0
+    } else {
+        mul_div(
+            liabilities - reserves,
+            REWARD_PRECISION,
+            liabilities,
+            Rounding::Liability,
+        )?
+    };
+
+    emit!(ShortfallSettled {
+        pool: ctx.accounts.pool.key(),
+        reserves,
+        liabilities,
+        haircut: pool.haircut,
+    });
+
+    pool.rebase_rate_guard()
+}
+
+fn handle_set_config_history(ctx: Context<SetConfigHistory>, enabled: bool) -> PoolResult {
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+
+    let old = pool.config_history_enabled != 0;
+    pool.config_history_enabled = enabled as u8;
+    record_config_change(
+        pool,
+        ctx.accounts.pool.key(),
+        ctx.accounts.config_change.as_mut(),
+        ConfigParam::ConfigHistory,
+        &old,
+        &enabled,
+        ctx.accounts.authority.key(),
+    )?;
+
+    Ok(())
+}
+
+fn handle_set_rate_guard(ctx: Context<SetRateGuard>, max_change_bps: u16) -> PoolResult {
+    require_gte!(
+        BPS_DENOMINATOR,
+        max_change_bps as u128,
+        PoolError::InvalidRateGuard
+    );
+
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+
+    let old = pool.max_rate_change_bps;
+    pool.max_rate_change_bps = max_change_bps;
+    record_config_change(
+        pool,
+        ctx.accounts.pool.key(),
+        ctx.accounts.config_change.as_mut(),
+        ConfigParam::RateGuard,
+        &old,
+        &max_change_bps,
+        ctx.accounts.authority.key(),
+    )?;
+
+    Ok(())
+}
+
+fn handle_set_memo_required(ctx: Context<SetMemoRequired>, required: bool) -> PoolResult {
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+
+    let old = pool.memo_required != 0;
+    pool.memo_required = required as u8;
+    record_config_change(
+        pool,
+        ctx.accounts.pool.key(),
+        ctx.accounts.config_change.as_mut(),
+        ConfigParam::MemoRequired,
+        &old,
+        &required,
+        ctx.accounts.authority.key(),
+    )?;
+
+    Ok(())
+}
+
+fn handle_set_compliance(ctx: Context<SetCompliance>, compliance: Option<Pubkey>) -> PoolResult {
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+
+    let old = pool.compliance;
+    let new = compliance.unwrap_or_default();
+    pool.compliance = new;
+    record_config_change(
+        pool,
+        ctx.accounts.pool.key(),
+        ctx.accounts.config_change.as_mut(),
+        ConfigParam::Compliance,
+        &old,
+        &new,
+        ctx.accounts.authority.key(),
+    )?;
+
+    Ok(())
+}
+
+fn handle_set_watcher(ctx: Context<SetWatcher>, watcher: Option<Pubkey>) -> PoolResult {
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+
+    let old = pool.watcher;
+    let new = watcher.unwrap_or_default();
+    pool.watcher = new;
+    record_config_change(
+        pool,
+        ctx.accounts.pool.key(),
+        ctx.accounts.config_change.as_mut(),
+        ConfigParam::Watcher,
+        &old,
+        &new,
+        ctx.accounts.authority.key(),
+    )?;
+
+    Ok(())
+}
+
+fn handle_set_operator(ctx: Context<SetOperator>, operator: Option<Pubkey>) -> PoolResult {
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+
+    let old = pool.operator;
+    let new = operator.unwrap_or_default();
+    pool.operator = new;
+    record_config_change(
+        pool,
+        ctx.accounts.pool.key(),
+        ctx.accounts.config_change.as_mut(),
+        ConfigParam::Operator,
+        &old,
+        &new,
+        ctx.accounts.authority.key(),
+    )?;
+
+    Ok(())
+}
+
+fn handle_set_terms(ctx: Context<SetTerms>, terms_hash: [u8; 32]) -> PoolResult {
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+
+    let old = pool.terms_hash;
+    pool.terms_hash = terms_hash;
+    record_config_change(
+        pool,
+        ctx.accounts.pool.key(),
+        ctx.accounts.config_change.as_mut(),
+        ConfigParam::Terms,
+        &old,
+        &terms_hash,
+        ctx.accounts.authority.key(),
+    )?;
+
+    Ok(())
+}
+
+fn handle_accept_terms(ctx: Context<AcceptTerms>, terms_hash: [u8; 32]) -> PoolResult {
+    let pool = ctx.accounts.pool.load()?;
+    require!(
+        terms_hash != [0; 32] && terms_hash == pool.terms_hash,
+        PoolError::InvalidTerms
+    );
+
+    let user_state = &mut ctx.accounts.user_state;
+    require!(user_state.initialized, PoolError::UserNotInitialized);
+    user_state.terms_hash = terms_hash;
+
+    emit!(TermsAccepted {
+        pool: ctx.accounts.pool.key(),
+        user: ctx.accounts.authority.key(),
+        terms_hash,
+        label: user_state.label,
+    });
+
+    Ok(())
+}
+
+fn handle_set_incident(ctx: Context<SetIncident>, reason: u16) -> PoolResult {
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+    let signer = ctx.accounts.authority.key();
+
+    require!(
+        signer == pool.authority || (pool.watcher != Pubkey::default() && signer == pool.watcher),
+        PoolError::Unauthorized
+    );
+    require!(reason != 0, PoolError::InvalidIncident);
+    require!(pool.incident_reason == 0, PoolError::IncidentActive);
+
+    pool.incident_reason = reason;
+    let alarm = pool.reserve_alarm;
+    record_config_change(
+        pool,
+        ctx.accounts.pool.key(),
+        ctx.accounts.config_change.as_mut(),
+        ConfigParam::Incident,
+        &(0u16, alarm),
+        &(reason, alarm),
+        ctx.accounts.authority.key(),
+    )?;
+
+    Ok(())
+}
+
+fn handle_resolve_incident(ctx: Context<ResolveIncident>) -> PoolResult {
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+    require!(pool.incident_reason != 0, PoolError::NoIncident);
+
+    let old = (pool.incident_reason, pool.reserve_alarm);
+    pool.incident_reason = 0;
+    pool.reserve_alarm = 0;
+    record_config_change(
+        pool,
+        ctx.accounts.pool.key(),
+        ctx.accounts.config_change.as_mut(),
+        ConfigParam::Incident,
+        &old,
+        &(0u16, 0u8),
+        ctx.accounts.authority.key(),
+    )?;
+
+    Ok(())
+}
+
+fn handle_emergency_withdraw<'info>(
+    ctx: Context<'_, '_, '_, 'info, EmergencyWithdraw<'info>>,
+) -> PoolResult {
+    let (mint, nonce, payout, before) = {
+        let pool = &mut ctx.accounts.pool.load_mut()?;
+
+        require!(pool.incident_reason != 0, PoolError::NoIncident);
+        require!(
+            ctx.accounts.user_state.initialized,
+            PoolError::UserNotInitialized
+        );
+
+        let amount = ctx.accounts.user_state.staked_amount;
+        require!(amount > 0, PoolError::ZeroAmount);
+
+        let before = (pool.reserves(&ctx.accounts.vault)?, pool.liabilities()?);
+        let payout = pool.emergency_payout(&ctx.accounts.vault, amount)?;
+
+        // An incident doesn't lift the user's own protections.
+        let now = Clock::get()?.unix_timestamp;
+        let destination = ctx.accounts.user_mint_acc.key();
+        let user_state = &mut ctx.accounts.user_state;
+        user_state.authorize_cold_withdrawal(destination, amount, now)?;
+        user_state.authorize_withdrawal(ctx.accounts.guardian.as_ref(), payout, now)?;
+
+        // Rewards stay pending and can be claimed once the pool recovers.
+        update_rewards(pool, user_state, now)?;
+
+        pool.staked_total = pool.staked_total.safe_sub(amount)?;
+        ctx.accounts.user_state.staked_amount = 0;
+        book_wallet_stake(
+            pool,
+            &ctx.accounts.user_state,
+            ctx.accounts.wallet_state.as_mut(),
+            amount,
+        )?;
+        drop_link_boost(
+            &mut ctx.accounts.user_state,
+            ctx.accounts.link_boosted.as_ref(),
+        )?;
+
+        record_checkpoints(
+            pool,
+            ctx.accounts.pool.key(),
+            &ctx.accounts.user_state,
+            ctx.accounts.wallet_state.as_ref(),
+            ctx.accounts.authority.key(),
+            ctx.accounts.user_checkpoints.as_ref(),
+            ctx.accounts.pool_checkpoints.as_ref(),
+        )?;
+        record_audit(
+            pool,
+            ctx.accounts.pool.key(),
+            ctx.accounts.audit_log.as_ref(),
+            AuditOp::EmergencyWithdraw,
+            ctx.accounts.authority.key(),
+            payout,
+        )?;
+
+        (pool.mint, pool.nonce, payout, before)
+    };
+
+    let seeds = &[
+        mint.as_ref(),
+        ctx.accounts.pool.to_account_info().key.as_ref(),
+        &[nonce],
+    ];
+    let signer = &[&seeds[..]];
+
+    transfer_checked_with_hook(
+        &ctx.accounts.token_program,
+        ctx.accounts.vault.to_account_info(),
+        &ctx.accounts.mint,
+        ctx.accounts.user_mint_acc.to_account_info(),
+        ctx.accounts.program_signer.to_account_info(),
+        ctx.remaining_accounts,
+        payout,
+        signer,
+    )?;
+
+    ctx.accounts.vault.reload()?;
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+    assert_exit_invariants(pool, &ctx.accounts.vault, before)?;
+    pool.publish_state()
+}
+
+fn handle_clear_reserve_alarm(ctx: Context<ClearReserveAlarm>) -> PoolResult {
+    ctx.accounts.pool.load_mut()?.reserve_alarm = 0;
+
+    Ok(())
+}
+
+fn handle_initialize_audit_log(ctx: Context<InitializeAuditLog>) -> PoolResult {
+    ctx.accounts.audit_log.load_init()?.pool = ctx.accounts.pool.key();
+    ctx.accounts.pool.load_mut()?.audit_log_enabled = 1;
+
+    Ok(())
+}
+
+fn handle_set_disabled_instructions(
+    ctx: Context<SetDisabledInstructions>,
+    disabled: u64,
+) -> PoolResult {
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+
+    let old = pool.disabled_instructions;
+    pool.disabled_instructions = disabled;
+    record_config_change(
+        pool,
+        ctx.accounts.pool.key(),
+        ctx.accounts.config_change.as_mut(),
+        ConfigParam::DisabledInstructions,
+        &old,
+        &disabled,
+        ctx.accounts.authority.key(),
+    )?;
+
+    Ok(())
+}
+
+fn handle_pause(ctx: Context<SetPaused>, freeze_rewards: bool) -> PoolResult {
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+    require!(pool.paused == 0, PoolError::PoolPaused);
+
+    let now = Clock::get()?.unix_timestamp;
+    pool.update_reward_index(now)?;
+
+    pool.paused = 1;
+    pool.pause_freezes_rewards = freeze_rewards as u8;
+    pool.paused_at = now;
+    record_config_change(
+        pool,
+        ctx.accounts.pool.key(),
+        ctx.accounts.config_change.as_mut(),
+        ConfigParam::Paused,
+        &(false, false),
+        &(true, freeze_rewards),
+        ctx.accounts.authority.key(),
+    )?;
+
+    Ok(())
+}
+
+fn handle_unpause(ctx: Context<SetPaused>) -> PoolResult {
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+    require!(pool.paused != 0, PoolError::PoolPaused);
+
+    let now = Clock::get()?.unix_timestamp;
+    pool.update_reward_index(now)?;
+
+    // Push the schedule back by the frozen time, so the pause is cut out of
+    // the emission curve rather than emitted in one go afterwards. A
+    // schedule set during the pause only lost the time since it was set.
+    if pool.rewards_frozen() {
+        let frozen = now - pool.paused_at.max(pool.emission_start_ts);
+        pool.emission_start_ts = pool
+            .emission_start_ts
+            .checked_add(frozen.max(0))
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        if pool.emission_stop_ts != 0 {
+            pool.emission_stop_ts = pool
+                .emission_stop_ts
+                .checked_add(frozen.max(0))
+                .ok_or(ProgramError::ArithmeticOverflow)?;
+        }
+    }
+
+    pool.total_paused_duration = pool
+        .total_paused_duration
+        .checked_add(now - pool.paused_at)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    let old = (true, pool.pause_freezes_rewards != 0);
+    pool.paused = 0;
+    pool.pause_freezes_rewards = 0;
+    record_config_change(
+        pool,
+        ctx.accounts.pool.key(),
+        ctx.accounts.config_change.as_mut(),
+        ConfigParam::Paused,
+        &old,
+        &(false, false),
+        ctx.accounts.authority.key(),
+    )?;
+
+    Ok(())
+}
+
+fn handle_set_dust_policy(ctx: Context<SetDustPolicy>, policy: DustPolicy) -> PoolResult {
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+
+    // Close out the index under the old policy.
+    pool.update_reward_index(Clock::get()?.unix_timestamp)?;
+
+    let old = pool.dust_policy();
+    pool.dust_policy = policy as u8;
+    record_config_change(
+        pool,
+        ctx.accounts.pool.key(),
+        ctx.accounts.config_change.as_mut(),
+        ConfigParam::DustPolicy,
+        &old,
+        &policy,
+        ctx.accounts.authority.key(),
+    )?;
+
+    Ok(())
+}
+
+fn handle_sweep_dust(ctx: Context<SweepDust>) -> PoolResult {
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+
+    pool.update_reward_index(Clock::get()?.unix_timestamp)?;
+    let amount = pool.take_dust()?;
+    require!(amount > 0, PoolError::ZeroAmount);
+    pool.record_distribution(amount)?;
+
+    let seeds = &[
+        pool.mint.as_ref(),
+        ctx.accounts.pool.to_account_info().key.as_ref(),
+        &[pool.nonce],
+    ];
+    let signer = &[&seeds[..]];
+
+    mint_rewards(
+        &ctx.accounts.token_program,
+        &ctx.accounts.mint,
+        ctx.accounts.destination.to_account_info(),
+        ctx.accounts.program_signer.to_account_info(),
+        amount,
+        signer,
+    )?;
+
+    emit!(DustSwept {
+        pool: ctx.accounts.pool.key(),
+        destination: ctx.accounts.destination.key(),
+        amount,
+    });
+
+    Ok(())
+}
+
+fn handle_set_reward_redirect(
+    ctx: Context<SetRewardRedirect>,
+    bps: u16,
+    destination: Option<Pubkey>,
+) -> PoolResult {
+    require!(bps <= MAX_REDIRECT_BPS, PoolError::InvalidRedirect);
+
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+
+    // Emissions so far go out under the old split.
+    pool.update_reward_index(Clock::get()?.unix_timestamp)?;
+
+    // The share accrued so far goes where it was meant to.
+    require!(
+        destination.unwrap_or_default() == pool.redirect_destination
+            || pool.redirected_rewards < REWARD_PRECISION,
+        PoolError::RedirectPending
+    );
+
+    let old = (pool.redirect_bps, pool.redirect_destination);
+    pool.redirect_bps = bps;
+    pool.redirect_destination = destination.unwrap_or_default();
+    let new = (bps, pool.redirect_destination);
+    record_config_change(
+        pool,
+        ctx.accounts.pool.key(),
+        ctx.accounts.config_change.as_mut(),
+        ConfigParam::RewardRedirect,
+        &old,
+        &new,
+        ctx.accounts.authority.key(),
+    )?;
+
+    Ok(())
+}
+
+fn handle_distribute_redirected_rewards(ctx: Context<DistributeRedirectedRewards>) -> PoolResult {
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+
+    pool.update_reward_index(Clock::get()?.unix_timestamp)?;
+    let amount = pool.take_redirected()?;
+
+    let (mint, nonce) = (pool.mint, pool.nonce);
+    let seeds = &[
+        mint.as_ref(),
+        ctx.accounts.pool.to_account_info().key.as_ref(),
+        &[nonce],
+    ];
+    let signer = &[&seeds[..]];
+
+    let (destination, amount) = match &ctx.accounts.destination {
+        Some(destination) => {
+            require!(amount > 0, PoolError::ZeroAmount);
+            mint_rewards(
+                &ctx.accounts.token_program,
+                &ctx.accounts.mint,
+                destination.to_account_info(),
+                ctx.accounts.program_signer.to_account_info(),
+                amount,
+                signer,
+            )?;
+            pool.record_distribution(amount)?;
+
+            (Some(destination.key()), amount)
+        }
+        None => {
+            require!(
+                pool.redirect_destination == Pubkey::default(),
+                PoolError::InvalidRedirect
+            );
+
+            // Burned out of the funded rewards in the vault; what they don't
+            // cover yet waits for more yield.
+            let burned = amount.min(pool.funded_rewards);
+            require!(burned > 0, PoolError::ZeroAmount);
+            pool.funded_rewards = pool.funded_rewards.safe_sub(burned)?;
+            pool.redirected_rewards = pool
+                .redirected_rewards
+                .safe_add((amount.safe_sub(burned)? as u128).safe_mul(REWARD_PRECISION)?)?;
+
+            token_interface::burn(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    Burn {
+                        mint: ctx.accounts.mint.to_account_info(),
+                        from: ctx.accounts.vault.to_account_info(),
+                        authority: ctx.accounts.program_signer.to_account_info(),
+                    },
+                    signer,
+                ),
+                burned,
+            )?;
+
+            (None, burned)
+        }
+    };
+
+    emit!(RewardsRedirected {
+        pool: ctx.accounts.pool.key(),
+        destination,
+        amount,
+    });
+
+    ctx.accounts.vault.reload()?;
+    assert_pool_invariants(pool, &ctx.accounts.vault)?;
+
+    Ok(())
+}
+
+fn handle_create_deposit_schedule(
+    ctx: Context<CreateDepositSchedule>,
+    amount: u64,
+    interval: i64,
+) -> PoolResult {
+    require!(
+        amount > 0 && interval > 0,
+        PoolError::InvalidDepositSchedule
+    );
+
+    let schedule = &mut ctx.accounts.deposit_schedule;
+    schedule.pool = ctx.accounts.pool.key();
+    schedule.user = ctx.accounts.authority.key();
+    schedule.funding = ctx.accounts.funding.key();
+    schedule.amount = amount;
+    schedule.interval = interval;
+    schedule.next_execution_ts = Clock::get()?.unix_timestamp;
+
+    Ok(())
+}
+
+fn handle_execute_scheduled_deposit<'info>(
+    ctx: Context<'_, '_, '_, 'info, ExecuteScheduledDeposit<'info>>,
+) -> PoolResult {
+    let now = Clock::get()?.unix_timestamp;
+    let schedule = &mut ctx.accounts.deposit_schedule;
+    require_gte!(now, schedule.next_execution_ts, PoolError::DepositNotDue);
+    schedule.next_execution_ts = now
+        .checked_add(schedule.interval)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+
+    let amount = schedule.amount;
+    require!(
+        ctx.accounts.funding.delegate == CSome(ctx.accounts.program_signer.key())
+            && ctx.accounts.funding.delegated_amount >= amount,
+        PoolError::InsufficientDelegation
+    );
+
+    let received = amount.safe_sub(transfer_fee(&ctx.accounts.mint, amount)?)?;
+
+    let (mint, nonce) = {
+        let pool = &mut ctx.accounts.pool.load_mut()?;
+        pool.require_enabled(instruction_flags::SCHEDULED_DEPOSIT)?;
+        // Carries no memo.
+        require!(pool.memo_required == 0, PoolError::MemoRequired);
+
+        require_eq!(pool.mint, ctx.accounts.mint.key(), PoolError::InvalidMint);
+        require_eq!(
+            pool.vault,
+            ctx.accounts.vault.key(),
+            PoolError::InvalidVault
+        );
+
+        credit_stake(
+            pool,
+            ctx.accounts.pool.key(),
+            ctx.accounts.deposit_schedule.user,
+            &mut ctx.accounts.user_state,
+            received,
+            &ctx.accounts.config,
+            ctx.accounts.attestation.as_ref(),
+            ctx.accounts.leaderboard.as_mut(),
+            ctx.accounts.user_checkpoints.as_ref(),
+            ctx.accounts.pool_checkpoints.as_ref(),
+            ctx.accounts.fee_exemption.as_ref(),
+            ctx.accounts.wallet_state.as_mut(),
+            true,
+        )?;
+        record_audit(
+            pool,
+            ctx.accounts.pool.key(),
+            ctx.accounts.audit_log.as_ref(),
+            AuditOp::ScheduledDeposit,
+            ctx.accounts.deposit_schedule.user,
+            received,
+        )?;
+
+        (pool.mint, pool.nonce)
+    };
+
+    let seeds = &[
+        mint.as_ref(),
+        ctx.accounts.pool.to_account_info().key.as_ref(),
+        &[nonce],
+    ];
+    let signer = &[&seeds[..]];
+
+    // The program signer spends the user's delegate approval.
+    transfer_checked_with_hook(
+        &ctx.accounts.token_program,
+        ctx.accounts.funding.to_account_info(),
+        &ctx.accounts.mint,
+        ctx.accounts.vault.to_account_info(),
+        ctx.accounts.program_signer.to_account_info(),
+        ctx.remaining_accounts,
+        amount,
+        signer,
+    )?;
+
+    ctx.accounts.vault.reload()?;
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+    assert_pool_invariants(pool, &ctx.accounts.vault)?;
+    pool.publish_state()
+}
+
+fn handle_pull_stake<'info>(
+    ctx: Context<'_, '_, '_, 'info, PullStake<'info>>,
+    amount: u64,
+) -> PoolResult {
+    require!(amount > 0, PoolError::ZeroAmount);
+    let self_signed = ctx.accounts.authority.key() == ctx.accounts.user.key();
+    require!(
+        ctx.accounts.funding.delegate == CSome(ctx.accounts.program_signer.key())
+            && ctx.accounts.funding.delegated_amount >= amount,
+        PoolError::InsufficientDelegation
+    );
+
+    let received = amount.safe_sub(transfer_fee(&ctx.accounts.mint, amount)?)?;
+
+    let (mint, nonce) = {
+        let pool = &mut ctx.accounts.pool.load_mut()?;
+        pool.require_enabled(instruction_flags::PULL_STAKE)?;
+        // Carries no memo.
+        require!(pool.memo_required == 0, PoolError::MemoRequired);
+
+        require_eq!(pool.mint, ctx.accounts.mint.key(), PoolError::InvalidMint);
+        require_eq!(
+            pool.vault,
+            ctx.accounts.vault.key(),
+            PoolError::InvalidVault
+        );
+
+        credit_stake(
+            pool,
+            ctx.accounts.pool.key(),
+            ctx.accounts.user.key(),
+            &mut ctx.accounts.user_state,
+            received,
+            &ctx.accounts.config,
+            ctx.accounts.attestation.as_ref(),
+            ctx.accounts.leaderboard.as_mut(),
+            ctx.accounts.user_checkpoints.as_ref(),
+            ctx.accounts.pool_checkpoints.as_ref(),
+            ctx.accounts.fee_exemption.as_ref(),
+            ctx.accounts.wallet_state.as_mut(),
+            self_signed,
+        )?;
+        record_audit(
+            pool,
+            ctx.accounts.pool.key(),
+            ctx.accounts.audit_log.as_ref(),
+            AuditOp::PullStake,
+            ctx.accounts.user.key(),
+            received,
+        )?;
+
+        (pool.mint, pool.nonce)
+    };
+
+    let seeds = &[
+        mint.as_ref(),
+        ctx.accounts.pool.to_account_info().key.as_ref(),
+        &[nonce],
+    ];
+    let signer = &[&seeds[..]];
+
+    transfer_checked_with_hook(
+        &ctx.accounts.token_program,
+        ctx.accounts.funding.to_account_info(),
+        &ctx.accounts.mint,
+        ctx.accounts.vault.to_account_info(),
+        ctx.accounts.program_signer.to_account_info(),
+        ctx.remaining_accounts,
+        amount,
+        signer,
+    )?;
+
+    ctx.accounts.vault.reload()?;
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+    assert_pool_invariants(pool, &ctx.accounts.vault)?;
+    pool.publish_state()
+}
+
+fn handle_set_integrator(
+    ctx: Context<SetIntegrator>,
+    integrator: Option<Pubkey>,
+    allowance: u64,
+) -> PoolResult {
+    let user_state = &mut ctx.accounts.user_state;
+
+    match integrator {
+        Some(integrator) => {
+            require!(
+                integrator != Pubkey::default(),
+                PoolError::InvalidIntegrator
+            );
+
+            user_state.integrator = integrator;
+            user_state.integrator_allowance = allowance;
+        }
+        None => {
+            user_state.integrator = Pubkey::default();
+            user_state.integrator_allowance = 0;
+        }
+    }
+
+    Ok(())
+}
+
+fn handle_set_puller(ctx: Context<SetPuller>, puller: Option<Pubkey>) -> PoolResult {
+    ctx.accounts.user_state.puller = puller.unwrap_or_default();
+
+    Ok(())
+}
+
+fn handle_integrator_stake<'info>(
+    ctx: Context<'_, '_, '_, 'info, IntegratorStake<'info>>,
+    amount: u64,
+) -> PoolResult {
+    require!(amount > 0, PoolError::ZeroAmount);
+    ctx.accounts
+        .user_state
+        .spend_allowance(ctx.accounts.integrator_authority.key(), amount)?;
+
+    let received = amount.safe_sub(transfer_fee(&ctx.accounts.mint, amount)?)?;
+
+    {
+        let pool = &mut ctx.accounts.pool.load_mut()?;
+        pool.require_enabled(instruction_flags::ENTER_STAKING)?;
+        // Carries no memo.
+        require!(pool.memo_required == 0, PoolError::MemoRequired);
+
+        require_eq!(pool.mint, ctx.accounts.mint.key(), PoolError::InvalidMint);
+        require_eq!(
+            pool.vault,
+            ctx.accounts.vault.key(),
+            PoolError::InvalidVault
+        );
+
+        credit_stake(
+            pool,
+            ctx.accounts.pool.key(),
+            ctx.accounts.user.key(),
+            &mut ctx.accounts.user_state,
+            received,
+            &ctx.accounts.config,
+            None,
+            None,
+            ctx.accounts.user_checkpoints.as_ref(),
+            ctx.accounts.pool_checkpoints.as_ref(),
+            ctx.accounts.fee_exemption.as_ref(),
+            ctx.accounts.wallet_state.as_mut(),
+            false,
+        )?;
+        record_audit(
+            pool,
+            ctx.accounts.pool.key(),
+            ctx.accounts.audit_log.as_ref(),
+            AuditOp::IntegratorStake,
+            ctx.accounts.user.key(),
+            received,
+        )?;
+    }
+
+    emit!(Staked {
+        pool: ctx.accounts.pool.key(),
+        user: ctx.accounts.user.key(),
+        amount: received,
+        tag: None,
+        label: ctx.accounts.user_state.label,
+    });
+
+    // The integrator's PDA already signed the outer instruction.
+    transfer_checked_with_hook(
+        &ctx.accounts.token_program,
+        ctx.accounts.funding.to_account_info(),
+        &ctx.accounts.mint,
+        ctx.accounts.vault.to_account_info(),
+        ctx.accounts.integrator_authority.to_account_info(),
+        ctx.remaining_accounts,
+        amount,
+        &[],
+    )?;
+
+    ctx.accounts.vault.reload()?;
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+    assert_pool_invariants(pool, &ctx.accounts.vault)?;
+    pool.publish_state()
+}
+
+fn handle_integrator_unstake<'info>(
+    ctx: Context<'_, '_, '_, 'info, IntegratorUnstake<'info>>,
+    amount: u64,
+) -> PoolResult {
+    require!(amount > 0, PoolError::ZeroAmount);
+
+    let (mint, nonce, payout) = {
+        let pool = &mut ctx.accounts.pool.load_mut()?;
+        pool.require_enabled(instruction_flags::LEAVE_STAKING)?;
+        // Carries no memo.
+        require!(pool.memo_required == 0, PoolError::MemoRequired);
+        require!(pool.incident_reason == 0, PoolError::IncidentActive);
+        require!(pool.reserve_alarm == 0, PoolError::ReserveAlarm);
+
+        require_eq!(pool.mint, ctx.accounts.mint.key(), PoolError::InvalidMint);
+        require_eq!(
+            pool.vault,
+            ctx.accounts.vault.key(),
+            PoolError::InvalidVault
+        );
+
+        let pool_key = ctx.accounts.pool.key();
+        let user = ctx.accounts.user.key();
+        let user_state = &mut ctx.accounts.user_state;
+        require!(user_state.initialized, PoolError::UserNotInitialized);
+        // The guardian and the cold key keep their say over integrators.
+        require!(
+            user_state.cold_key == Pubkey::default(),
+            PoolError::ColdApprovalRequired
+        );
+        let now = Clock::get()?.unix_timestamp;
+        user_state.authorize_withdrawal(None, amount, now)?;
+        user_state.spend_allowance(ctx.accounts.integrator_authority.key(), amount)?;
+
+        let (fee, penalty) = match pool_config(pool, &ctx.accounts.config)? {
+            Some(config) => charge_withdrawal(
+                config,
+                pool_key,
+                user,
+                user_state,
+                amount,
+                now,
+                ctx.accounts.fee_exemption.is_some(),
+            )?,
+            None => (0, 0),
+        };
+        let charge = fee.safe_add(penalty)?;
+
+        update_rewards(pool, user_state, now)?;
+
+        // Queued withdrawals are paid out before instant ones.
+        require_gte!(
+            ctx.accounts.vault.amount,
+            pool.written_down(pool.queued_amount.safe_add(amount)?)?,
+            PoolError::InsufficientBuffer
+        );
+
+        pool.staked_total = pool.staked_total.safe_sub(amount)?;
+        pool.book_charges(fee, penalty)?;
+        let before = user_state.staked_amount;
+        user_state.staked_amount = before.safe_sub(amount)?;
+        book_wallet_stake(pool, user_state, ctx.accounts.wallet_state.as_mut(), before)?;
+        drop_link_boost(user_state, ctx.accounts.link_boosted.as_ref())?;
+
+        record_checkpoints(
+            pool,
+            pool_key,
+            user_state,
+            ctx.accounts.wallet_state.as_ref(),
+            user,
+            ctx.accounts.user_checkpoints.as_ref(),
+            ctx.accounts.pool_checkpoints.as_ref(),
+        )?;
+        record_audit(
+            pool,
+            pool_key,
+            ctx.accounts.audit_log.as_ref(),
+            AuditOp::IntegratorUnstake,
+            user,
+            amount,
+        )?;
+        emit!(Unstaked {
+            pool: pool_key,
+            user,
+            amount,
+            label: user_state.label,
+        });
+
+        (
+            pool.mint,
+            pool.nonce,
+            pool.written_down(amount.safe_sub(charge)?)?,
+        )
+    };
+
+    let seeds = &[
+        mint.as_ref(),
+        ctx.accounts.pool.to_account_info().key.as_ref(),
+        &[nonce],
+    ];
+    let signer = &[&seeds[..]];
+
+    transfer_checked_with_hook(
+        &ctx.accounts.token_program,
+        ctx.accounts.vault.to_account_info(),
+        &ctx.accounts.mint,
+        ctx.accounts.user_mint_acc.to_account_info(),
+        ctx.accounts.program_signer.to_account_info(),
+        ctx.remaining_accounts,
+        payout,
+        signer,
+    )?;
+
+    ctx.accounts.vault.reload()?;
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+    assert_pool_invariants(pool, &ctx.accounts.vault)?;
+    pool.publish_state()
+}
+
+fn handle_zap_stake<'info>(
+    ctx: Context<'_, '_, '_, 'info, ZapStake<'info>>,
+    amount: u64,
+    min_out: u64,
+) -> PoolResult {
+    require!(amount > 0, PoolError::ZeroAmount);
+    ctx.accounts
+        .pool
+        .load()?
+        .require_enabled(instruction_flags::ZAP_STAKE)?;
+
+    // The user owns the input, so their signature carries through to the
+    // adapter and the program signer isn't involved.
+    let before = ctx.accounts.vault.amount;
+    invoke_swap(
+        &ctx.accounts.swap_adapter,
+        ctx.accounts.authority.to_account_info(),
+        ctx.accounts.user_input_acc.to_account_info(),
+        ctx.accounts.vault.to_account_info(),
+        [
+            ctx.accounts.input_mint.to_account_info(),
+            ctx.accounts.mint.to_account_info(),
+        ],
+        [
+            ctx.accounts.input_token_program.to_account_info(),
+            ctx.accounts.token_program.to_account_info(),
+        ],
+        ctx.remaining_accounts,
+        amount,
+        min_out,
+        &[],
+    )?;
+    ctx.accounts.vault.reload()?;
+
+    // Only what actually landed in the vault is credited.
+    let received = ctx.accounts.vault.amount.safe_sub(before)?;
+    require_gte!(received, min_out, PoolError::SlippageExceeded);
+    require!(received > 0, PoolError::ZeroAmount);
+
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+    require_memo(pool, ctx.accounts.instructions.as_ref())?;
+
+    let before = ctx.accounts.user_state.staked_amount;
+    credit_stake(
+        pool,
+        ctx.accounts.pool.key(),
+        ctx.accounts.authority.key(),
+        &mut ctx.accounts.user_state,
+        received,
+        &ctx.accounts.config,
+        ctx.accounts.attestation.as_ref(),
+        ctx.accounts.leaderboard.as_mut(),
+        ctx.accounts.user_checkpoints.as_ref(),
+        ctx.accounts.pool_checkpoints.as_ref(),
+        ctx.accounts.fee_exemption.as_ref(),
+        ctx.accounts.wallet_state.as_mut(),
+        true,
+    )?;
+    record_audit(
+        pool,
+        ctx.accounts.pool.key(),
+        ctx.accounts.audit_log.as_ref(),
+        AuditOp::ZapStake,
+        ctx.accounts.authority.key(),
+        received,
+    )?;
+    record_on_profile(
+        ctx.accounts.user_profile.as_mut(),
+        ctx.accounts.pool.key(),
+        before,
+        ctx.accounts.user_state.staked_amount,
+    )?;
+    record_rate(ctx.accounts.rate_publisher.as_mut(), pool)?;
+
+    assert_pool_invariants(pool, &ctx.accounts.vault)?;
+    pool.publish_state()
+}
+
+fn handle_add_fee_exemption(ctx: Context<AddFeeExemption>, wallet: Pubkey) -> PoolResult {
+    let exemption = &mut ctx.accounts.fee_exemption;
+    exemption.pool = ctx.accounts.pool.key();
+    exemption.wallet = wallet;
+
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+    record_config_change(
+        pool,
+        ctx.accounts.pool.key(),
+        ctx.accounts.config_change.as_mut(),
+        ConfigParam::FeeExemption,
+        &(wallet, false),
+        &(wallet, true),
+        ctx.accounts.authority.key(),
+    )?;
+
+    Ok(())
+}
+
+fn handle_remove_fee_exemption(ctx: Context<RemoveFeeExemption>) -> PoolResult {
+    let wallet = ctx.accounts.fee_exemption.wallet;
+
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+    record_config_change(
+        pool,
+        ctx.accounts.pool.key(),
+        ctx.accounts.config_change.as_mut(),
+        ConfigParam::FeeExemption,
+        &(wallet, true),
+        &(wallet, false),
+        ctx.accounts.authority.key(),
+    )?;
+
+    Ok(())
+}
+
+fn handle_publish_migration_root(
+    ctx: Context<PublishMigrationRoot>,
+    merkle_root: [u8; 32],
+) -> PoolResult {
+    let migration = &mut ctx.accounts.migration;
+    migration.pool = ctx.accounts.pool.key();
+    migration.merkle_root = merkle_root;
+
+    Ok(())
+}
+
+fn handle_fund_migration<'info>(
+    ctx: Context<'_, '_, '_, 'info, FundMigration<'info>>,
+    amount: u64,
+) -> PoolResult {
+    require!(amount > 0, PoolError::ZeroAmount);
+
+    let received = amount.safe_sub(transfer_fee(&ctx.accounts.mint, amount)?)?;
+    {
+        let pool = &mut ctx.accounts.pool.load_mut()?;
+        pool.migration_escrow = pool.migration_escrow.safe_add(received)?;
+    }
+
+    transfer_checked_with_hook(
+        &ctx.accounts.token_program,
+        ctx.accounts.source.to_account_info(),
+        &ctx.accounts.mint,
+        ctx.accounts.vault.to_account_info(),
+        ctx.accounts.authority.to_account_info(),
+        ctx.remaining_accounts,
+        amount,
+        &[],
+    )?;
+
+    ctx.accounts.vault.reload()?;
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+    assert_pool_invariants(pool, &ctx.accounts.vault)?;
+    pool.publish_state()
+}
+
+fn handle_import_stake(ctx: Context<ImportStake>, amount: u64, proof: Vec<[u8; 32]>) -> PoolResult {
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+    pool.require_enabled(instruction_flags::IMPORT_STAKE)?;
+    let user = ctx.accounts.user.key();
+
+    require!(amount > 0, PoolError::ZeroAmount);
+    require!(
+        ctx.accounts.user_state.initialized,
+        PoolError::UserNotInitialized
+    );
+    let (_, unwound) = wallet_stake(&ctx.accounts.user_state, ctx.accounts.wallet_state.as_ref())?;
+    require!(!unwound, PoolError::UserUnwound);
+
+    let leaf = hashv(&[user.as_ref(), &amount.to_le_bytes()]).to_bytes();
+    require!(
+        verify_merkle_proof(&proof, &ctx.accounts.migration.merkle_root, leaf),
+        PoolError::InvalidMerkleProof
+    );
+
+    // Imported stake has to be backed by the escrow already in the vault.
+    pool.migration_escrow = pool
+        .migration_escrow
+        .checked_sub(amount)
+        .ok_or(PoolError::InsufficientEscrow)?;
+
+    update_rewards(
+        pool,
+        &mut ctx.accounts.user_state,
+        Clock::get()?.unix_timestamp,
+    )?;
+
+    pool.staked_total = pool.staked_total.safe_add(amount)?;
+    let before = ctx.accounts.user_state.staked_amount;
+    ctx.accounts.user_state.staked_amount = before.safe_add(amount)?;
+    book_wallet_stake(
+        pool,
+        &ctx.accounts.user_state,
+        ctx.accounts.wallet_state.as_mut(),
+        before,
+    )?;
+
+    record_checkpoints(
+        pool,
+        ctx.accounts.pool.key(),
+        &ctx.accounts.user_state,
+        ctx.accounts.wallet_state.as_ref(),
+        user,
+        ctx.accounts.user_checkpoints.as_ref(),
+        ctx.accounts.pool_checkpoints.as_ref(),
+    )?;
+    record_audit(
+        pool,
+        ctx.accounts.pool.key(),
+        ctx.accounts.audit_log.as_ref(),
+        AuditOp::ImportStake,
+        user,
+        amount,
+    )?;
+
+    let migration = &mut ctx.accounts.migration;
+    migration.imported_total = migration.imported_total.safe_add(amount)?;
+
+    let receipt = &mut ctx.accounts.import_receipt;
+    receipt.user = user;
+    receipt.amount = amount;
+
+    Ok(())
+}
+
+fn handle_commit_snapshot(
+    ctx: Context<CommitSnapshot>,
+    id: u64,
+    merkle_root: [u8; 32],
+) -> PoolResult {
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+
+    require_eq!(id, pool.snapshot_count, PoolError::InvalidSnapshot);
+    pool.snapshot_count = pool.snapshot_count.safe_add(1)?;
+
+    let snapshot = &mut ctx.accounts.snapshot;
+    snapshot.pool = ctx.accounts.pool.key();
+    snapshot.id = id;
+    snapshot.slot = Clock::get()?.slot;
+    snapshot.merkle_root = merkle_root;
+    snapshot.staked_total = pool.staked_total;
+
+    Ok(())
+}
+
+fn handle_verify_snapshot_proof(
+    ctx: Context<VerifySnapshotProof>,
+    user: Pubkey,
+    amount: u64,
+    proof: Vec<[u8; 32]>,
+) -> PoolResult<bool> {
+    let leaf = hashv(&[user.as_ref(), &amount.to_le_bytes()]).to_bytes();
+
+    Ok(verify_merkle_proof(
+        &proof,
+        &ctx.accounts.snapshot.merkle_root,
+        leaf,
+    ))
+}
+
+fn handle_start_lottery<'info>(
+    ctx: Context<'_, '_, '_, 'info, StartLottery<'info>>,
+    epoch: u64,
+    merkle_root: [u8; 32],
+    total_weight: u64,
+    winners: u8,
+    amount: u64,
+) -> PoolResult {
+    require!(amount > 0, PoolError::ZeroAmount);
+    require!(
+        total_weight > 0 && (1..=MAX_LOTTERY_WINNERS).contains(&winners),
+        PoolError::InvalidLottery
+    );
+
+    let before = ctx.accounts.vault.amount;
+    transfer_checked_with_hook(
+        &ctx.accounts.token_program,
+        ctx.accounts.authority_mint_acc.to_account_info(),
+        &ctx.accounts.mint,
+        ctx.accounts.vault.to_account_info(),
+        ctx.accounts.authority.to_account_info(),
+        ctx.remaining_accounts,
+        amount,
+        &[],
+    )?;
+    ctx.accounts.vault.reload()?;
+
+    // Prizes are paid like any funded rewards; dust from the split stays
+    // with the other claims.
+    let received = ctx.accounts.vault.amount.safe_sub(before)?;
+    let prize = received / winners as u64;
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+    pool.funded_rewards = pool.funded_rewards.safe_add(received)?;
+
+    let lottery = &mut ctx.accounts.lottery;
+    lottery.pool = ctx.accounts.pool.key();
+    lottery.epoch = epoch;
+    lottery.merkle_root = merkle_root;
+    lottery.total_weight = total_weight;
+    lottery.prize = prize;
+    lottery.winners = winners;
+    lottery.randomness_program = ctx.accounts.randomness_program.key();
+
+    emit!(LotteryStarted {
+        pool: lottery.pool,
+        epoch,
+        winners,
+        prize,
+    });
+
+    assert_pool_invariants(pool, &ctx.accounts.vault)?;
+    pool.publish_state()
+}
+
+fn handle_request_lottery_draw<'info>(
+    ctx: Context<'_, '_, '_, 'info, RequestLotteryDraw<'info>>,
+) -> PoolResult {
+    let lottery = &mut ctx.accounts.lottery;
+    // One request per lottery, so a cranker can't retry until they like
+    // the outcome.
+    require!(lottery.request_slot == 0, PoolError::InvalidRandomness);
+
+    invoke_randomness(
+        &ctx.accounts.randomness_program,
+        &ctx.accounts.randomness_account,
+        ctx.remaining_accounts,
+        "request",
+    )?;
+
+    lottery.randomness_account = ctx.accounts.randomness_account.key();
+    lottery.request_slot = Clock::get()?.slot;
+
+    Ok(())
+}
+
+fn handle_draw_lottery<'info>(ctx: Context<'_, '_, '_, 'info, DrawLottery<'info>>) -> PoolResult {
+    let lottery = &mut ctx.accounts.lottery;
+    require!(
+        lottery.request_slot > 0 && !lottery.drawn,
+        PoolError::InvalidRandomness
+    );
+
+    invoke_randomness(
+        &ctx.accounts.randomness_program,
+        &ctx.accounts.randomness_account,
+        ctx.remaining_accounts,
+        "reveal",
+    )?;
+
+    let (program, data) = get_return_data().ok_or(PoolError::InvalidRandomness)?;
+    require_keys_eq!(
+        program,
+        lottery.randomness_program,
+        PoolError::InvalidRandomness
+    );
+    let (seed_slot, randomness) = <(u64, [u8; 32])>::try_from_slice(&data)?;
+    require_gte!(
+        seed_slot,
+        lottery.request_slot,
+        PoolError::InvalidRandomness
+    );
+
+    lottery.randomness = randomness;
+    lottery.drawn = true;
+
+    emit!(LotteryDrawn {
+        pool: lottery.pool,
+        epoch: lottery.epoch,
+        randomness,
+    });
+
+    Ok(())
+}
+
+fn handle_claim_lottery_prize(
+    ctx: Context<ClaimLotteryPrize>,
+    winner: u8,
+    start: u64,
+    amount: u64,
+    proof: Vec<[u8; 32]>,
+) -> PoolResult {
+    let lottery = &mut ctx.accounts.lottery;
+    let user = ctx.accounts.authority.key();
+
+    let leaf = hashv(&[user.as_ref(), &start.to_le_bytes(), &amount.to_le_bytes()]).to_bytes();
+    require!(
+        verify_merkle_proof(&proof, &lottery.merkle_root, leaf),
+        PoolError::InvalidMerkleProof
+    );
+
+    let ticket = lottery.winning_ticket(winner);
+    require!(
+        start <= ticket && ticket - start < amount,
+        PoolError::NotAWinner
+    );
+    let prize = lottery.take_prize(winner)?;
+
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+    let user_state = &mut ctx.accounts.user_state;
+    require!(user_state.initialized, PoolError::UserNotInitialized);
+    update_rewards(pool, user_state, Clock::get()?.unix_timestamp)?;
+    user_state.pending_rewards = user_state.pending_rewards.safe_add(prize)?;
+
+    emit!(LotteryPrizeCredited {
+        pool: lottery.pool,
+        epoch: lottery.epoch,
+        winner,
+        user,
+        prize,
+    });
+
+    Ok(())
+}
+
+fn handle_initialize_stake_shard(ctx: Context<InitializeStakeShard>, index: u8) -> PoolResult {
+    require_gt!(MAX_STAKE_SHARDS, index, PoolError::InvalidShard);
+
+    let shard = &mut ctx.accounts.shard;
+    shard.pool = ctx.accounts.pool.key();
+    shard.index = index;
+    shard.vault = ctx.accounts.shard_vault.key();
+
+    Ok(())
+}
+
+fn handle_deposit_to_shard<'info>(
+    ctx: Context<'_, '_, '_, 'info, DepositToShard<'info>>,
+    amount: u64,
+) -> PoolResult {
+    // Only what actually lands in the shard is deposited.
+    let received = amount.safe_sub(transfer_fee(&ctx.accounts.mint, amount)?)?;
+    require!(received > 0, PoolError::ZeroAmount);
+
+    let shard = &mut ctx.accounts.shard;
+    let deposit = &mut ctx.accounts.shard_deposit;
+    // Each deposit belongs to one batch, so a merged one is credited first.
+    require!(
+        deposit.amount == 0 || deposit.generation == shard.generation,
+        PoolError::ShardDepositPending
+    );
+
+    {
+        let pool = ctx.accounts.pool.load()?;
+        pool.require_enabled(instruction_flags::ENTER_STAKING)?;
+        // Carries no memo.
+        require!(pool.memo_required == 0, PoolError::MemoRequired);
+        require!(pool.paused == 0, PoolError::PoolPaused);
+        require!(pool.incident_reason == 0, PoolError::IncidentActive);
+        require!(pool.haircut == 0, PoolError::ShortfallSettled);
+
+        let user_state = &ctx.accounts.user_state;
+        require!(user_state.initialized, PoolError::UserNotInitialized);
+        let (wallet_staked, unwound) =
+            wallet_stake(user_state, ctx.accounts.wallet_state.as_ref())?;
+        require!(!unwound, PoolError::UserUnwound);
+        require!(
+            pool.terms_hash == [0; 32] || user_state.terms_hash == pool.terms_hash,
+            PoolError::TermsNotAccepted
+        );
+
+        // The wallet's cap counts its pending deposit too; the pool's is
+        // checked by the merge.
+        if let Some(config) = pool_config(&pool, &ctx.accounts.config)? {
+            let user = ctx.accounts.authority.key();
+            let now = Clock::get()?.unix_timestamp;
+            let attested = config.is_attested(user, ctx.accounts.attestation.as_ref(), now);
+            let cap = config.user_cap(attested);
+            require!(
+                cap == 0 || wallet_staked.safe_add(deposit.amount)?.safe_add(received)? <= cap,
+                PoolError::StakeCapExceeded
+            );
+        }
+    }
+
+    deposit.shard = shard.key();
+    deposit.user = ctx.accounts.authority.key();
+    deposit.generation = shard.generation;
+    deposit.amount = deposit.amount.safe_add(received)?;
+    shard.pending_amount = shard.pending_amount.safe_add(received)?;
+
+    transfer_checked_with_hook(
+        &ctx.accounts.token_program,
+        ctx.accounts.user_mint_acc.to_account_info(),
+        &ctx.accounts.mint,
+        ctx.accounts.shard_vault.to_account_info(),
+        ctx.accounts.authority.to_account_info(),
+        ctx.remaining_accounts,
+        amount,
+        &[],
+    )?;
+
+    emit!(ShardDeposited {
+        pool: shard.pool,
+        shard: shard.index,
+        user: deposit.user,
+        amount: received,
+    });
+
+    Ok(())
+}
+
+fn handle_merge_stake_shard<'info>(
+    ctx: Context<'_, '_, '_, 'info, MergeStakeShard<'info>>,
+) -> PoolResult {
+    let amount = ctx.accounts.shard.pending_amount;
+    require!(amount > 0, PoolError::ZeroAmount);
+    let received = amount.safe_sub(transfer_fee(&ctx.accounts.mint, amount)?)?;
+
+    let (mint, nonce) = {
+        let pool = &mut ctx.accounts.pool.load_mut()?;
+        require!(pool.paused == 0, PoolError::PoolPaused);
+        require!(pool.incident_reason == 0, PoolError::IncidentActive);
+        require!(pool.haircut == 0, PoolError::ShortfallSettled);
+
+        // Over the cap the shard stays unmerged, and its depositors can take
+        // their deposits back.
+        if let Some(config) = pool_config(pool, &ctx.accounts.config)? {
+            let cap = config.params.max_staked_total;
+            require!(
+                cap == 0 || pool.staked_total.safe_add(received)? <= cap,
+                PoolError::StakeCapExceeded
+            );
+        }
+
+        // The deposits are stake from here on, whenever they are credited.
+        pool.update_reward_index(Clock::get()?.unix_timestamp)?;
+        pool.staked_total = pool.staked_total.safe_add(received)?;
+
+        let batch = &mut ctx.accounts.shard_batch;
+        batch.shard = ctx.accounts.shard.key();
+        batch.generation = ctx.accounts.shard.generation;
+        batch.reward_per_token = pool.reward_per_token;
+        batch.sol_reward_per_token = pool.sol_reward_per_token;
+        batch.deposited = amount;
+        batch.received = received;
+        batch.payer = ctx.accounts.payer.key();
+
+        (pool.mint, pool.nonce)
+    };
+
+    let shard = &mut ctx.accounts.shard;
+    shard.generation = shard.generation.safe_add(1)?;
+    shard.pending_amount = 0;
+
+    let seeds = &[
+        mint.as_ref(),
+        ctx.accounts.pool.to_account_info().key.as_ref(),
+        &[nonce],
+    ];
+    let signer = &[&seeds[..]];
+
+    transfer_checked_with_hook(
+        &ctx.accounts.token_program,
+        ctx.accounts.shard_vault.to_account_info(),
+        &ctx.accounts.mint,
+        ctx.accounts.vault.to_account_info(),
+        ctx.accounts.program_signer.to_account_info(),
+        ctx.remaining_accounts,
+        amount,
+        signer,
+    )?;
+
+    emit!(ShardMerged {
+        pool: shard.pool,
+        shard: shard.index,
+        generation: ctx.accounts.shard_batch.generation,
+        amount: received,
+    });
+
+    ctx.accounts.vault.reload()?;
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+    assert_pool_invariants(pool, &ctx.accounts.vault)?;
+    pool.publish_state()
+}
+
+fn handle_credit_shard_deposit(ctx: Context<CreditShardDeposit>) -> PoolResult {
+    let pool_key = ctx.accounts.pool.key();
+    let pool = &mut ctx.accounts.pool.load_mut()?;
+    let user = ctx.accounts.shard_deposit.user;
+    let user_state = &mut ctx.accounts.user_state;
+    require!(user_state.initialized, PoolError::UserNotInitialized);
+    // Accepted before any unwind, so it is credited regardless.
+    wallet_stake(user_state, ctx.accounts.wallet_state.as_ref())?;
+
+    let now = Clock::get()?.unix_timestamp;
+    update_rewards(pool, user_state, now)?;
+    record_on_leaderboard(
+        ctx.accounts.leaderboard.as_mut(),
+        pool_key,
+        pool,
+        user,
+        user_state,
+        ctx.accounts.wallet_state.as_mut(),
+        now,
+    )?;
+
+    let batch = &mut ctx.accounts.shard_batch;
+    let (share, rewards, sol_rewards) = batch.take(ctx.accounts.shard_deposit.amount, pool)?;
+    // The caps were checked by the deposit and the merge.
+    let config = pool_config(pool, &ctx.accounts.config)?;
+    let fee = match config {
+        Some(config) => {
+            let exempt = ctx.accounts.fee_exemption.is_some();
+            let fee = config.fee_on_deposit(share, exempt)?;
+            emit!(FeeCharged {
+                pool: pool_key,
+                user,
+                fee,
+                exempt,
+                label: user_state.label,
+            });
+
+            fee
+        }
+        None => 0,
+    };
+
+    // The merge already counted the whole share as stake.
+    pool.staked_total = pool.staked_total.safe_sub(fee)?;
+    pool.book_charges(fee, 0)?;
+    let credited = share.safe_sub(fee)?;
+    let before = user_state.staked_amount;
+    user_state.staked_amount = before.safe_add(credited)?;
+    book_wallet_stake(pool, user_state, ctx.accounts.wallet_state.as_mut(), before)?;
+    user_state.pending_rewards = user_state.pending_rewards.safe_add(rewards)?;
+    user_state.sol_pending_rewards = user_state.sol_pending_rewards.safe_add(sol_rewards)?;
+    if let Some(config) = config {
+        user_state.locked_until = now
+            .checked_add(config.params.lock_duration)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+    }
+
+    record_checkpoints(
+        pool,
+        pool_key,
+        user_state,
+        ctx.accounts.wallet_state.as_ref(),
+        user,
+        ctx.accounts.user_checkpoints.as_ref(),
+        ctx.accounts.pool_checkpoints.as_ref(),
+    )?;
+    record_audit(
+        pool,
+        pool_key,
+        ctx.accounts.audit_log.as_ref(),
+        AuditOp::ShardDeposit,
+        user,
+        credited,
+    )?;
+
+    emit!(ShardDepositCredited {
+        pool: pool_key,
+        user,
+        amount: credited,
+        rewards,
+    });
+
+    if batch.deposited == 0 {
+        batch.close(ctx.accounts.batch_payer.to_account_info())?;
+    }
+
+    Ok(())
+}
+
+fn handle_withdraw_shard_deposit<'info>(
+    ctx: Context<'_, '_, '_, 'info, WithdrawShardDeposit<'info>>,
+) -> PoolResult {
+    let shard = &mut ctx.accounts.shard;
+    let amount = ctx.accounts.shard_deposit.amount;
+    require_eq!(
+        ctx.accounts.shard_deposit.generation,
+        shard.generation,
+        PoolError::InvalidShard
+    );
+    shard.pending_amount = shard.pending_amount.safe_sub(amount)?;
+
+    let (mint, nonce) = {
+        let pool = ctx.accounts.pool.load()?;
+        (pool.mint, pool.nonce)
+    };
+    let seeds = &[
+        mint.as_ref(),
+        ctx.accounts.pool.to_account_info().key.as_ref(),
+        &[nonce],
+    ];
+    let signer = &[&seeds[..]];
+
+    transfer_checked_with_hook(
+        &ctx.accounts.token_program,
+        ctx.accounts.shard_vault.to_account_info(),
+        &ctx.accounts.mint,
+        ctx.accounts.user_mint_acc.to_account_info(),
+        ctx.accounts.program_signer.to_account_info(),
+        ctx.remaining_accounts,
+        amount,
+        signer,
+    )
+}
+
+fn handle_simulate_action(
+    ctx: Context<SimulateAction>,
+    action: SimulatedAction,
+    amount: u64,
+) -> PoolResult<ActionPreview> {
+    // Works on copies, so nothing is written back.
+    let mut pool = *ctx.accounts.pool.load()?;
+    let mut user_state = UserState::clone(&ctx.accounts.user_state);
+    require!(user_state.initialized, PoolError::UserNotInitialized);
+
+    let now = Clock::get()?.unix_timestamp;
+    let mint = &ctx.accounts.mint;
+    let user = ctx.accounts.user.key();
+    let exempt = ctx.accounts.fee_exemption.is_some();
+    let config = pool_config(&pool, &ctx.accounts.config)?;
+    update_rewards(&mut pool, &mut user_state, now)?;
+
+    let (fee, penalty, received, rewards) = match action {
+        SimulatedAction::Stake => {
+            require!(amount > 0, PoolError::ZeroAmount);
+            let landed = amount.safe_sub(transfer_fee(mint, amount)?)?;
+            let fee = match config {
+                Some(config) => {
+                    let attested = config.is_attested(user, ctx.accounts.attestation.as_ref(), now);
+                    let (wallet_staked, _) =
+                        wallet_stake(&ctx.accounts.user_state, ctx.accounts.wallet_state.as_ref())?;
+                    config.deposit_fee(landed, &pool, wallet_staked, attested, exempt)?
+                }
+                None => 0,
+            };
+            let credited = landed.safe_sub(fee)?;
+            pool.staked_total = pool.staked_total.safe_add(credited)?;
+            user_state.staked_amount = user_state.staked_amount.safe_add(credited)?;
+
+            (fee, 0, credited, 0)
+        }
+        SimulatedAction::Unstake => {
+            require!(amount > 0, PoolError::ZeroAmount);
+            let (fee, penalty) = match config {
+                Some(config) => config.withdrawal_charges(amount, &user_state, now, exempt)?,
+                None => (0, 0),
+            };
+            pool.staked_total = pool.staked_total.safe_sub(amount)?;
+            user_state.staked_amount = user_state.staked_amount.safe_sub(amount)?;
+
+            let payout = pool.written_down(amount.safe_sub(fee.safe_add(penalty)?)?)?;
+            let rewards = if user_state.staked_amount == 0 {
+                user_state.take_rewards(&mut pool, now, None)?
+            } else {
+                0
+            };
+
+            (
+                fee,
+                penalty,
+                payout.safe_sub(transfer_fee(mint, payout)?)?,
+                rewards,
+            )
+        }
+        SimulatedAction::Claim => {
+            let owed = user_state
+                .take_rewards(&mut pool, now, Some(amount))?
+                .safe_add(user_state.reward_iou)?;
+            let (funded, minted) = pool.split_rewards(owed)?;
+            let rewards = match can_mint_rewards(&pool, mint) {
+                true => funded.safe_add(minted)?,
+                false => funded,
+            };
+
+            (0, 0, 0, rewards)
+        }
+    };
+
+    Ok(ActionPreview {
+        fee,
+        penalty,
+        received,
+        rewards,
+        staked_amount: user_state.staked_amount,
+        staked_total: pool.staked_total,
+        pending_rewards: user_state.pending_rewards,
+    })
+}
+
+fn handle_get_pool_stats(ctx: Context<GetPoolStats>) -> PoolResult<PoolStats> {
+    let pool = &ctx.accounts.pool.load()?;
+    let now = Clock::get()?.unix_timestamp;
+
+    let stopped = pool.emission_stop_ts != 0 && now >= pool.emission_stop_ts;
+    let (emission_rate, apr_bps) = if stopped {
+        (0, 0)
+    } else if pool.dynamic_apr != 0 {
+        let apr_bps = pool.current_apr_bps();
+        let rate = (pool.staked_total as u128)
+            .safe_mul(apr_bps)?
+            .safe_div(BPS_DENOMINATOR.safe_mul(SECONDS_PER_YEAR)?)?;
+
+        (rate, apr_bps)
+    } else {
+        let rate = pool.emission_rate_at(now - pool.emission_start_ts)?;
+        let apr_bps = match pool.staked_total {
+            0 => 0,
+            staked => rate
+                .safe_mul(SECONDS_PER_YEAR)?
+                .safe_mul(BPS_DENOMINATOR)?
+                .safe_div(staked as u128)?,
+        };
+
+        (rate, apr_bps)
+    };
+
+    let emission_end_ts = match pool.dynamic_apr {
+        0 => pool.emission_end_ts(now)?,
+        _ => Some(pool.emission_stop_ts).filter(|stop| *stop != 0),
+    };
+
+    Ok(PoolStats {
+        staked_total: pool.staked_total,
+        emission_rate: u64::try_from(emission_rate).unwrap_or(u64::MAX),
+        apr_bps: u64::try_from(apr_bps).unwrap_or(u64::MAX),
+        apy_bps: apy_bps(apr_bps),
+        emission_end_ts,
+        seconds_to_emission_end: emission_end_ts.map(|end| (end - now).max(0)),
+        total_rewards_distributed: pool.total_rewards_distributed,
+        total_fees_collected: pool.total_fees_collected,
+        total_penalties: pool.total_penalties,
+    })
+}
+
+/// Declares the program with `$instructions`, plus the `wormhole` feature's
+/// when it is on. `#[program]` ignores `#[cfg]` on instructions, so each
+/// build gets its own module.
+macro_rules! staking_pool_program {
+    ($($instructions:tt)*) => {
+        #[cfg(feature = "wormhole")]
+        #[program]
+        pub mod staking_pool {
+            $($instructions)*
+
+            /// Publishes the caller's stake and pending rewards as a Wormhole
+            /// message from the program's emitter, for partner contracts on
+            /// other chains. The payer covers the pool's attestation fee and
+            /// the bridge fee.
+            pub fn attest_stake(ctx: Context<AttestStake>, nonce: u32) -> PoolResult {
+                handle_attest_stake(ctx, nonce)
+            }
+
+            /// Sets the lamports `attest_stake` charges, paid to the authority.
+            pub fn set_attestation_fee(ctx: Context<SetAttestationFee>, fee: u64) -> PoolResult {
+                handle_set_attestation_fee(ctx, fee)
+            }
+        }
+
+        #[cfg(not(feature = "wormhole"))]
+        #[program]
+        pub mod staking_pool {
+            $($instructions)*
+        }
+    };
+}
+
+staking_pool_program! {
+    use super::*;
+
+    pub fn initialize(ctx: Context<Initialize>) -> PoolResult {
+        handle_initialize(ctx)
+    }
+
+    /// `initialize` with the economic parameters of `template_pool`, for
+    /// launching many similar pools.
+    pub fn clone_pool(ctx: Context<ClonePool>) -> PoolResult {
+        handle_clone_pool(ctx)
+    }
+
+    /// Creates the protocol's `GlobalConfig`. Only the program's upgrade
+    /// authority can, and it becomes the config admin.
+    pub fn initialize_global_config(
+        ctx: Context<InitializeGlobalConfig>,
+        allowlist_only: bool,
+        permissioned: bool,
+    ) -> PoolResult {
+        handle_initialize_global_config(ctx, allowlist_only, permissioned)
+    }
+
+    /// Copies the program's current upgrade authority into the
+    /// `GlobalConfig`, after it was handed over. Anyone can. An immutable
+    /// program has none, which closes pool creation while permissioned.
+    pub fn sync_upgrade_authority(ctx: Context<SyncUpgradeAuthority>) -> PoolResult {
+        handle_sync_upgrade_authority(ctx)
+    }
+
+    pub fn set_global_config(
+        ctx: Context<SetGlobalConfig>,
+        admin: Pubkey,
+        allowlist_only: bool,
+        permissioned: bool,
+    ) -> PoolResult {
+        handle_set_global_config(ctx, admin, allowlist_only, permissioned)
+    }
+
+    /// Allows or blocks `mint` for new pools. Delist and list again to
+    /// change the verdict.
+    pub fn list_mint(ctx: Context<ListMint>, mint: Pubkey, allowed: bool) -> PoolResult {
+        handle_list_mint(ctx, mint, allowed)
+    }
+
+    pub fn delist_mint(ctx: Context<DelistMint>) -> PoolResult {
+        handle_delist_mint(ctx)
+    }
+
+    /// Mints `amounts[i]` to the `i`th remaining account, for seeding many
+    /// users at once. The amounts come out of the pool's distribution cap,
+    /// so a malformed list can't mint more than intended.
+    pub fn distribute<'info>(
+        ctx: Context<'_, '_, '_, 'info, Distribute<'info>>,
+        amounts: Vec<u64>,
+    ) -> PoolResult {
+        handle_distribute(ctx, amounts)
+    }
+
+    /// Sets how much `distribute` may still mint.
+    pub fn set_distribution_cap(ctx: Context<SetDistributionCap>, cap: u64) -> PoolResult {
+        handle_set_distribution_cap(ctx, cap)
+    }
+
+    /// Creates the user state of `authority`. `payer` covers the rent, so a
+    /// dApp can sponsor users who don't hold SOL yet.
+    pub fn initialize_user_state(ctx: Context<InitializeUserState>) -> PoolResult {
+        handle_initialize_user_state(ctx)
+    }
+
+    /// Creates another position of `authority` in the pool, so a custodian
+    /// can keep clients' stake apart under one key. Index 0 is the default
+    /// position `initialize_user_state` creates, which has to exist first.
+    /// From then on the wallet's `WalletState` holds its stake across
+    /// positions, for the per-user cap and force unwinding, and every stake
+    /// change passes it. `label` names the position in its events.
+    pub fn initialize_user_position(
+        ctx: Context<InitializeUserPosition>,
+        index: u16,
+        label: Option<[u8; 16]>,
+    ) -> PoolResult {
+        handle_initialize_user_position(ctx, index, label)
+    }
+
+    /// Renames one of the caller's indexed positions.
+    pub fn set_position_label(
+        ctx: Context<SetPositionLabel>,
+        label: Option<[u8; 16]>,
+    ) -> PoolResult {
+        handle_set_position_label(ctx, label)
+    }
+
+    /// Creates the caller's profile, which stake changes passing it keep in
+    /// sync across pools.
+    pub fn initialize_user_profile(ctx: Context<InitializeUserProfile>) -> PoolResult {
+        handle_initialize_user_profile(ctx)
+    }
+
+    /// Creates the user state of `authority` with rent paid by `payer`, so
+    /// operators can set up wallets ahead of a migration.
+    pub fn init_user_state_for(ctx: Context<InitUserStateFor>, authority: Pubkey) -> PoolResult {
+        handle_init_user_state_for(ctx, authority)
+    }
+
+    /// Grows a pool from an older layout. One written before
+    /// `Pool::layout_version` existed also gets what `initialize` would
+    /// have set, signed by the mint's freeze authority, who becomes the
+    /// pool authority.
+    pub fn migrate_pool(ctx: Context<MigratePool>) -> PoolResult {
+        handle_migrate_pool(ctx)
+    }
+
+    /// Grows the user's default position to the current layout. Needed
+    /// once per position from before the pool's migration.
+    pub fn migrate_user_state(ctx: Context<MigrateUserState>) -> PoolResult {
+        handle_migrate_user_state(ctx)
+    }
+
+    /// Closes a user state without stake, rewards or activity for the
+    /// pool config's `gc_inactivity`. Anyone can crank it for the
+    /// `gc_cranker_bps` share of the rent; the payer gets the rest.
+    pub fn gc_user_state(ctx: Context<GcUserState>) -> PoolResult {
+        handle_gc_user_state(ctx)
+    }
+
+    /// `tag` is only echoed in the `Staked` event, for depositors that need
+    /// to attribute the stake off-chain.
+    pub fn enter_staking<'info>(
+        ctx: Context<'_, '_, '_, 'info, EnterStaking<'info>>,
+        amount: u64,
+        tag: Option<[u8; 32]>,
+    ) -> PoolResult {
+        handle_enter_staking(ctx, amount, tag)
+    }
+
+    pub fn leave_staking<'info>(
+        ctx: Context<'_, '_, '_, 'info, LeaveStaking<'info>>,
+        amount: u64,
+    ) -> PoolResult {
+        handle_leave_staking(ctx, amount)
+    }
+
+    /// `enter_staking` with versioned arguments, which new parameters are
+    /// added to instead of new instructions.
+    pub fn stake<'info>(
+        ctx: Context<'_, '_, '_, 'info, EnterStaking<'info>>,
+        args: StakeArgs,
+    ) -> PoolResult {
+        match args {
+            StakeArgs::V1 { amount, tag } => handle_enter_staking(ctx, amount, tag),
+        }
+    }
+
+    /// `leave_staking` with versioned arguments.
+    pub fn unstake<'info>(
+        ctx: Context<'_, '_, '_, 'info, LeaveStaking<'info>>,
+        args: UnstakeArgs,
+    ) -> PoolResult {
+        match args {
+            UnstakeArgs::V1 { amount } => handle_leave_staking(ctx, amount),
+        }
+    }
+
+    /// Unstakes whatever it takes for exactly `net_amount` to reach the
+    /// user after withdrawal fees, penalties and the mint's transfer fee.
+    pub fn leave_staking_exact_out<'info>(
+        ctx: Context<'_, '_, '_, 'info, LeaveStaking<'info>>,
+        net_amount: u64,
+    ) -> PoolResult {
+        handle_leave_staking_exact_out(ctx, net_amount)
+    }
+
+    /// Emits `emission_rate` per `schedule` until `end_ts`. The reward
+    /// reserve must already cover everything up to then.
+    pub fn set_emission_schedule(
+        ctx: Context<SetEmissionSchedule>,
+        emission_rate: u64,
+        schedule: EmissionSchedule,
+        end_ts: i64,
+    ) -> PoolResult {
+        handle_set_emission_schedule(ctx, emission_rate, schedule, end_ts)
+    }
+
+    /// Adds `amount` of the pool's mint to the reserve backing emissions.
+    pub fn fund_emissions<'info>(
+        ctx: Context<'_, '_, '_, 'info, FundEmissions<'info>>,
+        amount: u64,
+    ) -> PoolResult {
+        handle_fund_emissions(ctx, amount)
+    }
+
+    /// Moves the end of the emission period out to `end_ts`, as far as the
+    /// reserve covers.
+    pub fn extend_emissions(ctx: Context<ExtendEmissions>, end_ts: i64) -> PoolResult {
+        handle_extend_emissions(ctx, end_ts)
+    }
+
+    pub fn set_dynamic_apr(
+        ctx: Context<SetDynamicApr>,
+        enabled: bool,
+        utilization_target: u64,
+        min_apr_bps: u32,
+        max_apr_bps: u32,
+        end_ts: i64,
+    ) -> PoolResult {
+        handle_set_dynamic_apr(
+            ctx,
+            enabled,
+            utilization_target,
+            min_apr_bps,
+            max_apr_bps,
+            end_ts,
+        )
+    }
+
+    pub fn set_streak_boost(
+        ctx: Context<SetStreakBoost>,
+        epoch_duration: i64,
+        boost_bps_per_epoch: u32,
+        max_boost_bps: u32,
+    ) -> PoolResult {
+        handle_set_streak_boost(ctx, epoch_duration, boost_bps_per_epoch, max_boost_bps)
+    }
+
+    /// Claims `amount` of the pending rewards, or all of them for `None` or
+    /// 0. The rest keeps accruing. Funded rewards are paid from the vault;
+    /// while the program signer can't mint, the rest is kept as an IOU and
+    /// paid by a later claim.
+    pub fn claim_rewards<'info>(
+        ctx: Context<'_, '_, '_, 'info, ClaimRewards<'info>>,
+        amount: Option<u64>,
+    ) -> PoolResult {
+        handle_claim_rewards(ctx, amount)
+    }
+
+    /// `claim_rewards` with versioned arguments.
+    pub fn claim<'info>(
+        ctx: Context<'_, '_, '_, 'info, ClaimRewards<'info>>,
+        args: ClaimArgs,
+    ) -> PoolResult {
+        match args {
+            ClaimArgs::V1 { amount } => handle_claim_rewards(ctx, amount),
+        }
+    }
+
+    /// `claim_rewards` with the rewards swapped into the pool's claim output
+    /// mint through its claim adapter, failing if less than `min_out`
+    /// arrives. Fails instead of leaving an IOU.
+    pub fn claim_rewards_and_swap<'info>(
+        ctx: Context<'_, '_, '_, 'info, ClaimRewardsAndSwap<'info>>,
+        amount: Option<u64>,
+        min_out: u64,
+    ) -> PoolResult {
+        handle_claim_rewards_and_swap(ctx, amount, min_out)
+    }
+
+    pub fn set_points_mode(ctx: Context<SetPointsMode>, enabled: bool) -> PoolResult {
+        handle_set_points_mode(ctx, enabled)
+    }
+
+    pub fn settle_points(ctx: Context<SettlePoints>) -> PoolResult {
+        handle_settle_points(ctx)
+    }
+
+    pub fn start_season(ctx: Context<StartSeason>, season: u32) -> PoolResult {
+        handle_start_season(ctx, season)
+    }
+
+    pub fn update_leaderboard(ctx: Context<UpdateLeaderboard>) -> PoolResult {
+        handle_update_leaderboard(ctx)
+    }
+
+    pub fn set_vote_delegate(
+        ctx: Context<SetVoteDelegate>,
+        delegate: Option<Pubkey>,
+    ) -> PoolResult {
+        handle_set_vote_delegate(ctx, delegate)
+    }
+
+    pub fn set_checkpoints_enabled(
+        ctx: Context<SetCheckpointsEnabled>,
+        enabled: bool,
+    ) -> PoolResult {
+        handle_set_checkpoints_enabled(ctx, enabled)
+    }
+
+    pub fn open_checkpoint_page(ctx: Context<OpenCheckpointPage>, page: u32) -> PoolResult {
+        handle_open_checkpoint_page(ctx, page)
+    }
+
+    /// Stake a user, or the pool in total, held at `slot`.
+    pub fn get_voting_power_at(ctx: Context<GetVotingPowerAt>, slot: u64) -> PoolResult<u64> {
+        handle_get_voting_power_at(ctx, slot)
+    }
+
+    /// Whitelists the program vault funds may be deployed to and the share of
+    /// stake kept liquid in the vault.
+    /// `leave_staking` with the payout swapped into `output_mint` through the
+    /// pool's swap adapter, failing if less than `min_out` arrives.
+    pub fn leave_staking_and_swap<'info>(
+        ctx: Context<'_, '_, '_, 'info, LeaveStakingAndSwap<'info>>,
+        amount: u64,
+        min_out: u64,
+    ) -> PoolResult {
+        handle_leave_staking_and_swap(ctx, amount, min_out)
+    }
+
+    /// Swaps `amount` of another token into the stake mint through the
+    /// pool's swap adapter and stakes the output, failing if less than
+    /// `min_out` arrives.
+    pub fn zap_stake<'info>(
+        ctx: Context<'_, '_, '_, 'info, ZapStake<'info>>,
+        amount: u64,
+        min_out: u64,
+    ) -> PoolResult {
+        handle_zap_stake(ctx, amount, min_out)
+    }
+
+    /// Proposes the program swaps are routed through, applied by
+    /// `apply_swap_adapter` after `ADAPTER_CHANGE_DELAY`. `None` turns
+    /// swapping off right away and drops any proposal.
+    pub fn set_swap_adapter(
+        ctx: Context<SetSwapAdapter>,
+        swap_adapter: Option<Pubkey>,
+    ) -> PoolResult {
+        handle_set_swap_adapter(ctx, swap_adapter)
+    }
+
+    pub fn apply_swap_adapter(ctx: Context<SetSwapAdapter>) -> PoolResult {
+        handle_apply_swap_adapter(ctx)
+    }
+
+    /// Proposes the program claims can be swapped through and the token
+    /// they are swapped into, applied by `apply_claim_adapter` after
+    /// `ADAPTER_CHANGE_DELAY`. `None` turns converting rewards off right
+    /// away and drops any proposal.
+    pub fn set_claim_adapter(
+        ctx: Context<SetClaimAdapter>,
+        claim_adapter: Option<Pubkey>,
+        output_mint: Pubkey,
+    ) -> PoolResult {
+        handle_set_claim_adapter(ctx, claim_adapter, output_mint)
+    }
+
+    pub fn apply_claim_adapter(ctx: Context<SetClaimAdapter>) -> PoolResult {
+        handle_apply_claim_adapter(ctx)
+    }
+
+    /// Sets where achievement NFTs point their metadata, see
+    /// `AchievementConfig`.
+    pub fn set_achievement_uri(ctx: Context<SetAchievementUri>, base_uri: String) -> PoolResult {
+        handle_set_achievement_uri(ctx, base_uri)
+    }
+
+    /// Mints the NFT for a milestone one of the wallet's positions has
+    /// reached, once per wallet and achievement.
+    pub fn mint_achievement(ctx: Context<MintAchievement>, achievement: Achievement) -> PoolResult {
+        handle_mint_achievement(ctx, achievement)
+    }
+
+    /// Turns on compressed mode, initializing `merkle_tree` with the program
+    /// signer as its authority. Pools with a config can't use it.
+    pub fn enable_compression(
+        ctx: Context<EnableCompression>,
+        max_depth: u32,
+        max_buffer_size: u32,
+    ) -> PoolResult {
+        handle_enable_compression(ctx, max_depth, max_buffer_size)
+    }
+
+    /// Appends an empty position for the caller to the pool's tree, costing
+    /// no rent. Its index is logged in `CompressedPositionOpened`.
+    pub fn open_compressed_position(ctx: Context<OpenCompressedPosition>) -> PoolResult {
+        handle_open_compressed_position(ctx)
+    }
+
+    /// Stakes `amount` into the caller's compressed `position` at `index`,
+    /// paying out its rewards. The leaf's proof under `root` goes in the
+    /// remaining accounts.
+    pub fn compressed_stake<'info>(
+        ctx: Context<'_, '_, '_, 'info, CompressedStake<'info>>,
+        amount: u64,
+        root: [u8; 32],
+        index: u32,
+        position: CompressedPosition,
+    ) -> PoolResult {
+        handle_compressed_stake(ctx, amount, root, index, position)
+    }
+
+    /// Unstakes `amount` from the caller's compressed `position`, like
+    /// `compressed_stake`. During an incident it pays out pro rata like
+    /// `emergency_withdraw`.
+    pub fn compressed_unstake<'info>(
+        ctx: Context<'_, '_, '_, 'info, CompressedStake<'info>>,
+        amount: u64,
+        root: [u8; 32],
+        index: u32,
+        position: CompressedPosition,
+    ) -> PoolResult {
+        handle_compressed_unstake(ctx, amount, root, index, position)
+    }
+
+    /// Stakes `amount` of the authority's tokens as a clawbackable grant to
+    /// `beneficiary`, kept apart from their own stake. Clawed-back tokens go
+    /// to `treasury`.
+    pub fn stake_grant(ctx: Context<StakeGrant>, beneficiary: Pubkey, amount: u64) -> PoolResult {
+        handle_stake_grant(ctx, beneficiary, amount)
+    }
+
+    /// Moves a grant and its rewards into the beneficiary's own stake, after
+    /// which it can't be clawed back.
+    pub fn release_grant(ctx: Context<ReleaseGrant>) -> PoolResult {
+        handle_release_grant(ctx)
+    }
+
+    /// Returns a grant's principal and rewards to its treasury.
+    pub fn clawback_grant(ctx: Context<ClawbackGrant>) -> PoolResult {
+        handle_clawback_grant(ctx)
+    }
+
+    /// Brings the reward index up to date and republishes the pool's
+    /// light-client state. Anyone may call it.
+    pub fn publish_state(ctx: Context<PublishState>) -> PoolResult {
+        handle_publish_state(ctx)
+    }
+
+    /// Creates the pool's `RatePublisher`. Anyone may pay for it.
+    pub fn initialize_rate_publisher(ctx: Context<InitializeRatePublisher>) -> PoolResult {
+        handle_initialize_rate_publisher(ctx)
+    }
+
+    /// Registers a stream paying the stream recipient PDA in the stake mint
+    /// as a reward source; `None` removes it.
+    pub fn set_reward_stream(
+        ctx: Context<SetRewardStream>,
+        stream_program: Option<Pubkey>,
+        stream: Pubkey,
+    ) -> PoolResult {
+        handle_set_reward_stream(ctx, stream_program, stream)
+    }
+
+    /// Withdraws everything vested in the pool's reward stream into the vault
+    /// and shares it among stakers, who are paid it out of the vault.
+    pub fn claim_reward_stream<'info>(
+        ctx: Context<'_, '_, '_, 'info, ClaimRewardStream<'info>>,
+    ) -> PoolResult {
+        handle_claim_reward_stream(ctx)
+    }
+
+    /// Swaps `amount_in` of fee revenue held by the program signer into the
+    /// stake mint through the swap adapter and shares the proceeds among
+    /// stakers. The revenue is moved to `swap_source` first, and the adapter
+    /// is signed for by the swap authority alone.
+    pub fn buyback_and_distribute<'info>(
+        ctx: Context<'_, '_, '_, 'info, BuybackAndDistribute<'info>>,
+        amount_in: u64,
+        min_out: u64,
+    ) -> PoolResult {
+        handle_buyback_and_distribute(ctx, amount_in, min_out)
+    }
+
+    pub fn set_restaking_adapter(
+        ctx: Context<SetRestakingAdapter>,
+        adapter_program: Pubkey,
+        buffer_bps: u32,
+    ) -> PoolResult {
+        handle_set_restaking_adapter(ctx, adapter_program, buffer_bps)
+    }
+
+    pub fn deploy_funds<'info>(
+        ctx: Context<'_, '_, '_, 'info, MoveFunds<'info>>,
+        amount: u64,
+    ) -> PoolResult {
+        handle_deploy_funds(ctx, amount)
+    }
+
+    pub fn recall_funds<'info>(
+        ctx: Context<'_, '_, '_, 'info, MoveFunds<'info>>,
+        amount: u64,
+    ) -> PoolResult {
+        handle_recall_funds(ctx, amount)
+    }
+
+    /// Unstakes `amount` into the withdrawal queue, at the tail of the
+    /// regular queue or of the priority lane for VIP badge holders. The
+    /// request account is keyed by the user and `nonce`, any value they have
+    /// no open request under, so requesters don't race for the tail.
+    pub fn request_withdrawal(
+        ctx: Context<RequestWithdrawal>,
+        amount: u64,
+        nonce: u64,
+        priority: bool,
+    ) -> PoolResult {
+        handle_request_withdrawal(ctx, amount, nonce, priority)
+    }
+
+    /// Queues the whole stake of a user whose attestation was revoked for
+    /// exit through the regular withdrawal queue, and blocks them from
+    /// staking again. Signed by the pool's compliance key.
+    pub fn force_unwind(ctx: Context<ForceUnwind>, user: Pubkey) -> PoolResult {
+        handle_force_unwind(ctx, user)
+    }
+
+    pub fn set_vip_mint(ctx: Context<SetVipMint>, vip_mint: Option<Pubkey>) -> PoolResult {
+        handle_set_vip_mint(ctx, vip_mint)
+    }
+
+    /// Pays out the request at the head of the queue, or in pro-rata mode
+    /// the request's share of the open batch. Anyone can crank it. A request
+    /// paid in full is closed and its rent returned to the requester.
+    pub fn fulfill_withdrawal<'info>(
+        ctx: Context<'_, '_, '_, 'info, FulfillWithdrawal<'info>>,
+    ) -> PoolResult {
+        handle_fulfill_withdrawal(ctx)
+    }
+
+    /// Starts a pro-rata batch: every queued request may then be filled
+    /// once for the same share of its amount, as much as the vault covers.
+    /// Anyone can crank it once the previous batch is filled.
+    pub fn open_withdrawal_batch(ctx: Context<OpenWithdrawalBatch>) -> PoolResult {
+        handle_open_withdrawal_batch(ctx)
+    }
+
+    /// Switches the withdrawal queue between FIFO and pro-rata batches.
+    /// Only while nothing is queued.
+    pub fn set_pro_rata_withdrawals(
+        ctx: Context<SetProRataWithdrawals>,
+        enabled: bool,
+    ) -> PoolResult {
+        handle_set_pro_rata_withdrawals(ctx, enabled)
+    }
+
+    pub fn initialize_pool_config(
+        ctx: Context<InitializePoolConfig>,
+        params: PoolConfigParams,
+    ) -> PoolResult {
+        handle_initialize_pool_config(ctx, params)
+    }
+
+    pub fn set_pool_config(ctx: Context<SetPoolConfig>, params: PoolConfigParams) -> PoolResult {
+        handle_set_pool_config(ctx, params)
+    }
+
+    /// Links `linked_pool`: stake there boosts rewards here by up to
+    /// `max_boost_bps`, reached at `full_boost_stake` tokens.
+    pub fn initialize_boost_link(
+        ctx: Context<InitializeBoostLink>,
+        max_boost_bps: u32,
+        full_boost_stake: u64,
+    ) -> PoolResult {
+        handle_initialize_boost_link(ctx, max_boost_bps, full_boost_stake)
+    }
+
+    /// Changes the boost link. Boosts users already synced keep until their
+    /// next sync.
+    pub fn set_boost_link(
+        ctx: Context<SetBoostLink>,
+        max_boost_bps: u32,
+        full_boost_stake: u64,
+    ) -> PoolResult {
+        handle_set_boost_link(ctx, max_boost_bps, full_boost_stake)
+    }
+
+    /// Settles the user's rewards and recomputes their boost from their
+    /// current stake in the linked pool. That stake boosts one position at a
+    /// time, which loses the boost once the stake shrinks.
+    pub fn sync_link_boost(ctx: Context<SyncLinkBoost>) -> PoolResult {
+        handle_sync_link_boost(ctx)
+    }
+
+    /// Sends the fees and penalties held in the vault to `destination`.
+    pub fn collect_protocol_fees<'info>(
+        ctx: Context<'_, '_, '_, 'info, CollectProtocolFees<'info>>,
+    ) -> PoolResult {
+        handle_collect_protocol_fees(ctx)
+    }
+
+    /// Publishes the Merkle root of the legacy stake snapshot. It can't be
+    /// changed afterwards.
+    pub fn publish_migration_root(
+        ctx: Context<PublishMigrationRoot>,
+        merkle_root: [u8; 32],
+    ) -> PoolResult {
+        handle_publish_migration_root(ctx, merkle_root)
+    }
+
+    /// Deposits the tokens backing the legacy stake into the vault.
+    pub fn fund_migration<'info>(
+        ctx: Context<'_, '_, '_, 'info, FundMigration<'info>>,
+        amount: u64,
+    ) -> PoolResult {
+        handle_fund_migration(ctx, amount)
+    }
+
+    /// Credits a user's legacy stake once, given a proof against the
+    /// published root. Anyone can crank it.
+    pub fn import_stake(
+        ctx: Context<ImportStake>,
+        amount: u64,
+        proof: Vec<[u8; 32]>,
+    ) -> PoolResult {
+        handle_import_stake(ctx, amount, proof)
+    }
+
+    /// Commits the Merkle root of current stake balances as snapshot `id`,
+    /// which must be the pool's `snapshot_count`.
+    pub fn commit_snapshot(
+        ctx: Context<CommitSnapshot>,
+        id: u64,
+        merkle_root: [u8; 32],
+    ) -> PoolResult {
+        handle_commit_snapshot(ctx, id, merkle_root)
+    }
+
+    /// Whether `user` held `amount` in the snapshot.
+    pub fn verify_snapshot_proof(
+        ctx: Context<VerifySnapshotProof>,
+        user: Pubkey,
+        amount: u64,
+        proof: Vec<[u8; 32]>,
+    ) -> PoolResult<bool> {
+        handle_verify_snapshot_proof(ctx, user, amount, proof)
+    }
+
+    /// Opens the lottery of `epoch` with `amount` split among `winners`,
+    /// drawn by stake from the `(user, start, amount)` ranges committed in
+    /// `merkle_root`.
+    pub fn start_lottery<'info>(
+        ctx: Context<'_, '_, '_, 'info, StartLottery<'info>>,
+        epoch: u64,
+        merkle_root: [u8; 32],
+        total_weight: u64,
+        winners: u8,
+        amount: u64,
+    ) -> PoolResult {
+        handle_start_lottery(ctx, epoch, merkle_root, total_weight, winners, amount)
+    }
+
+    /// Requests the lottery's randomness through its adapter, once. Anyone
+    /// can crank it; the remaining accounts go to the adapter.
+    pub fn request_lottery_draw<'info>(
+        ctx: Context<'_, '_, '_, 'info, RequestLotteryDraw<'info>>,
+    ) -> PoolResult {
+        handle_request_lottery_draw(ctx)
+    }
+
+    /// Reveals the requested randomness, which fixes the winners. Anyone can
+    /// crank it.
+    pub fn draw_lottery<'info>(ctx: Context<'_, '_, '_, 'info, DrawLottery<'info>>) -> PoolResult {
+        handle_draw_lottery(ctx)
+    }
+
+    /// Credits the prize of winner `winner` to the signer's rewards, given
+    /// their ticket range and its proof.
+    pub fn claim_lottery_prize(
+        ctx: Context<ClaimLotteryPrize>,
+        winner: u8,
+        start: u64,
+        amount: u64,
+        proof: Vec<[u8; 32]>,
+    ) -> PoolResult {
+        handle_claim_lottery_prize(ctx, winner, start, amount, proof)
+    }
+
+    /// Opens deposit shard `index`, with a token account of its own.
+    pub fn initialize_stake_shard(ctx: Context<InitializeStakeShard>, index: u8) -> PoolResult {
+        handle_initialize_stake_shard(ctx, index)
+    }
+
+    /// Deposits into a shard without writing the pool or its vault. The
+    /// stake counts once the shard is merged, and lands in the default
+    /// position once credited.
+    pub fn deposit_to_shard<'info>(
+        ctx: Context<'_, '_, '_, 'info, DepositToShard<'info>>,
+        amount: u64,
+    ) -> PoolResult {
+        handle_deposit_to_shard(ctx, amount)
+    }
+
+    /// Moves a shard's deposits into the vault as stake. Anyone can crank
+    /// it.
+    pub fn merge_stake_shard<'info>(
+        ctx: Context<'_, '_, '_, 'info, MergeStakeShard<'info>>,
+    ) -> PoolResult {
+        handle_merge_stake_shard(ctx)
+    }
+
+    /// Credits a merged deposit, and what it earned since the merge, to the
+    /// depositor's default position. Anyone can crank it.
+    pub fn credit_shard_deposit(ctx: Context<CreditShardDeposit>) -> PoolResult {
+        handle_credit_shard_deposit(ctx)
+    }
+
+    /// Takes back a deposit that hasn't been merged yet.
+    pub fn withdraw_shard_deposit<'info>(
+        ctx: Context<'_, '_, '_, 'info, WithdrawShardDeposit<'info>>,
+    ) -> PoolResult {
+        handle_withdraw_shard_deposit(ctx)
+    }
+
+    /// Previews `action` for the user without changing any state: fees,
+    /// penalty, amounts received and the resulting balances.
+    pub fn simulate_action(
+        ctx: Context<SimulateAction>,
+        action: SimulatedAction,
+        amount: u64,
+    ) -> PoolResult<ActionPreview> {
+        handle_simulate_action(ctx, action, amount)
+    }
+
+    pub fn get_pool_stats(ctx: Context<GetPoolStats>) -> PoolResult<PoolStats> {
+        handle_get_pool_stats(ctx)
+    }
+
+    /// Compares the vault with what the pool owes and returns whether it
+    /// covers it, raising `reserve_alarm` if not; deployed funds count once
+    /// recalled. While raised, only emergency withdrawals leave the pool.
+    /// Anyone can crank it.
+    pub fn check_reserves(ctx: Context<CheckReserves>) -> PoolResult<bool> {
+        handle_check_reserves(ctx)
+    }
+
+    pub fn clear_reserve_alarm(ctx: Context<ClearReserveAlarm>) -> PoolResult {
+        handle_clear_reserve_alarm(ctx)
+    }
+
+    /// Recomputes the pool's books from its counters and checks them
+    /// against each other and the vault, putting the pool in incident mode
+    /// on a mismatch. Anyone can crank it.
+    pub fn reconcile(ctx: Context<CheckReserves>) -> PoolResult<Reconciliation> {
+        handle_reconcile(ctx)
+    }
+
+    /// Hands the stake mint's freeze authority from the initializer to the
+    /// program signer. Later changes go through the timelocked rotation.
+    pub fn take_freeze_authority(ctx: Context<TakeFreezeAuthority>) -> PoolResult {
+        handle_take_freeze_authority(ctx)
+    }
+
+    /// Schedules moving one of the stake mint's authorities away from the
+    /// program signer, executable after `AUTHORITY_ROTATION_DELAY`. `None`
+    /// revokes it. Rotating `MintTokens` away stops distributions and reward
+    /// payouts. A new proposal replaces the pending one and restarts the
+    /// clock.
+    pub fn propose_authority_rotation(
+        ctx: Context<AuthorityRotation>,
+        kind: MintAuthority,
+        new_authority: Option<Pubkey>,
+    ) -> PoolResult {
+        handle_propose_authority_rotation(ctx, kind, new_authority)
+    }
+
+    pub fn cancel_authority_rotation(ctx: Context<AuthorityRotation>) -> PoolResult {
+        handle_cancel_authority_rotation(ctx)
+    }
+
+    pub fn execute_authority_rotation(ctx: Context<ExecuteAuthorityRotation>) -> PoolResult {
+        handle_execute_authority_rotation(ctx)
+    }
+
+    /// Schedules moving the staked tokens to `new_vault`, another token
+    /// account of the program signer, e.g. one with Token-2022 extensions
+    /// or to leave a vault in a bad state. Executable after
+    /// `VAULT_ROTATION_DELAY` with `rotate_vault`. A new proposal replaces
+    /// the pending one and restarts the clock.
+    pub fn propose_vault_rotation(ctx: Context<ProposeVaultRotation>) -> PoolResult {
+        handle_propose_vault_rotation(ctx)
+    }
+
+    pub fn cancel_vault_rotation(ctx: Context<CancelVaultRotation>) -> PoolResult {
+        handle_cancel_vault_rotation(ctx)
+    }
+
+    /// Transfers the vault's whole balance to the proposed vault and makes
+    /// it the pool's vault. Pass the mint's transfer hook accounts as
+    /// remaining accounts.
+    pub fn rotate_vault<'info>(ctx: Context<'_, '_, '_, 'info, RotateVault<'info>>) -> PoolResult {
+        handle_rotate_vault(ctx)
+    }
+
+    /// Starts, changes or, with a zero rate, stops the pool's SOL reward
+    /// campaign: `emission_rate` lamports per second shared by stakers
+    /// until `end_ts`, paid from the `SOL_REWARD_VAULT_SEED` account.
+    /// Campaign managers may call it too, for their own campaigns.
+    pub fn set_sol_rewards(
+        ctx: Context<SetSolRewards>,
+        emission_rate: u64,
+        end_ts: i64,
+    ) -> PoolResult {
+        handle_set_sol_rewards(ctx, emission_rate, end_ts)
+    }
+
+    /// Adds `lamports` to the pool's SOL reward vault. The first deposit
+    /// must cover the vault's rent exemption.
+    pub fn fund_sol_rewards(ctx: Context<FundSolRewards>, lamports: u64) -> PoolResult {
+        handle_fund_sol_rewards(ctx, lamports)
+    }
+
+    /// Lets anyone lengthen the running SOL campaign by depositing
+    /// `lamports`. The rate never changes; the end moves out by as long as
+    /// the deposit lasts at that rate, at least a day.
+    pub fn extend_sol_rewards(ctx: Context<ExtendSolRewards>, lamports: u64) -> PoolResult {
+        handle_extend_sol_rewards(ctx, lamports)
+    }
+
+    /// Lets `manager` start and change the SOL campaign while no one
+    /// else's is running.
+    pub fn add_campaign_manager(ctx: Context<AddCampaignManager>, manager: Pubkey) -> PoolResult {
+        handle_add_campaign_manager(ctx, manager)
+    }
+
+    /// Revokes a campaign manager. A campaign they started keeps running.
+    pub fn remove_campaign_manager(ctx: Context<RemoveCampaignManager>) -> PoolResult {
+        handle_remove_campaign_manager(ctx)
+    }
+
+    /// Pays the user's SOL rewards to their wallet.
+    pub fn claim_sol_rewards(ctx: Context<ClaimSolRewards>) -> PoolResult {
+        handle_claim_sol_rewards(ctx)
+    }
+
+    /// Writes every balance down by the current shortfall, so the loss is
+    /// shared pro rata at withdrawal instead of falling on the last to leave.
+    /// Run it again after a recapitalisation to shrink or clear the haircut.
+    pub fn settle_shortfall(ctx: Context<SettleShortfall>) -> PoolResult {
+        handle_settle_shortfall(ctx)
+    }
+
+    /// Turns the config history on or off. While it is on, every parameter
+    /// setter takes the next `ConfigChange` PDA and records the old and new
+    /// values in it.
+    pub fn set_config_history(ctx: Context<SetConfigHistory>, enabled: bool) -> PoolResult {
+        handle_set_config_history(ctx, enabled)
+    }
+
+    /// Reverts instructions that move the exchange rate more than
+    /// `max_change_bps` within a slot; 0 turns the guard off. Settling a
+    /// shortfall is exempt.
+    pub fn set_rate_guard(ctx: Context<SetRateGuard>, max_change_bps: u16) -> PoolResult {
+        handle_set_rate_guard(ctx, max_change_bps)
+    }
+
+    /// Institutional mode: deposits and withdrawals must directly follow an
+    /// SPL Memo instruction, which is repeated ahead of payouts. Paths that
+    /// can't carry a memo are disabled while it is on.
+    pub fn set_memo_required(ctx: Context<SetMemoRequired>, required: bool) -> PoolResult {
+        handle_set_memo_required(ctx, required)
+    }
+
+    /// Sets the key that may call `force_unwind`; `None` removes it.
+    pub fn set_compliance(ctx: Context<SetCompliance>, compliance: Option<Pubkey>) -> PoolResult {
+        handle_set_compliance(ctx, compliance)
+    }
+
+    /// Sets the key that may declare incidents; `None` removes it.
+    pub fn set_watcher(ctx: Context<SetWatcher>, watcher: Option<Pubkey>) -> PoolResult {
+        handle_set_watcher(ctx, watcher)
+    }
+
+    /// Sets the operator key that may start seasons, commit snapshots and
+    /// run buybacks, but change nothing; `None` removes it.
+    pub fn set_operator(ctx: Context<SetOperator>, operator: Option<Pubkey>) -> PoolResult {
+        handle_set_operator(ctx, operator)
+    }
+
+    /// Requires every position to accept `terms_hash` before staking more;
+    /// a new hash has to be accepted again, all zeros drops the requirement.
+    pub fn set_terms(ctx: Context<SetTerms>, terms_hash: [u8; 32]) -> PoolResult {
+        handle_set_terms(ctx, terms_hash)
+    }
+
+    /// Records on the caller's position that they accept the pool's current
+    /// terms, passed back as `terms_hash`.
+    pub fn accept_terms(ctx: Context<AcceptTerms>, terms_hash: [u8; 32]) -> PoolResult {
+        handle_accept_terms(ctx, terms_hash)
+    }
+
+    /// Puts the pool in incident mode: deposits and regular exits stop and
+    /// stakers can only leave through `emergency_withdraw`.
+    pub fn set_incident(ctx: Context<SetIncident>, reason: u16) -> PoolResult {
+        handle_set_incident(ctx, reason)
+    }
+
+    pub fn resolve_incident(ctx: Context<ResolveIncident>) -> PoolResult {
+        handle_resolve_incident(ctx)
+    }
+
+    /// Withdraws the caller's whole stake during an incident, scaled down by
+    /// the pool's shortfall if there is one.
+    pub fn emergency_withdraw<'info>(
+        ctx: Context<'_, '_, '_, 'info, EmergencyWithdraw<'info>>,
+    ) -> PoolResult {
+        handle_emergency_withdraw(ctx)
+    }
+
+    /// Requires `guardian` to co-sign withdrawals above `threshold`, or the
+    /// withdrawal to be announced `delay` seconds ahead. `None` removes it.
+    pub fn set_guardian(
+        ctx: Context<SetGuardian>,
+        guardian: Option<Pubkey>,
+        threshold: u64,
+        delay: i64,
+    ) -> PoolResult {
+        handle_set_guardian(ctx, guardian, threshold, delay)
+    }
+
+    /// Starts the delay after which `amount` can be withdrawn without the
+    /// guardian. Replaces any earlier announcement.
+    pub fn announce_withdrawal(ctx: Context<AnnounceWithdrawal>, amount: u64) -> PoolResult {
+        handle_announce_withdrawal(ctx, amount)
+    }
+
+    /// Turns on two-key withdrawals: stake then only leaves to the token
+    /// account `destination`, through `request_cold_withdrawal` and
+    /// `approve_cold_withdrawal` within `window` seconds. Once set, changes
+    /// need the cold key. `None` turns it off.
+    pub fn set_cold_storage(
+        ctx: Context<SetColdStorage>,
+        cold_key: Option<Pubkey>,
+        destination: Pubkey,
+        window: i64,
+    ) -> PoolResult {
+        handle_set_cold_storage(ctx, cold_key, destination, window)
+    }
+
+    /// Asks the cold key to approve withdrawing `amount`. Replaces any
+    /// earlier request.
+    pub fn request_cold_withdrawal(ctx: Context<RequestColdWithdrawal>, amount: u64) -> PoolResult {
+        handle_request_cold_withdrawal(ctx, amount)
+    }
+
+    /// Approves the pending request for `amount`, which `leave_staking` can
+    /// then pay to the registered destination until the window closes.
+    pub fn approve_cold_withdrawal(ctx: Context<ApproveColdWithdrawal>, amount: u64) -> PoolResult {
+        handle_approve_cold_withdrawal(ctx, amount)
+    }
+
+    /// Registers a key that may claim rewards for the user until
+    /// `expiry_slot`. It can't withdraw stake. `None` revokes it.
+    pub fn set_session_key(
+        ctx: Context<SetSessionKey>,
+        session_key: Option<Pubkey>,
+        expiry_slot: u64,
+    ) -> PoolResult {
+        handle_set_session_key(ctx, session_key, expiry_slot)
+    }
+
+    /// Sets how many seconds a user must wait between claims; 0 disables it.
+    pub fn set_claim_cooldown(ctx: Context<SetClaimCooldown>, cooldown: u32) -> PoolResult {
+        handle_set_claim_cooldown(ctx, cooldown)
+    }
+
+    /// Stops deposits and claims; withdrawals stay open. With
+    /// `freeze_rewards`, no rewards accrue until `unpause`.
+    pub fn pause(ctx: Context<SetPaused>, freeze_rewards: bool) -> PoolResult {
+        handle_pause(ctx, freeze_rewards)
+    }
+
+    pub fn unpause(ctx: Context<SetPaused>) -> PoolResult {
+        handle_unpause(ctx)
+    }
+
+    /// Creates the pool's audit log; from then on every stake and reward
+    /// movement must pass it.
+    pub fn initialize_audit_log(ctx: Context<InitializeAuditLog>) -> PoolResult {
+        handle_initialize_audit_log(ctx)
+    }
+
+    /// Switches off the instructions whose `instruction_flags` bits are set
+    /// and re-enables the rest.
+    pub fn set_disabled_instructions(
+        ctx: Context<SetDisabledInstructions>,
+        disabled: u64,
+    ) -> PoolResult {
+        handle_set_disabled_instructions(ctx, disabled)
+    }
+
+    /// Diverts `bps` of emissions from stakers to `destination`, a token
+    /// account of the stake mint, or burns them when `None`. A new
+    /// destination needs the share accrued so far distributed first.
+    pub fn set_reward_redirect(
+        ctx: Context<SetRewardRedirect>,
+        bps: u16,
+        destination: Option<Pubkey>,
+    ) -> PoolResult {
+        handle_set_reward_redirect(ctx, bps, destination)
+    }
+
+    /// Pays out the diverted share accrued so far, or burns what the funded
+    /// rewards in the vault cover of it. Anyone can crank it.
+    pub fn distribute_redirected_rewards(ctx: Context<DistributeRedirectedRewards>) -> PoolResult {
+        handle_distribute_redirected_rewards(ctx)
+    }
+
+    pub fn set_dust_policy(ctx: Context<SetDustPolicy>, policy: DustPolicy) -> PoolResult {
+        handle_set_dust_policy(ctx, policy)
+    }
+
+    /// Mints the whole tokens of accumulated reward dust to `destination`,
+    /// whatever the policy, so a finished pool can be closed out.
+    pub fn sweep_dust(ctx: Context<SweepDust>) -> PoolResult {
+        handle_sweep_dust(ctx)
+    }
+
+    /// Sets up a recurring deposit from `funding`. The user must also approve
+    /// the program signer as delegate on `funding`.
+    pub fn create_deposit_schedule(
+        ctx: Context<CreateDepositSchedule>,
+        amount: u64,
+        interval: i64,
+    ) -> PoolResult {
+        handle_create_deposit_schedule(ctx, amount, interval)
+    }
+
+    pub fn cancel_deposit_schedule(_ctx: Context<CancelDepositSchedule>) -> PoolResult {
+        Ok(())
+    }
+
+    /// Stakes the next scheduled deposit once it is due. Anyone can crank it.
+    pub fn execute_scheduled_deposit<'info>(
+        ctx: Context<'_, '_, '_, 'info, ExecuteScheduledDeposit<'info>>,
+    ) -> PoolResult {
+        handle_execute_scheduled_deposit(ctx)
+    }
+
+    /// Lets the program `integrator` stake and unstake for the user through
+    /// CPI, up to `allowance` tokens in total. Replaces any earlier approval;
+    /// `None` revokes it.
+    pub fn set_integrator(
+        ctx: Context<SetIntegrator>,
+        integrator: Option<Pubkey>,
+        allowance: u64,
+    ) -> PoolResult {
+        handle_set_integrator(ctx, integrator, allowance)
+    }
+
+    /// Stakes `amount` of the integrator's tokens for the user, spending its
+    /// allowance. Signed by the integrator's `INTEGRATOR_SEED` PDA.
+    pub fn integrator_stake<'info>(
+        ctx: Context<'_, '_, '_, 'info, IntegratorStake<'info>>,
+        amount: u64,
+    ) -> PoolResult {
+        handle_integrator_stake(ctx, amount)
+    }
+
+    /// Unstakes `amount` for the user into their own token account, spending
+    /// the integrator's allowance.
+    pub fn integrator_unstake<'info>(
+        ctx: Context<'_, '_, '_, 'info, IntegratorUnstake<'info>>,
+        amount: u64,
+    ) -> PoolResult {
+        handle_integrator_unstake(ctx, amount)
+    }
+
+    /// Lets `puller` call `pull_stake` for the user. Replaces any earlier
+    /// puller; `None` revokes it.
+    pub fn set_puller(ctx: Context<SetPuller>, puller: Option<Pubkey>) -> PoolResult {
+        handle_set_puller(ctx, puller)
+    }
+
+    /// Stakes `amount` of the user's tokens on their behalf, spending the
+    /// delegate approval they gave the program signer. Signed by the user or
+    /// their registered puller; the stake is always credited to the owner of
+    /// `funding`, and only the user's own signature extends their lock.
+    pub fn pull_stake<'info>(
+        ctx: Context<'_, '_, '_, 'info, PullStake<'info>>,
+        amount: u64,
+    ) -> PoolResult {
+        handle_pull_stake(ctx, amount)
+    }
+
+    /// Exempts `wallet` from deposit and withdrawal fees.
+    pub fn add_fee_exemption(ctx: Context<AddFeeExemption>, wallet: Pubkey) -> PoolResult {
+        handle_add_fee_exemption(ctx, wallet)
+    }
+
+    pub fn remove_fee_exemption(ctx: Context<RemoveFeeExemption>) -> PoolResult {
+        handle_remove_fee_exemption(ctx)
+    }
+
+    /// Reports who votes with a user's stake and how much it weighs. The
+    /// wallet's other positions, passed as remaining accounts in increasing
+    /// index order, count too.
+    pub fn get_voter_weight(ctx: Context<GetVoterWeight>) -> PoolResult<VoterWeight> {
+        handle_get_voter_weight(ctx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool(staked_total: u64) -> Pool {
+        let mut pool: Pool = bytemuck::Zeroable::zeroed();
+        pool.staked_total = staked_total;
+        pool
+    }
+
+    fn user(staked_amount: u64) -> UserState {
+        let mut user = UserState::deserialize(&mut &vec![0; UserState::size()][..]).unwrap();
+        user.initialized = true;
+        user.staked_amount = staked_amount;
+        user
+    }
+
+    /// 100% APR on a target of `staked_total`, funded with `reserve` until
+    /// `stop`.
+    fn dynamic_pool(staked_total: u64, reserve: u64, stop: i64) -> Pool {
+        let mut pool = pool(staked_total);
+        pool.dynamic_apr = 1;
+        pool.utilization_target = staked_total;
+        pool.min_apr_bps = 10_000;
+        pool.max_apr_bps = 10_000;
+        pool.reward_reserve = reserve;
+        pool.emission_stop_ts = stop;
+        pool
+    }
+
+    #[test]
+    fn dynamic_apr_stops_when_the_reserve_runs_dry() {
+        let year = SECONDS_PER_YEAR as i64;
+        let mut pool = dynamic_pool(1_000_000, 1_000, 10 * year);
+
+        // A year at 100% owes the whole stake, the reserve pays 1,000.
+        pool.update_reward_index(year).unwrap();
+        assert_eq!(pool.reward_per_token, 1_000 * REWARD_PRECISION / 1_000_000);
+        assert_eq!(pool.reward_reserve, 0);
+        assert_eq!(pool.funded_rewards, 1_000);
+
+        let index = pool.reward_per_token;
+        pool.update_reward_index(2 * year).unwrap();
+        assert_eq!(pool.reward_per_token, index);
+    }
+
+    #[test]
+    fn dynamic_apr_needs_its_worst_case_funded() {
+        let year = SECONDS_PER_YEAR as i64;
+
+        // Unbounded never is.
+        assert!(dynamic_pool(1_000, 1_000_000, 0)
+            .require_emissions_funded()
+            .is_err());
+
+        // A year at the top APR on the whole target.
+        assert!(dynamic_pool(1_000, 999, year)
+            .require_emissions_funded()
+            .is_err());
+        assert!(dynamic_pool(1_000, 1_000, year)
+            .require_emissions_funded()
+            .is_ok());
+    }
+
+    #[test]
+    fn wallet_season_counts_every_position() {
+        let mut pool = pool(0);
+        pool.season = 1;
+        pool.season_start_ts = 100;
+
+        // 400 in the default position and 600 in another one.
+        let mut wallet = WalletState {
+            pool: Pubkey::default(),
+            wallet: Pubkey::default(),
+            staked_amount: 1_000,
+            unwound: false,
+            checkpoint_pages: 0,
+            season: 0,
+            season_stake_seconds: 7,
+            last_settle_ts: 50,
+        };
+
+        // Last season's score and the time before this one started don't count.
+        assert_eq!(wallet.settle_season(&pool, 110).unwrap(), 10_000);
+
+        // Settled before the second position grows by 500.
+        wallet.settle_season(&pool, 120).unwrap();
+        wallet.staked_amount += 500;
+        assert_eq!(wallet.settle_season(&pool, 130).unwrap(), 35_000);
+    }
+
+    #[test]
+    fn emissions_draw_the_reserve_until_they_stop() {
+        let mut pool = pool(1_000);
+        pool.emission_rate = 100;
+        pool.emission_stop_ts = 10;
+        pool.reward_reserve = 1_000;
+        let mut alice = user(1_000);
+
+        update_rewards(&mut pool, &mut alice, 20).unwrap();
+
+        assert_eq!(alice.pending_rewards, 1_000);
+        assert_eq!(pool.reward_reserve, 0);
+        assert_eq!(pool.funded_rewards, 1_000);
+    }
+
+    #[test]
+    fn emissions_must_be_covered_by_the_reserve() {
+        let mut pool = pool(1_000);
+        pool.emission_rate = 100;
+        pool.reward_reserve = 999;
+
+        // Unbounded emissions are never covered.
+        assert!(pool.require_emissions_funded().is_err());
+
+        pool.emission_stop_ts = 10;
+        assert!(pool.require_emissions_funded().is_err());
+
+        pool.reward_reserve = 1_000;
+        assert!(pool.require_emissions_funded().is_ok());
+    }
+
+    #[test]
+    fn settles_sol_rewards_until_the_campaign_ends() {
+        let mut pool = pool(1_000);
+        pool.sol_emission_rate = 50;
+        pool.sol_emission_end_ts = 10;
+        let mut alice = user(1_000);
+
+        update_rewards(&mut pool, &mut alice, 20).unwrap();
+
+        assert_eq!(alice.sol_pending_rewards, 500);
+        assert_eq!(alice.pending_rewards, 0);
+        assert_eq!(alice.sol_reward_per_token_paid, pool.sol_reward_per_token);
+    }
+
+    fn lottery(total_weight: u64, winners: u8) -> Lottery {
+        let mut lottery = Lottery::deserialize(&mut &vec![0; Lottery::size()][..]).unwrap();
+        lottery.total_weight = total_weight;
+        lottery.winners = winners;
+        lottery.prize = 100;
+        lottery.randomness = [7; 32];
+        lottery
+    }
+
+    #[test]
+    fn winning_tickets_fall_within_the_weight() {
+        let lottery = lottery(1_000, MAX_LOTTERY_WINNERS);
+
+        let tickets: Vec<u64> = (0..MAX_LOTTERY_WINNERS)
+            .map(|winner| lottery.winning_ticket(winner))
+            .collect();
+        assert!(tickets.iter().all(|ticket| *ticket < 1_000));
+
+        // The same randomness always draws the same winners, different
+        // randomness others.
+        assert_eq!(lottery.winning_ticket(0), tickets[0]);
+        let mut redrawn = lottery.clone();
+        redrawn.randomness = [8; 32];
+        assert_ne!(
+            (0..MAX_LOTTERY_WINNERS)
+                .map(|winner| redrawn.winning_ticket(winner))
+                .collect::<Vec<_>>(),
+            tickets
+        );
+    }
+
+    #[test]
+    fn prizes_are_credited_once_per_winner_after_the_draw() {
+        let mut lottery = lottery(1_000, 2);
+        assert!(lottery.take_prize(0).is_err());
+
+        lottery.drawn = true;
+        assert_eq!(lottery.take_prize(0).unwrap(), 100);
+        assert!(lottery.take_prize(0).is_err());
+        assert_eq!(lottery.take_prize(1).unwrap(), 100);
+        assert!(lottery.take_prize(2).is_err());
+    }
+
+    #[test]
+    fn shard_deposits_split_what_the_vault_received() {
+        let mut batch = ShardBatch::deserialize(&mut &vec![0; ShardBatch::size()][..]).unwrap();
+        batch.deposited = 300;
+        batch.received = 299;
+        batch.reward_per_token = REWARD_PRECISION;
+
+        let mut pool = pool(1_000);
+        pool.reward_per_token = 3 * REWARD_PRECISION;
+
+        // Earned from the merge index on, on the share that reached the vault.
+        assert_eq!(batch.take(100, &pool).unwrap(), (99, 198, 0));
+        assert_eq!(batch.take(100, &pool).unwrap(), (100, 200, 0));
+        // The last deposit gets what rounding left.
+        assert_eq!(batch.take(100, &pool).unwrap(), (100, 200, 0));
+        assert_eq!((batch.deposited, batch.received), (0, 0));
     }
 }
@@ -0,0 +1,230 @@
+//! Integer math under a single rounding policy: whatever the pool pays out
+//! rounds down and whatever is owed to it rounds up, so rounding never moves
+//! value out of the pool. Products are taken at full width, so only results
+//! that don't fit fail.
+
+use anchor_lang::prelude::*;
+use anchor_safe_math::SafeMath;
+
+use crate::{PoolResult, BPS_DENOMINATOR};
+
+/// Which way an amount rounds, by who it favors.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Rounding {
+    /// Paid out by the pool: rewards, withdrawals, shares. Rounds down.
+    Payout,
+
+    /// Owed to the pool: fees, penalties, haircuts. Rounds up.
+    Liability,
+}
+
+/// `value * numerator / denominator` rounded per `rounding`.
+pub(crate) fn mul_div(
+    value: u128,
+    numerator: u128,
+    denominator: u128,
+    rounding: Rounding,
+) -> PoolResult<u128> {
+    let (quotient, remainder) = mul_div_rem(value, numerator, denominator)?;
+
+    Ok(match rounding {
+        Rounding::Payout => quotient,
+        Rounding::Liability => quotient.safe_add((remainder != 0) as u128)?,
+    })
+}
+
+/// Quotient and remainder of `value * numerator / denominator`, for callers
+/// that keep track of what rounding down dropped.
+pub(crate) fn mul_div_rem(
+    value: u128,
+    numerator: u128,
+    denominator: u128,
+) -> PoolResult<(u128, u128)> {
+    let (high, low) = widening_mul(value, numerator);
+
+    div_wide(high, low, denominator).ok_or_else(|| ProgramError::ArithmeticOverflow.into())
+}
+
+/// The 256-bit product of `a` and `b` as its high and low halves.
+fn widening_mul(a: u128, b: u128) -> (u128, u128) {
+    const HALF: u32 = u64::BITS;
+    const MASK: u128 = u64::MAX as u128;
+
+    let (a_high, a_low) = (a >> HALF, a & MASK);
+    let (b_high, b_low) = (b >> HALF, b & MASK);
+
+    let low_low = a_low * b_low;
+    let high_low = a_high * b_low;
+    let low_high = a_low * b_high;
+    let middle = (low_low >> HALF) + (high_low & MASK) + (low_high & MASK);
+
+    (
+        a_high * b_high + (high_low >> HALF) + (low_high >> HALF) + (middle >> HALF),
+        (low_low & MASK) | (middle << HALF),
+    )
+}
+
+/// Quotient and remainder of the 256-bit `high:low` by `divisor`, `None`
+/// for a zero divisor or a quotient that doesn't fit.
+fn div_wide(high: u128, low: u128, divisor: u128) -> Option<(u128, u128)> {
+    if divisor == 0 || high >= divisor {
+        return None;
+    }
+    if high == 0 {
+        return Some((low / divisor, low % divisor));
+    }
+
+    // Long division, one bit of `low` at a time.
+    let (mut quotient, mut remainder) = (0u128, high);
+    for bit in (0..u128::BITS).rev() {
+        let carry = remainder >> (u128::BITS - 1);
+        remainder = (remainder << 1) | ((low >> bit) & 1);
+        quotient <<= 1;
+        if carry == 1 || remainder >= divisor {
+            remainder = remainder.wrapping_sub(divisor);
+            quotient |= 1;
+        }
+    }
+
+    Some((quotient, remainder))
+}
+
+/// `mul_div` of a token amount, checked to fit back into a `u64`.
+pub(crate) fn mul_div_amount(
+    amount: u64,
+    numerator: u128,
+    denominator: u128,
+    rounding: Rounding,
+) -> PoolResult<u64> {
+    u64::try_from(mul_div(amount as u128, numerator, denominator, rounding)?)
+        .map_err(|_| ProgramError::ArithmeticOverflow.into())
+}
+
+/// `bps` basis points of `amount`.
+pub(crate) fn bps_of(amount: u64, bps: u128, rounding: Rounding) -> PoolResult<u64> {
+    mul_div_amount(amount, bps, BPS_DENOMINATOR, rounding)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rounds_by_side_exhaustively() {
+        for value in 0..64u128 {
+            for numerator in 0..64u128 {
+                for denominator in 1..32u128 {
+                    let down = mul_div(value, numerator, denominator, Rounding::Payout).unwrap();
+                    let up = mul_div(value, numerator, denominator, Rounding::Liability).unwrap();
+                    let product = value * numerator;
+
+                    assert!(down * denominator <= product);
+                    assert!(product < (down + 1) * denominator);
+                    assert!(up * denominator >= product);
+                    assert_eq!(up - down, (product % denominator != 0) as u128);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn charge_and_remainder_add_up() {
+        for amount in 0..2_000u64 {
+            for bps in [0, 1, 3, 30, 333, 5_000, 9_999, 10_000] {
+                let charge = bps_of(amount, bps, Rounding::Liability).unwrap();
+                let rest = bps_of(amount, BPS_DENOMINATOR - bps, Rounding::Payout).unwrap();
+
+                assert_eq!(charge + rest, amount);
+            }
+        }
+    }
+
+    #[test]
+    fn zero_denominator_fails() {
+        for rounding in [Rounding::Payout, Rounding::Liability] {
+            assert!(mul_div(1, 1, 0, rounding).is_err());
+        }
+    }
+
+    #[test]
+    fn products_wider_than_u128() {
+        let rounded = |value, numerator, denominator| {
+            (
+                mul_div(value, numerator, denominator, Rounding::Payout).unwrap(),
+                mul_div(value, numerator, denominator, Rounding::Liability).unwrap(),
+            )
+        };
+
+        assert_eq!(
+            rounded(u128::MAX, u128::MAX, u128::MAX),
+            (u128::MAX, u128::MAX)
+        );
+        assert_eq!(rounded(1 << 100, 1 << 100, 1 << 90), (1 << 110, 1 << 110));
+        assert_eq!(rounded(u128::MAX, 2, 4), (u128::MAX / 2, u128::MAX / 2 + 1));
+        assert_eq!(
+            rounded(u128::MAX, u128::MAX - 1, u128::MAX),
+            (u128::MAX - 1, u128::MAX - 1)
+        );
+        assert!(mul_div(u128::MAX, 3, 2, Rounding::Payout).is_err());
+    }
+
+    #[test]
+    fn widening_mul_matches_narrow_products() {
+        for (a, b) in [(0, 0), (1, u128::MAX), (u64::MAX as u128, u64::MAX as u128)] {
+            assert_eq!(widening_mul(a, b), (0, a * b));
+        }
+        assert_eq!(widening_mul(u128::MAX, u128::MAX), (u128::MAX - 1, 1));
+        assert_eq!(widening_mul(1 << 64, 1 << 64), (1, 0));
+    }
+
+    #[test]
+    fn amounts_at_u64_scale() {
+        // A full-supply stake earning a full-supply reward.
+        assert_eq!(
+            mul_div_amount(
+                u64::MAX,
+                1_000_000_000_000,
+                1_000_000_000_000,
+                Rounding::Payout
+            )
+            .unwrap(),
+            u64::MAX
+        );
+        assert_eq!(
+            bps_of(u64::MAX, BPS_DENOMINATOR, Rounding::Liability).unwrap(),
+            u64::MAX
+        );
+        assert_eq!(
+            bps_of(u64::MAX, 1, Rounding::Liability).unwrap(),
+            (u64::MAX / 10_000) + 1
+        );
+    }
+
+    #[test]
+    fn amounts_that_do_not_fit_fail() {
+        assert_eq!(
+            mul_div_amount(u64::MAX, 1, 1, Rounding::Liability).unwrap(),
+            u64::MAX
+        );
+        assert!(mul_div_amount(u64::MAX, 2, 1, Rounding::Payout).is_err());
+        assert!(mul_div_amount(u64::MAX, u128::MAX, 1, Rounding::Payout).is_err());
+    }
+
+    #[test]
+    fn full_supply_balances_sum_without_overflow() {
+        let mut pool: crate::Pool = bytemuck::Zeroable::zeroed();
+        pool.staked_total = u64::MAX;
+        pool.queued_amount = u64::MAX;
+        pool.protocol_fees = u64::MAX;
+        pool.migration_escrow = u64::MAX;
+
+        assert_eq!(pool.liabilities().unwrap(), 4 * u64::MAX as u128);
+
+        pool.haircut = crate::REWARD_PRECISION / 2;
+        assert_eq!(
+            pool.written_down_total(pool.liabilities().unwrap())
+                .unwrap(),
+            2 * u64::MAX as u128
+        );
+    }
+}
@@ -0,0 +1,141 @@
+//! Reward settlement. Every instruction that reads a user's pending rewards
+//! or changes what they earn on goes through `update_rewards` first, so the
+//! result doesn't depend on which instructions ran before it.
+
+use crate::{Pool, PoolResult, UserState};
+
+/// Brings the pool's reward index up to `now`, then moves what the user
+/// earned since their last settlement into `pending_rewards`.
+pub(crate) fn update_rewards(pool: &mut Pool, user_state: &mut UserState, now: i64) -> PoolResult {
+    pool.update_reward_index(now)?;
+    user_state.settle_rewards(pool)
+}
+
+/// Rewards the user could claim at `now`, settled on copies so nothing is
+/// written. For views and off-chain readers.
+pub fn pending_rewards(pool: &Pool, user_state: &UserState, now: i64) -> PoolResult<u64> {
+    let (mut pool, mut user_state) = (*pool, user_state.clone());
+    update_rewards(&mut pool, &mut user_state, now)?;
+
+    Ok(user_state.pending_rewards)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anchor_lang::AnchorDeserialize;
+
+    fn pool(emission_rate: u64, staked_total: u64) -> Pool {
+        let mut pool: Pool = bytemuck::Zeroable::zeroed();
+        pool.emission_rate = emission_rate;
+        pool.staked_total = staked_total;
+        pool
+    }
+
+    fn user(staked_amount: u64) -> UserState {
+        let mut user = UserState::deserialize(&mut &vec![0; UserState::size()][..]).unwrap();
+        user.initialized = true;
+        user.staked_amount = staked_amount;
+        user
+    }
+
+    #[test]
+    fn settles_accrued_rewards() {
+        let mut pool = pool(100, 1_000);
+        let mut alice = user(1_000);
+
+        update_rewards(&mut pool, &mut alice, 10).unwrap();
+
+        assert_eq!(alice.pending_rewards, 1_000);
+        assert_eq!(alice.reward_per_token_paid, pool.reward_per_token);
+    }
+
+    #[test]
+    fn settlement_rounding_goes_to_dust() {
+        let mut pool = pool(100, 3);
+        let mut users = [user(1), user(1), user(1)];
+
+        for user in &mut users {
+            update_rewards(&mut pool, user, 1).unwrap();
+        }
+
+        // 100 over three stakers: each gets 33, the last token is dust no
+        // matter where it was rounded off.
+        assert!(users.iter().all(|user| user.pending_rewards == 33));
+        assert_eq!(pool.reward_dust, crate::REWARD_PRECISION);
+    }
+
+    #[test]
+    fn pending_rewards_writes_nothing() {
+        let pool = pool(100, 1_000);
+        let alice = user(1_000);
+
+        assert_eq!(pending_rewards(&pool, &alice, 10).unwrap(), 1_000);
+        assert_eq!(pool.last_update_ts, 0);
+        assert_eq!(alice.pending_rewards, 0);
+    }
+
+    #[test]
+    fn settling_twice_is_a_no_op() {
+        let mut pool = pool(100, 1_000);
+        let mut alice = user(1_000);
+
+        update_rewards(&mut pool, &mut alice, 10).unwrap();
+        update_rewards(&mut pool, &mut alice, 10).unwrap();
+
+        assert_eq!(alice.pending_rewards, 1_000);
+    }
+
+    #[test]
+    fn order_of_touches_does_not_matter() {
+        let settle = |first_alice: bool| {
+            let mut pool = pool(100, 1_000);
+            let mut alice = user(750);
+            let mut bob = user(250);
+
+            update_rewards(&mut pool, &mut bob, 3).unwrap();
+            if first_alice {
+                update_rewards(&mut pool, &mut alice, 7).unwrap();
+                update_rewards(&mut pool, &mut bob, 10).unwrap();
+            } else {
+                update_rewards(&mut pool, &mut bob, 7).unwrap();
+                update_rewards(&mut pool, &mut alice, 10).unwrap();
+            }
+            update_rewards(&mut pool, &mut alice, 10).unwrap();
+            update_rewards(&mut pool, &mut bob, 10).unwrap();
+
+            (alice.pending_rewards, bob.pending_rewards)
+        };
+
+        assert_eq!(settle(true), settle(false));
+        assert_eq!(settle(true), (750, 250));
+    }
+
+    #[test]
+    fn settles_at_full_supply() {
+        let mut pool = pool(u64::MAX / 1_000, u64::MAX);
+        let mut alice = user(u64::MAX);
+
+        update_rewards(&mut pool, &mut alice, 1_000).unwrap();
+
+        // The index rounds off less than one unit per staked token; the
+        // rest waits as dust.
+        let emitted = u64::MAX / 1_000 * 1_000;
+        let rounding = u64::MAX / crate::REWARD_PRECISION as u64 + 1;
+        assert!(alice.pending_rewards <= emitted);
+        assert!(alice.pending_rewards >= emitted - rounding);
+    }
+
+    #[test]
+    fn stake_changes_apply_from_the_settlement_on() {
+        let mut pool = pool(100, 1_000);
+        let mut alice = user(1_000);
+
+        update_rewards(&mut pool, &mut alice, 10).unwrap();
+        alice.staked_amount = 0;
+        pool.staked_total = 0;
+        update_rewards(&mut pool, &mut alice, 20).unwrap();
+
+        assert_eq!(alice.pending_rewards, 1_000);
+    }
+}
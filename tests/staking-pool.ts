@@ -1,15 +1,31 @@
 import * as anchor from "@coral-xyz/anchor";
 import { BN, Program } from "@coral-xyz/anchor";
 import { StakingPool } from "../target/types/staking_pool";
-import { Keypair, PublicKey, SystemProgram } from "@solana/web3.js";
 import {
+    Keypair,
+    PublicKey,
+    SystemProgram,
+    sendAndConfirmTransaction,
+} from "@solana/web3.js";
+import {
+    approve,
+    createAccount,
     createMint,
+    getAccount,
+    getAssociatedTokenAddressSync,
+    getMint,
     getOrCreateAssociatedTokenAccount,
+    mintTo,
     Account,
     TOKEN_PROGRAM_ID,
+    TOKEN_2022_PROGRAM_ID,
 } from "@solana/spl-token";
 import { min } from "bn.js";
 import { assert, expect } from "chai";
+import { createHash } from "crypto";
+import { readFileSync, writeFileSync } from "fs";
+import { tmpdir } from "os";
+import { join } from "path";
 import { publicKey } from "@coral-xyz/anchor/dist/cjs/utils";
 
 function getProviderAndProgram() {
@@ -43,19 +59,21 @@ describe("Test staking-pool", () => {
     let vault: Account;
     let programSigner: PublicKey;
     let nonce: number;
+    let tokenProgram: PublicKey;
 
     let alice: Keypair;
     let bob: Keypair;
 
     async function initialize() {
         await program.methods
-            .initialize(nonce)
+            .initialize()
             .accounts({
                 pool: pool.publicKey,
                 mint: mint.publicKey,
                 programSigner,
                 vault: vault.address,
                 authority: authority.publicKey,
+                tokenProgram,
             })
             .preInstructions([
                 await program.account.pool.createInstruction(pool),
@@ -64,31 +82,82 @@ describe("Test staking-pool", () => {
             .rpc();
     }
 
-    async function airdrop(user: Keypair, amount: number): Promise<PublicKey> {
+    // Tokens for `user`, distributed by the pool authority under a cap
+    // raised for just this amount.
+    async function distribute_to(
+        user: Keypair,
+        amount: number
+    ): Promise<PublicKey> {
         let userMintAcc = await getOrCreateAssociatedTokenAccount(
             connection,
             user,
             mint.publicKey,
-            user.publicKey
+            user.publicKey,
+            false,
+            undefined,
+            undefined,
+            tokenProgram
         );
 
+        const poolAccount = await program.account.pool.fetch(pool.publicKey);
+        const configChange = poolAccount.configHistoryEnabled
+            ? PublicKey.findProgramAddressSync(
+                  [
+                      Buffer.from("config_change"),
+                      pool.publicKey.toBuffer(),
+                      poolAccount.configChangeCount.toArrayLike(
+                          Buffer,
+                          "le",
+                          8
+                      ),
+                  ],
+                  program.programId
+              )[0]
+            : null;
+        const auditLog = poolAccount.auditLogEnabled
+            ? PublicKey.findProgramAddressSync(
+                  [Buffer.from("audit_log"), pool.publicKey.toBuffer()],
+                  program.programId
+              )[0]
+            : null;
+
         await program.methods
-            .airdrop(new BN(amount))
+            .distribute([new BN(amount)])
             .accounts({
                 pool: pool.publicKey,
-                mint: mint.publicKey,
                 programSigner,
-                userMintAcc: userMintAcc.address,
-                authority: user.publicKey,
-                tokenProgram: TOKEN_PROGRAM_ID,
+                mint: mint.publicKey,
+                authority: authority.publicKey,
+                tokenProgram,
+                auditLog,
             })
-            .signers([user])
+            .remainingAccounts([
+                {
+                    pubkey: userMintAcc.address,
+                    isWritable: true,
+                    isSigner: false,
+                },
+            ])
+            .preInstructions([
+                await program.methods
+                    .setDistributionCap(new BN(amount))
+                    .accounts({
+                        pool: pool.publicKey,
+                        authority: authority.publicKey,
+                        configChange,
+                    })
+                    .instruction(),
+            ])
+            .signers([authority.payer])
             .rpc();
 
         return userMintAcc.address;
     }
 
-    async function initialize_user_state(user: Keypair): Promise<PublicKey> {
+    async function initialize_user_state(
+        user: Keypair,
+        payer: Keypair = user
+    ): Promise<PublicKey> {
         const [userState] = PublicKey.findProgramAddressSync(
             [pool.publicKey.toBuffer(), user.publicKey.toBuffer()],
             program.programId
@@ -100,8 +169,9 @@ describe("Test staking-pool", () => {
                 userState,
                 pool: pool.publicKey,
                 authority: user.publicKey,
+                payer: payer.publicKey,
             })
-            .signers([user])
+            .signers(payer === user ? [user] : [user, payer])
             .rpc();
 
         return userState;
@@ -109,13 +179,20 @@ describe("Test staking-pool", () => {
 
     async function enter_staking(
         user: Keypair,
-        amount: number
+        amount: number,
+        config: PublicKey | null = null,
+        attestation: PublicKey | null = null,
+        userProfile: PublicKey | null = null
     ): Promise<PublicKey> {
         let userMintAcc = await getOrCreateAssociatedTokenAccount(
             connection,
             user,
             mint.publicKey,
-            user.publicKey
+            user.publicKey,
+            false,
+            undefined,
+            undefined,
+            tokenProgram
         );
 
         const [userState] = PublicKey.findProgramAddressSync(
@@ -124,7 +201,7 @@ describe("Test staking-pool", () => {
         );
 
         await program.methods
-            .enterStaking(new BN(amount))
+            .enterStaking(new BN(amount), null)
             .accounts({
                 pool: pool.publicKey,
                 mint: mint.publicKey,
@@ -132,7 +209,18 @@ describe("Test staking-pool", () => {
                 userState,
                 vault: vault.address,
                 authority: user.publicKey,
-                tokenProgram: TOKEN_PROGRAM_ID,
+                tokenProgram,
+                leaderboard: null,
+                userCheckpoints: null,
+                poolCheckpoints: null,
+                config,
+                attestation,
+                feeExemption: null,
+                auditLog: null,
+                userProfile,
+                ratePublisher: null,
+                instructions: null,
+                walletState: null,
             })
             .signers([user])
             .rpc();
@@ -142,13 +230,19 @@ describe("Test staking-pool", () => {
 
     async function leave_staking(
         user: Keypair,
-        amount: number
+        amount: number,
+        config: PublicKey | null = null,
+        guardian: Keypair | null = null
     ): Promise<PublicKey> {
         let userMintAcc = await getOrCreateAssociatedTokenAccount(
             connection,
             user,
             mint.publicKey,
-            user.publicKey
+            user.publicKey,
+            false,
+            undefined,
+            undefined,
+            tokenProgram
         );
 
         const [userState] = PublicKey.findProgramAddressSync(
@@ -166,20 +260,333 @@ describe("Test staking-pool", () => {
                 userState,
                 vault: vault.address,
                 authority: user.publicKey,
-                tokenProgram: TOKEN_PROGRAM_ID,
+                tokenProgram,
+                leaderboard: null,
+                userCheckpoints: null,
+                poolCheckpoints: null,
+                config,
+                guardian: guardian?.publicKey ?? null,
+                feeExemption: null,
+                auditLog: null,
+                userProfile: null,
+                ratePublisher: null,
+                instructions: null,
+                memoProgram: null,
+                walletState: null,
+                linkBoosted: null,
+            })
+            .signers(guardian ? [user, guardian] : [user])
+            .rpc();
+
+        return userMintAcc.address;
+    }
+
+    async function request_withdrawal(
+        user: Keypair,
+        amount: number,
+        nonce: number,
+        vipBadge: PublicKey | null = null
+    ): Promise<PublicKey> {
+        const [userState] = PublicKey.findProgramAddressSync(
+            [pool.publicKey.toBuffer(), user.publicKey.toBuffer()],
+            program.programId
+        );
+        const [withdrawalRequest] = PublicKey.findProgramAddressSync(
+            [
+                Buffer.from(vipBadge ? "priority_withdrawal" : "withdrawal"),
+                pool.publicKey.toBuffer(),
+                user.publicKey.toBuffer(),
+                new BN(nonce).toArrayLike(Buffer, "le", 8),
+            ],
+            program.programId
+        );
+
+        await program.methods
+            .requestWithdrawal(new BN(amount), new BN(nonce), vipBadge != null)
+            .accounts({
+                pool: pool.publicKey,
+                userState,
+                withdrawalRequest,
+                authority: user.publicKey,
+                payer: user.publicKey,
+                leaderboard: null,
+                userCheckpoints: null,
+                poolCheckpoints: null,
+                config: null,
+                guardian: null,
+                vipBadge,
+                feeExemption: null,
+                auditLog: null,
+                walletState: null,
+                linkBoosted: null,
             })
             .signers([user])
             .rpc();
 
+        return withdrawalRequest;
+    }
+
+    async function fulfill_withdrawal(
+        user: Keypair,
+        withdrawalRequest: PublicKey
+    ) {
+        const userMintAcc = await getOrCreateAssociatedTokenAccount(
+            connection,
+            user,
+            mint.publicKey,
+            user.publicKey,
+            false,
+            undefined,
+            undefined,
+            tokenProgram
+        );
+
+        await program.methods
+            .fulfillWithdrawal()
+            .accounts({
+                pool: pool.publicKey,
+                programSigner,
+                mint: mint.publicKey,
+                vault: vault.address,
+                withdrawalRequest,
+                requester: user.publicKey,
+                userMintAcc: userMintAcc.address,
+                tokenProgram,
+                auditLog: null,
+            })
+            .rpc();
+    }
+
+    async function chain_time(): Promise<number> {
+        return await connection.getBlockTime(await connection.getSlot());
+    }
+
+    async function fund_emissions(funder: Keypair, amount: number) {
+        const funderMintAcc = await distribute_to(funder, amount);
+
+        await program.methods
+            .fundEmissions(new BN(amount))
+            .accounts({
+                pool: pool.publicKey,
+                mint: mint.publicKey,
+                vault: vault.address,
+                funderMintAcc,
+                funder: funder.publicKey,
+                tokenProgram,
+            })
+            .signers([funder])
+            .rpc();
+    }
+
+    // Funds the reserve for `duration` seconds at `rate` first, which
+    // covers every schedule.
+    async function set_emission_schedule(
+        admin: Keypair,
+        rate: number,
+        schedule: any,
+        duration: number = 86_400
+    ) {
+        if (rate > 0) {
+            await fund_emissions(admin, rate * duration);
+        }
+
+        await program.methods
+            .setEmissionSchedule(
+                new BN(rate),
+                schedule,
+                new BN((await chain_time()) + duration)
+            )
+            .accounts({
+                pool: pool.publicKey,
+                authority: admin.publicKey,
+                configChange: null,
+            })
+            .signers([admin])
+            .rpc();
+    }
+
+    // Funds the worst case for `duration` seconds first: `maxAprBps` on
+    // the whole target.
+    async function set_dynamic_apr(
+        admin: Keypair,
+        enabled: boolean,
+        utilizationTarget: number,
+        minAprBps: number,
+        maxAprBps: number,
+        duration: number = 86_400
+    ) {
+        if (enabled) {
+            const worstCase = new BN(maxAprBps)
+                .mul(new BN(utilizationTarget))
+                .mul(new BN(duration))
+                .div(new BN(10_000 * 31_536_000))
+                .addn(1);
+            await fund_emissions(admin, worstCase.toNumber());
+        }
+
+        await program.methods
+            .setDynamicApr(
+                enabled,
+                new BN(utilizationTarget),
+                minAprBps,
+                maxAprBps,
+                new BN((await chain_time()) + duration)
+            )
+            .accounts({
+                pool: pool.publicKey,
+                authority: admin.publicKey,
+                configChange: null,
+            })
+            .signers([admin])
+            .rpc();
+    }
+
+    async function set_streak_boost(
+        admin: Keypair,
+        epochDuration: number,
+        boostBpsPerEpoch: number,
+        maxBoostBps: number
+    ) {
+        await program.methods
+            .setStreakBoost(
+                new BN(epochDuration),
+                boostBpsPerEpoch,
+                maxBoostBps
+            )
+            .accounts({
+                pool: pool.publicKey,
+                authority: admin.publicKey,
+                configChange: null,
+            })
+            .signers([admin])
+            .rpc();
+    }
+
+    async function claim_rewards(
+        user: Keypair,
+        session: Keypair | null = null,
+        amount: number | null = null
+    ): Promise<PublicKey> {
+        let userMintAcc = await getOrCreateAssociatedTokenAccount(
+            connection,
+            user,
+            mint.publicKey,
+            user.publicKey,
+            false,
+            undefined,
+            undefined,
+            tokenProgram
+        );
+
+        const [userState] = PublicKey.findProgramAddressSync(
+            [pool.publicKey.toBuffer(), user.publicKey.toBuffer()],
+            program.programId
+        );
+
+        await program.methods
+            .claimRewards(amount == null ? null : new BN(amount))
+            .accounts({
+                pool: pool.publicKey,
+                programSigner,
+                mint: mint.publicKey,
+                vault: vault.address,
+                userMintAcc: userMintAcc.address,
+                user: user.publicKey,
+                userState,
+                authority: (session ?? user).publicKey,
+                tokenProgram,
+                leaderboard: null,
+                walletState: null,
+                auditLog: null,
+            })
+            .signers([session ?? user])
+            .rpc();
+
         return userMintAcc.address;
     }
 
+    function sha256(...parts: Buffer[]): Buffer {
+        return createHash("sha256").update(Buffer.concat(parts)).digest();
+    }
+
+    function merkle_leaf(user: PublicKey, amount: number): Buffer {
+        return sha256(
+            user.toBuffer(),
+            new BN(amount).toArrayLike(Buffer, "le", 8)
+        );
+    }
+
+    function merkle_parent(a: Buffer, b: Buffer): Buffer {
+        return Buffer.compare(a, b) <= 0 ? sha256(a, b) : sha256(b, a);
+    }
+
+    function get_checkpoints_address(
+        holder: PublicKey,
+        page: number
+    ): PublicKey {
+        const pageBytes = Buffer.alloc(4);
+        pageBytes.writeUInt32LE(page);
+
+        const [checkpoints] = PublicKey.findProgramAddressSync(
+            [
+                Buffer.from("checkpoints"),
+                pool.publicKey.toBuffer(),
+                holder.toBuffer(),
+                pageBytes,
+            ],
+            program.programId
+        );
+
+        return checkpoints;
+    }
+
+    function get_leaderboard_address(season: number): PublicKey {
+        const seasonBytes = Buffer.alloc(4);
+        seasonBytes.writeUInt32LE(season);
+
+        const [leaderboard] = PublicKey.findProgramAddressSync(
+            [
+                Buffer.from("leaderboard"),
+                pool.publicKey.toBuffer(),
+                seasonBytes,
+            ],
+            program.programId
+        );
+
+        return leaderboard;
+    }
+
+    // The next entry of the protocol's config history, which every global
+    // config change has to create.
+    async function next_global_config_change(): Promise<PublicKey> {
+        const [globalConfig] = PublicKey.findProgramAddressSync(
+            [Buffer.from("global_config")],
+            program.programId
+        );
+        const config = await program.account.globalConfig.fetch(globalConfig);
+
+        const [configChange] = PublicKey.findProgramAddressSync(
+            [
+                Buffer.from("config_change"),
+                globalConfig.toBuffer(),
+                config.configChangeCount.toArrayLike(Buffer, "le", 8),
+            ],
+            program.programId
+        );
+
+        return configChange;
+    }
+
     async function getMintBalanceOf(user: Keypair): Promise<BN> {
         let userMintAcc = await getOrCreateAssociatedTokenAccount(
             connection,
             user,
             mint.publicKey,
-            user.publicKey
+            user.publicKey,
+            false,
+            undefined,
+            undefined,
+            tokenProgram
         );
 
         const mintBalance = (
@@ -207,14 +614,86 @@ describe("Test staking-pool", () => {
         return us.stakedAmount;
     }
 
-    beforeEach(async () => {
+    // Compute units used by the last transaction `action` sends through
+    // the program's provider.
+    async function compute_units(
+        action: () => Promise<unknown>
+    ): Promise<number> {
+        const provider = program.provider as anchor.AnchorProvider;
+        const send = provider.sendAndConfirm;
+        let signature: string;
+        provider.sendAndConfirm = async (...args) =>
+            (signature = await send.apply(provider, args));
+        try {
+            await action();
+        } finally {
+            provider.sendAndConfirm = send;
+        }
+
+        await connection.confirmTransaction(signature, "confirmed");
+        const tx = await connection.getTransaction(signature, {
+            commitment: "confirmed",
+            maxSupportedTransactionVersion: 0,
+        });
+        return tx.meta.computeUnitsConsumed;
+    }
+
+    // A value of IDL type `type`, with distinct integers so that fields
+    // landing on the wrong bytes show up when re-encoded.
+    function sample_value(type: anchor.IdlType, counter = { next: 1 }): any {
+        if (typeof type === "string") {
+            switch (type) {
+                case "bool":
+                    return true;
+                case "pubkey":
+                    return Keypair.generate().publicKey;
+                case "string":
+                    return "sample";
+                case "bytes":
+                    return Buffer.from([counter.next++]);
+                case "u8":
+                case "i8":
+                case "u16":
+                case "i16":
+                case "u32":
+                case "i32":
+                    return counter.next++;
+                default:
+                    return new BN(counter.next++);
+            }
+        }
+        if ("option" in type) return sample_value(type.option, counter);
+        if ("vec" in type) return [sample_value(type.vec, counter)];
+        if ("array" in type) {
+            const [item, len] = type.array;
+            return Array.from({ length: len as number }, () =>
+                sample_value(item, counter)
+            );
+        }
+        if ("defined" in type) {
+            const def = program.idl.types.find(
+                (def) => def.name === (type as any).defined.name
+            );
+            assert.ok(def, `type ${(type as any).defined.name} in the IDL`);
+            const ty = def.type as any;
+            const fields = (fields: anchor.IdlField[] = []) =>
+                Object.fromEntries(
+                    fields.map((field) => [
+                        field.name,
+                        sample_value(field.type, counter),
+                    ])
+                );
+            if (ty.kind === "struct") return fields(ty.fields);
+            const variant = ty.variants[ty.variants.length - 1];
+            return { [variant.name]: fields(variant.fields) };
+        }
+        throw new Error(`no sample for ${JSON.stringify(type)}`);
+    }
+
+    async function create_pool_mint(programId: PublicKey) {
         pool = Keypair.generate();
         mint = Keypair.generate();
-        alice = Keypair.generate();
-        bob = Keypair.generate();
-
-        await airdropSol(provider, alice.publicKey, 100_000_000_000);
-        await airdropSol(provider, bob.publicKey, 100_000_000_000);
+        tokenProgram = programId;
 
         [programSigner, nonce] = PublicKey.findProgramAddressSync(
             [mint.publicKey.toBuffer(), pool.publicKey.toBuffer()],
@@ -227,7 +706,9 @@ describe("Test staking-pool", () => {
             programSigner,
             authority.publicKey,
             6,
-            mint
+            mint,
+            undefined,
+            tokenProgram
         );
 
         vault = await getOrCreateAssociatedTokenAccount(
@@ -235,8 +716,21 @@ describe("Test staking-pool", () => {
             authority.payer,
             mint.publicKey,
             programSigner,
-            true
+            true,
+            undefined,
+            undefined,
+            tokenProgram
         );
+    }
+
+    beforeEach(async () => {
+        alice = Keypair.generate();
+        bob = Keypair.generate();
+
+        await airdropSol(provider, alice.publicKey, 100_000_000_000);
+        await airdropSol(provider, bob.publicKey, 100_000_000_000);
+
+        await create_pool_mint(TOKEN_PROGRAM_ID);
     });
 
     it("Initialize", async () => {
@@ -250,16 +744,19 @@ describe("Test staking-pool", () => {
             programSigner.toString()
         );
         expect(poolContent.nonce).eq(nonce);
+        expect(poolContent.authority.toString()).eq(
+            authority.publicKey.toString()
+        );
     });
 
     it("Airdrop", async () => {
         await initialize();
 
-        await airdrop(alice, 10_000_000);
+        await distribute_to(alice, 10_000_000);
         const aliceMintBalance = await getMintBalanceOf(alice);
         expect(aliceMintBalance.toNumber()).eq(10_000_000);
 
-        await airdrop(bob, 30_000_000);
+        await distribute_to(bob, 30_000_000);
         const bobMintBalance = await getMintBalanceOf(bob);
         expect(bobMintBalance.toNumber()).eq(30_000_000);
     });
@@ -276,10 +773,10 @@ describe("Test staking-pool", () => {
     it("Enter staking", async () => {
         await initialize();
         await initialize_user_state(alice);
-        await airdrop(alice, 10_000_000);
+        await distribute_to(alice, 10_000_000);
 
         await initialize_user_state(bob);
-        await airdrop(bob, 30_000_000);
+        await distribute_to(bob, 30_000_000);
 
         // Alice staking
         await enter_staking(alice, 10_000_000);
@@ -307,7 +804,7 @@ describe("Test staking-pool", () => {
     it("Enter staking zero amount", async () => {
         await initialize();
         await initialize_user_state(alice);
-        await airdrop(alice, 10_000_000);
+        await distribute_to(alice, 10_000_000);
 
         // Alice staking zero
         try {
@@ -330,10 +827,10 @@ describe("Test staking-pool", () => {
     it("Leave staking", async () => {
         await initialize();
         await initialize_user_state(alice);
-        await airdrop(alice, 10_000_000);
+        await distribute_to(alice, 10_000_000);
 
         await initialize_user_state(bob);
-        await airdrop(bob, 30_000_000);
+        await distribute_to(bob, 30_000_000);
 
         // Alice & bob staking
         await enter_staking(alice, 10_000_000);
@@ -368,10 +865,10 @@ describe("Test staking-pool", () => {
     it("Leave staking partially", async () => {
         await initialize();
         await initialize_user_state(alice);
-        await airdrop(alice, 10_000_000);
+        await distribute_to(alice, 10_000_000);
 
         await initialize_user_state(bob);
-        await airdrop(bob, 30_000_000);
+        await distribute_to(bob, 30_000_000);
 
         // Alice & bob staking
         await enter_staking(alice, 10_000_000);
@@ -406,7 +903,7 @@ describe("Test staking-pool", () => {
     it("Leave staking invalid amount", async () => {
         await initialize();
         await initialize_user_state(alice);
-        await airdrop(alice, 10_000_000);
+        await distribute_to(alice, 10_000_000);
 
         // Alice staking
         await enter_staking(alice, 10_000_000);
@@ -427,4 +924,5357 @@ describe("Test staking-pool", () => {
         let alice_staked = await get_user_staked(alice);
         expect(alice_staked.toNumber()).eq(10_000_000);
     });
+
+    it("Leave staking invalid program signer", async () => {
+        await initialize();
+        await initialize_user_state(alice);
+        await distribute_to(alice, 10_000_000);
+
+        // Alice staking
+        let aliceMintAcc = await enter_staking(alice, 10_000_000);
+
+        const [userState] = PublicKey.findProgramAddressSync(
+            [pool.publicKey.toBuffer(), alice.publicKey.toBuffer()],
+            program.programId
+        );
+
+        try {
+            await program.methods
+                .leaveStaking(new BN(10_000_000))
+                .accounts({
+                    pool: pool.publicKey,
+                    mint: mint.publicKey,
+                    programSigner: Keypair.generate().publicKey,
+                    userMintAcc: aliceMintAcc,
+                    userState,
+                    vault: vault.address,
+                    authority: alice.publicKey,
+                    tokenProgram,
+                    leaderboard: null,
+                    userCheckpoints: null,
+                    poolCheckpoints: null,
+                    config: null,
+                    guardian: null,
+                    feeExemption: null,
+                    auditLog: null,
+                    userProfile: null,
+                    ratePublisher: null,
+                    instructions: null,
+                    memoProgram: null,
+                    walletState: null,
+                    linkBoosted: null,
+                })
+                .signers([alice])
+                .rpc();
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        let total = await get_staked_total();
+        expect(total.toNumber()).eq(10_000_000);
+
+        let alice_staked = await get_user_staked(alice);
+        expect(alice_staked.toNumber()).eq(10_000_000);
+    });
+
+    it("Enter and leave staking with Token-2022 mint", async () => {
+        await create_pool_mint(TOKEN_2022_PROGRAM_ID);
+
+        await initialize();
+        await initialize_user_state(alice);
+        await distribute_to(alice, 10_000_000);
+
+        await enter_staking(alice, 10_000_000);
+        let total = await get_staked_total();
+        expect(total.toNumber()).eq(10_000_000);
+
+        await leave_staking(alice, 4_000_000);
+        total = await get_staked_total();
+        expect(total.toNumber()).eq(6_000_000);
+
+        const aliceMintBalance = await getMintBalanceOf(alice);
+        expect(aliceMintBalance.toNumber()).eq(4_000_000);
+
+        let alice_staked = await get_user_staked(alice);
+        expect(alice_staked.toNumber()).eq(6_000_000);
+    });
+
+    it("Claim rewards", async () => {
+        await initialize();
+        await initialize_user_state(alice);
+        await distribute_to(alice, 10_000_000);
+
+        await enter_staking(alice, 10_000_000);
+        await set_emission_schedule(authority.payer, 1_000, { constant: {} });
+
+        await new Promise((resolve) => setTimeout(resolve, 3000));
+
+        await claim_rewards(alice);
+        const aliceMintBalance = await getMintBalanceOf(alice);
+        expect(aliceMintBalance.toNumber()).gt(0);
+
+        let alice_staked = await get_user_staked(alice);
+        expect(alice_staked.toNumber()).eq(10_000_000);
+    });
+
+    it("Set emission schedule unauthorized", async () => {
+        await initialize();
+
+        try {
+            await set_emission_schedule(bob, 1_000, {
+                halving: { interval: new BN(86_400) },
+            });
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        const poolContent = await program.account.pool.fetch(pool.publicKey);
+        expect(poolContent.emissionRate.toNumber()).eq(0);
+    });
+
+    it("Emissions need a funded reserve", async () => {
+        await initialize();
+
+        const set_schedule = async (rate: number, endTs: number) =>
+            program.methods
+                .setEmissionSchedule(new BN(rate), { constant: {} }, new BN(endTs))
+                .accounts({
+                    pool: pool.publicKey,
+                    authority: authority.publicKey,
+                    configChange: null,
+                })
+                .rpc();
+        const extend = async (endTs: number) =>
+            program.methods
+                .extendEmissions(new BN(endTs))
+                .accounts({
+                    pool: pool.publicKey,
+                    authority: authority.publicKey,
+                    configChange: null,
+                })
+                .rpc();
+
+        // Nothing in the reserve
+        try {
+            await set_schedule(1_000, (await chain_time()) + 100);
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        await fund_emissions(authority.payer, 100_000);
+        let poolContent = await program.account.pool.fetch(pool.publicKey);
+        expect(poolContent.rewardReserve.toNumber()).eq(100_000);
+
+        await set_schedule(1_000, (await chain_time()) + 50);
+        poolContent = await program.account.pool.fetch(pool.publicKey);
+        const stop = poolContent.emissionStopTs.toNumber();
+
+        // Past what the reserve covers
+        try {
+            await extend(stop + 1_000);
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        await fund_emissions(authority.payer, 1_000_000);
+        await extend(stop + 1_000);
+        poolContent = await program.account.pool.fetch(pool.publicKey);
+        expect(poolContent.emissionStopTs.toNumber()).eq(stop + 1_000);
+    });
+
+    it("Dynamic APR", async () => {
+        await initialize();
+        await initialize_user_state(alice);
+        await distribute_to(alice, 10_000_000);
+
+        // Invalid: floor above cap
+        try {
+            await set_dynamic_apr(
+                authority.payer,
+                true,
+                100_000_000,
+                2_000,
+                1_000
+            );
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        // Invalid: no reserve behind the curve
+        try {
+            await program.methods
+                .setDynamicApr(
+                    true,
+                    new BN(100_000_000),
+                    1_000,
+                    50_000,
+                    new BN((await chain_time()) + 86_400)
+                )
+                .accounts({
+                    pool: pool.publicKey,
+                    authority: authority.publicKey,
+                    configChange: null,
+                })
+                .rpc();
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        await set_dynamic_apr(
+            authority.payer,
+            true,
+            100_000_000,
+            1_000,
+            50_000
+        );
+        await enter_staking(alice, 10_000_000);
+
+        await new Promise((resolve) => setTimeout(resolve, 3000));
+
+        await claim_rewards(alice);
+        const aliceMintBalance = await getMintBalanceOf(alice);
+        expect(aliceMintBalance.toNumber()).gt(0);
+
+        const poolContent = await program.account.pool.fetch(pool.publicKey);
+        expect(poolContent.dynamicApr).eq(1);
+        expect(poolContent.minAprBps).eq(1_000);
+        expect(poolContent.maxAprBps).eq(50_000);
+    });
+
+    it("Streak boost", async () => {
+        await initialize();
+        await initialize_user_state(alice);
+        await distribute_to(alice, 10_000_000);
+
+        // Invalid: boost cap above the hard limit
+        try {
+            await set_streak_boost(authority.payer, 1, 1_000, 20_000);
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        await set_streak_boost(authority.payer, 1, 1_000, 5_000);
+        await set_emission_schedule(authority.payer, 1_000, { constant: {} });
+        await enter_staking(alice, 10_000_000);
+
+        await new Promise((resolve) => setTimeout(resolve, 3000));
+
+        await claim_rewards(alice);
+        const aliceMintBalance = await getMintBalanceOf(alice);
+        expect(aliceMintBalance.toNumber()).gt(0);
+
+        const [userState] = PublicKey.findProgramAddressSync(
+            [pool.publicKey.toBuffer(), alice.publicKey.toBuffer()],
+            program.programId
+        );
+        const us = await program.account.userState.fetch(userState);
+        expect(us.streakEpochs.toNumber()).gt(0);
+    });
+
+    it("Points mode", async () => {
+        await initialize();
+        await initialize_user_state(alice);
+        await distribute_to(alice, 10_000_000);
+
+        await program.methods
+            .setPointsMode(true)
+            .accounts({
+                pool: pool.publicKey,
+                authority: authority.publicKey,
+                configChange: null,
+            })
+            .signers([authority.payer])
+            .rpc();
+
+        await set_emission_schedule(authority.payer, 1_000, { constant: {} });
+        await enter_staking(alice, 10_000_000);
+
+        await new Promise((resolve) => setTimeout(resolve, 3000));
+
+        // Claiming credits points, nothing is minted
+        await claim_rewards(alice);
+        const aliceMintBalance = await getMintBalanceOf(alice);
+        expect(aliceMintBalance.toNumber()).eq(0);
+
+        const [userState] = PublicKey.findProgramAddressSync(
+            [pool.publicKey.toBuffer(), alice.publicKey.toBuffer()],
+            program.programId
+        );
+        let us = await program.account.userState.fetch(userState);
+        const points = us.points.toNumber();
+        expect(points).gt(0);
+
+        // Only the authority can settle
+        try {
+            await program.methods
+                .settlePoints()
+                .accounts({
+                    pool: pool.publicKey,
+                    user: alice.publicKey,
+                    userState,
+                    authority: bob.publicKey,
+                })
+                .signers([bob])
+                .rpc();
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        await program.methods
+            .settlePoints()
+            .accounts({
+                pool: pool.publicKey,
+                user: alice.publicKey,
+                userState,
+                authority: authority.publicKey,
+            })
+            .signers([authority.payer])
+            .rpc();
+
+        us = await program.account.userState.fetch(userState);
+        expect(us.points.toNumber()).eq(0);
+        expect(us.settledPoints.toNumber()).eq(points);
+    });
+
+    it("Season leaderboard", async () => {
+        await initialize();
+        await initialize_user_state(alice);
+        await distribute_to(alice, 10_000_000);
+        await initialize_user_state(bob);
+        await distribute_to(bob, 30_000_000);
+
+        const leaderboard = get_leaderboard_address(1);
+        await program.methods
+            .startSeason(1)
+            .accounts({
+                pool: pool.publicKey,
+                leaderboard,
+                authority: authority.publicKey,
+            })
+            .signers([authority.payer])
+            .rpc();
+
+        await enter_staking(alice, 10_000_000);
+        await enter_staking(bob, 30_000_000);
+
+        await new Promise((resolve) => setTimeout(resolve, 2000));
+
+        // Anyone can crank the leaderboard
+        for (const user of [alice, bob]) {
+            const [userState] = PublicKey.findProgramAddressSync(
+                [pool.publicKey.toBuffer(), user.publicKey.toBuffer()],
+                program.programId
+            );
+
+            await program.methods
+                .updateLeaderboard()
+                .accounts({
+                    pool: pool.publicKey,
+                    user: user.publicKey,
+                    userState,
+                    leaderboard,
+                    walletState: null,
+                })
+                .rpc();
+        }
+
+        const board = await program.account.leaderboard.fetch(leaderboard);
+        expect(board.season).eq(1);
+        expect(board.entries[0].user.toString()).eq(bob.publicKey.toString());
+        expect(board.entries[1].user.toString()).eq(
+            alice.publicKey.toString()
+        );
+        expect(board.entries[0].stakeSeconds.toNumber()).gt(
+            board.entries[1].stakeSeconds.toNumber()
+        );
+    });
+
+    it("Vote delegation", async () => {
+        await initialize();
+        await initialize_user_state(alice);
+        await distribute_to(alice, 10_000_000);
+        await enter_staking(alice, 10_000_000);
+
+        const [userState] = PublicKey.findProgramAddressSync(
+            [pool.publicKey.toBuffer(), alice.publicKey.toBuffer()],
+            program.programId
+        );
+
+        let weight = await program.methods
+            .getVoterWeight()
+            .accounts({
+                pool: pool.publicKey,
+                user: alice.publicKey,
+                userState,
+            })
+            .view();
+        expect(weight.voter.toString()).eq(alice.publicKey.toString());
+        expect(weight.weight.toNumber()).eq(10_000_000);
+
+        await program.methods
+            .setVoteDelegate(bob.publicKey)
+            .accounts({
+                pool: pool.publicKey,
+                userState,
+                authority: alice.publicKey,
+            })
+            .signers([alice])
+            .rpc();
+
+        weight = await program.methods
+            .getVoterWeight()
+            .accounts({
+                pool: pool.publicKey,
+                user: alice.publicKey,
+                userState,
+            })
+            .view();
+        expect(weight.voter.toString()).eq(bob.publicKey.toString());
+        expect(weight.weight.toNumber()).eq(10_000_000);
+    });
+
+    it("Voting power checkpoints", async () => {
+        await initialize();
+        await initialize_user_state(alice);
+        await distribute_to(alice, 30_000_001);
+        await enter_staking(alice, 10_000_000);
+
+        const [userState] = PublicKey.findProgramAddressSync(
+            [pool.publicKey.toBuffer(), alice.publicKey.toBuffer()],
+            program.programId
+        );
+        const userCheckpoints = get_checkpoints_address(alice.publicKey, 0);
+        const poolCheckpoints = get_checkpoints_address(pool.publicKey, 0);
+
+        // Anyone can pay for opening a page
+        for (const [holder, state, checkpoints] of [
+            [pool.publicKey, null, poolCheckpoints],
+            [alice.publicKey, userState, userCheckpoints],
+        ]) {
+            await program.methods
+                .openCheckpointPage(0)
+                .accounts({
+                    pool: pool.publicKey,
+                    holder,
+                    userState: state,
+                    walletState: null,
+                    previousCheckpoints: null,
+                    checkpoints,
+                    payer: authority.publicKey,
+                })
+                .rpc();
+        }
+
+        await program.methods
+            .setCheckpointsEnabled(true)
+            .accounts({
+                pool: pool.publicKey,
+                authority: authority.publicKey,
+                configChange: null,
+            })
+            .signers([authority.payer])
+            .rpc();
+
+        const before = await connection.getSlot();
+        await new Promise((resolve) => setTimeout(resolve, 1000));
+
+        let userMintAcc = await getOrCreateAssociatedTokenAccount(
+            connection,
+            alice,
+            mint.publicKey,
+            alice.publicKey,
+            false,
+            undefined,
+            undefined,
+            tokenProgram
+        );
+        await program.methods
+            .enterStaking(new BN(20_000_000), null)
+            .accounts({
+                pool: pool.publicKey,
+                mint: mint.publicKey,
+                userMintAcc: userMintAcc.address,
+                userState,
+                vault: vault.address,
+                authority: alice.publicKey,
+                tokenProgram,
+                leaderboard: null,
+                userCheckpoints,
+                poolCheckpoints,
+                config: null,
+                attestation: null,
+                feeExemption: null,
+                auditLog: null,
+                userProfile: null,
+                ratePublisher: null,
+                instructions: null,
+                walletState: null,
+            })
+            .signers([alice])
+            .rpc();
+
+        // Checkpoints are required while enabled
+        try {
+            await enter_staking(alice, 1);
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        const after = await connection.getSlot();
+
+        const voting_power_at = async (holder: PublicKey, slot: number) =>
+            (
+                await program.methods
+                    .getVotingPowerAt(new BN(slot))
+                    .accounts({
+                        pool: pool.publicKey,
+                        holder,
+                        userState: holder.equals(pool.publicKey)
+                            ? null
+                            : userState,
+                        walletState: null,
+                        checkpoints: get_checkpoints_address(holder, 0),
+                        nextCheckpoints: null,
+                    })
+                    .view()
+            ).toNumber();
+
+        expect(await voting_power_at(alice.publicKey, before)).eq(10_000_000);
+        expect(await voting_power_at(alice.publicKey, after)).eq(30_000_000);
+        expect(await voting_power_at(pool.publicKey, before)).eq(10_000_000);
+        expect(await voting_power_at(pool.publicKey, after)).eq(30_000_000);
+    });
+    it("Restaking adapter", async () => {
+        await initialize();
+        await initialize_user_state(alice);
+        await distribute_to(alice, 10_000_000);
+        await enter_staking(alice, 10_000_000);
+
+        const adapter = Keypair.generate().publicKey;
+
+        // Buffer can't exceed 100%
+        try {
+            await program.methods
+                .setRestakingAdapter(adapter, 10_001)
+                .accounts({
+                    pool: pool.publicKey,
+                    authority: authority.publicKey,
+                    configChange: null,
+                })
+                .signers([authority.payer])
+                .rpc();
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        await program.methods
+            .setRestakingAdapter(adapter, 2_000)
+            .accounts({
+                pool: pool.publicKey,
+                authority: authority.publicKey,
+                configChange: null,
+            })
+            .signers([authority.payer])
+            .rpc();
+
+        const poolAccount = await program.account.pool.fetch(pool.publicKey);
+        expect(poolAccount.adapterProgram.toString()).eq(adapter.toString());
+        expect(poolAccount.bufferBps).eq(2_000);
+
+        // The adapter is only ever signed for by the restaking authority
+        const [restakingAuthority] = PublicKey.findProgramAddressSync(
+            [Buffer.from("restaking"), pool.publicKey.toBuffer()],
+            program.programId
+        );
+        const restakingSource = await getOrCreateAssociatedTokenAccount(
+            connection,
+            authority.payer,
+            mint.publicKey,
+            restakingAuthority,
+            true,
+            undefined,
+            undefined,
+            tokenProgram
+        );
+
+        // Only the whitelisted adapter can receive funds
+        try {
+            await program.methods
+                .deployFunds(new BN(1_000_000))
+                .accounts({
+                    pool: pool.publicKey,
+                    programSigner,
+                    mint: mint.publicKey,
+                    vault: vault.address,
+                    adapterProgram: SystemProgram.programId,
+                    restakingAuthority,
+                    restakingSource: restakingSource.address,
+                    authority: authority.publicKey,
+                    tokenProgram,
+                })
+                .signers([authority.payer])
+                .rpc();
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+    });
+    it("Withdrawal queue", async () => {
+        await initialize();
+        await initialize_user_state(alice);
+        await distribute_to(alice, 10_000_000);
+        await enter_staking(alice, 10_000_000);
+
+        const withdrawalRequest = await request_withdrawal(
+            alice,
+            4_000_000,
+            0
+        );
+
+        let poolAccount = await program.account.pool.fetch(pool.publicKey);
+        expect(poolAccount.queueTail.toNumber()).eq(1);
+        expect(poolAccount.queuedAmount.toNumber()).eq(4_000_000);
+        expect((await get_user_staked(alice)).toNumber()).eq(6_000_000);
+
+        // Instant withdrawals can't take liquidity owed to the queue
+        try {
+            await leave_staking(alice, 6_000_001);
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        // Anyone can fulfill the head of the queue, but only once
+        await fulfill_withdrawal(alice, withdrawalRequest);
+        try {
+            await fulfill_withdrawal(alice, withdrawalRequest);
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        // Paid in full, so the request is closed
+        expect(await connection.getAccountInfo(withdrawalRequest)).eq(null);
+        expect((await getMintBalanceOf(alice)).toNumber()).eq(4_000_000);
+
+        poolAccount = await program.account.pool.fetch(pool.publicKey);
+        expect(poolAccount.queueHead.toNumber()).eq(1);
+        expect(poolAccount.queuedAmount.toNumber()).eq(0);
+    });
+    it("Pool config", async () => {
+        await initialize();
+        await initialize_user_state(alice);
+        await distribute_to(alice, 30_000_000);
+
+        const [config] = PublicKey.findProgramAddressSync(
+            [Buffer.from("config"), pool.publicKey.toBuffer()],
+            program.programId
+        );
+        const params = {
+            depositFeeBps: 100,
+            withdrawalFeeBps: 100,
+            earlyWithdrawalPenaltyBps: 500,
+            lockDuration: new BN(3_600),
+            maxStakePerUser: new BN(20_000_000),
+            maxStakedTotal: new BN(0),
+            verifiedMaxStakePerUser: new BN(0),
+            attestationProgram: PublicKey.default,
+            attestationSchema: PublicKey.default,
+            gcInactivity: new BN(0),
+            gcCrankerBps: 0,
+        };
+
+        // Fees are capped no matter who the admin is
+        try {
+            await program.methods
+                .initializePoolConfig({ ...params, depositFeeBps: 10_000 })
+                .accounts({
+                    pool: pool.publicKey,
+                    config,
+                    authority: authority.publicKey,
+                })
+                .signers([authority.payer])
+                .rpc();
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        await program.methods
+            .initializePoolConfig(params)
+            .accounts({
+                pool: pool.publicKey,
+                config,
+                authority: authority.publicKey,
+            })
+            .signers([authority.payer])
+            .rpc();
+
+        // The config can't be skipped once it exists
+        try {
+            await enter_staking(alice, 10_000_000);
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        await enter_staking(alice, 10_000_000, config);
+        expect((await get_user_staked(alice)).toNumber()).eq(9_900_000);
+
+        try {
+            await enter_staking(alice, 20_000_000, config);
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        // Still locked: withdrawal fee plus early-withdrawal penalty
+        await leave_staking(alice, 1_000_000, config);
+        expect((await getMintBalanceOf(alice)).toNumber()).eq(20_940_000);
+
+        const poolAccount = await program.account.pool.fetch(pool.publicKey);
+        expect(poolAccount.protocolFees.toNumber()).eq(160_000);
+
+        // Deposit and withdrawal fees, and the penalty, counted apart
+        const stats = await program.methods
+            .getPoolStats()
+            .accounts({ pool: pool.publicKey })
+            .view();
+        expect(stats.totalFeesCollected.toNumber()).eq(110_000);
+        expect(stats.totalPenalties.toNumber()).eq(50_000);
+
+        const destination = await getOrCreateAssociatedTokenAccount(
+            connection,
+            authority.payer,
+            mint.publicKey,
+            authority.publicKey,
+            false,
+            undefined,
+            undefined,
+            tokenProgram
+        );
+        await program.methods
+            .collectProtocolFees()
+            .accounts({
+                pool: pool.publicKey,
+                programSigner,
+                mint: mint.publicKey,
+                vault: vault.address,
+                destination: destination.address,
+                authority: authority.publicKey,
+                tokenProgram,
+            })
+            .signers([authority.payer])
+            .rpc();
+
+        const collected = await connection.getTokenAccountBalance(
+            destination.address
+        );
+        expect(collected.value.amount).eq("160000");
+    });
+    it("Initialize user states for migrating wallets", async () => {
+        await initialize();
+
+        // The operator pays rent; users don't sign
+        for (const user of [alice, bob]) {
+            const [userState] = PublicKey.findProgramAddressSync(
+                [pool.publicKey.toBuffer(), user.publicKey.toBuffer()],
+                program.programId
+            );
+
+            await program.methods
+                .initUserStateFor(user.publicKey)
+                .accounts({
+                    userState,
+                    pool: pool.publicKey,
+                    payer: authority.publicKey,
+                })
+                .signers([authority.payer])
+                .rpc();
+        }
+
+        // Already initialized
+        try {
+            await initialize_user_state(alice);
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        await distribute_to(alice, 10_000_000);
+        await enter_staking(alice, 10_000_000);
+        expect((await get_user_staked(alice)).toNumber()).eq(10_000_000);
+    });
+    it("Import legacy stake", async () => {
+        await initialize();
+        await initialize_user_state(alice);
+        await initialize_user_state(bob);
+
+        const aliceLeaf = merkle_leaf(alice.publicKey, 5_000_000);
+        const bobLeaf = merkle_leaf(bob.publicKey, 7_000_000);
+        const root = merkle_parent(aliceLeaf, bobLeaf);
+
+        const [migration] = PublicKey.findProgramAddressSync(
+            [Buffer.from("migration"), pool.publicKey.toBuffer()],
+            program.programId
+        );
+
+        await program.methods
+            .publishMigrationRoot([...root])
+            .accounts({
+                pool: pool.publicKey,
+                migration,
+                authority: authority.publicKey,
+            })
+            .signers([authority.payer])
+            .rpc();
+
+        const source = await distribute_to(authority.payer, 12_000_000);
+        await program.methods
+            .fundMigration(new BN(12_000_000))
+            .accounts({
+                pool: pool.publicKey,
+                mint: mint.publicKey,
+                vault: vault.address,
+                source,
+                authority: authority.publicKey,
+                tokenProgram,
+            })
+            .signers([authority.payer])
+            .rpc();
+
+        const import_stake = async (
+            user: Keypair,
+            amount: number,
+            proof: Buffer[]
+        ) => {
+            const [userState] = PublicKey.findProgramAddressSync(
+                [pool.publicKey.toBuffer(), user.publicKey.toBuffer()],
+                program.programId
+            );
+            const [importReceipt] = PublicKey.findProgramAddressSync(
+                [
+                    Buffer.from("imported"),
+                    pool.publicKey.toBuffer(),
+                    user.publicKey.toBuffer(),
+                ],
+                program.programId
+            );
+
+            await program.methods
+                .importStake(new BN(amount), proof.map((node) => [...node]))
+                .accounts({
+                    pool: pool.publicKey,
+                    migration,
+                    user: user.publicKey,
+                    userState,
+                    importReceipt,
+                    payer: authority.publicKey,
+                    userCheckpoints: null,
+                    poolCheckpoints: null,
+                    auditLog: null,
+                    walletState: null,
+                })
+                .signers([authority.payer])
+                .rpc();
+        };
+
+        // Amount must match the snapshot
+        try {
+            await import_stake(alice, 6_000_000, [bobLeaf]);
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        await import_stake(alice, 5_000_000, [bobLeaf]);
+        await import_stake(bob, 7_000_000, [aliceLeaf]);
+        expect((await get_user_staked(alice)).toNumber()).eq(5_000_000);
+        expect((await get_user_staked(bob)).toNumber()).eq(7_000_000);
+
+        // Each wallet imports once
+        try {
+            await import_stake(alice, 5_000_000, [bobLeaf]);
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        const poolAccount = await program.account.pool.fetch(pool.publicKey);
+        expect(poolAccount.migrationEscrow.toNumber()).eq(0);
+        expect(poolAccount.stakedTotal.toNumber()).eq(12_000_000);
+    });
+    it("Commit snapshot", async () => {
+        await initialize();
+        for (const [user, amount] of [
+            [alice, 10_000_000],
+            [bob, 30_000_000],
+        ] as [Keypair, number][]) {
+            await initialize_user_state(user);
+            await distribute_to(user, amount);
+            await enter_staking(user, amount);
+        }
+
+        const aliceLeaf = merkle_leaf(alice.publicKey, 10_000_000);
+        const bobLeaf = merkle_leaf(bob.publicKey, 30_000_000);
+        const root = merkle_parent(aliceLeaf, bobLeaf);
+
+        const [snapshot] = PublicKey.findProgramAddressSync(
+            [
+                Buffer.from("snapshot"),
+                pool.publicKey.toBuffer(),
+                new BN(0).toArrayLike(Buffer, "le", 8),
+            ],
+            program.programId
+        );
+
+        await program.methods
+            .commitSnapshot(new BN(0), [...root])
+            .accounts({
+                pool: pool.publicKey,
+                snapshot,
+                authority: authority.publicKey,
+            })
+            .signers([authority.payer])
+            .rpc();
+
+        const snapshotAccount = await program.account.snapshot.fetch(snapshot);
+        expect(snapshotAccount.stakedTotal.toNumber()).eq(40_000_000);
+        expect(snapshotAccount.slot.toNumber()).gt(0);
+
+        const verify = (user: Keypair, amount: number, proof: Buffer[]) =>
+            program.methods
+                .verifySnapshotProof(
+                    user.publicKey,
+                    new BN(amount),
+                    proof.map((node) => [...node])
+                )
+                .accounts({ snapshot })
+                .view();
+
+        expect(await verify(alice, 10_000_000, [bobLeaf])).eq(true);
+        expect(await verify(bob, 30_000_000, [aliceLeaf])).eq(true);
+        expect(await verify(bob, 40_000_000, [aliceLeaf])).eq(false);
+    });
+    it("Lottery", async () => {
+        await initialize();
+        await initialize_user_state(alice);
+        await distribute_to(alice, 10_000_000);
+        await enter_staking(alice, 10_000_000);
+
+        // Alice holds every ticket
+        const aliceLeaf = sha256(
+            alice.publicKey.toBuffer(),
+            new BN(0).toArrayLike(Buffer, "le", 8),
+            new BN(10_000_000).toArrayLike(Buffer, "le", 8)
+        );
+        const [userState] = PublicKey.findProgramAddressSync(
+            [pool.publicKey.toBuffer(), alice.publicKey.toBuffer()],
+            program.programId
+        );
+
+        const [lottery] = PublicKey.findProgramAddressSync(
+            [
+                Buffer.from("lottery"),
+                pool.publicKey.toBuffer(),
+                new BN(1).toArrayLike(Buffer, "le", 8),
+            ],
+            program.programId
+        );
+
+        const start_lottery = async () => {
+            const authorityMintAcc = await distribute_to(
+                authority.payer,
+                1_000_000
+            );
+            await program.methods
+                .startLottery(
+                    new BN(1),
+                    [...aliceLeaf],
+                    new BN(10_000_000),
+                    2,
+                    new BN(1_000_000)
+                )
+                .accounts({
+                    pool: pool.publicKey,
+                    lottery,
+                    // Stands in for the randomness adapter
+                    randomnessProgram: SystemProgram.programId,
+                    mint: mint.publicKey,
+                    vault: vault.address,
+                    authorityMintAcc,
+                    authority: authority.publicKey,
+                    tokenProgram,
+                })
+                .signers([authority.payer])
+                .rpc();
+        };
+
+        await start_lottery();
+        const lotteryAccount = await program.account.lottery.fetch(lottery);
+        expect(lotteryAccount.prize.toNumber()).eq(500_000);
+        expect(lotteryAccount.drawn).eq(false);
+        const poolAccount = await program.account.pool.fetch(pool.publicKey);
+        expect(poolAccount.fundedRewards.toNumber()).eq(1_000_000);
+
+        // One lottery per epoch
+        try {
+            await start_lottery();
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        // Nothing to reveal before the request
+        try {
+            await program.methods
+                .drawLottery()
+                .accounts({
+                    lottery,
+                    randomnessProgram: SystemProgram.programId,
+                    randomnessAccount: SystemProgram.programId,
+                })
+                .rpc();
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        // No prizes before the draw
+        try {
+            await program.methods
+                .claimLotteryPrize(0, new BN(0), new BN(10_000_000), [])
+                .accounts({
+                    pool: pool.publicKey,
+                    lottery,
+                    userState,
+                    authority: alice.publicKey,
+                })
+                .signers([alice])
+                .rpc();
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+    });
+    it("Sharded deposits", async () => {
+        await initialize();
+        await initialize_user_state(alice);
+        await initialize_user_state(bob);
+
+        const [shard] = PublicKey.findProgramAddressSync(
+            [Buffer.from("shard"), pool.publicKey.toBuffer(), Buffer.from([0])],
+            program.programId
+        );
+        const [shardVault] = PublicKey.findProgramAddressSync(
+            [Buffer.from("shard_vault"), shard.toBuffer()],
+            program.programId
+        );
+        const shard_deposit = (user: Keypair) =>
+            PublicKey.findProgramAddressSync(
+                [
+                    Buffer.from("shard"),
+                    shard.toBuffer(),
+                    user.publicKey.toBuffer(),
+                ],
+                program.programId
+            )[0];
+        const user_state = (user: Keypair) =>
+            PublicKey.findProgramAddressSync(
+                [pool.publicKey.toBuffer(), user.publicKey.toBuffer()],
+                program.programId
+            )[0];
+        const shard_batch = (generation: number) =>
+            PublicKey.findProgramAddressSync(
+                [
+                    Buffer.from("shard_batch"),
+                    shard.toBuffer(),
+                    new BN(generation).toArrayLike(Buffer, "le", 8),
+                ],
+                program.programId
+            )[0];
+
+        await program.methods
+            .initializeStakeShard(0)
+            .accounts({
+                pool: pool.publicKey,
+                programSigner,
+                shard,
+                shardVault,
+                mint: mint.publicKey,
+                authority: authority.publicKey,
+                tokenProgram,
+            })
+            .signers([authority.payer])
+            .rpc();
+
+        const deposit = async (user: Keypair, amount: number) => {
+            const userMintAcc = await distribute_to(user, amount);
+            await program.methods
+                .depositToShard(new BN(amount))
+                .accounts({
+                    pool: pool.publicKey,
+                    shard,
+                    shardVault,
+                    mint: mint.publicKey,
+                    shardDeposit: shard_deposit(user),
+                    userMintAcc,
+                    userState: user_state(user),
+                    walletState: null,
+                    authority: user.publicKey,
+                    config: null,
+                    attestation: null,
+                    tokenProgram,
+                })
+                .signers([user])
+                .rpc();
+
+            return userMintAcc;
+        };
+
+        await deposit(alice, 10_000_000);
+        const bobMintAcc = await deposit(bob, 5_000_000);
+        let poolAccount = await program.account.pool.fetch(pool.publicKey);
+        expect(poolAccount.stakedTotal.toNumber()).eq(0);
+
+        // Bob takes his deposit back before the merge
+        await program.methods
+            .withdrawShardDeposit()
+            .accounts({
+                pool: pool.publicKey,
+                programSigner,
+                mint: mint.publicKey,
+                shard,
+                shardVault,
+                shardDeposit: shard_deposit(bob),
+                userMintAcc: bobMintAcc,
+                authority: bob.publicKey,
+                tokenProgram,
+            })
+            .signers([bob])
+            .rpc();
+
+        await program.methods
+            .mergeStakeShard()
+            .accounts({
+                pool: pool.publicKey,
+                programSigner,
+                mint: mint.publicKey,
+                vault: vault.address,
+                shard,
+                shardVault,
+                shardBatch: shard_batch(0),
+                payer: authority.publicKey,
+                config: null,
+                tokenProgram,
+            })
+            .signers([authority.payer])
+            .rpc();
+        poolAccount = await program.account.pool.fetch(pool.publicKey);
+        expect(poolAccount.stakedTotal.toNumber()).eq(10_000_000);
+
+        // A merged deposit is credited before the next one
+        try {
+            await deposit(alice, 1_000_000);
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        // Anyone can credit it
+        await program.methods
+            .creditShardDeposit()
+            .accounts({
+                pool: pool.publicKey,
+                shard,
+                shardDeposit: shard_deposit(alice),
+                user: alice.publicKey,
+                shardBatch: shard_batch(0),
+                batchPayer: authority.publicKey,
+                userState: user_state(alice),
+                walletState: null,
+                leaderboard: null,
+                userCheckpoints: null,
+                poolCheckpoints: null,
+                config: null,
+                feeExemption: null,
+                auditLog: null,
+            })
+            .rpc();
+        expect((await get_user_staked(alice)).toNumber()).eq(10_000_000);
+        expect(await connection.getAccountInfo(shard_batch(0))).eq(null);
+        poolAccount = await program.account.pool.fetch(pool.publicKey);
+        expect(poolAccount.stakedTotal.toNumber()).eq(10_000_000);
+    });
+    it("Pool stats", async () => {
+        await initialize();
+        await initialize_user_state(alice);
+        await distribute_to(alice, 10_000_000);
+        await enter_staking(alice, 10_000_000);
+
+        const get_pool_stats = () =>
+            program.methods
+                .getPoolStats()
+                .accounts({ pool: pool.publicKey })
+                .view();
+
+        await set_emission_schedule(authority.payer, 1, { constant: {} });
+        let stats = await get_pool_stats();
+        expect(stats.stakedTotal.toNumber()).eq(10_000_000);
+        expect(stats.emissionRate.toNumber()).eq(1);
+        // 1 token/s over a year against 10M staked
+        expect(stats.aprBps.toNumber()).eq(31_536);
+        expect(stats.apyBps.toNumber()).gt(31_536);
+        expect(stats.secondsToEmissionEnd.toNumber()).lte(86_400);
+
+        await set_emission_schedule(authority.payer, 1_000, {
+            linearDecay: { duration: new BN(1_000) },
+        });
+        stats = await get_pool_stats();
+        expect(stats.emissionRate.toNumber()).lte(1_000);
+        expect(stats.secondsToEmissionEnd.toNumber()).gt(0);
+        expect(stats.secondsToEmissionEnd.toNumber()).lte(1_000);
+
+        // Nothing left to emit, whatever the schedule.
+        await set_emission_schedule(authority.payer, 0, { constant: {} });
+        stats = await get_pool_stats();
+        expect(stats.secondsToEmissionEnd.toNumber()).eq(0);
+    });
+    it("Withdrawal guardian", async () => {
+        await initialize();
+        await initialize_user_state(alice);
+        await distribute_to(alice, 10_000_000);
+        await enter_staking(alice, 10_000_000);
+
+        const [userState] = PublicKey.findProgramAddressSync(
+            [pool.publicKey.toBuffer(), alice.publicKey.toBuffer()],
+            program.programId
+        );
+
+        const set_guardian = (guardian: Keypair | null, signers: Keypair[]) =>
+            program.methods
+                .setGuardian(
+                    guardian?.publicKey ?? null,
+                    new BN(1_000_000),
+                    new BN(1)
+                )
+                .accounts({
+                    pool: pool.publicKey,
+                    userState,
+                    authority: alice.publicKey,
+                    guardian: signers[1]?.publicKey ?? null,
+                })
+                .signers(signers)
+                .rpc();
+
+        await set_guardian(bob, [alice]);
+
+        // Small withdrawals don't need the guardian
+        await leave_staking(alice, 500_000);
+
+        // ... until they add up past the threshold within a day
+        try {
+            await leave_staking(alice, 600_000);
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        try {
+            await leave_staking(alice, 2_000_000);
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+        await leave_staking(alice, 2_000_000, null, bob);
+
+        // Announced withdrawals go through once the delay has passed
+        await program.methods
+            .announceWithdrawal(new BN(3_000_000))
+            .accounts({
+                pool: pool.publicKey,
+                userState,
+                authority: alice.publicKey,
+            })
+            .signers([alice])
+            .rpc();
+        await new Promise((resolve) => setTimeout(resolve, 2000));
+        await leave_staking(alice, 3_000_000);
+
+        // Removing the guardian needs the guardian too
+        try {
+            await set_guardian(null, [alice]);
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+        await set_guardian(null, [alice, bob]);
+        await leave_staking(alice, 4_500_000);
+
+        expect((await get_user_staked(alice)).toNumber()).eq(0);
+    });
+    it("Session keys", async () => {
+        await initialize();
+        await initialize_user_state(alice);
+        await distribute_to(alice, 10_000_000);
+        await enter_staking(alice, 10_000_000);
+        await set_emission_schedule(authority.payer, 1_000, { constant: {} });
+
+        const [userState] = PublicKey.findProgramAddressSync(
+            [pool.publicKey.toBuffer(), alice.publicKey.toBuffer()],
+            program.programId
+        );
+        const session = Keypair.generate();
+
+        const set_session_key = (key: PublicKey | null, expirySlot: number) =>
+            program.methods
+                .setSessionKey(key, new BN(expirySlot))
+                .accounts({
+                    pool: pool.publicKey,
+                    userState,
+                    authority: alice.publicKey,
+                })
+                .signers([alice])
+                .rpc();
+
+        await set_session_key(
+            session.publicKey,
+            (await connection.getSlot()) + 1_000
+        );
+        await new Promise((resolve) => setTimeout(resolve, 2000));
+
+        // Rewards still land in alice's account
+        await claim_rewards(alice, session);
+        expect((await getMintBalanceOf(alice)).toNumber()).gt(0);
+
+        await set_session_key(null, 0);
+        await new Promise((resolve) => setTimeout(resolve, 1000));
+        try {
+            await claim_rewards(alice, session);
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+    });
+
+    it("Priority withdrawal lane", async () => {
+        await initialize();
+        for (const user of [alice, bob]) {
+            await initialize_user_state(user);
+            await distribute_to(user, 10_000_000);
+            await enter_staking(user, 10_000_000);
+        }
+
+        const vipMint = await createMint(
+            connection,
+            authority.payer,
+            authority.publicKey,
+            null,
+            0
+        );
+        const badge = await getOrCreateAssociatedTokenAccount(
+            connection,
+            bob,
+            vipMint,
+            bob.publicKey
+        );
+        await mintTo(
+            connection,
+            authority.payer,
+            vipMint,
+            badge.address,
+            authority.payer,
+            1
+        );
+
+        await program.methods
+            .setVipMint(vipMint)
+            .accounts({
+                pool: pool.publicKey,
+                authority: authority.publicKey,
+                configChange: null,
+            })
+            .signers([authority.payer])
+            .rpc();
+
+        const aliceRequest = await request_withdrawal(alice, 4_000_000, 0);
+
+        // Only badge holders get into the priority lane
+        try {
+            await request_withdrawal(alice, 1_000_000, 0, badge.address);
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+        const bobRequest = await request_withdrawal(
+            bob,
+            3_000_000,
+            0,
+            badge.address
+        );
+
+        // The priority lane is served first
+        try {
+            await fulfill_withdrawal(alice, aliceRequest);
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+        await fulfill_withdrawal(bob, bobRequest);
+        await fulfill_withdrawal(alice, aliceRequest);
+
+        expect((await getMintBalanceOf(alice)).toNumber()).eq(4_000_000);
+        expect((await getMintBalanceOf(bob)).toNumber()).eq(3_000_000);
+    });
+
+    it("Attested stake cap", async () => {
+        await initialize();
+        await initialize_user_state(alice);
+        await distribute_to(alice, 20_000_000);
+
+        const [config] = PublicKey.findProgramAddressSync(
+            [Buffer.from("config"), pool.publicKey.toBuffer()],
+            program.programId
+        );
+        const [userState] = PublicKey.findProgramAddressSync(
+            [pool.publicKey.toBuffer(), alice.publicKey.toBuffer()],
+            program.programId
+        );
+
+        const params = {
+            depositFeeBps: 0,
+            withdrawalFeeBps: 0,
+            earlyWithdrawalPenaltyBps: 0,
+            lockDuration: new BN(0),
+            maxStakePerUser: new BN(5_000_000),
+            maxStakedTotal: new BN(0),
+            verifiedMaxStakePerUser: new BN(20_000_000),
+            attestationProgram: program.programId,
+            attestationSchema: pool.publicKey,
+            gcInactivity: new BN(0),
+            gcCrankerBps: 0,
+        };
+        const initialize_config = (verifiedMaxStakePerUser: BN) =>
+            program.methods
+                .initializePoolConfig({ ...params, verifiedMaxStakePerUser })
+                .accounts({
+                    pool: pool.publicKey,
+                    config,
+                    authority: authority.publicKey,
+                })
+                .signers([authority.payer])
+                .rpc();
+
+        // The verified cap can only raise the default one
+        try {
+            await initialize_config(new BN(1_000_000));
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+        await initialize_config(params.verifiedMaxStakePerUser);
+
+        try {
+            await enter_staking(alice, 10_000_000, config);
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        // The attestation must belong to the staker
+        const [bobState] = PublicKey.findProgramAddressSync(
+            [pool.publicKey.toBuffer(), bob.publicKey.toBuffer()],
+            program.programId
+        );
+        await initialize_user_state(bob);
+        try {
+            await enter_staking(alice, 10_000_000, config, bobState);
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        // An account at the right address isn't enough: alice's user state
+        // doesn't hold an attestation of her.
+        try {
+            await enter_staking(alice, 10_000_000, config, userState);
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        await enter_staking(alice, 5_000_000, config, userState);
+        expect((await get_user_staked(alice)).toNumber()).eq(5_000_000);
+    });
+
+    it("Claim cooldown", async () => {
+        await initialize();
+        await initialize_user_state(alice);
+        await distribute_to(alice, 10_000_000);
+        await enter_staking(alice, 10_000_000);
+        await set_emission_schedule(authority.payer, 1_000, { constant: {} });
+
+        await program.methods
+            .setClaimCooldown(3)
+            .accounts({
+                pool: pool.publicKey,
+                authority: authority.publicKey,
+                configChange: null,
+            })
+            .signers([authority.payer])
+            .rpc();
+
+        await new Promise((resolve) => setTimeout(resolve, 1000));
+        await claim_rewards(alice);
+
+        await new Promise((resolve) => setTimeout(resolve, 1000));
+        try {
+            await claim_rewards(alice);
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        await new Promise((resolve) => setTimeout(resolve, 3000));
+        await claim_rewards(alice);
+    });
+
+    it("Full exit claims pending rewards", async () => {
+        await initialize();
+        await initialize_user_state(alice);
+        await distribute_to(alice, 10_000_000);
+        await enter_staking(alice, 10_000_000);
+        await set_emission_schedule(authority.payer, 1_000, { constant: {} });
+        await new Promise((resolve) => setTimeout(resolve, 2000));
+
+        // A partial exit leaves rewards pending
+        await leave_staking(alice, 4_000_000);
+        expect((await getMintBalanceOf(alice)).toNumber()).eq(4_000_000);
+
+        await leave_staking(alice, 6_000_000);
+        expect((await getMintBalanceOf(alice)).toNumber()).gt(10_000_000);
+
+        const [userState] = PublicKey.findProgramAddressSync(
+            [pool.publicKey.toBuffer(), alice.publicKey.toBuffer()],
+            program.programId
+        );
+        const state = await program.account.userState.fetch(userState);
+        expect(state.pendingRewards.toNumber()).eq(0);
+    });
+
+    it("Sweep reward dust", async () => {
+        await initialize();
+        await initialize_user_state(alice);
+
+        // 1_000 tokens a second don't divide evenly over this much stake
+        await distribute_to(alice, 3_000_000_000_001);
+        await enter_staking(alice, 3_000_000_000_001);
+        await program.methods
+            .setDustPolicy({ treasury: {} })
+            .accounts({
+                pool: pool.publicKey,
+                authority: authority.publicKey,
+                configChange: null,
+            })
+            .signers([authority.payer])
+            .rpc();
+        await set_emission_schedule(authority.payer, 1_000, { constant: {} });
+        await new Promise((resolve) => setTimeout(resolve, 3000));
+
+        const destination = await getOrCreateAssociatedTokenAccount(
+            connection,
+            bob,
+            mint.publicKey,
+            bob.publicKey,
+            false,
+            undefined,
+            undefined,
+            tokenProgram
+        );
+        await program.methods
+            .sweepDust()
+            .accounts({
+                pool: pool.publicKey,
+                programSigner,
+                mint: mint.publicKey,
+                destination: destination.address,
+                authority: authority.publicKey,
+                tokenProgram,
+            })
+            .signers([authority.payer])
+            .rpc();
+
+        const swept = await connection.getTokenAccountBalance(
+            destination.address
+        );
+        expect(Number(swept.value.amount)).gt(0);
+    });
+
+    it("Exact-output unstake", async () => {
+        await initialize();
+        await initialize_user_state(alice);
+        await distribute_to(alice, 10_000_000);
+
+        const [config] = PublicKey.findProgramAddressSync(
+            [Buffer.from("config"), pool.publicKey.toBuffer()],
+            program.programId
+        );
+        await program.methods
+            .initializePoolConfig({
+                depositFeeBps: 0,
+                withdrawalFeeBps: 30,
+                earlyWithdrawalPenaltyBps: 500,
+                lockDuration: new BN(3_600),
+                maxStakePerUser: new BN(0),
+                maxStakedTotal: new BN(0),
+                verifiedMaxStakePerUser: new BN(0),
+                attestationProgram: PublicKey.default,
+                attestationSchema: PublicKey.default,
+                gcInactivity: new BN(0),
+                gcCrankerBps: 0,
+            })
+            .accounts({
+                pool: pool.publicKey,
+                config,
+                authority: authority.publicKey,
+            })
+            .signers([authority.payer])
+            .rpc();
+
+        const userMintAcc = await enter_staking(alice, 10_000_000, config);
+        const [userState] = PublicKey.findProgramAddressSync(
+            [pool.publicKey.toBuffer(), alice.publicKey.toBuffer()],
+            program.programId
+        );
+
+        // Still locked: both the fee and the penalty come out of the stake
+        await program.methods
+            .leaveStakingExactOut(new BN(1_234_567))
+            .accounts({
+                pool: pool.publicKey,
+                mint: mint.publicKey,
+                programSigner,
+                userMintAcc,
+                userState,
+                vault: vault.address,
+                authority: alice.publicKey,
+                tokenProgram,
+                leaderboard: null,
+                userCheckpoints: null,
+                poolCheckpoints: null,
+                config,
+                guardian: null,
+                feeExemption: null,
+                auditLog: null,
+                userProfile: null,
+                ratePublisher: null,
+                instructions: null,
+                memoProgram: null,
+                walletState: null,
+                linkBoosted: null,
+            })
+            .signers([alice])
+            .rpc();
+
+        expect((await getMintBalanceOf(alice)).toNumber()).eq(1_234_567);
+        expect((await get_user_staked(alice)).toNumber()).lt(8_700_000);
+    });
+
+    it("Scheduled deposits", async () => {
+        await initialize();
+        await initialize_user_state(alice);
+        const funding = await distribute_to(alice, 10_000_000);
+
+        const [depositSchedule] = PublicKey.findProgramAddressSync(
+            [
+                Buffer.from("deposit_schedule"),
+                pool.publicKey.toBuffer(),
+                alice.publicKey.toBuffer(),
+            ],
+            program.programId
+        );
+        const [userState] = PublicKey.findProgramAddressSync(
+            [pool.publicKey.toBuffer(), alice.publicKey.toBuffer()],
+            program.programId
+        );
+
+        await program.methods
+            .createDepositSchedule(new BN(1_000_000), new BN(2))
+            .accounts({
+                pool: pool.publicKey,
+                depositSchedule,
+                funding,
+                authority: alice.publicKey,
+                payer: alice.publicKey,
+            })
+            .signers([alice])
+            .rpc();
+        await approve(
+            connection,
+            alice,
+            funding,
+            programSigner,
+            alice,
+            3_000_000,
+            [],
+            undefined,
+            tokenProgram
+        );
+
+        const execute = () =>
+            program.methods
+                .executeScheduledDeposit()
+                .accounts({
+                    pool: pool.publicKey,
+                    programSigner,
+                    mint: mint.publicKey,
+                    vault: vault.address,
+                    depositSchedule,
+                    funding,
+                    userState,
+                    tokenProgram,
+                    leaderboard: null,
+                    userCheckpoints: null,
+                    poolCheckpoints: null,
+                    config: null,
+                    attestation: null,
+                    feeExemption: null,
+                    auditLog: null,
+                    walletState: null,
+                })
+                .rpc();
+
+        await execute();
+        expect((await get_user_staked(alice)).toNumber()).eq(1_000_000);
+
+        try {
+            await execute();
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        await new Promise((resolve) => setTimeout(resolve, 3000));
+        await execute();
+        expect((await get_user_staked(alice)).toNumber()).eq(2_000_000);
+
+        await program.methods
+            .cancelDepositSchedule()
+            .accounts({
+                depositSchedule,
+                authority: alice.publicKey,
+            })
+            .signers([alice])
+            .rpc();
+        expect(await connection.getAccountInfo(depositSchedule)).eq(null);
+    });
+
+    it("Pull stake through a delegate approval", async () => {
+        await initialize();
+        await initialize_user_state(alice);
+        const funding = await distribute_to(alice, 10_000_000);
+
+        const [userState] = PublicKey.findProgramAddressSync(
+            [pool.publicKey.toBuffer(), alice.publicKey.toBuffer()],
+            program.programId
+        );
+        const pull_stake = (amount: number, signer = alice) =>
+            program.methods
+                .pullStake(new BN(amount))
+                .accounts({
+                    pool: pool.publicKey,
+                    programSigner,
+                    mint: mint.publicKey,
+                    vault: vault.address,
+                    funding,
+                    user: alice.publicKey,
+                    userState,
+                    authority: signer.publicKey,
+                    tokenProgram,
+                    leaderboard: null,
+                    userCheckpoints: null,
+                    poolCheckpoints: null,
+                    config: null,
+                    attestation: null,
+                    feeExemption: null,
+                    auditLog: null,
+                    walletState: null,
+                })
+                .signers([signer])
+                .rpc();
+
+        // Nothing approved yet
+        try {
+            await pull_stake(1_000_000);
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        await approve(
+            connection,
+            alice,
+            funding,
+            programSigner,
+            alice,
+            2_000_000,
+            [],
+            undefined,
+            tokenProgram
+        );
+
+        // Only alice or her registered puller can spend the approval
+        try {
+            await pull_stake(1_000_000, bob);
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        await program.methods
+            .setPuller(bob.publicKey)
+            .accounts({
+                pool: pool.publicKey,
+                userState,
+                authority: alice.publicKey,
+            })
+            .signers([alice])
+            .rpc();
+
+        await pull_stake(1_000_000, bob);
+        await pull_stake(500_000);
+        expect((await get_user_staked(alice)).toNumber()).eq(1_500_000);
+
+        try {
+            await pull_stake(1_000_000);
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        await program.methods
+            .setPuller(null)
+            .accounts({
+                pool: pool.publicKey,
+                userState,
+                authority: alice.publicKey,
+            })
+            .signers([alice])
+            .rpc();
+        expect(
+            (await program.account.userState.fetch(userState)).puller.equals(
+                PublicKey.default
+            )
+        ).eq(true);
+    });
+
+    it("Fee exemptions", async () => {
+        await initialize();
+        for (const user of [alice, bob]) {
+            await initialize_user_state(user);
+            await distribute_to(user, 10_000_000);
+        }
+
+        const [config] = PublicKey.findProgramAddressSync(
+            [Buffer.from("config"), pool.publicKey.toBuffer()],
+            program.programId
+        );
+        await program.methods
+            .initializePoolConfig({
+                depositFeeBps: 100,
+                withdrawalFeeBps: 100,
+                earlyWithdrawalPenaltyBps: 0,
+                lockDuration: new BN(0),
+                maxStakePerUser: new BN(0),
+                maxStakedTotal: new BN(0),
+                verifiedMaxStakePerUser: new BN(0),
+                attestationProgram: PublicKey.default,
+                attestationSchema: PublicKey.default,
+                gcInactivity: new BN(0),
+                gcCrankerBps: 0,
+            })
+            .accounts({
+                pool: pool.publicKey,
+                config,
+                authority: authority.publicKey,
+            })
+            .signers([authority.payer])
+            .rpc();
+
+        const [feeExemption] = PublicKey.findProgramAddressSync(
+            [
+                Buffer.from("fee_exemption"),
+                pool.publicKey.toBuffer(),
+                bob.publicKey.toBuffer(),
+            ],
+            program.programId
+        );
+        await program.methods
+            .addFeeExemption(bob.publicKey)
+            .accounts({
+                pool: pool.publicKey,
+                feeExemption,
+                authority: authority.publicKey,
+                configChange: null,
+            })
+            .signers([authority.payer])
+            .rpc();
+
+        await enter_staking(alice, 10_000_000, config);
+        expect((await get_user_staked(alice)).toNumber()).eq(9_900_000);
+
+        const [userState] = PublicKey.findProgramAddressSync(
+            [pool.publicKey.toBuffer(), bob.publicKey.toBuffer()],
+            program.programId
+        );
+        const userMintAcc = await getOrCreateAssociatedTokenAccount(
+            connection,
+            bob,
+            mint.publicKey,
+            bob.publicKey,
+            false,
+            undefined,
+            undefined,
+            tokenProgram
+        );
+        await program.methods
+            .enterStaking(new BN(10_000_000), null)
+            .accounts({
+                pool: pool.publicKey,
+                mint: mint.publicKey,
+                userMintAcc: userMintAcc.address,
+                userState,
+                vault: vault.address,
+                authority: bob.publicKey,
+                tokenProgram,
+                leaderboard: null,
+                userCheckpoints: null,
+                poolCheckpoints: null,
+                config,
+                attestation: null,
+                feeExemption,
+                auditLog: null,
+                userProfile: null,
+                ratePublisher: null,
+                instructions: null,
+                walletState: null,
+            })
+            .signers([bob])
+            .rpc();
+        expect((await get_user_staked(bob)).toNumber()).eq(10_000_000);
+
+        await program.methods
+            .removeFeeExemption()
+            .accounts({
+                pool: pool.publicKey,
+                feeExemption,
+                authority: authority.publicKey,
+                configChange: null,
+            })
+            .signers([authority.payer])
+            .rpc();
+        expect(await connection.getAccountInfo(feeExemption)).eq(null);
+    });
+
+    it("Pause with frozen rewards", async () => {
+        await initialize();
+        await initialize_user_state(alice);
+        await distribute_to(alice, 10_000_000);
+        await enter_staking(alice, 5_000_000);
+        await set_emission_schedule(authority.payer, 1_000, { constant: {} });
+
+        const admin = (method: any) =>
+            method
+                .accounts({
+                    pool: pool.publicKey,
+                    authority: authority.publicKey,
+                    configChange: null,
+                })
+                .signers([authority.payer])
+                .rpc();
+
+        await admin(program.methods.pause(true));
+        const before = (await program.account.pool.fetch(pool.publicKey))
+            .rewardPerToken;
+
+        try {
+            await enter_staking(alice, 5_000_000);
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+        try {
+            await claim_rewards(alice);
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        await new Promise((resolve) => setTimeout(resolve, 2000));
+        await admin(program.methods.unpause());
+
+        const poolAccount = await program.account.pool.fetch(pool.publicKey);
+        expect(poolAccount.rewardPerToken.eq(before)).eq(true);
+        expect(poolAccount.totalPausedDuration.toNumber()).gt(0);
+
+        await enter_staking(alice, 5_000_000);
+    });
+
+    it("Disable individual instructions", async () => {
+        await initialize();
+        await initialize_user_state(alice);
+
+        const DISTRIBUTE = 1 << 0;
+        const ENTER_STAKING = 1 << 1;
+        const set_disabled = (mask: number) =>
+            program.methods
+                .setDisabledInstructions(new BN(mask))
+                .accounts({
+                    pool: pool.publicKey,
+                    authority: authority.publicKey,
+                    configChange: null,
+                })
+                .signers([authority.payer])
+                .rpc();
+
+        await set_disabled(DISTRIBUTE);
+        try {
+            await distribute_to(alice, 10_000_000);
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        await set_disabled(ENTER_STAKING);
+        await distribute_to(alice, 10_000_000);
+        try {
+            await enter_staking(alice, 10_000_000);
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        await set_disabled(0);
+        await enter_staking(alice, 10_000_000);
+        expect((await get_user_staked(alice)).toNumber()).eq(10_000_000);
+    });
+
+    it("Audit log", async () => {
+        await initialize();
+        await initialize_user_state(alice);
+        const userMintAcc = await distribute_to(alice, 10_000_000);
+
+        const [auditLog] = PublicKey.findProgramAddressSync(
+            [Buffer.from("audit_log"), pool.publicKey.toBuffer()],
+            program.programId
+        );
+        await program.methods
+            .initializeAuditLog()
+            .accounts({
+                pool: pool.publicKey,
+                auditLog,
+                authority: authority.publicKey,
+            })
+            .signers([authority.payer])
+            .rpc();
+
+        // The log can't be skipped once it exists
+        try {
+            await enter_staking(alice, 10_000_000);
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        const [userState] = PublicKey.findProgramAddressSync(
+            [pool.publicKey.toBuffer(), alice.publicKey.toBuffer()],
+            program.programId
+        );
+        for (const amount of [4_000_000, 6_000_000]) {
+            await program.methods
+                .enterStaking(new BN(amount), null)
+                .accounts({
+                    pool: pool.publicKey,
+                    mint: mint.publicKey,
+                    userMintAcc,
+                    userState,
+                    vault: vault.address,
+                    authority: alice.publicKey,
+                    tokenProgram,
+                    leaderboard: null,
+                    userCheckpoints: null,
+                    poolCheckpoints: null,
+                    config: null,
+                    attestation: null,
+                    feeExemption: null,
+                    auditLog,
+                    userProfile: null,
+                    ratePublisher: null,
+                    instructions: null,
+                    walletState: null,
+                })
+                .signers([alice])
+                .rpc();
+        }
+
+        const log = await program.account.auditLog.fetch(auditLog);
+        expect(log.head.toNumber()).eq(2);
+        expect(log.entries[1].user.equals(alice.publicKey)).eq(true);
+        expect(log.entries[1].amount.toNumber()).eq(6_000_000);
+        expect(log.entries[1].op).eq(0);
+    });
+
+    it("Check reserves", async () => {
+        await initialize();
+        await initialize_user_state(alice);
+        await distribute_to(alice, 10_000_000);
+        await enter_staking(alice, 10_000_000);
+
+        await program.methods
+            .checkReserves()
+            .accounts({ pool: pool.publicKey, vault: vault.address })
+            .rpc();
+
+        const poolAccount = await program.account.pool.fetch(pool.publicKey);
+        expect(poolAccount.reserveAlarm).eq(0);
+    });
+    it("Reconcile", async () => {
+        await initialize();
+        await initialize_user_state(alice);
+        await distribute_to(alice, 10_000_000);
+        await enter_staking(alice, 10_000_000);
+        await leave_staking(alice, 4_000_000);
+
+        const reconcile = program.methods
+            .reconcile()
+            .accounts({ pool: pool.publicKey, vault: vault.address });
+        const reconciliation = await reconcile.view();
+        expect(reconciliation.reserves.toNumber()).eq(6_000_000);
+        expect(reconciliation.liabilities.toNumber()).eq(6_000_000);
+        expect(reconciliation.expectedProtocolFees.toNumber()).eq(0);
+        expect(reconciliation.queueConsistent).eq(true);
+        expect(reconciliation.balanced).eq(true);
+
+        await reconcile.rpc();
+        const poolAccount = await program.account.pool.fetch(pool.publicKey);
+        expect(poolAccount.incidentReason).eq(0);
+    });
+
+    it("Incident mode with emergency withdrawals", async () => {
+        await initialize();
+        await initialize_user_state(alice);
+        await distribute_to(alice, 10_000_000);
+        await enter_staking(alice, 10_000_000);
+
+        const watcher = Keypair.generate();
+        await program.methods
+            .setWatcher(watcher.publicKey)
+            .accounts({
+                pool: pool.publicKey,
+                authority: authority.publicKey,
+                configChange: null,
+            })
+            .signers([authority.payer])
+            .rpc();
+
+        await program.methods
+            .setIncident(7)
+            .accounts({
+                pool: pool.publicKey,
+                authority: watcher.publicKey,
+                configChange: null,
+            })
+            .signers([watcher])
+            .rpc();
+        expect(
+            (await program.account.pool.fetch(pool.publicKey)).incidentReason
+        ).eq(7);
+
+        try {
+            await enter_staking(alice, 1_000_000);
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+        try {
+            await leave_staking(alice, 1_000_000);
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        const userMintAcc = await getOrCreateAssociatedTokenAccount(
+            connection,
+            alice,
+            mint.publicKey,
+            alice.publicKey,
+            false,
+            undefined,
+            undefined,
+            tokenProgram
+        );
+        const [userState] = PublicKey.findProgramAddressSync(
+            [pool.publicKey.toBuffer(), alice.publicKey.toBuffer()],
+            program.programId
+        );
+        await program.methods
+            .emergencyWithdraw()
+            .accounts({
+                pool: pool.publicKey,
+                programSigner,
+                mint: mint.publicKey,
+                vault: vault.address,
+                userMintAcc: userMintAcc.address,
+                userState,
+                authority: alice.publicKey,
+                tokenProgram,
+                userCheckpoints: null,
+                poolCheckpoints: null,
+                auditLog: null,
+                guardian: null,
+                walletState: null,
+                linkBoosted: null,
+            })
+            .signers([alice])
+            .rpc();
+
+        const userStateAccount = await program.account.userState.fetch(
+            userState
+        );
+        expect(userStateAccount.stakedAmount.toNumber()).eq(0);
+
+        await program.methods
+            .resolveIncident()
+            .accounts({
+                pool: pool.publicKey,
+                authority: authority.publicKey,
+                configChange: null,
+            })
+            .signers([authority.payer])
+            .rpc();
+        expect(
+            (await program.account.pool.fetch(pool.publicKey)).incidentReason
+        ).eq(0);
+    });
+
+    it("Settle shortfall on a solvent pool", async () => {
+        await initialize();
+        await initialize_user_state(alice);
+        await distribute_to(alice, 10_000_000);
+        await enter_staking(alice, 10_000_000);
+
+        await program.methods
+            .settleShortfall()
+            .accounts({
+                pool: pool.publicKey,
+                vault: vault.address,
+                authority: authority.publicKey,
+            })
+            .signers([authority.payer])
+            .rpc();
+
+        const poolAccount = await program.account.pool.fetch(pool.publicKey);
+        expect(poolAccount.haircut.toNumber()).eq(0);
+
+        await leave_staking(alice, 10_000_000);
+    });
+
+    it("Freeze authority moves to the program signer", async () => {
+        await initialize();
+
+        await program.methods
+            .takeFreezeAuthority()
+            .accounts({
+                pool: pool.publicKey,
+                programSigner,
+                mint: mint.publicKey,
+                authority: authority.publicKey,
+                tokenProgram,
+            })
+            .signers([authority.payer])
+            .rpc();
+
+        let mintAccount = await getMint(
+            connection,
+            mint.publicKey,
+            undefined,
+            tokenProgram
+        );
+        expect(mintAccount.freezeAuthority.equals(programSigner)).eq(true);
+
+        const rotation = {
+            pool: pool.publicKey,
+            authority: authority.publicKey,
+            configChange: null,
+        };
+        await program.methods
+            .proposeAuthorityRotation(
+                { freezeAccount: {} },
+                authority.publicKey
+            )
+            .accounts(rotation)
+            .signers([authority.payer])
+            .rpc();
+
+        try {
+            await program.methods
+                .executeAuthorityRotation()
+                .accounts({
+                    pool: pool.publicKey,
+                    programSigner,
+                    mint: mint.publicKey,
+                    authority: authority.publicKey,
+                    tokenProgram,
+                    configChange: null,
+                })
+                .signers([authority.payer])
+                .rpc();
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        await program.methods
+            .cancelAuthorityRotation()
+            .accounts(rotation)
+            .signers([authority.payer])
+            .rpc();
+
+        const poolAccount = await program.account.pool.fetch(pool.publicKey);
+        expect(poolAccount.rotationKind).eq(0);
+    });
+
+    it("Initialize runs only once per pool", async () => {
+        await initialize();
+
+        const [poolMarker] = PublicKey.findProgramAddressSync(
+            [Buffer.from("pool_marker"), pool.publicKey.toBuffer()],
+            program.programId
+        );
+        const marker = await program.account.poolMarker.fetch(poolMarker);
+        expect(marker.pool.equals(pool.publicKey)).eq(true);
+
+        try {
+            await program.methods
+                .initialize()
+                .accounts({
+                    pool: pool.publicKey,
+                    mint: mint.publicKey,
+                    programSigner,
+                    vault: vault.address,
+                    authority: authority.publicKey,
+                    tokenProgram,
+                })
+                .signers([authority.payer])
+                .rpc();
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+    });
+
+    it("Blocked mints can't back new pools", async () => {
+        const [globalConfig] = PublicKey.findProgramAddressSync(
+            [Buffer.from("global_config")],
+            program.programId
+        );
+        if (!(await program.account.globalConfig.fetchNullable(globalConfig))) {
+            const [programData] = PublicKey.findProgramAddressSync(
+                [program.programId.toBuffer()],
+                new PublicKey("BPFLoaderUpgradeab1e11111111111111111111111")
+            );
+            await program.methods
+                .initializeGlobalConfig(false, false)
+                .accounts({
+                    program: program.programId,
+                    programData,
+                    authority: authority.publicKey,
+                })
+                .signers([authority.payer])
+                .rpc();
+        }
+
+        const [mintListing] = PublicKey.findProgramAddressSync(
+            [Buffer.from("mint_listing"), mint.publicKey.toBuffer()],
+            program.programId
+        );
+        const listing = await next_global_config_change();
+        await program.methods
+            .listMint(mint.publicKey, false)
+            .accounts({ admin: authority.publicKey, configChange: listing })
+            .signers([authority.payer])
+            .rpc();
+        const change = await program.account.configChange.fetch(listing);
+        expect(change.param).to.have.property("mintListing");
+        expect(change.pool.equals(globalConfig)).eq(true);
+
+        try {
+            await initialize();
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        await program.methods
+            .delistMint()
+            .accounts({
+                mintListing,
+                admin: authority.publicKey,
+                configChange: await next_global_config_change(),
+            })
+            .signers([authority.payer])
+            .rpc();
+        await initialize();
+    });
+
+    it("Redirect a share of emissions", async () => {
+        await initialize();
+        await initialize_user_state(alice);
+        await distribute_to(alice, 10_000_000);
+        await enter_staking(alice, 10_000_000);
+
+        const destination = await getOrCreateAssociatedTokenAccount(
+            connection,
+            bob,
+            mint.publicKey,
+            bob.publicKey,
+            false,
+            undefined,
+            undefined,
+            tokenProgram
+        );
+        await program.methods
+            .setRewardRedirect(2_500, destination.address)
+            .accounts({
+                pool: pool.publicKey,
+                authority: authority.publicKey,
+                configChange: null,
+            })
+            .signers([authority.payer])
+            .rpc();
+        await set_emission_schedule(authority.payer, 1_000, { constant: {} });
+        await new Promise((resolve) => setTimeout(resolve, 2000));
+
+        const distribute = () =>
+            program.methods.distributeRedirectedRewards().accounts({
+                pool: pool.publicKey,
+                programSigner,
+                mint: mint.publicKey,
+                destination: destination.address,
+                vault: vault.address,
+                tokenProgram,
+            });
+        await distribute().rpc();
+
+        const redirected = await connection.getTokenAccountBalance(
+            destination.address
+        );
+        expect(Number(redirected.value.amount)).gt(0);
+
+        // Switching to burning leaves nothing accrued for the old destination
+        await new Promise((resolve) => setTimeout(resolve, 2000));
+        const burn = () =>
+            program.methods.setRewardRedirect(2_500, null).accounts({
+                pool: pool.publicKey,
+                authority: authority.publicKey,
+                configChange: null,
+            });
+        try {
+            await burn().signers([authority.payer]).rpc();
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+        await burn()
+            .preInstructions([await distribute().instruction()])
+            .signers([authority.payer])
+            .rpc();
+
+        // Nothing funded to burn from yet
+        await new Promise((resolve) => setTimeout(resolve, 2000));
+        try {
+            await program.methods
+                .distributeRedirectedRewards()
+                .accounts({
+                    pool: pool.publicKey,
+                    programSigner,
+                    mint: mint.publicKey,
+                    destination: null,
+                    vault: vault.address,
+                    tokenProgram,
+                })
+                .rpc();
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+    });
+
+    it("Buyback through the swap adapter", async () => {
+        await initialize();
+
+        const adapter = Keypair.generate().publicKey;
+        await program.methods
+            .setSwapAdapter(adapter)
+            .accounts({
+                pool: pool.publicKey,
+                authority: authority.publicKey,
+                configChange: null,
+            })
+            .signers([authority.payer])
+            .rpc();
+
+        // A new adapter waits out the timelock
+        const poolAccount = await program.account.pool.fetch(pool.publicKey);
+        expect(poolAccount.swapAdapterTarget.equals(adapter)).eq(true);
+        expect(poolAccount.swapAdapterEta.toNumber()).gt(0);
+        expect(poolAccount.swapAdapter.equals(PublicKey.default)).eq(true);
+        try {
+            await program.methods
+                .applySwapAdapter()
+                .accounts({
+                    pool: pool.publicKey,
+                    authority: authority.publicKey,
+                    configChange: null,
+                })
+                .signers([authority.payer])
+                .rpc();
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        const revenueMint = await createMint(
+            connection,
+            authority.payer,
+            authority.publicKey,
+            null,
+            6,
+            undefined,
+            undefined,
+            TOKEN_PROGRAM_ID
+        );
+        const revenue = await getOrCreateAssociatedTokenAccount(
+            connection,
+            authority.payer,
+            revenueMint,
+            programSigner,
+            true,
+            undefined,
+            undefined,
+            TOKEN_PROGRAM_ID
+        );
+        await mintTo(
+            connection,
+            authority.payer,
+            revenueMint,
+            revenue.address,
+            authority.payer,
+            1_000_000,
+            [],
+            undefined,
+            TOKEN_PROGRAM_ID
+        );
+        const [swapAuthority] = PublicKey.findProgramAddressSync(
+            [Buffer.from("swap"), pool.publicKey.toBuffer()],
+            program.programId
+        );
+        const swapSource = await getOrCreateAssociatedTokenAccount(
+            connection,
+            authority.payer,
+            revenueMint,
+            swapAuthority,
+            true,
+            undefined,
+            undefined,
+            TOKEN_PROGRAM_ID
+        );
+
+        // Only the whitelisted adapter can swap the revenue
+        try {
+            await program.methods
+                .buybackAndDistribute(new BN(1_000_000), new BN(1))
+                .accounts({
+                    pool: pool.publicKey,
+                    programSigner,
+                    mint: mint.publicKey,
+                    vault: vault.address,
+                    revenue: revenue.address,
+                    revenueMint,
+                    swapAuthority,
+                    swapSource: swapSource.address,
+                    swapAdapter: SystemProgram.programId,
+                    authority: authority.publicKey,
+                    tokenProgram,
+                    revenueTokenProgram: TOKEN_PROGRAM_ID,
+                })
+                .signers([authority.payer])
+                .rpc();
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+    });
+
+    it("Zap stake needs the whitelisted adapter", async () => {
+        await initialize();
+        await initialize_user_state(alice);
+
+        const inputMint = await createMint(
+            connection,
+            authority.payer,
+            authority.publicKey,
+            null,
+            6,
+            undefined,
+            undefined,
+            TOKEN_PROGRAM_ID
+        );
+        const userInputAcc = await getOrCreateAssociatedTokenAccount(
+            connection,
+            alice,
+            inputMint,
+            alice.publicKey,
+            false,
+            undefined,
+            undefined,
+            TOKEN_PROGRAM_ID
+        );
+        const [userState] = PublicKey.findProgramAddressSync(
+            [pool.publicKey.toBuffer(), alice.publicKey.toBuffer()],
+            program.programId
+        );
+
+        try {
+            await program.methods
+                .zapStake(new BN(1_000_000), new BN(1))
+                .accounts({
+                    pool: pool.publicKey,
+                    mint: mint.publicKey,
+                    vault: vault.address,
+                    inputMint,
+                    userInputAcc: userInputAcc.address,
+                    userState,
+                    swapAdapter: SystemProgram.programId,
+                    authority: alice.publicKey,
+                    tokenProgram,
+                    inputTokenProgram: TOKEN_PROGRAM_ID,
+                    leaderboard: null,
+                    userCheckpoints: null,
+                    poolCheckpoints: null,
+                    config: null,
+                    attestation: null,
+                    feeExemption: null,
+                    auditLog: null,
+                    userProfile: null,
+                    ratePublisher: null,
+                    instructions: null,
+                    walletState: null,
+                })
+                .signers([alice])
+                .rpc();
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+    });
+
+    it("Clone a pool from a template", async () => {
+        await initialize();
+        await set_emission_schedule(authority.payer, 1_000, {
+            halving: { interval: new BN(3600) },
+        });
+        const template = pool.publicKey;
+
+        await create_pool_mint(tokenProgram);
+        await program.methods
+            .clonePool()
+            .accounts({
+                init: {
+                    pool: pool.publicKey,
+                    mint: mint.publicKey,
+                    programSigner,
+                    vault: vault.address,
+                    authority: authority.publicKey,
+                    tokenProgram,
+                },
+                templatePool: template,
+            })
+            .preInstructions([
+                await program.account.pool.createInstruction(pool),
+            ])
+            .signers([authority.payer, pool])
+            .rpc();
+
+        const poolAccount = await program.account.pool.fetch(pool.publicKey);
+        expect(poolAccount.mint.equals(mint.publicKey)).eq(true);
+        expect(poolAccount.emissionRate.toNumber()).eq(1_000);
+        expect(poolAccount.emissionPeriod.toNumber()).eq(3600);
+        // Nothing to emit until the clone's own reserve is funded.
+        expect(poolAccount.emissionStopTs.toNumber()).eq(
+            poolAccount.emissionStartTs.toNumber()
+        );
+    });
+
+    it("Record parameter changes in the config history", async () => {
+        await initialize();
+
+        const changeAddress = (id: number) =>
+            PublicKey.findProgramAddressSync(
+                [
+                    Buffer.from("config_change"),
+                    pool.publicKey.toBuffer(),
+                    new BN(id).toArrayLike(Buffer, "le", 8),
+                ],
+                program.programId
+            )[0];
+
+        await program.methods
+            .setConfigHistory(true)
+            .accounts({
+                pool: pool.publicKey,
+                authority: authority.publicKey,
+                configChange: changeAddress(0),
+            })
+            .signers([authority.payer])
+            .rpc();
+
+        try {
+            await program.methods
+                .setClaimCooldown(5)
+                .accounts({
+                    pool: pool.publicKey,
+                    authority: authority.publicKey,
+                    configChange: null,
+                })
+                .signers([authority.payer])
+                .rpc();
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        await program.methods
+            .setClaimCooldown(5)
+            .accounts({
+                pool: pool.publicKey,
+                authority: authority.publicKey,
+                configChange: changeAddress(1),
+            })
+            .signers([authority.payer])
+            .rpc();
+
+        const change = await program.account.configChange.fetch(
+            changeAddress(1)
+        );
+        expect(change.id.toNumber()).eq(1);
+        expect(change.param).to.have.property("claimCooldown");
+        expect(change.oldValue.slice(0, 4)).deep.eq([0, 0, 0, 0]);
+        expect(change.newValue.slice(0, 4)).deep.eq([5, 0, 0, 0]);
+        expect(change.authority.equals(authority.publicKey)).eq(true);
+
+        // Pausing is recorded like any other setter.
+        await program.methods
+            .pause(false)
+            .accounts({
+                pool: pool.publicKey,
+                authority: authority.publicKey,
+                configChange: changeAddress(2),
+            })
+            .signers([authority.payer])
+            .rpc();
+        const pause = await program.account.configChange.fetch(
+            changeAddress(2)
+        );
+        expect(pause.param).to.have.property("paused");
+        expect(pause.newValue.slice(0, 2)).deep.eq([1, 0]);
+
+        const poolAccount = await program.account.pool.fetch(pool.publicKey);
+        expect(poolAccount.configChangeCount.toNumber()).eq(3);
+    });
+
+    it("Register a reward stream", async () => {
+        await initialize();
+
+        const stream = Keypair.generate().publicKey;
+        await program.methods
+            .setRewardStream(SystemProgram.programId, stream)
+            .accounts({
+                pool: pool.publicKey,
+                authority: authority.publicKey,
+                configChange: null,
+            })
+            .signers([authority.payer])
+            .rpc();
+
+        let poolAccount = await program.account.pool.fetch(pool.publicKey);
+        expect(poolAccount.rewardStream.equals(stream)).eq(true);
+
+        // Streams pay a PDA of their own, not the program signer
+        const [streamRecipient] = PublicKey.findProgramAddressSync(
+            [Buffer.from("stream"), pool.publicKey.toBuffer()],
+            program.programId
+        );
+        const streamTokens = await getOrCreateAssociatedTokenAccount(
+            connection,
+            authority.payer,
+            mint.publicKey,
+            streamRecipient,
+            true,
+            undefined,
+            undefined,
+            tokenProgram
+        );
+
+        try {
+            await program.methods
+                .claimRewardStream()
+                .accounts({
+                    pool: pool.publicKey,
+                    streamRecipient,
+                    streamTokens: streamTokens.address,
+                    mint: mint.publicKey,
+                    vault: vault.address,
+                    stream: Keypair.generate().publicKey,
+                    streamProgram: SystemProgram.programId,
+                    tokenProgram,
+                })
+                .rpc();
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        await program.methods
+            .setRewardStream(null, stream)
+            .accounts({
+                pool: pool.publicKey,
+                authority: authority.publicKey,
+                configChange: null,
+            })
+            .signers([authority.payer])
+            .rpc();
+
+        poolAccount = await program.account.pool.fetch(pool.publicKey);
+        expect(poolAccount.rewardStreamProgram.equals(PublicKey.default)).eq(
+            true
+        );
+    });
+
+    it("Boost rewards by stake in a linked pool", async () => {
+        await initialize();
+        await initialize_user_state(alice);
+        await distribute_to(alice, 10_000_000);
+        await enter_staking(alice, 5_000_000);
+        const linkedPool = pool.publicKey;
+
+        await create_pool_mint(tokenProgram);
+        await initialize();
+        const userState = await initialize_user_state(alice);
+
+        const [boostLink] = PublicKey.findProgramAddressSync(
+            [Buffer.from("boost_link"), pool.publicKey.toBuffer()],
+            program.programId
+        );
+        await program.methods
+            .initializeBoostLink(10_000, new BN(10_000_000))
+            .accounts({
+                pool: pool.publicKey,
+                linkedPool,
+                authority: authority.publicKey,
+            })
+            .signers([authority.payer])
+            .rpc();
+
+        const [linkedUserState] = PublicKey.findProgramAddressSync(
+            [linkedPool.toBuffer(), alice.publicKey.toBuffer()],
+            program.programId
+        );
+        await program.methods
+            .syncLinkBoost()
+            .accounts({
+                pool: pool.publicKey,
+                boostLink,
+                user: alice.publicKey,
+                userState,
+                linkedPool,
+                linkedUserState,
+            })
+            .signers([alice])
+            .rpc();
+
+        const us = await program.account.userState.fetch(userState);
+        expect(us.linkBoostBps).eq(5_000);
+        const linked = await program.account.userState.fetch(linkedUserState);
+        expect(linked.linkBoosted.equals(userState)).eq(true);
+    });
+
+    it("Track positions in a user profile", async () => {
+        await initialize();
+        await initialize_user_state(alice);
+        await distribute_to(alice, 10_000_000);
+
+        const [userProfile] = PublicKey.findProgramAddressSync(
+            [Buffer.from("user_profile"), alice.publicKey.toBuffer()],
+            program.programId
+        );
+        await program.methods
+            .initializeUserProfile()
+            .accounts({ authority: alice.publicKey, payer: alice.publicKey })
+            .signers([alice])
+            .rpc();
+
+        await enter_staking(alice, 4_000_000, null, null, userProfile);
+        await enter_staking(alice, 1_000_000, null, null, userProfile);
+
+        const profile = await program.account.userProfile.fetch(userProfile);
+        expect(profile.owner.equals(alice.publicKey)).eq(true);
+        expect(profile.poolCount).eq(1);
+        expect(profile.pools[0].equals(pool.publicKey)).eq(true);
+        expect(profile.lifetimeStaked.toNumber()).eq(5_000_000);
+    });
+
+    it("Compressed positions need compressed mode", async () => {
+        await initialize();
+
+        try {
+            await program.methods
+                .openCompressedPosition()
+                .accounts({
+                    pool: pool.publicKey,
+                    programSigner,
+                    merkleTree: Keypair.generate().publicKey,
+                    authority: alice.publicKey,
+                })
+                .signers([alice])
+                .rpc();
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+    });
+
+    it("Publish light-client state at a fixed offset", async () => {
+        await initialize();
+        await initialize_user_state(alice);
+        await distribute_to(alice, 10_000_000);
+        await enter_staking(alice, 3_000_000);
+
+        await program.methods
+            .publishState()
+            .accounts({
+                pool: pool.publicKey,
+                vault: vault.address,
+                ratePublisher: null,
+            })
+            .rpc();
+
+        // LIGHT_CLIENT_STATE_OFFSET
+        const offset = 952;
+        const info = await connection.getAccountInfo(pool.publicKey);
+        const stakedTotal = info.data.readBigUInt64LE(offset);
+        expect(stakedTotal.toString()).eq("3000000");
+
+        const poolAccount = await program.account.pool.fetch(pool.publicKey);
+        expect(poolAccount.lightClientState.stakedTotal.toNumber()).eq(
+            3_000_000
+        );
+        expect(poolAccount.lightClientState.exchangeRate.toString()).eq(
+            "1000000000000"
+        );
+    });
+
+    it("Publish the exchange rate for integrations", async () => {
+        await initialize();
+
+        const [ratePublisher] = PublicKey.findProgramAddressSync(
+            [Buffer.from("rate_publisher"), pool.publicKey.toBuffer()],
+            program.programId
+        );
+        await program.methods
+            .initializeRatePublisher()
+            .accounts({ pool: pool.publicKey, payer: authority.publicKey })
+            .rpc();
+
+        await program.methods
+            .publishState()
+            .accounts({
+                pool: pool.publicKey,
+                vault: vault.address,
+                ratePublisher,
+            })
+            .rpc();
+
+        const publisher = await program.account.ratePublisher.fetch(
+            ratePublisher
+        );
+        expect(publisher.rate.toString()).eq("1000000000000");
+        expect(publisher.twap.toString()).eq("1000000000000");
+
+        // The rate grows as rewards accrue.
+        await initialize_user_state(alice);
+        await distribute_to(alice, 10_000_000);
+        await enter_staking(alice, 10_000_000);
+        await set_emission_schedule(authority.payer, 1_000, { constant: {} });
+        await new Promise((resolve) => setTimeout(resolve, 2000));
+
+        await program.methods
+            .publishState()
+            .accounts({
+                pool: pool.publicKey,
+                vault: vault.address,
+                ratePublisher,
+            })
+            .rpc();
+
+        const grown = await program.account.ratePublisher.fetch(ratePublisher);
+        expect(grown.rate.gt(publisher.rate)).eq(true);
+    });
+
+    it("Guard the exchange rate against sudden moves", async () => {
+        await initialize();
+
+        try {
+            await program.methods
+                .setRateGuard(10_001)
+                .accounts({
+                    pool: pool.publicKey,
+                    authority: authority.publicKey,
+                    configChange: null,
+                })
+                .signers([authority.payer])
+                .rpc();
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        await program.methods
+            .setRateGuard(100)
+            .accounts({
+                pool: pool.publicKey,
+                authority: authority.publicKey,
+                configChange: null,
+            })
+            .signers([authority.payer])
+            .rpc();
+
+        await initialize_user_state(alice);
+        await distribute_to(alice, 10_000_000);
+        await enter_staking(alice, 1_000_000);
+
+        const poolAccount = await program.account.pool.fetch(pool.publicKey);
+        expect(poolAccount.maxRateChangeBps).eq(100);
+        expect(poolAccount.lightClientState.exchangeRate.toString()).eq(
+            "1000000000000"
+        );
+    });
+
+    it("Require memos in institutional mode", async () => {
+        await initialize();
+        await initialize_user_state(alice);
+        await distribute_to(alice, 10_000_000);
+
+        await program.methods
+            .setMemoRequired(true)
+            .accounts({
+                pool: pool.publicKey,
+                authority: authority.publicKey,
+                configChange: null,
+            })
+            .signers([authority.payer])
+            .rpc();
+
+        try {
+            await enter_staking(alice, 1_000_000);
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+    });
+
+    it("Unwind users whose attestation was revoked", async () => {
+        await initialize();
+        await initialize_user_state(alice);
+        await distribute_to(alice, 10_000_000);
+
+        const [config] = PublicKey.findProgramAddressSync(
+            [Buffer.from("config"), pool.publicKey.toBuffer()],
+            program.programId
+        );
+        const attestationProgram = Keypair.generate().publicKey;
+        const attestationSchema = Keypair.generate().publicKey;
+        await program.methods
+            .initializePoolConfig({
+                depositFeeBps: 0,
+                withdrawalFeeBps: 0,
+                earlyWithdrawalPenaltyBps: 0,
+                lockDuration: new BN(0),
+                maxStakePerUser: new BN(0),
+                maxStakedTotal: new BN(0),
+                verifiedMaxStakePerUser: new BN(0),
+                attestationProgram,
+                attestationSchema,
+                gcInactivity: new BN(0),
+                gcCrankerBps: 0,
+            })
+            .accounts({
+                pool: pool.publicKey,
+                config,
+                authority: authority.publicKey,
+            })
+            .signers([authority.payer])
+            .rpc();
+        await enter_staking(alice, 10_000_000, config);
+
+        await program.methods
+            .setCompliance(authority.publicKey)
+            .accounts({
+                pool: pool.publicKey,
+                authority: authority.publicKey,
+                configChange: null,
+            })
+            .signers([authority.payer])
+            .rpc();
+
+        const [userState] = PublicKey.findProgramAddressSync(
+            [pool.publicKey.toBuffer(), alice.publicKey.toBuffer()],
+            program.programId
+        );
+        const [withdrawalRequest] = PublicKey.findProgramAddressSync(
+            [
+                Buffer.from("withdrawal"),
+                pool.publicKey.toBuffer(),
+                new BN(0).toArrayLike(Buffer, "le", 8),
+            ],
+            program.programId
+        );
+        // Never issued, so as good as revoked
+        const [attestation] = PublicKey.findProgramAddressSync(
+            [attestationSchema.toBuffer(), alice.publicKey.toBuffer()],
+            attestationProgram
+        );
+        await program.methods
+            .forceUnwind(alice.publicKey)
+            .accounts({
+                pool: pool.publicKey,
+                userState,
+                withdrawalRequest,
+                config,
+                attestation,
+                compliance: authority.publicKey,
+                userCheckpoints: null,
+                poolCheckpoints: null,
+                auditLog: null,
+                walletState: null,
+                linkBoosted: null,
+            })
+            .signers([authority.payer])
+            .rpc();
+
+        const us = await program.account.userState.fetch(userState);
+        expect(us.unwound).eq(true);
+        expect(us.stakedAmount.toNumber()).eq(0);
+        const request = await program.account.withdrawalRequest.fetch(
+            withdrawalRequest
+        );
+        expect(request.amount.toNumber()).eq(10_000_000);
+
+        try {
+            await enter_staking(alice, 1_000_000, config);
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+    });
+
+    it("Claw back a team grant", async () => {
+        await initialize();
+        const treasury = await distribute_to(authority.payer, 1_000_000);
+
+        const [grant] = PublicKey.findProgramAddressSync(
+            [
+                Buffer.from("grant"),
+                pool.publicKey.toBuffer(),
+                alice.publicKey.toBuffer(),
+            ],
+            program.programId
+        );
+        await program.methods
+            .stakeGrant(alice.publicKey, new BN(1_000_000))
+            .accounts({
+                pool: pool.publicKey,
+                mint: mint.publicKey,
+                vault: vault.address,
+                authorityMintAcc: treasury,
+                treasury,
+                grant,
+                authority: authority.publicKey,
+                tokenProgram,
+            })
+            .signers([authority.payer])
+            .rpc();
+
+        const grantAccount = await program.account.grantPosition.fetch(grant);
+        expect(grantAccount.stakedAmount.toNumber()).eq(1_000_000);
+
+        await program.methods
+            .clawbackGrant()
+            .accounts({
+                pool: pool.publicKey,
+                programSigner,
+                mint: mint.publicKey,
+                vault: vault.address,
+                grant,
+                treasury,
+                authority: authority.publicKey,
+                tokenProgram,
+            })
+            .signers([authority.payer])
+            .rpc();
+
+        const balance = await connection.getTokenAccountBalance(treasury);
+        expect(Number(balance.value.amount)).gte(1_000_000);
+        expect(await connection.getAccountInfo(grant)).eq(null);
+    });
+
+    it("Claim part of the rewards", async () => {
+        await initialize();
+        await initialize_user_state(alice);
+        await distribute_to(alice, 10_000_000);
+
+        await enter_staking(alice, 10_000_000);
+        await set_emission_schedule(authority.payer, 1_000, { constant: {} });
+
+        await new Promise((resolve) => setTimeout(resolve, 3000));
+
+        try {
+            await claim_rewards(alice, null, 1_000_000_000);
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        await claim_rewards(alice, null, 1_000);
+        expect((await getMintBalanceOf(alice)).toNumber()).eq(1_000);
+
+        const [userState] = PublicKey.findProgramAddressSync(
+            [pool.publicKey.toBuffer(), alice.publicKey.toBuffer()],
+            program.programId
+        );
+        const us = await program.account.userState.fetch(userState);
+        expect(us.pendingRewards.toNumber()).gt(0);
+    });
+
+    it("Simulate actions without changing state", async () => {
+        await initialize();
+        await initialize_user_state(alice);
+        await distribute_to(alice, 10_000_000);
+        await enter_staking(alice, 4_000_000);
+
+        const [userState] = PublicKey.findProgramAddressSync(
+            [pool.publicKey.toBuffer(), alice.publicKey.toBuffer()],
+            program.programId
+        );
+        const simulate = async (action: object, amount: number) =>
+            await program.methods
+                .simulateAction(action as any, new BN(amount))
+                .accounts({
+                    pool: pool.publicKey,
+                    mint: mint.publicKey,
+                    user: alice.publicKey,
+                    userState,
+                    config: null,
+                    attestation: null,
+                    feeExemption: null,
+                    walletState: null,
+                })
+                .view();
+
+        const stake = await simulate({ stake: {} }, 1_000_000);
+        expect(stake.received.toNumber()).eq(1_000_000);
+        expect(stake.stakedAmount.toNumber()).eq(5_000_000);
+        expect(stake.stakedTotal.toNumber()).eq(5_000_000);
+
+        const unstake = await simulate({ unstake: {} }, 4_000_000);
+        expect(unstake.fee.toNumber()).eq(0);
+        expect(unstake.received.toNumber()).eq(4_000_000);
+        expect(unstake.stakedAmount.toNumber()).eq(0);
+
+        expect((await get_user_staked(alice)).toNumber()).eq(4_000_000);
+    });
+
+    it("Compute units and account sizes stay within budget", async () => {
+        // Baselines recorded from a run of this test, with BENCH_RECORD=1
+        // to record them again. An instruction fails once it uses more
+        // than its baseline plus the tolerance, or has no baseline at all.
+        // Each run's measurements go to the temp directory.
+        const benchmarks = "tests/benchmarks.json";
+        const budget = JSON.parse(readFileSync(benchmarks, "utf8"));
+        const computeUnits: Record<string, number> = {};
+
+        computeUnits.initialize = await compute_units(initialize);
+        await distribute_to(alice, 10_000_000);
+        computeUnits.initializeUserState = await compute_units(() =>
+            initialize_user_state(alice)
+        );
+        await set_emission_schedule(authority.payer, 1_000, { constant: {} });
+
+        computeUnits.enterStaking = await compute_units(() =>
+            enter_staking(alice, 10_000_000)
+        );
+        await new Promise((resolve) => setTimeout(resolve, 2000));
+        computeUnits.claimRewards = await compute_units(() =>
+            claim_rewards(alice)
+        );
+        computeUnits.leaveStaking = await compute_units(() =>
+            leave_staking(alice, 10_000_000)
+        );
+
+        const [userState] = PublicKey.findProgramAddressSync(
+            [pool.publicKey.toBuffer(), alice.publicKey.toBuffer()],
+            program.programId
+        );
+        const accountSizes = {
+            pool: (await connection.getAccountInfo(pool.publicKey)).data
+                .length,
+            userState: (await connection.getAccountInfo(userState)).data
+                .length,
+        };
+
+        writeFileSync(
+            join(tmpdir(), "staking-pool-bench.json"),
+            JSON.stringify({ computeUnits, accountSizes }, null, 4) + "\n"
+        );
+        if (process.env.BENCH_RECORD) {
+            budget.computeUnits = computeUnits;
+            writeFileSync(benchmarks, JSON.stringify(budget, null, 4) + "\n");
+        }
+
+        for (const [name, used] of Object.entries(computeUnits)) {
+            const baseline = budget.computeUnits[name];
+            expect(baseline, `${name} baseline`).to.be.a("number");
+            expect(used, name).lte(
+                Math.floor((baseline * (100 + budget.tolerancePercent)) / 100)
+            );
+        }
+        for (const [name, size] of Object.entries(accountSizes)) {
+            expect(size, name).lte(budget.accountSizes[name]);
+        }
+    });
+
+    it("Only the upgrade authority creates pools when permissioned", async () => {
+        const [globalConfig] = PublicKey.findProgramAddressSync(
+            [Buffer.from("global_config")],
+            program.programId
+        );
+        const [programData] = PublicKey.findProgramAddressSync(
+            [program.programId.toBuffer()],
+            new PublicKey("BPFLoaderUpgradeab1e11111111111111111111111")
+        );
+        if (!(await program.account.globalConfig.fetchNullable(globalConfig))) {
+            await program.methods
+                .initializeGlobalConfig(false, false)
+                .accounts({
+                    program: program.programId,
+                    programData,
+                    authority: authority.publicKey,
+                })
+                .signers([authority.payer])
+                .rpc();
+        }
+
+        await program.methods
+            .syncUpgradeAuthority()
+            .accounts({
+                program: program.programId,
+                programData,
+                payer: authority.publicKey,
+                configChange: await next_global_config_change(),
+            })
+            .rpc();
+        const config = await program.account.globalConfig.fetch(globalConfig);
+        expect(config.upgradeAuthority.toString()).eq(
+            authority.publicKey.toString()
+        );
+
+        const setPermissioned = async (permissioned: boolean) =>
+            await program.methods
+                .setGlobalConfig(authority.publicKey, false, permissioned)
+                .accounts({
+                    admin: authority.publicKey,
+                    configChange: await next_global_config_change(),
+                })
+                .signers([authority.payer])
+                .rpc();
+        await setPermissioned(true);
+
+        try {
+            // A pool of alice's would claim to be official.
+            const roguePool = Keypair.generate();
+            const rogueMint = Keypair.generate();
+            const [rogueSigner] = PublicKey.findProgramAddressSync(
+                [rogueMint.publicKey.toBuffer(), roguePool.publicKey.toBuffer()],
+                program.programId
+            );
+            await createMint(
+                connection,
+                alice,
+                rogueSigner,
+                rogueSigner,
+                6,
+                rogueMint,
+                undefined,
+                tokenProgram
+            );
+            const rogueVault = await getOrCreateAssociatedTokenAccount(
+                connection,
+                alice,
+                rogueMint.publicKey,
+                rogueSigner,
+                true,
+                undefined,
+                undefined,
+                tokenProgram
+            );
+
+            try {
+                await program.methods
+                    .initialize()
+                    .accounts({
+                        pool: roguePool.publicKey,
+                        mint: rogueMint.publicKey,
+                        programSigner: rogueSigner,
+                        vault: rogueVault.address,
+                        authority: alice.publicKey,
+                        tokenProgram,
+                    })
+                    .preInstructions([
+                        await program.account.pool.createInstruction(roguePool),
+                    ])
+                    .signers([alice, roguePool])
+                    .rpc();
+                assert(false);
+            } catch (error) {
+                // console.log(error);
+            }
+
+            await initialize();
+        } finally {
+            await setPermissioned(false);
+        }
+    });
+
+    it("Initialize creates the vault", async () => {
+        pool = Keypair.generate();
+        mint = Keypair.generate();
+        [programSigner] = PublicKey.findProgramAddressSync(
+            [mint.publicKey.toBuffer(), pool.publicKey.toBuffer()],
+            program.programId
+        );
+        await createMint(
+            connection,
+            authority.payer,
+            programSigner,
+            authority.publicKey,
+            6,
+            mint,
+            undefined,
+            tokenProgram
+        );
+
+        const init = async (vault: PublicKey) =>
+            await program.methods
+                .initialize()
+                .accounts({
+                    pool: pool.publicKey,
+                    mint: mint.publicKey,
+                    programSigner,
+                    vault,
+                    authority: authority.publicKey,
+                    tokenProgram,
+                })
+                .preInstructions([
+                    await program.account.pool.createInstruction(pool),
+                ])
+                .signers([authority.payer, pool])
+                .rpc();
+
+        // Any other account of the program signer is a misconfigured vault.
+        const other = await createAccount(
+            connection,
+            authority.payer,
+            mint.publicKey,
+            programSigner,
+            Keypair.generate(),
+            undefined,
+            tokenProgram
+        );
+        try {
+            await init(other);
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        const vaultAddress = getAssociatedTokenAddressSync(
+            mint.publicKey,
+            programSigner,
+            true,
+            tokenProgram
+        );
+        await init(vaultAddress);
+
+        const created = await getAccount(
+            connection,
+            vaultAddress,
+            undefined,
+            tokenProgram
+        );
+        expect(created.owner.toString()).eq(programSigner.toString());
+        const poolContent = await program.account.pool.fetch(pool.publicKey);
+        expect(poolContent.vault.toString()).eq(vaultAddress.toString());
+    });
+
+    it("Rotate the vault after the timelock", async () => {
+        await initialize();
+        await initialize_user_state(alice);
+        await distribute_to(alice, 10_000_000);
+        await enter_staking(alice, 10_000_000);
+
+        const newVault = await createAccount(
+            connection,
+            authority.payer,
+            mint.publicKey,
+            programSigner,
+            Keypair.generate(),
+            undefined,
+            tokenProgram
+        );
+        await program.methods
+            .proposeVaultRotation()
+            .accounts({
+                pool: pool.publicKey,
+                newVault,
+                authority: authority.publicKey,
+                configChange: null,
+            })
+            .signers([authority.payer])
+            .rpc();
+
+        let poolContent = await program.account.pool.fetch(pool.publicKey);
+        expect(poolContent.vaultRotationTarget.toString()).eq(
+            newVault.toString()
+        );
+
+        try {
+            await program.methods
+                .rotateVault()
+                .accounts({
+                    pool: pool.publicKey,
+                    programSigner,
+                    mint: mint.publicKey,
+                    vault: vault.address,
+                    newVault,
+                    authority: authority.publicKey,
+                    tokenProgram,
+                    configChange: null,
+                })
+                .signers([authority.payer])
+                .rpc();
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        await program.methods
+            .cancelVaultRotation()
+            .accounts({
+                pool: pool.publicKey,
+                authority: authority.publicKey,
+                configChange: null,
+            })
+            .signers([authority.payer])
+            .rpc();
+
+        poolContent = await program.account.pool.fetch(pool.publicKey);
+        expect(poolContent.vault.toString()).eq(vault.address.toString());
+        expect(poolContent.vaultRotationTarget.toString()).eq(
+            PublicKey.default.toString()
+        );
+    });
+    it("IDL round-trips events, errors and view returns", async () => {
+        const idl = program.idl as anchor.Idl;
+        const coder = new anchor.BorshCoder(idl);
+
+        // Every event decodes from a log back to the bytes it came from.
+        assert.isNotEmpty(idl.events);
+        for (const event of idl.events) {
+            const data = sample_value({ defined: { name: event.name } });
+            const encoded = coder.types.encode(event.name, data);
+            const log = Buffer.concat([
+                Buffer.from(event.discriminator),
+                encoded,
+            ]).toString("base64");
+
+            const decoded = coder.events.decode(log);
+            expect(decoded?.name, event.name).eq(event.name);
+            expect(
+                coder.types.encode(event.name, decoded.data).equals(encoded),
+                event.name
+            ).eq(true);
+        }
+
+        // View return types are described, and decode to what was encoded.
+        const views = idl.instructions.filter((ix) => ix.returns);
+        expect(views.map((ix) => ix.name)).to.include.members([
+            "getPoolStats",
+            "simulateAction",
+            "getVoterWeight",
+            "checkReserves",
+            "reconcile",
+        ]);
+        for (const { name, returns } of views) {
+            if (typeof returns === "string") continue;
+            const type = (returns as { defined: { name: string } }).defined
+                .name;
+            const encoded = coder.types.encode(type, sample_value(returns));
+            const decoded = coder.types.decode(type, encoded);
+            expect(coder.types.encode(type, decoded).equals(encoded), name).eq(
+                true
+            );
+        }
+
+        // Errors keep the codes they're declared with.
+        assert.isNotEmpty(idl.errors);
+        idl.errors.forEach((error, index) => {
+            expect(error.code, error.name).eq(6000 + index);
+            assert.ok(error.msg, error.name);
+        });
+    });
+    it("SOL rewards", async () => {
+        await initialize();
+        const userState = await initialize_user_state(alice);
+        await distribute_to(alice, 10_000_000);
+        await enter_staking(alice, 10_000_000);
+
+        const [solRewardVault] = PublicKey.findProgramAddressSync(
+            [Buffer.from("sol_rewards"), pool.publicKey.toBuffer()],
+            program.programId
+        );
+        const now = Math.floor(Date.now() / 1000);
+        await program.methods
+            .setSolRewards(new BN(1_000), new BN(now + 3600))
+            .accounts({
+                pool: pool.publicKey,
+                authority: authority.publicKey,
+                campaignManager: null,
+                configChange: null,
+            })
+            .signers([authority.payer])
+            .rpc();
+        await program.methods
+            .fundSolRewards(new BN(anchor.web3.LAMPORTS_PER_SOL))
+            .accounts({
+                pool: pool.publicKey,
+                solRewardVault,
+                funder: authority.publicKey,
+            })
+            .signers([authority.payer])
+            .rpc();
+
+        await new Promise((resolve) => setTimeout(resolve, 3000));
+
+        // The provider pays the fee, so alice's balance only moves by the
+        // rewards.
+        const aliceBefore = await connection.getBalance(alice.publicKey);
+        const vaultBefore = await connection.getBalance(solRewardVault);
+        await program.methods
+            .claimSolRewards()
+            .accounts({
+                pool: pool.publicKey,
+                solRewardVault,
+                user: alice.publicKey,
+                userState,
+                authority: alice.publicKey,
+                auditLog: null,
+            })
+            .signers([alice])
+            .rpc();
+
+        const claimed =
+            (await connection.getBalance(alice.publicKey)) - aliceBefore;
+        expect(claimed).gte(1_000);
+        expect(await connection.getBalance(solRewardVault)).eq(
+            vaultBefore - claimed
+        );
+
+        const userContent = await program.account.userState.fetch(userState);
+        expect(userContent.solPendingRewards.toNumber()).eq(0);
+    });
+    it("Anyone extends the SOL campaign", async () => {
+        await initialize();
+        await initialize_user_state(alice);
+        await distribute_to(alice, 10_000_000);
+        await enter_staking(alice, 10_000_000);
+
+        const [solRewardVault] = PublicKey.findProgramAddressSync(
+            [Buffer.from("sol_rewards"), pool.publicKey.toBuffer()],
+            program.programId
+        );
+        const extend_sol_rewards = (lamports: number) =>
+            program.methods
+                .extendSolRewards(new BN(lamports))
+                .accounts({
+                    pool: pool.publicKey,
+                    solRewardVault,
+                    funder: bob.publicKey,
+                })
+                .signers([bob])
+                .rpc();
+
+        // Nothing running to extend yet.
+        try {
+            await extend_sol_rewards(100_000_000);
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        const end = Math.floor(Date.now() / 1000) + 3600;
+        await program.methods
+            .setSolRewards(new BN(1_000), new BN(end))
+            .accounts({
+                pool: pool.publicKey,
+                authority: authority.publicKey,
+                campaignManager: null,
+                configChange: null,
+            })
+            .signers([authority.payer])
+            .rpc();
+
+        // An hour's worth is too little to move the end.
+        try {
+            await extend_sol_rewards(3_600_000);
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        // A day and a half at the current rate, plus change.
+        await extend_sol_rewards(129_600_500);
+        const poolAccount = await program.account.pool.fetch(pool.publicKey);
+        expect(poolAccount.solEmissionRate.toNumber()).eq(1_000);
+        expect(poolAccount.solEmissionEndTs.toNumber()).eq(end + 129_600);
+        expect(await connection.getBalance(solRewardVault)).eq(129_600_500);
+    });
+    it("Claim rewards converted through the claim adapter", async () => {
+        await initialize();
+        const userState = await initialize_user_state(alice);
+        const userMintAcc = await distribute_to(alice, 10_000_000);
+        await enter_staking(alice, 10_000_000);
+
+        const usdc = await createMint(
+            connection,
+            authority.payer,
+            authority.publicKey,
+            null,
+            6,
+            undefined,
+            undefined,
+            TOKEN_PROGRAM_ID
+        );
+        const adapter = Keypair.generate().publicKey;
+
+        // Converting into the reward token itself is pointless.
+        try {
+            await program.methods
+                .setClaimAdapter(adapter, mint.publicKey)
+                .accounts({
+                    pool: pool.publicKey,
+                    authority: authority.publicKey,
+                    configChange: null,
+                })
+                .signers([authority.payer])
+                .rpc();
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        await program.methods
+            .setClaimAdapter(adapter, usdc)
+            .accounts({
+                pool: pool.publicKey,
+                authority: authority.publicKey,
+                configChange: null,
+            })
+            .signers([authority.payer])
+            .rpc();
+
+        // A new adapter waits out the timelock
+        const poolAccount = await program.account.pool.fetch(pool.publicKey);
+        expect(poolAccount.claimAdapterTarget.equals(adapter)).eq(true);
+        expect(poolAccount.claimOutputMintTarget.equals(usdc)).eq(true);
+        expect(poolAccount.claimAdapter.equals(PublicKey.default)).eq(true);
+        try {
+            await program.methods
+                .applyClaimAdapter()
+                .accounts({
+                    pool: pool.publicKey,
+                    authority: authority.publicKey,
+                    configChange: null,
+                })
+                .signers([authority.payer])
+                .rpc();
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        const userUsdc = await getOrCreateAssociatedTokenAccount(
+            connection,
+            alice,
+            usdc,
+            alice.publicKey,
+            false,
+            undefined,
+            undefined,
+            TOKEN_PROGRAM_ID
+        );
+
+        const [swapAuthority] = PublicKey.findProgramAddressSync(
+            [Buffer.from("swap"), pool.publicKey.toBuffer()],
+            program.programId
+        );
+        const swapSource = await getOrCreateAssociatedTokenAccount(
+            connection,
+            alice,
+            mint.publicKey,
+            swapAuthority,
+            true,
+            undefined,
+            undefined,
+            tokenProgram
+        );
+
+        // Only the whitelisted adapter can convert the rewards
+        try {
+            await program.methods
+                .claimRewardsAndSwap(null, new BN(1))
+                .accounts({
+                    claim: {
+                        pool: pool.publicKey,
+                        programSigner,
+                        mint: mint.publicKey,
+                        vault: vault.address,
+                        userMintAcc,
+                        user: alice.publicKey,
+                        userState,
+                        authority: alice.publicKey,
+                        tokenProgram,
+                        leaderboard: null,
+                        walletState: null,
+                        auditLog: null,
+                    },
+                    swapAuthority,
+                    swapSource: swapSource.address,
+                    outputMint: usdc,
+                    userOutputAcc: userUsdc.address,
+                    claimAdapter: SystemProgram.programId,
+                    outputTokenProgram: TOKEN_PROGRAM_ID,
+                })
+                .signers([alice])
+                .rpc();
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+    });
+    it("Garbage collect abandoned user states", async () => {
+        await initialize();
+        const userState = await initialize_user_state(alice);
+        await distribute_to(alice, 10_000_000);
+
+        const [config] = PublicKey.findProgramAddressSync(
+            [Buffer.from("config"), pool.publicKey.toBuffer()],
+            program.programId
+        );
+        await program.methods
+            .initializePoolConfig({
+                depositFeeBps: 0,
+                withdrawalFeeBps: 0,
+                earlyWithdrawalPenaltyBps: 0,
+                lockDuration: new BN(0),
+                maxStakePerUser: new BN(0),
+                maxStakedTotal: new BN(0),
+                verifiedMaxStakePerUser: new BN(0),
+                attestationProgram: PublicKey.default,
+                attestationSchema: PublicKey.default,
+                gcInactivity: new BN(2),
+                gcCrankerBps: 2_000,
+            })
+            .accounts({
+                pool: pool.publicKey,
+                config,
+                authority: authority.publicKey,
+            })
+            .signers([authority.payer])
+            .rpc();
+
+        const gc = () =>
+            program.methods
+                .gcUserState()
+                .accounts({
+                    pool: pool.publicKey,
+                    config,
+                    user: alice.publicKey,
+                    userState,
+                    payer: alice.publicKey,
+                    cranker: bob.publicKey,
+                })
+                .signers([bob])
+                .rpc();
+
+        await enter_staking(alice, 10_000_000, config);
+        await new Promise((resolve) => setTimeout(resolve, 3000));
+
+        // Staked positions are never collected
+        try {
+            await gc();
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        await leave_staking(alice, 10_000_000, config);
+        await new Promise((resolve) => setTimeout(resolve, 3000));
+
+        const rent = (await connection.getAccountInfo(userState)).lamports;
+        const aliceBefore = await connection.getBalance(alice.publicKey);
+        const bobBefore = await connection.getBalance(bob.publicKey);
+        await gc();
+
+        expect(await connection.getAccountInfo(userState)).eq(null);
+        const crankerShare = Math.floor((rent * 2_000) / 10_000);
+        expect((await connection.getBalance(bob.publicKey)) - bobBefore).eq(
+            crankerShare
+        );
+        expect(
+            (await connection.getBalance(alice.publicKey)) - aliceBefore
+        ).eq(rent - crankerShare);
+    });
+    it("Sponsored user state", async () => {
+        await initialize();
+
+        // A new wallet without any SOL
+        const carol = Keypair.generate();
+        const userState = await initialize_user_state(carol, authority.payer);
+
+        const userContent = await program.account.userState.fetch(userState);
+        expect(userContent.initialized).eq(true);
+        expect(userContent.payer.equals(authority.publicKey)).eq(true);
+        expect(await connection.getBalance(carol.publicKey)).eq(0);
+    });
+    it("Relayer pays fees and rent", async () => {
+        await initialize();
+
+        // dave never holds SOL: the relayer pays every fee and all rent.
+        const dave = Keypair.generate();
+        const relayer = Keypair.generate();
+        await airdropSol(provider, relayer.publicKey, 1_000_000_000);
+        const relay = async (method: { transaction(): Promise<any> }) => {
+            const tx = await method.transaction();
+            tx.feePayer = relayer.publicKey;
+            await sendAndConfirmTransaction(connection, tx, [relayer, dave]);
+        };
+
+        const userState = await initialize_user_state(dave, relayer);
+        const userMintAcc = await getOrCreateAssociatedTokenAccount(
+            connection,
+            relayer,
+            mint.publicKey,
+            dave.publicKey,
+            false,
+            undefined,
+            undefined,
+            tokenProgram
+        );
+        await relay(
+            program.methods.distribute_to(new BN(10_000_000)).accounts({
+                pool: pool.publicKey,
+                mint: mint.publicKey,
+                programSigner,
+                userMintAcc: userMintAcc.address,
+                authority: dave.publicKey,
+                tokenProgram,
+            })
+        );
+        await relay(
+            program.methods.enterStaking(new BN(10_000_000), null).accounts({
+                pool: pool.publicKey,
+                mint: mint.publicKey,
+                userMintAcc: userMintAcc.address,
+                userState,
+                vault: vault.address,
+                authority: dave.publicKey,
+                tokenProgram,
+                leaderboard: null,
+                userCheckpoints: null,
+                poolCheckpoints: null,
+                config: null,
+                attestation: null,
+                feeExemption: null,
+                auditLog: null,
+                userProfile: null,
+                ratePublisher: null,
+                instructions: null,
+                walletState: null,
+            })
+        );
+
+        const [withdrawalRequest] = PublicKey.findProgramAddressSync(
+            [
+                Buffer.from("withdrawal"),
+                pool.publicKey.toBuffer(),
+                dave.publicKey.toBuffer(),
+                new BN(0).toArrayLike(Buffer, "le", 8),
+            ],
+            program.programId
+        );
+        await relay(
+            program.methods
+                .requestWithdrawal(new BN(5_000_000), new BN(0), false)
+                .accounts({
+                    pool: pool.publicKey,
+                    userState,
+                    withdrawalRequest,
+                    authority: dave.publicKey,
+                    payer: relayer.publicKey,
+                    leaderboard: null,
+                    userCheckpoints: null,
+                    poolCheckpoints: null,
+                    config: null,
+                    guardian: null,
+                    vipBadge: null,
+                    feeExemption: null,
+                    auditLog: null,
+                    walletState: null,
+                    linkBoosted: null,
+                })
+        );
+
+        const staked = await get_user_staked(dave);
+        expect(staked.toNumber()).eq(5_000_000);
+        expect(await connection.getBalance(dave.publicKey)).eq(0);
+    });
+    it("Versioned stake, unstake and claim", async () => {
+        await initialize();
+        await set_emission_schedule(authority.payer, 1_000, { constant: {} });
+        const userMintAcc = await distribute_to(alice, 10_000_000);
+        await initialize_user_state(alice);
+
+        const [userState] = PublicKey.findProgramAddressSync(
+            [pool.publicKey.toBuffer(), alice.publicKey.toBuffer()],
+            program.programId
+        );
+        await program.methods
+            .stake({ v1: { amount: new BN(10_000_000), tag: null } })
+            .accounts({
+                pool: pool.publicKey,
+                mint: mint.publicKey,
+                userMintAcc,
+                userState,
+                vault: vault.address,
+                authority: alice.publicKey,
+                tokenProgram,
+                leaderboard: null,
+                userCheckpoints: null,
+                poolCheckpoints: null,
+                config: null,
+                attestation: null,
+                feeExemption: null,
+                auditLog: null,
+                userProfile: null,
+                ratePublisher: null,
+                instructions: null,
+                walletState: null,
+            })
+            .signers([alice])
+            .rpc();
+        expect((await get_user_staked(alice)).toNumber()).to.equal(10_000_000);
+
+        await program.methods
+            .unstake({ v1: { amount: new BN(4_000_000) } })
+            .accounts({
+                pool: pool.publicKey,
+                mint: mint.publicKey,
+                programSigner,
+                userMintAcc,
+                userState,
+                vault: vault.address,
+                authority: alice.publicKey,
+                tokenProgram,
+                leaderboard: null,
+                userCheckpoints: null,
+                poolCheckpoints: null,
+                config: null,
+                guardian: null,
+                feeExemption: null,
+                auditLog: null,
+                userProfile: null,
+                ratePublisher: null,
+                instructions: null,
+                memoProgram: null,
+                walletState: null,
+                linkBoosted: null,
+            })
+            .signers([alice])
+            .rpc();
+        expect((await get_user_staked(alice)).toNumber()).to.equal(6_000_000);
+
+        const before = await getMintBalanceOf(alice);
+        await program.methods
+            .claim({ v1: { amount: null } })
+            .accounts({
+                pool: pool.publicKey,
+                programSigner,
+                mint: mint.publicKey,
+                vault: vault.address,
+                userMintAcc,
+                user: alice.publicKey,
+                userState,
+                authority: alice.publicKey,
+                tokenProgram,
+                leaderboard: null,
+                auditLog: null,
+            })
+            .signers([alice])
+            .rpc();
+        expect((await getMintBalanceOf(alice)).gt(before)).to.be.true;
+    });
+    it("Several positions per wallet", async () => {
+        await initialize();
+        const userMintAcc = await distribute_to(alice, 10_000_000);
+        await initialize_user_state(alice);
+
+        const position = (index: number) =>
+            PublicKey.findProgramAddressSync(
+                [
+                    pool.publicKey.toBuffer(),
+                    alice.publicKey.toBuffer(),
+                    index == 0
+                        ? Buffer.alloc(0)
+                        : new BN(index).toArrayLike(Buffer, "le", 2),
+                ],
+                program.programId
+            )[0];
+        const [walletState] = PublicKey.findProgramAddressSync(
+            [
+                Buffer.from("wallet"),
+                pool.publicKey.toBuffer(),
+                alice.publicKey.toBuffer(),
+            ],
+            program.programId
+        );
+        for (const index of [1, 2]) {
+            await program.methods
+                .initializeUserPosition(index, null)
+                .accounts({
+                    userState: position(index),
+                    defaultUserState: position(0),
+                    walletState,
+                    pool: pool.publicKey,
+                    authority: alice.publicKey,
+                    payer: alice.publicKey,
+                })
+                .signers([alice])
+                .rpc();
+        }
+
+        const stake = async (
+            index: number,
+            amount: number,
+            wallet: PublicKey | null = walletState
+        ) =>
+            program.methods
+                .stake({ v1: { amount: new BN(amount), tag: null } })
+                .accounts({
+                    pool: pool.publicKey,
+                    mint: mint.publicKey,
+                    userMintAcc,
+                    userState: position(index),
+                    vault: vault.address,
+                    authority: alice.publicKey,
+                    tokenProgram,
+                    leaderboard: null,
+                    userCheckpoints: null,
+                    poolCheckpoints: null,
+                    config: null,
+                    attestation: null,
+                    feeExemption: null,
+                    auditLog: null,
+                    userProfile: null,
+                    ratePublisher: null,
+                    instructions: null,
+                    walletState: wallet,
+                })
+                .signers([alice])
+                .rpc();
+
+        // Every position of the wallet books its stake in the wallet state.
+        try {
+            await stake(0, 1_000_000, null);
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+        await stake(0, 1_000_000);
+        await stake(1, 2_000_000);
+        await stake(2, 3_000_000);
+
+        const staked = async (index: number) =>
+            (
+                await program.account.userState.fetch(position(index))
+            ).stakedAmount.toNumber();
+        expect(await staked(0)).to.equal(1_000_000);
+        expect(await staked(1)).to.equal(2_000_000);
+        expect(await staked(2)).to.equal(3_000_000);
+        expect((await get_staked_total()).toNumber()).to.equal(6_000_000);
+        expect(
+            (
+                await program.account.walletState.fetch(walletState)
+            ).stakedAmount.toNumber()
+        ).to.equal(6_000_000);
+
+        const voterWeight = (indices: number[]) =>
+            program.methods
+                .getVoterWeight()
+                .accounts({
+                    pool: pool.publicKey,
+                    user: alice.publicKey,
+                    userState: position(0),
+                })
+                .remainingAccounts(
+                    indices.map((index) => ({
+                        pubkey: position(index),
+                        isWritable: false,
+                        isSigner: false,
+                    }))
+                )
+                .view();
+        expect((await voterWeight([1, 2])).weight.toNumber()).to.equal(
+            6_000_000
+        );
+
+        // Listing a position twice would count it twice.
+        try {
+            await voterWeight([1, 1]);
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        // Bob can't stake into alice's position.
+        const bobMintAcc = await distribute_to(bob, 1_000_000);
+        try {
+            await program.methods
+                .stake({ v1: { amount: new BN(1_000_000), tag: null } })
+                .accounts({
+                    pool: pool.publicKey,
+                    mint: mint.publicKey,
+                    userMintAcc: bobMintAcc,
+                    userState: position(1),
+                    vault: vault.address,
+                    authority: bob.publicKey,
+                    tokenProgram,
+                    leaderboard: null,
+                    userCheckpoints: null,
+                    poolCheckpoints: null,
+                    config: null,
+                    attestation: null,
+                    feeExemption: null,
+                    auditLog: null,
+                    userProfile: null,
+                    ratePublisher: null,
+                    instructions: null,
+                    walletState: null,
+                })
+                .signers([bob])
+                .rpc();
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+        expect(await staked(1)).to.equal(2_000_000);
+    });
+    it("Leaderboard and checkpoints count every position", async () => {
+        await initialize();
+        const userMintAcc = await distribute_to(alice, 10_000_000);
+        await initialize_user_state(alice);
+
+        const position = (index: number) =>
+            PublicKey.findProgramAddressSync(
+                [
+                    pool.publicKey.toBuffer(),
+                    alice.publicKey.toBuffer(),
+                    index == 0
+                        ? Buffer.alloc(0)
+                        : new BN(index).toArrayLike(Buffer, "le", 2),
+                ],
+                program.programId
+            )[0];
+        const [walletState] = PublicKey.findProgramAddressSync(
+            [
+                Buffer.from("wallet"),
+                pool.publicKey.toBuffer(),
+                alice.publicKey.toBuffer(),
+            ],
+            program.programId
+        );
+        await program.methods
+            .initializeUserPosition(1, null)
+            .accounts({
+                userState: position(1),
+                defaultUserState: position(0),
+                walletState,
+                pool: pool.publicKey,
+                authority: alice.publicKey,
+                payer: alice.publicKey,
+            })
+            .signers([alice])
+            .rpc();
+
+        const leaderboard = get_leaderboard_address(1);
+        await program.methods
+            .startSeason(1)
+            .accounts({
+                pool: pool.publicKey,
+                leaderboard,
+                authority: authority.publicKey,
+            })
+            .signers([authority.payer])
+            .rpc();
+
+        const userCheckpoints = get_checkpoints_address(alice.publicKey, 0);
+        const poolCheckpoints = get_checkpoints_address(pool.publicKey, 0);
+        for (const [holder, state, wallet, checkpoints] of [
+            [pool.publicKey, null, null, poolCheckpoints],
+            [alice.publicKey, position(0), walletState, userCheckpoints],
+        ]) {
+            await program.methods
+                .openCheckpointPage(0)
+                .accounts({
+                    pool: pool.publicKey,
+                    holder,
+                    userState: state,
+                    walletState: wallet,
+                    previousCheckpoints: null,
+                    checkpoints,
+                    payer: authority.publicKey,
+                })
+                .rpc();
+        }
+        await program.methods
+            .setCheckpointsEnabled(true)
+            .accounts({
+                pool: pool.publicKey,
+                authority: authority.publicKey,
+                configChange: null,
+            })
+            .signers([authority.payer])
+            .rpc();
+
+        for (const [index, amount] of [
+            [0, 1_000_000],
+            [1, 2_000_000],
+        ]) {
+            await program.methods
+                .stake({ v1: { amount: new BN(amount), tag: null } })
+                .accounts({
+                    pool: pool.publicKey,
+                    mint: mint.publicKey,
+                    userMintAcc,
+                    userState: position(index),
+                    vault: vault.address,
+                    authority: alice.publicKey,
+                    tokenProgram,
+                    leaderboard,
+                    userCheckpoints,
+                    poolCheckpoints,
+                    config: null,
+                    attestation: null,
+                    feeExemption: null,
+                    auditLog: null,
+                    userProfile: null,
+                    ratePublisher: null,
+                    instructions: null,
+                    walletState,
+                })
+                .signers([alice])
+                .rpc();
+        }
+        const slot = await connection.getSlot();
+
+        await new Promise((resolve) => setTimeout(resolve, 2000));
+
+        // The wallet's score is required once it holds several positions
+        const update_leaderboard = (wallet: PublicKey | null) =>
+            program.methods
+                .updateLeaderboard()
+                .accounts({
+                    pool: pool.publicKey,
+                    user: alice.publicKey,
+                    userState: position(0),
+                    leaderboard,
+                    walletState: wallet,
+                })
+                .rpc();
+        try {
+            await update_leaderboard(null);
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+        await update_leaderboard(walletState);
+
+        // Both positions count towards the ranking, not just the default one
+        const board = await program.account.leaderboard.fetch(leaderboard);
+        const wallet = await program.account.walletState.fetch(walletState);
+        const defaultPosition = await program.account.userState.fetch(
+            position(0)
+        );
+        expect(board.entries[0].user.toString()).eq(
+            alice.publicKey.toString()
+        );
+        expect(board.entries[0].stakeSeconds.toString()).eq(
+            wallet.seasonStakeSeconds.toString()
+        );
+        expect(board.entries[0].stakeSeconds.toNumber()).gt(
+            defaultPosition.seasonStakeSeconds.toNumber()
+        );
+
+        // Voting history follows the wallet's total
+        const power = await program.methods
+            .getVotingPowerAt(new BN(slot))
+            .accounts({
+                pool: pool.publicKey,
+                holder: alice.publicKey,
+                userState: position(0),
+                walletState,
+                checkpoints: userCheckpoints,
+                nextCheckpoints: null,
+            })
+            .view();
+        expect(power.toNumber()).eq(3_000_000);
+    });
+    it("Labeled positions in events", async () => {
+        await initialize();
+        const userMintAcc = await distribute_to(alice, 10_000_000);
+        await initialize_user_state(alice);
+
+        const [defaultPosition] = PublicKey.findProgramAddressSync(
+            [pool.publicKey.toBuffer(), alice.publicKey.toBuffer()],
+            program.programId
+        );
+        const [position] = PublicKey.findProgramAddressSync(
+            [
+                pool.publicKey.toBuffer(),
+                alice.publicKey.toBuffer(),
+                new BN(1).toArrayLike(Buffer, "le", 2),
+            ],
+            program.programId
+        );
+        const [walletState] = PublicKey.findProgramAddressSync(
+            [
+                Buffer.from("wallet"),
+                pool.publicKey.toBuffer(),
+                alice.publicKey.toBuffer(),
+            ],
+            program.programId
+        );
+        const label = Buffer.alloc(16);
+        label.write("client-a");
+        await program.methods
+            .initializeUserPosition(1, [...label])
+            .accounts({
+                userState: position,
+                defaultUserState: defaultPosition,
+                walletState,
+                pool: pool.publicKey,
+                authority: alice.publicKey,
+                payer: alice.publicKey,
+            })
+            .signers([alice])
+            .rpc({ commitment: "confirmed" });
+
+        const signature = await program.methods
+            .stake({ v1: { amount: new BN(10_000_000), tag: null } })
+            .accounts({
+                pool: pool.publicKey,
+                mint: mint.publicKey,
+                userMintAcc,
+                userState: position,
+                vault: vault.address,
+                authority: alice.publicKey,
+                tokenProgram,
+                leaderboard: null,
+                userCheckpoints: null,
+                poolCheckpoints: null,
+                config: null,
+                attestation: null,
+                feeExemption: null,
+                auditLog: null,
+                userProfile: null,
+                ratePublisher: null,
+                instructions: null,
+                walletState,
+            })
+            .signers([alice])
+            .rpc({ commitment: "confirmed" });
+
+        const tx = await connection.getTransaction(signature, {
+            commitment: "confirmed",
+            maxSupportedTransactionVersion: 0,
+        });
+        const parser = new anchor.EventParser(program.programId, program.coder);
+        const events = [...parser.parseLogs(tx.meta.logMessages)];
+        const staked = events.find((event) => event.name == "staked");
+        expect(Buffer.from(staked.data.label).equals(label)).to.be.true;
+
+        // Relabeling works on indexed positions only.
+        const relabel = Buffer.alloc(16);
+        relabel.write("client-b");
+        await program.methods
+            .setPositionLabel([...relabel])
+            .accounts({
+                pool: pool.publicKey,
+                userState: position,
+                authority: alice.publicKey,
+            })
+            .signers([alice])
+            .rpc();
+        const state = await program.account.userState.fetch(position);
+        expect(Buffer.from(state.label).equals(relabel)).to.be.true;
+
+        try {
+            await program.methods
+                .setPositionLabel([...relabel])
+                .accounts({
+                    pool: pool.publicKey,
+                    userState: defaultPosition,
+                    authority: alice.publicKey,
+                })
+                .signers([alice])
+                .rpc();
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+    });
+    it("Cold storage withdrawals", async () => {
+        await initialize();
+        await initialize_user_state(alice);
+        await distribute_to(alice, 10_000_000);
+        await enter_staking(alice, 10_000_000);
+
+        const [userState] = PublicKey.findProgramAddressSync(
+            [pool.publicKey.toBuffer(), alice.publicKey.toBuffer()],
+            program.programId
+        );
+        const cold = Keypair.generate();
+        const destination = await getOrCreateAssociatedTokenAccount(
+            connection,
+            alice,
+            mint.publicKey,
+            Keypair.generate().publicKey,
+            false,
+            undefined,
+            undefined,
+            tokenProgram
+        );
+
+        const set_cold_storage = (window: number, coldKey: Keypair | null) =>
+            program.methods
+                .setColdStorage(
+                    cold.publicKey,
+                    destination.address,
+                    new BN(window)
+                )
+                .accounts({
+                    pool: pool.publicKey,
+                    userState,
+                    authority: alice.publicKey,
+                    coldKey: coldKey?.publicKey ?? null,
+                })
+                .signers(coldKey ? [alice, coldKey] : [alice])
+                .rpc();
+        const request = (amount: number) =>
+            program.methods
+                .requestColdWithdrawal(new BN(amount))
+                .accounts({
+                    pool: pool.publicKey,
+                    userState,
+                    authority: alice.publicKey,
+                })
+                .signers([alice])
+                .rpc();
+        const approve = (amount: number) =>
+            program.methods
+                .approveColdWithdrawal(new BN(amount))
+                .accounts({
+                    pool: pool.publicKey,
+                    user: alice.publicKey,
+                    userState,
+                    coldKey: cold.publicKey,
+                })
+                .signers([cold])
+                .rpc();
+        const leave = (amount: number) =>
+            program.methods
+                .leaveStaking(new BN(amount))
+                .accounts({
+                    pool: pool.publicKey,
+                    mint: mint.publicKey,
+                    programSigner,
+                    userMintAcc: destination.address,
+                    userState,
+                    vault: vault.address,
+                    authority: alice.publicKey,
+                    tokenProgram,
+                    leaderboard: null,
+                    userCheckpoints: null,
+                    poolCheckpoints: null,
+                    config: null,
+                    guardian: null,
+                    feeExemption: null,
+                    auditLog: null,
+                    userProfile: null,
+                    ratePublisher: null,
+                    instructions: null,
+                    memoProgram: null,
+                    walletState: null,
+                    linkBoosted: null,
+                })
+                .signers([alice])
+                .rpc();
+
+        // An approval after the window is too late.
+        await set_cold_storage(1, null);
+        await request(4_000_000);
+        await new Promise((resolve) => setTimeout(resolve, 3000));
+        try {
+            await approve(4_000_000);
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        // Settings can't change without the cold key anymore.
+        try {
+            await set_cold_storage(60, null);
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+        await set_cold_storage(60, cold);
+
+        // The hot key alone gets nothing out, not even to the destination.
+        try {
+            await leave_staking(alice, 1_000_000);
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+        await request(4_000_000);
+        try {
+            await leave(4_000_000);
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        await approve(4_000_000);
+        await leave(4_000_000);
+
+        const balance = await getAccount(
+            connection,
+            destination.address,
+            undefined,
+            tokenProgram
+        );
+        expect(Number(balance.amount)).eq(4_000_000);
+        expect((await get_user_staked(alice)).toNumber()).eq(6_000_000);
+
+        // The approval was used up.
+        try {
+            await leave(4_000_000);
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+    });
+    it("Integrator allowances", async () => {
+        await initialize();
+        await initialize_user_state(alice);
+        await distribute_to(alice, 10_000_000);
+        await enter_staking(alice, 10_000_000);
+
+        const [userState] = PublicKey.findProgramAddressSync(
+            [pool.publicKey.toBuffer(), alice.publicKey.toBuffer()],
+            program.programId
+        );
+        const integrator = Keypair.generate().publicKey;
+        const set_integrator = (program_id: PublicKey | null, allowance: number) =>
+            program.methods
+                .setIntegrator(program_id, new BN(allowance))
+                .accounts({
+                    pool: pool.publicKey,
+                    userState,
+                    authority: alice.publicKey,
+                })
+                .signers([alice])
+                .rpc();
+
+        await set_integrator(integrator, 5_000_000);
+        let state = await program.account.userState.fetch(userState);
+        expect(state.integrator.equals(integrator)).eq(true);
+        expect(state.integratorAllowance.toNumber()).eq(5_000_000);
+
+        // Only the integrator's own PDA can spend the allowance, and a plain
+        // keypair can't be one.
+        const impostor = Keypair.generate();
+        const userMintAcc = await getOrCreateAssociatedTokenAccount(
+            connection,
+            alice,
+            mint.publicKey,
+            alice.publicKey,
+            false,
+            undefined,
+            undefined,
+            tokenProgram
+        );
+        try {
+            await program.methods
+                .integratorUnstake(new BN(1_000_000))
+                .accounts({
+                    pool: pool.publicKey,
+                    programSigner,
+                    mint: mint.publicKey,
+                    vault: vault.address,
+                    user: alice.publicKey,
+                    userState,
+                    userMintAcc: userMintAcc.address,
+                    integratorAuthority: impostor.publicKey,
+                    tokenProgram,
+                    userCheckpoints: null,
+                    poolCheckpoints: null,
+                    config: null,
+                    feeExemption: null,
+                    auditLog: null,
+                    walletState: null,
+                    linkBoosted: null,
+                })
+                .signers([impostor])
+                .rpc();
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+        expect((await get_user_staked(alice)).toNumber()).eq(10_000_000);
+
+        await set_integrator(null, 0);
+        state = await program.account.userState.fetch(userState);
+        expect(state.integrator.equals(PublicKey.default)).eq(true);
+        expect(state.integratorAllowance.toNumber()).eq(0);
+    });
+
+    it("Achievement NFTs", async () => {
+        await initialize();
+        await initialize_user_state(alice);
+
+        const [achievementConfig] = PublicKey.findProgramAddressSync(
+            [Buffer.from("achievement"), pool.publicKey.toBuffer()],
+            program.programId
+        );
+        const set_achievement_uri = (base_uri: string) =>
+            program.methods
+                .setAchievementUri(base_uri)
+                .accounts({
+                    pool: pool.publicKey,
+                    authority: authority.publicKey,
+                    configChange: null,
+                })
+                .signers([authority.payer])
+                .rpc();
+
+        // Longer than the config history can hold.
+        try {
+            await set_achievement_uri("a".repeat(101));
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        await set_achievement_uri("https://example.com/achievements/");
+        const config = await program.account.achievementConfig.fetch(
+            achievementConfig
+        );
+        expect(config.pool.equals(pool.publicKey)).eq(true);
+        expect(config.baseUri).eq("https://example.com/achievements/");
+
+        const [userState] = PublicKey.findProgramAddressSync(
+            [pool.publicKey.toBuffer(), alice.publicKey.toBuffer()],
+            program.programId
+        );
+        const metadataProgram = new PublicKey(
+            "metaqbxxUerdq28cj1RbAWkYQm3ybzjb6a8bt518x1s"
+        );
+        const [walletAchievements] = PublicKey.findProgramAddressSync(
+            [
+                Buffer.from("achievement"),
+                pool.publicKey.toBuffer(),
+                alice.publicKey.toBuffer(),
+            ],
+            program.programId
+        );
+        const [achievementMint] = PublicKey.findProgramAddressSync(
+            [
+                Buffer.from("achievement"),
+                pool.publicKey.toBuffer(),
+                alice.publicKey.toBuffer(),
+                Buffer.from([0]),
+            ],
+            program.programId
+        );
+        const [metadata] = PublicKey.findProgramAddressSync(
+            [
+                Buffer.from("metadata"),
+                metadataProgram.toBuffer(),
+                achievementMint.toBuffer(),
+            ],
+            metadataProgram
+        );
+        const [masterEdition] = PublicKey.findProgramAddressSync(
+            [
+                Buffer.from("metadata"),
+                metadataProgram.toBuffer(),
+                achievementMint.toBuffer(),
+                Buffer.from("edition"),
+            ],
+            metadataProgram
+        );
+
+        // Nothing staked yet, so the first stake milestone isn't reached.
+        try {
+            await program.methods
+                .mintAchievement({ firstStake: {} })
+                .accounts({
+                    pool: pool.publicKey,
+                    achievementConfig,
+                    programSigner,
+                    user: alice.publicKey,
+                    userState,
+                    leaderboard: null,
+                    walletAchievements,
+                    achievementMint,
+                    userNftAcc: getAssociatedTokenAddressSync(
+                        achievementMint,
+                        alice.publicKey
+                    ),
+                    metadata,
+                    masterEdition,
+                    tokenMetadataProgram: metadataProgram,
+                    nftTokenProgram: TOKEN_PROGRAM_ID,
+                })
+                .signers([alice])
+                .rpc();
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        // Nothing recorded for the wallet either.
+        expect(
+            await program.account.walletAchievements.fetchNullable(
+                walletAchievements
+            )
+        ).eq(null);
+    });
+
+    it("Pro-rata withdrawal batches", async () => {
+        await initialize();
+        await initialize_user_state(alice);
+        await initialize_user_state(bob);
+        await distribute_to(alice, 10_000_000);
+        await distribute_to(bob, 10_000_000);
+        await enter_staking(alice, 10_000_000);
+        await enter_staking(bob, 10_000_000);
+
+        const set_pro_rata_withdrawals = (enabled: boolean) =>
+            program.methods
+                .setProRataWithdrawals(enabled)
+                .accounts({
+                    pool: pool.publicKey,
+                    authority: authority.publicKey,
+                    configChange: null,
+                })
+                .signers([authority.payer])
+                .rpc();
+        const open_withdrawal_batch = () =>
+            program.methods
+                .openWithdrawalBatch()
+                .accounts({ pool: pool.publicKey, vault: vault.address })
+                .rpc();
+
+        await set_pro_rata_withdrawals(true);
+        const aliceRequest = await request_withdrawal(alice, 4_000_000, 0);
+        // Nonces are per user
+        const bobRequest = await request_withdrawal(bob, 2_000_000, 0);
+
+        // The mode can't change under queued requests
+        try {
+            await set_pro_rata_withdrawals(false);
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        // Nothing is paid before a batch opens
+        try {
+            await fulfill_withdrawal(alice, aliceRequest);
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        await open_withdrawal_batch();
+        let poolAccount = await program.account.pool.fetch(pool.publicKey);
+        expect(poolAccount.batchId.toNumber()).eq(1);
+        expect(poolAccount.batchRemaining.toNumber()).eq(6_000_000);
+
+        // Requests queued after the batch opened wait for the next one
+        const lateRequest = await request_withdrawal(alice, 1_000_000, 1);
+        try {
+            await fulfill_withdrawal(alice, lateRequest);
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        // The batch has to be filled before the next one opens
+        try {
+            await open_withdrawal_batch();
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        // Out of order, once per batch
+        await fulfill_withdrawal(bob, bobRequest);
+        try {
+            await fulfill_withdrawal(bob, bobRequest);
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+        await fulfill_withdrawal(alice, aliceRequest);
+        expect((await getMintBalanceOf(alice)).toNumber()).eq(4_000_000);
+        expect((await getMintBalanceOf(bob)).toNumber()).eq(2_000_000);
+
+        await open_withdrawal_batch();
+        await fulfill_withdrawal(alice, lateRequest);
+        expect((await getMintBalanceOf(alice)).toNumber()).eq(5_000_000);
+
+        poolAccount = await program.account.pool.fetch(pool.publicKey);
+        expect(poolAccount.queuedAmount.toNumber()).eq(0);
+        expect(poolAccount.queueHead.toNumber()).eq(3);
+        expect(poolAccount.batchRemaining.toNumber()).eq(0);
+
+        await set_pro_rata_withdrawals(false);
+    });
+
+    it("Operator key", async () => {
+        await initialize();
+        const operator = Keypair.generate();
+        await airdropSol(provider, operator.publicKey, 1_000_000_000);
+
+        const set_operator = (key: PublicKey | null) =>
+            program.methods
+                .setOperator(key)
+                .accounts({
+                    pool: pool.publicKey,
+                    authority: authority.publicKey,
+                    configChange: null,
+                })
+                .signers([authority.payer])
+                .rpc();
+        const start_season = (season: number) =>
+            program.methods
+                .startSeason(season)
+                .accounts({
+                    pool: pool.publicKey,
+                    leaderboard: get_leaderboard_address(season),
+                    authority: operator.publicKey,
+                })
+                .signers([operator])
+                .rpc();
+
+        try {
+            await start_season(1);
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        await set_operator(operator.publicKey);
+        await start_season(1);
+        expect((await program.account.pool.fetch(pool.publicKey)).season).eq(1);
+
+        const [snapshot] = PublicKey.findProgramAddressSync(
+            [
+                Buffer.from("snapshot"),
+                pool.publicKey.toBuffer(),
+                new BN(0).toArrayLike(Buffer, "le", 8),
+            ],
+            program.programId
+        );
+        await program.methods
+            .commitSnapshot(new BN(0), new Array(32).fill(0))
+            .accounts({
+                pool: pool.publicKey,
+                snapshot,
+                authority: operator.publicKey,
+            })
+            .signers([operator])
+            .rpc();
+
+        // Parameters stay with the admin
+        try {
+            await program.methods
+                .setWatcher(operator.publicKey)
+                .accounts({
+                    pool: pool.publicKey,
+                    authority: operator.publicKey,
+                    configChange: null,
+                })
+                .signers([operator])
+                .rpc();
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        await set_operator(null);
+        try {
+            await start_season(2);
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+    });
+    it("Campaign managers run their own SOL campaigns", async () => {
+        await initialize();
+
+        const [campaignManager] = PublicKey.findProgramAddressSync(
+            [
+                Buffer.from("campaign_manager"),
+                pool.publicKey.toBuffer(),
+                bob.publicKey.toBuffer(),
+            ],
+            program.programId
+        );
+        const set_sol_rewards = (
+            signer: Keypair,
+            manager: PublicKey | null,
+            end: number
+        ) =>
+            program.methods
+                .setSolRewards(new BN(1_000), new BN(end))
+                .accounts({
+                    pool: pool.publicKey,
+                    authority: signer.publicKey,
+                    campaignManager: manager,
+                    configChange: null,
+                })
+                .signers([signer])
+                .rpc();
+        const now = Math.floor(Date.now() / 1000);
+
+        // Bob isn't a manager yet.
+        try {
+            await set_sol_rewards(bob, null, now + 3600);
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        await program.methods
+            .addCampaignManager(bob.publicKey)
+            .accounts({
+                pool: pool.publicKey,
+                campaignManager,
+                authority: authority.publicKey,
+                configChange: null,
+            })
+            .signers([authority.payer])
+            .rpc();
+
+        await set_sol_rewards(bob, campaignManager, now + 3600);
+        let poolAccount = await program.account.pool.fetch(pool.publicKey);
+        expect(poolAccount.solCampaignOwner.equals(bob.publicKey)).eq(true);
+
+        // Managers can change their own campaign.
+        await set_sol_rewards(bob, campaignManager, now + 7200);
+
+        // The authority takes over, and Bob can't touch its campaign.
+        await set_sol_rewards(authority.payer, null, now + 3600);
+        poolAccount = await program.account.pool.fetch(pool.publicKey);
+        expect(poolAccount.solCampaignOwner.equals(authority.publicKey)).eq(
+            true
+        );
+        try {
+            await set_sol_rewards(bob, campaignManager, now + 7200);
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        await program.methods
+            .removeCampaignManager()
+            .accounts({
+                pool: pool.publicKey,
+                campaignManager,
+                authority: authority.publicKey,
+                configChange: null,
+            })
+            .signers([authority.payer])
+            .rpc();
+        expect(await connection.getAccountInfo(campaignManager)).eq(null);
+    });
+    it("Terms must be accepted before staking", async () => {
+        await initialize();
+        const userState = await initialize_user_state(alice);
+        await distribute_to(alice, 20_000_000);
+
+        const set_terms = (terms: number[]) =>
+            program.methods
+                .setTerms(terms)
+                .accounts({
+                    pool: pool.publicKey,
+                    authority: authority.publicKey,
+                    configChange: null,
+                })
+                .signers([authority.payer])
+                .rpc();
+        const accept_terms = (terms: number[]) =>
+            program.methods
+                .acceptTerms(terms)
+                .accounts({
+                    pool: pool.publicKey,
+                    userState,
+                    authority: alice.publicKey,
+                })
+                .signers([alice])
+                .rpc();
+        const terms = Array(32).fill(1);
+        const newTerms = Array(32).fill(2);
+
+        await set_terms(terms);
+        try {
+            await enter_staking(alice, 10_000_000);
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        // Accepting anything but the current terms is rejected.
+        try {
+            await accept_terms(newTerms);
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        await accept_terms(terms);
+        const userAccount = await program.account.userState.fetch(userState);
+        expect(userAccount.termsHash).deep.eq(terms);
+        await enter_staking(alice, 10_000_000);
+
+        // New terms have to be accepted again.
+        await set_terms(newTerms);
+        try {
+            await enter_staking(alice, 10_000_000);
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+        await accept_terms(newTerms);
+        await enter_staking(alice, 10_000_000);
+        expect((await get_user_staked(alice)).toNumber()).eq(20_000_000);
+    });
+    it("Distribute to many token accounts at once", async () => {
+        await initialize();
+        const aliceMintAcc = await distribute_to(alice, 0);
+        const bobMintAcc = await distribute_to(bob, 0);
+
+        const distribute = (
+            amounts: number[],
+            signer: Keypair = authority.payer
+        ) =>
+            program.methods
+                .distribute(amounts.map((amount) => new BN(amount)))
+                .accounts({
+                    pool: pool.publicKey,
+                    programSigner,
+                    mint: mint.publicKey,
+                    authority: signer.publicKey,
+                    tokenProgram,
+                    auditLog: null,
+                })
+                .remainingAccounts(
+                    [aliceMintAcc, bobMintAcc].map((pubkey) => ({
+                        pubkey,
+                        isWritable: true,
+                        isSigner: false,
+                    }))
+                )
+                .signers([signer])
+                .rpc();
+        const balance = async (address: PublicKey) =>
+            Number(
+                (
+                    await getAccount(
+                        connection,
+                        address,
+                        undefined,
+                        tokenProgram
+                    )
+                ).amount
+            );
+
+        const admin = (method: any) =>
+            method
+                .accounts({
+                    pool: pool.publicKey,
+                    authority: authority.publicKey,
+                    configChange: null,
+                })
+                .signers([authority.payer])
+                .rpc();
+
+        // Only the pool authority distributes.
+        await admin(program.methods.setDistributionCap(new BN(2_999)));
+        try {
+            await distribute([1_000, 2_000], alice);
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        // One amount per account, within the cap.
+        try {
+            await distribute([1_000]);
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+        try {
+            await distribute([1_000, 2_000]);
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        // Nothing while paused.
+        await admin(program.methods.setDistributionCap(new BN(3_000)));
+        await admin(program.methods.pause(false));
+        try {
+            await distribute([1_000, 2_000]);
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+        await admin(program.methods.unpause());
+
+        await distribute([1_000, 2_000]);
+        expect(await balance(aliceMintAcc)).eq(1_000);
+        expect(await balance(bobMintAcc)).eq(2_000);
+
+        // The cap is used up.
+        try {
+            await distribute([1, 0]);
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+    });
+
+    it("Migrations only touch accounts from older layouts", async () => {
+        await initialize();
+        const userState = await initialize_user_state(alice);
+
+        expect(
+            (await program.account.pool.fetch(pool.publicKey)).layoutVersion
+        ).eq(3);
+        expect(
+            (await program.account.userState.fetch(userState)).layoutVersion
+        ).eq(1);
+
+        // Both are already on the current layout.
+        try {
+            await program.methods
+                .migratePool()
+                .accounts({
+                    pool: pool.publicKey,
+                    mint: mint.publicKey,
+                    authority: authority.publicKey,
+                })
+                .signers([authority.payer])
+                .rpc();
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+        try {
+            await program.methods
+                .migrateUserState()
+                .accounts({
+                    pool: pool.publicKey,
+                    userState,
+                    user: alice.publicKey,
+                })
+                .signers([alice])
+                .rpc();
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+
+        // Only the mint's freeze authority takes a pool over.
+        try {
+            await program.methods
+                .migratePool()
+                .accounts({
+                    pool: pool.publicKey,
+                    mint: mint.publicKey,
+                    authority: alice.publicKey,
+                })
+                .signers([alice])
+                .rpc();
+            assert(false);
+        } catch (error) {
+            // console.log(error);
+        }
+    });
 });